@@ -0,0 +1,148 @@
+//! Installed-keg lookups against a Cellar of 100 installed formulae, each with an older keg kept
+//! beside the linked one: resolving a plan over all of them, and looking each up directly, once
+//! with a registry that scans the Cellar per query and once with one answering from a
+//! [`KegSnapshot`], whose one-off load is timed on its own. Prints the best time of each.
+//!
+//! `cargo bench -p sps-common --bench kegs`. Set `SPS_BENCH_NODES` for the number of formulae
+//! (default 100) and `SPS_BENCH_RUNS` for the number of rounds timed (default 20).
+
+use std::fs;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use serde_json::json;
+use sps_common::dependency::{DependencyResolver, ResolutionContext};
+use sps_common::formulary::Formulary;
+use sps_common::keg::{KegRegistry, KegSnapshot};
+use sps_common::{Cache, Config};
+
+fn env_or(name: &str, default: usize) -> usize {
+    std::env::var(name)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default)
+}
+
+fn name(i: usize) -> String {
+    format!("libsynthetic-component-{i:04}")
+}
+
+fn best_of(runs: usize, mut step: impl FnMut()) -> Duration {
+    (0..runs)
+        .map(|_| {
+            let started = Instant::now();
+            step();
+            started.elapsed()
+        })
+        .min()
+        .unwrap_or_default()
+}
+
+/// Resolves `target`, whose dependencies are every other formula, and returns how many of them
+/// were found installed.
+fn resolve(config: &Config, keg_registry: &KegRegistry, target: &str) -> usize {
+    let formulary = Formulary::new(config.clone());
+    let mut resolver = DependencyResolver::new(ResolutionContext {
+        formulary: &formulary,
+        keg_registry,
+        sps_prefix: &config.prefix,
+        include_optional: false,
+        include_test: false,
+        skip_recommended: false,
+        force_build: false,
+        ignore_installed: false,
+        only_missing: false,
+        platform: None,
+    });
+    let graph = resolver.resolve_targets(&[target.to_string()]).unwrap();
+    graph
+        .resolution_details
+        .values()
+        .filter(|dep| dep.keg_path.is_some())
+        .count()
+}
+
+fn look_up_all(keg_registry: &KegRegistry, nodes: usize) -> usize {
+    (0..nodes)
+        .filter(|&i| keg_registry.get_installed_keg(&name(i)).unwrap().is_some())
+        .count()
+}
+
+fn main() {
+    let nodes = env_or("SPS_BENCH_NODES", 100);
+    let runs = env_or("SPS_BENCH_RUNS", 20);
+    let dir = tempfile::tempdir().unwrap();
+    let config = Config {
+        prefix: dir.path().to_path_buf(),
+        cellar: dir.path().join("Cellar"),
+        cache_dir: dir.path().join("cache"),
+        ..Config::load().unwrap()
+    };
+    fs::create_dir_all(config.prefix.join("opt")).unwrap();
+    for i in 0..nodes {
+        for version in ["0.9", "1.0"] {
+            let keg = config.cellar.join(name(i)).join(version);
+            fs::create_dir_all(keg.join("bin")).unwrap();
+            fs::write(keg.join("INSTALL_RECEIPT.json"), "{}").unwrap();
+        }
+        std::os::unix::fs::symlink(
+            config.cellar.join(name(i)).join("1.0"),
+            config.prefix.join("opt").join(name(i)),
+        )
+        .unwrap();
+    }
+    let target = "bench-target";
+    let mut formulae: Vec<_> = (0..nodes)
+        .map(|i| json!({ "name": name(i), "versions": { "stable": "1.0" } }))
+        .collect();
+    formulae.push(json!({
+        "name": target,
+        "versions": { "stable": "1.0" },
+        "dependencies": (0..nodes).map(name).collect::<Vec<_>>(),
+    }));
+    Cache::new(&config.cache_dir)
+        .unwrap()
+        .store_raw("formula.json", &json!(formulae).to_string())
+        .unwrap();
+
+    let scanning = KegRegistry::new(config.clone());
+    let snapshot = KegRegistry::with_snapshot(config.clone(), KegSnapshot::load(&config).unwrap());
+    assert_eq!(resolve(&config, &scanning, target), nodes);
+    assert_eq!(resolve(&config, &snapshot, target), nodes);
+    println!("kegs: {nodes} formulae, 2 kegs each; best of {runs}");
+    let timings = [
+        (
+            "snapshot load",
+            best_of(runs, || {
+                black_box(KegSnapshot::load(&config).unwrap());
+            }),
+        ),
+        (
+            "resolve, scanning",
+            best_of(runs, || {
+                black_box(resolve(&config, &scanning, target));
+            }),
+        ),
+        (
+            "resolve, snapshot",
+            best_of(runs, || {
+                black_box(resolve(&config, &snapshot, target));
+            }),
+        ),
+        (
+            "lookups, scanning",
+            best_of(runs, || {
+                black_box(look_up_all(&scanning, nodes));
+            }),
+        ),
+        (
+            "lookups, snapshot",
+            best_of(runs, || {
+                black_box(look_up_all(&snapshot, nodes));
+            }),
+        ),
+    ];
+    for (step, took) in timings {
+        println!("{step:>18}: {:>8.2} ms", took.as_secs_f64() * 1e3);
+    }
+}
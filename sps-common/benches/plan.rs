@@ -0,0 +1,168 @@
+//! Plans a synthetic 500-formula install the way `sps install` does: resolves the targets, then
+//! drives the install scheduler over the plan as the pipeline's coordinator does, one node at a
+//! time. Prints the best time of each step.
+//!
+//! `cargo bench -p sps-common --bench plan`. Set `SPS_BENCH_NODES` for the number of formulae
+//! (default 500) and `SPS_BENCH_RUNS` for the number of plans timed (default 20).
+
+use std::fs;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use serde_json::json;
+use sps_common::dependency::{
+    DependencyResolver, DependencyTag, Direction, FailurePolicy, ResolutionContext, ResolvedGraph,
+    Scheduler,
+};
+use sps_common::formulary::Formulary;
+use sps_common::keg::KegRegistry;
+use sps_common::{Cache, Config};
+
+fn env_or(name: &str, default: usize) -> usize {
+    std::env::var(name)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default)
+}
+
+/// Names about as long as real ones, sharing a prefix the way `lib*` and `python@*` do.
+fn name(i: usize) -> String {
+    format!("libsynthetic-component-{i:04}")
+}
+
+/// Node `i` depends on the one before it, so the whole graph is planned, and on up to three
+/// more spread over the earlier nodes, giving long chains as well as widely shared leaves.
+/// Every fifth edge is a build dependency.
+fn dependencies(i: usize) -> Vec<(usize, DependencyTag)> {
+    let mut deps: Vec<usize> = [
+        i.wrapping_sub(1),
+        i / 2,
+        (i * 7 + 3) % i.max(1),
+        (i * 13 + 5) % i.max(1),
+    ]
+    .into_iter()
+    .filter(|&dep| dep < i)
+    .collect();
+    deps.sort_unstable();
+    deps.dedup();
+    deps.into_iter()
+        .map(|dep| {
+            let tags = if (i + dep) % 5 == 0 {
+                DependencyTag::BUILD
+            } else {
+                DependencyTag::RUNTIME
+            };
+            (dep, tags)
+        })
+        .collect()
+}
+
+fn best_of(runs: usize, mut step: impl FnMut()) -> Duration {
+    (0..runs)
+        .map(|_| {
+            let started = Instant::now();
+            step();
+            started.elapsed()
+        })
+        .min()
+        .unwrap_or_default()
+}
+
+fn resolve(config: &Config, targets: &[String]) -> ResolvedGraph {
+    let formulary = Formulary::new(config.clone());
+    let keg_registry = KegRegistry::new(config.clone());
+    let mut resolver = DependencyResolver::new(ResolutionContext {
+        formulary: &formulary,
+        keg_registry: &keg_registry,
+        sps_prefix: &config.prefix,
+        include_optional: false,
+        include_test: false,
+        skip_recommended: false,
+        force_build: false,
+        ignore_installed: false,
+        only_missing: false,
+        platform: None,
+    });
+    resolver.resolve_targets(targets).unwrap()
+}
+
+/// The install scheduler over `graph`'s plan, grouped under `targets`, run to the end.
+fn schedule(graph: &ResolvedGraph, targets: &[String]) -> usize {
+    let mut scheduler = Scheduler::new(Direction::DependenciesFirst, FailurePolicy::SkipBlocked);
+    for dep in &graph.install_plan {
+        scheduler.add_node(dep.formula.name());
+    }
+    for dep in &graph.install_plan {
+        for edge in dep.formula.dependencies() {
+            scheduler.add_tagged_edge(dep.formula.name(), &edge.name, edge.tags);
+        }
+    }
+    scheduler.set_targets(targets.iter().map(String::as_str));
+    let mut done = 0;
+    while !scheduler.is_finished() {
+        for ready in scheduler.ready_matching(8, |_| true) {
+            if scheduler.start(&ready) {
+                scheduler.complete(&ready, true);
+                done += 1;
+            }
+        }
+    }
+    done
+}
+
+fn main() {
+    let nodes = env_or("SPS_BENCH_NODES", 500);
+    let runs = env_or("SPS_BENCH_RUNS", 20);
+    let dir = tempfile::tempdir().unwrap();
+    let config = Config {
+        prefix: dir.path().to_path_buf(),
+        cellar: dir.path().join("Cellar"),
+        cache_dir: dir.path().join("cache"),
+        ..Config::load().unwrap()
+    };
+    fs::create_dir_all(&config.cellar).unwrap();
+    let formulae: Vec<_> = (0..nodes)
+        .map(|i| {
+            let deps = dependencies(i);
+            let runtime: Vec<String> = deps
+                .iter()
+                .filter(|(_, tags)| *tags == DependencyTag::RUNTIME)
+                .map(|(d, _)| name(*d))
+                .collect();
+            let build: Vec<String> = deps
+                .iter()
+                .filter(|(_, tags)| *tags == DependencyTag::BUILD)
+                .map(|(d, _)| name(*d))
+                .collect();
+            json!({
+                "name": name(i),
+                "versions": { "stable": "1.0" },
+                "dependencies": runtime,
+                "build_dependencies": build,
+            })
+        })
+        .collect();
+    Cache::new(&config.cache_dir)
+        .unwrap()
+        .store_raw("formula.json", &json!(formulae).to_string())
+        .unwrap();
+    // The last tenth are requested, as a large `sps install a b c ...` would be.
+    let targets: Vec<String> = (nodes - nodes / 10..nodes).map(name).collect();
+
+    let graph = resolve(&config, &targets);
+    assert_eq!(schedule(&graph, &targets), graph.install_plan.len());
+    println!(
+        "plan: {} of {nodes} formulae, {} targets; best of {runs}",
+        graph.install_plan.len(),
+        targets.len()
+    );
+    let resolving = best_of(runs, || {
+        black_box(resolve(&config, &targets));
+    });
+    let scheduling = best_of(runs, || {
+        black_box(schedule(&graph, &targets));
+    });
+    for (step, took) in [("resolve", resolving), ("schedule", scheduling)] {
+        println!("{step:>9}: {:>8.2} ms", took.as_secs_f64() * 1e3);
+    }
+}
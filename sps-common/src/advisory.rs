@@ -0,0 +1,539 @@
+// sps-common/src/advisory.rs
+//! Security advisories for packages in a plan, from an OSV-format feed (`advisory_feed`).
+//!
+//! `sps update` downloads the feed into the cache; resolution then matches plan entries
+//! against the cached copy, so checking never needs the network. The feed may be a JSON array
+//! of OSV records, an object with a `vulns` array (as OSV's query API returns) or a single
+//! record.
+//!
+//! A record matches a package when one of its `affected` entries names it, with an ecosystem
+//! that is missing or `Homebrew`, and the version falls in one of the entry's `ECOSYSTEM` or
+//! `SEMVER` ranges or is listed in its `versions`. Names are compared lowercased and without a
+//! `homebrew/core/` prefix; versions are compared as [`PkgVersion`]s, ignoring the revision.
+
+use std::cmp::Ordering;
+use std::path::PathBuf;
+use std::{fmt, fs};
+
+use serde::Deserialize;
+use serde_json::Value;
+use tracing::debug;
+
+use super::config::Config;
+use super::error::{Result, SpsError};
+use super::model::formula::canonical_formula_name;
+use super::model::PkgVersion;
+
+/// File in the cache directory holding the last downloaded feed.
+const INDEX_FILE: &str = "advisories.json";
+
+/// How severe an advisory is, from its `database_specific.severity` or a numeric CVSS score.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Unknown,
+    Low,
+    Moderate,
+    High,
+    Critical,
+}
+
+impl Severity {
+    fn parse(label: &str) -> Option<Self> {
+        match label.trim().to_ascii_uppercase().as_str() {
+            "LOW" => Some(Self::Low),
+            "MODERATE" | "MEDIUM" => Some(Self::Moderate),
+            "HIGH" => Some(Self::High),
+            "CRITICAL" => Some(Self::Critical),
+            _ => None,
+        }
+    }
+
+    /// CVSS qualitative rating of a base score.
+    fn from_score(score: f64) -> Self {
+        match score {
+            s if s >= 9.0 => Self::Critical,
+            s if s >= 7.0 => Self::High,
+            s if s >= 4.0 => Self::Moderate,
+            s if s > 0.0 => Self::Low,
+            _ => Self::Unknown,
+        }
+    }
+
+    /// Whether `advisories = block` refuses packages with such an advisory.
+    pub fn blocks(self) -> bool {
+        self >= Self::High
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Unknown => "UNKNOWN",
+            Self::Low => "LOW",
+            Self::Moderate => "MODERATE",
+            Self::High => "HIGH",
+            Self::Critical => "CRITICAL",
+        })
+    }
+}
+
+/// One OSV record, reduced to what matching and reporting need.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Advisory {
+    pub id: String,
+    #[serde(default)]
+    pub aliases: Vec<String>,
+    #[serde(default)]
+    pub summary: Option<String>,
+    #[serde(default)]
+    severity: Vec<OsvSeverity>,
+    #[serde(default)]
+    database_specific: Option<Value>,
+    #[serde(default)]
+    affected: Vec<Affected>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct OsvSeverity {
+    #[serde(default)]
+    score: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct Affected {
+    #[serde(default)]
+    package: Option<Package>,
+    #[serde(default)]
+    ranges: Vec<Range>,
+    #[serde(default)]
+    versions: Vec<String>,
+    #[serde(default)]
+    database_specific: Option<Value>,
+    #[serde(default)]
+    ecosystem_specific: Option<Value>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct Package {
+    #[serde(default)]
+    ecosystem: String,
+    name: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct Range {
+    #[serde(rename = "type", default)]
+    kind: String,
+    #[serde(default)]
+    events: Vec<Event>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Event {
+    Introduced(String),
+    Fixed(String),
+    LastAffected(String),
+    Limit(String),
+}
+
+impl Advisory {
+    /// The CVE IDs among the record's ID and aliases, else the record's own ID.
+    pub fn cve_ids(&self) -> Vec<&str> {
+        let cves: Vec<&str> = std::iter::once(&self.id)
+            .chain(&self.aliases)
+            .map(String::as_str)
+            .filter(|id| id.starts_with("CVE-"))
+            .collect();
+        if cves.is_empty() {
+            vec![self.id.as_str()]
+        } else {
+            cves
+        }
+    }
+
+    /// The highest severity the record states for itself or for `affected`.
+    fn severity_of(&self, affected: &Affected) -> Severity {
+        let labelled = [
+            &self.database_specific,
+            &affected.database_specific,
+            &affected.ecosystem_specific,
+        ]
+        .into_iter()
+        .flatten()
+        .filter_map(|v| v.get("severity").and_then(Value::as_str))
+        .filter_map(Severity::parse);
+        let scored = self
+            .severity
+            .iter()
+            .filter_map(|s| s.score.trim().parse::<f64>().ok())
+            .map(Severity::from_score);
+        labelled.chain(scored).max().unwrap_or(Severity::Unknown)
+    }
+}
+
+/// An advisory that applies to a package version, with the severity it has for it.
+#[derive(Debug, Clone)]
+pub struct AdvisoryMatch<'a> {
+    pub advisory: &'a Advisory,
+    pub severity: Severity,
+}
+
+/// The parsed advisory feed.
+#[derive(Debug, Clone, Default)]
+pub struct AdvisoryIndex {
+    advisories: Vec<Advisory>,
+}
+
+impl AdvisoryIndex {
+    /// Parses a feed in any of the shapes the module documentation lists. Records that don't
+    /// parse are skipped with a debug message.
+    pub fn parse(json: &str) -> Result<Self> {
+        let value: Value = serde_json::from_str(json)
+            .map_err(|e| SpsError::ValidationError(format!("Advisory feed is not JSON: {e}")))?;
+        let records = match value {
+            Value::Array(records) => records,
+            Value::Object(mut object) => match object.remove("vulns") {
+                Some(Value::Array(records)) => records,
+                _ => vec![Value::Object(object)],
+            },
+            _ => {
+                return Err(SpsError::ValidationError(
+                    "Advisory feed is neither a record nor a list of records".to_string(),
+                ))
+            }
+        };
+        let advisories = records
+            .into_iter()
+            .filter_map(|record| match serde_json::from_value::<Advisory>(record) {
+                Ok(advisory) => Some(advisory),
+                Err(e) => {
+                    debug!("Skipping unparseable advisory record: {}", e);
+                    None
+                }
+            })
+            .collect();
+        Ok(Self { advisories })
+    }
+
+    pub fn len(&self) -> usize {
+        self.advisories.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.advisories.is_empty()
+    }
+
+    /// The advisories affecting `name` at `version`, most severe first.
+    pub fn matches(&self, name: &str, version: &str) -> Vec<AdvisoryMatch<'_>> {
+        let name = normalize_name(name);
+        let version = PkgVersion::parse(version).with_revision(0);
+        let mut found: Vec<AdvisoryMatch<'_>> = Vec::new();
+        for advisory in &self.advisories {
+            let severity = advisory
+                .affected
+                .iter()
+                .filter(|affected| names_package(affected, &name))
+                .filter(|affected| affects_version(affected, &version))
+                .map(|affected| advisory.severity_of(affected))
+                .max();
+            if let Some(severity) = severity {
+                found.push(AdvisoryMatch { advisory, severity });
+            }
+        }
+        found.sort_by(|a, b| {
+            b.severity
+                .cmp(&a.severity)
+                .then_with(|| a.advisory.id.cmp(&b.advisory.id))
+        });
+        found
+    }
+}
+
+/// Where the downloaded feed is cached.
+pub fn index_path(config: &Config) -> PathBuf {
+    config.cache_dir.join(INDEX_FILE)
+}
+
+/// Stores a freshly downloaded feed, after checking that it parses.
+pub fn store_index(config: &Config, json: &str) -> Result<AdvisoryIndex> {
+    let index = AdvisoryIndex::parse(json)?;
+    fs::write(index_path(config), json)?;
+    Ok(index)
+}
+
+/// The cached feed, or `None` if `sps update` hasn't downloaded one yet.
+pub fn load_index(config: &Config) -> Result<Option<AdvisoryIndex>> {
+    match fs::read_to_string(index_path(config)) {
+        Ok(json) => AdvisoryIndex::parse(&json).map(Some),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Lowercase, without a `homebrew/core/` prefix, so advisory names match formula names.
+fn normalize_name(name: &str) -> String {
+    canonical_formula_name(name.trim()).to_ascii_lowercase()
+}
+
+fn names_package(affected: &Affected, name: &str) -> bool {
+    affected.package.as_ref().is_some_and(|package| {
+        (package.ecosystem.is_empty() || package.ecosystem.eq_ignore_ascii_case("homebrew"))
+            && normalize_name(&package.name) == name
+    })
+}
+
+fn affects_version(affected: &Affected, version: &PkgVersion) -> bool {
+    affected
+        .versions
+        .iter()
+        .any(|listed| PkgVersion::parse(listed).with_revision(0) == *version)
+        || affected
+            .ranges
+            .iter()
+            .filter(|range| range.kind == "ECOSYSTEM" || range.kind == "SEMVER")
+            .any(|range| range_contains(range, version))
+}
+
+/// Evaluates a range's events in version order: an `introduced` at or below `version` opens
+/// it, a `fixed` or `limit` at or below closes it, and a `last_affected` below closes it.
+fn range_contains(range: &Range, version: &PkgVersion) -> bool {
+    let bound = |event: &Event| match event {
+        Event::Introduced(v) if v == "0" => None,
+        Event::Introduced(v) | Event::Fixed(v) | Event::LastAffected(v) | Event::Limit(v) => {
+            Some(PkgVersion::parse(v))
+        }
+    };
+    let mut events: Vec<(Option<PkgVersion>, &Event)> =
+        range.events.iter().map(|e| (bound(e), e)).collect();
+    // `None` (introduced "0") sorts first, as the lowest version.
+    events.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+    let mut affected = false;
+    for (bound, event) in events {
+        let order = bound.as_ref().map_or(Ordering::Greater, |b| version.cmp(b));
+        match event {
+            Event::Introduced(_) if order != Ordering::Less => affected = true,
+            Event::Fixed(_) | Event::Limit(_) if order != Ordering::Less => affected = false,
+            Event::LastAffected(_) if order == Ordering::Greater => affected = false,
+            _ => {}
+        }
+    }
+    affected
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    /// OSV records shaped like the ones published for Homebrew formulae.
+    fn feed() -> Value {
+        json!([
+            {
+                "id": "GHSA-aaaa-0001",
+                "aliases": ["CVE-2023-0001"],
+                "summary": "Overflow in the record parser",
+                "database_specific": { "severity": "HIGH" },
+                "affected": [{
+                    "package": { "ecosystem": "Homebrew", "name": "homebrew/core/OpenSSL@3" },
+                    "ranges": [{
+                        "type": "ECOSYSTEM",
+                        "events": [{ "introduced": "0" }, { "fixed": "3.1.4" }],
+                    }],
+                }],
+            },
+            {
+                "id": "OSV-2024-0002",
+                "summary": "Two vulnerable release lines",
+                "severity": [{ "type": "CVSS_V3", "score": "9.8" }],
+                "affected": [{
+                    "package": { "name": "libfoo" },
+                    "ranges": [{
+                        "type": "SEMVER",
+                        "events": [
+                            { "introduced": "3.0" },
+                            { "last_affected": "3.1" },
+                            { "introduced": "2.0" },
+                            { "fixed": "2.4" },
+                        ],
+                    }],
+                }],
+            },
+            {
+                "id": "OSV-2024-0003",
+                "affected": [{
+                    "package": { "ecosystem": "Homebrew", "name": "libfoo" },
+                    "ranges": [
+                        {
+                            "type": "ECOSYSTEM",
+                            "events": [{ "introduced": "5.0" }, { "limit": "5.2" }],
+                        },
+                        { "type": "GIT", "events": [{ "introduced": "0" }] },
+                    ],
+                    "versions": ["1.0.2"],
+                    "ecosystem_specific": { "severity": "low" },
+                }],
+            },
+            {
+                "id": "PYSEC-2024-0004",
+                "database_specific": { "severity": "CRITICAL" },
+                "affected": [{
+                    "package": { "ecosystem": "PyPI", "name": "libfoo" },
+                    "ranges": [{ "type": "ECOSYSTEM", "events": [{ "introduced": "0" }] }],
+                }],
+            },
+        ])
+    }
+
+    fn index() -> AdvisoryIndex {
+        AdvisoryIndex::parse(&feed().to_string()).unwrap()
+    }
+
+    /// IDs of the advisories affecting `name` at `version`, in reporting order.
+    fn ids(name: &str, version: &str) -> Vec<String> {
+        index()
+            .matches(name, version)
+            .iter()
+            .map(|found| found.advisory.id.clone())
+            .collect()
+    }
+
+    #[test]
+    fn names_are_compared_lowercased_without_the_core_tap() {
+        assert_eq!(normalize_name(" homebrew/core/OpenSSL@3 "), "openssl@3");
+        assert_eq!(normalize_name("someone/tap/foo"), "someone/tap/foo");
+        for name in ["openssl@3", "OpenSSL@3", "homebrew/core/openssl@3"] {
+            assert_eq!(ids(name, "3.1.3"), ["GHSA-aaaa-0001"], "{name}");
+        }
+        assert!(ids("openssl@1.1", "1.1.1").is_empty());
+    }
+
+    #[test]
+    fn only_homebrew_or_unnamed_ecosystems_match() {
+        // The PyPI record covers every version and would otherwise match each of these.
+        for version in ["0.1", "2.4", "4.0"] {
+            assert!(
+                !ids("libfoo", version).contains(&"PYSEC-2024-0004".to_string()),
+                "{version}"
+            );
+        }
+    }
+
+    #[test]
+    fn ranges_open_at_introduced_and_close_at_fixed_limit_or_after_last_affected() {
+        let cases = [
+            ("1.9", false),
+            ("2.0", true),
+            ("2.3.9", true),
+            ("2.4", false),
+            ("2.9", false),
+            ("3.0", true),
+            ("3.1", true),
+            ("3.1.1", false),
+        ];
+        for (version, affected) in cases {
+            assert_eq!(
+                ids("libfoo", version) == ["OSV-2024-0002"],
+                affected,
+                "{version}"
+            );
+        }
+        assert_eq!(ids("openssl@3", "0.9"), ["GHSA-aaaa-0001"]);
+        assert!(ids("openssl@3", "3.1.4").is_empty());
+        assert!(ids("openssl@3", "3.2").is_empty());
+        assert_eq!(ids("libfoo", "5.1.9"), ["OSV-2024-0003"]);
+        assert!(ids("libfoo", "5.2").is_empty());
+    }
+
+    #[test]
+    fn listed_versions_match_and_git_ranges_and_revisions_are_ignored() {
+        assert_eq!(ids("libfoo", "1.0.2"), ["OSV-2024-0003"]);
+        // The GIT range is open from the start; only the listed version matches.
+        assert!(ids("libfoo", "1.0.3").is_empty());
+        assert_eq!(ids("openssl@3", "3.1.3_2"), ["GHSA-aaaa-0001"]);
+        assert_eq!(ids("libfoo", "1.0.2_1"), ["OSV-2024-0003"]);
+    }
+
+    #[test]
+    fn severity_comes_from_labels_or_cvss_scores_and_orders_the_matches() {
+        let scored = AdvisoryIndex::parse(
+            &json!([
+                {
+                    "id": "OSV-B",
+                    "severity": [{ "score": "5.3" }],
+                    "affected": [{ "package": { "name": "jq" }, "versions": ["1.6"] }],
+                },
+                {
+                    "id": "OSV-A",
+                    "severity": [{ "score": "CVSS:3.1/AV:N/AC:L/PR:N/UI:N/S:U/C:H/I:H/A:H" }],
+                    "affected": [{ "package": { "name": "jq" }, "versions": ["1.6"] }],
+                },
+                {
+                    "id": "OSV-C",
+                    "severity": [{ "score": "4.0" }],
+                    "database_specific": { "severity": "critical" },
+                    "affected": [{ "package": { "name": "jq" }, "versions": ["1.6"] }],
+                },
+            ])
+            .to_string(),
+        )
+        .unwrap();
+
+        let found: Vec<(&str, Severity)> = scored
+            .matches("jq", "1.6")
+            .iter()
+            .map(|found| (found.advisory.id.as_str(), found.severity))
+            .collect();
+
+        assert_eq!(
+            found,
+            [
+                ("OSV-C", Severity::Critical),
+                ("OSV-B", Severity::Moderate),
+                ("OSV-A", Severity::Unknown),
+            ]
+        );
+        let index = index();
+        let matches = index.matches("libfoo", "3.0");
+        assert_eq!(matches[0].severity, Severity::Critical);
+        assert!(matches[0].severity.blocks() && !Severity::Moderate.blocks());
+        assert_eq!(matches[0].advisory.cve_ids(), ["OSV-2024-0002"]);
+        assert_eq!(
+            index.matches("openssl@3", "3.0")[0].advisory.cve_ids(),
+            ["CVE-2023-0001"]
+        );
+    }
+
+    #[test]
+    fn feeds_parse_as_a_list_a_query_response_or_one_record() {
+        let record = feed()[0].clone();
+        for shape in [
+            json!([record.clone(), { "id": 42 }]),
+            json!({ "vulns": [record.clone()] }),
+            record,
+        ] {
+            let index = AdvisoryIndex::parse(&shape.to_string()).unwrap();
+            assert_eq!(index.len(), 1, "{shape}");
+        }
+        assert!(AdvisoryIndex::parse("\"advisories\"").is_err());
+        assert!(AdvisoryIndex::parse("<html>").is_err());
+    }
+
+    #[test]
+    fn a_missing_cache_loads_as_none_and_a_stored_feed_round_trips() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            cache_dir: dir.path().to_path_buf(),
+            ..Config::load().unwrap()
+        };
+        assert!(load_index(&config).unwrap().is_none());
+
+        assert!(store_index(&config, "not json").is_err());
+        assert!(!index_path(&config).exists());
+
+        store_index(&config, &feed().to_string()).unwrap();
+        assert_eq!(load_index(&config).unwrap().unwrap().len(), 4);
+    }
+}
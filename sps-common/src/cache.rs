@@ -16,10 +16,29 @@ use super::error::{Result, SpsError};
 const CACHE_SUBDIR: &str = "sps";
 // Define how long cache entries are considered valid
 const CACHE_TTL: Duration = Duration::from_secs(24 * 60 * 60); // 24 hours
+/// Remembers the download root of the previous run, to detect relocations
+const LAST_DOWNLOAD_DIR_FILE: &str = ".sps_last_download_dir";
+// Subdirectory of the download root holding bottle archives
+const BOTTLE_SUBDIR: &str = "bottles";
+// Subdirectory of the cache holding formula definitions fetched one at a time
+const FORMULA_SUBDIR: &str = "formula";
+// Appended to a cached artifact's name for the marker recording the checksum it matched
+const VERIFIED_SUFFIX: &str = ".verified";
+
+/// What [`Cache::evict_stale_bottles`] removed.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct EvictedBottles {
+    /// Bottles of another rebuild or digest: the bottle was rebuilt upstream.
+    pub stale: usize,
+    /// Bottles cached under the unkeyed `<stem>.tar.gz` name that didn't match the expected
+    /// checksum, so they couldn't be kept.
+    pub legacy: usize,
+}
 
 /// Cache struct to manage cache operations
 pub struct Cache {
     cache_dir: PathBuf,
+    download_dir: PathBuf,
 }
 
 impl Cache {
@@ -30,14 +49,194 @@ impl Cache {
 
         Ok(Self {
             cache_dir: cache_dir.to_path_buf(),
+            download_dir: cache_dir.to_path_buf(),
         })
     }
 
+    /// Creates a cache whose downloaded artifacts live in `download_dir` while API metadata stays
+    /// in `cache_dir`. Warns if the previous run used a different download root that still holds
+    /// artifacts, since those would otherwise be orphaned silently.
+    pub fn with_download_dir(cache_dir: &Path, download_dir: &Path) -> Result<Self> {
+        let mut cache = Self::new(cache_dir)?;
+        if !download_dir.exists() {
+            fs::create_dir_all(download_dir)?;
+        }
+        cache.download_dir = download_dir.to_path_buf();
+        cache.check_download_dir_relocation();
+        Ok(cache)
+    }
+
     /// Gets the cache directory path
     pub fn get_dir(&self) -> &Path {
         &self.cache_dir
     }
 
+    /// Gets the root directory for downloaded artifacts
+    pub fn get_download_dir(&self) -> &Path {
+        &self.download_dir
+    }
+
+    /// Gets the bottle cache directory, creating it if necessary
+    pub fn bottle_dir(&self) -> Result<PathBuf> {
+        let dir = self.download_dir.join(BOTTLE_SUBDIR);
+        fs::create_dir_all(&dir)?;
+        Ok(dir)
+    }
+
+    /// Gets the cache path for a bottle archive named `filename`
+    pub fn bottle_path(&self, filename: &str) -> Result<PathBuf> {
+        Ok(self.bottle_dir()?.join(filename))
+    }
+
+    /// Returns the cached bottle `filename` if it is present
+    pub fn cached_bottle(&self, filename: &str) -> Result<Option<PathBuf>> {
+        let path = self.bottle_path(filename)?;
+        Ok(path.is_file().then_some(path))
+    }
+
+    /// Removes an artifact that failed verification or was only partially downloaded
+    pub fn discard_artifact(&self, path: &Path) {
+        let _ = fs::remove_file(verified_marker(path));
+        if let Err(e) = fs::remove_file(path) {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                tracing::debug!("Failed to remove cached artifact {}: {}", path.display(), e);
+            }
+        }
+    }
+
+    /// Records that the artifact at `path` matched `sha256`, so a later run (such as a retry of
+    /// a failed install) reuses it without hashing it again. Best effort.
+    pub fn mark_verified(&self, path: &Path, sha256: &str) {
+        if sha256.is_empty() {
+            return;
+        }
+        let Some(stamp) = file_stamp(path) else {
+            return;
+        };
+        let marker = verified_marker(path);
+        if let Err(e) = fs::write(
+            &marker,
+            format!("{} {stamp}\n", sha256.to_ascii_lowercase()),
+        ) {
+            tracing::debug!("Failed to write {}: {}", marker.display(), e);
+        }
+    }
+
+    /// Whether the artifact at `path` was verified against `sha256` and has not changed since:
+    /// it still has the size and modification time it had then.
+    pub fn is_verified(&self, path: &Path, sha256: &str) -> bool {
+        if sha256.is_empty() {
+            return false;
+        }
+        let Some(stamp) = file_stamp(path) else {
+            return false;
+        };
+        fs::read_to_string(verified_marker(path))
+            .is_ok_and(|marker| marker.trim() == format!("{} {stamp}", sha256.to_ascii_lowercase()))
+    }
+
+    /// Cached artifacts an earlier run verified, with the checksum each one matched then,
+    /// sorted by path. Covers bottles and the downloads kept directly in the download root.
+    pub fn verified_artifacts(&self) -> Vec<(PathBuf, String)> {
+        let mut artifacts = Vec::new();
+        for dir in [
+            self.download_dir.clone(),
+            self.download_dir.join(BOTTLE_SUBDIR),
+        ] {
+            let Ok(entries) = fs::read_dir(&dir) else {
+                continue;
+            };
+            for entry in entries.flatten() {
+                let marker = entry.path();
+                let Some(name) = marker.file_name().and_then(|n| n.to_str()) else {
+                    continue;
+                };
+                let Some(artifact) = name.strip_suffix(VERIFIED_SUFFIX) else {
+                    continue;
+                };
+                let artifact = dir.join(artifact);
+                let sha256 = fs::read_to_string(&marker)
+                    .ok()
+                    .and_then(|m| m.split_whitespace().next().map(str::to_string));
+                if let (true, Some(sha256)) = (artifact.is_file(), sha256) {
+                    artifacts.push((artifact, sha256));
+                }
+            }
+        }
+        artifacts.sort();
+        artifacts
+    }
+
+    /// Removes cached bottles for the same formula version and platform whose name (rebuild
+    /// number, digest or origin) differs from `current`. An entry that `verify` accepts is the
+    /// expected bottle under an older naming scheme and is renamed to `current` instead.
+    pub fn evict_stale_bottles(
+        &self,
+        stem: &str,
+        current: &str,
+        verify: impl Fn(&Path) -> bool,
+    ) -> EvictedBottles {
+        let mut evicted = EvictedBottles::default();
+        let dir = self.download_dir.join(BOTTLE_SUBDIR);
+        let Ok(entries) = fs::read_dir(&dir) else {
+            return evicted;
+        };
+        let prefix = format!("{stem}.");
+        let legacy = format!("{stem}.tar.gz");
+        for entry in entries.flatten() {
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            if name == current || !name.starts_with(&prefix) || !name.ends_with(".tar.gz") {
+                continue;
+            }
+            let _ = fs::remove_file(verified_marker(&entry.path()));
+            if name == legacy {
+                // Named before rebuilds and digests were part of the key, so it says nothing
+                // about whether the bottle changed upstream.
+                if !migrate_legacy_entry(&entry.path(), &dir.join(current), &verify) {
+                    evicted.legacy += 1;
+                }
+                continue;
+            }
+            let (path, keyed) = (entry.path(), dir.join(current));
+            if !keyed.exists() && verify(&path) && fs::rename(&path, &keyed).is_ok() {
+                tracing::debug!(
+                    "Renamed cached bottle {} to {}",
+                    path.display(),
+                    keyed.display()
+                );
+                continue;
+            }
+            tracing::debug!("Evicting stale cached bottle {}", path.display());
+            match fs::remove_file(&path) {
+                Ok(()) => evicted.stale += 1,
+                Err(e) => tracing::warn!(
+                    "Failed to remove stale cached bottle {}: {}",
+                    path.display(),
+                    e
+                ),
+            }
+        }
+        evicted
+    }
+
+    fn check_download_dir_relocation(&self) {
+        let marker = self.cache_dir.join(LAST_DOWNLOAD_DIR_FILE);
+        let previous = fs::read_to_string(&marker)
+            .map(|s| PathBuf::from(s.trim()))
+            .unwrap_or_else(|_| self.cache_dir.clone());
+        if previous != self.download_dir && contains_artifacts(&previous) {
+            tracing::warn!(
+                "Download directory changed from {} to {}; the old location still contains downloaded artifacts. Remove them or move them to the new location to reuse them.",
+                previous.display(),
+                self.download_dir.display()
+            );
+        }
+        if let Err(e) = fs::write(&marker, self.download_dir.to_string_lossy().as_bytes()) {
+            tracing::debug!("Failed to record download directory: {}", e);
+        }
+    }
+
     /// Stores raw string data in the cache
     pub fn store_raw(&self, filename: &str, data: &str) -> Result<()> {
         let path = self.cache_dir.join(filename);
@@ -76,6 +275,61 @@ impl Cache {
         Ok(age <= CACHE_TTL)
     }
 
+    /// Marks a cache file as fresh again, e.g. after the server said it is unchanged
+    pub fn touch(&self, filename: &str) -> Result<()> {
+        let file = fs::OpenOptions::new()
+            .append(true)
+            .open(self.cache_dir.join(filename))?;
+        file.set_modified(SystemTime::now())?;
+        Ok(())
+    }
+
+    /// Loads the ETag the server sent with a cache file, if one was stored
+    pub fn load_etag(&self, filename: &str) -> Option<String> {
+        fs::read_to_string(self.cache_dir.join(format!("{filename}.etag")))
+            .ok()
+            .map(|etag| etag.trim().to_string())
+            .filter(|etag| !etag.is_empty())
+    }
+
+    /// Stores the ETag for a cache file, or removes the stored one when the server sent none
+    pub fn store_etag(&self, filename: &str, etag: Option<&str>) -> Result<()> {
+        let path = self.cache_dir.join(format!("{filename}.etag"));
+        match etag {
+            Some(etag) => fs::write(&path, etag)?,
+            None if path.exists() => fs::remove_file(&path)?,
+            None => {}
+        }
+        Ok(())
+    }
+
+    /// Stores the JSON of one formula fetched on its own, next to the full index. Names that
+    /// can't be a file name (tapped `user/repo/name`) are not stored.
+    pub fn store_formula(&self, name: &str, data: &str) -> Result<()> {
+        let Some(filename) = formula_file(name) else {
+            return Ok(());
+        };
+        fs::create_dir_all(self.cache_dir.join(FORMULA_SUBDIR))?;
+        self.store_raw(&filename, data)
+    }
+
+    /// Loads the JSON stored by [`Self::store_formula`], along with whether it is within the TTL
+    pub fn load_formula(&self, name: &str) -> Option<(String, bool)> {
+        let filename = formula_file(name)?;
+        let data = self.load_raw(&filename).ok()?;
+        let fresh = self.is_cache_valid(&filename).unwrap_or(false);
+        Some((data, fresh))
+    }
+
+    /// Removes every formula stored by [`Self::store_formula`]
+    pub fn clear_formulae(&self) -> Result<()> {
+        let dir = self.cache_dir.join(FORMULA_SUBDIR);
+        if dir.exists() {
+            fs::remove_dir_all(&dir)?;
+        }
+        Ok(())
+    }
+
     /// Clears a specific cache file
     pub fn clear_file(&self, filename: &str) -> Result<()> {
         let path = self.cache_dir.join(filename);
@@ -95,6 +349,120 @@ impl Cache {
     }
 }
 
+/// The cache file name for a formula fetched on its own, e.g. `formula/wget.json`.
+fn formula_file(name: &str) -> Option<String> {
+    let valid = !name.is_empty() && !name.starts_with('.') && !name.contains(['/', '\\']);
+    valid.then(|| format!("{FORMULA_SUBDIR}/{name}.json"))
+}
+
+/// Size and modification time of the file at `path`, which change when it is rewritten.
+fn file_stamp(path: &Path) -> Option<String> {
+    let metadata = fs::metadata(path).ok()?;
+    let modified = metadata
+        .modified()
+        .ok()?
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .ok()?;
+    Some(format!("{} {}", metadata.len(), modified.as_nanos()))
+}
+
+/// Where the verification marker of the artifact at `path` is kept: beside it.
+fn verified_marker(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(VERIFIED_SUFFIX);
+    path.with_file_name(name)
+}
+
+/// Whether `dir` holds any downloaded artifacts (bottles, sources, resources, cask archives).
+fn contains_artifacts(dir: &Path) -> bool {
+    let non_empty = |p: PathBuf| {
+        fs::read_dir(p)
+            .map(|mut it| it.next().is_some())
+            .unwrap_or(false)
+    };
+    if [BOTTLE_SUBDIR, "sources", "resources"]
+        .iter()
+        .any(|sub| non_empty(dir.join(sub)))
+    {
+        return true;
+    }
+    fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .flatten()
+                .any(|e| e.file_name().to_string_lossy().starts_with("cask-"))
+        })
+        .unwrap_or(false)
+}
+
+/// Distinguishes download cache entries that share a file name but may differ in content: the
+/// first 12 hex digits of the expected SHA-256 when there is one, otherwise the host the artifact
+/// comes from. Keeps mirrors (and prefixes configured with different mirrors) sharing one
+/// download directory from reusing each other's artifacts.
+pub fn origin_tag(sha256: &str, url: &str) -> String {
+    if sha256.len() == 64 && sha256.chars().all(|c| c.is_ascii_hexdigit()) {
+        return sha256[..12].to_ascii_lowercase();
+    }
+    let authority = url
+        .split_once("://")
+        .map_or(url, |(_, rest)| rest)
+        .split(['/', '?', '#'])
+        .next()
+        .unwrap_or_default();
+    let host = authority.rsplit('@').next().unwrap_or_default();
+    let host: String = host
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '.' || c == '-' {
+                c.to_ascii_lowercase()
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if host.is_empty() {
+        "unknown-origin".to_string()
+    } else {
+        host
+    }
+}
+
+/// Moves a download cached under a name from before [`origin_tag`] keys to `keyed` if `verify`
+/// accepts its contents, and evicts it otherwise (it may have come from another mirror). Returns
+/// whether `keyed` now holds the migrated file. Does nothing if `legacy` is not a file or
+/// `keyed` already exists, apart from removing the redundant legacy entry in the latter case.
+pub fn migrate_legacy_entry(legacy: &Path, keyed: &Path, verify: impl Fn(&Path) -> bool) -> bool {
+    if !legacy.is_file() {
+        return false;
+    }
+    if !keyed.exists() && verify(legacy) {
+        match fs::rename(legacy, keyed) {
+            Ok(()) => {
+                tracing::debug!(
+                    "Migrated cached download {} to {}",
+                    legacy.display(),
+                    keyed.display()
+                );
+                return true;
+            }
+            Err(e) => tracing::debug!(
+                "Failed to migrate cached download {}: {}",
+                legacy.display(),
+                e
+            ),
+        }
+    }
+    tracing::debug!("Evicting legacy cached download {}", legacy.display());
+    if let Err(e) = fs::remove_file(legacy) {
+        tracing::warn!(
+            "Failed to remove legacy cached download {}: {}",
+            legacy.display(),
+            e
+        );
+    }
+    false
+}
+
 /// Gets the path to the application's cache directory, creating it if necessary.
 /// Uses dirs::cache_dir() to find the appropriate system cache location.
 pub fn get_cache_dir() -> Result<PathBuf> {
@@ -181,3 +549,195 @@ pub fn is_cache_valid(filename: &str) -> Result<bool> {
         .map_err(|e| SpsError::Cache(format!("System time error: {e}")))?;
     Ok(age <= CACHE_TTL)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STEM: &str = "jq-1.7.1.arm64_sonoma.bottle";
+
+    fn cache_with_bottles(names: &[&str]) -> (tempfile::TempDir, Cache) {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = Cache::new(dir.path()).unwrap();
+        for name in names {
+            fs::write(cache.bottle_path(name).unwrap(), name).unwrap();
+        }
+        (dir, cache)
+    }
+
+    fn bottles(cache: &Cache) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(cache.bottle_dir().unwrap())
+            .unwrap()
+            .flatten()
+            .map(|e| e.file_name().to_string_lossy().to_string())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn downloads_go_to_the_download_dir_while_metadata_stays_in_the_cache_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache_dir = dir.path().join("cache");
+        let download_dir = dir.path().join("downloads/sps");
+
+        let cache = Cache::with_download_dir(&cache_dir, &download_dir).unwrap();
+        cache.store_raw("formula.json", "[]").unwrap();
+
+        assert!(download_dir.is_dir());
+        assert_eq!(cache.get_dir(), cache_dir);
+        assert_eq!(cache.get_download_dir(), download_dir);
+        assert_eq!(
+            cache.bottle_path("jq.tar.gz").unwrap(),
+            download_dir.join("bottles/jq.tar.gz")
+        );
+        assert!(cache_dir.join("formula.json").is_file());
+        assert!(!download_dir.join("formula.json").exists());
+        assert_eq!(
+            fs::read_to_string(cache_dir.join(LAST_DOWNLOAD_DIR_FILE)).unwrap(),
+            download_dir.to_string_lossy()
+        );
+    }
+
+    #[test]
+    fn only_bottles_sources_resources_and_cask_downloads_count_as_artifacts() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("formula.json"), "[]").unwrap();
+        fs::create_dir(dir.path().join(BOTTLE_SUBDIR)).unwrap();
+        assert!(!contains_artifacts(dir.path()));
+
+        fs::write(dir.path().join("cask-viewer-abcdef012345-viewer.dmg"), "").unwrap();
+        assert!(contains_artifacts(dir.path()));
+
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("sources/ghcr.io")).unwrap();
+        assert!(contains_artifacts(dir.path()));
+    }
+
+    #[test]
+    fn evicts_other_rebuilds_and_digests_of_the_same_bottle() {
+        let current = format!("{STEM}.2.aaaa.tar.gz");
+        let (_dir, cache) = cache_with_bottles(&[
+            &current,
+            &format!("{STEM}.1.aaaa.tar.gz"),
+            &format!("{STEM}.2.bbbb.tar.gz"),
+            "jq-1.7.1.x86_64_sonoma.bottle.aaaa.tar.gz",
+            "jql-1.0.arm64_sonoma.bottle.aaaa.tar.gz",
+        ]);
+
+        let evicted = cache.evict_stale_bottles(STEM, &current, |_| false);
+
+        assert_eq!(
+            evicted,
+            EvictedBottles {
+                stale: 2,
+                legacy: 0
+            }
+        );
+        assert_eq!(
+            bottles(&cache),
+            [
+                "jq-1.7.1.arm64_sonoma.bottle.2.aaaa.tar.gz",
+                "jq-1.7.1.x86_64_sonoma.bottle.aaaa.tar.gz",
+                "jql-1.0.arm64_sonoma.bottle.aaaa.tar.gz",
+            ]
+        );
+    }
+
+    #[test]
+    fn a_legacy_name_is_not_a_rebuild() {
+        let current = format!("{STEM}.aaaa.tar.gz");
+        let (_dir, cache) = cache_with_bottles(&[&format!("{STEM}.tar.gz")]);
+
+        let evicted = cache.evict_stale_bottles(STEM, &current, |_| false);
+
+        assert_eq!(
+            evicted,
+            EvictedBottles {
+                stale: 0,
+                legacy: 1
+            }
+        );
+        assert!(bottles(&cache).is_empty());
+    }
+
+    #[test]
+    fn an_entry_with_the_expected_contents_is_renamed_instead() {
+        let current = format!("{STEM}.aaaa.tar.gz");
+        for old in [format!("{STEM}.tar.gz"), format!("{STEM}.bbbb.tar.gz")] {
+            let (_dir, cache) = cache_with_bottles(&[&old]);
+
+            let evicted = cache.evict_stale_bottles(STEM, &current, |_| true);
+
+            assert_eq!(evicted, EvictedBottles::default(), "{old}");
+            assert_eq!(bottles(&cache), [current.as_str()], "{old}");
+            assert_eq!(
+                fs::read_to_string(cache.bottle_path(&current).unwrap()).unwrap(),
+                old
+            );
+        }
+    }
+
+    #[test]
+    fn eviction_drops_verified_markers() {
+        let current = format!("{STEM}.aaaa.tar.gz");
+        let stale = format!("{STEM}.1.aaaa.tar.gz");
+        let (_dir, cache) = cache_with_bottles(&[&stale]);
+        let stale_path = cache.bottle_path(&stale).unwrap();
+        cache.mark_verified(&stale_path, "aaaa");
+
+        cache.evict_stale_bottles(STEM, &current, |_| false);
+
+        assert!(bottles(&cache).is_empty());
+    }
+
+    #[test]
+    fn origin_tags_prefer_the_digest_and_fall_back_to_the_host() {
+        let sha = "ABCDEF0123456789abcdef0123456789abcdef0123456789abcdef0123456789";
+        let cases = [
+            (sha, "https://ghcr.io/x", "abcdef012345"),
+            ("", "https://ghcr.io/v2/homebrew/core/jq", "ghcr.io"),
+            (
+                "",
+                "https://user:pw@Mirror.Example:8443/jq?x=1",
+                "mirror.example_8443",
+            ),
+            ("abcd", "https://mirror.example/jq", "mirror.example"),
+            ("", "jq-1.7.1.tar.gz", "jq-1.7.1.tar.gz"),
+            ("", "", "unknown-origin"),
+        ];
+        for (sha256, url, expected) in cases {
+            assert_eq!(origin_tag(sha256, url), expected, "{url}");
+        }
+        // The same file from two mirrors, with no digest to tell them apart.
+        assert_ne!(
+            origin_tag("", "https://a.example/jq.tar.gz"),
+            origin_tag("", "https://b.example/jq.tar.gz")
+        );
+    }
+
+    #[test]
+    fn a_legacy_entry_is_renamed_when_verified_and_evicted_otherwise() {
+        let dir = tempfile::tempdir().unwrap();
+        let legacy = dir.path().join("cask-foo-foo.dmg");
+        let keyed = dir.path().join("cask-foo-abcdef012345-foo.dmg");
+
+        fs::write(&legacy, "ok").unwrap();
+        assert!(migrate_legacy_entry(&legacy, &keyed, |_| true));
+        assert!(!legacy.exists());
+        assert_eq!(fs::read_to_string(&keyed).unwrap(), "ok");
+
+        fs::write(&legacy, "from another mirror").unwrap();
+        assert!(!migrate_legacy_entry(&legacy, &keyed, |_| true));
+        assert!(!legacy.exists(), "redundant once the keyed entry exists");
+        assert_eq!(fs::read_to_string(&keyed).unwrap(), "ok");
+
+        fs::remove_file(&keyed).unwrap();
+        fs::write(&legacy, "unverified").unwrap();
+        assert!(!migrate_legacy_entry(&legacy, &keyed, |_| false));
+        assert!(!legacy.exists());
+        assert!(!keyed.exists());
+
+        assert!(!migrate_legacy_entry(&legacy, &keyed, |_| true));
+    }
+}
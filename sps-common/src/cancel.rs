@@ -0,0 +1,113 @@
+// sps-common/src/cancel.rs
+//! Cancellation of a running install, for the CLI's Ctrl-C handler and for anything else
+//! driving the pipeline.
+//!
+//! The caller holds a [`CancellationToken`] and installs it for the work it starts: with
+//! [`with_token`] around download tasks, and with [`with_thread_token`] on the worker threads
+//! that pour. Code that can stop part way (streaming a download, unpacking an archive) asks
+//! [`check`] or [`current`] between steps. Nothing needs the token passed down explicitly, and
+//! work done outside one is never cancelled.
+
+use std::cell::RefCell;
+use std::fmt::Display;
+use std::future::Future;
+
+pub use tokio_util::sync::{CancellationToken, DropGuard};
+
+use crate::error::{Result, SpsError};
+
+tokio::task_local! {
+    static TASK_TOKEN: CancellationToken;
+}
+
+thread_local! {
+    static THREAD_TOKEN: RefCell<Option<CancellationToken>> = const { RefCell::new(None) };
+}
+
+/// Runs `fut` with `token` as the cancellation token of everything it awaits.
+pub async fn with_token<F: Future>(token: CancellationToken, fut: F) -> F::Output {
+    TASK_TOKEN.scope(token, fut).await
+}
+
+/// Runs `f` with `token` as the cancellation token of this thread.
+pub fn with_thread_token<T>(token: CancellationToken, f: impl FnOnce() -> T) -> T {
+    // Restores the previous token even if `f` panics, as worker threads are reused.
+    struct Restore(Option<CancellationToken>);
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            THREAD_TOKEN.with(|t| *t.borrow_mut() = self.0.take());
+        }
+    }
+    let _restore = Restore(THREAD_TOKEN.with(|t| t.replace(Some(token))));
+    f()
+}
+
+/// The token of the current task, else of the current thread.
+pub fn current() -> Option<CancellationToken> {
+    TASK_TOKEN
+        .try_with(CancellationToken::clone)
+        .ok()
+        .or_else(|| THREAD_TOKEN.with(|t| t.borrow().clone()))
+}
+
+/// Fails with [`SpsError::Cancelled`] once the current token is cancelled; `what` names the
+/// work that stopped.
+pub fn check(what: &dyn Display) -> Result<()> {
+    if current().is_some_and(|token| token.is_cancelled()) {
+        return Err(SpsError::Cancelled(format!("{what} was cancelled")));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn work_outside_a_token_is_never_cancelled() {
+        assert!(current().is_none());
+        assert!(check(&"Pouring jq").is_ok());
+    }
+
+    #[test]
+    fn a_thread_token_applies_inside_and_is_restored_after() {
+        let outer = CancellationToken::new();
+        let inner = CancellationToken::new();
+        inner.cancel();
+
+        with_thread_token(outer.clone(), || {
+            let err = with_thread_token(inner, || check(&"Pouring jq")).unwrap_err();
+            assert!(matches!(err, SpsError::Cancelled(msg) if msg == "Pouring jq was cancelled"));
+            assert!(check(&"Pouring jq").is_ok(), "the outer token is back");
+        });
+        assert!(current().is_none());
+    }
+
+    #[test]
+    fn a_thread_token_is_restored_after_a_panic() {
+        let result = std::panic::catch_unwind(|| {
+            with_thread_token(CancellationToken::new(), || panic!("job failed"))
+        });
+
+        assert!(result.is_err());
+        assert!(current().is_none());
+    }
+
+    #[test]
+    fn a_task_token_wins_over_the_threads() {
+        let task = CancellationToken::new();
+        task.cancel();
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+
+        // The thread's token isn't cancelled; the task's is, and is the one checked.
+        let cancelled = with_thread_token(CancellationToken::new(), || {
+            runtime.block_on(with_token(task, async {
+                check(&"Downloading jq").is_err()
+            }))
+        });
+
+        assert!(cancelled);
+    }
+}
@@ -5,20 +5,44 @@ use std::path::{Path, PathBuf};
 use dirs;
 use tracing::debug;
 
-use super::cache;
+use super::config_file::ConfigFile;
 use super::error::Result; // for home directory lookup
+use super::model::formula::keg_dir_name;
+use super::{cache, homebrew_env};
 
 /// Default installation prefixes
 const DEFAULT_LINUX_PREFIX: &str = "/home/linuxbrew/.linuxbrew";
 const DEFAULT_MACOS_INTEL_PREFIX: &str = "/usr/local";
 const DEFAULT_MACOS_ARM_PREFIX: &str = "/opt/homebrew";
 
+/// Downloads larger than this are refused unless the limit is raised; anything bigger almost
+/// certainly means broken metadata.
+const DEFAULT_MAX_DOWNLOAD_SIZE: u64 = 20 * 1024 * 1024 * 1024;
+
+/// Directory made for builds inside a configured temp root.
+const BUILD_TEMP_NAME: &str = "sps-build";
+/// Build directory under the cache when no temp root is configured.
+pub const DEFAULT_BUILD_TEMP_SUBDIR: &str = "build-temp";
+
+/// Default for `keg_retention_days`.
+const DEFAULT_KEG_RETENTION_DAYS: u64 = 30;
+
+/// Ceiling for parallel downloads and installs; beyond this a run only floods the mirrors.
+pub const MAX_CONCURRENT_INSTALLS: usize = 32;
+/// `auto` stays at or below this: past a handful of parallel downloads from the same CDN the
+/// throughput stops improving.
+const AUTO_CONCURRENCY_NETWORK_CAP: usize = 6;
+
 /// Determines the active prefix for installation.
-/// Checks sps_PREFIX/HOMEBREW_PREFIX env vars, then OS-specific defaults.
-fn determine_prefix() -> PathBuf {
-    if let Ok(prefix) = env::var("sps_PREFIX").or_else(|_| env::var("HOMEBREW_PREFIX")) {
+/// Checks sps_PREFIX/HOMEBREW_PREFIX env vars, then the config file, then OS-specific defaults.
+fn determine_prefix(file: Option<&ConfigFile>) -> Result<PathBuf> {
+    if let Some(prefix) = homebrew_env::var("sps_PREFIX") {
         debug!("Using prefix from environment variable: {}", prefix);
-        return PathBuf::from(prefix);
+        return Ok(PathBuf::from(prefix));
+    }
+    if let Some(prefix) = file.map(|f| f.path("prefix")).transpose()?.flatten() {
+        debug!("Using prefix from config file: {}", prefix.display());
+        return Ok(prefix);
     }
 
     let default_prefix = if cfg!(target_os = "linux") {
@@ -34,7 +58,178 @@ fn determine_prefix() -> PathBuf {
         "/usr/local/sps"
     };
     debug!("Using default prefix for OS/Arch: {}", default_prefix);
-    PathBuf::from(default_prefix)
+    Ok(PathBuf::from(default_prefix))
+}
+
+/// How much of the caller's environment is passed to builds and installer subprocesses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EnvMode {
+    /// Minimal controlled environment (PATH, HOME, TMPDIR, locale, allowlisted vars).
+    #[default]
+    Std,
+    /// Pass the user's environment through unchanged.
+    Inherit,
+}
+
+/// What happens when a poured bottle contains setuid/setgid files, world-writable entries or files
+/// owned by another user.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BottleAuditMode {
+    /// List the findings and install anyway.
+    #[default]
+    Warn,
+    /// Strip the offending mode bits and take ownership, then install.
+    Fix,
+    /// Refuse to install the bottle.
+    Strict,
+}
+
+impl BottleAuditMode {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "warn" => Some(Self::Warn),
+            "fix" => Some(Self::Fix),
+            "strict" => Some(Self::Strict),
+            _ => None,
+        }
+    }
+}
+
+/// User commands run around package operations, from the `[hooks]` section of the config file.
+/// Each is passed to `sh -c` with `sps_FORMULA`, `sps_VERSION`, `sps_KEG_PATH` and
+/// `sps_OPT_PATH` set.
+#[derive(Debug, Clone, Default)]
+pub struct Hooks {
+    /// After a formula is installed or reinstalled.
+    pub post_install: Option<String>,
+    /// Before a formula is uninstalled.
+    pub pre_uninstall: Option<String>,
+    /// After a formula is upgraded.
+    pub post_upgrade: Option<String>,
+    /// Whether a failing hook fails the operation instead of only being logged.
+    pub strict: bool,
+}
+
+/// Whether install, upgrade and uninstall counts are kept (see [`crate::metrics`]). There is no
+/// mode that sends anything anywhere.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MetricsMode {
+    #[default]
+    Off,
+    /// Keep counters in a file under the prefix.
+    Local,
+}
+
+impl MetricsMode {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "off" => Some(Self::Off),
+            "local" => Some(Self::Local),
+            _ => None,
+        }
+    }
+}
+
+/// Where formula definitions come from (see `sps_core::metadata`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MetadataStrategy {
+    /// Download the whole `formula.json` index and revalidate it with its ETag.
+    Full,
+    /// Fetch each formula's JSON when it is first needed, several at a time.
+    Lazy,
+    /// `full` when the index is already cached or many packages are asked for, `lazy` otherwise.
+    #[default]
+    Auto,
+}
+
+impl MetadataStrategy {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "full" => Some(Self::Full),
+            "lazy" => Some(Self::Lazy),
+            "auto" => Some(Self::Auto),
+            _ => None,
+        }
+    }
+}
+
+/// How keg files are placed in the shared prefix directories (`lib`, `include`, `share`). Opt
+/// links are symlinks under every strategy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LinkStrategy {
+    /// Symlinks into the keg.
+    #[default]
+    Symlink,
+    /// Real directories with hard links to the keg's files; copies where the keg is on another
+    /// filesystem.
+    Hardlink,
+    /// Real directories with copies of the keg's files.
+    Copy,
+}
+
+impl LinkStrategy {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "symlink" => Some(Self::Symlink),
+            "hardlink" => Some(Self::Hardlink),
+            "copy" => Some(Self::Copy),
+            _ => None,
+        }
+    }
+
+    /// The config value, as recorded in receipts.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Symlink => "symlink",
+            Self::Hardlink => "hardlink",
+            Self::Copy => "copy",
+        }
+    }
+}
+
+/// Whether downloaded bottles and cask artifacts must carry a detached signature by one of
+/// `signature_keys`, fetched from `<artifact url>.minisig` or `<artifact url>.sig`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SignatureMode {
+    #[default]
+    Off,
+    /// Verify, and only log a missing or bad signature.
+    Warn,
+    /// Verify, and fail the package on a missing or bad signature.
+    Require,
+}
+
+impl SignatureMode {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "off" => Some(Self::Off),
+            "warn" => Some(Self::Warn),
+            "require" => Some(Self::Require),
+            _ => None,
+        }
+    }
+}
+
+/// What a match in the advisory feed (`advisory_feed`) does to a plan.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AdvisoryMode {
+    #[default]
+    Off,
+    /// Print the advisories affecting planned packages.
+    Warn,
+    /// Print them, and fail the plan on HIGH or CRITICAL ones (unless `--allow-vulnerable`).
+    Block,
+}
+
+impl AdvisoryMode {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "off" => Some(Self::Off),
+            "warn" => Some(Self::Warn),
+            "block" => Some(Self::Block),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -43,26 +238,291 @@ pub struct Config {
     pub cellar: PathBuf,
     pub taps_dir: PathBuf,
     pub cache_dir: PathBuf,
+    /// Root for downloaded artifacts (bottles, sources, cask archives). Defaults to `cache_dir`;
+    /// API metadata always stays in `cache_dir`.
+    pub download_dir: PathBuf,
+    /// Where source builds are unpacked and built (`sps_TEMP`/`HOMEBREW_TEMP` or `temp_dir`,
+    /// plus `sps-build`; else `build-temp` under `cache_dir`).
+    pub build_temp_dir: PathBuf,
+    /// Host serving Homebrew core bottles in place of ghcr.io, as with `HOMEBREW_BOTTLE_DOMAIN`.
+    pub bottle_domain: Option<String>,
     pub api_base_url: String,
     pub artifact_domain: Option<String>,
     pub docker_registry_token: Option<String>,
     pub docker_registry_basic_auth: Option<String>,
     pub github_api_token: Option<String>,
+    pub env_mode: EnvMode,
+    /// Extra variables passed through in `EnvMode::Std` (from `sps_ENV_PASSTHROUGH`).
+    pub env_passthrough: Vec<String>,
+    /// Upper bound in bytes for a single downloaded artifact (`sps_MAX_DOWNLOAD_SIZE`).
+    pub max_download_size: u64,
+    /// Packages installed in parallel (`sps_MAX_CONCURRENT_INSTALLS`).
+    pub max_concurrent_installs: usize,
+    /// Artifacts downloaded in parallel (`sps_MAX_CONCURRENT_DOWNLOADS`); `None` follows
+    /// `max_concurrent_installs`. See [`Config::download_concurrency`].
+    pub max_concurrent_downloads: Option<usize>,
+    /// Preferred cask languages, most preferred first (`sps_LANGUAGE`, else the system locale).
+    pub cask_languages: Vec<String>,
+    /// Post-extraction audit of bottle contents (`sps_BOTTLE_AUDIT`).
+    pub bottle_audit: BottleAuditMode,
+    /// Local formula/cask definitions that take precedence over the API (`sps_OVERRIDES_DIR`).
+    pub overrides_dir: PathBuf,
+    /// Local usage counters (`sps_METRICS`).
+    pub metrics: MetricsMode,
+    /// Commands run around installs, upgrades and uninstalls (`[hooks]`, off with `--no-hooks`).
+    pub hooks: Hooks,
+    /// Run linked executables with `--version` after each install (`sps_POST_INSTALL_CHECK`,
+    /// `--verify-run`).
+    pub post_install_check: bool,
+    /// Refuse plans that would build any formula from source (`sps_BOTTLE_ONLY`,
+    /// `--bottle-only`).
+    pub bottle_only: bool,
+    /// Pour bottles of this tag instead of the host's own (`sps_BOTTLE_TAG`, `--bottle-tag`).
+    pub bottle_tag: Option<String>,
+    /// Days a keg kept from an earlier version survives `sps cleanup` after it stopped being
+    /// current (`sps_KEG_RETENTION_DAYS`).
+    pub keg_retention_days: u64,
+    /// How formula definitions are fetched (`sps_METADATA_STRATEGY`).
+    pub metadata_strategy: MetadataStrategy,
+    /// How kegs are linked into the prefix (`sps_LINK_STRATEGY`).
+    pub link_strategy: LinkStrategy,
+    /// Signature verification of downloaded artifacts (`sps_SIGNATURE_MODE`).
+    pub signature_mode: SignatureMode,
+    /// Public keys accepted for artifact signatures, each a minisign public key, a raw Ed25519
+    /// key in hex or base64, or a path to a file holding one (`sps_SIGNATURE_KEYS`).
+    pub signature_keys: Vec<String>,
+    /// Command that prints the JSON of a tap's Ruby-only formula given its path
+    /// (`sps_TAP_RUBY_COMMAND`); `None` uses `brew ruby` when Homebrew is installed.
+    pub tap_ruby_command: Option<String>,
+    /// Checking planned packages against the advisory feed (`sps_ADVISORIES`).
+    pub advisories: AdvisoryMode,
+    /// URL of an OSV-format advisory feed, downloaded by `sps update` (`sps_ADVISORY_FEED`).
+    pub advisory_feed: Option<String>,
 }
 
 impl Config {
     pub fn load() -> Result<Self> {
         debug!("Loadingspsconfiguration");
-        let prefix = determine_prefix();
+        homebrew_env::warn_unsupported();
+        // Precedence for each setting: environment variable, then the config file (host section
+        // over top level), then the built-in default. CLI flags are applied over this by main.
+        let file = ConfigFile::load()?;
+        let file = file.as_ref();
+        let file_string = |key: &str| file.map(|f| f.string(key)).transpose().map(Option::flatten);
+
+        let prefix = determine_prefix(file)?;
         let cellar = prefix.join("Cellar");
+        let overrides_dir = match env::var("sps_OVERRIDES_DIR") {
+            Ok(dir) => PathBuf::from(dir),
+            Err(_) => file
+                .map(|f| f.path("overrides_dir"))
+                .transpose()?
+                .flatten()
+                .unwrap_or_else(|| prefix.join("etc/sps/overrides")),
+        };
         let taps_dir = prefix.join("Library/Taps");
         let cache_dir = cache::get_cache_dir()?;
+        let download_dir = match homebrew_env::var("sps_DOWNLOAD_DIR") {
+            Some(dir) => PathBuf::from(dir),
+            None => file
+                .map(|f| f.path("download_dir"))
+                .transpose()?
+                .flatten()
+                .unwrap_or_else(|| cache_dir.clone()),
+        };
+        // A temp root like /tmp is shared with everything else, so builds get their own
+        // directory in it; the reaper clears out whatever is in this one.
+        let build_temp_dir = match homebrew_env::var("sps_TEMP") {
+            Some(dir) => PathBuf::from(dir).join(BUILD_TEMP_NAME),
+            None => match file.map(|f| f.path("temp_dir")).transpose()?.flatten() {
+                Some(dir) => dir.join(BUILD_TEMP_NAME),
+                None => cache_dir.join(DEFAULT_BUILD_TEMP_SUBDIR),
+            },
+        };
+        let bottle_domain = homebrew_env::var("sps_BOTTLE_DOMAIN")
+            .or(file_string("bottle_domain")?)
+            .map(|domain| domain.trim_end_matches('/').to_string())
+            .filter(|domain| !domain.is_empty());
         let api_base_url = "https://formulae.brew.sh/api".to_string();
 
-        let artifact_domain = env::var("HOMEBREW_ARTIFACT_DOMAIN").ok();
+        let artifact_domain = match env::var("HOMEBREW_ARTIFACT_DOMAIN") {
+            Ok(domain) => Some(domain),
+            Err(_) => file_string("artifact_domain")?,
+        };
         let docker_registry_token = env::var("HOMEBREW_DOCKER_REGISTRY_TOKEN").ok();
         let docker_registry_basic_auth = env::var("HOMEBREW_DOCKER_REGISTRY_BASIC_AUTH_TOKEN").ok();
         let github_api_token = env::var("HOMEBREW_GITHUB_API_TOKEN").ok();
+        let env_mode = match env::var("sps_ENV").ok().or(file_string("env")?).as_deref() {
+            Some("inherit") => EnvMode::Inherit,
+            _ => EnvMode::Std,
+        };
+        let env_passthrough = match env::var("sps_ENV_PASSTHROUGH") {
+            Ok(v) => v
+                .split(|c: char| c == ',' || c.is_whitespace())
+                .filter(|s| !s.is_empty())
+                .map(str::to_string)
+                .collect(),
+            Err(_) => file
+                .map(|f| f.string_list("env_passthrough"))
+                .transpose()?
+                .flatten()
+                .unwrap_or_default(),
+        };
+
+        let max_download_size = env::var("sps_MAX_DOWNLOAD_SIZE")
+            .ok()
+            .or(file_string("max_download_size")?)
+            .and_then(|v| parse_size(&v))
+            .unwrap_or(DEFAULT_MAX_DOWNLOAD_SIZE);
+        let max_concurrent_installs = env::var("sps_MAX_CONCURRENT_INSTALLS")
+            .ok()
+            .or(file_string("max_concurrent_installs")?)
+            .and_then(|v| parse_concurrency(&v))
+            .unwrap_or_else(auto_concurrent_installs)
+            .min(MAX_CONCURRENT_INSTALLS);
+        let max_concurrent_downloads = env::var("sps_MAX_CONCURRENT_DOWNLOADS")
+            .ok()
+            .or(file_string("max_concurrent_downloads")?)
+            .and_then(|v| parse_concurrency(&v))
+            .map(|n| n.min(MAX_CONCURRENT_INSTALLS));
+
+        let cask_languages = match homebrew_env::var("sps_LANGUAGE").or(file_string("language")?) {
+            Some(list) => parse_language_list(&list),
+            None => system_languages(),
+        };
+        let bottle_audit = match env::var("sps_BOTTLE_AUDIT")
+            .ok()
+            .or(file_string("bottle_audit")?)
+        {
+            Some(value) => BottleAuditMode::parse(&value).unwrap_or_else(|| {
+                tracing::warn!(
+                    "Unknown bottle_audit setting '{}' (expected warn, fix or strict); using warn",
+                    value
+                );
+                BottleAuditMode::Warn
+            }),
+            None => BottleAuditMode::Warn,
+        };
+        let metrics = match env::var("sps_METRICS").ok().or(file_string("metrics")?) {
+            Some(value) => MetricsMode::parse(&value).unwrap_or_else(|| {
+                tracing::warn!(
+                    "Unknown metrics setting '{}' (expected off or local); using off",
+                    value
+                );
+                MetricsMode::Off
+            }),
+            None => MetricsMode::Off,
+        };
+        let hooks = match file.map(|f| f.section("hooks")).transpose()?.flatten() {
+            Some(section) => Hooks {
+                post_install: section.string("post_install")?,
+                pre_uninstall: section.string("pre_uninstall")?,
+                post_upgrade: section.string("post_upgrade")?,
+                strict: section.bool("strict")?.unwrap_or(false),
+            },
+            None => Hooks::default(),
+        };
+        let post_install_check = match env::var("sps_POST_INSTALL_CHECK") {
+            Ok(value) => value == "1" || value.eq_ignore_ascii_case("true"),
+            Err(_) => file
+                .map(|f| f.bool("post_install_check"))
+                .transpose()?
+                .flatten()
+                .unwrap_or(false),
+        };
+        let bottle_only = match env::var("sps_BOTTLE_ONLY") {
+            Ok(value) => value == "1" || value.eq_ignore_ascii_case("true"),
+            Err(_) => file
+                .map(|f| f.bool("bottle_only"))
+                .transpose()?
+                .flatten()
+                .unwrap_or(false),
+        };
+        let bottle_tag = env::var("sps_BOTTLE_TAG")
+            .ok()
+            .or(file_string("bottle_tag")?)
+            .filter(|tag| !tag.is_empty());
+        let keg_retention_days = env::var("sps_KEG_RETENTION_DAYS")
+            .ok()
+            .or(file_string("keg_retention_days")?)
+            .and_then(|v| v.trim().parse().ok())
+            .unwrap_or(DEFAULT_KEG_RETENTION_DAYS);
+        let metadata_strategy = match env::var("sps_METADATA_STRATEGY")
+            .ok()
+            .or(file_string("metadata_strategy")?)
+        {
+            Some(value) => MetadataStrategy::parse(&value).unwrap_or_else(|| {
+                tracing::warn!(
+                    "Unknown metadata_strategy '{}' (expected full, lazy or auto); using auto",
+                    value
+                );
+                MetadataStrategy::Auto
+            }),
+            None => MetadataStrategy::Auto,
+        };
+        let link_strategy = match env::var("sps_LINK_STRATEGY")
+            .ok()
+            .or(file_string("link_strategy")?)
+        {
+            Some(value) => LinkStrategy::parse(&value).unwrap_or_else(|| {
+                tracing::warn!(
+                    "Unknown link_strategy '{}' (expected symlink, hardlink or copy); using symlink",
+                    value
+                );
+                LinkStrategy::Symlink
+            }),
+            None => LinkStrategy::Symlink,
+        };
+        let signature_mode = match env::var("sps_SIGNATURE_MODE")
+            .ok()
+            .or(file_string("signature_mode")?)
+        {
+            Some(value) => SignatureMode::parse(&value).unwrap_or_else(|| {
+                tracing::warn!(
+                    "Unknown signature_mode '{}' (expected off, warn or require); using require",
+                    value
+                );
+                SignatureMode::Require
+            }),
+            None => SignatureMode::Off,
+        };
+        // Commas only, so key file paths may contain spaces.
+        let signature_keys = match env::var("sps_SIGNATURE_KEYS") {
+            Ok(v) => v
+                .split(',')
+                .map(str::trim)
+                .filter(|s| !s.is_empty())
+                .map(str::to_string)
+                .collect(),
+            Err(_) => file
+                .map(|f| f.string_list("signature_keys"))
+                .transpose()?
+                .flatten()
+                .unwrap_or_default(),
+        };
+        let tap_ruby_command = env::var("sps_TAP_RUBY_COMMAND")
+            .ok()
+            .or(file_string("tap_ruby_command")?)
+            .filter(|command| !command.trim().is_empty());
+        let advisory_feed = env::var("sps_ADVISORY_FEED")
+            .ok()
+            .or(file_string("advisory_feed")?)
+            .filter(|url| !url.trim().is_empty());
+        let advisories = match env::var("sps_ADVISORIES")
+            .ok()
+            .or(file_string("advisories")?)
+        {
+            Some(value) => AdvisoryMode::parse(&value).unwrap_or_else(|| {
+                tracing::warn!(
+                    "Unknown advisories setting '{}' (expected off, warn or block); using block",
+                    value
+                );
+                AdvisoryMode::Block
+            }),
+            // A configured feed is used unless turned off explicitly.
+            None if advisory_feed.is_some() => AdvisoryMode::Warn,
+            None => AdvisoryMode::Off,
+        };
 
         if artifact_domain.is_some() {
             debug!("Loaded HOMEBREW_ARTIFACT_DOMAIN");
@@ -83,14 +543,80 @@ impl Config {
             cellar,
             taps_dir,
             cache_dir,
+            download_dir,
+            build_temp_dir,
+            bottle_domain,
             api_base_url,
             artifact_domain,
             docker_registry_token,
             docker_registry_basic_auth,
             github_api_token,
+            env_mode,
+            env_passthrough,
+            max_download_size,
+            max_concurrent_installs,
+            max_concurrent_downloads,
+            cask_languages,
+            bottle_audit,
+            overrides_dir,
+            metrics,
+            hooks,
+            post_install_check,
+            bottle_only,
+            bottle_tag,
+            keg_retention_days,
+            metadata_strategy,
+            link_strategy,
+            signature_mode,
+            signature_keys,
+            tap_ruby_command,
+            advisories,
+            advisory_feed,
         })
     }
 
+    /// Sets the install concurrency, capping it at `MAX_CONCURRENT_INSTALLS` with a warning.
+    pub fn set_max_concurrent_installs(&mut self, requested: usize) {
+        if requested > MAX_CONCURRENT_INSTALLS {
+            tracing::warn!(
+                "--max-concurrent-installs {} is above the limit; using {}",
+                requested,
+                MAX_CONCURRENT_INSTALLS
+            );
+        }
+        self.max_concurrent_installs = requested.clamp(1, MAX_CONCURRENT_INSTALLS);
+    }
+
+    /// Sets the download concurrency, capping it at `MAX_CONCURRENT_INSTALLS` with a warning.
+    pub fn set_max_concurrent_downloads(&mut self, requested: usize) {
+        if requested > MAX_CONCURRENT_INSTALLS {
+            tracing::warn!(
+                "--max-concurrent-downloads {} is above the limit; using {}",
+                requested,
+                MAX_CONCURRENT_INSTALLS
+            );
+        }
+        self.max_concurrent_downloads = Some(requested.clamp(1, MAX_CONCURRENT_INSTALLS));
+    }
+
+    /// Moves everything kept under the prefix (Cellar, taps, and the overrides directory unless
+    /// it was configured elsewhere) to `prefix`.
+    pub fn set_prefix(&mut self, prefix: PathBuf) {
+        if self.overrides_dir == self.prefix.join("etc/sps/overrides") {
+            self.overrides_dir = prefix.join("etc/sps/overrides");
+        }
+        self.cellar = prefix.join("Cellar");
+        self.taps_dir = prefix.join("Library/Taps");
+        self.prefix = prefix;
+    }
+
+    /// Downloads run at once: `max_concurrent_downloads`, else as many as installs.
+    pub fn download_concurrency(&self) -> usize {
+        self.max_concurrent_downloads
+            .unwrap_or(self.max_concurrent_installs)
+            .max(1)
+    }
+
     // --- Start: New Path Methods ---
 
     pub fn prefix(&self) -> &Path {
@@ -122,7 +648,7 @@ impl Config {
     }
 
     pub fn formula_cellar_dir(&self, formula_name: &str) -> PathBuf {
-        self.cellar_path().join(formula_name)
+        self.cellar_path().join(keg_dir_name(formula_name))
     }
 
     pub fn formula_keg_path(&self, formula_name: &str, version_str: &str) -> PathBuf {
@@ -130,7 +656,7 @@ impl Config {
     }
 
     pub fn formula_opt_link_path(&self, formula_name: &str) -> PathBuf {
-        self.opt_dir().join(formula_name)
+        self.opt_dir().join(keg_dir_name(formula_name))
     }
 
     pub fn cask_dir(&self, cask_token: &str) -> PathBuf {
@@ -192,3 +718,82 @@ impl Default for Config {
 pub fn load_config() -> Result<Config> {
     Config::load()
 }
+
+/// Parses a byte size such as `2048`, `500M`, `20G` or `1.5GB` (binary multiples).
+pub fn parse_size(s: &str) -> Option<u64> {
+    let s = s.trim().to_ascii_uppercase();
+    let s = s.strip_suffix('B').unwrap_or(&s);
+    let (number, multiplier) = match s.chars().last()? {
+        'K' => (&s[..s.len() - 1], 1u64 << 10),
+        'M' => (&s[..s.len() - 1], 1 << 20),
+        'G' => (&s[..s.len() - 1], 1 << 30),
+        'T' => (&s[..s.len() - 1], 1 << 40),
+        _ => (s, 1),
+    };
+    let value: f64 = number.trim().parse().ok()?;
+    (value >= 0.0).then_some((value * multiplier as f64) as u64)
+}
+
+/// One less than the available cores, bounded by what the network side can use.
+pub fn auto_concurrent_installs() -> usize {
+    let cores = std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1);
+    cores
+        .saturating_sub(1)
+        .clamp(1, AUTO_CONCURRENCY_NETWORK_CAP)
+}
+
+/// Parses a concurrency setting: a positive count or `auto`. Zero is rejected because no work
+/// could ever start.
+/// Splits a comma-separated language list such as `de,en-GB`.
+pub fn parse_language_list(s: &str) -> Vec<String> {
+    s.split(',')
+        .map(str::trim)
+        .filter(|l| !l.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Languages from the locale environment (`LC_ALL`, `LC_MESSAGES`, `LANG`), falling back to the
+/// macOS `AppleLanguages` preference. `de_DE.UTF-8` becomes `de-DE`; `C` and `POSIX` are skipped.
+fn system_languages() -> Vec<String> {
+    let from_env = ["LC_ALL", "LC_MESSAGES", "LANG"]
+        .iter()
+        .filter_map(|var| env::var(var).ok())
+        .map(|v| {
+            v.split(['.', '@'])
+                .next()
+                .unwrap_or_default()
+                .replace('_', "-")
+        })
+        .find(|v| !v.is_empty() && v != "C" && v != "POSIX");
+    if let Some(language) = from_env {
+        return vec![language];
+    }
+    if !cfg!(target_os = "macos") {
+        return Vec::new();
+    }
+    // Prints a plist array like `(\n    "de-DE",\n    en\n)`.
+    std::process::Command::new("/usr/bin/defaults")
+        .args(["read", "-g", "AppleLanguages"])
+        .output()
+        .ok()
+        .filter(|o| o.status.success())
+        .map(|o| {
+            String::from_utf8_lossy(&o.stdout)
+                .split(['(', ')', ',', '\n'])
+                .map(|l| l.trim().trim_matches('"').to_string())
+                .filter(|l| !l.is_empty())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+pub fn parse_concurrency(s: &str) -> Option<usize> {
+    let s = s.trim();
+    if s.eq_ignore_ascii_case("auto") {
+        return Some(auto_concurrent_installs());
+    }
+    s.parse::<usize>().ok().filter(|n| *n > 0)
+}
@@ -0,0 +1,384 @@
+// sps-common/src/config_file.rs
+//! Optional TOML config file, read from `sps_CONFIG` or `$XDG_CONFIG_HOME/sps/config.toml`
+//! (default `~/.config/sps/config.toml`).
+//!
+//! Top-level keys apply on every machine. A `[host."name"]` section overrides them on the host
+//! whose name, in full or up to the first dot, is `name`. String values may reference
+//! environment variables as `${VAR}`; `$$` stands for a literal `$`.
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::{env, fs};
+
+use toml::{Table, Value};
+use tracing::debug;
+
+use crate::error::{Result, SpsError};
+
+const HOST_SECTION: &str = "host";
+
+#[derive(Debug, Clone)]
+pub struct ConfigFile {
+    path: PathBuf,
+    /// Top-level values with the matching host section merged over them.
+    values: Table,
+}
+
+impl ConfigFile {
+    /// Loads the config file for this host. A missing default file is not an error; a missing
+    /// file named by `sps_CONFIG` is.
+    pub fn load() -> Result<Option<Self>> {
+        let (path, explicit) = match env::var_os("sps_CONFIG") {
+            Some(path) => (PathBuf::from(path), true),
+            None => match default_path() {
+                Some(path) => (path, false),
+                None => return Ok(None),
+            },
+        };
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound && !explicit => {
+                debug!("No config file at {}", path.display());
+                return Ok(None);
+            }
+            Err(e) => {
+                return Err(SpsError::Config(format!(
+                    "Failed to read {}: {e}",
+                    path.display()
+                )))
+            }
+        };
+        let hostname = hostname();
+        debug!(
+            "Loading config file {} for host {:?}",
+            path.display(),
+            hostname
+        );
+        Self::parse(&text, &path, hostname.as_deref()).map(Some)
+    }
+
+    /// Parses `text` and merges the `[host."<hostname>"]` section, if any, over the top level.
+    pub fn parse(text: &str, path: &Path, hostname: Option<&str>) -> Result<Self> {
+        let mut values: Table = text
+            .parse()
+            .map_err(|e| SpsError::Config(format!("{}: {e}", path.display())))?;
+        let hosts = match values.remove(HOST_SECTION) {
+            None => Table::new(),
+            Some(Value::Table(hosts)) => hosts,
+            Some(_) => {
+                return Err(SpsError::Config(format!(
+                    "{}: `{HOST_SECTION}` must be a table of per-host sections",
+                    path.display()
+                )))
+            }
+        };
+        let section = hostname.and_then(|full| {
+            let short = full.split('.').next().unwrap_or(full);
+            hosts.get(full).or_else(|| hosts.get(short))
+        });
+        match section {
+            None => {}
+            Some(Value::Table(overrides)) => {
+                debug!("Applying host section with {} key(s)", overrides.len());
+                values.extend(overrides.clone());
+            }
+            Some(_) => {
+                return Err(SpsError::Config(format!(
+                    "{}: host sections must be tables, e.g. [host.\"{}\"]",
+                    path.display(),
+                    hostname.unwrap_or_default()
+                )))
+            }
+        }
+        Ok(Self {
+            path: path.to_path_buf(),
+            values,
+        })
+    }
+
+    /// A string setting with environment variables expanded. Integers are accepted as their
+    /// decimal form, so `max_download_size = 1024` and `= "1K"` both work.
+    pub fn string(&self, key: &str) -> Result<Option<String>> {
+        match self.values.get(key) {
+            None => Ok(None),
+            Some(Value::String(s)) => self.interpolate(key, s).map(Some),
+            Some(Value::Integer(i)) => Ok(Some(i.to_string())),
+            Some(other) => Err(self.type_error(key, "a string", other)),
+        }
+    }
+
+    /// A path setting; like [`ConfigFile::string`] with a leading `~/` expanded to the home
+    /// directory.
+    pub fn path(&self, key: &str) -> Result<Option<PathBuf>> {
+        Ok(self.string(key)?.map(|s| match s.strip_prefix("~/") {
+            Some(rest) => dirs::home_dir().unwrap_or_default().join(rest),
+            None => PathBuf::from(s),
+        }))
+    }
+
+    /// An array-of-strings setting, each element interpolated.
+    pub fn string_list(&self, key: &str) -> Result<Option<Vec<String>>> {
+        match self.values.get(key) {
+            None => Ok(None),
+            Some(Value::Array(items)) => items
+                .iter()
+                .map(|item| match item {
+                    Value::String(s) => self.interpolate(key, s),
+                    other => Err(self.type_error(key, "an array of strings", other)),
+                })
+                .collect::<Result<Vec<_>>>()
+                .map(Some),
+            Some(other) => Err(self.type_error(key, "an array of strings", other)),
+        }
+    }
+
+    /// A boolean setting.
+    pub fn bool(&self, key: &str) -> Result<Option<bool>> {
+        match self.values.get(key) {
+            None => Ok(None),
+            Some(Value::Boolean(b)) => Ok(Some(*b)),
+            Some(other) => Err(self.type_error(key, "true or false", other)),
+        }
+    }
+
+    /// A `[key]` table, whose settings are read with the same accessors.
+    pub fn section(&self, key: &str) -> Result<Option<Self>> {
+        match self.values.get(key) {
+            None => Ok(None),
+            Some(Value::Table(values)) => Ok(Some(Self {
+                path: self.path.clone(),
+                values: values.clone(),
+            })),
+            Some(other) => Err(self.type_error(key, "a table", other)),
+        }
+    }
+
+    fn interpolate(&self, key: &str, raw: &str) -> Result<String> {
+        let mut out = String::with_capacity(raw.len());
+        let mut rest = raw;
+        while let Some(pos) = rest.find('$') {
+            out.push_str(&rest[..pos]);
+            let after = &rest[pos + 1..];
+            if let Some(tail) = after.strip_prefix('$') {
+                out.push('$');
+                rest = tail;
+            } else if let Some(body) = after.strip_prefix('{') {
+                let end = body.find('}').ok_or_else(|| {
+                    self.error(format!("`{key}` has an unterminated `${{` in \"{raw}\""))
+                })?;
+                let name = &body[..end];
+                let value = env::var(name).map_err(|_| {
+                    self.error(format!(
+                        "`{key}` refers to environment variable {name}, which is not set"
+                    ))
+                })?;
+                out.push_str(&value);
+                rest = &body[end + 1..];
+            } else {
+                out.push('$');
+                rest = after;
+            }
+        }
+        out.push_str(rest);
+        Ok(out)
+    }
+
+    fn type_error(&self, key: &str, expected: &str, found: &Value) -> SpsError {
+        self.error(format!(
+            "`{key}` must be {expected}, found {}",
+            found.type_str()
+        ))
+    }
+
+    fn error(&self, message: String) -> SpsError {
+        SpsError::Config(format!("{}: {message}", self.path.display()))
+    }
+}
+
+fn default_path() -> Option<PathBuf> {
+    let base = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .filter(|p| p.is_absolute())
+        .or_else(|| dirs::home_dir().map(|home| home.join(".config")))?;
+    Some(base.join("sps").join("config.toml"))
+}
+
+/// `HOSTNAME` if exported, otherwise the output of `hostname`.
+fn hostname() -> Option<String> {
+    if let Ok(name) = env::var("HOSTNAME") {
+        if !name.is_empty() {
+            return Some(name);
+        }
+    }
+    let output = Command::new("hostname").output().ok()?;
+    let name = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (output.status.success() && !name.is_empty()).then_some(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::test_env::ScopedEnv;
+
+    const HOSTS: &str = r#"
+        max_concurrent_downloads = 2
+        download_dir = "/global"
+
+        [host."build01"]
+        max_concurrent_downloads = 3
+
+        [host."build01.example.com"]
+        download_dir = "/full-name"
+    "#;
+
+    fn parse(text: &str, hostname: Option<&str>) -> Result<ConfigFile> {
+        ConfigFile::parse(text, Path::new("config.toml"), hostname)
+    }
+
+    #[test]
+    fn a_host_section_overrides_the_top_level_on_its_host_only() {
+        let here = parse(HOSTS, Some("build01")).unwrap();
+        assert_eq!(
+            here.string("max_concurrent_downloads").unwrap().as_deref(),
+            Some("3")
+        );
+        assert_eq!(
+            here.string("download_dir").unwrap().as_deref(),
+            Some("/global")
+        );
+
+        let elsewhere = parse(HOSTS, Some("ci02")).unwrap();
+        assert_eq!(
+            elsewhere
+                .string("max_concurrent_downloads")
+                .unwrap()
+                .as_deref(),
+            Some("2")
+        );
+        let no_hostname = parse(HOSTS, None).unwrap();
+        assert_eq!(
+            no_hostname.string("download_dir").unwrap().as_deref(),
+            Some("/global")
+        );
+    }
+
+    #[test]
+    fn the_full_hostname_is_preferred_over_its_short_form() {
+        let file = parse(HOSTS, Some("build01.example.com")).unwrap();
+        assert_eq!(
+            file.string("download_dir").unwrap().as_deref(),
+            Some("/full-name")
+        );
+        // Only the first matching section applies; the short one is not merged in as well.
+        assert_eq!(
+            file.string("max_concurrent_downloads").unwrap().as_deref(),
+            Some("2")
+        );
+    }
+
+    #[test]
+    fn an_unset_key_is_left_to_the_default() {
+        let file = parse(HOSTS, Some("build01")).unwrap();
+        assert_eq!(file.string("bottle_domain").unwrap(), None);
+        assert_eq!(file.bool("no_hooks").unwrap(), None);
+    }
+
+    #[test]
+    fn a_malformed_host_table_is_rejected() {
+        let err = parse("host = 1", Some("build01")).unwrap_err();
+        assert!(err.to_string().contains("per-host sections"), "{err}");
+        let err = parse("[host]\nbuild01 = \"x\"", Some("build01")).unwrap_err();
+        assert!(err.to_string().contains("must be tables"), "{err}");
+    }
+
+    #[test]
+    fn values_in_a_host_section_are_interpolated() {
+        env::set_var("SPS_CONFIG_FILE_TEST_ROOT", "/Volumes/fast");
+        let file = parse(
+            r#"
+            temp_dir = "/tmp"
+            [host."build01"]
+            temp_dir = "${SPS_CONFIG_FILE_TEST_ROOT}/tmp"
+            tags = ["$${SPS_CONFIG_FILE_TEST_ROOT}", "${SPS_CONFIG_FILE_TEST_ROOT}"]
+            "#,
+            Some("build01"),
+        )
+        .unwrap();
+
+        assert_eq!(
+            file.path("temp_dir").unwrap(),
+            Some(PathBuf::from("/Volumes/fast/tmp"))
+        );
+        assert_eq!(
+            file.string_list("tags").unwrap().unwrap(),
+            ["${SPS_CONFIG_FILE_TEST_ROOT}", "/Volumes/fast"]
+        );
+    }
+
+    #[test]
+    fn an_unset_variable_in_a_host_section_names_the_key_and_variable() {
+        env::remove_var("SPS_CONFIG_FILE_TEST_UNSET");
+        let file = parse(
+            r#"
+            [host."build01"]
+            download_dir = "${SPS_CONFIG_FILE_TEST_UNSET}/downloads"
+            "#,
+            Some("build01"),
+        )
+        .unwrap();
+
+        let err = file.path("download_dir").unwrap_err().to_string();
+        assert!(err.contains("config.toml"), "{err}");
+        assert!(err.contains("`download_dir`"), "{err}");
+        assert!(err.contains("SPS_CONFIG_FILE_TEST_UNSET"), "{err}");
+        assert!(err.contains("not set"), "{err}");
+        // Another host never reads the section, so the missing variable is no error there.
+        let elsewhere = parse(
+            r#"
+            [host."build01"]
+            download_dir = "${SPS_CONFIG_FILE_TEST_UNSET}/downloads"
+            "#,
+            Some("ci02"),
+        )
+        .unwrap();
+        assert_eq!(elsewhere.path("download_dir").unwrap(), None);
+    }
+
+    #[test]
+    fn an_unterminated_reference_is_an_error() {
+        let file = parse(r#"bottle_domain = "${HOME""#, None).unwrap();
+        let err = file.string("bottle_domain").unwrap_err().to_string();
+        assert!(err.contains("unterminated"), "{err}");
+    }
+
+    #[test]
+    fn settings_resolve_cli_over_env_over_host_over_global_over_default() {
+        let mut env = ScopedEnv::new();
+        // Kept on disk: other tests load `Config` concurrently and may still see `sps_CONFIG`.
+        let dir = tempfile::tempdir().unwrap().keep();
+        let empty = dir.join("empty.toml");
+        let hosts = dir.join("hosts.toml");
+        fs::write(&empty, "").unwrap();
+        fs::write(&hosts, HOSTS).unwrap();
+        env.remove("sps_MAX_CONCURRENT_DOWNLOADS")
+            .set("HOSTNAME", "build01.example.com");
+
+        env.set("sps_CONFIG", &empty);
+        let default = Config::load().unwrap().max_concurrent_downloads;
+        env.set("sps_CONFIG", &hosts).set("HOSTNAME", "ci02");
+        let global = Config::load().unwrap().max_concurrent_downloads;
+        env.set("HOSTNAME", "build01.lan");
+        let host = Config::load().unwrap().max_concurrent_downloads;
+        env.set("sps_MAX_CONCURRENT_DOWNLOADS", "4");
+        let mut config = Config::load().unwrap();
+        let from_env = config.max_concurrent_downloads;
+        config.set_max_concurrent_downloads(5);
+        let cli = config.max_concurrent_downloads;
+
+        assert_eq!(
+            [default, global, host, from_env, cli],
+            [None, Some(2), Some(3), Some(4), Some(5)]
+        );
+    }
+}
@@ -22,6 +22,35 @@ bitflags! {
     }
 }
 
+impl DependencyTag {
+    /// The tag for a name used in the API (`build`, `test`, ...), if this version knows it.
+    pub fn from_api_name(name: &str) -> Option<Self> {
+        match name.trim_start_matches(':') {
+            "run" | "runtime" => Some(Self::RUNTIME),
+            "build" => Some(Self::BUILD),
+            "test" => Some(Self::TEST),
+            "optional" => Some(Self::OPTIONAL),
+            "recommended" => Some(Self::RECOMMENDED),
+            _ => None,
+        }
+    }
+
+    /// The API names of the set flags (`runtime`, `build`, ...), in declaration order.
+    pub fn api_names(self) -> Vec<&'static str> {
+        [
+            (Self::RUNTIME, "runtime"),
+            (Self::BUILD, "build"),
+            (Self::TEST, "test"),
+            (Self::OPTIONAL, "optional"),
+            (Self::RECOMMENDED, "recommended"),
+        ]
+        .into_iter()
+        .filter(|(flag, _)| self.contains(*flag))
+        .map(|(_, name)| name)
+        .collect()
+    }
+}
+
 impl Default for DependencyTag {
     // By default, a dependency is considered runtime unless specified otherwise.
     fn default() -> Self {
@@ -43,6 +72,13 @@ pub struct Dependency {
     /// Tags associated with this dependency (e.g., build, optional).
     #[serde(default)] // Use default tags (RUNTIME) if missing in serialization
     pub tags: DependencyTag,
+    /// Lowest acceptable version, from a bound such as `openssl@3 >= 3.2`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_version: Option<String>,
+    /// Tags the API attached that [`DependencyTag`] has no flag for, kept so they survive a
+    /// round trip.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub other_tags: Vec<String>,
     // We could add requirements here later:
     // pub requirements: Vec<Requirement>,
 }
@@ -53,6 +89,8 @@ impl Dependency {
         Self {
             name: name.into(),
             tags: DependencyTag::RUNTIME,
+            min_version: None,
+            other_tags: Vec::new(),
         }
     }
 
@@ -61,6 +99,32 @@ impl Dependency {
         Self {
             name: name.into(),
             tags,
+            min_version: None,
+            other_tags: Vec::new(),
+        }
+    }
+
+    /// Parses a dependency list entry: a bare name, or a name with a minimum version such as
+    /// `openssl@3 >= 3.2`.
+    pub fn from_spec(spec: &str, tags: DependencyTag) -> Self {
+        match spec.split_once(">=") {
+            Some((name, version)) if !version.trim().is_empty() => Self {
+                name: name.trim().to_string(),
+                tags,
+                min_version: Some(version.trim().to_string()),
+                other_tags: Vec::new(),
+            },
+            // A dangling `>=` bounds nothing.
+            Some((name, _)) => Self::new_with_tags(name.trim(), tags),
+            None => Self::new_with_tags(spec.trim(), tags),
+        }
+    }
+
+    /// The inverse of [`Dependency::from_spec`].
+    pub fn spec(&self) -> String {
+        match &self.min_version {
+            Some(version) => format!("{} >= {version}", self.name),
+            None => self.name.clone(),
         }
     }
 }
@@ -107,3 +171,27 @@ impl DependencyExt for Vec<Dependency> {
 }
 
 // Required for bitflags!
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_a_minimum_version_and_writes_it_back() {
+        let dep = Dependency::from_spec("openssl@3 >= 3.2", DependencyTag::RUNTIME);
+
+        assert_eq!(dep.name, "openssl@3");
+        assert_eq!(dep.min_version.as_deref(), Some("3.2"));
+        assert_eq!(dep.spec(), "openssl@3 >= 3.2");
+    }
+
+    #[test]
+    fn a_bare_name_or_empty_bound_has_no_minimum_version() {
+        for spec in ["zlib", " zlib ", "zlib >= "] {
+            let dep = Dependency::from_spec(spec, DependencyTag::RUNTIME);
+            assert_eq!(dep.name, "zlib", "{spec:?}");
+            assert_eq!(dep.min_version, None, "{spec:?}");
+            assert_eq!(dep.spec(), "zlib");
+        }
+    }
+}
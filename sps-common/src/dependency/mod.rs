@@ -1,6 +1,8 @@
 pub mod definition; // Renamed from 'dependency'
 pub mod requirement;
 pub mod resolver;
+pub mod reverse;
+pub mod scheduler;
 
 // Re-export key types for easier access
 pub use definition::{Dependency, DependencyExt, DependencyTag}; // Updated source module
@@ -8,3 +10,5 @@ pub use requirement::Requirement;
 pub use resolver::{
     DependencyResolver, ResolutionContext, ResolutionStatus, ResolvedDependency, ResolvedGraph,
 };
+pub use reverse::{runtime_dependencies, ReverseDependencyGraph};
+pub use scheduler::{Direction, FailurePolicy, Outcome, Progress, Scheduler};
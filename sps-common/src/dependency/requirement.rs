@@ -3,6 +3,8 @@ use std::fmt;
 
 use serde::{Deserialize, Serialize};
 
+use crate::macos::{Comparator, MacOSConstraint};
+
 /// Represents a requirement beyond a simple formula dependency.
 /// Placeholder - This needs significant expansion based on Homebrew's Requirement system.
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
@@ -16,10 +18,24 @@ pub enum Requirement {
     Other(String),
 }
 
+impl Requirement {
+    /// The macOS constraint this requirement imposes, if it is a macOS requirement with a
+    /// concrete version. `depends_on macos: :monterey` in a formula means "at least".
+    pub fn macos_constraint(&self) -> Option<MacOSConstraint> {
+        match self {
+            Self::MacOS(v) => MacOSConstraint::parse(v, Comparator::Ge),
+            _ => None,
+        }
+    }
+}
+
 impl fmt::Display for Requirement {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Self::MacOS(v) => write!(f, "macOS >= {v}"),
+            Self::MacOS(v) => match self.macos_constraint() {
+                Some(constraint) => write!(f, "{constraint}"),
+                None => write!(f, "macOS >= {v}"),
+            },
             Self::Xcode(v) => write!(f, "Xcode >= {v}"),
             Self::Other(s) => write!(f, "Requirement: {s}"),
         }
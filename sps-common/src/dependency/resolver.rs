@@ -1,6 +1,6 @@
 // FILE: sps-core/src/dependency/resolver.rs
 
-use std::collections::{HashMap, HashSet, VecDeque};
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
@@ -9,8 +9,9 @@ use tracing::{debug, error, warn};
 use crate::dependency::{Dependency, DependencyTag};
 use crate::error::{Result, SpsError};
 use crate::formulary::Formulary;
-use crate::keg::KegRegistry;
-use crate::model::formula::Formula;
+use crate::keg::{self, KegRegistry};
+use crate::model::formula::{Formula, FormulaLifecycle, HEAD_VERSION_PREFIX};
+use crate::model::PkgVersion;
 
 #[derive(Debug, Clone)]
 pub struct ResolvedDependency {
@@ -20,11 +21,17 @@ pub struct ResolvedDependency {
     pub status: ResolutionStatus,
     pub tags: DependencyTag,
     pub failure_reason: Option<String>,
+    /// For `Outdated` nodes: which dependent needs a newer version, and why.
+    pub upgrade_reason: Option<String>,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ResolutionStatus {
     Installed,
+    /// A keg is present but its opt link is missing or points elsewhere; needs relinking.
+    InstalledUnlinked,
+    /// A keg is present but older than a dependent being installed needs; planned as an upgrade.
+    Outdated,
     Missing,
     Requested,
     SkippedOptional,
@@ -37,7 +44,9 @@ pub struct ResolvedGraph {
     pub install_plan: Vec<ResolvedDependency>,
     pub build_dependency_opt_paths: Vec<PathBuf>,
     pub runtime_dependency_opt_paths: Vec<PathBuf>,
-    pub resolution_details: HashMap<String, ResolvedDependency>,
+    pub resolution_details: HashMap<Arc<str>, ResolvedDependency>,
+    /// Names that could not be resolved, e.g. disabled formulae and everything needing them.
+    pub errors: HashMap<Arc<str>, Arc<SpsError>>,
 }
 
 pub struct ResolutionContext<'a> {
@@ -45,41 +54,91 @@ pub struct ResolutionContext<'a> {
     pub keg_registry: &'a KegRegistry,
     pub sps_prefix: &'a Path,
     pub include_optional: bool,
+    /// Include the test dependencies of the requested targets (not those of their dependencies).
     pub include_test: bool,
     pub skip_recommended: bool,
     pub force_build: bool,
+    /// Resolve as if nothing were installed, producing a complete plan.
+    pub ignore_installed: bool,
+    /// Never plan changes to existing kegs; installed means done, even if unlinked.
+    pub only_missing: bool,
+    /// Platform tag whose formula `variations` apply (see [`Formula::for_platform`]); `None`
+    /// resolves the default definitions.
+    pub platform: Option<&'a str>,
 }
 
 pub struct DependencyResolver<'a> {
     context: ResolutionContext<'a>,
-    formula_cache: HashMap<String, Arc<Formula>>,
-    visiting: HashSet<String>,
-    resolution_details: HashMap<String, ResolvedDependency>,
+    /// Every name seen, interned once so the maps below share one allocation per node.
+    names: HashSet<Arc<str>>,
+    formula_cache: HashMap<Arc<str>, Arc<Formula>>,
+    visiting: HashSet<Arc<str>>,
+    targets: HashSet<Arc<str>>,
+    resolution_details: HashMap<Arc<str>, ResolvedDependency>,
     // Store Arc<SpsError> instead of SpsError
-    errors: HashMap<String, Arc<SpsError>>,
+    errors: HashMap<Arc<str>, Arc<SpsError>>,
 }
 
 impl<'a> DependencyResolver<'a> {
     pub fn new(context: ResolutionContext<'a>) -> Self {
         Self {
             context,
+            names: HashSet::new(),
             formula_cache: HashMap::new(),
             visiting: HashSet::new(),
+            targets: HashSet::new(),
             resolution_details: HashMap::new(),
             errors: HashMap::new(),
         }
     }
 
+    /// The interned copy of `name`.
+    fn intern(&mut self, name: &str) -> Arc<str> {
+        if let Some(interned) = self.names.get(name) {
+            return Arc::clone(interned);
+        }
+        let interned: Arc<str> = Arc::from(name);
+        self.names.insert(Arc::clone(&interned));
+        interned
+    }
+
+    /// Loads `name`, with its dependencies renamed to canonical names so that every edge of the
+    /// graph meets its node under the same key, however the dependency was declared.
+    fn load_formula(&self, name: &str) -> Result<Formula> {
+        let formula = self.context.formulary.load_formula(name)?;
+        let mut formula = match self.context.platform {
+            Some(platform) => formula.for_platform(platform),
+            None => formula,
+        };
+        for dep in &mut formula.dependencies {
+            let canonical = self.context.formulary.canonical_name(&dep.name);
+            if canonical != dep.name {
+                debug!(
+                    "'{}' depends on '{}', an alias of '{}'",
+                    formula.name, dep.name, canonical
+                );
+                dep.name = canonical;
+            }
+        }
+        Ok(formula)
+    }
+
     pub fn resolve_targets(&mut self, targets: &[String]) -> Result<ResolvedGraph> {
         debug!("Starting dependency resolution for targets: {:?}", targets);
         self.visiting.clear();
+        self.targets = targets.iter().map(|t| self.intern(t)).collect();
         self.resolution_details.clear();
         self.errors.clear();
 
         for target_name in targets {
             if let Err(e) = self.resolve_recursive(target_name, DependencyTag::RUNTIME, true) {
+                if let Some(node) = self.resolution_details.get_mut(target_name.as_str()) {
+                    node.status = ResolutionStatus::Failed;
+                    node.failure_reason = Some(e.to_string());
+                }
                 // Wrap error in Arc for storage
-                self.errors.insert(target_name.clone(), Arc::new(e));
+                let target_name = self.intern(target_name);
+                self.errors.insert(Arc::clone(&target_name), Arc::new(e));
                 warn!(
                     "Resolution failed for target '{}', but continuing for others.",
                     target_name
@@ -95,6 +154,8 @@ impl<'a> DependencyResolver<'a> {
                 .collect::<Vec<_>>()
         );
 
+        self.check_edges()?;
+
         let sorted_list = match self.topological_sort() {
             Ok(list) => list,
             Err(e @ SpsError::DependencyError(_)) => {
@@ -112,7 +173,9 @@ impl<'a> DependencyResolver<'a> {
             .filter(|dep| {
                 matches!(
                     dep.status,
-                    ResolutionStatus::Missing | ResolutionStatus::Requested
+                    ResolutionStatus::Missing
+                        | ResolutionStatus::Requested
+                        | ResolutionStatus::Outdated
                 )
             })
             .collect();
@@ -126,6 +189,8 @@ impl<'a> DependencyResolver<'a> {
             if matches!(
                 dep.status,
                 ResolutionStatus::Installed
+                    | ResolutionStatus::InstalledUnlinked
+                    | ResolutionStatus::Outdated
                     | ResolutionStatus::Requested
                     | ResolutionStatus::Missing
             ) {
@@ -191,6 +256,7 @@ impl<'a> DependencyResolver<'a> {
             build_dependency_opt_paths: build_paths,
             runtime_dependency_opt_paths: runtime_paths,
             resolution_details: self.resolution_details.clone(),
+            errors: self.errors.clone(),
         })
     }
 
@@ -231,8 +297,14 @@ impl<'a> DependencyResolver<'a> {
                         && !self.context.skip_recommended)
                     || (is_target && self.context.include_optional))
             {
-                new_status = if existing.keg_path.is_some() {
-                    ResolutionStatus::Installed
+                new_status = if let Some(keg_path) = &existing.keg_path {
+                    if self.context.only_missing
+                        || self.context.keg_registry.is_keg_linked(name, keg_path)
+                    {
+                        ResolutionStatus::Installed
+                    } else {
+                        ResolutionStatus::InstalledUnlinked
+                    }
                 } else if is_target {
                     ResolutionStatus::Requested
                 } else {
@@ -274,17 +346,19 @@ impl<'a> DependencyResolver<'a> {
         }
         // -------- first time we see this node ---------------------------------------------
         else {
-            self.visiting.insert(name.to_string());
+            let interned = self.intern(name);
+            self.visiting.insert(Arc::clone(&interned));
 
             // load / cache the formula -----------------------------------------------------
             let formula: Arc<Formula> = match self.formula_cache.get(name) {
                 Some(f) => f.clone(),
                 None => {
                     debug!("Loading formula definition for '{}'", name);
-                    match self.context.formulary.load_formula(name) {
+                    match self.load_formula(name) {
                         Ok(f) => {
                             let arc = Arc::new(f);
-                            self.formula_cache.insert(name.to_string(), arc.clone());
+                            self.formula_cache
+                                .insert(Arc::clone(&interned), arc.clone());
                             arc
                         }
                         Err(e) => {
@@ -292,7 +366,7 @@ impl<'a> DependencyResolver<'a> {
 
                             let msg = e.to_string();
                             self.resolution_details.insert(
-                                name.to_string(),
+                                Arc::clone(&interned),
                                 ResolvedDependency {
                                     formula: Arc::new(Formula::placeholder(name)),
                                     keg_path: None,
@@ -300,12 +374,13 @@ impl<'a> DependencyResolver<'a> {
                                     status: ResolutionStatus::NotFound,
                                     tags: tags_from_parent,
                                     failure_reason: Some(msg.clone()),
+                                    upgrade_reason: None,
                                 },
                             );
                             self.visiting.remove(name);
 
                             self.errors
-                                .insert(name.to_string(), Arc::new(SpsError::NotFound(msg)));
+                                .insert(interned, Arc::new(SpsError::NotFound(msg)));
 
                             return Ok(()); // treat “not found” as a soft failure
                         }
@@ -314,7 +389,7 @@ impl<'a> DependencyResolver<'a> {
             };
 
             // work out installation state --------------------------------------------------
-            let installed_keg = if self.context.force_build {
+            let installed_keg = if self.context.force_build || self.context.ignore_installed {
                 None
             } else {
                 self.context.keg_registry.get_installed_keg(name)?
@@ -322,8 +397,18 @@ impl<'a> DependencyResolver<'a> {
             let opt_path = self.context.keg_registry.get_opt_path(name);
 
             let (status, keg_path) = match installed_keg {
-                Some(keg) => (ResolutionStatus::Installed, Some(keg.path)),
-                None => (
+                Some(keg)
+                    if self.context.only_missing
+                        || self.context.keg_registry.is_keg_linked(name, &keg.path) =>
+                {
+                    (ResolutionStatus::Installed, Some(keg.path))
+                }
+                // An unlinked keg without a receipt was left by a pour that never finished;
+                // pouring again moves it aside (see `keg::quarantine`).
+                Some(keg) if keg::has_valid_receipt(&keg.path) => {
+                    (ResolutionStatus::InstalledUnlinked, Some(keg.path))
+                }
+                _ => (
                     if is_target {
                         ResolutionStatus::Requested
                     } else {
@@ -333,6 +418,22 @@ impl<'a> DependencyResolver<'a> {
                 ),
             };
 
+            // Installed kegs of a deprecated/disabled formula keep working; only new installs
+            // are refused or warned about.
+            if matches!(
+                status,
+                ResolutionStatus::Requested | ResolutionStatus::Missing
+            ) {
+                match formula.lifecycle() {
+                    FormulaLifecycle::Disabled(msg) => {
+                        self.visiting.remove(name);
+                        return Err(SpsError::FormulaDisabled(msg));
+                    }
+                    FormulaLifecycle::Deprecated(msg) => warn!("{}", msg),
+                    FormulaLifecycle::Active => {}
+                }
+            }
+
             debug!(
                 "Initial status for '{}': {:?}, keg: {:?}, opt: {}",
                 name,
@@ -342,7 +443,7 @@ impl<'a> DependencyResolver<'a> {
             );
 
             self.resolution_details.insert(
-                name.to_string(),
+                interned,
                 ResolvedDependency {
                     formula,
                     keg_path,
@@ -350,28 +451,26 @@ impl<'a> DependencyResolver<'a> {
                     status,
                     tags: tags_from_parent,
                     failure_reason: None,
+                    upgrade_reason: None,
                 },
             );
         }
 
         // --------------------------------------------------------------------- recurse ----
-        let dep_snapshot = self
-            .resolution_details
-            .get(name)
-            .expect("just inserted")
-            .clone();
+        let node = self.resolution_details.get(name).expect("just inserted");
 
         // if this node is already irrecoverably broken, stop here
         if matches!(
-            dep_snapshot.status,
+            node.status,
             ResolutionStatus::Failed | ResolutionStatus::NotFound
         ) {
             self.visiting.remove(name);
             return Ok(());
         }
+        let (status, formula) = (node.status, Arc::clone(&node.formula));
 
         // iterate its declared dependencies -----------------------------------------------
-        for dep in dep_snapshot.formula.dependencies()? {
+        for dep in formula.dependencies() {
             let dep_name = &dep.name;
             let dep_tags = dep.tags;
 
@@ -381,17 +480,19 @@ impl<'a> DependencyResolver<'a> {
             );
 
             // optional / test filtering
-            if !self.should_consider_dependency(&dep) {
+            if !self.should_consider_dependency(dep, name) {
                 if !self.resolution_details.contains_key(dep_name.as_str()) {
                     debug!("Marking '{}' as SkippedOptional", dep_name);
 
-                    if let Ok(f) = self.context.formulary.load_formula(dep_name) {
+                    if let Ok(f) = self.load_formula(dep_name) {
                         let arc = Arc::new(f);
                         let opt = self.context.keg_registry.get_opt_path(dep_name);
+                        let dep_name = self.intern(dep_name);
 
-                        self.formula_cache.insert(dep_name.to_string(), arc.clone());
+                        self.formula_cache
+                            .insert(Arc::clone(&dep_name), arc.clone());
                         self.resolution_details.insert(
-                            dep_name.to_string(),
+                            dep_name,
                             ResolvedDependency {
                                 formula: arc,
                                 keg_path: None,
@@ -399,6 +500,7 @@ impl<'a> DependencyResolver<'a> {
                                 status: ResolutionStatus::SkippedOptional,
                                 tags: dep_tags,
                                 failure_reason: None,
+                                upgrade_reason: None,
                             },
                         );
                     }
@@ -418,9 +520,8 @@ impl<'a> DependencyResolver<'a> {
                 let msg = e.to_string();
 
                 // move `e` into the error map
-                self.errors
-                    .entry(dep_name.to_string())
-                    .or_insert_with(|| Arc::new(e));
+                let interned = self.intern(dep_name);
+                self.errors.entry(interned).or_insert_with(|| Arc::new(e));
 
                 // mark the node as failed
                 if let Some(node) = self.resolution_details.get_mut(dep_name.as_str()) {
@@ -428,6 +529,17 @@ impl<'a> DependencyResolver<'a> {
                     node.failure_reason = Some(msg);
                 }
 
+                // a disabled dependency makes the dependent uninstallable too
+                if let Some(SpsError::FormulaDisabled(reason)) =
+                    self.errors.get(dep_name.as_str()).map(|e| e.as_ref())
+                {
+                    let reason = reason.clone();
+                    self.visiting.remove(name);
+                    return Err(SpsError::FormulaDisabled(format!(
+                        "{name} depends on a disabled formula: {reason}"
+                    )));
+                }
+
                 // propagate cycles upward
                 if is_cycle {
                     self.visiting.remove(name);
@@ -435,6 +547,23 @@ impl<'a> DependencyResolver<'a> {
                         "Circular dependency detected".into(),
                     ));
                 }
+                continue;
+            }
+
+            // A dependent that is about to be poured must not link against a keg older than
+            // it needs, so such kegs are upgraded rather than reused.
+            if matches!(
+                status,
+                ResolutionStatus::Requested | ResolutionStatus::Missing
+            ) && !self.context.only_missing
+            {
+                if let Some(reason) = self.upgrade_reason(name, dep) {
+                    debug!("Planning upgrade of '{}': {}", dep_name, reason);
+                    if let Some(node) = self.resolution_details.get_mut(dep_name.as_str()) {
+                        node.status = ResolutionStatus::Outdated;
+                        node.upgrade_reason = Some(reason);
+                    }
+                }
             }
         }
 
@@ -443,73 +572,99 @@ impl<'a> DependencyResolver<'a> {
         Ok(())
     }
 
+    /// Fails when a dependency of a resolved node has no node of its own. Later stages look
+    /// dependencies up by name, and a miss there would silently drop the edge.
+    fn check_edges(&self) -> Result<()> {
+        let mut missing: Vec<String> = Vec::new();
+        for (name, node) in &self.resolution_details {
+            if matches!(
+                node.status,
+                ResolutionStatus::NotFound
+                    | ResolutionStatus::Failed
+                    | ResolutionStatus::SkippedOptional
+            ) {
+                continue;
+            }
+            for dep in node.formula.dependencies() {
+                if self.should_consider_dependency(dep, name)
+                    && !self.resolution_details.contains_key(dep.name.as_str())
+                    && !self.errors.contains_key(dep.name.as_str())
+                {
+                    missing.push(format!("{name} -> {}", dep.name));
+                }
+            }
+        }
+        if missing.is_empty() {
+            return Ok(());
+        }
+        missing.sort();
+        Err(SpsError::DependencyError(format!(
+            "Dependency graph has edges to unknown nodes: {}",
+            missing.join(", ")
+        )))
+    }
+
     fn topological_sort(&self) -> Result<Vec<ResolvedDependency>> {
         debug!("Starting topological sort");
-        let mut in_degree: HashMap<String, usize> = HashMap::new();
-        let mut adj: HashMap<String, HashSet<String>> = HashMap::new();
+        // Keyed by names borrowed from `resolution_details`, and edges come straight from each
+        // formula's dependency list, so large plans don't pay for string and Vec clones here.
+        // Nodes and edges are visited by name, so the same graph always yields the same plan.
+        let mut in_degree: HashMap<&str, usize> = HashMap::new();
+        let mut adj: HashMap<&str, BTreeSet<&str>> = HashMap::new();
         let mut sorted_list = Vec::new();
         let mut queue = VecDeque::new();
 
-        let relevant_nodes: Vec<_> = self
+        let relevant_nodes: BTreeSet<&str> = self
             .resolution_details
             .iter()
             .filter(|(_, dep)| {
                 matches!(
                     dep.status,
                     ResolutionStatus::Installed
+                        | ResolutionStatus::InstalledUnlinked
+                        | ResolutionStatus::Outdated
                         | ResolutionStatus::Missing
                         | ResolutionStatus::Requested
                 )
             })
-            .map(|(name, _)| name.clone())
+            .map(|(name, _)| &**name)
             .collect();
 
-        for name in &relevant_nodes {
-            in_degree.entry(name.clone()).or_insert(0);
-            adj.entry(name.clone()).or_default();
+        for &name in &relevant_nodes {
+            in_degree.entry(name).or_insert(0);
+            adj.entry(name).or_default();
         }
 
-        for name in &relevant_nodes {
-            let resolved_dep = self.resolution_details.get(name).unwrap();
-            match resolved_dep.formula.dependencies() {
-                Ok(dependencies) => {
-                    for dep in dependencies {
-                        if relevant_nodes.contains(&dep.name)
-                            && self.should_consider_dependency(&dep)
-                            && adj
-                                .entry(dep.name.clone())
-                                .or_default()
-                                .insert(name.clone())
-                        {
-                            *in_degree.entry(name.clone()).or_insert(0) += 1;
-                        }
+        for &name in &relevant_nodes {
+            let resolved_dep = &self.resolution_details[name];
+            for dep in resolved_dep.formula.dependencies() {
+                if let Some(&dep_name) = relevant_nodes.get(dep.name.as_str()) {
+                    if self.should_consider_dependency(dep, name)
+                        && adj.entry(dep_name).or_default().insert(name)
+                    {
+                        *in_degree.entry(name).or_insert(0) += 1;
                     }
                 }
-                Err(e) => {
-                    error!(
-                        "Failed to get dependencies for '{}' during sort: {}",
-                        name, e
-                    );
-                    return Err(e);
-                }
             }
         }
 
         debug!("In-degrees (relevant nodes only): {:?}", in_degree);
 
-        for name in &relevant_nodes {
-            if *in_degree.get(name).unwrap_or(&1) == 0 {
-                queue.push_back(name.clone());
+        for &name in &relevant_nodes {
+            if in_degree.get(name).copied().unwrap_or(1) == 0 {
+                queue.push_back(name);
             }
         }
 
         debug!("Initial queue: {:?}", queue);
 
         while let Some(u_name) = queue.pop_front() {
-            if let Some(resolved_dep) = self.resolution_details.get(&u_name) {
+            if let Some(resolved_dep) = self.resolution_details.get(u_name) {
                 if matches!(
                     resolved_dep.status,
                     ResolutionStatus::Installed
+                        | ResolutionStatus::InstalledUnlinked
+                        | ResolutionStatus::Outdated
                         | ResolutionStatus::Missing
                         | ResolutionStatus::Requested
                 ) {
@@ -525,14 +680,12 @@ impl<'a> DependencyResolver<'a> {
                 )));
             }
 
-            if let Some(neighbors) = adj.get(&u_name) {
-                for v_name in neighbors {
-                    if relevant_nodes.contains(v_name) {
-                        if let Some(degree) = in_degree.get_mut(v_name) {
-                            *degree = degree.saturating_sub(1);
-                            if *degree == 0 {
-                                queue.push_back(v_name.clone());
-                            }
+            if let Some(neighbors) = adj.get(u_name) {
+                for &v_name in neighbors {
+                    if let Some(degree) = in_degree.get_mut(v_name) {
+                        *degree = degree.saturating_sub(1);
+                        if *degree == 0 {
+                            queue.push_back(v_name);
                         }
                     }
                 }
@@ -547,8 +700,7 @@ impl<'a> DependencyResolver<'a> {
             );
             let cyclic_nodes: Vec<_> = relevant_nodes
                 .iter()
-                .filter(|n| in_degree.get(*n).unwrap_or(&0) > &0)
-                .cloned()
+                .filter(|n| in_degree.get(*n).copied().unwrap_or(0) > 0)
                 .collect();
             error!(
                 "Nodes potentially involved in cycle (relevant, in-degree > 0): {:?}",
@@ -566,9 +718,52 @@ impl<'a> DependencyResolver<'a> {
         Ok(sorted_list)
     }
 
-    fn should_consider_dependency(&self, dep: &Dependency) -> bool {
+    /// Why the installed keg of `dep` is too old for `dependent`, if it is. An explicit minimum
+    /// version decides when present; otherwise the keg counts as too old when its major version
+    /// is behind the current formula's, which is what the dependent's bottle was built against.
+    fn upgrade_reason(&self, dependent: &str, dep: &Dependency) -> Option<String> {
+        let node = self.resolution_details.get(dep.name.as_str())?;
+        if !matches!(
+            node.status,
+            ResolutionStatus::Installed | ResolutionStatus::InstalledUnlinked
+        ) {
+            return None;
+        }
+        let installed_str = node.keg_path.as_ref()?.file_name()?.to_str()?;
+        // A keg built from the head is only replaced on request.
+        if installed_str.starts_with(HEAD_VERSION_PREFIX) {
+            return None;
+        }
+        let installed = PkgVersion::parse(installed_str);
+        let current = node.formula.pkg_version();
+        if current <= installed {
+            return None;
+        }
+        match &dep.min_version {
+            Some(min) => {
+                let wanted = PkgVersion::parse(min);
+                (installed < wanted).then(|| {
+                    format!(
+                        "{dependent} needs {} >= {min}, {installed_str} is installed",
+                        dep.name
+                    )
+                })
+            }
+            None => (installed.major()? < current.major()?).then(|| {
+                format!(
+                    "{dependent} is built against {} {}, {installed_str} is installed",
+                    dep.name,
+                    node.formula.version_str_full()
+                )
+            }),
+        }
+    }
+
+    fn should_consider_dependency(&self, dep: &Dependency, parent: &str) -> bool {
         let tags = dep.tags;
-        if tags.contains(DependencyTag::TEST) && !self.context.include_test {
+        if tags.contains(DependencyTag::TEST)
+            && !(self.context.include_test && self.targets.contains(parent))
+        {
             return false;
         }
         if tags.contains(DependencyTag::OPTIONAL) && !self.context.include_optional {
@@ -587,6 +782,7 @@ impl Formula {
             name: name.to_string(),
             stable_version_str: "0.0.0".to_string(),
             version_semver: semver::Version::new(0, 0, 0),
+            head_version_str: None,
             revision: 0,
             desc: Some("Placeholder for unresolved formula".to_string()),
             homepage: None,
@@ -597,7 +793,438 @@ impl Formula {
             dependencies: Vec::new(),
             requirements: Vec::new(),
             resources: Vec::new(),
+            caveats: None,
+            keg_only: false,
+            keg_only_reason: None,
+            conflicts_with: Vec::new(),
+            extra: Default::default(),
             install_keg_path: None,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::cache::Cache;
+    use crate::config::Config;
+
+    struct Env {
+        _dir: tempfile::TempDir,
+        config: Config,
+    }
+
+    /// A prefix with no kegs and a cached formula index holding `formulae`.
+    fn env(formulae: Value) -> Env {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            prefix: dir.path().to_path_buf(),
+            cellar: dir.path().join("Cellar"),
+            cache_dir: dir.path().join("cache"),
+            ..Config::load().unwrap()
+        };
+        std::fs::create_dir_all(&config.cellar).unwrap();
+        Cache::new(&config.cache_dir)
+            .unwrap()
+            .store_raw("formula.json", &formulae.to_string())
+            .unwrap();
+        Env { _dir: dir, config }
+    }
+
+    fn formula(name: &str, deps: &[&str], extra: Value) -> Value {
+        let mut value = json!({
+            "name": name,
+            "versions": { "stable": "1.0" },
+            "dependencies": deps,
+        });
+        value
+            .as_object_mut()
+            .unwrap()
+            .extend(extra.as_object().unwrap().clone());
+        value
+    }
+
+    fn disabled() -> Value {
+        json!({
+            "disabled": true,
+            "disable_date": "2024-05-01",
+            "disable_reason": "unmaintained",
+        })
+    }
+
+    fn resolve(env: &Env, targets: &[&str], only_missing: bool) -> ResolvedGraph {
+        resolve_with(env, targets, false, only_missing, None)
+    }
+
+    fn resolve_with(
+        env: &Env,
+        targets: &[&str],
+        ignore_installed: bool,
+        only_missing: bool,
+        platform: Option<&str>,
+    ) -> ResolvedGraph {
+        let formulary = Formulary::new(env.config.clone());
+        let keg_registry = KegRegistry::new(env.config.clone());
+        let mut resolver = DependencyResolver::new(ResolutionContext {
+            formulary: &formulary,
+            keg_registry: &keg_registry,
+            sps_prefix: &env.config.prefix,
+            include_optional: false,
+            include_test: false,
+            skip_recommended: false,
+            force_build: false,
+            ignore_installed,
+            only_missing,
+            platform,
+        });
+        let targets: Vec<String> = targets.iter().map(|t| t.to_string()).collect();
+        resolver.resolve_targets(&targets).unwrap()
+    }
+
+    fn planned(graph: &ResolvedGraph) -> Vec<&str> {
+        graph
+            .install_plan
+            .iter()
+            .map(|d| d.formula.name.as_str())
+            .collect()
+    }
+
+    fn disabled_message(graph: &ResolvedGraph, name: &str) -> String {
+        match graph.errors.get(name).map(|e| e.as_ref()) {
+            Some(SpsError::FormulaDisabled(msg)) => msg.clone(),
+            other => panic!("expected {name} to be disabled, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn a_disabled_target_fails_with_its_date_and_reason() {
+        let env = env(json!([formula("old", &[], disabled())]));
+
+        let graph = resolve(&env, &["old"], false);
+
+        assert!(planned(&graph).is_empty());
+        assert_eq!(
+            disabled_message(&graph, "old"),
+            "old was disabled on 2024-05-01 because it is not maintained upstream"
+        );
+    }
+
+    #[test]
+    fn dependents_of_a_disabled_formula_fail_and_others_go_on() {
+        let env = env(json!([
+            formula("app", &["lib"], json!({})),
+            formula("lib", &["old"], json!({})),
+            formula("old", &[], disabled()),
+            formula("other", &[], json!({})),
+        ]));
+
+        let graph = resolve(&env, &["app", "other"], false);
+
+        assert_eq!(planned(&graph), ["other"]);
+        let message = disabled_message(&graph, "app");
+        assert!(
+            message.starts_with("app depends on a disabled formula: lib depends on"),
+            "{message}"
+        );
+        assert!(
+            message
+                .ends_with("old was disabled on 2024-05-01 because it is not maintained upstream"),
+            "{message}"
+        );
+    }
+
+    #[test]
+    fn a_deprecated_dependency_is_still_planned() {
+        let env = env(json!([
+            formula("app", &["lib"], json!({})),
+            formula("lib", &[], json!({ "deprecated": true })),
+        ]));
+
+        let graph = resolve(&env, &["app"], false);
+
+        assert!(graph.errors.is_empty(), "{:?}", graph.errors);
+        assert_eq!(planned(&graph), ["lib", "app"]);
+    }
+
+    #[test]
+    fn an_installed_disabled_dependency_keeps_working() {
+        let env = env(json!([
+            formula("app", &["old"], json!({})),
+            formula("old", &[], disabled()),
+        ]));
+        installed_keg(&env, "old", "1.0");
+
+        let graph = resolve(&env, &["app"], true);
+
+        assert!(graph.errors.is_empty(), "{:?}", graph.errors);
+        assert_eq!(planned(&graph), ["app"]);
+    }
+
+    #[test]
+    fn an_unlinked_keg_without_a_receipt_is_poured_again() {
+        let env = env(json!([
+            formula("app", &["lib"], json!({})),
+            formula("lib", &[], json!({})),
+        ]));
+        std::fs::create_dir_all(env.config.cellar.join("lib/1.0/bin")).unwrap();
+
+        let graph = resolve(&env, &["app"], false);
+
+        assert!(graph.errors.is_empty(), "{:?}", graph.errors);
+        assert_eq!(
+            graph.resolution_details["lib"].status,
+            ResolutionStatus::Missing
+        );
+        assert_eq!(graph.resolution_details["lib"].keg_path, None);
+        assert_eq!(planned(&graph), ["lib", "app"]);
+    }
+
+    /// A finished keg of `name` at `version`: the directory with an install receipt.
+    fn installed_keg(env: &Env, name: &str, version: &str) -> PathBuf {
+        let keg = env.config.cellar.join(name).join(version);
+        std::fs::create_dir_all(&keg).unwrap();
+        std::fs::write(keg.join("INSTALL_RECEIPT.json"), "{}").unwrap();
+        keg
+    }
+
+    fn version(version: &str) -> Value {
+        json!({ "versions": { "stable": version } })
+    }
+
+    /// The status of `name` after resolving `app`, whose formula depends on `dep_spec`, with
+    /// `lib` at `current` in the index and `installed` in the Cellar.
+    fn lib_after_resolving_app(
+        dep_spec: &str,
+        current: &str,
+        installed: &str,
+        only_missing: bool,
+    ) -> ResolvedDependency {
+        let env = env(json!([
+            formula("app", &[dep_spec], json!({})),
+            formula("lib", &[], version(current)),
+        ]));
+        installed_keg(&env, "lib", installed);
+
+        let graph = resolve(&env, &["app"], only_missing);
+
+        assert!(graph.errors.is_empty(), "{:?}", graph.errors);
+        graph.resolution_details["lib"].clone()
+    }
+
+    #[test]
+    fn a_keg_a_major_version_behind_is_upgraded_for_a_new_dependent() {
+        let lib = lib_after_resolving_app("lib", "3.1", "2.0", false);
+
+        assert_eq!(lib.status, ResolutionStatus::Outdated);
+        assert_eq!(
+            lib.upgrade_reason.as_deref(),
+            Some("app is built against lib 3.1, 2.0 is installed")
+        );
+    }
+
+    #[test]
+    fn a_keg_on_the_current_major_version_is_reused() {
+        let lib = lib_after_resolving_app("lib", "2.9", "2.1", false);
+
+        assert_ne!(lib.status, ResolutionStatus::Outdated);
+        assert_eq!(lib.upgrade_reason, None);
+    }
+
+    #[test]
+    fn a_minimum_version_decides_when_given() {
+        let lib = lib_after_resolving_app("lib >= 2.5", "2.9", "2.1", false);
+        assert_eq!(lib.status, ResolutionStatus::Outdated);
+        assert_eq!(
+            lib.upgrade_reason.as_deref(),
+            Some("app needs lib >= 2.5, 2.1 is installed")
+        );
+
+        let lib = lib_after_resolving_app("lib >= 2.5", "3.0", "2.6", false);
+        assert_ne!(lib.status, ResolutionStatus::Outdated);
+    }
+
+    #[test]
+    fn only_missing_never_upgrades_an_installed_dependency() {
+        let lib = lib_after_resolving_app("lib", "3.1", "2.0", true);
+
+        assert_ne!(lib.status, ResolutionStatus::Outdated);
+    }
+
+    #[test]
+    fn the_install_modes_plan_one_cellar_differently() {
+        let env = env(json!([
+            formula("app", &["base", "lib", "zlib"], json!({})),
+            formula("base", &[], json!({})),
+            formula("lib", &[], version("3.1")),
+            formula("zlib", &[], json!({})),
+        ]));
+        // `lib` is a major version behind and `base` lost its opt link; `zlib` is current.
+        for (name, installed, linked) in [
+            ("base", "1.0", false),
+            ("lib", "2.0", true),
+            ("zlib", "1.0", true),
+        ] {
+            let keg = installed_keg(&env, name, installed);
+            if linked {
+                let opt = env.config.prefix.join("opt");
+                std::fs::create_dir_all(&opt).unwrap();
+                std::os::unix::fs::symlink(&keg, opt.join(name)).unwrap();
+            }
+        }
+        // What happens to each dependency, and what gets poured.
+        let outcome = |ignore_installed, only_missing| {
+            let graph = resolve_with(&env, &["app"], ignore_installed, only_missing, None);
+            assert!(graph.errors.is_empty(), "{:?}", graph.errors);
+            let statuses: Vec<ResolutionStatus> = ["base", "lib", "zlib"]
+                .iter()
+                .map(|name| graph.resolution_details[*name].status)
+                .collect();
+            let planned: Vec<String> = planned(&graph).into_iter().map(str::to_string).collect();
+            (statuses, planned)
+        };
+        use ResolutionStatus::*;
+
+        // Relinks `base`, upgrades `lib` and reuses `zlib`.
+        assert_eq!(
+            outcome(false, false),
+            (
+                vec![InstalledUnlinked, Outdated, Installed],
+                vec!["lib".into(), "app".into()]
+            )
+        );
+        // Pours everything again, as if nothing were installed.
+        assert_eq!(
+            outcome(true, false),
+            (
+                vec![Missing, Missing, Missing],
+                ["base", "lib", "zlib", "app"].map(String::from).to_vec()
+            )
+        );
+        // Touches nothing that is installed in any form.
+        assert_eq!(
+            outcome(false, true),
+            (vec![Installed, Installed, Installed], vec!["app".into()])
+        );
+    }
+
+    #[test]
+    fn a_platform_variation_adds_its_dependencies_to_the_plan() {
+        let arm64 =
+            |deps: &[&str]| json!({ "variations": { "arm64_sonoma": { "dependencies": deps } } });
+        let env = env(json!([
+            formula("app", &["lib"], arm64(&["lib", "rosetta-shim"])),
+            formula("lib", &[], arm64(&["neon"])),
+            formula("rosetta-shim", &[], json!({})),
+            formula("neon", &[], json!({})),
+        ]));
+
+        let plan = |platform| {
+            let graph = resolve_with(&env, &["app"], false, false, platform);
+            assert!(graph.errors.is_empty(), "{:?}", graph.errors);
+            let mut planned: Vec<String> =
+                planned(&graph).into_iter().map(str::to_string).collect();
+            planned.sort();
+            planned
+        };
+
+        // Dependencies of a dependency follow the same platform.
+        assert_eq!(
+            plan(Some("arm64_sonoma")),
+            ["app", "lib", "neon", "rosetta-shim"]
+        );
+        assert_eq!(plan(Some("sonoma")), ["app", "lib"]);
+        assert_eq!(plan(None), ["app", "lib"]);
+    }
+
+    #[test]
+    fn plans_a_diamond_in_a_stable_order() {
+        let env = env(json!([
+            formula("app", &["tool", "lib"], json!({})),
+            formula("tool", &["base"], json!({})),
+            formula("lib", &["base", "zlib"], json!({})),
+            formula("base", &[], json!({})),
+            formula("zlib", &[], json!({})),
+            formula("unrelated", &[], json!({})),
+        ]));
+
+        let first = resolve(&env, &["app"], false);
+        let again = resolve(&env, &["app"], false);
+
+        assert_eq!(planned(&first), ["base", "zlib", "tool", "lib", "app"]);
+        assert_eq!(planned(&again), planned(&first));
+        assert_eq!(
+            first.resolution_details["zlib"].formula.dependencies(),
+            &[] as &[Dependency]
+        );
+    }
+
+    #[test]
+    fn a_dependency_declared_by_alias_resolves_to_the_formula_it_names() {
+        let env = env(json!([
+            formula("openssl@3", &[], json!({ "aliases": ["openssl"] })),
+            formula("app", &["openssl"], json!({})),
+        ]));
+
+        let graph = resolve(&env, &["app"], false);
+
+        assert_eq!(planned(&graph), ["openssl@3", "app"]);
+        let app = &graph.resolution_details["app"].formula;
+        let deps: Vec<&str> = app.dependencies.iter().map(|d| d.name.as_str()).collect();
+        assert_eq!(deps, ["openssl@3"]);
+        assert!(!graph.resolution_details.contains_key("openssl"));
+    }
+
+    #[test]
+    fn canonical_names_prefer_exact_names_and_the_first_claim_on_an_alias() {
+        let env = env(json!([
+            formula("openssl@3", &[], json!({ "aliases": ["openssl", "ssl"] })),
+            formula("libressl", &[], json!({ "aliases": ["ssl"] })),
+            formula("python", &[], json!({})),
+            formula("python@3.12", &[], json!({ "aliases": ["python"] })),
+        ]));
+        let formulary = Formulary::new(env.config.clone());
+        formulary.load_formula("openssl@3").unwrap();
+
+        assert_eq!(formulary.canonical_name("openssl"), "openssl@3");
+        assert_eq!(
+            formulary.canonical_name("homebrew/core/openssl"),
+            "openssl@3"
+        );
+        assert_eq!(formulary.canonical_name("ssl"), "openssl@3");
+        assert_eq!(formulary.canonical_name("python"), "python");
+        assert_eq!(formulary.canonical_name("unknown"), "unknown");
+    }
+
+    #[test]
+    fn an_edge_to_a_node_that_was_never_resolved_is_an_error() {
+        let env = env(json!([
+            formula("lib", &[], json!({})),
+            formula("app", &["lib"], json!({})),
+        ]));
+        let formulary = Formulary::new(env.config.clone());
+        let keg_registry = KegRegistry::new(env.config.clone());
+        let mut resolver = DependencyResolver::new(ResolutionContext {
+            formulary: &formulary,
+            keg_registry: &keg_registry,
+            sps_prefix: &env.config.prefix,
+            include_optional: false,
+            include_test: false,
+            skip_recommended: false,
+            force_build: false,
+            ignore_installed: false,
+            only_missing: false,
+            platform: None,
+        });
+        resolver.resolve_targets(&["app".to_string()]).unwrap();
+        assert!(resolver.check_edges().is_ok());
+
+        resolver.resolution_details.remove("lib");
+
+        match resolver.check_edges() {
+            Err(SpsError::DependencyError(msg)) => assert!(msg.ends_with("app -> lib"), "{msg}"),
+            other => panic!("expected a dependency error, got {other:?}"),
+        }
+    }
+}
@@ -0,0 +1,287 @@
+// sps-common/src/dependency/reverse.rs
+//! Reverse runtime-dependency graph over installed kegs, used to decide what can be removed
+//! safely and in which order.
+
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+
+use serde_json::Value;
+use tracing::debug;
+
+use crate::dependency::scheduler::{Direction, FailurePolicy, Scheduler};
+use crate::dependency::DependencyTag;
+use crate::error::Result;
+use crate::formulary::Formulary;
+use crate::keg::{InstalledKeg, KegRegistry};
+
+/// The installed kegs as a [`Scheduler`] graph, the same graph installs run on, read from its
+/// reverse edges.
+#[derive(Debug, Clone)]
+pub struct ReverseDependencyGraph {
+    /// Installed formula -> the installed formulae it depends on at runtime, removed
+    /// dependents first.
+    graph: Scheduler,
+}
+
+impl Default for ReverseDependencyGraph {
+    fn default() -> Self {
+        Self {
+            graph: Scheduler::new(Direction::DependentsFirst, FailurePolicy::Continue),
+        }
+    }
+}
+
+impl ReverseDependencyGraph {
+    /// Builds the graph from the receipts of every current keg; kegs kept from earlier versions
+    /// don't count.
+    pub fn from_installed(keg_registry: &KegRegistry, formulary: &Formulary) -> Result<Self> {
+        let kegs = keg_registry.list_current_kegs()?;
+        let mut graph = Self::default();
+        for keg in &kegs {
+            graph.graph.add_node(&keg.name);
+        }
+        // Dependencies that are not installed are not nodes, so the scheduler drops them.
+        for keg in &kegs {
+            for dep in runtime_dependencies(keg, formulary) {
+                graph.graph.add_edge(&keg.name, &dep);
+            }
+        }
+        Ok(graph)
+    }
+
+    /// Records that `dependent` needs `dependency` at runtime, adding either as a node if needed.
+    pub fn add_edge(&mut self, dependent: &str, dependency: &str) {
+        self.graph.add_node(dependent);
+        self.graph.add_node(dependency);
+        self.graph.add_edge(dependent, dependency);
+    }
+
+    pub fn dependents_of(&self, name: &str) -> impl Iterator<Item = &str> {
+        self.graph.dependents_of(name)
+    }
+
+    /// For each member of `targets`, the direct dependents that are not themselves in
+    /// `targets`. Targets without such dependents are omitted.
+    pub fn outside_dependents(&self, targets: &HashSet<String>) -> BTreeMap<String, Vec<String>> {
+        targets
+            .iter()
+            .filter_map(|target| {
+                let outside: Vec<String> = self
+                    .dependents_of(target)
+                    .filter(|d| !targets.contains(*d))
+                    .map(str::to_string)
+                    .collect();
+                (!outside.is_empty()).then(|| (target.clone(), outside))
+            })
+            .collect()
+    }
+
+    /// `targets` plus everything that transitively depends on them.
+    pub fn with_all_dependents(&self, targets: &HashSet<String>) -> HashSet<String> {
+        let mut closure = targets.clone();
+        let mut stack: Vec<String> = targets.iter().cloned().collect();
+        while let Some(name) = stack.pop() {
+            for dependent in self.dependents_of(&name) {
+                if closure.insert(dependent.to_string()) {
+                    stack.push(dependent.to_string());
+                }
+            }
+        }
+        closure
+    }
+
+    /// A dependents-first [`Scheduler`] over `names`: nothing is removed while something else in
+    /// the set still needs it.
+    pub fn removal_scheduler(&self, names: &HashSet<String>, policy: FailurePolicy) -> Scheduler {
+        let mut names: Vec<&String> = names.iter().collect();
+        names.sort();
+        let mut scheduler = self
+            .graph
+            .subgraph(names.iter().map(|n| n.as_str()), policy);
+        // Names outside the graph (not installed) are still removed, with nothing to wait on.
+        for name in names {
+            scheduler.add_node(name);
+        }
+        scheduler
+    }
+
+    /// Orders `names` so every formula comes after all of its dependents within the set. Ties
+    /// are broken alphabetically to keep plans stable.
+    pub fn removal_order(&self, names: &HashSet<String>) -> Vec<String> {
+        self.removal_scheduler(names, FailurePolicy::Continue)
+            .order()
+            .iter()
+            .map(|name| name.to_string())
+            .collect()
+    }
+}
+
+/// Runtime dependencies recorded in the keg's install receipt, falling back to the current
+/// formulary metadata for kegs whose receipt predates the `runtime_dependencies` field. Receipts
+/// written by Homebrew (adopted kegs) list objects with a `full_name` instead of names.
+pub fn runtime_dependencies(keg: &InstalledKeg, formulary: &Formulary) -> Vec<String> {
+    let receipt_path = keg.path.join("INSTALL_RECEIPT.json");
+    let recorded = fs::read_to_string(&receipt_path)
+        .ok()
+        .and_then(|s| serde_json::from_str::<Value>(&s).ok())
+        .and_then(|receipt| {
+            receipt
+                .get("runtime_dependencies")
+                .and_then(Value::as_array)
+                .map(|deps| {
+                    deps.iter()
+                        .filter_map(|dep| {
+                            dep.as_str()
+                                .or_else(|| dep.get("full_name").and_then(Value::as_str))
+                        })
+                        .map(str::to_string)
+                        .collect::<Vec<_>>()
+                })
+        });
+    if let Some(deps) = recorded {
+        return deps;
+    }
+
+    debug!(
+        "No recorded runtime dependencies for {}; using current metadata.",
+        keg.name
+    );
+    match formulary.load_formula(&keg.name) {
+        Ok(formula) => formula
+            .dependencies()
+            .iter()
+            .filter(|d| {
+                d.tags.contains(DependencyTag::RUNTIME) && !d.tags.contains(DependencyTag::OPTIONAL)
+            })
+            .map(|d| d.name.clone())
+            .collect(),
+        Err(e) => {
+            debug!("Could not load formula {}: {}", keg.name, e);
+            Vec::new()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    fn graph(edges: &[(&str, &str)]) -> ReverseDependencyGraph {
+        let mut graph = ReverseDependencyGraph::default();
+        for (dependent, dependency) in edges {
+            graph.add_edge(dependent, dependency);
+        }
+        graph
+    }
+
+    fn set(names: &[&str]) -> HashSet<String> {
+        names.iter().map(|n| n.to_string()).collect()
+    }
+
+    #[test]
+    fn a_chain_is_removed_from_the_top_down() {
+        // app -> lib -> base
+        let graph = graph(&[("app", "lib"), ("lib", "base")]);
+
+        assert_eq!(
+            graph.outside_dependents(&set(&["base"])),
+            BTreeMap::from([("base".to_string(), vec!["lib".to_string()])])
+        );
+        let cascade = graph.with_all_dependents(&set(&["base"]));
+        assert_eq!(cascade, set(&["app", "lib", "base"]));
+        assert_eq!(graph.removal_order(&cascade), ["app", "lib", "base"]);
+    }
+
+    #[test]
+    fn a_diamond_removes_the_shared_dependency_last() {
+        // app -> {left, right} -> base
+        let graph = graph(&[
+            ("app", "left"),
+            ("app", "right"),
+            ("left", "base"),
+            ("right", "base"),
+        ]);
+
+        let mut dependents: Vec<&str> = graph.dependents_of("base").collect();
+        dependents.sort();
+        assert_eq!(dependents, ["left", "right"]);
+        let cascade = graph.with_all_dependents(&set(&["base"]));
+        assert_eq!(cascade, set(&["app", "left", "right", "base"]));
+        assert_eq!(
+            graph.removal_order(&cascade),
+            ["app", "left", "right", "base"]
+        );
+        assert_eq!(
+            graph.outside_dependents(&set(&["left", "base"])),
+            BTreeMap::from([
+                ("base".to_string(), vec!["right".to_string()]),
+                ("left".to_string(), vec!["app".to_string()]),
+            ])
+        );
+    }
+
+    #[test]
+    fn a_dependent_in_the_requested_set_does_not_block_removal() {
+        let graph = graph(&[("app", "lib"), ("lib", "base"), ("tool", "base")]);
+        let requested = set(&["lib", "base", "tool"]);
+
+        assert_eq!(
+            graph.outside_dependents(&requested),
+            BTreeMap::from([("lib".to_string(), vec!["app".to_string()])])
+        );
+        assert!(graph
+            .outside_dependents(&set(&["app", "lib", "base", "tool"]))
+            .is_empty());
+        assert_eq!(
+            graph.removal_order(&set(&["lib", "base", "tool"])),
+            ["lib", "tool", "base"]
+        );
+    }
+
+    #[test]
+    fn names_outside_the_graph_are_still_ordered() {
+        let graph = graph(&[("app", "lib")]);
+
+        assert_eq!(
+            graph.removal_order(&set(&["lib", "stray", "app"])),
+            ["app", "lib", "stray"]
+        );
+    }
+
+    #[test]
+    fn builds_edges_from_install_receipts_of_installed_kegs() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            prefix: dir.path().to_path_buf(),
+            cellar: dir.path().join("Cellar"),
+            ..Config::load().unwrap()
+        };
+        for (name, deps) in [
+            ("app", serde_json::json!(["lib", "not-installed"])),
+            // Homebrew's receipts list objects.
+            ("lib", serde_json::json!([{ "full_name": "base" }])),
+            ("base", serde_json::json!([])),
+        ] {
+            let keg = config.cellar.join(name).join("1.0");
+            fs::create_dir_all(&keg).unwrap();
+            fs::write(
+                keg.join("INSTALL_RECEIPT.json"),
+                serde_json::json!({ "runtime_dependencies": deps }).to_string(),
+            )
+            .unwrap();
+        }
+        let registry = KegRegistry::new(config.clone());
+        let formulary = Formulary::new(config);
+
+        let graph = ReverseDependencyGraph::from_installed(&registry, &formulary).unwrap();
+
+        assert_eq!(graph.dependents_of("lib").collect::<Vec<_>>(), ["app"]);
+        assert_eq!(graph.dependents_of("base").collect::<Vec<_>>(), ["lib"]);
+        assert_eq!(graph.dependents_of("not-installed").count(), 0);
+        assert_eq!(
+            graph.removal_order(&set(&["app", "lib", "base"])),
+            ["app", "lib", "base"]
+        );
+    }
+}
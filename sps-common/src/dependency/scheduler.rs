@@ -0,0 +1,879 @@
+// sps-common/src/dependency/scheduler.rs
+//! Orders work over a dependency graph: install and upgrade run dependencies first, uninstall
+//! runs dependents first. The scheduler only tracks state; callers either drive it step by step
+//! ([`Scheduler::ready`], [`Scheduler::start`], [`Scheduler::complete`]) from their own worker
+//! pool, or hand [`Scheduler::run`] a closure to execute nodes one at a time.
+//!
+//! Ties are broken alphabetically so plans are stable between runs. If the graph has a cycle,
+//! the alphabetically first waiting node is released once nothing else can make progress.
+//!
+//! Nodes can be grouped under the targets the user asked for ([`Scheduler::set_targets`]); a node
+//! is part of every target that needs it. [`FailurePolicy::StopAll`] then only gives up the
+//! targets a failed node is part of, and stops work nothing else needs, so unrelated targets
+//! still complete.
+//!
+//! Each edge keeps the [`DependencyTag`] it was added with. Under
+//! [`FailurePolicy::SkipBlocked`] a failed or skipped optional or recommended dependency doesn't
+//! hold its dependents back; they run without it ([`Scheduler::missing_soft_dependencies`]).
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
+
+use tracing::debug;
+
+use crate::dependency::DependencyTag;
+use crate::error::{Result, SpsError};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// A node runs after everything it depends on (install, upgrade).
+    DependenciesFirst,
+    /// A node runs after everything that depends on it (uninstall).
+    DependentsFirst,
+}
+
+/// What a failed node does to the rest of the graph.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailurePolicy {
+    /// Nodes waiting on the failed one through required edges, directly or transitively, are
+    /// skipped; the rest go on.
+    SkipBlocked,
+    /// Nothing further is started (`--fail-fast`). With targets set, only for the targets the
+    /// failed node is part of.
+    StopAll,
+    /// Failures don't hold anything back.
+    Continue,
+}
+
+#[derive(Debug)]
+pub enum Outcome<T> {
+    Done(T),
+    Failed(SpsError),
+    /// Not run because the named node failed or was skipped itself (or, under
+    /// [`FailurePolicy::StopAll`], because something failed).
+    Skipped(Arc<str>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum NodeState {
+    Pending,
+    Running,
+    Done,
+    Failed,
+    Skipped(Arc<str>),
+}
+
+/// How many nodes are in each phase. Every node is counted in exactly one of `pending`,
+/// `running`, `finished`, `failed_unstarted` and `skipped`; the loop drivers terminate on these
+/// rather than on a scan of the node states.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Progress {
+    pub pending: usize,
+    pub running: usize,
+    /// Nodes ever started, whether or not they have finished.
+    pub started: usize,
+    /// Started nodes that completed, successfully or not.
+    pub finished: usize,
+    /// Nodes failed before they were started (e.g. their download failed).
+    pub failed_unstarted: usize,
+    pub skipped: usize,
+}
+
+/// Index of a node in [`Scheduler`]'s interned names.
+type NodeId = usize;
+
+/// Edges of one node, keyed by the name at the other end so they come out in name order, with
+/// that node's id and the tags of the edge.
+type Edges = BTreeMap<Arc<str>, (NodeId, DependencyTag)>;
+
+#[derive(Debug, Clone)]
+pub struct Scheduler {
+    direction: Direction,
+    policy: FailurePolicy,
+    /// Node names, interned once when the node is added. Everything else refers to a node by
+    /// its index here, and names handed out are clones of these rather than fresh strings.
+    names: Vec<Arc<str>>,
+    /// Name -> node, in name order, which is the order nodes are offered in.
+    ids: BTreeMap<Arc<str>, NodeId>,
+    /// Node -> the nodes it depends on, within the graph.
+    dependencies: Vec<Edges>,
+    /// Node -> the nodes that depend on it, within the graph.
+    dependents: Vec<Edges>,
+    states: Vec<NodeState>,
+    /// Node -> the targets it is part of; empty unless [`Self::set_targets`] was called.
+    targets_of: Vec<BTreeSet<Arc<str>>>,
+    /// Targets given up under [`FailurePolicy::StopAll`].
+    failed_targets: BTreeSet<Arc<str>>,
+    progress: Progress,
+    stopped: bool,
+}
+
+impl Scheduler {
+    pub fn new(direction: Direction, policy: FailurePolicy) -> Self {
+        Self {
+            direction,
+            policy,
+            names: Vec::new(),
+            ids: BTreeMap::new(),
+            dependencies: Vec::new(),
+            dependents: Vec::new(),
+            states: Vec::new(),
+            targets_of: Vec::new(),
+            failed_targets: BTreeSet::new(),
+            progress: Progress::default(),
+            stopped: false,
+        }
+    }
+
+    pub fn add_node(&mut self, name: &str) {
+        if !self.ids.contains_key(name) {
+            let name: Arc<str> = Arc::from(name);
+            self.ids.insert(Arc::clone(&name), self.names.len());
+            self.names.push(name);
+            self.dependencies.push(Edges::new());
+            self.dependents.push(Edges::new());
+            self.states.push(NodeState::Pending);
+            self.progress.pending += 1;
+        }
+    }
+
+    fn id(&self, name: &str) -> Option<NodeId> {
+        self.ids.get(name).copied()
+    }
+
+    /// Records that `dependent` needs `dependency`. Edges to names that are not nodes are
+    /// ignored, so callers can pass a package's full dependency list.
+    pub fn add_edge(&mut self, dependent: &str, dependency: &str) {
+        self.add_tagged_edge(dependent, dependency, DependencyTag::RUNTIME);
+    }
+
+    /// Like [`Self::add_edge`], for a dependency declared with `tags`. An edge added twice keeps
+    /// the union of its tags, and is only optional if every declaration is.
+    pub fn add_tagged_edge(&mut self, dependent: &str, dependency: &str, tags: DependencyTag) {
+        let (Some(from), Some(to)) = (self.id(dependent), self.id(dependency)) else {
+            return;
+        };
+        if from == to {
+            return;
+        }
+        let merged = match self.dependencies[from].get(dependency) {
+            Some(&(_, existing)) if is_soft(existing) && is_soft(tags) => existing | tags,
+            Some(&(_, existing)) => {
+                (existing | tags) - (DependencyTag::OPTIONAL | DependencyTag::RECOMMENDED)
+            }
+            None => tags,
+        };
+        let (from_name, to_name) = (Arc::clone(&self.names[from]), Arc::clone(&self.names[to]));
+        self.dependencies[from].insert(to_name, (to, merged));
+        self.dependents[to].insert(from_name, (from, merged));
+    }
+
+    /// Groups the nodes under `targets`: each target is part of itself and of everything it
+    /// waits on, directly or transitively. Nodes no target reaches become targets of their own,
+    /// grouped the same way, so a failure below one of them still gives it up.
+    /// Call once every node and edge has been added.
+    pub fn set_targets<'a>(&mut self, targets: impl IntoIterator<Item = &'a str>) {
+        let mut targets_of = vec![BTreeSet::new(); self.names.len()];
+        for target in targets {
+            if let Some(id) = self.id(target) {
+                self.group_under(id, &mut targets_of);
+            }
+        }
+        let unreached: Vec<NodeId> = self
+            .ids
+            .values()
+            .copied()
+            .filter(|&id| targets_of[id].is_empty())
+            .collect();
+        for id in unreached {
+            self.group_under(id, &mut targets_of);
+        }
+        self.targets_of = targets_of;
+    }
+
+    /// Adds `target` to the targets of itself and of everything it waits on.
+    fn group_under(&self, target: NodeId, targets_of: &mut [BTreeSet<Arc<str>>]) {
+        let mut stack = vec![target];
+        while let Some(id) = stack.pop() {
+            if !targets_of[id].insert(Arc::clone(&self.names[target])) {
+                continue;
+            }
+            stack.extend(self.waits_on(id).map(|(_, dep, _)| dep));
+        }
+    }
+
+    /// The nodes with an edge to `name`, in name order.
+    pub fn dependents_of(&self, name: &str) -> impl Iterator<Item = &str> {
+        self.id(name)
+            .map(|id| &self.dependents[id])
+            .into_iter()
+            .flatten()
+            .map(|(n, _)| &**n)
+    }
+
+    /// A fresh scheduler over the members of `names` that are nodes here, with the edges among
+    /// them and this scheduler's direction.
+    pub fn subgraph<'a>(
+        &self,
+        names: impl IntoIterator<Item = &'a str>,
+        policy: FailurePolicy,
+    ) -> Scheduler {
+        let mut subgraph = Scheduler::new(self.direction, policy);
+        let ids: Vec<NodeId> = names.into_iter().filter_map(|n| self.id(n)).collect();
+        for &id in &ids {
+            subgraph.add_node(&self.names[id]);
+        }
+        for &id in &ids {
+            for (dependency, (_, tags)) in &self.dependencies[id] {
+                subgraph.add_tagged_edge(&self.names[id], dependency, *tags);
+            }
+        }
+        subgraph
+    }
+
+    /// Node -> the targets it is part of, as grouped by [`Self::set_targets`].
+    pub fn targets_of(&self) -> BTreeMap<Arc<str>, BTreeSet<Arc<str>>> {
+        self.names
+            .iter()
+            .cloned()
+            .zip(self.targets_of.iter().cloned())
+            .collect()
+    }
+
+    pub fn policy(&self) -> FailurePolicy {
+        self.policy
+    }
+
+    pub fn progress(&self) -> Progress {
+        self.progress
+    }
+
+    /// Moves node `id` to `to`, keeping the counters in step. Only forward moves are allowed: a
+    /// pending node may start, fail or be skipped, and a running node may finish. Returns whether
+    /// the node moved.
+    fn transition(&mut self, id: NodeId, to: NodeState) -> bool {
+        let state = &mut self.states[id];
+        let progress = &mut self.progress;
+        match (&*state, &to) {
+            (NodeState::Pending, NodeState::Running) => {
+                progress.pending -= 1;
+                progress.running += 1;
+                progress.started += 1;
+            }
+            (NodeState::Pending, NodeState::Failed) => {
+                progress.pending -= 1;
+                progress.failed_unstarted += 1;
+            }
+            (NodeState::Pending, NodeState::Skipped(_)) => {
+                progress.pending -= 1;
+                progress.skipped += 1;
+            }
+            (NodeState::Running, NodeState::Done | NodeState::Failed) => {
+                progress.running -= 1;
+                progress.finished += 1;
+            }
+            (from, to) => {
+                debug!("Ignoring {:?} -> {:?} for {}", from, to, self.names[id]);
+                return false;
+            }
+        }
+        *state = to;
+        true
+    }
+
+    /// Skips node `id` because of `cause`, if it is still pending, returning the pair
+    /// [`Self::complete`] reports for it.
+    fn skip(&mut self, id: NodeId, cause: &Arc<str>) -> Option<(Arc<str>, Arc<str>)> {
+        self.transition(id, NodeState::Skipped(Arc::clone(cause)))
+            .then(|| (Arc::clone(&self.names[id]), Arc::clone(cause)))
+    }
+
+    /// Nodes not yet started, failed or skipped, in name order.
+    pub fn pending(&self) -> Vec<Arc<str>> {
+        self.pending_ids()
+            .map(|id| Arc::clone(&self.names[id]))
+            .collect()
+    }
+
+    fn pending_ids(&self) -> impl Iterator<Item = NodeId> + '_ {
+        self.ids
+            .values()
+            .copied()
+            .filter(|&id| self.states[id] == NodeState::Pending)
+    }
+
+    /// The nodes that must finish before node `id` may start, with the tags of the edge.
+    fn waits_on(&self, id: NodeId) -> impl Iterator<Item = (&Arc<str>, NodeId, DependencyTag)> {
+        match self.direction {
+            Direction::DependenciesFirst => &self.dependencies[id],
+            Direction::DependentsFirst => &self.dependents[id],
+        }
+        .iter()
+        .map(|(name, &(id, tags))| (name, id, tags))
+    }
+
+    /// The nodes that wait for node `id` through a required edge, and so can't run if it fails.
+    fn blocks(&self, id: NodeId) -> impl Iterator<Item = NodeId> + '_ {
+        match self.direction {
+            Direction::DependenciesFirst => &self.dependents[id],
+            Direction::DependentsFirst => &self.dependencies[id],
+        }
+        .values()
+        .filter(|(_, tags)| !self.tolerates_failure(*tags))
+        .map(|&(id, _)| id)
+    }
+
+    /// Whether a failed or skipped node across an edge tagged `tags` lets the waiting one run.
+    fn tolerates_failure(&self, tags: DependencyTag) -> bool {
+        match self.policy {
+            FailurePolicy::Continue => true,
+            FailurePolicy::SkipBlocked => is_soft(tags),
+            FailurePolicy::StopAll => false,
+        }
+    }
+
+    fn is_satisfied(&self, id: NodeId, tags: DependencyTag) -> bool {
+        match self.states[id] {
+            NodeState::Done => true,
+            NodeState::Failed | NodeState::Skipped(_) => self.tolerates_failure(tags),
+            _ => false,
+        }
+    }
+
+    /// The optional and recommended prerequisites of `name` that failed or were skipped, with
+    /// the tags of their edge. A node started under [`FailurePolicy::SkipBlocked`] runs without
+    /// these.
+    pub fn missing_soft_dependencies(&self, name: &str) -> Vec<(Arc<str>, DependencyTag)> {
+        let Some(id) = self.id(name) else {
+            return Vec::new();
+        };
+        self.waits_on(id)
+            .filter(|&(_, dep, tags)| {
+                is_soft(tags)
+                    && matches!(self.states[dep], NodeState::Failed | NodeState::Skipped(_))
+            })
+            .map(|(dep, _, tags)| (Arc::clone(dep), tags))
+            .collect()
+    }
+
+    /// Pending nodes whose prerequisites have all finished, in name order.
+    pub fn ready(&self) -> Vec<Arc<str>> {
+        self.ready_matching(usize::MAX, |_| true)
+    }
+
+    /// The first `limit` nodes of [`Self::ready`] that `accept` takes, in name order, without
+    /// collecting the rest; drivers with a fixed number of free slots ask for that many.
+    pub fn ready_matching(
+        &self,
+        limit: usize,
+        mut accept: impl FnMut(&str) -> bool,
+    ) -> Vec<Arc<str>> {
+        if self.stopped || limit == 0 {
+            return Vec::new();
+        }
+        let mut any_ready = false;
+        let ready: Vec<Arc<str>> = self
+            .pending_ids()
+            .filter(|&id| {
+                self.waits_on(id)
+                    .all(|(_, dep, tags)| self.is_satisfied(dep, tags))
+            })
+            .inspect(|_| any_ready = true)
+            .map(|id| &self.names[id])
+            .filter(|name| accept(name))
+            .take(limit)
+            .cloned()
+            .collect();
+        if any_ready || self.progress.running > 0 {
+            return ready;
+        }
+        // Nothing is running and nothing is ready, so whatever is still pending waits on itself.
+        match self
+            .pending_ids()
+            .map(|id| &self.names[id])
+            .find(|name| accept(name))
+        {
+            Some(name) => {
+                debug!("Dependency cycle among pending nodes; releasing {}", name);
+                vec![Arc::clone(name)]
+            }
+            None => Vec::new(),
+        }
+    }
+
+    /// Marks a node returned by [`Self::ready`] as running. Returns `false`, leaving the node
+    /// alone, if it is no longer pending (it was skipped or failed since `ready` returned it);
+    /// the caller must not run it then.
+    pub fn start(&mut self, name: &str) -> bool {
+        !self.stopped
+            && self
+                .id(name)
+                .is_some_and(|id| self.transition(id, NodeState::Running))
+    }
+
+    /// Records the result of a running node, or the failure of a pending one that could not be
+    /// started, and returns the nodes skipped because of it, each with the node that caused it.
+    /// Results for nodes that already finished or were skipped are ignored.
+    pub fn complete(&mut self, name: &str, success: bool) -> Vec<(Arc<str>, Arc<str>)> {
+        let Some(id) = self.id(name) else {
+            return Vec::new();
+        };
+        let to = if success {
+            NodeState::Done
+        } else {
+            NodeState::Failed
+        };
+        if !self.transition(id, to) || success {
+            return Vec::new();
+        }
+        match self.policy {
+            FailurePolicy::Continue => Vec::new(),
+            FailurePolicy::SkipBlocked => self.skip_blocked_by(id),
+            FailurePolicy::StopAll if !self.targets_of.is_empty() => self.give_up_targets_of(id),
+            FailurePolicy::StopAll => {
+                self.stopped = true;
+                let cause = Arc::clone(&self.names[id]);
+                let pending: Vec<NodeId> = self.pending_ids().collect();
+                pending
+                    .into_iter()
+                    .filter_map(|n| self.skip(n, &cause))
+                    .collect()
+            }
+        }
+    }
+
+    /// Gives up every target `failed` is part of and skips the pending nodes only those targets
+    /// need. Anything waiting on `failed` is among them, since it is part of no target `failed`
+    /// isn't. Stops the run once every target is given up.
+    fn give_up_targets_of(&mut self, failed: NodeId) -> Vec<(Arc<str>, Arc<str>)> {
+        if let Some(targets) = self.targets_of.get(failed) {
+            self.failed_targets.extend(targets.iter().cloned());
+        }
+        let given_up: Vec<NodeId> = self
+            .pending_ids()
+            .filter(|&id| {
+                self.targets_of
+                    .get(id)
+                    .is_none_or(|targets| targets.is_subset(&self.failed_targets))
+            })
+            .collect();
+        let cause = Arc::clone(&self.names[failed]);
+        let skipped = given_up
+            .into_iter()
+            .filter_map(|id| self.skip(id, &cause))
+            .collect();
+        if self
+            .targets_of
+            .iter()
+            .all(|targets| targets.is_subset(&self.failed_targets))
+        {
+            self.stopped = true;
+        }
+        skipped
+    }
+
+    /// Skips every pending node that waits on `failed` through required edges, transitively.
+    fn skip_blocked_by(&mut self, failed: NodeId) -> Vec<(Arc<str>, Arc<str>)> {
+        let cause = Arc::clone(&self.names[failed]);
+        let mut skipped = Vec::new();
+        let mut stack = vec![failed];
+        while let Some(id) = stack.pop() {
+            let blocked: Vec<NodeId> = self
+                .blocks(id)
+                .filter(|&n| self.states[n] == NodeState::Pending)
+                .collect();
+            for node in blocked {
+                skipped.extend(self.skip(node, &cause));
+                stack.push(node);
+            }
+        }
+        skipped
+    }
+
+    /// Whether every node has finished, failed or been skipped (or, once stopped, whether nothing
+    /// is still running).
+    pub fn is_finished(&self) -> bool {
+        self.progress.running == 0 && (self.progress.pending == 0 || self.stopped)
+    }
+
+    /// The order [`Self::run`] would execute the nodes in if every one succeeded.
+    pub fn order(&self) -> Vec<Arc<str>> {
+        let mut dry = self.clone();
+        let mut order = Vec::with_capacity(dry.states.len());
+        while let Some(name) = dry.ready().into_iter().next() {
+            if !dry.start(&name) {
+                break;
+            }
+            dry.complete(&name, true);
+            order.push(name);
+        }
+        order
+    }
+
+    /// Executes the nodes one at a time, in order. Returns every node's outcome in the order
+    /// they were decided.
+    pub fn run<T>(
+        mut self,
+        mut execute: impl FnMut(&str) -> Result<T>,
+    ) -> Vec<(Arc<str>, Outcome<T>)> {
+        let mut outcomes = Vec::with_capacity(self.states.len());
+        while let Some(name) = self.ready().into_iter().next() {
+            if !self.start(&name) {
+                break;
+            }
+            let result = execute(&name);
+            let skipped = self.complete(&name, result.is_ok());
+            outcomes.push((
+                name,
+                match result {
+                    Ok(value) => Outcome::Done(value),
+                    Err(e) => Outcome::Failed(e),
+                },
+            ));
+            outcomes.extend(
+                skipped
+                    .into_iter()
+                    .map(|(node, cause)| (node, Outcome::Skipped(cause))),
+            );
+        }
+        outcomes
+    }
+}
+
+/// Whether a dependency with `tags` is one a dependent can do without.
+fn is_soft(tags: DependencyTag) -> bool {
+    tags.intersects(DependencyTag::OPTIONAL | DependencyTag::RECOMMENDED)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scheduler(
+        direction: Direction,
+        policy: FailurePolicy,
+        nodes: &[&str],
+        edges: &[(&str, &str)],
+    ) -> Scheduler {
+        let mut scheduler = Scheduler::new(direction, policy);
+        for node in nodes {
+            scheduler.add_node(node);
+        }
+        for (dependent, dependency) in edges {
+            scheduler.add_edge(dependent, dependency);
+        }
+        scheduler
+    }
+
+    fn names(names: &[Arc<str>]) -> Vec<&str> {
+        names.iter().map(|name| &**name).collect()
+    }
+
+    /// The counters recomputed from the node states.
+    fn recount(scheduler: &Scheduler) -> (usize, usize, usize) {
+        let count = |f: fn(&NodeState) -> bool| scheduler.states.iter().filter(|s| f(s)).count();
+        (
+            count(|s| *s == NodeState::Pending),
+            count(|s| *s == NodeState::Running),
+            count(|s| matches!(s, NodeState::Skipped(_))),
+        )
+    }
+
+    fn assert_counters_match(scheduler: &Scheduler) {
+        let p = scheduler.progress();
+        assert_eq!((p.pending, p.running, p.skipped), recount(scheduler));
+        assert_eq!(
+            p.pending + p.running + p.finished + p.failed_unstarted + p.skipped,
+            scheduler.states.len()
+        );
+        assert_eq!(p.started, p.running + p.finished);
+    }
+
+    #[test]
+    fn runs_dependencies_first_and_dependents_first() {
+        let edges = [("app", "lib"), ("lib", "base"), ("tool", "base")];
+        let nodes = ["app", "base", "lib", "tool"];
+
+        let install = scheduler(
+            Direction::DependenciesFirst,
+            FailurePolicy::SkipBlocked,
+            &nodes,
+            &edges,
+        );
+        let uninstall = scheduler(
+            Direction::DependentsFirst,
+            FailurePolicy::SkipBlocked,
+            &nodes,
+            &edges,
+        );
+
+        assert_eq!(names(&install.order()), ["base", "lib", "app", "tool"]);
+        assert_eq!(names(&uninstall.order()), ["app", "lib", "tool", "base"]);
+    }
+
+    #[test]
+    fn only_forward_transitions_move_a_node() {
+        let mut s = scheduler(
+            Direction::DependenciesFirst,
+            FailurePolicy::SkipBlocked,
+            &["a", "b"],
+            &[],
+        );
+
+        assert!(
+            s.complete("a", true).is_empty(),
+            "a pending node can't succeed"
+        );
+        assert_eq!(s.progress().pending, 2);
+        assert!(s.start("a"));
+        assert!(!s.start("a"), "already running");
+        s.complete("a", true);
+        s.complete("a", false);
+        assert!(!s.start("a"), "already done");
+        s.complete("b", false);
+        assert!(!s.start("b"), "failed before it started");
+
+        assert_eq!(
+            s.progress(),
+            Progress {
+                pending: 0,
+                running: 0,
+                started: 1,
+                finished: 1,
+                failed_unstarted: 1,
+                skipped: 0,
+            }
+        );
+        assert!(s.is_finished());
+    }
+
+    #[test]
+    fn a_ready_node_skipped_by_another_failure_is_not_started() {
+        // `b` and `c` are both ready; `c` fails before it starts, and `d` needs both.
+        let mut s = scheduler(
+            Direction::DependenciesFirst,
+            FailurePolicy::SkipBlocked,
+            &["b", "c", "d"],
+            &[("d", "b"), ("d", "c")],
+        );
+        assert_eq!(names(&s.ready()), ["b", "c"]);
+
+        let skipped = s.complete("c", false);
+
+        assert_eq!(skipped, [(Arc::from("d"), Arc::from("c"))]);
+        assert!(!s.start("d"), "a stale queue entry must not run");
+        assert!(s.start("b"));
+        s.complete("b", true);
+        assert!(s.is_finished());
+        assert_counters_match(&s);
+    }
+
+    #[test]
+    fn a_cycle_is_released_alphabetically_once_nothing_else_can_run() {
+        let s = scheduler(
+            Direction::DependenciesFirst,
+            FailurePolicy::SkipBlocked,
+            &["x", "y", "z"],
+            &[("x", "y"), ("y", "x"), ("z", "x")],
+        );
+
+        assert_eq!(names(&s.order()), ["x", "y", "z"]);
+    }
+
+    #[test]
+    fn stop_all_with_targets_gives_up_only_the_failed_target() {
+        let mut s = scheduler(
+            Direction::DependenciesFirst,
+            FailurePolicy::StopAll,
+            &["app", "lib", "other", "shared"],
+            &[("app", "lib"), ("app", "shared"), ("other", "shared")],
+        );
+        s.set_targets(["app", "other"]);
+        assert!(s.start("lib"));
+
+        let skipped = s.complete("lib", false);
+
+        assert_eq!(skipped, [(Arc::from("app"), Arc::from("lib"))]);
+        assert_eq!(names(&s.ready()), ["shared"]);
+        assert!(!s.is_finished());
+        for node in ["shared", "other"] {
+            assert!(s.start(node));
+            s.complete(node, true);
+        }
+        assert!(s.is_finished());
+    }
+
+    #[test]
+    fn a_node_no_target_reaches_is_given_up_when_its_dependency_fails() {
+        let mut s = scheduler(
+            Direction::DependenciesFirst,
+            FailurePolicy::StopAll,
+            &["app", "lib", "stray", "base"],
+            &[("app", "lib"), ("stray", "base")],
+        );
+        s.set_targets(["app"]);
+        assert!(s.start("base"));
+
+        let skipped = s.complete("base", false);
+
+        assert_eq!(skipped, [(Arc::from("stray"), Arc::from("base"))]);
+        assert_eq!(names(&s.ready()), ["lib"]);
+    }
+
+    #[test]
+    fn a_failed_optional_dependency_does_not_block_under_skip_blocked() {
+        let mut s = scheduler(
+            Direction::DependenciesFirst,
+            FailurePolicy::SkipBlocked,
+            &["app", "extra"],
+            &[],
+        );
+        s.add_tagged_edge("app", "extra", DependencyTag::OPTIONAL);
+
+        s.complete("extra", false);
+
+        assert_eq!(names(&s.ready()), ["app"]);
+        assert_eq!(
+            s.missing_soft_dependencies("app"),
+            [(Arc::from("extra"), DependencyTag::OPTIONAL)]
+        );
+    }
+
+    /// xorshift64*, so the random graphs are the same on every run.
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 >> 12;
+            self.0 ^= self.0 << 25;
+            self.0 ^= self.0 >> 27;
+            self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+        }
+
+        fn below(&mut self, n: usize) -> usize {
+            (self.next() % n as u64) as usize
+        }
+
+        fn chance(&mut self, percent: u64) -> bool {
+            self.next() % 100 < percent
+        }
+    }
+
+    /// A random graph of up to 24 nodes. Edges mostly point at lower-numbered nodes, with an
+    /// occasional back edge to make cycles, and some are optional.
+    fn random_scheduler(rng: &mut Rng, direction: Direction, policy: FailurePolicy) -> Scheduler {
+        let n = 1 + rng.below(24);
+        let names: Vec<String> = (0..n).map(|i| format!("n{i:02}")).collect();
+        let mut s = Scheduler::new(direction, policy);
+        for name in &names {
+            s.add_node(name);
+        }
+        for i in 0..n {
+            for _ in 0..rng.below(4) {
+                let j = rng.below(n);
+                if j < i || rng.chance(5) {
+                    let tags = if rng.chance(20) {
+                        DependencyTag::OPTIONAL
+                    } else {
+                        DependencyTag::RUNTIME
+                    };
+                    s.add_tagged_edge(&names[i], &names[j], tags);
+                }
+            }
+        }
+        if rng.chance(50) {
+            let targets: Vec<&str> = names
+                .iter()
+                .filter(|_| rng.chance(30))
+                .map(String::as_str)
+                .collect();
+            s.set_targets(targets);
+        }
+        s
+    }
+
+    /// Drives `s` the way the install pipeline does: up to `slots` nodes run at once, results
+    /// arrive in random order, and nodes sometimes fail before they start (a failed download).
+    /// Stale entries in the queue are left for `start` to reject.
+    fn drive(rng: &mut Rng, mut s: Scheduler, slots: usize) -> Scheduler {
+        let mut queue: Vec<Arc<str>> = Vec::new();
+        let mut running: Vec<Arc<str>> = Vec::new();
+        let limit = 10 * s.states.len() + 10;
+        for _ in 0..limit {
+            if s.is_finished() {
+                assert!(running.is_empty());
+                return s;
+            }
+            let free = slots - running.len();
+            let fresh = s.ready_matching(free, |n| !queue.iter().any(|q| &**q == n));
+            queue.extend(fresh);
+            if rng.chance(15) {
+                if let Some(name) = queue.pop() {
+                    s.complete(&name, false);
+                }
+            }
+            while running.len() < slots && !queue.is_empty() {
+                let name = queue.remove(0);
+                if s.start(&name) {
+                    // Every prerequisite is settled before a node starts, unless a cycle was
+                    // released and the rest of it is still waiting.
+                    let unsettled: Vec<NodeId> = s
+                        .waits_on(s.id(&name).unwrap())
+                        .filter(|&(_, w, tags)| !s.is_satisfied(w, tags))
+                        .map(|(_, w, _)| w)
+                        .collect();
+                    let in_cycle = unsettled.iter().all(|&w| s.states[w] == NodeState::Pending);
+                    assert!(
+                        unsettled.is_empty() || in_cycle,
+                        "{name} started before {:?}",
+                        unsettled
+                            .iter()
+                            .map(|&w| (&s.names[w], &s.states[w]))
+                            .collect::<Vec<_>>()
+                    );
+                    running.push(name);
+                }
+            }
+            if !running.is_empty() {
+                let name = running.swap_remove(rng.below(running.len()));
+                s.complete(&name, !rng.chance(20));
+            }
+            assert_counters_match(&s);
+        }
+        panic!("no progress after {limit} steps: {:?}", s.progress());
+    }
+
+    #[test]
+    fn random_graphs_with_random_failures_always_settle_every_node() {
+        let mut rng = Rng(0x5eed_1234_abcd_0001);
+        for round in 0..600 {
+            let direction = if round % 2 == 0 {
+                Direction::DependenciesFirst
+            } else {
+                Direction::DependentsFirst
+            };
+            let policy = [
+                FailurePolicy::SkipBlocked,
+                FailurePolicy::StopAll,
+                FailurePolicy::Continue,
+            ][round % 3];
+            let s = random_scheduler(&mut rng, direction, policy);
+            let slots = 1 + rng.below(4);
+
+            let s = drive(&mut rng, s, slots);
+
+            let p = s.progress();
+            assert_eq!((p.pending, p.running), (0, 0), "round {round}: {p:?}");
+            assert!(
+                s.states.iter().all(|state| matches!(
+                    state,
+                    NodeState::Done | NodeState::Failed | NodeState::Skipped(_)
+                )),
+                "round {round}"
+            );
+            if policy == FailurePolicy::Continue {
+                assert_eq!(p.skipped, 0, "round {round}");
+            }
+        }
+    }
+}
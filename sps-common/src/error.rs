@@ -55,6 +55,10 @@ pub enum SpsError {
     #[error("Checksum Error: {0}")]
     ChecksumError(String),
 
+    /// A downloaded artifact's detached signature is missing or doesn't verify.
+    #[error("Signature invalid: {0}")]
+    SignatureInvalid(String),
+
     #[error("Parsing Error in {0}: {1}")]
     ParseError(&'static str, String),
 
@@ -84,6 +88,98 @@ pub enum SpsError {
 
     #[error("Codesign Error: {0}")]
     CodesignError(String),
+
+    #[error("Permission denied: {0}")]
+    PermissionDenied(String),
+
+    #[error("Failed to unpack downloaded artifact: {0}")]
+    ArtifactUnpackError(String),
+
+    #[error("Formula disabled: {0}")]
+    FormulaDisabled(String),
+
+    /// State under `<prefix>/var/sps` that can't be read or migrated.
+    #[error("State error: {0}")]
+    StateError(String),
+
+    /// The keg is poured and linked, but its post-install step failed.
+    #[error("Post-install failed for {0}: {1}")]
+    PostInstallFailed(String, String),
+
+    /// Summary of a multi-package operation; carries the exit code chosen for the whole run.
+    #[error("Operation failed: {1}")]
+    OperationFailed(i32, String),
+
+    /// The operation was stopped on request (e.g. Ctrl-C) before it finished.
+    #[error("Cancelled: {0}")]
+    Cancelled(String),
+
+    /// A blocking job panicked; the panic was caught and attributed to its package and phase.
+    #[error("{phase} task for {package} panicked: {message}")]
+    TaskPanicked {
+        package: String,
+        phase: String,
+        message: String,
+    },
+}
+
+/// Process exit codes, so scripts can tell retryable failures from ones that need a human.
+pub mod exit_code {
+    pub const GENERIC: i32 = 1;
+    pub const NOT_FOUND: i32 = 2;
+    pub const NETWORK: i32 = 3;
+    pub const CHECKSUM: i32 = 4;
+    pub const CONFLICT: i32 = 5;
+    pub const PERMISSION: i32 = 6;
+    /// Several failures of different kinds occurred, and no requested target was installed.
+    pub const MIXED_FAILURES: i32 = 10;
+    /// Some requested targets were installed, others failed.
+    pub const PARTIAL_SUCCESS: i32 = 11;
+    /// Stopped on request, as a shell reports a command ended by SIGINT.
+    pub const INTERRUPTED: i32 = 130;
+}
+
+impl SpsError {
+    /// Exit code the CLI uses when this error ends the process.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            SpsError::NotFound(_) => exit_code::NOT_FOUND,
+            SpsError::Http(_)
+            | SpsError::HttpError(_)
+            | SpsError::Api(_)
+            | SpsError::ApiRequestError(_)
+            | SpsError::DownloadError(..) => exit_code::NETWORK,
+            SpsError::ChecksumMismatch(_)
+            | SpsError::ChecksumError(_)
+            | SpsError::SignatureInvalid(_) => exit_code::CHECKSUM,
+            SpsError::DependencyError(_) => exit_code::CONFLICT,
+            SpsError::PermissionDenied(_) => exit_code::PERMISSION,
+            SpsError::Io(e) if e.kind() == std::io::ErrorKind::PermissionDenied => {
+                exit_code::PERMISSION
+            }
+            SpsError::OperationFailed(code, _) => *code,
+            SpsError::Cancelled(_) => exit_code::INTERRUPTED,
+            _ => exit_code::GENERIC,
+        }
+    }
+}
+
+/// Chooses a single exit code for a run that hit several failures: the shared code if every
+/// specific failure agrees, [`exit_code::MIXED_FAILURES`] if they disagree. Generic failures
+/// only decide the outcome when nothing more specific is known.
+pub fn combined_exit_code<'a>(errors: impl IntoIterator<Item = &'a SpsError>) -> i32 {
+    let mut chosen: Option<i32> = None;
+    for code in errors.into_iter().map(SpsError::exit_code) {
+        if code == exit_code::GENERIC {
+            continue;
+        }
+        match chosen {
+            None => chosen = Some(code),
+            Some(c) if c == code => {}
+            Some(_) => return exit_code::MIXED_FAILURES,
+        }
+    }
+    chosen.unwrap_or(exit_code::GENERIC)
 }
 
 impl From<std::io::Error> for SpsError {
@@ -117,3 +213,37 @@ impl From<object::read::Error> for SpsError {
 }
 
 pub type Result<T> = std::result::Result<T, SpsError>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn not_found() -> SpsError {
+        SpsError::NotFound("a".into())
+    }
+
+    fn network() -> SpsError {
+        SpsError::DownloadError("b".into(), "url".into(), "404".into())
+    }
+
+    #[test]
+    fn agreeing_failures_keep_their_code() {
+        let errors = [network(), network(), SpsError::Generic("c".into())];
+        assert_eq!(combined_exit_code(&errors), exit_code::NETWORK);
+    }
+
+    #[test]
+    fn failures_of_different_kinds_are_mixed() {
+        let errors = [not_found(), network()];
+        assert_eq!(combined_exit_code(&errors), exit_code::MIXED_FAILURES);
+    }
+
+    #[test]
+    fn generic_failures_decide_only_when_alone() {
+        assert_eq!(
+            combined_exit_code(&[SpsError::Generic("c".into())]),
+            exit_code::GENERIC
+        );
+        assert_eq!(combined_exit_code(&[]), exit_code::GENERIC);
+    }
+}
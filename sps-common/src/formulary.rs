@@ -1,23 +1,33 @@
 use std::collections::HashMap; // For caching parsed formulas
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 // Removed: use std::fs;
 // Removed: use std::path::PathBuf;
 // Removed: const DEFAULT_CORE_TAP: &str = "homebrew/core";
+use serde::de::DeserializeOwned;
+use serde_json::Value;
 use tracing::debug;
 
 use super::cache::Cache;
 use super::config::Config;
 use super::error::{Result, SpsError};
-use super::model::formula::Formula; // Import the Cache struct // Import Arc for thread-safe shared ownership
+use super::model::cask::Cask;
+use super::model::formula::{canonical_formula_name, split_tap_name, Formula}; /* Import the Cache struct // Import Arc for thread-safe shared ownership */
+use super::{overrides, tap_ruby};
 
 /// Responsible for finding and loading Formula definitions from the API cache.
 #[derive()]
 pub struct Formulary {
-    // config: Config, // Keep config if needed for cache path, etc.
+    config: Config,
     cache: Cache,
     // Optional: Add a cache for *parsed* formulas to avoid repeated parsing of the large JSON
     parsed_cache: std::sync::Mutex<HashMap<String, std::sync::Arc<Formula>>>, /* Using Arc for thread-safety */
+    /// Local overrides (see [`overrides`]), consulted before the API cache.
+    overrides: HashMap<String, Arc<Formula>>,
+    /// Alias or old name -> canonical name, for every formula seen so far.
+    aliases: std::sync::Mutex<HashMap<String, String>>,
 }
 
 impl Formulary {
@@ -28,20 +38,40 @@ impl Formulary {
             // Using expect here for simplicity, but Result is better.
             panic!("Failed to initialize cache in Formulary: {e}");
         });
+        let overrides: HashMap<String, Arc<Formula>> = overrides::formulae(&config)
+            .into_iter()
+            .map(|(name, formula)| (name, Arc::new(formula)))
+            .collect();
+        let mut aliases = HashMap::new();
+        for formula in overrides.values() {
+            record_aliases(&mut aliases, formula);
+        }
         Self {
-            // config,
+            config,
             cache,
             parsed_cache: std::sync::Mutex::new(HashMap::new()),
+            overrides,
+            aliases: std::sync::Mutex::new(aliases),
         }
     }
 
     // Removed: resolve_formula_path
     // Removed: parse_qualified_name
 
-    /// Loads a formula definition by name from the API cache.
+    /// Loads a formula definition by name from the API cache. Tapped formulae are found by their
+    /// qualified `user/repo/name`; `homebrew/core/name` is the same as `name`.
     pub fn load_formula(&self, name: &str) -> Result<Formula> {
+        let name = canonical_formula_name(name);
+        if let Some(formula) = self.overrides.get(name) {
+            debug!("Loaded formula '{}' from local override.", name);
+            return Ok(formula.as_ref().clone());
+        }
+        if let Some(formula) = tap_formula(&self.config, name)? {
+            debug!("Loaded formula '{}' from its local tap.", name);
+            return Ok(formula);
+        }
         // 1. Check parsed cache first
-        let mut parsed_cache_guard = self.parsed_cache.lock().unwrap();
+        let parsed_cache_guard = self.parsed_cache.lock().unwrap();
         if let Some(formula_arc) = parsed_cache_guard.get(name) {
             debug!("Loaded formula '{}' from parsed cache.", name);
             return Ok(Arc::clone(formula_arc).as_ref().clone());
@@ -49,30 +79,67 @@ impl Formulary {
         // Release lock early if not found
         drop(parsed_cache_guard);
 
-        // 2. Load the raw formula list from the main cache file
+        // 2. Load the raw formula list from the main cache file, if there is one
+        if let Some(formula) = self.load_from_index(name)? {
+            return Ok(formula);
+        }
+        // The index has been parsed now, so its aliases are known.
+        let canonical = self.canonical_name(name);
+        if canonical != name {
+            debug!("'{}' is an alias of '{}'", name, canonical);
+            return self.load_formula(&canonical);
+        }
+
+        // 3. Fall back to a definition fetched on its own (`metadata_strategy = lazy`)
+        if let Some((raw_data, _)) = self.cache.load_formula(name) {
+            let formula: Formula = serde_json::from_str(&raw_data).map_err(|e| {
+                SpsError::Cache(format!("Failed to parse cached formula '{name}': {e}"))
+            })?;
+            debug!(
+                "Loaded formula '{}' version {} from its own cache file",
+                formula.name,
+                formula.version_str_full()
+            );
+            self.insert_formula(formula.clone());
+            return Ok(formula);
+        }
+
+        debug!("Formula '{}' not found in the cached formula data.", name);
+        Err(SpsError::Generic(format!(
+            "Formula '{name}' not found in cache."
+        )))
+    }
+
+    /// Parses `formula.json` into the parsed cache and returns `name` from it. `Ok(None)` when
+    /// there is no index or it doesn't list `name`.
+    fn load_from_index(&self, name: &str) -> Result<Option<Formula>> {
         debug!("Loading raw formula data from cache file 'formula.json'...");
-        let raw_data = self.cache.load_raw("formula.json")?; // Assumes update stored it here
+        let raw_data = match self.cache.load_raw("formula.json") {
+            Ok(raw_data) => raw_data,
+            Err(e) => {
+                debug!("No formula index to load from: {}", e);
+                return Ok(None);
+            }
+        };
 
-        // 3. Parse the entire JSON array
         // This could be expensive, hence the parsed_cache above.
         debug!("Parsing full formula list");
         let all_formulas: Vec<Formula> = serde_json::from_str(&raw_data)
             .map_err(|e| SpsError::Cache(format!("Failed to parse cached formula data: {e}")))?;
         debug!("Parsed {} formulas.", all_formulas.len());
 
-        // 4. Find the requested formula and populate the parsed cache
+        // Find the requested formula and populate the parsed cache
         let mut found_formula: Option<Formula> = None;
-        // Lock again to update the parsed cache
-        parsed_cache_guard = self.parsed_cache.lock().unwrap();
-        // Use entry API to avoid redundant lookups if another thread populated it
+        let mut parsed_cache_guard = self.parsed_cache.lock().unwrap();
+        let mut aliases = self.aliases.lock().unwrap();
         for formula in all_formulas {
+            record_aliases(&mut aliases, &formula);
             let formula_name = formula.name.clone(); // Clone name for insertion
             let formula_arc = std::sync::Arc::new(formula); // Create Arc once
 
             // If this is the formula we're looking for, store it for return value
             if formula_name == name {
                 found_formula = Some(Arc::clone(&formula_arc).as_ref().clone());
-                // Clone Formula out
             }
 
             // Insert into parsed cache using entry API
@@ -80,26 +147,133 @@ impl Formulary {
                 .entry(formula_name)
                 .or_insert(formula_arc);
         }
+        if let Some(f) = &found_formula {
+            debug!(
+                "Successfully loaded formula '{}' version {}",
+                f.name,
+                f.version_str_full()
+            );
+        }
+        Ok(found_formula)
+    }
 
-        // 5. Return the found formula or an error
-        match found_formula {
-            Some(f) => {
-                debug!(
-                    "Successfully loaded formula '{}' version {}",
-                    f.name,
-                    f.version_str_full()
-                );
-                Ok(f)
-            }
-            None => {
-                debug!(
-                    "Formula '{}' not found within the cached formula data.",
-                    name
-                );
-                Err(SpsError::Generic(format!(
-                    "Formula '{name}' not found in cache."
-                )))
+    /// Returns whether `name` can be served without a network round trip, parsing the cached
+    /// formula list once if nothing has been loaded yet. Formulae fetched on their own count too.
+    pub fn has_formula(&self, name: &str) -> bool {
+        let name = canonical_formula_name(name);
+        if self.overrides.contains_key(name) {
+            return true;
+        }
+        let is_empty = {
+            let guard = self.parsed_cache.lock().unwrap();
+            if guard.contains_key(name) {
+                return true;
             }
+            guard.is_empty()
+        };
+        (is_empty || self.cache.load_formula(name).is_some()) && self.load_formula(name).is_ok()
+    }
+
+    /// Adds a definition fetched elsewhere (e.g. directly from the API) to the parsed cache.
+    /// Existing entries win, so this never replaces what `formula.json` provides.
+    pub fn insert_formula(&self, formula: Formula) {
+        record_aliases(&mut self.aliases.lock().unwrap(), &formula);
+        let mut guard = self.parsed_cache.lock().unwrap();
+        guard
+            .entry(formula.name.clone())
+            .or_insert_with(|| Arc::new(formula));
+    }
+
+    /// The canonical name of `name`, which may be an alias or old name of a formula loaded so
+    /// far. Exact names win over aliases; other names come back as given, minus a
+    /// `homebrew/core/` prefix.
+    pub fn canonical_name(&self, name: &str) -> String {
+        let name = canonical_formula_name(name);
+        if self.overrides.contains_key(name) || self.parsed_cache.lock().unwrap().contains_key(name)
+        {
+            return name.to_string();
         }
+        self.aliases
+            .lock()
+            .unwrap()
+            .get(name)
+            .cloned()
+            .unwrap_or_else(|| name.to_string())
     }
 }
+
+/// Maps the aliases of `formula` to its name; the first formula to claim an alias keeps it.
+fn record_aliases(aliases: &mut HashMap<String, String>, formula: &Formula) {
+    for alias in formula.alias_names() {
+        aliases.entry(alias).or_insert_with(|| formula.name.clone());
+    }
+}
+
+/// Where the JSON definition of `user/repo/name` lives in a local tap checkout:
+/// `<taps_dir>/user/homebrew-repo/<subdir>/name.json`. `None` for core names.
+fn tap_definition_path(config: &Config, name: &str, subdir: &str) -> Option<PathBuf> {
+    let (Some(tap), short) = split_tap_name(name) else {
+        return None;
+    };
+    Some(
+        config
+            .get_tap_path(tap)?
+            .join(subdir)
+            .join(format!("{short}.json")),
+    )
+}
+
+/// Path of the formula `user/repo/name` in its local tap (`Formula/name.json`).
+pub fn tap_formula_path(config: &Config, name: &str) -> Option<PathBuf> {
+    tap_definition_path(config, name, "Formula")
+}
+
+/// Path of the cask `user/repo/token` in its local tap (`Casks/token.json`).
+pub fn tap_cask_path(config: &Config, name: &str) -> Option<PathBuf> {
+    tap_definition_path(config, name, "Casks")
+}
+
+/// The formula `user/repo/name` from a local tap checkout, if the tap defines it: in JSON, or
+/// else in Ruby, extracted to JSON by [`tap_ruby`]. A definition in a tap belongs to that tap
+/// even without a `tap` key.
+pub fn tap_formula(config: &Config, name: &str) -> Result<Option<Formula>> {
+    let Some(json_path) = tap_formula_path(config, name) else {
+        return Ok(None);
+    };
+    let rb_path = json_path.with_extension("rb");
+    let (path, mut value) = if json_path.is_file() {
+        let value = read_definition(&json_path)?;
+        (json_path, value)
+    } else if rb_path.is_file() {
+        let value = tap_ruby::extract_formula_json(config, &rb_path)?;
+        (rb_path, value)
+    } else {
+        return Ok(None);
+    };
+    if let (Some(object), (Some(tap), _)) = (value.as_object_mut(), split_tap_name(name)) {
+        object
+            .entry("tap")
+            .or_insert_with(|| Value::String(tap.to_string()));
+    }
+    parse_definition(&path, value).map(Some)
+}
+
+/// The cask `user/repo/token` from a local tap checkout, if the tap has a JSON definition of it.
+pub fn tap_cask(config: &Config, name: &str) -> Result<Option<Cask>> {
+    let Some(path) = tap_cask_path(config, name).filter(|p| p.is_file()) else {
+        return Ok(None);
+    };
+    parse_definition(&path, read_definition(&path)?).map(Some)
+}
+
+fn read_definition(path: &Path) -> Result<Value> {
+    serde_json::from_str(&fs::read_to_string(path)?).map_err(|e| invalid_definition(path, e))
+}
+
+fn parse_definition<T: DeserializeOwned>(path: &Path, value: Value) -> Result<T> {
+    serde_json::from_value(value).map_err(|e| invalid_definition(path, e))
+}
+
+fn invalid_definition(path: &Path, e: serde_json::Error) -> SpsError {
+    SpsError::ValidationError(format!("{} is not a valid definition: {e}", path.display()))
+}
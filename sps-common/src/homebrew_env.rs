@@ -0,0 +1,311 @@
+// sps-common/src/homebrew_env.rs
+//! `HOMEBREW_*` environment variables read in place of sps's own, so scripts written for Homebrew
+//! keep working. Each one in [`MAPPINGS`] stands in for an `sps_*` variable and is only read when
+//! that one is unset; like the `sps_*` variables it wins over the config file, and command-line
+//! flags win over both. Which ones were used is logged at debug level.
+//!
+//! Variables in [`UNSUPPORTED`] change what Homebrew does in ways sps doesn't follow. Setting one
+//! gets a single warning per process instead of being ignored silently.
+
+use std::env;
+use std::sync::Once;
+
+use tracing::{debug, warn};
+
+/// A `HOMEBREW_*` variable, the `sps_*` variable it stands in for, and how its value becomes a
+/// value of that variable (`None` when it carries nothing sps uses).
+pub struct Mapping {
+    pub homebrew: &'static str,
+    pub sps: &'static str,
+    pub translate: fn(&str) -> Option<String>,
+}
+
+pub const MAPPINGS: &[Mapping] = &[
+    Mapping {
+        homebrew: "HOMEBREW_PREFIX",
+        sps: "sps_PREFIX",
+        translate: same_value,
+    },
+    Mapping {
+        homebrew: "HOMEBREW_CACHE",
+        sps: "sps_DOWNLOAD_DIR",
+        translate: same_value,
+    },
+    Mapping {
+        homebrew: "HOMEBREW_TEMP",
+        sps: "sps_TEMP",
+        translate: same_value,
+    },
+    Mapping {
+        homebrew: "HOMEBREW_BOTTLE_DOMAIN",
+        sps: "sps_BOTTLE_DOMAIN",
+        translate: same_value,
+    },
+    Mapping {
+        homebrew: "HOMEBREW_NO_AUTO_UPDATE",
+        sps: "sps_NO_AUTO_UPDATE",
+        translate: flag,
+    },
+    Mapping {
+        homebrew: "HOMEBREW_AUTO_UPDATE_SECS",
+        sps: "sps_AUTO_UPDATE_SECS",
+        translate: same_value,
+    },
+    Mapping {
+        homebrew: "HOMEBREW_CASK_OPTS",
+        sps: "sps_LANGUAGE",
+        translate: cask_opts_language,
+    },
+];
+
+/// Recognized `HOMEBREW_*` variables sps does not honor.
+pub const UNSUPPORTED: &[&str] = &[
+    "HOMEBREW_CLEANUP_MAX_AGE_DAYS",
+    "HOMEBREW_CURL_RETRIES",
+    "HOMEBREW_FORCE_BREWED_CURL",
+    "HOMEBREW_MAKE_JOBS",
+    "HOMEBREW_NO_BOTTLE_SOURCE_FALLBACK",
+    "HOMEBREW_NO_INSTALL_CLEANUP",
+    "HOMEBREW_NO_INSTALL_FROM_API",
+    "HOMEBREW_NO_INSTALL_UPGRADE",
+    "HOMEBREW_NO_INSTALLED_DEPENDENTS_CHECK",
+];
+
+/// `HOMEBREW_CASK_OPTS` options sps has no equivalent for.
+const UNSUPPORTED_CASK_OPTS: &[&str] =
+    &["--appdir", "--fontdir", "--no-quarantine", "--require-sha"];
+
+static WARN_ONCE: Once = Once::new();
+
+/// The value of the `sps_*` variable `name`: its own value if set, else the one translated from
+/// the `HOMEBREW_*` variable mapped onto it.
+pub fn var(name: &str) -> Option<String> {
+    lookup(name, |key| env::var(key).ok())
+}
+
+/// [`var`] over the variables `get` returns.
+fn lookup(name: &str, get: impl Fn(&str) -> Option<String>) -> Option<String> {
+    if let Some(value) = get(name) {
+        return Some(value);
+    }
+    let mapping = MAPPINGS.iter().find(|m| m.sps == name)?;
+    let value = get(mapping.homebrew)?;
+    let translated = (mapping.translate)(&value)?;
+    debug!("Honoring {} as {}", mapping.homebrew, mapping.sps);
+    Some(translated)
+}
+
+/// Warns, once per process, about each recognized `HOMEBREW_*` variable that is set but not
+/// honored, and about `HOMEBREW_CASK_OPTS` options that are not.
+pub fn warn_unsupported() {
+    WARN_ONCE.call_once(|| {
+        for name in UNSUPPORTED {
+            if env::var_os(name).is_some() {
+                warn!(
+                    "{} is set, but sps does not support it; it has no effect",
+                    name
+                );
+            }
+        }
+        if let Ok(opts) = env::var("HOMEBREW_CASK_OPTS") {
+            for opt in opts.split_whitespace() {
+                let key = opt.split('=').next().unwrap_or(opt);
+                if UNSUPPORTED_CASK_OPTS.contains(&key) {
+                    warn!(
+                        "HOMEBREW_CASK_OPTS option {} is not supported by sps; it has no effect",
+                        key
+                    );
+                }
+            }
+        }
+    });
+}
+
+fn same_value(value: &str) -> Option<String> {
+    Some(value.to_string())
+}
+
+/// Homebrew treats any non-empty value as set.
+fn flag(value: &str) -> Option<String> {
+    (!value.is_empty()).then(|| "1".to_string())
+}
+
+/// The `--language=de,en` option of `HOMEBREW_CASK_OPTS`, as a language list.
+fn cask_opts_language(opts: &str) -> Option<String> {
+    let mut words = opts.split_whitespace();
+    while let Some(word) = words.next() {
+        if let Some(languages) = word.strip_prefix("--language=") {
+            return Some(languages.to_string());
+        }
+        if word == "--language" {
+            return words.next().map(str::to_string);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::config::Config;
+    use crate::test_env::ScopedEnv;
+
+    /// `name` looked up among `vars` only.
+    fn lookup_in(vars: &[(&str, &str)], name: &str) -> Option<String> {
+        let vars: HashMap<&str, &str> = vars.iter().copied().collect();
+        lookup(name, |key| vars.get(key).map(|v| v.to_string()))
+    }
+
+    #[test]
+    fn each_homebrew_variable_stands_in_for_its_sps_variable() {
+        let cases = [
+            (
+                "HOMEBREW_PREFIX",
+                "/opt/brew",
+                "sps_PREFIX",
+                Some("/opt/brew"),
+            ),
+            (
+                "HOMEBREW_CACHE",
+                "/var/cache/brew",
+                "sps_DOWNLOAD_DIR",
+                Some("/var/cache/brew"),
+            ),
+            ("HOMEBREW_TEMP", "/scratch", "sps_TEMP", Some("/scratch")),
+            (
+                "HOMEBREW_BOTTLE_DOMAIN",
+                "https://mirror.example.com/bottles",
+                "sps_BOTTLE_DOMAIN",
+                Some("https://mirror.example.com/bottles"),
+            ),
+            (
+                "HOMEBREW_NO_AUTO_UPDATE",
+                "true",
+                "sps_NO_AUTO_UPDATE",
+                Some("1"),
+            ),
+            ("HOMEBREW_NO_AUTO_UPDATE", "", "sps_NO_AUTO_UPDATE", None),
+            (
+                "HOMEBREW_AUTO_UPDATE_SECS",
+                "600",
+                "sps_AUTO_UPDATE_SECS",
+                Some("600"),
+            ),
+            (
+                "HOMEBREW_CASK_OPTS",
+                "--language=de,en",
+                "sps_LANGUAGE",
+                Some("de,en"),
+            ),
+            (
+                "HOMEBREW_CASK_OPTS",
+                "--no-quarantine --language fr",
+                "sps_LANGUAGE",
+                Some("fr"),
+            ),
+            (
+                "HOMEBREW_CASK_OPTS",
+                "--appdir=/Applications",
+                "sps_LANGUAGE",
+                None,
+            ),
+        ];
+
+        for (homebrew, value, sps, expected) in cases {
+            assert_eq!(
+                lookup_in(&[(homebrew, value)], sps).as_deref(),
+                expected,
+                "{homebrew}={value:?}"
+            );
+        }
+        // The cases cover the whole table, and nothing in it is also listed as unsupported.
+        for mapping in MAPPINGS {
+            assert!(
+                cases
+                    .iter()
+                    .any(|case| (case.0, case.2) == (mapping.homebrew, mapping.sps)),
+                "{}",
+                mapping.homebrew
+            );
+            assert!(
+                !UNSUPPORTED.contains(&mapping.homebrew),
+                "{}",
+                mapping.homebrew
+            );
+        }
+    }
+
+    #[test]
+    fn an_sps_variable_wins_over_its_homebrew_counterpart() {
+        let both = [
+            ("sps_BOTTLE_DOMAIN", "https://sps.example.com"),
+            ("HOMEBREW_BOTTLE_DOMAIN", "https://brew.example.com"),
+        ];
+        assert_eq!(
+            lookup_in(&both, "sps_BOTTLE_DOMAIN").as_deref(),
+            Some("https://sps.example.com")
+        );
+        // Even an empty sps_* variable counts as set.
+        let empty = [
+            ("sps_AUTO_UPDATE_SECS", ""),
+            ("HOMEBREW_AUTO_UPDATE_SECS", "600"),
+        ];
+        assert_eq!(
+            lookup_in(&empty, "sps_AUTO_UPDATE_SECS").as_deref(),
+            Some("")
+        );
+        // Only variables in the table are translated.
+        let unmapped = [("HOMEBREW_MAKE_JOBS", "8")];
+        assert_eq!(lookup_in(&unmapped, "sps_MAKE_JOBS"), None);
+    }
+
+    #[test]
+    fn config_reads_homebrew_variables_over_its_file_and_under_sps_variables() {
+        // Kept on disk: other tests load `Config` concurrently and may still see `sps_CONFIG`.
+        let dir = tempfile::tempdir().unwrap().keep();
+        let file = dir.join("config.toml");
+        std::fs::write(
+            &file,
+            "bottle_domain = \"https://file.example.com\"\nlanguage = \"it\"\n",
+        )
+        .unwrap();
+        let mut env = ScopedEnv::new();
+        env.set("sps_CONFIG", &file)
+            .remove("sps_BOTTLE_DOMAIN")
+            .remove("sps_LANGUAGE")
+            .remove("HOMEBREW_BOTTLE_DOMAIN")
+            .remove("HOMEBREW_CASK_OPTS");
+        let settings = || {
+            let config = Config::load().unwrap();
+            (config.bottle_domain.unwrap(), config.cask_languages)
+        };
+
+        let from_file = settings();
+        env.set("HOMEBREW_BOTTLE_DOMAIN", "https://brew.example.com/")
+            .set("HOMEBREW_CASK_OPTS", "--language=de");
+        let from_homebrew = settings();
+        env.set("sps_BOTTLE_DOMAIN", "https://sps.example.com")
+            .set("sps_LANGUAGE", "fr");
+        let from_sps = settings();
+
+        assert_eq!(
+            [from_file, from_homebrew, from_sps],
+            [
+                (
+                    "https://file.example.com".to_string(),
+                    vec!["it".to_string()]
+                ),
+                (
+                    "https://brew.example.com".to_string(),
+                    vec!["de".to_string()]
+                ),
+                (
+                    "https://sps.example.com".to_string(),
+                    vec!["fr".to_string()]
+                ),
+            ]
+        );
+    }
+}
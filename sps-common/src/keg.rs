@@ -1,162 +1,194 @@
+use std::collections::{BTreeSet, HashMap};
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
 
-use semver::Version;
+use serde_json::Value;
+use tracing::debug;
 
 use super::config::Config;
 use super::error::Result;
+use super::model::formula::{canonical_formula_name, formula_name_from_keg_dir, keg_dir_name};
+use super::model::PkgVersion;
+
+const RECEIPT_FILE: &str = "INSTALL_RECEIPT.json";
+/// Left in a keg from when it is poured until it is linked. A keg holding it counts as unlinked,
+/// so a run that failed in between is resumed by linking the keg rather than pouring it again.
+pub const UNLINKED_MARKER: &str = ".sps_unlinked";
+/// Between the version and the Unix timestamp in the name of a keg directory moved aside by
+/// [`quarantine`] (`1.7.1.broken-1760000000`). Such directories are not kegs.
+pub const QUARANTINE_INFIX: &str = ".broken-";
+
+/// Kegs this process quarantined, for the summary at the end of a run.
+static QUARANTINED: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
 
 /// Represents information about an installed package (Keg).
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct InstalledKeg {
     pub name: String,
-    pub version: Version,
+    /// Parsed from the keg directory name, revision included.
+    pub version: PkgVersion,
     pub path: PathBuf,
-    pub revision: u32,
 }
 
 /// Manages querying installed packages in the Cellar.
 #[derive(Debug)]
 pub struct KegRegistry {
     config: Config,
+    snapshot: Option<Arc<KegSnapshot>>,
 }
 
 impl KegRegistry {
     pub fn new(config: Config) -> Self {
-        Self { config }
+        Self {
+            config,
+            snapshot: None,
+        }
+    }
+
+    /// A registry that answers keg queries from `snapshot` instead of scanning the Cellar.
+    pub fn with_snapshot(config: Config, snapshot: Arc<KegSnapshot>) -> Self {
+        Self {
+            config,
+            snapshot: Some(snapshot),
+        }
+    }
+
+    /// A registry over the Cellar of another installation at `prefix`, e.g. a Homebrew
+    /// installation whose kegs are being adopted.
+    pub fn for_prefix(config: &Config, prefix: &Path) -> Self {
+        let mut config = config.clone();
+        config.prefix = prefix.to_path_buf();
+        config.cellar = prefix.join("Cellar");
+        Self::new(config)
     }
 
     /// Gets the path to the directory containing all versions for a formula.
     fn formula_cellar_path(&self, name: &str) -> PathBuf {
-        self.config.cellar.join(name)
+        self.config.cellar.join(keg_dir_name(name))
     }
 
     /// Calculates the conventional 'opt' path for a formula (e.g., /opt/homebrew/opt/foo).
     /// This path typically points to the currently linked/active version.
     pub fn get_opt_path(&self, name: &str) -> PathBuf {
-        self.config.prefix.join("opt").join(name)
+        self.config.prefix.join("opt").join(keg_dir_name(name))
+    }
+
+    /// Whether the formula's opt link currently points into `keg_path`. A keg can be present in
+    /// the Cellar while unlinked, e.g. after a manual unlink or an interrupted upgrade.
+    pub fn is_keg_linked(&self, name: &str, keg_path: &Path) -> bool {
+        if is_marked_unlinked(keg_path) {
+            return false;
+        }
+        let Ok(opt_target) = fs::canonicalize(self.get_opt_path(name)) else {
+            return false;
+        };
+        fs::canonicalize(keg_path).is_ok_and(|keg| opt_target.starts_with(keg))
     }
 
     /// Checks if a formula is installed and returns its Keg info if it is.
-    /// If multiple versions are installed, returns the latest version (considering revisions).
+    /// If multiple versions are installed (upgrades keep the previous keg), returns the current
+    /// one: the keg the opt link points into, else the latest version (considering revisions).
+    /// `name` may be tap-qualified (`user/repo/name`); `homebrew/core/name` means `name`.
     pub fn get_installed_keg(&self, name: &str) -> Result<Option<InstalledKeg>> {
-        let formula_dir = self.formula_cellar_path(name);
-
-        if !formula_dir.is_dir() {
-            return Ok(None);
+        let name = canonical_formula_name(name);
+        if let Some(snapshot) = &self.snapshot {
+            return Ok(snapshot.current(name));
         }
+        Ok(self.current_keg(self.scan_formula_kegs(name)?))
+    }
 
-        let mut latest_keg: Option<InstalledKeg> = None;
+    /// Every installed keg of `name`, all versions.
+    pub fn list_formula_kegs(&self, name: &str) -> Result<Vec<InstalledKeg>> {
+        let name = canonical_formula_name(name);
+        if let Some(snapshot) = &self.snapshot {
+            return Ok(snapshot.all_of(name));
+        }
+        self.scan_formula_kegs(name)
+    }
 
-        for entry_result in fs::read_dir(&formula_dir)? {
-            let entry = entry_result?;
-            let path = entry.path();
-
-            if path.is_dir() {
-                if let Some(version_str_full) = path.file_name().and_then(|n| n.to_str()) {
-                    let mut parts = version_str_full.splitn(2, '_');
-                    let version_part = parts.next().unwrap_or(version_str_full);
-                    let revision = parts
-                        .next()
-                        .and_then(|s| s.parse::<u32>().ok())
-                        .unwrap_or(0);
-
-                    let version_str_padded = if version_part.split('.').count() < 3 {
-                        let v_parts: Vec<&str> = version_part.split('.').collect();
-                        match v_parts.len() {
-                            1 => format!("{}.0.0", v_parts[0]),
-                            2 => format!("{}.{}.0", v_parts[0], v_parts[1]),
-                            _ => version_part.to_string(),
-                        }
-                    } else {
-                        version_part.to_string()
-                    };
-
-                    if let Ok(version) = Version::parse(&version_str_padded) {
-                        let current_keg = InstalledKeg {
-                            name: name.to_string(),
-                            version: version.clone(),
-                            revision,
-                            path: path.clone(),
-                        };
-
-                        match latest_keg {
-                            Some(ref latest) => {
-                                if version > latest.version
-                                    || (version == latest.version && revision > latest.revision)
-                                {
-                                    latest_keg = Some(current_keg);
-                                }
-                            }
-                            None => {
-                                latest_keg = Some(current_keg);
-                            }
-                        }
-                    }
-                }
-            }
+    /// The current keg of every installed formula (see [`KegRegistry::get_installed_keg`]);
+    /// kegs kept from earlier versions are left out.
+    pub fn list_current_kegs(&self) -> Result<Vec<InstalledKeg>> {
+        let mut by_name: HashMap<String, Vec<InstalledKeg>> = HashMap::new();
+        for keg in self.list_installed_kegs()? {
+            by_name.entry(keg.name.clone()).or_default().push(keg);
         }
+        Ok(by_name
+            .into_values()
+            .filter_map(|kegs| self.current_keg(kegs))
+            .collect())
+    }
 
-        Ok(latest_keg)
+    /// Picks the linked keg among `kegs` (all of one formula), else the latest.
+    fn current_keg(&self, kegs: Vec<InstalledKeg>) -> Option<InstalledKeg> {
+        if let Some(linked) = kegs
+            .iter()
+            .find(|keg| self.is_keg_linked(&keg.name, &keg.path))
+        {
+            return Some(linked.clone());
+        }
+        latest_keg(kegs)
     }
 
     /// Lists all installed kegs.
     /// Reads the cellar directory and parses all valid keg structures found.
     pub fn list_installed_kegs(&self) -> Result<Vec<InstalledKeg>> {
+        if let Some(snapshot) = &self.snapshot {
+            return Ok(snapshot.all());
+        }
         let mut installed_kegs = Vec::new();
-        let cellar_dir = self.cellar_path();
+        for formula_name in self.scan_formula_names()? {
+            installed_kegs.extend(self.scan_formula_kegs(&formula_name)?);
+        }
+        Ok(installed_kegs)
+    }
 
+    /// Names of all formula directories in the Cellar, tap-qualified for tapped formulae.
+    fn scan_formula_names(&self) -> Result<Vec<String>> {
+        let cellar_dir = self.cellar_path();
         if !cellar_dir.is_dir() {
-            return Ok(installed_kegs);
+            return Ok(Vec::new());
         }
-
+        let mut names = Vec::new();
         for formula_entry in fs::read_dir(cellar_dir)? {
-            let formula_entry = formula_entry?;
-            let formula_path = formula_entry.path();
-
+            let formula_path = formula_entry?.path();
             if formula_path.is_dir() {
                 if let Some(formula_name) = formula_path.file_name().and_then(|n| n.to_str()) {
-                    for version_entry in fs::read_dir(&formula_path)? {
-                        let version_entry = version_entry?;
-                        let version_path = version_entry.path();
-
-                        if version_path.is_dir() {
-                            if let Some(version_str_full) =
-                                version_path.file_name().and_then(|n| n.to_str())
-                            {
-                                let mut parts = version_str_full.splitn(2, '_');
-                                let version_part = parts.next().unwrap_or(version_str_full);
-                                let revision = parts
-                                    .next()
-                                    .and_then(|s| s.parse::<u32>().ok())
-                                    .unwrap_or(0);
-                                let version_str_padded = if version_part.split('.').count() < 3 {
-                                    let v_parts: Vec<&str> = version_part.split('.').collect();
-                                    match v_parts.len() {
-                                        1 => format!("{}.0.0", v_parts[0]),
-                                        2 => format!("{}.{}.0", v_parts[0], v_parts[1]),
-                                        _ => version_part.to_string(),
-                                    }
-                                } else {
-                                    version_part.to_string()
-                                };
-
-                                if let Ok(version) = Version::parse(&version_str_padded) {
-                                    installed_kegs.push(InstalledKeg {
-                                        name: formula_name.to_string(),
-                                        version,
-                                        revision,
-                                        path: version_path.clone(),
-                                    });
-                                }
-                            }
-                        }
-                    }
+                    names.push(formula_name_from_keg_dir(formula_name));
                 }
             }
         }
+        Ok(names)
+    }
 
-        Ok(installed_kegs)
+    /// Reads every version directory of one formula from disk.
+    fn scan_formula_kegs(&self, name: &str) -> Result<Vec<InstalledKeg>> {
+        let formula_dir = self.formula_cellar_path(name);
+        if !formula_dir.is_dir() {
+            return Ok(Vec::new());
+        }
+
+        let mut kegs = Vec::new();
+        for entry_result in fs::read_dir(&formula_dir)? {
+            let path = entry_result?.path();
+            if !path.is_dir() {
+                continue;
+            }
+            let Some(dir_name) = path.file_name().and_then(|n| n.to_str()) else {
+                continue;
+            };
+            if dir_name.starts_with('.') || is_quarantined(dir_name) {
+                continue;
+            }
+            kegs.push(InstalledKeg {
+                name: name.to_string(),
+                version: PkgVersion::parse(dir_name),
+                path: path.clone(),
+            });
+        }
+        Ok(kegs)
     }
 
     /// Returns the root path of the Cellar.
@@ -164,14 +196,531 @@ impl KegRegistry {
         &self.config.cellar
     }
 
-    /// Returns the path for a specific versioned keg (whether installed or not).
-    /// Includes revision in the path name if revision > 0.
-    pub fn get_keg_path(&self, name: &str, version: &Version, revision: u32) -> PathBuf {
-        let version_string = if revision > 0 {
-            format!("{version}_{revision}")
+    /// Returns the path for a specific versioned keg (whether installed or not), named after the
+    /// version including any revision.
+    pub fn get_keg_path(&self, name: &str, version: &PkgVersion) -> PathBuf {
+        self.formula_cellar_path(name).join(version.to_string())
+    }
+}
+
+/// Marks the freshly poured keg at `keg_path` as not linked yet (see [`UNLINKED_MARKER`]).
+pub fn mark_unlinked(keg_path: &Path) -> Result<()> {
+    fs::write(keg_path.join(UNLINKED_MARKER), "")?;
+    Ok(())
+}
+
+/// Removes the marker once the keg at `keg_path` is linked.
+pub fn clear_unlinked(keg_path: &Path) {
+    match fs::remove_file(keg_path.join(UNLINKED_MARKER)) {
+        Ok(()) => debug!("{} is linked now", keg_path.display()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => debug!(
+            "Could not clear the unlinked marker in {}: {}",
+            keg_path.display(),
+            e
+        ),
+    }
+}
+
+/// Whether the keg at `keg_path` was poured but never linked.
+pub fn is_marked_unlinked(keg_path: &Path) -> bool {
+    keg_path.join(UNLINKED_MARKER).exists()
+}
+
+/// Whether the keg at `keg_path` has a receipt that parses, as every completed pour writes last.
+pub fn has_valid_receipt(keg_path: &Path) -> bool {
+    fs::read_to_string(keg_path.join(RECEIPT_FILE))
+        .ok()
+        .and_then(|text| serde_json::from_str::<serde_json::Map<String, Value>>(&text).ok())
+        .is_some()
+}
+
+/// Whether a directory name in `Cellar/<formula>` is a quarantined keg.
+pub fn is_quarantined(dir_name: &str) -> bool {
+    dir_name
+        .split_once(QUARANTINE_INFIX)
+        .is_some_and(|(_, stamp)| !stamp.is_empty())
+}
+
+/// Moves the keg directory at `keg_path` aside to `<version>.broken-<timestamp>`, so a fresh pour
+/// doesn't mix its files with whatever an interrupted one left, and the old files stay there to
+/// inspect. Returns where it went.
+pub fn quarantine(keg_path: &Path) -> Result<PathBuf> {
+    let name = keg_path
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let stamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let mut target = keg_path.with_file_name(format!("{name}{QUARANTINE_INFIX}{stamp}"));
+    let mut attempt = 1;
+    while target.symlink_metadata().is_ok() {
+        target = keg_path.with_file_name(format!("{name}{QUARANTINE_INFIX}{stamp}-{attempt}"));
+        attempt += 1;
+    }
+    fs::rename(keg_path, &target)?;
+    QUARANTINED
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .push(target.clone());
+    Ok(target)
+}
+
+/// The kegs quarantined since the last call.
+pub fn take_quarantined() -> Vec<PathBuf> {
+    std::mem::take(&mut *QUARANTINED.lock().unwrap_or_else(|e| e.into_inner()))
+}
+
+/// Every quarantined keg in the Cellar, as formula name and path.
+pub fn quarantined_kegs(config: &Config) -> Result<Vec<(String, PathBuf)>> {
+    let mut found = Vec::new();
+    if !config.cellar.is_dir() {
+        return Ok(found);
+    }
+    for formula_entry in fs::read_dir(&config.cellar)? {
+        let formula_dir = formula_entry?.path();
+        if !formula_dir.is_dir() {
+            continue;
+        }
+        let Ok(entries) = fs::read_dir(&formula_dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            if entry.file_name().to_str().is_some_and(is_quarantined) {
+                let name = formula_dir
+                    .file_name()
+                    .map(|n| formula_name_from_keg_dir(&n.to_string_lossy()))
+                    .unwrap_or_default();
+                found.push((name, entry.path()));
+            }
+        }
+    }
+    found.sort();
+    Ok(found)
+}
+
+/// Picks the highest version (revision included) among `kegs`. `HEAD-<commit>` kegs (built with
+/// --HEAD) only win when nothing else is installed, as a release keg is what the formula tracks.
+fn latest_keg(kegs: Vec<InstalledKeg>) -> Option<InstalledKeg> {
+    kegs.into_iter()
+        .max_by(|a, b| (!a.version.is_head(), &a.version).cmp(&(!b.version.is_head(), &b.version)))
+}
+
+/// In-memory index of the Cellar (name -> installed versions), read once at the start of an
+/// operation and shared via `Arc` so repeated lookups don't re-scan directories. Tasks that add
+/// or remove a keg call [`KegSnapshot::invalidate`] for that formula.
+#[derive(Debug)]
+pub struct KegSnapshot {
+    registry: KegRegistry,
+    kegs: RwLock<HashMap<String, Vec<InstalledKeg>>>,
+}
+
+impl KegSnapshot {
+    pub fn load(config: &Config) -> Result<Arc<Self>> {
+        let registry = KegRegistry::new(config.clone());
+        let mut kegs: HashMap<String, Vec<InstalledKeg>> = HashMap::new();
+        for keg in registry.list_installed_kegs()? {
+            kegs.entry(keg.name.clone()).or_default().push(keg);
+        }
+        debug!("Loaded keg snapshot with {} formulae", kegs.len());
+        Ok(Arc::new(Self {
+            registry,
+            kegs: RwLock::new(kegs),
+        }))
+    }
+
+    /// Current keg of `name`, if any (see [`KegRegistry::get_installed_keg`]).
+    pub fn current(&self, name: &str) -> Option<InstalledKeg> {
+        let kegs = self.kegs.read().unwrap().get(name)?.clone();
+        self.registry.current_keg(kegs)
+    }
+
+    /// Every installed keg of `name`, all versions.
+    pub fn all_of(&self, name: &str) -> Vec<InstalledKeg> {
+        let kegs = self.kegs.read().unwrap();
+        kegs.get(name).cloned().unwrap_or_default()
+    }
+
+    /// Every installed keg, all versions.
+    pub fn all(&self) -> Vec<InstalledKeg> {
+        let kegs = self.kegs.read().unwrap();
+        kegs.values().flatten().cloned().collect()
+    }
+
+    /// Re-reads `name` from disk, e.g. after a task poured or removed one of its kegs.
+    pub fn invalidate(&self, name: &str) -> Result<()> {
+        let fresh = self.registry.scan_formula_kegs(name)?;
+        let mut kegs = self.kegs.write().unwrap();
+        if fresh.is_empty() {
+            kegs.remove(name);
         } else {
-            version.to_string()
+            kegs.insert(name.to_string(), fresh);
+        }
+        Ok(())
+    }
+}
+
+/// Why a keg is installed, as recorded in its install receipt: requested by the user, and/or
+/// pulled in by the listed root targets.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InstallReason {
+    pub on_request: bool,
+    pub dependency_of: BTreeSet<String>,
+}
+
+impl InstallReason {
+    pub fn requested() -> Self {
+        Self {
+            on_request: true,
+            dependency_of: BTreeSet::new(),
+        }
+    }
+
+    /// Reads the reason from the receipt in `keg_path`. `None` if there is no receipt or it
+    /// predates these fields.
+    pub fn read(keg_path: &Path) -> Option<Self> {
+        let receipt: Value =
+            serde_json::from_str(&fs::read_to_string(keg_path.join(RECEIPT_FILE)).ok()?).ok()?;
+        let on_request = receipt.get("installed_on_request").and_then(Value::as_bool);
+        let dependency_of = receipt
+            .get("installed_as_dependency_of")
+            .and_then(Value::as_array);
+        if on_request.is_none() && dependency_of.is_none() {
+            return None;
+        }
+        Some(Self {
+            on_request: on_request.unwrap_or(false),
+            dependency_of: dependency_of
+                .into_iter()
+                .flatten()
+                .filter_map(Value::as_str)
+                .map(str::to_string)
+                .collect(),
+        })
+    }
+
+    /// A keg stays requested once requested, and accumulates every root that needed it.
+    pub fn merge(&mut self, other: &Self) {
+        self.on_request |= other.on_request;
+        self.dependency_of
+            .extend(other.dependency_of.iter().cloned());
+    }
+
+    /// Merges this reason into the receipt in `keg_path`, keeping what earlier installs recorded.
+    pub fn record(&self, keg_path: &Path) -> Result<()> {
+        let receipt_path = keg_path.join(RECEIPT_FILE);
+        let mut receipt: serde_json::Map<String, Value> =
+            serde_json::from_str(&fs::read_to_string(&receipt_path)?)?;
+        let mut merged = Self::read(keg_path).unwrap_or_default();
+        merged.merge(self);
+        // A requested keg can still be listed as a dependency of other roots; both are kept.
+        receipt.insert(
+            "installed_on_request".to_string(),
+            Value::Bool(merged.on_request),
+        );
+        receipt.insert(
+            "installed_as_dependency_of".to_string(),
+            Value::Array(
+                merged
+                    .dependency_of
+                    .into_iter()
+                    .map(Value::String)
+                    .collect(),
+            ),
+        );
+        fs::write(&receipt_path, serde_json::to_string_pretty(&receipt)?)?;
+        Ok(())
+    }
+
+    /// `"installed on request"`, `"installed as dependency of: ffmpeg, mpv"` or both.
+    pub fn describe(&self) -> String {
+        let roots: Vec<&str> = self.dependency_of.iter().map(String::as_str).collect();
+        match (self.on_request, roots.is_empty()) {
+            (true, true) => "installed on request".to_string(),
+            (false, false) => format!("installed as dependency of: {}", roots.join(", ")),
+            (true, false) => format!(
+                "installed on request; also a dependency of: {}",
+                roots.join(", ")
+            ),
+            (false, true) => "installed as a dependency".to_string(),
+        }
+    }
+}
+
+/// An optional or recommended dependency that failed to install, so the keg was installed
+/// without it. Listed under `skipped_dependencies` in the receipt.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SkippedDependency {
+    pub name: String,
+    /// `recommended` or `optional`.
+    pub tag: &'static str,
+    pub reason: String,
+}
+
+impl SkippedDependency {
+    /// Writes `skipped` into the receipt in `keg_path`, replacing any earlier list.
+    pub fn record(keg_path: &Path, skipped: &[Self]) -> Result<()> {
+        let receipt_path = keg_path.join(RECEIPT_FILE);
+        let mut receipt: serde_json::Map<String, Value> =
+            serde_json::from_str(&fs::read_to_string(&receipt_path)?)?;
+        receipt.insert(
+            "skipped_dependencies".to_string(),
+            Value::Array(
+                skipped
+                    .iter()
+                    .map(|dep| {
+                        serde_json::json!({
+                            "name": dep.name,
+                            "tag": dep.tag,
+                            "reason": dep.reason,
+                        })
+                    })
+                    .collect(),
+            ),
+        );
+        fs::write(&receipt_path, serde_json::to_string_pretty(&receipt)?)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn keg_with_receipt(receipt: Value) -> tempfile::TempDir {
+        let keg = tempfile::tempdir().unwrap();
+        fs::write(keg.path().join(RECEIPT_FILE), receipt.to_string()).unwrap();
+        keg
+    }
+
+    fn dependency_of(roots: &[&str]) -> InstallReason {
+        InstallReason {
+            on_request: false,
+            dependency_of: roots.iter().map(|r| r.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn a_receipt_without_the_fields_has_no_reason() {
+        let keg = keg_with_receipt(json!({ "source": {} }));
+
+        assert_eq!(InstallReason::read(keg.path()), None);
+    }
+
+    #[test]
+    fn recording_keeps_the_rest_of_the_receipt() {
+        let keg = keg_with_receipt(json!({ "time": 1, "runtime_dependencies": ["a"] }));
+
+        dependency_of(&["ffmpeg"]).record(keg.path()).unwrap();
+
+        let receipt: Value =
+            serde_json::from_str(&fs::read_to_string(keg.path().join(RECEIPT_FILE)).unwrap())
+                .unwrap();
+        assert_eq!(receipt["time"], 1);
+        assert_eq!(receipt["runtime_dependencies"], json!(["a"]));
+        assert_eq!(receipt["installed_on_request"], false);
+        assert_eq!(receipt["installed_as_dependency_of"], json!(["ffmpeg"]));
+    }
+
+    #[test]
+    fn later_installs_add_roots_and_never_drop_the_request() {
+        let keg = keg_with_receipt(json!({}));
+
+        dependency_of(&["mpv"]).record(keg.path()).unwrap();
+        dependency_of(&["ffmpeg", "mpv"])
+            .record(keg.path())
+            .unwrap();
+        InstallReason::requested().record(keg.path()).unwrap();
+        dependency_of(&["vlc"]).record(keg.path()).unwrap();
+
+        let reason = InstallReason::read(keg.path()).unwrap();
+        assert!(reason.on_request);
+        assert_eq!(
+            reason.dependency_of.iter().collect::<Vec<_>>(),
+            ["ffmpeg", "mpv", "vlc"]
+        );
+        assert_eq!(
+            reason.describe(),
+            "installed on request; also a dependency of: ffmpeg, mpv, vlc"
+        );
+    }
+
+    #[test]
+    fn reads_receipts_from_before_roots_were_recorded() {
+        let keg = keg_with_receipt(json!({ "installed_on_request": false }));
+
+        let reason = InstallReason::read(keg.path()).unwrap();
+
+        assert_eq!(reason, InstallReason::default());
+        assert_eq!(reason.describe(), "installed as a dependency");
+    }
+
+    /// A Cellar in a temporary prefix holding `kegs` (name, version), each formula linked to its
+    /// first listed version.
+    fn cellar_with(kegs: &[(&str, &str)]) -> (tempfile::TempDir, Config) {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            prefix: dir.path().to_path_buf(),
+            cellar: dir.path().join("Cellar"),
+            ..Config::load().unwrap()
         };
-        self.formula_cellar_path(name).join(version_string)
+        fs::create_dir_all(dir.path().join("opt")).unwrap();
+        for (name, version) in kegs {
+            add_keg(&config, name, version);
+        }
+        (dir, config)
+    }
+
+    /// Pours `name` `version` into the Cellar, linking it unless another keg of `name` is.
+    fn add_keg(config: &Config, name: &str, version: &str) {
+        let keg = config.cellar.join(name).join(version);
+        fs::create_dir_all(&keg).unwrap();
+        let opt = config.prefix.join("opt").join(name);
+        if fs::symlink_metadata(&opt).is_err() {
+            std::os::unix::fs::symlink(&keg, opt).unwrap();
+        }
+    }
+
+    fn versions(kegs: Vec<InstalledKeg>) -> Vec<String> {
+        let mut versions: Vec<String> = kegs
+            .into_iter()
+            .map(|k| format!("{} {}", k.name, k.version))
+            .collect();
+        versions.sort();
+        versions
+    }
+
+    #[test]
+    fn a_snapshot_answers_like_a_cellar_scan() {
+        let (_dir, config) = cellar_with(&[("jq", "1.6"), ("jq", "1.7"), ("oniguruma", "6.9")]);
+        let scanning = KegRegistry::new(config.clone());
+        let snapshot =
+            KegRegistry::with_snapshot(config.clone(), KegSnapshot::load(&config).unwrap());
+
+        for name in ["jq", "oniguruma", "wget"] {
+            assert_eq!(
+                snapshot.get_installed_keg(name).unwrap(),
+                scanning.get_installed_keg(name).unwrap(),
+                "{name}"
+            );
+            assert_eq!(
+                versions(snapshot.list_formula_kegs(name).unwrap()),
+                versions(scanning.list_formula_kegs(name).unwrap()),
+                "{name}"
+            );
+        }
+        // The linked keg is current even though a newer one is installed.
+        assert_eq!(
+            snapshot.get_installed_keg("jq").unwrap().unwrap().version,
+            PkgVersion::parse("1.6")
+        );
+        assert_eq!(
+            versions(snapshot.list_installed_kegs().unwrap()),
+            ["jq 1.6", "jq 1.7", "oniguruma 6.9"]
+        );
+    }
+
+    #[test]
+    fn a_keg_added_mid_operation_is_seen_once_its_formula_is_invalidated() {
+        let (_dir, config) = cellar_with(&[("jq", "1.6")]);
+        let snapshot = KegSnapshot::load(&config).unwrap();
+        let registry = KegRegistry::with_snapshot(config.clone(), Arc::clone(&snapshot));
+
+        // Another task pours a new formula and a second version of one already installed.
+        add_keg(&config, "oniguruma", "6.9");
+        add_keg(&config, "jq", "1.7");
+        assert_eq!(registry.get_installed_keg("oniguruma").unwrap(), None);
+        assert_eq!(
+            versions(registry.list_formula_kegs("jq").unwrap()),
+            ["jq 1.6"]
+        );
+
+        snapshot.invalidate("oniguruma").unwrap();
+        snapshot.invalidate("jq").unwrap();
+        assert_eq!(
+            registry
+                .get_installed_keg("oniguruma")
+                .unwrap()
+                .unwrap()
+                .version,
+            PkgVersion::parse("6.9")
+        );
+        assert_eq!(
+            versions(registry.list_formula_kegs("jq").unwrap()),
+            ["jq 1.6", "jq 1.7"]
+        );
+
+        // Which keg is linked is read live, so relinking needs no invalidation.
+        let opt = config.prefix.join("opt/jq");
+        fs::remove_file(&opt).unwrap();
+        std::os::unix::fs::symlink(config.cellar.join("jq/1.7"), &opt).unwrap();
+        assert_eq!(
+            registry.get_installed_keg("jq").unwrap().unwrap().version,
+            PkgVersion::parse("1.7")
+        );
+
+        // A removed formula drops out entirely.
+        fs::remove_dir_all(config.cellar.join("oniguruma")).unwrap();
+        snapshot.invalidate("oniguruma").unwrap();
+        assert_eq!(registry.get_installed_keg("oniguruma").unwrap(), None);
+        assert_eq!(
+            versions(registry.list_installed_kegs().unwrap()),
+            ["jq 1.6", "jq 1.7"]
+        );
+    }
+
+    #[test]
+    fn a_partial_keg_is_quarantined_beside_its_version_and_found_by_cleanup() {
+        let (_dir, config) = cellar_with(&[("jq", "1.6")]);
+        let partial = config.cellar.join("jq/1.7.1");
+        fs::create_dir_all(partial.join("bin")).unwrap();
+        fs::write(partial.join("bin/jq"), "half a binary").unwrap();
+        assert!(!has_valid_receipt(&partial));
+
+        let first = quarantine(&partial).unwrap();
+        fs::create_dir_all(&partial).unwrap();
+        let second = quarantine(&partial).unwrap();
+
+        assert!(!partial.exists());
+        let name = |path: &Path| path.file_name().unwrap().to_string_lossy().to_string();
+        let (version, stamp) = name(&first)
+            .split_once(QUARANTINE_INFIX)
+            .map(|(v, s)| (v.to_string(), s.to_string()))
+            .unwrap();
+        assert_eq!(version, "1.7.1");
+        assert!(stamp.parse::<u64>().is_ok(), "{stamp}");
+        // Two kegs moved aside within the same second don't clobber each other.
+        assert_ne!(first, second);
+        assert_eq!(
+            fs::read_to_string(first.join("bin/jq")).unwrap(),
+            "half a binary"
+        );
+        assert!(is_quarantined(&name(&first)) && is_quarantined(&name(&second)));
+        assert!(!is_quarantined("1.7.1") && !is_quarantined("1.7.1.broken-"));
+
+        let mut expected = vec![
+            ("jq".to_string(), first.clone()),
+            ("jq".to_string(), second.clone()),
+        ];
+        expected.sort();
+        assert_eq!(quarantined_kegs(&config).unwrap(), expected);
+        let reported = take_quarantined();
+        assert!(
+            reported.contains(&first) && reported.contains(&second),
+            "{reported:?}"
+        );
+        // Quarantined kegs are not versions of the formula.
+        assert_eq!(
+            versions(
+                KegRegistry::new(config.clone())
+                    .list_formula_kegs("jq")
+                    .unwrap()
+            ),
+            ["jq 1.6"]
+        );
     }
 }
@@ -1,11 +1,22 @@
 // sps-common/src/lib.rs
+pub mod advisory;
 pub mod cache;
+pub mod cancel;
 pub mod config;
+pub mod config_file;
 pub mod dependency;
 pub mod error;
 pub mod formulary;
+pub mod homebrew_env;
 pub mod keg;
+pub mod macos;
+pub mod metrics;
 pub mod model;
+pub mod overrides;
+pub mod state;
+pub mod tap_ruby;
+#[cfg(test)]
+mod test_env;
 // Optional: pub mod dependency_def;
 
 // Re-export key types
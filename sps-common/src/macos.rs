@@ -0,0 +1,327 @@
+// sps-common/src/macos.rs
+//! macOS version requirements as they appear in formula `requirements` and cask `depends_on`
+//! entries: plain versions (`"12"`, `"10.15"`), release symbols (`":big_sur"`) and comparisons
+//! (`">= :monterey"`).
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::process::Command;
+use std::sync::OnceLock;
+
+use tracing::debug;
+
+use crate::model::cask::MacOSReq;
+
+/// Release names known to Homebrew, newest first.
+const RELEASES: &[(&str, u32, u32)] = &[
+    ("tahoe", 26, 0),
+    ("sequoia", 15, 0),
+    ("sonoma", 14, 0),
+    ("ventura", 13, 0),
+    ("monterey", 12, 0),
+    ("big_sur", 11, 0),
+    ("catalina", 10, 15),
+    ("mojave", 10, 14),
+    ("high_sierra", 10, 13),
+    ("sierra", 10, 12),
+    ("el_capitan", 10, 11),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct MacOSVersion {
+    pub major: u32,
+    /// Only significant for 10.x releases; compared as 0 from Big Sur on.
+    pub minor: u32,
+}
+
+impl MacOSVersion {
+    /// Parses `"12"`, `"10.15"`, `"14.4.1"`, `"monterey"` or `":monterey"`.
+    pub fn parse(s: &str) -> Option<Self> {
+        let s = s.trim().trim_start_matches(':');
+        if let Some((_, major, minor)) = RELEASES.iter().find(|(name, ..)| *name == s) {
+            return Some(Self {
+                major: *major,
+                minor: *minor,
+            });
+        }
+        let mut parts = s.split('.');
+        let major: u32 = parts.next()?.parse().ok()?;
+        let minor: u32 = match parts.next() {
+            Some(p) => p.parse().ok()?,
+            None => 0,
+        };
+        // 11.x and later are one release per major version.
+        let minor = if major >= 11 { 0 } else { minor };
+        Some(Self { major, minor })
+    }
+
+    /// The running system's version, or `None` when not on macOS or `sw_vers` fails.
+    pub fn host() -> Option<Self> {
+        static HOST: OnceLock<Option<MacOSVersion>> = OnceLock::new();
+        *HOST.get_or_init(|| {
+            if !cfg!(target_os = "macos") {
+                return None;
+            }
+            let output = Command::new("/usr/bin/sw_vers")
+                .arg("-productVersion")
+                .output()
+                .ok()?;
+            let version = String::from_utf8_lossy(&output.stdout);
+            let parsed = Self::parse(version.trim());
+            debug!("Host macOS version: {:?} ({})", parsed, version.trim());
+            parsed
+        })
+    }
+
+    fn release_name(&self) -> Option<&'static str> {
+        RELEASES
+            .iter()
+            .find(|(_, major, minor)| *major == self.major && *minor == self.minor)
+            .map(|(name, ..)| *name)
+    }
+}
+
+impl fmt::Display for MacOSVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.major >= 11 {
+            write!(f, "{}", self.major)?;
+        } else {
+            write!(f, "{}.{}", self.major, self.minor)?;
+        }
+        if let Some(name) = self.release_name() {
+            write!(f, " ({name})")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparator {
+    Eq,
+    Ge,
+    Gt,
+    Le,
+    Lt,
+}
+
+impl Comparator {
+    fn symbol(&self) -> &'static str {
+        match self {
+            Comparator::Eq => "=",
+            Comparator::Ge => ">=",
+            Comparator::Gt => ">",
+            Comparator::Le => "<=",
+            Comparator::Lt => "<",
+        }
+    }
+}
+
+/// One version constraint, e.g. `>= 12 (monterey)`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MacOSConstraint {
+    pub comparator: Comparator,
+    pub version: MacOSVersion,
+}
+
+impl MacOSConstraint {
+    /// Parses an optional comparator followed by a version or symbol. A bare version uses
+    /// `default`: formulae mean "at least", casks listing symbols mean "exactly". Returns `None`
+    /// for the open-ended `"any"` / `"latest"` placeholders and anything unparseable.
+    pub fn parse(s: &str, default: Comparator) -> Option<Self> {
+        let s = s.trim();
+        let (comparator, rest) = [
+            (">=", Comparator::Ge),
+            ("<=", Comparator::Le),
+            ("==", Comparator::Eq),
+            (">", Comparator::Gt),
+            ("<", Comparator::Lt),
+        ]
+        .iter()
+        .find_map(|(prefix, cmp)| s.strip_prefix(prefix).map(|rest| (*cmp, rest)))
+        .unwrap_or((default, s));
+        Some(Self {
+            comparator,
+            version: MacOSVersion::parse(rest)?,
+        })
+    }
+
+    pub fn is_satisfied_by(&self, host: MacOSVersion) -> bool {
+        let ord = host.cmp(&self.version);
+        match self.comparator {
+            Comparator::Eq => ord == Ordering::Equal,
+            Comparator::Ge => ord != Ordering::Less,
+            Comparator::Gt => ord == Ordering::Greater,
+            Comparator::Le => ord != Ordering::Greater,
+            Comparator::Lt => ord == Ordering::Less,
+        }
+    }
+}
+
+impl fmt::Display for MacOSConstraint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "macOS {} {}", self.comparator.symbol(), self.version)
+    }
+}
+
+/// Constraints from a cask's `depends_on.macos`; the host must satisfy at least one.
+pub fn cask_constraints(req: &MacOSReq) -> Vec<MacOSConstraint> {
+    match req {
+        MacOSReq::Symbol(s) | MacOSReq::Comparison(s) => MacOSConstraint::parse(s, Comparator::Eq)
+            .into_iter()
+            .collect(),
+        MacOSReq::Symbols(list) => list
+            .iter()
+            .filter_map(|s| MacOSConstraint::parse(s, Comparator::Eq))
+            .collect(),
+        MacOSReq::Map(map) => map
+            .iter()
+            .flat_map(|(op, versions)| {
+                versions.iter().filter_map(move |v| {
+                    MacOSConstraint::parse(&format!("{op} {v}"), Comparator::Eq)
+                })
+            })
+            .collect(),
+        MacOSReq::Other(value) => {
+            crate::model::lenient::note_once("depends_on.macos", format!("unknown form {value}"));
+            Vec::new()
+        }
+    }
+}
+
+/// Describes why the host does not meet `constraints`, or `None` if it does (or if the host
+/// version is unknown, e.g. on Linux).
+pub fn unmet_requirement(constraints: &[MacOSConstraint]) -> Option<String> {
+    if constraints.is_empty() {
+        return None;
+    }
+    let host = MacOSVersion::host()?;
+    if constraints.iter().any(|c| c.is_satisfied_by(host)) {
+        return None;
+    }
+    let wanted: Vec<String> = constraints.iter().map(ToString::to_string).collect();
+    Some(format!(
+        "requires {}, running macOS {}",
+        wanted.join(" or "),
+        host
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn v(major: u32, minor: u32) -> MacOSVersion {
+        MacOSVersion { major, minor }
+    }
+
+    #[test]
+    fn parses_versions_and_release_symbols() {
+        let cases = [
+            (":monterey", Some(v(12, 0))),
+            ("monterey", Some(v(12, 0))),
+            (" :big_sur ", Some(v(11, 0))),
+            (":catalina", Some(v(10, 15))),
+            ("12", Some(v(12, 0))),
+            ("14.4.1", Some(v(14, 0))),
+            ("10.15", Some(v(10, 15))),
+            ("10.15.7", Some(v(10, 15))),
+            ("10", Some(v(10, 0))),
+            (":leopard", None),
+            ("latest", None),
+            ("", None),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(MacOSVersion::parse(input), expected, "{input:?}");
+        }
+    }
+
+    #[test]
+    fn orders_ten_point_releases_below_big_sur() {
+        assert!(v(10, 15) < v(11, 0));
+        assert!(v(10, 14) < v(10, 15));
+        assert!(MacOSVersion::parse("11.7") == MacOSVersion::parse(":big_sur"));
+    }
+
+    #[test]
+    fn parses_constraints_with_and_without_a_comparator() {
+        let cases = [
+            (">= :big_sur", Comparator::Eq, Comparator::Ge, v(11, 0)),
+            (">=:big_sur", Comparator::Eq, Comparator::Ge, v(11, 0)),
+            ("> 10.15", Comparator::Eq, Comparator::Gt, v(10, 15)),
+            ("<= :monterey", Comparator::Eq, Comparator::Le, v(12, 0)),
+            ("< 12", Comparator::Eq, Comparator::Lt, v(12, 0)),
+            ("== :sonoma", Comparator::Ge, Comparator::Eq, v(14, 0)),
+            (":monterey", Comparator::Eq, Comparator::Eq, v(12, 0)),
+            ("12", Comparator::Ge, Comparator::Ge, v(12, 0)),
+            ("10.15", Comparator::Ge, Comparator::Ge, v(10, 15)),
+        ];
+        for (input, default, comparator, version) in cases {
+            assert_eq!(
+                MacOSConstraint::parse(input, default),
+                Some(MacOSConstraint {
+                    comparator,
+                    version
+                }),
+                "{input:?}"
+            );
+        }
+        assert_eq!(MacOSConstraint::parse(">= any", Comparator::Ge), None);
+    }
+
+    #[test]
+    fn checks_constraints_against_a_host() {
+        let cases = [
+            (">= :big_sur", v(12, 0), true),
+            (">= :big_sur", v(11, 0), true),
+            (">= :big_sur", v(10, 15), false),
+            ("> 10.15", v(11, 0), true),
+            ("> 10.15", v(10, 15), false),
+            ("<= :monterey", v(13, 0), false),
+            ("< 12", v(11, 0), true),
+            (":monterey", v(12, 0), true),
+            (":monterey", v(13, 0), false),
+        ];
+        for (input, host, expected) in cases {
+            let constraint = MacOSConstraint::parse(input, Comparator::Eq).unwrap();
+            assert_eq!(
+                constraint.is_satisfied_by(host),
+                expected,
+                "{input:?} on {host}"
+            );
+        }
+    }
+
+    #[test]
+    fn reads_every_cask_depends_on_shape() {
+        let symbols = MacOSReq::Symbols(vec![":catalina".into(), ":big_sur".into()]);
+        assert_eq!(
+            cask_constraints(&symbols),
+            [
+                MacOSConstraint::parse("== 10.15", Comparator::Eq).unwrap(),
+                MacOSConstraint::parse("== 11", Comparator::Eq).unwrap(),
+            ]
+        );
+        let comparison = MacOSReq::Comparison(">= :big_sur".into());
+        assert_eq!(
+            cask_constraints(&comparison),
+            [MacOSConstraint::parse(">= 11", Comparator::Eq).unwrap()]
+        );
+        let map = MacOSReq::Map([(">=".to_string(), vec!["12".to_string()])].into());
+        assert_eq!(
+            cask_constraints(&map),
+            [MacOSConstraint::parse(">= :monterey", Comparator::Eq).unwrap()]
+        );
+    }
+
+    #[test]
+    fn displays_release_names() {
+        assert_eq!(
+            MacOSConstraint::parse(">= :big_sur", Comparator::Eq)
+                .unwrap()
+                .to_string(),
+            "macOS >= 11 (big_sur)"
+        );
+        assert_eq!(v(10, 15).to_string(), "10.15 (catalina)");
+        assert_eq!(v(10, 9).to_string(), "10.9");
+    }
+}
@@ -0,0 +1,147 @@
+// sps-common/src/metrics.rs
+//! Local-only usage counters: how often each package was installed, upgraded or uninstalled on
+//! this machine and how long its installs took in total. Nothing here ever leaves the machine;
+//! the store is one JSON file under `<prefix>/var/sps` ([`state::METRICS`]), written only when
+//! `metrics = local`.
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+use crate::config::{Config, MetricsMode};
+use crate::error::Result;
+use crate::state;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MetricEvent {
+    Install,
+    Reinstall,
+    Upgrade,
+    Uninstall,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PackageStats {
+    /// Fresh installs and reinstalls.
+    pub installs: u64,
+    pub upgrades: u64,
+    pub uninstalls: u64,
+    /// Wall time spent installing, upgrading and reinstalling, in seconds.
+    pub install_seconds: f64,
+    /// Unix time of the last recorded event.
+    pub last_used: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Metrics {
+    pub packages: BTreeMap<String, PackageStats>,
+}
+
+/// Where the store lives for this prefix.
+pub fn store_path(config: &Config) -> PathBuf {
+    state::METRICS.path(config)
+}
+
+/// Reads the store. A missing file is an empty store.
+pub fn load(config: &Config) -> Result<Metrics> {
+    Ok(state::load(config, &state::METRICS)?.unwrap_or_default())
+}
+
+/// Counts one successful `event` for `name`, with the time it took for install-like events.
+/// Does nothing unless metrics are enabled; failures to update the store are only logged, since
+/// they should never fail the operation being counted.
+pub fn record(config: &Config, name: &str, event: MetricEvent, duration: Option<Duration>) {
+    if config.metrics != MetricsMode::Local {
+        return;
+    }
+    // Install workers finish concurrently; serialize the read-modify-write within the process.
+    static STORE: Mutex<()> = Mutex::new(());
+    let _guard = STORE.lock().unwrap_or_else(|e| e.into_inner());
+    if let Err(e) = update(config, name, event, duration) {
+        warn!("Could not update local metrics: {}", e);
+    }
+}
+
+fn update(
+    config: &Config,
+    name: &str,
+    event: MetricEvent,
+    duration: Option<Duration>,
+) -> Result<()> {
+    let mut metrics = load(config).unwrap_or_else(|e| {
+        warn!("Starting a new local metrics store: {}", e);
+        Metrics::default()
+    });
+    let stats = metrics.packages.entry(name.to_string()).or_default();
+    match event {
+        MetricEvent::Install | MetricEvent::Reinstall => stats.installs += 1,
+        MetricEvent::Upgrade => stats.upgrades += 1,
+        MetricEvent::Uninstall => stats.uninstalls += 1,
+    }
+    if let Some(duration) = duration {
+        stats.install_seconds += duration.as_secs_f64();
+    }
+    stats.last_used = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+
+    state::store(config, &state::METRICS, &metrics)?;
+    debug!(
+        "Recorded {:?} of {} in {}",
+        event,
+        name,
+        store_path(config).display()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(dir: &std::path::Path, metrics: MetricsMode) -> Config {
+        Config {
+            prefix: dir.to_path_buf(),
+            cellar: dir.join("Cellar"),
+            metrics,
+            ..Config::load().unwrap()
+        }
+    }
+
+    #[test]
+    fn events_accumulate_per_package_only_when_local() {
+        let dir = tempfile::tempdir().unwrap();
+        let off = config(dir.path(), MetricsMode::Off);
+        record(
+            &off,
+            "jq",
+            MetricEvent::Install,
+            Some(Duration::from_secs(1)),
+        );
+        assert!(!store_path(&off).exists());
+
+        let local = config(dir.path(), MetricsMode::Local);
+        for (event, duration) in [
+            (MetricEvent::Install, Some(Duration::from_millis(1500))),
+            (MetricEvent::Reinstall, Some(Duration::from_millis(500))),
+            (MetricEvent::Upgrade, Some(Duration::from_secs(1))),
+            (MetricEvent::Uninstall, None),
+        ] {
+            record(&local, "jq", event, duration);
+        }
+
+        let metrics = load(&local).unwrap();
+        let jq = &metrics.packages["jq"];
+        assert_eq!((jq.installs, jq.upgrades, jq.uninstalls), (2, 1, 1));
+        assert_eq!(jq.install_seconds, 3.0);
+        assert!(jq.last_used > 0);
+        assert_eq!(metrics.packages.len(), 1);
+    }
+}
@@ -5,6 +5,8 @@ use std::fs;
 use serde::{Deserialize, Serialize};
 
 use crate::config::Config; // <-- Added import
+use crate::error::{Result, SpsError};
+use crate::model::lenient;
 
 pub type Artifact = serde_json::Value;
 
@@ -32,6 +34,8 @@ pub enum Sha256Field {
         no_check: bool,
     },
     PerArch(HashMap<String, String>),
+    /// A shape newer than this model; kept as-is and treated as "no checksum".
+    Other(serde_json::Value),
 }
 
 /// Appcast metadata
@@ -44,9 +48,9 @@ pub struct Appcast {
 /// Represents conflicts with other casks or formulae
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ConflictsWith {
-    #[serde(default)]
+    #[serde(default, deserialize_with = "lenient::or_default")]
     pub cask: Vec<String>,
-    #[serde(default)]
+    #[serde(default, deserialize_with = "lenient::or_default")]
     pub formula: Vec<String>,
     #[serde(flatten)]
     pub extra: HashMap<String, serde_json::Value>,
@@ -59,6 +63,8 @@ pub enum ArchReq {
     One(String),          // e.g., "arm64"
     Many(Vec<String>),    // e.g., ["arm64", "x86_64"]
     Specs(Vec<ArchSpec>), // Add this variant to handle [{"type": "arm", "bits": 64}]
+    /// A shape newer than this model, kept as-is.
+    Other(serde_json::Value),
 }
 
 /// Helper for macOS requirements: symbol, list, comparison, or map
@@ -69,6 +75,8 @@ pub enum MacOSReq {
     Symbols(Vec<String>), // [":catalina", ":big_sur"]
     Comparison(String),   // ">= :big_sur"
     Map(HashMap<String, Vec<String>>),
+    /// A shape newer than this model, kept as-is and treated as no constraint.
+    Other(serde_json::Value),
 }
 
 /// Helper to coerce string-or-list into Vec<String>
@@ -99,56 +107,122 @@ pub struct ArchSpec {
 /// Represents `depends_on` block with multiple possible keys
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct DependsOn {
-    #[serde(default)]
+    #[serde(default, deserialize_with = "lenient::or_default")]
     pub cask: Vec<String>,
-    #[serde(default)]
+    #[serde(default, deserialize_with = "lenient::or_default")]
     pub formula: Vec<String>,
-    #[serde(default)]
+    #[serde(default, deserialize_with = "lenient::or_default")]
     pub arch: Option<ArchReq>,
-    #[serde(default)]
+    #[serde(default, deserialize_with = "lenient::or_default")]
     pub macos: Option<MacOSReq>,
     #[serde(flatten)]
     pub extra: HashMap<String, serde_json::Value>,
 }
 
+/// Overrides published for one platform (under `variations`) or one language.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CaskVariation {
+    #[serde(default)]
+    pub url: Option<UrlField>,
+    #[serde(default)]
+    pub sha256: Option<Sha256Field>,
+    #[serde(default)]
+    pub version: Option<String>,
+    #[serde(default)]
+    pub artifacts: Option<Vec<Artifact>>,
+    #[serde(flatten)]
+    pub extra: HashMap<String, serde_json::Value>,
+}
+
+/// Which variation of a cask was installed, recorded in the install receipt. `None` fields mean
+/// the cask's default artifact.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CaskVariant {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub platform: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+}
+
 /// The main Cask model matching Homebrew JSON v2
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct Cask {
     pub token: String,
 
-    #[serde(default)]
+    #[serde(default, deserialize_with = "lenient::or_default")]
     pub name: Option<Vec<String>>,
+    #[serde(default, deserialize_with = "lenient::or_default")]
     pub version: Option<String>,
+    #[serde(default, deserialize_with = "lenient::or_default")]
     pub desc: Option<String>,
+    #[serde(default, deserialize_with = "lenient::or_default")]
     pub homepage: Option<String>,
 
-    #[serde(default)]
+    #[serde(default, deserialize_with = "lenient::or_default")]
     pub artifacts: Option<Vec<Artifact>>,
 
-    #[serde(default)]
+    #[serde(default, deserialize_with = "lenient::or_default")]
     pub url: Option<UrlField>,
-    #[serde(default)]
+    #[serde(default, deserialize_with = "lenient::or_default")]
     pub url_specs: Option<HashMap<String, serde_json::Value>>,
 
-    #[serde(default)]
+    #[serde(default, deserialize_with = "lenient::or_default")]
     pub sha256: Option<Sha256Field>,
 
+    #[serde(default, deserialize_with = "lenient::or_default")]
     pub appcast: Option<Appcast>,
+    #[serde(default, deserialize_with = "lenient::or_default")]
     pub auto_updates: Option<bool>,
 
-    #[serde(default)]
+    #[serde(default, deserialize_with = "lenient::or_default")]
     pub depends_on: Option<DependsOn>,
 
-    #[serde(default)]
+    #[serde(default, deserialize_with = "lenient::or_default")]
     pub conflicts_with: Option<ConflictsWith>,
 
+    #[serde(default, deserialize_with = "lenient::or_default")]
     pub caveats: Option<String>,
+    #[serde(default, deserialize_with = "lenient::or_default")]
     pub stage_only: Option<bool>,
 
-    #[serde(default)]
+    #[serde(default, deserialize_with = "lenient::or_default")]
     pub uninstall: Option<HashMap<String, serde_json::Value>>,
-    #[serde(default)]
+    #[serde(default, deserialize_with = "lenient::or_default")]
     pub zap: Option<HashMap<String, serde_json::Value>>,
+
+    /// Per-platform overrides keyed by bottle-style tag (`arm64_sonoma`, `sonoma`).
+    #[serde(
+        default,
+        deserialize_with = "lenient::map_skipping_invalid",
+        skip_serializing_if = "HashMap::is_empty"
+    )]
+    pub variations: HashMap<String, CaskVariation>,
+    /// Languages offered by the cask's `language` blocks.
+    #[serde(
+        default,
+        deserialize_with = "lenient::or_default",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub languages: Vec<String>,
+    /// Per-language overrides keyed by language code. The public API only carries the default
+    /// language's artifact; tap or local cask JSON may provide these.
+    #[serde(
+        default,
+        deserialize_with = "lenient::map_skipping_invalid",
+        skip_serializing_if = "HashMap::is_empty"
+    )]
+    pub language_variations: HashMap<String, CaskVariation>,
+    /// Set by [`Cask::resolve_variant`]; absent in upstream JSON.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub variant: Option<CaskVariant>,
+    /// Where the definition came from when it was not the API: a local file or URL given to
+    /// `install`. Recorded in the receipt so upgrades don't compare it against the API.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub definition_source: Option<String>,
+
+    /// Upstream fields not modelled above, preserved verbatim.
+    #[serde(flatten)]
+    pub extra: HashMap<String, serde_json::Value>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -157,8 +231,104 @@ pub struct CaskList {
 }
 
 impl Cask {
+    /// Parses a cask definition supplied by the user (`source` names it in errors), rejecting
+    /// it unless it has a token, a version, a download URL, a checksum or `no_check`, and at
+    /// least one artifact. The lenient API parsing would otherwise default a bad field away and
+    /// fail much later in the install.
+    pub fn from_definition(value: serde_json::Value, source: &str) -> Result<Cask> {
+        let invalid = |msg: String| SpsError::ValidationError(format!("{source}: {msg}"));
+        let Some(object) = value.as_object() else {
+            return Err(invalid(
+                "a cask definition must be a JSON object".to_string(),
+            ));
+        };
+        for field in ["token", "version"] {
+            match object.get(field) {
+                Some(serde_json::Value::String(s)) if !s.trim().is_empty() => {}
+                Some(_) => return Err(invalid(format!("`{field}` must be a non-empty string"))),
+                None => return Err(invalid(format!("missing required field `{field}`"))),
+            }
+        }
+        if !object.contains_key("url") && !object.contains_key("url_specs") {
+            return Err(invalid("missing required field `url`".to_string()));
+        }
+        match object.get("sha256") {
+            None => {
+                return Err(invalid(
+                    "missing required field `sha256` (use \"no_check\" to skip verification)"
+                        .to_string(),
+                ))
+            }
+            Some(serde_json::Value::String(s))
+                if s == "no_check"
+                    || (s.len() == 64 && s.chars().all(|c| c.is_ascii_hexdigit())) => {}
+            Some(serde_json::Value::Object(o))
+                if o.get("no_check") == Some(&serde_json::Value::Bool(true)) => {}
+            Some(_) => {
+                return Err(invalid(
+                    "`sha256` must be a 64-character hex digest or \"no_check\"".to_string(),
+                ))
+            }
+        }
+        match object.get("artifacts") {
+            Some(serde_json::Value::Array(a)) if !a.is_empty() => {}
+            _ => {
+                return Err(invalid(
+                    "`artifacts` must list at least one artifact".to_string(),
+                ))
+            }
+        }
+        let mut cask: Cask = serde_json::from_value(value)
+            .map_err(|e| invalid(format!("not a valid cask definition: {e}")))?;
+        cask.definition_source = Some(source.to_string());
+        Ok(cask)
+    }
+
+    /// The cask as it installs on `platform` (a bottle-style tag) for a user preferring
+    /// `languages`, most preferred first: the matching platform variation and language variation
+    /// are applied over the defaults, and [`Cask::variant`] records which were used.
+    pub fn resolve_variant(&self, platform: &str, languages: &[String]) -> Cask {
+        let mut cask = self.clone();
+        let mut variant = CaskVariant::default();
+        if let Some(variation) = self.variations.get(platform) {
+            cask.apply_variation(variation);
+            variant.platform = Some(platform.to_string());
+        }
+        if let Some(language) = select_language(&self.languages, languages) {
+            match self.language_variations.get(language) {
+                Some(variation) => {
+                    cask.apply_variation(variation);
+                    variant.language = Some(language.to_string());
+                }
+                None => tracing::debug!(
+                    "{}: no artifact published for language {}; using the default",
+                    self.token,
+                    language
+                ),
+            }
+        }
+        cask.variant = Some(variant);
+        cask
+    }
+
+    fn apply_variation(&mut self, variation: &CaskVariation) {
+        if let Some(url) = &variation.url {
+            self.url = Some(url.clone());
+        }
+        if let Some(sha256) = &variation.sha256 {
+            self.sha256 = Some(sha256.clone());
+        }
+        if let Some(version) = &variation.version {
+            self.version = Some(version.clone());
+        }
+        if let Some(artifacts) = &variation.artifacts {
+            self.artifacts = Some(artifacts.clone());
+        }
+    }
+
     /// Check if this cask is installed by looking for a manifest file
-    /// in any versioned directory within the Caskroom.
+    /// in any versioned directory within the Caskroom. This says nothing about which version or
+    /// whether its artifacts still exist; `sps_core::build::cask::install_state` does.
     pub fn is_installed(&self, config: &Config) -> bool {
         let cask_dir = config.cask_dir(&self.token); // e.g., /opt/homebrew/Caskroom/firefox
         if !cask_dir.exists() || !cask_dir.is_dir() {
@@ -236,3 +406,22 @@ impl Cask {
             .unwrap_or_else(|| self.token.clone())
     }
 }
+
+/// The first of `preferred` that `offered` matches, exactly or by primary subtag (`de-AT` and `de`
+/// both match `de-DE` when that is all the cask offers).
+fn select_language<'a>(offered: &'a [String], preferred: &[String]) -> Option<&'a str> {
+    let primary = |tag: &str| {
+        tag.split(['-', '_'])
+            .next()
+            .unwrap_or(tag)
+            .to_ascii_lowercase()
+    };
+    preferred.iter().find_map(|wanted| {
+        let wanted = wanted.replace('_', "-");
+        offered
+            .iter()
+            .find(|o| o.eq_ignore_ascii_case(&wanted))
+            .or_else(|| offered.iter().find(|o| primary(o) == primary(&wanted)))
+            .map(String::as_str)
+    })
+}
@@ -0,0 +1,149 @@
+// sps-common/src/model/cask_version.rs
+//! Cask versions, which differ from formula versions in two ways. A cask may be `:latest` (the
+//! API's `"latest"`), meaning the download is unversioned and no version can say whether it
+//! changed. And a version may carry build metadata after commas or colons (`1.2.3,4567`,
+//! `2.0:abc`), as casks do for download URLs that need a build number or hash.
+//!
+//! Each comma- or colon-separated part is compared as a [`PkgVersion`], left to right, so
+//! `1.2.3,4567` < `1.2.3,4568` < `1.2.4,1`. A version without a part the other has is older
+//! (`1.2.3` < `1.2.3,4567`). `:latest` is equal to itself and not ordered against any other
+//! version.
+
+use std::cmp::Ordering;
+use std::fmt;
+
+use super::PkgVersion;
+
+#[derive(Debug, Clone)]
+pub enum CaskVersion {
+    /// `version :latest`.
+    Latest,
+    /// A concrete version: its parts, and the text as written.
+    Versioned { parts: Vec<PkgVersion>, raw: String },
+}
+
+impl CaskVersion {
+    /// Parses a cask version as the API or an installed receipt writes it.
+    pub fn parse(s: &str) -> Self {
+        let s = s.trim();
+        if s == "latest" || s == ":latest" {
+            return Self::Latest;
+        }
+        // Split before parsing so `12:34` isn't read as an epoch.
+        let parts = s
+            .split([',', ':'])
+            .map(|part| PkgVersion::parse(part).with_revision(0))
+            .collect();
+        Self::Versioned {
+            parts,
+            raw: s.to_string(),
+        }
+    }
+
+    pub fn is_latest(&self) -> bool {
+        matches!(self, Self::Latest)
+    }
+}
+
+impl PartialOrd for CaskVersion {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        match (self, other) {
+            (Self::Latest, Self::Latest) => Some(Ordering::Equal),
+            (Self::Latest, _) | (_, Self::Latest) => None,
+            (Self::Versioned { parts: a, .. }, Self::Versioned { parts: b, .. }) => {
+                for (x, y) in a.iter().zip(b) {
+                    match x.cmp(y) {
+                        Ordering::Equal => {}
+                        order => return Some(order),
+                    }
+                }
+                Some(a.len().cmp(&b.len()))
+            }
+        }
+    }
+}
+
+impl PartialEq for CaskVersion {
+    fn eq(&self, other: &Self) -> bool {
+        self.partial_cmp(other) == Some(Ordering::Equal)
+    }
+}
+
+impl fmt::Display for CaskVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Latest => f.write_str("latest"),
+            Self::Versioned { raw, .. } => f.write_str(raw),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn v(s: &str) -> CaskVersion {
+        CaskVersion::parse(s)
+    }
+
+    #[test]
+    fn latest_is_recognized_in_both_spellings() {
+        for s in ["latest", ":latest", " :latest\n"] {
+            assert!(v(s).is_latest(), "{s:?}");
+            assert_eq!(v(s).to_string(), "latest");
+        }
+        assert!(!v("1.0").is_latest());
+        assert!(!v("latest-beta").is_latest());
+    }
+
+    #[test]
+    fn build_metadata_after_a_comma_is_its_own_part() {
+        let CaskVersion::Versioned { parts, raw } = v("1.2.3,4567") else {
+            panic!("1.2.3,4567 is not :latest");
+        };
+        assert_eq!(
+            parts,
+            [PkgVersion::parse("1.2.3"), PkgVersion::parse("4567")]
+        );
+        assert_eq!(raw, "1.2.3,4567");
+        assert_eq!(v("1.2.3,4567").to_string(), "1.2.3,4567");
+        // A colon separates parts too, rather than marking an epoch.
+        let CaskVersion::Versioned { parts, .. } = v("12:34") else {
+            panic!("12:34 is not :latest");
+        };
+        assert_eq!(parts, [PkgVersion::parse("12"), PkgVersion::parse("34")]);
+    }
+
+    #[test]
+    fn parts_compare_left_to_right() {
+        let ascending = [
+            "1.2.2,9999",
+            "1.2.3",
+            "1.2.3,4567",
+            "1.2.3,4568",
+            "1.2.3,4568:abc",
+            "1.2.4,1",
+            "1.10,1",
+        ];
+        for pair in ascending.windows(2) {
+            assert!(v(pair[0]) < v(pair[1]), "{} < {}", pair[0], pair[1]);
+            assert!(v(pair[1]) > v(pair[0]), "{} > {}", pair[1], pair[0]);
+        }
+        assert_eq!(v("1.2.3,4567"), v(" 1.2.3,4567 "));
+        assert_ne!(v("1.2.3,4567"), v("1.2.3"));
+    }
+
+    #[test]
+    fn latest_equals_only_itself_and_is_unordered() {
+        assert_eq!(v(":latest"), v("latest"));
+        assert_eq!(
+            v(":latest").partial_cmp(&v("latest")),
+            Some(Ordering::Equal)
+        );
+        for other in ["1.2.3,4567", "0"] {
+            assert_eq!(v(":latest").partial_cmp(&v(other)), None, "{other}");
+            assert_eq!(v(other).partial_cmp(&v(":latest")), None, "{other}");
+            assert_ne!(v(":latest"), v(other));
+        }
+    }
+}
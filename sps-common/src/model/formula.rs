@@ -6,12 +6,15 @@ use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 
 use semver::Version;
-use serde::{de, Deserialize, Deserializer, Serialize};
-use serde_json::Value;
+use serde::ser::SerializeMap;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::{Map, Value};
 use tracing::{debug, error};
 
 use crate::dependency::{Dependency, DependencyTag, Requirement};
 use crate::error::Result; // <-- Import only Result // Use log crate imports
+use crate::model::lenient;
+use crate::model::pkg_version::PkgVersion;
 
 // --- Resource Spec Struct ---
 // *** Added struct definition, REMOVED #[derive(Deserialize)] ***
@@ -23,11 +26,22 @@ pub struct ResourceSpec {
     // Add other potential fields like version if needed later
 }
 
+/// The `head` entry of a formula's `urls`: the repository a `--HEAD` install builds from.
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct HeadSpec {
+    pub url: String,
+    /// Branch to build; the remote's default branch when absent.
+    pub branch: Option<String>,
+}
+
 // --- Bottle Related Structs (Original structure) ---
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct BottleFileSpec {
     pub url: String,
     pub sha256: String,
+    /// Upstream fields not modelled above (`cellar`), preserved verbatim.
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq, Eq)]
@@ -37,9 +51,13 @@ pub struct BottleSpec {
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq, Eq)]
 pub struct BottleStableSpec {
+    #[serde(default, deserialize_with = "lenient::null_as_default")]
     pub rebuild: u32,
-    #[serde(default)]
+    #[serde(default, deserialize_with = "lenient::map_skipping_invalid")]
     pub files: HashMap<String, BottleFileSpec>,
+    /// Upstream fields not modelled above (`root_url`), preserved verbatim.
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
 }
 
 // --- Formula Version Struct (Original structure) ---
@@ -47,39 +65,98 @@ pub struct BottleStableSpec {
 pub struct FormulaVersions {
     pub stable: Option<String>,
     pub head: Option<String>,
-    #[serde(default)]
+    #[serde(default, deserialize_with = "lenient::null_as_default")]
     pub bottle: bool,
 }
 
+/// Version prefix of kegs built from a formula's head.
+pub const HEAD_VERSION_PREFIX: &str = "HEAD-";
+
+/// The tap API formulae come from; its formulae are known by their short names.
+pub const CORE_TAP: &str = "homebrew/core";
+
+/// Separator between the tap and the short name in Cellar and opt directory names
+/// (`Cellar/mycorp--tools--jq`), which can't contain `/`.
+const KEG_DIR_SEPARATOR: &str = "--";
+
+/// Splits a `user/repo/name` formula name into its tap and short name. Plain names and names
+/// qualified with the core tap (`homebrew/core/jq`) have no tap.
+pub fn split_tap_name(name: &str) -> (Option<&str>, &str) {
+    match name.rsplit_once('/') {
+        Some((CORE_TAP, short)) => (None, short),
+        Some((tap, short)) if tap.matches('/').count() == 1 => (Some(tap), short),
+        _ => (None, name),
+    }
+}
+
+/// The name a formula is keyed by everywhere: `user/repo/name` for tapped formulae, the short
+/// name for core ones (so `homebrew/core/jq` and `jq` are the same formula).
+pub fn canonical_formula_name(name: &str) -> &str {
+    match split_tap_name(name) {
+        (None, short) => short,
+        (Some(_), _) => name,
+    }
+}
+
+/// The name an API-format formula object is keyed by (see [`canonical_formula_name`]), for code
+/// that reads the JSON without deserializing a [`Formula`].
+pub fn qualified_formula_name(value: &Value) -> Option<String> {
+    let name = value.get("name").and_then(Value::as_str)?;
+    Some(match value.get("tap").and_then(Value::as_str) {
+        Some(tap) if tap != CORE_TAP && !name.contains('/') => format!("{tap}/{name}"),
+        _ => name.to_string(),
+    })
+}
+
+/// The Cellar and opt directory name of the formula keyed `name`: the short name for core
+/// formulae, `user--repo--name` for tapped ones.
+pub fn keg_dir_name(name: &str) -> String {
+    match split_tap_name(name) {
+        (None, short) => short.to_string(),
+        (Some(_), _) => name.replace('/', KEG_DIR_SEPARATOR),
+    }
+}
+
+/// The formula name a Cellar directory belongs to; the inverse of [`keg_dir_name`]. GitHub user
+/// names can't contain `--` and formula names don't, so the split is unambiguous.
+pub fn formula_name_from_keg_dir(dir: &str) -> String {
+    let Some((rest, short)) = dir.rsplit_once(KEG_DIR_SEPARATOR) else {
+        return dir.to_string();
+    };
+    match rest.split_once(KEG_DIR_SEPARATOR) {
+        Some((user, repo)) if !user.is_empty() && !repo.is_empty() && !short.is_empty() => {
+            format!("{user}/{repo}/{short}")
+        }
+        _ => dir.to_string(),
+    }
+}
+
 // --- Main Formula Struct ---
 // *** Added 'resources' field ***
-#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+// Serialization is implemented manually (see below) so that the output matches the API JSON
+// shape accepted by the custom `Deserialize` impl, allowing a lossless round-trip.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Formula {
     pub name: String,
     pub stable_version_str: String,
-    #[serde(rename = "versions")]
     pub version_semver: Version,
-    #[serde(default)]
+    pub head_version_str: Option<String>,
     pub revision: u32,
-    #[serde(default)]
     pub desc: Option<String>,
-    #[serde(default)]
     pub homepage: Option<String>,
-    #[serde(default)]
     pub url: String,
-    #[serde(default)]
     pub sha256: String,
-    #[serde(default)]
     pub mirrors: Vec<String>,
-    #[serde(default)]
     pub bottle: BottleSpec,
-    #[serde(skip_deserializing)]
     pub dependencies: Vec<Dependency>,
-    #[serde(default, deserialize_with = "deserialize_requirements")]
     pub requirements: Vec<Requirement>,
-    #[serde(skip_deserializing)] // Skip direct deserialization for this field
     pub resources: Vec<ResourceSpec>, // Stores parsed resources
-    #[serde(skip)]
+    pub caveats: Option<String>,
+    pub keg_only: bool,
+    pub keg_only_reason: Option<Value>,
+    pub conflicts_with: Vec<String>,
+    /// Upstream fields not modelled above, preserved verbatim.
+    pub extra: Map<String, Value>,
     pub install_keg_path: Option<PathBuf>,
 }
 
@@ -94,38 +171,48 @@ impl<'de> Deserialize<'de> for Formula {
         #[derive(Deserialize, Debug)]
         struct RawFormulaData {
             name: String,
-            #[serde(default)]
+            #[serde(default, deserialize_with = "lenient::null_as_default")]
             revision: u32,
             desc: Option<String>,
             homepage: Option<String>,
             versions: FormulaVersions,
-            #[serde(default)]
+            #[serde(default, deserialize_with = "lenient::null_as_default")]
             url: String,
-            #[serde(default)]
+            #[serde(default, deserialize_with = "lenient::null_as_default")]
             sha256: String,
-            #[serde(default)]
+            #[serde(default, deserialize_with = "lenient::or_default")]
             mirrors: Vec<String>,
-            #[serde(default)]
+            #[serde(default, deserialize_with = "lenient::or_default")]
             bottle: BottleSpec,
-            #[serde(default)]
-            dependencies: Vec<String>,
-            #[serde(default)]
-            build_dependencies: Vec<String>,
-            #[serde(default)]
-            test_dependencies: Vec<String>,
-            #[serde(default)]
-            recommended_dependencies: Vec<String>,
-            #[serde(default)]
-            optional_dependencies: Vec<String>,
+            #[serde(default, deserialize_with = "deserialize_dependency_list")]
+            dependencies: Vec<DependencyEntry>,
+            #[serde(default, deserialize_with = "deserialize_dependency_list")]
+            build_dependencies: Vec<DependencyEntry>,
+            #[serde(default, deserialize_with = "deserialize_dependency_list")]
+            test_dependencies: Vec<DependencyEntry>,
+            #[serde(default, deserialize_with = "deserialize_dependency_list")]
+            recommended_dependencies: Vec<DependencyEntry>,
+            #[serde(default, deserialize_with = "deserialize_dependency_list")]
+            optional_dependencies: Vec<DependencyEntry>,
             #[serde(default, deserialize_with = "deserialize_requirements")]
             requirements: Vec<Requirement>,
             #[serde(default)]
             resources: Vec<Value>, // Capture resources as generic Value first
             #[serde(default)]
             urls: Option<Value>,
+            #[serde(default, deserialize_with = "lenient::or_default")]
+            caveats: Option<String>,
+            #[serde(default, deserialize_with = "lenient::null_as_default")]
+            keg_only: bool,
+            #[serde(default)]
+            keg_only_reason: Option<Value>,
+            #[serde(default, deserialize_with = "lenient::or_default")]
+            conflicts_with: Vec<String>,
+            #[serde(flatten)]
+            extra: Map<String, Value>,
         }
 
-        let raw: RawFormulaData = RawFormulaData::deserialize(deserializer)?;
+        let mut raw: RawFormulaData = RawFormulaData::deserialize(deserializer)?;
 
         // --- Version Parsing (Original logic) ---
         let stable_version_str = raw
@@ -195,7 +282,7 @@ impl<'de> Deserialize<'de> for Formula {
         let mut final_url = raw.url;
         let mut final_sha256 = raw.sha256;
         if final_url.is_empty() {
-            if let Some(Value::Object(urls_map)) = raw.urls {
+            if let Some(Value::Object(urls_map)) = &raw.urls {
                 if let Some(Value::Object(stable_url_info)) = urls_map.get("stable") {
                     if let Some(Value::String(u)) = stable_url_info.get("url") {
                         final_url = u.clone();
@@ -212,15 +299,35 @@ impl<'de> Deserialize<'de> for Formula {
         if final_url.is_empty() && raw.versions.head.is_none() {
             debug!("Warning: Formula '{}' has no stable URL defined.", raw.name);
         }
+        // Keep the raw `urls` block so serialization can reproduce it.
+        if let Some(urls) = raw.urls.take() {
+            raw.extra.insert("urls".to_string(), urls);
+        }
 
         // --- Dependency Processing (Original logic) ---
         let mut combined_dependencies: Vec<Dependency> = Vec::new();
-        let mut seen_deps: HashMap<String, DependencyTag> = HashMap::new();
-        let mut process_list = |deps: &[String], tag: DependencyTag| {
-            for name in deps {
-                *seen_deps
-                    .entry(name.clone())
-                    .or_insert(DependencyTag::empty()) |= tag;
+        let mut seen_deps: HashMap<String, Dependency> = HashMap::new();
+        let mut process_list = |deps: &[DependencyEntry], tag: DependencyTag| {
+            for (spec, tag_names) in deps {
+                let dep = Dependency::from_spec(spec, tag);
+                let entry = seen_deps
+                    .entry(dep.name.clone())
+                    .or_insert_with(|| Dependency::new_with_tags(dep.name, DependencyTag::empty()));
+                entry.tags |= tag;
+                if dep.min_version.is_some() {
+                    entry.min_version = dep.min_version;
+                }
+                for name in tag_names {
+                    match DependencyTag::from_api_name(name) {
+                        Some(known) => entry.tags |= known,
+                        None => {
+                            lenient::note_once("dependency tags", format!("unknown tag '{name}'"));
+                            if !entry.other_tags.contains(name) {
+                                entry.other_tags.push(name.clone());
+                            }
+                        }
+                    }
+                }
             }
         };
         process_list(&raw.dependencies, DependencyTag::RUNTIME);
@@ -234,9 +341,9 @@ impl<'de> Deserialize<'de> for Formula {
             &raw.optional_dependencies,
             DependencyTag::OPTIONAL | DependencyTag::RUNTIME,
         );
-        for (name, tags) in seen_deps {
-            combined_dependencies.push(Dependency::new_with_tags(name, tags));
-        }
+        combined_dependencies.extend(seen_deps.into_values());
+        // HashMap iteration order is random; keep the result deterministic.
+        combined_dependencies.sort_by(|a, b| a.name.cmp(&b.name));
 
         // --- Resource Processing ---
         // *** Added parsing logic for the 'resources' field ***
@@ -294,10 +401,20 @@ impl<'de> Deserialize<'de> for Formula {
             }
         }
 
+        // Tapped formulae are keyed by their full name, so they never collide with a core formula
+        // of the same short name.
+        let name = match raw.extra.get("tap").and_then(Value::as_str) {
+            Some(tap) if tap != CORE_TAP && !raw.name.contains('/') => {
+                format!("{tap}/{}", raw.name)
+            }
+            _ => raw.name,
+        };
+
         Ok(Self {
-            name: raw.name,
+            name,
             stable_version_str,
             version_semver,
+            head_version_str: raw.versions.head,
             revision: raw.revision,
             desc: raw.desc,
             homepage: raw.homepage,
@@ -308,16 +425,95 @@ impl<'de> Deserialize<'de> for Formula {
             dependencies: combined_dependencies,
             requirements: raw.requirements,
             resources: combined_resources, // Assign parsed resources
+            caveats: raw.caveats,
+            keg_only: raw.keg_only,
+            keg_only_reason: raw.keg_only_reason,
+            conflicts_with: raw.conflicts_with,
+            extra: raw.extra,
             install_keg_path: None,
         })
     }
 }
 
+// Custom serialization logic for Formula, mirroring the API JSON shape read above.
+impl Serialize for Formula {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        // Dependencies carrying tags we don't model are written in the object form they were
+        // read from, so the tags are not lost.
+        let names_with = |include: DependencyTag, exclude: DependencyTag| -> Vec<Value> {
+            self.dependencies
+                .iter()
+                .filter(|d| d.tags.intersects(include) && !d.tags.intersects(exclude))
+                .map(|d| match d.other_tags.as_slice() {
+                    [] => Value::String(d.spec()),
+                    tags => serde_json::json!({ (d.spec()): tags }),
+                })
+                .collect()
+        };
+        let runtime = names_with(
+            DependencyTag::RUNTIME,
+            DependencyTag::OPTIONAL | DependencyTag::RECOMMENDED,
+        );
+        let build = names_with(DependencyTag::BUILD, DependencyTag::empty());
+        let test = names_with(DependencyTag::TEST, DependencyTag::empty());
+        let recommended = names_with(DependencyTag::RECOMMENDED, DependencyTag::empty());
+        let optional = names_with(DependencyTag::OPTIONAL, DependencyTag::empty());
+
+        let versions = FormulaVersions {
+            stable: Some(self.stable_version_str.clone()),
+            head: self.head_version_str.clone(),
+            bottle: self.bottle.stable.is_some(),
+        };
+        let resources: Vec<Value> = self
+            .resources
+            .iter()
+            .map(|r| {
+                let mut entry = Map::new();
+                entry.insert(
+                    r.name.clone(),
+                    serde_json::json!({ "url": r.url, "sha256": r.sha256 }),
+                );
+                Value::Object(entry)
+            })
+            .collect();
+
+        let mut map = serializer.serialize_map(None)?;
+        map.serialize_entry("name", &self.name)?;
+        map.serialize_entry("revision", &self.revision)?;
+        map.serialize_entry("desc", &self.desc)?;
+        map.serialize_entry("homepage", &self.homepage)?;
+        map.serialize_entry("versions", &versions)?;
+        map.serialize_entry("url", &self.url)?;
+        map.serialize_entry("sha256", &self.sha256)?;
+        map.serialize_entry("mirrors", &self.mirrors)?;
+        map.serialize_entry("bottle", &self.bottle)?;
+        map.serialize_entry("dependencies", &runtime)?;
+        map.serialize_entry("build_dependencies", &build)?;
+        map.serialize_entry("test_dependencies", &test)?;
+        map.serialize_entry("recommended_dependencies", &recommended)?;
+        map.serialize_entry("optional_dependencies", &optional)?;
+        map.serialize_entry("requirements", &self.requirements)?;
+        map.serialize_entry("resources", &resources)?;
+        map.serialize_entry("caveats", &self.caveats)?;
+        map.serialize_entry("keg_only", &self.keg_only)?;
+        map.serialize_entry("keg_only_reason", &self.keg_only_reason)?;
+        map.serialize_entry("conflicts_with", &self.conflicts_with)?;
+        for (key, value) in &self.extra {
+            map.serialize_entry(key, value)?;
+        }
+        map.end()
+    }
+}
+
 // --- Formula impl Methods ---
 impl Formula {
-    // dependencies() and requirements() are unchanged
-    pub fn dependencies(&self) -> Result<Vec<Dependency>> {
-        Ok(self.dependencies.clone())
+    /// The dependency list parsed at deserialization time, borrowed; planners call this for
+    /// every node several times, so it must not allocate.
+    pub fn dependencies(&self) -> &[Dependency] {
+        &self.dependencies
     }
     pub fn requirements(&self) -> Result<Vec<Requirement>> {
         Ok(self.requirements.clone())
@@ -332,6 +528,10 @@ impl Formula {
     pub fn set_keg_path(&mut self, path: PathBuf) {
         self.install_keg_path = Some(path);
     }
+    /// The version with its revision, parsed for comparison against kegs and other formulae.
+    pub fn pkg_version(&self) -> PkgVersion {
+        PkgVersion::parse(&self.stable_version_str).with_revision(self.revision)
+    }
     pub fn version_str_full(&self) -> String {
         if self.revision > 0 {
             format!("{}_{}", self.stable_version_str, self.revision)
@@ -342,6 +542,81 @@ impl Formula {
     pub fn name(&self) -> &str {
         &self.name
     }
+    /// The name without its tap, as used for links and bottle contents.
+    pub fn short_name(&self) -> &str {
+        split_tap_name(&self.name).1
+    }
+    /// The tap of a formula from outside the core tap.
+    pub fn tap(&self) -> Option<&str> {
+        split_tap_name(&self.name).0
+    }
+    /// The Cellar and opt directory name (see [`keg_dir_name`]).
+    pub fn keg_dir_name(&self) -> String {
+        keg_dir_name(&self.name)
+    }
+    /// The git repository of `urls.head`, if the formula has one. Only git heads are supported.
+    pub fn head_spec(&self) -> Option<HeadSpec> {
+        let head = self.extra.get("urls")?.get("head")?;
+        let url = head.get("url").and_then(Value::as_str)?;
+        match head.get("using").and_then(Value::as_str) {
+            None | Some("git") => {}
+            Some(_) => return None,
+        }
+        Some(HeadSpec {
+            url: url.to_string(),
+            branch: head
+                .get("branch")
+                .and_then(Value::as_str)
+                .map(str::to_string),
+        })
+    }
+    /// A copy of this formula versioned `HEAD-<short_commit>`, the keg name of a `--HEAD` build.
+    pub fn for_head(&self, short_commit: &str) -> Formula {
+        let mut formula = self.clone();
+        formula.stable_version_str = format!("{HEAD_VERSION_PREFIX}{short_commit}");
+        formula.revision = 0;
+        formula.install_keg_path = None;
+        formula
+    }
+    /// The formula as it installs on `platform` (a bottle-style tag such as `arm64_sonoma`): the
+    /// fields listed in the matching entry of `variations` (dependencies, requirements, caveats
+    /// and so on) replace the defaults. Formulae without one are returned unchanged.
+    pub fn for_platform(&self, platform: &str) -> Formula {
+        let Some(variation) = self
+            .extra
+            .get("variations")
+            .and_then(|v| v.get(platform))
+            .and_then(Value::as_object)
+        else {
+            return self.clone();
+        };
+        // The JSON round-trip is lossless, so the variation is applied in the API's own shape.
+        let mut value = match serde_json::to_value(self) {
+            Ok(Value::Object(map)) => map,
+            _ => return self.clone(),
+        };
+        for (key, field) in variation {
+            value.insert(key.clone(), field.clone());
+        }
+        match serde_json::from_value::<Formula>(Value::Object(value)) {
+            Ok(mut formula) => {
+                debug!("Applied the {} variation of {}", platform, self.name);
+                formula.install_keg_path = self.install_keg_path.clone();
+                formula
+            }
+            Err(e) => {
+                debug!(
+                    "Ignoring the {} variation of {}: {}",
+                    platform, self.name, e
+                );
+                self.clone()
+            }
+        }
+    }
+    /// Whether this is a `--HEAD` build (see [`Formula::for_head`]).
+    pub fn is_head(&self) -> bool {
+        self.stable_version_str.starts_with(HEAD_VERSION_PREFIX)
+    }
     pub fn version(&self) -> &Version {
         &self.version_semver
     }
@@ -354,6 +629,89 @@ impl Formula {
     pub fn get_bottle_spec(&self, bottle_tag: &str) -> Option<&BottleFileSpec> {
         self.bottle.stable.as_ref()?.files.get(bottle_tag)
     }
+    pub fn lifecycle(&self) -> FormulaLifecycle {
+        FormulaLifecycle::from_json(&self.name, &self.extra)
+    }
+    /// Alternative names the formula answers to (`aliases`, `oldnames`, legacy `oldname`),
+    /// each of which gets its own opt link.
+    pub fn alias_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = ["aliases", "oldnames"]
+            .iter()
+            .filter_map(|key| self.extra.get(*key).and_then(Value::as_array))
+            .flatten()
+            .chain(self.extra.get("oldname"))
+            .filter_map(Value::as_str)
+            .filter(|n| *n != self.short_name() && !n.is_empty() && !n.contains('/'))
+            .map(str::to_string)
+            .collect();
+        names.sort();
+        names.dedup();
+        names
+    }
+}
+
+// --- Deprecation / Disable Status ---
+/// Whether the API marks a formula as deprecated or disabled, with a ready-to-print message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FormulaLifecycle {
+    Active,
+    Deprecated(String),
+    Disabled(String),
+}
+
+impl FormulaLifecycle {
+    /// Reads the `deprecated`/`disabled` fields of an API formula object.
+    pub fn from_json(name: &str, obj: &Map<String, Value>) -> Self {
+        let field = |key: &str| {
+            obj.get(key)
+                .and_then(Value::as_str)
+                .filter(|s| !s.is_empty())
+        };
+        let flag = |key: &str| obj.get(key).and_then(Value::as_bool).unwrap_or(false);
+        let describe = |verb: &str, prefix: &str| {
+            let mut msg = match field(&format!("{prefix}_date")) {
+                Some(date) => format!("{name} was {verb} on {date}"),
+                None => format!("{name} has been {verb}"),
+            };
+            if let Some(reason) = field(&format!("{prefix}_reason")) {
+                msg.push_str(" because ");
+                msg.push_str(&reason_phrase(reason));
+            }
+            let replacement = field(&format!("{prefix}_replacement"))
+                .or_else(|| field(&format!("{prefix}_replacement_formula")))
+                .or_else(|| field(&format!("{prefix}_replacement_cask")));
+            if let Some(replacement) = replacement {
+                msg.push_str(&format!("; use {replacement} instead"));
+            }
+            msg
+        };
+        if flag("disabled") {
+            Self::Disabled(describe("disabled", "disable"))
+        } else if flag("deprecated") {
+            Self::Deprecated(describe("deprecated", "deprecation"))
+        } else {
+            Self::Active
+        }
+    }
+}
+
+/// Homebrew's reason symbols as the sentence fragments `brew` prints; free-form reasons are used
+/// verbatim.
+fn reason_phrase(reason: &str) -> String {
+    let phrase = match reason {
+        "does_not_build" => "it does not build",
+        "fails_gatekeeper_check" => "it does not pass the macOS Gatekeeper check",
+        "no_license" => "it has no license",
+        "repo_archived" => "its upstream repository has been archived",
+        "repo_removed" => "its upstream repository has been removed",
+        "unmaintained" => "it is not maintained upstream",
+        "unsupported" => "it is not supported upstream",
+        "deprecated_upstream" => "it is deprecated upstream",
+        "versioned_formula" => "it is a versioned formula",
+        "checksum_mismatch" => "its source checksum changed after release",
+        other => return other.replace('_', " "),
+    };
+    phrase.to_string()
 }
 
 // --- BuildEnvironment Dependency Interface (Unchanged) ---
@@ -370,7 +728,7 @@ impl FormulaDependencies for Formula {
     }
     fn install_prefix(&self, cellar_path: &Path) -> Result<PathBuf> {
         let version_string = self.version_str_full();
-        Ok(cellar_path.join(self.name()).join(version_string))
+        Ok(cellar_path.join(self.keg_dir_name()).join(version_string))
     }
     fn resolved_runtime_dependency_paths(&self) -> Result<Vec<PathBuf>> {
         Ok(Vec::new())
@@ -385,6 +743,42 @@ impl FormulaDependencies for Formula {
 
 // --- Deserialization Helpers ---
 // deserialize_requirements remains unchanged
+/// A dependency list entry: the spec (name, optionally with a `>= version` bound) and any tags
+/// given alongside it.
+type DependencyEntry = (String, Vec<String>);
+
+/// Reads a dependency list whose entries are names, or objects mapping a name to one or more tags
+/// (`{"python@3.12": ["build", "test"]}`). Entries of any other shape are skipped.
+fn deserialize_dependency_list<'de, D>(
+    deserializer: D,
+) -> std::result::Result<Vec<DependencyEntry>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let raw = Option::<Vec<Value>>::deserialize(deserializer)?.unwrap_or_default();
+    let mut entries = Vec::with_capacity(raw.len());
+    for value in raw {
+        match value {
+            Value::String(spec) => entries.push((spec, Vec::new())),
+            Value::Object(map) => {
+                for (spec, tags) in map {
+                    let tags = match tags {
+                        Value::String(tag) => vec![tag],
+                        Value::Array(list) => list
+                            .into_iter()
+                            .filter_map(|t| t.as_str().map(str::to_string))
+                            .collect(),
+                        _ => Vec::new(),
+                    };
+                    entries.push((spec, tags));
+                }
+            }
+            other => lenient::note_once("dependency lists", format!("unexpected entry {other}")),
+        }
+    }
+    Ok(entries)
+}
+
 fn deserialize_requirements<'de, D>(
     deserializer: D,
 ) -> std::result::Result<Vec<Requirement>, D::Error>
@@ -405,7 +799,10 @@ where
     let raw_reqs: Vec<Value> = Deserialize::deserialize(deserializer)?;
     let mut requirements = Vec::new();
     for req_val in raw_reqs {
-        if let Ok(req_obj) = serde_json::from_value::<ReqWrapper>(req_val.clone()) {
+        // Already in our own serialized form (e.g. `{"MacOS": "12"}`)
+        if let Ok(req) = serde_json::from_value::<Requirement>(req_val.clone()) {
+            requirements.push(req);
+        } else if let Ok(req_obj) = serde_json::from_value::<ReqWrapper>(req_val.clone()) {
             match req_obj.name.as_str() {
                 "macos" => {
                     requirements.push(Requirement::MacOS(
@@ -474,3 +871,79 @@ impl<'de> Deserialize<'de> for ResourceSpec {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn qualified_names_split_into_tap_and_short_name() {
+        assert_eq!(split_tap_name("jq"), (None, "jq"));
+        assert_eq!(split_tap_name("homebrew/core/jq"), (None, "jq"));
+        assert_eq!(
+            split_tap_name("mycorp/tools/jq"),
+            (Some("mycorp/tools"), "jq")
+        );
+        // Not a tap: too few or too many segments.
+        assert_eq!(split_tap_name("tools/jq"), (None, "tools/jq"));
+        assert_eq!(split_tap_name("a/b/c/jq"), (None, "a/b/c/jq"));
+        assert_eq!(canonical_formula_name("homebrew/core/jq"), "jq");
+        assert_eq!(canonical_formula_name("mycorp/tools/jq"), "mycorp/tools/jq");
+    }
+
+    #[test]
+    fn keg_directory_names_round_trip() {
+        for (name, dir) in [
+            ("jq", "jq"),
+            ("mycorp/tools/jq", "mycorp--tools--jq"),
+            ("my-corp/home-brew/jq", "my-corp--home-brew--jq"),
+        ] {
+            assert_eq!(keg_dir_name(name), dir);
+            assert_eq!(formula_name_from_keg_dir(dir), name);
+        }
+        assert_eq!(formula_name_from_keg_dir("--x"), "--x");
+        assert_eq!(formula_name_from_keg_dir("a--b"), "a--b");
+    }
+
+    #[test]
+    fn a_tapped_formula_is_keyed_by_its_full_name() {
+        let value = json!({
+            "name": "jq",
+            "tap": "mycorp/tools",
+            "versions": { "stable": "2.0" },
+            "aliases": ["jq"],
+        });
+        let formula: Formula = serde_json::from_value(value.clone()).unwrap();
+        let core: Formula = serde_json::from_value(json!({
+            "name": "jq",
+            "tap": "homebrew/core",
+            "versions": { "stable": "1.7" },
+        }))
+        .unwrap();
+
+        assert_eq!(formula.name(), "mycorp/tools/jq");
+        assert_eq!(formula.short_name(), "jq");
+        assert_eq!(formula.tap(), Some("mycorp/tools"));
+        assert_eq!(formula.keg_dir_name(), "mycorp--tools--jq");
+        assert_eq!(
+            qualified_formula_name(&value).as_deref(),
+            Some("mycorp/tools/jq")
+        );
+        assert!(
+            formula.alias_names().is_empty(),
+            "its short name is not an alias of itself"
+        );
+        assert_eq!(core.name(), "jq");
+        assert_eq!(core.tap(), None);
+        assert_eq!(
+            core.install_prefix(Path::new("/c")).unwrap(),
+            Path::new("/c/jq/1.7")
+        );
+        assert_eq!(
+            formula.install_prefix(Path::new("/c")).unwrap(),
+            Path::new("/c/mycorp--tools--jq/2.0")
+        );
+    }
+}
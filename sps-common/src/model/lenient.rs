@@ -0,0 +1,71 @@
+// sps-common/src/model/lenient.rs
+//! Deserialization helpers that let one odd field degrade to its default instead of failing the
+//! whole formula or cask. The Homebrew API grows fields and new value shapes without notice; a
+//! package whose `depends_on` we cannot read is still worth listing and usually installable.
+//!
+//! Each kind of surprise is logged once per process at debug level.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt::Display;
+use std::sync::Mutex;
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Deserializer};
+use serde_json::Value;
+use tracing::debug;
+
+/// Logs `detail` the first time something is reported under `what`.
+pub fn note_once(what: &str, detail: impl Display) {
+    static SEEN: Mutex<Option<HashSet<String>>> = Mutex::new(None);
+    let mut seen = SEEN.lock().unwrap_or_else(|e| e.into_inner());
+    if seen
+        .get_or_insert_with(HashSet::new)
+        .insert(what.to_string())
+    {
+        debug!(
+            "API data for {} did not match the expected shape ({}); using a fallback. Further occurrences are not logged.",
+            what, detail
+        );
+    }
+}
+
+/// `null` reads as the type's default, like a missing field does with `#[serde(default)]`.
+pub fn null_as_default<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de> + Default,
+{
+    Ok(Option::<T>::deserialize(deserializer)?.unwrap_or_default())
+}
+
+/// Falls back to the default value if the field doesn't have the expected shape.
+pub fn or_default<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: DeserializeOwned + Default,
+{
+    let value = Value::deserialize(deserializer)?;
+    Ok(serde_json::from_value(value).unwrap_or_else(|e| {
+        note_once(std::any::type_name::<T>(), e);
+        T::default()
+    }))
+}
+
+/// A map whose unreadable entries are dropped instead of failing the whole map.
+pub fn map_skipping_invalid<'de, D, T>(deserializer: D) -> Result<HashMap<String, T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: DeserializeOwned,
+{
+    let raw = Option::<HashMap<String, Value>>::deserialize(deserializer)?.unwrap_or_default();
+    Ok(raw
+        .into_iter()
+        .filter_map(|(key, value)| match serde_json::from_value(value) {
+            Ok(parsed) => Some((key, parsed)),
+            Err(e) => {
+                note_once(std::any::type_name::<T>(), format!("entry '{key}': {e}"));
+                None
+            }
+        })
+        .collect())
+}
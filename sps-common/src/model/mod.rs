@@ -3,12 +3,17 @@
 use std::sync::Arc;
 
 pub mod cask;
+pub mod cask_version;
 pub mod formula;
+pub mod lenient;
+pub mod pkg_version;
 pub mod version;
 
 // Re-export
 pub use cask::Cask;
+pub use cask_version::CaskVersion;
 pub use formula::Formula;
+pub use pkg_version::PkgVersion;
 
 #[derive(Debug, Clone)]
 pub enum InstallTargetIdentifier {
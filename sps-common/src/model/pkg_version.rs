@@ -0,0 +1,543 @@
+// sps-common/src/model/pkg_version.rs
+//! Formula versions compared the way Homebrew compares them. They aren't semver: `1.0b` and
+//! `2.3.4.5` are valid, pre-releases (`1.0rc1`, `2.0-beta2`) come before their release and patch
+//! levels (`9.3p1`) after it, and a keg directory adds the formula revision (`1.2.3_1`). On top
+//! of that a version may carry an epoch (`1:2.3`, compared first) and a bottle rebuild number
+//! (compared last, never part of the string).
+//!
+//! The version is split into numbers and words, compared pairwise with the shorter one padded,
+//! so `1.0` equals `1.0.0`:
+//! - numbers by value, and above any word;
+//! - `alpha`/`aN` < `beta`/`bN` < `pre` < `rc` < nothing, hence `1.0rc1` < `1.0`;
+//! - nothing < `p`/`patch` < `post` < other words, hence `1.0` < `1.0p1` < `1.0a`;
+//! - two of the same kind by their number, other words alphabetically;
+//! - a `0` facing a word is skipped, so `2.1.0-p194` < `2.1-p195`.
+//!
+//! `HEAD` builds (`HEAD`, `HEAD-<commit>`) are newer than any release and equal to each other.
+
+use std::cmp::Ordering;
+use std::convert::Infallible;
+use std::fmt;
+use std::str::FromStr;
+
+use super::formula::HEAD_VERSION_PREFIX;
+
+/// A parsed version: `[epoch:]version[_revision]`, plus a bottle rebuild number.
+#[derive(Debug, Clone)]
+pub struct PkgVersion {
+    epoch: u64,
+    version: String,
+    revision: u32,
+    rebuild: u32,
+    tokens: Vec<Token>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Number(u64),
+    Alpha(u64),
+    Beta(u64),
+    Pre(u64),
+    Rc(u64),
+    Patch(u64),
+    Post(u64),
+    Word(String),
+}
+
+impl Token {
+    /// Order between kinds; numbers rank above everything and are handled separately.
+    fn rank(&self) -> u8 {
+        match self {
+            Self::Alpha(_) => 0,
+            Self::Beta(_) => 1,
+            Self::Pre(_) => 2,
+            Self::Rc(_) => 3,
+            Self::Patch(_) => 4,
+            Self::Post(_) => 5,
+            Self::Word(_) => 6,
+            Self::Number(_) => 7,
+        }
+    }
+
+    fn number(&self) -> u64 {
+        match self {
+            Self::Number(n)
+            | Self::Alpha(n)
+            | Self::Beta(n)
+            | Self::Pre(n)
+            | Self::Rc(n)
+            | Self::Patch(n)
+            | Self::Post(n) => *n,
+            Self::Word(_) => 0,
+        }
+    }
+
+    /// How this token compares to a missing one in the other version.
+    fn cmp_missing(&self) -> Ordering {
+        match self {
+            Self::Number(n) => n.cmp(&0),
+            Self::Alpha(_) | Self::Beta(_) | Self::Pre(_) | Self::Rc(_) => Ordering::Less,
+            Self::Patch(_) | Self::Post(_) | Self::Word(_) => Ordering::Greater,
+        }
+    }
+
+    fn cmp_token(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (Self::Word(a), Self::Word(b)) => a.cmp(b),
+            _ => self
+                .rank()
+                .cmp(&other.rank())
+                .then_with(|| self.number().cmp(&other.number())),
+        }
+    }
+}
+
+impl PkgVersion {
+    /// Parses `[epoch:]version[_revision]`, e.g. a keg directory name. Never fails; text that
+    /// doesn't look like a version still orders consistently.
+    pub fn parse(s: &str) -> Self {
+        let s = s.trim();
+        let (epoch, rest) = match s.split_once(':') {
+            Some((epoch, rest))
+                if !epoch.is_empty() && epoch.bytes().all(|b| b.is_ascii_digit()) =>
+            {
+                (epoch.parse().unwrap_or(u64::MAX), rest)
+            }
+            _ => (0, s),
+        };
+        let (version, revision) = match rest.rsplit_once('_') {
+            Some((version, revision))
+                if !version.is_empty()
+                    && !revision.is_empty()
+                    && revision.bytes().all(|b| b.is_ascii_digit()) =>
+            {
+                (version, revision.parse().unwrap_or(u32::MAX))
+            }
+            _ => (rest, 0),
+        };
+        Self {
+            epoch,
+            version: version.to_string(),
+            revision,
+            rebuild: 0,
+            tokens: tokenize(version),
+        }
+    }
+
+    /// This version with the formula revision set, e.g. a formula's stable version plus its
+    /// `revision`.
+    pub fn with_revision(mut self, revision: u32) -> Self {
+        self.revision = revision;
+        self
+    }
+
+    /// This version with a bottle rebuild number, which orders bottles of the same version.
+    pub fn with_rebuild(mut self, rebuild: u32) -> Self {
+        self.rebuild = rebuild;
+        self
+    }
+
+    /// The version without epoch and revision.
+    pub fn version(&self) -> &str {
+        &self.version
+    }
+
+    pub fn epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    pub fn revision(&self) -> u32 {
+        self.revision
+    }
+
+    pub fn rebuild(&self) -> u32 {
+        self.rebuild
+    }
+
+    /// For people: the revision spelled out, `1.2.3 (revision 1)`, rather than the keg
+    /// directory's `1.2.3_1`.
+    pub fn describe(&self) -> String {
+        let mut text = if self.epoch > 0 {
+            format!("{}:{}", self.epoch, self.version)
+        } else {
+            self.version.clone()
+        };
+        if self.revision > 0 {
+            text.push_str(&format!(" (revision {})", self.revision));
+        }
+        text
+    }
+
+    /// Whether this is a `--HEAD` build.
+    pub fn is_head(&self) -> bool {
+        self.version == "HEAD" || self.version.starts_with(HEAD_VERSION_PREFIX)
+    }
+
+    /// The leading number (`3` for `3.2.1`), if the version starts with one.
+    pub fn major(&self) -> Option<u64> {
+        match self.tokens.first() {
+            Some(Token::Number(n)) if !self.is_head() => Some(*n),
+            _ => None,
+        }
+    }
+
+    /// Whether a version named by a user selects this one: they are equal, or `wanted` has no
+    /// revision and equals this version without its revision (`1.2` selects `1.2_1`).
+    pub fn matches(&self, wanted: &Self) -> bool {
+        if wanted.revision == 0 {
+            self.epoch == wanted.epoch && self.cmp_version(wanted) == Ordering::Equal
+        } else {
+            self == wanted
+        }
+    }
+
+    fn cmp_version(&self, other: &Self) -> Ordering {
+        match (self.is_head(), other.is_head()) {
+            (true, true) => return Ordering::Equal,
+            (true, false) => return Ordering::Greater,
+            (false, true) => return Ordering::Less,
+            (false, false) => {}
+        }
+        let (mut i, mut j) = (0, 0);
+        loop {
+            let ord = match (self.tokens.get(i), other.tokens.get(j)) {
+                (None, None) => return Ordering::Equal,
+                // A zero facing a word is padding (`2.1.0-p194` against `2.1-p195`) and skipped.
+                (Some(Token::Number(0)), Some(b)) if !matches!(b, Token::Number(_)) => {
+                    i += 1;
+                    continue;
+                }
+                (Some(a), Some(Token::Number(0))) if !matches!(a, Token::Number(_)) => {
+                    j += 1;
+                    continue;
+                }
+                (Some(a), Some(b)) => a.cmp_token(b),
+                (Some(a), None) => a.cmp_missing(),
+                (None, Some(b)) => b.cmp_missing().reverse(),
+            };
+            if ord != Ordering::Equal {
+                return ord;
+            }
+            i += 1;
+            j += 1;
+        }
+    }
+}
+
+/// Splits a version into numbers and words, folding a pre-release or patch word and the number
+/// after it into one token.
+fn tokenize(version: &str) -> Vec<Token> {
+    // (is_number, text, preceded by a separator)
+    let mut runs: Vec<(bool, String, bool)> = Vec::new();
+    let mut separated = false;
+    for c in version.chars() {
+        if !c.is_ascii_alphanumeric() {
+            separated = true;
+            continue;
+        }
+        let digit = c.is_ascii_digit();
+        let c = c.to_ascii_lowercase();
+        match runs.last_mut() {
+            Some((is_number, text, _)) if !separated && *is_number == digit => text.push(c),
+            _ => runs.push((digit, c.to_string(), separated)),
+        }
+        separated = false;
+    }
+
+    let number = |text: &str| text.parse::<u64>().unwrap_or(u64::MAX);
+    let mut tokens = Vec::new();
+    let mut runs = runs.into_iter().peekable();
+    while let Some((is_number, text, _)) = runs.next() {
+        if is_number {
+            tokens.push(Token::Number(number(&text)));
+            continue;
+        }
+        // Spelled-out kinds may be followed by `.`, `-` or `_` before their number; the short
+        // `a`/`b` only count when the number follows directly (`1.0b2`, not `1.0b`).
+        let (kind, needs_number): (fn(u64) -> Token, bool) = match text.as_str() {
+            "alpha" => (Token::Alpha, false),
+            "a" => (Token::Alpha, true),
+            "beta" => (Token::Beta, false),
+            "b" => (Token::Beta, true),
+            "pre" => (Token::Pre, false),
+            "rc" => (Token::Rc, false),
+            "p" | "pl" | "patch" => (Token::Patch, false),
+            "post" => (Token::Post, false),
+            _ => {
+                tokens.push(Token::Word(text));
+                continue;
+            }
+        };
+        let next = runs
+            .next_if(|(next_number, _, next_separated)| {
+                *next_number && !(needs_number && *next_separated)
+            })
+            .map(|(_, n, _)| number(&n));
+        match next {
+            Some(n) => tokens.push(kind(n)),
+            None if needs_number => tokens.push(Token::Word(text)),
+            None => tokens.push(kind(0)),
+        }
+    }
+    tokens
+}
+
+impl Ord for PkgVersion {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.epoch
+            .cmp(&other.epoch)
+            .then_with(|| self.cmp_version(other))
+            .then_with(|| self.revision.cmp(&other.revision))
+            .then_with(|| self.rebuild.cmp(&other.rebuild))
+    }
+}
+
+impl PartialOrd for PkgVersion {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for PkgVersion {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for PkgVersion {}
+
+impl FromStr for PkgVersion {
+    type Err = Infallible;
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        Ok(Self::parse(s))
+    }
+}
+
+/// `[epoch:]version[_revision]`, as in keg directory names; the rebuild number is not shown.
+impl fmt::Display for PkgVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.epoch > 0 {
+            write!(f, "{}:", self.epoch)?;
+        }
+        f.write_str(&self.version)?;
+        if self.revision > 0 {
+            write!(f, "_{}", self.revision)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn v(s: &str) -> PkgVersion {
+        PkgVersion::parse(s)
+    }
+
+    /// Asserts each pair compares as given, and the reverse pair the other way round.
+    fn assert_ordered(cases: &[(&str, Ordering, &str)]) {
+        for (a, expected, b) in cases {
+            assert_eq!(v(a).cmp(&v(b)), *expected, "{a} vs {b}");
+            assert_eq!(v(b).cmp(&v(a)), expected.reverse(), "{b} vs {a}");
+        }
+    }
+
+    // The cases below are ported from Homebrew's test/version_spec.rb.
+
+    #[test]
+    fn compares_plain_versions() {
+        use Ordering::*;
+        assert_ordered(&[
+            ("0.1", Equal, "0.1.0"),
+            ("0.1", Less, "0.2"),
+            ("1.2.3", Greater, "1.2.2"),
+            ("1.2.4", Less, "1.2.4.1"),
+            ("1.2.10", Greater, "1.2.9"),
+            ("1", Greater, "0.9"),
+        ]);
+    }
+
+    #[test]
+    fn compares_alpha_versions() {
+        use Ordering::*;
+        assert_ordered(&[
+            ("1.2.3alpha", Less, "1.2.3"),
+            ("1.2.3", Less, "1.2.3a"),
+            ("1.2.3alpha4", Equal, "1.2.3a4"),
+            ("1.2.3alpha4", Equal, "1.2.3A4"),
+            ("1.2.3alpha4", Greater, "1.2.3alpha3"),
+            ("1.2.3alpha4", Less, "1.2.3alpha5"),
+            ("1.2.3alpha4", Less, "1.2.3alpha10"),
+            ("1.2.3alpha4", Less, "1.2.3beta2"),
+            ("1.2.3alpha4", Less, "1.2.3rc3"),
+            ("1.2.3alpha4", Less, "1.2.3"),
+            ("1.2.3alpha4", Less, "1.2.3-p34"),
+        ]);
+    }
+
+    #[test]
+    fn compares_beta_versions() {
+        use Ordering::*;
+        assert_ordered(&[
+            ("1.2.3beta2", Equal, "1.2.3b2"),
+            ("1.2.3beta2", Equal, "1.2.3B2"),
+            ("1.2.3beta2", Greater, "1.2.3beta1"),
+            ("1.2.3beta2", Less, "1.2.3beta3"),
+            ("1.2.3beta2", Less, "1.2.3beta10"),
+            ("1.2.3beta2", Greater, "1.2.3alpha4"),
+            ("1.2.3beta2", Less, "1.2.3rc3"),
+            ("1.2.3beta2", Less, "1.2.3"),
+            ("1.2.3beta2", Less, "1.2.3-p34"),
+        ]);
+    }
+
+    #[test]
+    fn compares_pre_versions() {
+        use Ordering::*;
+        assert_ordered(&[
+            ("1.2.3pre9", Equal, "1.2.3PRE9"),
+            ("1.2.3pre9", Greater, "1.2.3pre8"),
+            ("1.2.3pre9", Less, "1.2.3pre10"),
+            ("1.2.3pre9", Greater, "1.2.3beta2"),
+            ("1.2.3pre9", Greater, "1.2.3alpha4"),
+            ("1.2.3pre9", Less, "1.2.3rc2"),
+            ("1.2.3pre9", Less, "1.2.3"),
+            ("1.2.3pre9", Less, "1.2.3-p34"),
+        ]);
+    }
+
+    #[test]
+    fn compares_rc_versions() {
+        use Ordering::*;
+        assert_ordered(&[
+            ("1.2.3rc3", Equal, "1.2.3RC3"),
+            ("1.2.3rc3", Greater, "1.2.3rc2"),
+            ("1.2.3rc3", Less, "1.2.3rc4"),
+            ("1.2.3rc3", Less, "1.2.3rc10"),
+            ("1.2.3rc3", Greater, "1.2.3pre9"),
+            ("1.2.3rc3", Greater, "1.2.3beta2"),
+            ("1.2.3rc3", Greater, "1.2.3alpha4"),
+            ("1.2.3rc3", Less, "1.2.3"),
+            ("1.2.3rc3", Less, "1.2.3-p34"),
+        ]);
+    }
+
+    #[test]
+    fn compares_patch_level_versions() {
+        use Ordering::*;
+        assert_ordered(&[
+            ("1.2.3-p34", Equal, "1.2.3-P34"),
+            ("1.2.3-p34", Greater, "1.2.3-p33"),
+            ("1.2.3-p34", Less, "1.2.3-p35"),
+            ("1.2.3-p34", Greater, "1.2.3-p9"),
+            ("1.2.3-p34", Greater, "1.2.3rc3"),
+            ("1.2.3-p34", Greater, "1.2.3"),
+            ("1.2.3-p34", Less, "1.2.4"),
+            // `p`, `pl` and `patch` are the same kind.
+            ("9.3p1", Equal, "9.3pl1"),
+            ("9.3p1", Equal, "9.3-patch1"),
+            ("9.3pl2", Greater, "9.3patch1"),
+        ]);
+    }
+
+    #[test]
+    fn compares_post_level_versions() {
+        use Ordering::*;
+        assert_ordered(&[
+            ("1.2.3.post34", Greater, "1.2.3.post33"),
+            ("1.2.3.post34", Less, "1.2.3.post35"),
+            ("1.2.3.post34", Greater, "1.2.3rc35"),
+            ("1.2.3.post34", Greater, "1.2.3"),
+            ("1.2.3.post34", Greater, "1.2.3-p34"),
+            ("1.2.3.post34", Less, "1.2.4"),
+        ]);
+    }
+
+    #[test]
+    fn compares_unevenly_padded_versions() {
+        use Ordering::*;
+        assert_ordered(&[
+            ("2.1.0-p194", Less, "2.1-p195"),
+            ("2.1-p194", Less, "2.1.0-p195"),
+            ("2-p194", Less, "2.1-p195"),
+            ("2.1.0-p194", Equal, "2.1-p194"),
+        ]);
+    }
+
+    #[test]
+    fn short_a_and_b_only_count_before_an_attached_number() {
+        use Ordering::*;
+        assert_ordered(&[
+            ("1.0b2", Less, "1.0"),
+            ("1.0a1", Less, "1.0"),
+            // Without a number they are ordinary letters, which come after the release.
+            ("1.0b", Greater, "1.0"),
+            ("1.0a", Greater, "1.0"),
+            ("1.0b", Greater, "1.0b2"),
+            ("1.0-b2", Less, "1.0"),
+            // A separator in between detaches the number from the short form only.
+            ("1.0b-2", Greater, "1.0"),
+            ("1.0beta-2", Less, "1.0"),
+        ]);
+    }
+
+    #[test]
+    fn head_is_newer_than_any_release() {
+        use Ordering::*;
+        assert_ordered(&[
+            ("HEAD", Greater, "1.2.3"),
+            ("HEAD-abcdef", Greater, "1.2.3"),
+            ("HEAD-abcdef", Equal, "HEAD-fedcba"),
+            ("HEAD", Equal, "HEAD-fedcba"),
+        ]);
+        assert!(v("HEAD-abcdef").is_head());
+        assert_eq!(v("HEAD-abcdef").major(), None);
+    }
+
+    #[test]
+    fn epoch_is_compared_first() {
+        use Ordering::*;
+        assert_ordered(&[
+            ("1:1.0", Greater, "2.0"),
+            ("1:1.0", Less, "2:0.1"),
+            ("1:1.0", Equal, "1:1.0.0"),
+            ("0:1.0", Equal, "1.0"),
+        ]);
+        assert_eq!(v("2:1.0_1").epoch(), 2);
+        assert_eq!(v("2:1.0_1").to_string(), "2:1.0_1");
+    }
+
+    #[test]
+    fn revision_is_compared_after_the_version() {
+        use Ordering::*;
+        assert_ordered(&[
+            ("1.2.3_1", Greater, "1.2.3"),
+            ("1.2.3_2", Greater, "1.2.3_1"),
+            ("1.2.3_10", Greater, "1.2.3_9"),
+            ("1.2.4", Greater, "1.2.3_5"),
+            ("1.2.3rc1_3", Less, "1.2.3"),
+        ]);
+        let version = v("1.2.3_4");
+        assert_eq!((version.version(), version.revision()), ("1.2.3", 4));
+        assert_eq!(version.describe(), "1.2.3 (revision 4)");
+        // Only digits after the last `_` are a revision.
+        assert_eq!(v("1.2_beta").revision(), 0);
+        assert_eq!(v("1.2_beta").version(), "1.2_beta");
+    }
+
+    #[test]
+    fn rebuild_orders_bottles_of_the_same_version() {
+        assert!(v("1.0").with_rebuild(1) > v("1.0"));
+        assert!(v("1.0").with_rebuild(5) < v("1.0_1"));
+        assert_eq!(v("1.0").with_rebuild(2).to_string(), "1.0");
+    }
+
+    #[test]
+    fn matches_ignores_the_revision_only_when_none_is_wanted() {
+        assert!(v("1.2_1").matches(&v("1.2")));
+        assert!(v("1.2.0_1").matches(&v("1.2")));
+        assert!(!v("1.2_1").matches(&v("1.2_2")));
+        assert!(v("1.2_2").matches(&v("1.2_2")));
+    }
+}
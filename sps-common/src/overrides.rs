@@ -0,0 +1,188 @@
+// sps-common/src/overrides.rs
+//! Local formula and cask definitions that win over the API, for patching a broken entry (wrong
+//! checksum, dead URL) without waiting for upstream. Each definition is one API-format JSON file,
+//! `<overrides_dir>/<name>.json`; a file with a `token` key is a cask, anything else a formula.
+//!
+//! The directory lives under the prefix rather than the cache, so `sps update` never touches it.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+
+use serde_json::Value;
+use tracing::{debug, warn};
+
+use crate::config::Config;
+use crate::error::{Result, SpsError};
+use crate::model::cask::Cask;
+use crate::model::formula::Formula;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverrideKind {
+    Formula,
+    Cask,
+}
+
+impl OverrideKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Formula => "formula",
+            Self::Cask => "cask",
+        }
+    }
+}
+
+/// One file in the overrides directory.
+#[derive(Debug, Clone)]
+pub struct OverrideEntry {
+    pub name: String,
+    pub kind: OverrideKind,
+    pub path: PathBuf,
+    /// The raw definition, as `info` prints it.
+    pub value: Value,
+}
+
+/// Every override, sorted by name. Files that can't be read as JSON are skipped with a warning.
+pub fn list(config: &Config) -> Result<Vec<OverrideEntry>> {
+    let entries = match fs::read_dir(&config.overrides_dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let mut overrides = Vec::new();
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.extension().and_then(|e| e.to_str()) != Some("json") {
+            continue;
+        }
+        let Some(name) = path.file_stem().and_then(|s| s.to_str()) else {
+            continue;
+        };
+        match read_entry(name, path.clone()) {
+            Ok(entry) => overrides.push(entry),
+            Err(e) => warn!("Ignoring local override: {}", e),
+        }
+    }
+    overrides.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(overrides)
+}
+
+/// The override for `name`, if there is one.
+pub fn find(config: &Config, name: &str) -> Result<Option<OverrideEntry>> {
+    let path = path_for(config, name);
+    if !path.is_file() {
+        return Ok(None);
+    }
+    read_entry(name, path).map(Some)
+}
+
+/// Whether `name` has an override file of the given kind. Unreadable files count as absent.
+pub fn is_overridden(config: &Config, name: &str, kind: OverrideKind) -> bool {
+    matches!(find(config, name), Ok(Some(entry)) if entry.kind == kind)
+}
+
+/// The formula override for `name`, parsed.
+pub fn formula(config: &Config, name: &str) -> Result<Option<Formula>> {
+    match find(config, name)? {
+        Some(entry) if entry.kind == OverrideKind::Formula => parse(&entry).map(Some),
+        _ => Ok(None),
+    }
+}
+
+/// The cask override for `token`, parsed.
+pub fn cask(config: &Config, token: &str) -> Result<Option<Cask>> {
+    match find(config, token)? {
+        Some(entry) if entry.kind == OverrideKind::Cask => parse(&entry).map(Some),
+        _ => Ok(None),
+    }
+}
+
+/// All formula overrides, keyed by file name, or by `user/repo/name` for an override with a
+/// `tap` so it never stands in for the core formula of that name. An override that doesn't parse
+/// is skipped with a warning rather than failing every lookup.
+pub fn formulae(config: &Config) -> HashMap<String, Formula> {
+    parse_all::<Formula>(config, OverrideKind::Formula)
+        .into_iter()
+        .map(|(name, formula)| match formula.tap() {
+            Some(_) => (formula.name.clone(), formula),
+            None => (name, formula),
+        })
+        .collect()
+}
+
+/// All cask overrides, keyed by file name, skipping any that don't parse.
+pub fn casks(config: &Config) -> HashMap<String, Cask> {
+    parse_all(config, OverrideKind::Cask)
+}
+
+/// Deletes the override for `name`. Returns false if there was none.
+pub fn remove(config: &Config, name: &str) -> Result<bool> {
+    let path = path_for(config, name);
+    match fs::remove_file(&path) {
+        Ok(()) => {
+            debug!("Removed override {}", path.display());
+            Ok(true)
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn path_for(config: &Config, name: &str) -> PathBuf {
+    config.overrides_dir.join(format!("{name}.json"))
+}
+
+fn read_entry(name: &str, path: PathBuf) -> Result<OverrideEntry> {
+    let text = fs::read_to_string(&path)?;
+    let value: Value = serde_json::from_str(&text).map_err(|e| {
+        SpsError::ValidationError(format!(
+            "Override {} is not valid JSON: {e}",
+            path.display()
+        ))
+    })?;
+    let kind = if value.get("token").is_some() {
+        OverrideKind::Cask
+    } else {
+        OverrideKind::Formula
+    };
+    Ok(OverrideEntry {
+        name: name.to_string(),
+        kind,
+        path,
+        value,
+    })
+}
+
+fn parse<T: serde::de::DeserializeOwned>(entry: &OverrideEntry) -> Result<T> {
+    serde_json::from_value(entry.value.clone()).map_err(|e| {
+        SpsError::ValidationError(format!(
+            "Override {} is not a valid {} definition: {e}",
+            entry.path.display(),
+            entry.kind.as_str()
+        ))
+    })
+}
+
+fn parse_all<T: serde::de::DeserializeOwned>(
+    config: &Config,
+    kind: OverrideKind,
+) -> HashMap<String, T> {
+    let entries = match list(config) {
+        Ok(entries) => entries,
+        Err(e) => {
+            warn!("Ignoring local overrides: {}", e);
+            return HashMap::new();
+        }
+    };
+    entries
+        .iter()
+        .filter(|entry| entry.kind == kind)
+        .filter_map(|entry| match parse::<T>(entry) {
+            Ok(parsed) => Some((entry.name.clone(), parsed)),
+            Err(e) => {
+                warn!("Ignoring local override: {}", e);
+                None
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,216 @@
+// sps-common/src/state.rs
+//! The state directory `<prefix>/var/sps/` and the versioned JSON files in it (usage metrics,
+//! the link ownership index). Everything reads and writes them through [`load`] and [`store`].
+//!
+//! The directory carries a layout version in `STATE_VERSION`, and every file a `schema_version`
+//! key. Files written before versioning count as version 1. [`migrate`] runs at startup: a file
+//! behind its current schema is copied to `<file>.v<N>.bak` and brought forward by its
+//! registered migrations, one version at a time. A directory or file stamped with a newer
+//! version than this build knows is refused instead, so an older sps never rewrites state it
+//! doesn't understand.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use tracing::{debug, info};
+
+use crate::config::Config;
+use crate::error::{Result, SpsError};
+
+/// Version of the directory layout, written to `STATE_VERSION`.
+pub const LAYOUT_VERSION: u32 = 1;
+const LAYOUT_FILE: &str = "STATE_VERSION";
+const VERSION_KEY: &str = "schema_version";
+
+/// Turns a file's content at version N into version N + 1.
+type Migration = fn(Map<String, Value>) -> Result<Map<String, Value>>;
+
+/// A versioned file in the state directory.
+#[derive(Debug)]
+pub struct StateFile {
+    pub name: &'static str,
+    /// The schema this build reads and writes.
+    pub version: u32,
+    /// `migrations[i]` upgrades version `i + 1` to `i + 2`.
+    migrations: &'static [Migration],
+}
+
+/// Local usage counters (`metrics = local`). v2 stamps the schema version.
+pub const METRICS: StateFile = StateFile {
+    name: "metrics.json",
+    version: 2,
+    migrations: &[stamp_only],
+};
+
+/// Which keg owns each prefix link (`sps owner`). v1 kept its own `version` key.
+pub const OWNERS: StateFile = StateFile {
+    name: "owners.json",
+    version: 2,
+    migrations: &[owners_v1_to_v2],
+};
+
+/// Every registered file, migrated by [`migrate`].
+const FILES: &[&StateFile] = &[&METRICS, &OWNERS];
+
+impl StateFile {
+    /// Where this file lives for the prefix of `config`.
+    pub fn path(&self, config: &Config) -> PathBuf {
+        state_dir(config).join(self.name)
+    }
+}
+
+/// `<prefix>/var/sps`.
+pub fn state_dir(config: &Config) -> PathBuf {
+    config.prefix().join("var/sps")
+}
+
+/// Reads `file`, migrating it first if it is behind. `None` if it doesn't exist.
+pub fn load<T: DeserializeOwned>(config: &Config, file: &StateFile) -> Result<Option<T>> {
+    let path = file.path(config);
+    let Some(content) = read_current(&path, file)? else {
+        return Ok(None);
+    };
+    Ok(Some(serde_json::from_value(Value::Object(content))?))
+}
+
+/// Writes `value` (which must serialize to a JSON object) as `file`, stamped with its schema
+/// version, through a temporary file so readers never see half of it.
+pub fn store<T: Serialize>(config: &Config, file: &StateFile, value: &T) -> Result<()> {
+    let Value::Object(mut content) = serde_json::to_value(value)? else {
+        return Err(SpsError::StateError(format!(
+            "{} must be stored as a JSON object",
+            file.name
+        )));
+    };
+    content.insert(VERSION_KEY.to_string(), Value::from(file.version));
+    ensure_layout(config)?;
+    write_atomically(&file.path(config), &Value::Object(content))
+}
+
+/// Checks the layout version and brings every registered file up to date. Fails when anything
+/// was written by a newer sps. A prefix without a state directory is left alone.
+pub fn migrate(config: &Config) -> Result<()> {
+    let dir = state_dir(config);
+    if !dir.is_dir() {
+        return Ok(());
+    }
+    check_layout(&dir)?;
+    for file in FILES {
+        let path = file.path(config);
+        if path.is_file() {
+            read_current(&path, file)?;
+        }
+    }
+    Ok(())
+}
+
+/// Creates the state directory and stamps its layout version if it isn't yet.
+fn ensure_layout(config: &Config) -> Result<()> {
+    let dir = state_dir(config);
+    fs::create_dir_all(&dir)?;
+    let stamp = dir.join(LAYOUT_FILE);
+    if !stamp.exists() {
+        fs::write(&stamp, format!("{LAYOUT_VERSION}\n"))?;
+    }
+    Ok(())
+}
+
+fn check_layout(dir: &Path) -> Result<()> {
+    let stamp = dir.join(LAYOUT_FILE);
+    let version = match fs::read_to_string(&stamp) {
+        Ok(text) => text.trim().parse::<u32>().map_err(|_| {
+            SpsError::StateError(format!("{} is not a version number", stamp.display()))
+        })?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    if version > LAYOUT_VERSION {
+        return Err(SpsError::StateError(format!(
+            "{} uses state layout {version}, but this sps only understands up to \
+             {LAYOUT_VERSION}; upgrade sps",
+            dir.display()
+        )));
+    }
+    Ok(())
+}
+
+/// The content of `path` at the current schema of `file`, migrating (and backing up) an older
+/// one on disk.
+fn read_current(path: &Path, file: &StateFile) -> Result<Option<Map<String, Value>>> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let Value::Object(mut content) = serde_json::from_str::<Value>(&text)? else {
+        return Err(SpsError::StateError(format!(
+            "{} is not a JSON object",
+            path.display()
+        )));
+    };
+    let on_disk = match content.get(VERSION_KEY) {
+        Some(v) => v
+            .as_u64()
+            .and_then(|v| u32::try_from(v).ok())
+            .ok_or_else(|| {
+                SpsError::StateError(format!("{} has an invalid {VERSION_KEY}", path.display()))
+            })?,
+        None => 1,
+    };
+    if on_disk > file.version {
+        return Err(SpsError::StateError(format!(
+            "{} has schema {on_disk}, but this sps only understands up to {}; upgrade sps",
+            path.display(),
+            file.version
+        )));
+    }
+    if on_disk == file.version {
+        return Ok(Some(content));
+    }
+
+    let backup = path.with_file_name(format!("{}.v{on_disk}.bak", file.name));
+    fs::copy(path, &backup)?;
+    for version in on_disk..file.version {
+        let migration = file.migrations.get(version as usize - 1).ok_or_else(|| {
+            SpsError::StateError(format!(
+                "No migration for {} from schema {version}",
+                file.name
+            ))
+        })?;
+        content = migration(content)?;
+        debug!("Migrated {} to schema {}", path.display(), version + 1);
+    }
+    content.insert(VERSION_KEY.to_string(), Value::from(file.version));
+    write_atomically(path, &Value::Object(content.clone()))?;
+    info!(
+        "Migrated {} from schema {on_disk} to {} (backup in {})",
+        path.display(),
+        file.version,
+        backup.display()
+    );
+    Ok(Some(content))
+}
+
+fn write_atomically(path: &Path, value: &Value) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let temp_path = path.with_extension("json.tmp");
+    fs::write(&temp_path, serde_json::to_string_pretty(value)?)?;
+    fs::rename(&temp_path, path)?;
+    Ok(())
+}
+
+/// For files whose content didn't change between versions; the new stamp is added by the caller.
+fn stamp_only(content: Map<String, Value>) -> Result<Map<String, Value>> {
+    Ok(content)
+}
+
+/// owners.json v1 stamped itself with `"version": 1`, now replaced by `schema_version`.
+fn owners_v1_to_v2(mut content: Map<String, Value>) -> Result<Map<String, Value>> {
+    content.remove("version");
+    Ok(content)
+}
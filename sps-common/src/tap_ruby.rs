@@ -0,0 +1,378 @@
+// sps-common/src/tap_ruby.rs
+//! Best-effort JSON for tap formulae that only exist as Ruby (`Formula/<name>.rb`), as many
+//! third-party taps ship no JSON.
+//!
+//! The formula is handed to an extractor command that prints its JSON on stdout: the
+//! configured `tap_ruby_command`, else `brew ruby` when Homebrew is installed (evaluating a
+//! formula needs Homebrew's DSL, which a bare `ruby` lacks). The extractor runs with a cleared
+//! environment, a throwaway HOME and working directory, and proxies pointing nowhere; on macOS
+//! it also runs under `sandbox-exec` with network access denied. The output is cached by the
+//! SHA256 of the `.rb` file, so an unchanged formula is only evaluated once.
+
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+use std::{env, thread};
+
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use tracing::debug;
+
+use super::config::Config;
+use super::error::{Result, SpsError};
+
+/// Cache subdirectory holding extracted definitions, named `<sha256 of the .rb>.json`.
+const CACHE_SUBDIR: &str = "tap-formula";
+const EXTRACT_TIMEOUT: Duration = Duration::from_secs(60);
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+/// Denies all network access to the extractor under macOS's `sandbox-exec`.
+const SANDBOX_PROFILE: &str = "(version 1)(allow default)(deny network*)";
+/// Proxy address that refuses connections, for tools that honour proxy variables.
+const NO_PROXY_ADDRESS: &str = "http://127.0.0.1:9";
+/// Evaluated by `brew ruby` with the formula path as its argument.
+const BREW_DUMP_SCRIPT: &str =
+    "puts JSON.generate(Formulary.factory(Pathname(ARGV.fetch(0))).to_hash_with_variations)";
+
+/// The JSON definition of the Ruby formula at `rb_path`, from the cache or the extractor.
+pub fn extract_formula_json(config: &Config, rb_path: &Path) -> Result<Value> {
+    let source = fs::read(rb_path)?;
+    let digest = hex::encode(Sha256::digest(&source));
+    let cached = config
+        .cache_dir
+        .join(CACHE_SUBDIR)
+        .join(format!("{digest}.json"));
+    if let Some(value) = fs::read_to_string(&cached)
+        .ok()
+        .and_then(|text| serde_json::from_str(&text).ok())
+    {
+        debug!(
+            "Using cached JSON of {} from {}",
+            rb_path.display(),
+            cached.display()
+        );
+        return Ok(value);
+    }
+
+    let Some(mut command) = extractor_command(config) else {
+        return Err(SpsError::ValidationError(format!(
+            "tap formula {} requires Ruby-based parsing; install ruby (with Homebrew, for \
+             `brew ruby`) or set tap_ruby_command, or provide JSON",
+            rb_path.display()
+        )));
+    };
+    let json = run_extractor(&mut command, rb_path)?;
+    let value: Value = serde_json::from_str(&json).map_err(|e| {
+        SpsError::ValidationError(format!(
+            "The Ruby extractor printed no valid JSON for {}: {e}",
+            rb_path.display()
+        ))
+    })?;
+
+    if let Some(parent) = cached.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    if let Err(e) = fs::write(&cached, &json) {
+        debug!("Failed to cache {}: {}", cached.display(), e);
+    }
+    Ok(value)
+}
+
+/// The extractor to run, without the formula path: `tap_ruby_command` split on whitespace, or
+/// `brew ruby -e <script>` when `brew` is on PATH.
+fn extractor_command(config: &Config) -> Option<Command> {
+    let argv: Vec<String> = match &config.tap_ruby_command {
+        Some(configured) => configured.split_whitespace().map(str::to_string).collect(),
+        None => {
+            let brew = find_in_path("brew")?;
+            vec![
+                brew.to_string_lossy().to_string(),
+                "ruby".to_string(),
+                "-e".to_string(),
+                BREW_DUMP_SCRIPT.to_string(),
+            ]
+        }
+    };
+    let (program, args) = argv.split_first()?;
+    let sandbox = Path::new("/usr/bin/sandbox-exec");
+    let mut command = if cfg!(target_os = "macos") && sandbox.is_file() {
+        let mut c = Command::new(sandbox);
+        c.arg("-p").arg(SANDBOX_PROFILE).arg(program);
+        c
+    } else {
+        Command::new(program)
+    };
+    command.args(args);
+    Some(command)
+}
+
+/// Runs `command` on `rb_path` in a scratch directory and returns its stdout.
+fn run_extractor(command: &mut Command, rb_path: &Path) -> Result<String> {
+    let scratch = tempfile::Builder::new().prefix("sps-tap-ruby").tempdir()?;
+    let home = scratch.path();
+    let stdout_path = home.join("stdout");
+    let stderr_path = home.join("stderr");
+    command
+        .arg(rb_path)
+        .current_dir(home)
+        .env_clear()
+        .env("PATH", env::var_os("PATH").unwrap_or_default())
+        .env("HOME", home)
+        .env("TMPDIR", home)
+        .env("LANG", "en_US.UTF-8")
+        .env("HOMEBREW_NO_AUTO_UPDATE", "1")
+        .env("HOMEBREW_NO_ANALYTICS", "1")
+        .env("HOMEBREW_NO_ENV_HINTS", "1")
+        .stdin(Stdio::null())
+        .stdout(File::create(&stdout_path)?)
+        .stderr(File::create(&stderr_path)?);
+    for var in ["http_proxy", "https_proxy", "all_proxy"] {
+        command.env(var, NO_PROXY_ADDRESS);
+        command.env(var.to_ascii_uppercase(), NO_PROXY_ADDRESS);
+    }
+
+    debug!(
+        "Extracting JSON from {} with {:?}",
+        rb_path.display(),
+        command
+    );
+    let mut child = command.spawn().map_err(|e| {
+        SpsError::CommandExecError(format!(
+            "Failed to start the Ruby extractor for {}: {e}",
+            rb_path.display()
+        ))
+    })?;
+    let deadline = Instant::now() + EXTRACT_TIMEOUT;
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(SpsError::CommandExecError(format!(
+                "The Ruby extractor for {} timed out after {}s",
+                rb_path.display(),
+                EXTRACT_TIMEOUT.as_secs()
+            )));
+        }
+        thread::sleep(POLL_INTERVAL);
+    };
+    if !status.success() {
+        let stderr = fs::read_to_string(&stderr_path).unwrap_or_default();
+        return Err(SpsError::CommandExecError(format!(
+            "The Ruby extractor failed for {} ({status}): {}",
+            rb_path.display(),
+            stderr.trim()
+        )));
+    }
+    Ok(fs::read_to_string(&stdout_path)?)
+}
+
+fn find_in_path(program: &str) -> Option<PathBuf> {
+    env::split_paths(&env::var_os("PATH")?)
+        .map(|dir| dir.join(program))
+        .find(|candidate| candidate.is_file())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+    use crate::formulary;
+
+    /// Logs its environment and working directory to the file named by its first argument,
+    /// then prints the `# json:` line of the formula, or fails if the formula says so.
+    const STUB: &str = r#"
+log="$1"
+rb="$2"
+{ env; echo "cwd=$(pwd)"; echo "home_exists=$(test -d "$HOME" && echo yes)"; } > "$log"
+echo run >> "$log.runs"
+if grep -q '^# fail' "$rb"; then
+    echo "cannot evaluate $rb" >&2
+    exit 3
+fi
+sed -n 's/^# json: //p' "$rb"
+"#;
+
+    struct Tap {
+        dir: tempfile::TempDir,
+        config: Config,
+    }
+
+    impl Tap {
+        /// A checkout of `someone/tools` with the stub as `tap_ruby_command`.
+        fn new() -> Self {
+            let dir = tempfile::tempdir().unwrap();
+            let stub = dir.path().join("extract.sh");
+            fs::write(&stub, STUB).unwrap();
+            fs::create_dir_all(dir.path().join("taps/someone/homebrew-tools/Formula")).unwrap();
+            let config = Config {
+                taps_dir: dir.path().join("taps"),
+                cache_dir: dir.path().join("cache"),
+                tap_ruby_command: Some(format!(
+                    "/bin/sh {} {}",
+                    stub.display(),
+                    dir.path().join("env.log").display()
+                )),
+                ..Config::load().unwrap()
+            };
+            Self { dir, config }
+        }
+
+        /// Writes `Formula/<name>.rb`: a tiny formula whose JSON the stub prints as `json`.
+        fn formula(&self, name: &str, json: &str) -> PathBuf {
+            let path = self
+                .config
+                .taps_dir
+                .join(format!("someone/homebrew-tools/Formula/{name}.rb"));
+            fs::write(
+                &path,
+                format!(
+                    "# json: {json}\nclass Hello < Formula\n  url \
+                     \"https://example.com/{name}-1.0.tar.gz\"\nend\n"
+                ),
+            )
+            .unwrap();
+            path
+        }
+
+        /// The environment of the last extractor run.
+        fn logged_env(&self) -> BTreeMap<String, String> {
+            fs::read_to_string(self.dir.path().join("env.log"))
+                .unwrap()
+                .lines()
+                .filter_map(|line| line.split_once('='))
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect()
+        }
+
+        fn runs(&self) -> usize {
+            fs::read_to_string(self.dir.path().join("env.log.runs"))
+                .map(|runs| runs.lines().count())
+                .unwrap_or(0)
+        }
+    }
+
+    #[test]
+    fn the_extractor_runs_isolated_and_its_json_becomes_the_formula() {
+        let tap = Tap::new();
+        tap.formula(
+            "hello",
+            r#"{"name":"hello","versions":{"stable":"1.0"},"desc":"Says hello"}"#,
+        );
+
+        let formula = formulary::tap_formula(&tap.config, "someone/tools/hello")
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(formula.name, "someone/tools/hello");
+        assert_eq!(formula.tap(), Some("someone/tools"));
+        assert_eq!(formula.version_str_full(), "1.0");
+        assert_eq!(formula.desc.as_deref(), Some("Says hello"));
+        let env = tap.logged_env();
+        let home = PathBuf::from(&env["HOME"]);
+        assert!(
+            home.file_name()
+                .unwrap()
+                .to_string_lossy()
+                .starts_with("sps-tap-ruby"),
+            "{env:#?}"
+        );
+        assert_eq!(env["home_exists"], "yes");
+        assert_eq!(env["cwd"], env["HOME"]);
+        assert_eq!(env["TMPDIR"], env["HOME"]);
+        assert!(!home.exists(), "the scratch HOME is removed afterwards");
+        for proxy in ["http_proxy", "HTTPS_PROXY", "all_proxy", "ALL_PROXY"] {
+            assert_eq!(env[proxy], NO_PROXY_ADDRESS, "{proxy}");
+        }
+        // Nothing of this process's environment but PATH gets through; the shell adds the rest.
+        let passed: Vec<&str> = env
+            .keys()
+            .map(String::as_str)
+            .filter(|key| !["PWD", "SHLVL", "_", "OLDPWD", "cwd", "home_exists"].contains(key))
+            .collect();
+        assert_eq!(
+            passed,
+            [
+                "ALL_PROXY",
+                "HOME",
+                "HOMEBREW_NO_ANALYTICS",
+                "HOMEBREW_NO_AUTO_UPDATE",
+                "HOMEBREW_NO_ENV_HINTS",
+                "HTTPS_PROXY",
+                "HTTP_PROXY",
+                "LANG",
+                "PATH",
+                "TMPDIR",
+                "all_proxy",
+                "http_proxy",
+                "https_proxy",
+            ]
+        );
+    }
+
+    #[test]
+    fn an_unchanged_formula_is_extracted_once() {
+        let tap = Tap::new();
+        let rb = tap.formula("hello", r#"{"name":"hello","versions":{"stable":"1.0"}}"#);
+
+        extract_formula_json(&tap.config, &rb).unwrap();
+        let cached = extract_formula_json(&tap.config, &rb).unwrap();
+
+        assert_eq!(tap.runs(), 1);
+        assert_eq!(cached["name"], "hello");
+
+        tap.formula("hello", r#"{"name":"hello","versions":{"stable":"1.1"}}"#);
+        let changed = extract_formula_json(&tap.config, &rb).unwrap();
+
+        assert_eq!(tap.runs(), 2);
+        assert_eq!(changed["versions"]["stable"], "1.1");
+    }
+
+    #[test]
+    fn json_that_is_no_formula_is_rejected_naming_the_file() {
+        let tap = Tap::new();
+        let rb = tap.formula("hello", r#"{"name":"hello","versions":"1.0"}"#);
+
+        let err = formulary::tap_formula(&tap.config, "someone/tools/hello").unwrap_err();
+
+        assert!(
+            err.to_string()
+                .contains(&format!("{} is not a valid definition", rb.display())),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn extractor_failures_name_the_formula() {
+        let tap = Tap::new();
+        let rb = tap.formula("hello", "not json");
+
+        let err = extract_formula_json(&tap.config, &rb).unwrap_err();
+
+        assert!(
+            err.to_string().contains(&format!(
+                "The Ruby extractor printed no valid JSON for {}",
+                rb.display()
+            )),
+            "{err}"
+        );
+
+        fs::write(&rb, "# fail\n").unwrap();
+        let err = extract_formula_json(&tap.config, &rb).unwrap_err();
+
+        assert!(
+            err.to_string().contains(&format!(
+                "The Ruby extractor failed for {} (exit status: 3): cannot evaluate {}",
+                rb.display(),
+                rb.display()
+            )),
+            "{err}"
+        );
+        // Nothing was cached for either.
+        let cached = fs::read_dir(tap.config.cache_dir.join(CACHE_SUBDIR));
+        assert!(cached.map_or(true, |mut entries| entries.next().is_none()));
+    }
+}
@@ -0,0 +1,54 @@
+//! Changes to the process environment for unit tests, undone when the [`ScopedEnv`] is dropped.
+//!
+//! Tests holding a `ScopedEnv` run one at a time, but every other test keeps running alongside
+//! them, so only change variables those don't read.
+
+use std::env;
+use std::ffi::{OsStr, OsString};
+use std::sync::{Mutex, MutexGuard};
+
+static LOCK: Mutex<()> = Mutex::new(());
+
+pub struct ScopedEnv {
+    saved: Vec<(String, Option<OsString>)>,
+    _lock: MutexGuard<'static, ()>,
+}
+
+impl ScopedEnv {
+    pub fn new() -> Self {
+        Self {
+            saved: Vec::new(),
+            _lock: LOCK.lock().unwrap_or_else(|e| e.into_inner()),
+        }
+    }
+
+    pub fn set(&mut self, key: &str, value: impl AsRef<OsStr>) -> &mut Self {
+        self.save(key);
+        env::set_var(key, value);
+        self
+    }
+
+    pub fn remove(&mut self, key: &str) -> &mut Self {
+        self.save(key);
+        env::remove_var(key);
+        self
+    }
+
+    /// Remembers the value `key` had before this guard first changed it.
+    fn save(&mut self, key: &str) {
+        if !self.saved.iter().any(|(saved, _)| saved == key) {
+            self.saved.push((key.to_string(), env::var_os(key)));
+        }
+    }
+}
+
+impl Drop for ScopedEnv {
+    fn drop(&mut self) {
+        for (key, value) in self.saved.drain(..).rev() {
+            match value {
+                Some(value) => env::set_var(&key, value),
+                None => env::remove_var(&key),
+            }
+        }
+    }
+}
@@ -0,0 +1,159 @@
+//! Formula and cask API JSON survives deserialize -> serialize -> deserialize unchanged, and
+//! fields the models don't know about come back out verbatim.
+//!
+//! The fixtures in `fixtures/api` are shaped after formulae.brew.sh responses.
+
+use std::fs;
+use std::path::Path;
+
+use serde_json::Value;
+use sps_common::dependency::DependencyTag;
+use sps_common::model::{Cask, Formula};
+
+const FORMULAE: &[&str] = &["wget", "python@3.13", "openjdk@17"];
+const CASKS: &[&str] = &["firefox", "docker"];
+
+/// Top-level formula fields the model rebuilds on the way out: `versions.bottle` is recomputed
+/// from the bottle block and requirements are written in the model's own form.
+const RESHAPED_FORMULA_FIELDS: &[&str] = &["versions", "requirements"];
+
+fn fixture(name: &str) -> Value {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures/api")
+        .join(format!("{name}.json"));
+    serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap()
+}
+
+fn object(value: &Value) -> &serde_json::Map<String, Value> {
+    value.as_object().expect("a JSON object")
+}
+
+/// Asserts everything in `raw` is in `written`. `written` may add fields the model fills with
+/// defaults, and may leave out nulls and empty collections.
+fn assert_preserved(raw: &Value, written: Option<&Value>, at: &str) {
+    let is_empty = match raw {
+        Value::Null => true,
+        Value::Array(items) => items.is_empty(),
+        Value::Object(fields) => fields.is_empty(),
+        _ => false,
+    };
+    match (raw, written) {
+        (_, None) if is_empty => {}
+        (Value::Object(fields), Some(Value::Object(written_fields))) => {
+            for (key, value) in fields {
+                assert_preserved(value, written_fields.get(key), &format!("{at}.{key}"));
+            }
+        }
+        _ => assert_eq!(written, Some(raw), "{at} was not preserved"),
+    }
+}
+
+fn names(value: Option<&Value>) -> Vec<&str> {
+    let mut names: Vec<&str> = value
+        .and_then(Value::as_array)
+        .map(|a| a.iter().filter_map(Value::as_str).collect())
+        .unwrap_or_default();
+    names.sort_unstable();
+    names
+}
+
+#[test]
+fn formulae_round_trip_through_serialize() {
+    for name in FORMULAE {
+        let raw = fixture(name);
+        let parsed: Formula = serde_json::from_value(raw.clone()).unwrap();
+
+        let written = serde_json::to_value(&parsed).unwrap();
+        let reparsed: Formula = serde_json::from_value(written.clone()).unwrap();
+
+        assert_eq!(reparsed, parsed, "{name}");
+        assert_eq!(serde_json::to_value(&reparsed).unwrap(), written, "{name}");
+        for (key, value) in object(&raw) {
+            if RESHAPED_FORMULA_FIELDS.contains(&key.as_str()) {
+                continue;
+            }
+            let written_value = object(&written).get(key);
+            if key.ends_with("dependencies") && value.is_array() {
+                // Regrouped by tag and sorted, but the same names per group.
+                assert_eq!(names(written_value), names(Some(value)), "{name}: {key}");
+                continue;
+            }
+            assert_preserved(value, written_value, &format!("{name}: {key}"));
+        }
+    }
+}
+
+#[test]
+fn formula_fields_the_model_consumes_survive() {
+    let wget: Formula = serde_json::from_value(fixture("wget")).unwrap();
+    let wget: Formula = serde_json::from_value(serde_json::to_value(&wget).unwrap()).unwrap();
+    assert_eq!(wget.stable_version_str, "1.25.0");
+    assert_eq!(wget.head_version_str.as_deref(), Some("HEAD"));
+    assert_eq!(wget.url, "https://ftp.gnu.org/gnu/wget/wget-1.25.0.tar.gz");
+    let mut tags: Vec<&String> = wget.bottle.stable.as_ref().unwrap().files.keys().collect();
+    tags.sort();
+    assert_eq!(
+        tags,
+        ["arm64_sequoia", "arm64_sonoma", "sonoma", "x86_64_linux"]
+    );
+    let pkgconf = wget
+        .dependencies
+        .iter()
+        .find(|d| d.name == "pkgconf")
+        .unwrap();
+    assert!(pkgconf.tags.contains(DependencyTag::BUILD));
+    assert!(!pkgconf.tags.contains(DependencyTag::RUNTIME));
+    assert_eq!(wget.extra["license"], "GPL-3.0-or-later");
+
+    let python: Formula = serde_json::from_value(fixture("python@3.13")).unwrap();
+    let python: Formula = serde_json::from_value(serde_json::to_value(&python).unwrap()).unwrap();
+    assert_eq!(python.revision, 1);
+    assert_eq!(python.bottle.stable.as_ref().unwrap().rebuild, 2);
+    assert_eq!(python.resources.len(), 3);
+    assert_eq!(python.requirements.len(), 1);
+    assert!(python.caveats.as_deref().unwrap().contains("python3"));
+
+    let jdk: Formula = serde_json::from_value(fixture("openjdk@17")).unwrap();
+    let jdk: Formula = serde_json::from_value(serde_json::to_value(&jdk).unwrap()).unwrap();
+    assert!(jdk.keg_only);
+    assert_eq!(
+        jdk.keg_only_reason.as_ref().unwrap()["reason"],
+        ":versioned_formula"
+    );
+    assert!(jdk.extra.contains_key("variations"));
+}
+
+#[test]
+fn casks_round_trip_through_serialize() {
+    for token in CASKS {
+        let raw = fixture(token);
+        let parsed: Cask = serde_json::from_value(raw.clone()).unwrap();
+
+        let written = serde_json::to_value(&parsed).unwrap();
+        let reparsed: Cask = serde_json::from_value(written.clone()).unwrap();
+
+        assert_eq!(serde_json::to_value(&reparsed).unwrap(), written, "{token}");
+        assert_preserved(&raw, Some(&written), token);
+    }
+}
+
+#[test]
+fn cask_fields_the_model_consumes_survive() {
+    let docker: Cask = serde_json::from_value(fixture("docker")).unwrap();
+    let docker: Cask = serde_json::from_value(serde_json::to_value(&docker).unwrap()).unwrap();
+    assert_eq!(docker.version.as_deref(), Some("4.38.0,181591"));
+    assert_eq!(docker.artifacts.as_ref().unwrap().len(), 7);
+    let conflicts = docker.conflicts_with.as_ref().unwrap();
+    assert_eq!(conflicts.formula.len(), 3);
+    assert_eq!(conflicts.cask, ["docker@edge"]);
+    assert!(docker.depends_on.as_ref().unwrap().macos.is_some());
+    assert_eq!(docker.variations.len(), 2);
+    assert!(docker.variations["sonoma"].sha256.is_some());
+
+    let firefox: Cask = serde_json::from_value(fixture("firefox")).unwrap();
+    let firefox: Cask = serde_json::from_value(serde_json::to_value(&firefox).unwrap()).unwrap();
+    assert_eq!(firefox.languages.len(), 10);
+    assert_eq!(firefox.auto_updates, Some(true));
+    assert_eq!(firefox.extra["full_token"], "firefox");
+    assert_eq!(firefox.extra["ruby_source_path"], "Casks/f/firefox.rb");
+}
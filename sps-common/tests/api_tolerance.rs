@@ -0,0 +1,106 @@
+//! API entries from a newer schema still parse: unknown fields are kept, unknown dependency
+//! tags and artifact types are preserved, and an unreadable bottle entry only loses itself.
+//!
+//! The fixtures in `fixtures/api/future` are current entries with such additions injected.
+
+use std::fs;
+use std::path::Path;
+
+use serde_json::{json, Value};
+use sps_common::dependency::DependencyTag;
+use sps_common::model::{Cask, Formula};
+
+fn fixture(name: &str) -> Value {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures/api/future")
+        .join(format!("{name}.json"));
+    serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap()
+}
+
+fn formula() -> Formula {
+    serde_json::from_value(fixture("formula")).expect("the formula parses")
+}
+
+fn cask() -> Cask {
+    serde_json::from_value(fixture("cask")).expect("the cask parses")
+}
+
+#[test]
+fn a_formula_keeps_fields_it_does_not_know() {
+    let formula = formula();
+
+    assert_eq!(formula.name, "wget");
+    assert_eq!(formula.version_str_full(), "1.25.0");
+    let written = serde_json::to_value(&formula).unwrap();
+    for field in ["sbom", "provenance", "install_hints"] {
+        assert_eq!(written[field], fixture("formula")[field], "{field}");
+    }
+}
+
+#[test]
+fn unknown_dependency_tags_are_kept_beside_the_known_ones() {
+    let formula = formula();
+    let dep = |name: &str| {
+        formula
+            .dependencies()
+            .iter()
+            .find(|d| d.name == name)
+            .unwrap_or_else(|| panic!("{name} missing"))
+            .clone()
+    };
+
+    assert_eq!(dep("libidn2").tags, DependencyTag::RUNTIME);
+    assert!(dep("libidn2").other_tags.is_empty());
+    assert_eq!(dep("openssl@3").tags, DependencyTag::RUNTIME);
+    assert_eq!(dep("openssl@3").other_tags, ["linked_statically"]);
+    assert!(dep("pkgconf").tags.contains(DependencyTag::BUILD));
+    assert_eq!(dep("pkgconf").other_tags, ["hermetic"]);
+
+    let written = serde_json::to_value(&formula).unwrap();
+    let reparsed: Formula = serde_json::from_value(written).unwrap();
+    assert_eq!(reparsed.dependencies(), formula.dependencies());
+}
+
+#[test]
+fn an_unreadable_bottle_entry_drops_only_itself() {
+    let formula = formula();
+    let stable = formula.bottle.stable.as_ref().unwrap();
+
+    assert_eq!(stable.rebuild, 0);
+    assert_eq!(
+        stable.files.keys().collect::<Vec<_>>(),
+        ["arm64_sonoma"],
+        "{:?}",
+        stable.files
+    );
+    let sonoma = formula.get_bottle_spec("arm64_sonoma").unwrap();
+    assert_eq!(sonoma.extra["attestation"], json!({ "bundle": "sigstore" }));
+}
+
+#[test]
+fn a_cask_keeps_an_artifact_type_it_does_not_know() {
+    let cask = cask();
+
+    let artifacts = cask.artifacts.as_ref().unwrap();
+    assert_eq!(artifacts.len(), 3);
+    assert_eq!(
+        artifacts[1]["quantum_widget"][0],
+        json!("Hologram.qwidget"),
+        "{artifacts:?}"
+    );
+    let written = serde_json::to_value(&cask).unwrap();
+    assert_eq!(written["artifacts"], fixture("cask")["artifacts"]);
+}
+
+#[test]
+fn a_cask_field_of_an_unexpected_shape_falls_back_alone() {
+    let cask = cask();
+
+    // `auto_updates` should be a bool; the rest of the cask is unaffected.
+    assert_eq!(cask.auto_updates, None);
+    assert_eq!(cask.version.as_deref(), Some("2.0"));
+    let depends_on = cask.depends_on.as_ref().unwrap();
+    assert!(depends_on.macos.is_some());
+    assert_eq!(depends_on.extra["neural_engine"], json!(true));
+    assert_eq!(cask.extra["spatial_support"], json!({ "visionos": ">= 2" }));
+}
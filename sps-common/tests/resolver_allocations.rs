@@ -0,0 +1,165 @@
+//! Allocation counts of dependency resolution, measured with a counting global allocator.
+//! Planners ask every node for its dependencies several times, so the lookup must borrow the
+//! parsed list rather than build a new one, and a plan's allocations must grow with its size,
+//! not with the number of lookups.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+use serde_json::{json, Value};
+use sps_common::cache::Cache;
+use sps_common::config::Config;
+use sps_common::dependency::{DependencyResolver, ResolutionContext};
+use sps_common::formulary::Formulary;
+use sps_common::keg::KegRegistry;
+use sps_common::model::Formula;
+
+/// Counts the allocations of the current thread only, so tests running in parallel don't see
+/// each other's.
+struct Counting;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|n| n.set(n.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|n| n.set(n.get() + 1));
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+/// Allocations made by `f` on this thread.
+fn allocations<T>(f: impl FnOnce() -> T) -> (usize, T) {
+    let before = ALLOCATIONS.with(Cell::get);
+    let result = f();
+    (ALLOCATIONS.with(Cell::get) - before, result)
+}
+
+const WIDTH: usize = 30;
+
+fn name(i: usize) -> String {
+    format!("f{i:03}")
+}
+
+/// `f000`..`f<n-1>` in layers of [`WIDTH`], each formula depending on three of the layer below:
+/// a dense DAG, ten levels deep at 300 formulae, in which most formulae are reached along many
+/// paths.
+fn index(n: usize) -> Value {
+    let formulae: Vec<Value> = (0..n)
+        .map(|i| {
+            let (layer, j) = (i / WIDTH, i % WIDTH);
+            let deps: Vec<String> = match layer {
+                0 => Vec::new(),
+                _ => [0, 7, 13]
+                    .iter()
+                    .map(|k| name((layer - 1) * WIDTH + (j + k) % WIDTH))
+                    .collect(),
+            };
+            json!({ "name": name(i), "versions": { "stable": "1.0" }, "dependencies": deps })
+        })
+        .collect();
+    Value::Array(formulae)
+}
+
+/// Resolves the top layer of an `n`-formula index in a fresh prefix; returns the allocations of
+/// the resolution itself, index loading included, and the planned names.
+fn resolve(n: usize) -> (usize, Vec<String>) {
+    let dir = tempfile::tempdir().unwrap();
+    let config = Config {
+        prefix: dir.path().to_path_buf(),
+        cellar: dir.path().join("Cellar"),
+        cache_dir: dir.path().join("cache"),
+        ..Config::load().unwrap()
+    };
+    std::fs::create_dir_all(&config.cellar).unwrap();
+    Cache::new(&config.cache_dir)
+        .unwrap()
+        .store_raw("formula.json", &index(n).to_string())
+        .unwrap();
+    let formulary = Formulary::new(config.clone());
+    let keg_registry = KegRegistry::new(config.clone());
+    let targets: Vec<String> = (n - WIDTH..n).map(name).collect();
+
+    allocations(|| {
+        let mut resolver = DependencyResolver::new(ResolutionContext {
+            formulary: &formulary,
+            keg_registry: &keg_registry,
+            sps_prefix: &config.prefix,
+            include_optional: false,
+            include_test: false,
+            skip_recommended: false,
+            force_build: false,
+            ignore_installed: false,
+            only_missing: false,
+            platform: None,
+        });
+        let graph = resolver.resolve_targets(&targets).unwrap();
+        assert!(graph.errors.is_empty(), "{:?}", graph.errors);
+        graph
+            .install_plan
+            .iter()
+            .map(|d| d.formula.name.clone())
+            .collect()
+    })
+}
+
+#[test]
+fn asking_a_formula_for_its_dependencies_does_not_allocate() {
+    let formulae: Vec<Formula> = serde_json::from_value(index(300)).unwrap();
+
+    let (count, total) = allocations(|| {
+        let mut total = 0;
+        for _ in 0..3 {
+            for formula in &formulae {
+                total += formula.dependencies().len();
+            }
+        }
+        total
+    });
+
+    assert!(total > 600, "{total}");
+    assert_eq!(count, 0);
+}
+
+#[test]
+fn resolving_300_formulae_allocates_in_proportion_to_the_plan() {
+    let (small, small_plan) = resolve(100);
+    let (large, large_plan) = resolve(300);
+
+    assert_eq!(small_plan.len(), 100);
+    assert_eq!(large_plan.len(), 300);
+    // Three times the formulae, reached along many more paths. Allocating per lookup, or
+    // rescanning the plan per node, makes this ratio approach 9.
+    assert!(large < small * 4, "{small} -> {large}");
+}
+
+#[test]
+fn a_300_formula_plan_lists_every_dependency_before_its_dependents() {
+    let (_, plan) = resolve(300);
+
+    let position = |name: &str| plan.iter().position(|p| p == name).unwrap();
+    let formulae: Vec<Formula> = serde_json::from_value(index(300)).unwrap();
+    for formula in &formulae {
+        for dep in formula.dependencies() {
+            assert!(
+                position(&dep.name) < position(&formula.name),
+                "{} is planned after {}",
+                dep.name,
+                formula.name
+            );
+        }
+    }
+}
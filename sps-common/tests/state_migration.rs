@@ -0,0 +1,142 @@
+//! State files written before versioning (schema 1) are still readable after `state::migrate`
+//! brings them to the current schema, and state written by a newer sps is refused untouched.
+//!
+//! The fixtures in `fixtures/state/v1` are `var/sps` files as sps wrote them before versioning.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde_json::{json, Value};
+use sps_common::config::Config;
+use sps_common::error::SpsError;
+use sps_common::{metrics, state};
+use tempfile::TempDir;
+
+/// A config whose prefix is a fresh directory, with the v1 fixtures copied into its state dir.
+fn prefix_with_v1_state() -> (TempDir, Config) {
+    let dir = tempfile::tempdir().unwrap();
+    let config = Config {
+        prefix: dir.path().to_path_buf(),
+        cellar: dir.path().join("Cellar"),
+        ..Config::load().unwrap()
+    };
+    let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/state/v1");
+    let state_dir = state::state_dir(&config);
+    fs::create_dir_all(&state_dir).unwrap();
+    for entry in fs::read_dir(fixtures).unwrap() {
+        let entry = entry.unwrap();
+        fs::copy(entry.path(), state_dir.join(entry.file_name())).unwrap();
+    }
+    (dir, config)
+}
+
+fn read_json(path: &Path) -> Value {
+    serde_json::from_str(&fs::read_to_string(path).unwrap()).unwrap()
+}
+
+fn backup(config: &Config, file: &state::StateFile, version: u32) -> PathBuf {
+    state::state_dir(config).join(format!("{}.v{version}.bak", file.name))
+}
+
+#[test]
+fn v1_files_are_migrated_and_backed_up() {
+    let (_dir, config) = prefix_with_v1_state();
+    let metrics_v1 = read_json(&state::METRICS.path(&config));
+    let owners_v1 = read_json(&state::OWNERS.path(&config));
+
+    state::migrate(&config).unwrap();
+
+    let metrics = read_json(&state::METRICS.path(&config));
+    assert_eq!(metrics["schema_version"], json!(2));
+    assert_eq!(metrics["packages"], metrics_v1["packages"]);
+    let owners = read_json(&state::OWNERS.path(&config));
+    assert_eq!(owners["schema_version"], json!(2));
+    assert_eq!(owners.get("version"), None);
+    assert_eq!(owners["links"], owners_v1["links"]);
+
+    assert_eq!(read_json(&backup(&config, &state::METRICS, 1)), metrics_v1);
+    assert_eq!(read_json(&backup(&config, &state::OWNERS, 1)), owners_v1);
+}
+
+#[test]
+fn v1_files_read_through_their_current_types() {
+    let (_dir, config) = prefix_with_v1_state();
+
+    // Loading migrates on the way, without a separate `migrate` call.
+    let stats = metrics::load(&config).unwrap();
+    assert_eq!(stats.packages.len(), 2);
+    assert_eq!(stats.packages["jq"].installs, 3);
+    assert_eq!(stats.packages["jq"].install_seconds, 4.25);
+    assert_eq!(stats.packages["wget"].last_used, 1_713_000_000);
+    assert!(backup(&config, &state::METRICS, 1).is_file());
+
+    let owners: Value = state::load(&config, &state::OWNERS).unwrap().unwrap();
+    assert_eq!(owners["links"]["bin/jq"], json!("jq/1.7.1"));
+}
+
+#[test]
+fn migrating_twice_changes_nothing() {
+    let (_dir, config) = prefix_with_v1_state();
+    state::migrate(&config).unwrap();
+    let migrated = fs::read_to_string(state::OWNERS.path(&config)).unwrap();
+    fs::remove_file(backup(&config, &state::OWNERS, 1)).unwrap();
+
+    state::migrate(&config).unwrap();
+
+    assert_eq!(
+        fs::read_to_string(state::OWNERS.path(&config)).unwrap(),
+        migrated
+    );
+    assert!(!backup(&config, &state::OWNERS, 1).exists());
+}
+
+#[test]
+fn a_file_from_a_newer_sps_is_refused_and_left_alone() {
+    let (_dir, config) = prefix_with_v1_state();
+    let path = state::OWNERS.path(&config);
+    let newer = r#"{"schema_version": 3, "links": {}, "owners_by_keg": {}}"#;
+    fs::write(&path, newer).unwrap();
+
+    let result = state::migrate(&config);
+
+    match result {
+        Err(SpsError::StateError(msg)) => {
+            assert!(msg.contains("schema 3"), "{msg}");
+            assert!(msg.contains("upgrade sps"), "{msg}");
+        }
+        other => panic!("expected a state error, got {other:?}"),
+    }
+    assert_eq!(fs::read_to_string(&path).unwrap(), newer);
+    assert!(!backup(&config, &state::OWNERS, 3).exists());
+    assert!(state::load::<Value>(&config, &state::OWNERS).is_err());
+}
+
+#[test]
+fn a_state_dir_with_a_newer_layout_is_refused() {
+    let (_dir, config) = prefix_with_v1_state();
+    let stamp = state::state_dir(&config).join("STATE_VERSION");
+    fs::write(&stamp, format!("{}\n", state::LAYOUT_VERSION + 1)).unwrap();
+
+    let result = state::migrate(&config);
+
+    assert!(matches!(result, Err(SpsError::StateError(_))), "{result:?}");
+    // Nothing was migrated.
+    assert!(!backup(&config, &state::METRICS, 1).exists());
+    assert_eq!(
+        read_json(&state::METRICS.path(&config)).get("schema_version"),
+        None
+    );
+}
+
+#[test]
+fn a_prefix_without_state_is_left_alone() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = Config {
+        prefix: dir.path().to_path_buf(),
+        ..Config::load().unwrap()
+    };
+
+    state::migrate(&config).unwrap();
+
+    assert!(!state::state_dir(&config).exists());
+}
@@ -0,0 +1,162 @@
+//! Pours a synthetic bottle the way a bottle install does (`extract_tar_pipelined_hashed`, then
+//! the keg's integrity manifest from those hashes) and compares it with decoding and unpacking
+//! on one thread and hashing the keg afterwards. The bottle is timed gzipped, as Homebrew
+//! publishes it, and as multi-frame zstd, which the pour decodes in parallel.
+//!
+//! `cargo bench -p sps-core --bench extract`. Set `SPS_BENCH_MB` for the bottle's uncompressed
+//! size in MiB (default 256) and `SPS_BENCH_RUNS` for the number of pours timed (default 5).
+//! Exits non-zero when the zstd pour isn't `SPS_BENCH_MIN_SPEEDUP` times faster than its
+//! baseline, or the gzip pour is more than [`NOISE`] slower than its own. The default target
+//! is 2 with four or more CPUs, where decoding frames in parallel has room to pay off; with
+//! fewer, the zstd pour is only held to the same noise margin as gzip, whose single stream
+//! can't be split.
+
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use sps_core::build::extract::extract_tar_pipelined_hashed;
+use sps_core::build::formula::integrity::write_keg_file_manifest;
+
+/// How much slower than its baseline a pour may measure before the bench fails, as a speedup.
+/// Runs on a busy disk vary by about this much.
+const NOISE: f64 = 0.9;
+
+fn env_or(name: &str, default: usize) -> usize {
+    std::env::var(name)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default)
+}
+
+/// The tarball of a bottle of about `size_mb` MiB: many small files, as in `share/`, and a few
+/// large ones, as in `lib/`. The data is only partly compressible, like binaries.
+fn bottle(size_mb: usize) -> Vec<u8> {
+    let mut builder = tar::Builder::new(Vec::new());
+    let mut seed = 0x2545_f491_u32;
+    let mut data = |len: usize| -> Vec<u8> {
+        (0..len)
+            .map(|i| {
+                seed ^= seed << 13;
+                seed ^= seed >> 17;
+                seed ^= seed << 5;
+                if i % 4 == 0 {
+                    seed as u8
+                } else {
+                    b'a' + (i % 23) as u8
+                }
+            })
+            .collect()
+    };
+    let mut append = |path: String, contents: Vec<u8>| {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(contents.len() as u64);
+        header.set_mode(0o644);
+        header.set_entry_type(tar::EntryType::Regular);
+        builder
+            .append_data(&mut header, path, contents.as_slice())
+            .unwrap();
+    };
+    let budget = size_mb << 20;
+    let small = budget / 4 / (16 << 10);
+    for i in 0..small {
+        append(format!("bench/1.0/share/doc/file{i}.txt"), data(16 << 10));
+    }
+    let large = 3 * budget / 4 / (32 << 20);
+    for i in 0..large.max(1) {
+        append(format!("bench/1.0/lib/libbench{i}.dylib"), data(32 << 20));
+    }
+    builder.into_inner().unwrap()
+}
+
+fn gzipped(tar: &[u8]) -> Vec<u8> {
+    let mut gz = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    gz.write_all(tar).unwrap();
+    gz.finish().unwrap()
+}
+
+/// `tar` as independent zstd frames of 4 MiB input each, as `pzstd` writes them.
+fn zstd_frames(tar: &[u8]) -> Vec<u8> {
+    tar.chunks(4 << 20)
+        .flat_map(|frame| zstd::stream::encode_all(frame, 3).unwrap())
+        .collect()
+}
+
+/// Decodes and unpacks on one thread, then hashes the keg for its manifest.
+fn sequential(decoder: impl Read, target: &Path) {
+    tar::Archive::new(decoder).unpack(target).unwrap();
+    write_keg_file_manifest(&target.join("bench/1.0"), &BTreeMap::new()).unwrap();
+}
+
+/// The bottle install's pour.
+fn pour(archive: &Path, target: &Path) {
+    let hashes = extract_tar_pipelined_hashed(archive, target, 2).unwrap();
+    write_keg_file_manifest(target, &hashes).unwrap();
+}
+
+fn time(runs: usize, archive: &Path, pour: impl Fn(&Path, &Path)) -> Duration {
+    let mut best = Duration::MAX;
+    for _ in 0..runs {
+        let target = tempfile::tempdir().unwrap();
+        let started = Instant::now();
+        pour(archive, target.path());
+        best = best.min(started.elapsed());
+    }
+    best
+}
+
+fn main() {
+    let size_mb = env_or("SPS_BENCH_MB", 256);
+    let runs = env_or("SPS_BENCH_RUNS", 5);
+    let cpus = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let min_speedup = std::env::var("SPS_BENCH_MIN_SPEEDUP")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(if cpus >= 4 { 2.0 } else { NOISE });
+    let dir = tempfile::tempdir().unwrap();
+    let tar = bottle(size_mb);
+    let gz = dir.path().join("bench-1.0.bottle.tar.gz");
+    fs::write(&gz, gzipped(&tar)).unwrap();
+    let zst = dir.path().join("bench-1.0.bottle.tar.zst");
+    fs::write(&zst, zstd_frames(&tar)).unwrap();
+    drop(tar);
+    println!("bottle: {size_mb} MiB unpacked, {cpus} CPUs, best of {runs}");
+
+    let mut failed = false;
+    for (name, archive, required) in [("gzip", &gz, NOISE), ("zstd", &zst, min_speedup)] {
+        let baseline = time(runs, archive, |archive, target| {
+            let file = File::open(archive).unwrap();
+            if name == "gzip" {
+                sequential(flate2::read::GzDecoder::new(file), target)
+            } else {
+                sequential(zstd::stream::read::Decoder::new(file).unwrap(), target)
+            }
+        });
+        let poured = time(runs, archive, pour);
+        let compressed = fs::metadata(archive).unwrap().len() as f64 / (1 << 20) as f64;
+        println!("{name} ({compressed:.1} MiB):");
+        for (label, took) in [("sequential", baseline), ("pour", poured)] {
+            let rate = size_mb as f64 / took.as_secs_f64();
+            println!(
+                "{label:>14}: {:>8.1} ms  {rate:>7.1} MiB/s",
+                took.as_secs_f64() * 1e3
+            );
+        }
+        let speedup = baseline.as_secs_f64() / poured.as_secs_f64();
+        let verdict = if speedup >= required {
+            "ok"
+        } else {
+            "too slow"
+        };
+        println!(
+            "{:>14}: {speedup:.2}x (need {required:.2}x) {verdict}",
+            "speedup"
+        );
+        failed |= speedup < required;
+    }
+    if failed {
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,187 @@
+// sps-core/src/blocking.rs
+//! Blocking filesystem work (pouring, linking, extracting, hashing) run with the package and
+//! phase it belongs to.
+//!
+//! Every job takes a slot from one process-wide limit, so pours on the install workers and
+//! verification or hashing started elsewhere don't pile up on the disk at once. A job that
+//! already holds a slot runs nested jobs on it instead of waiting for a second one. A panic in
+//! a job is caught and returned as [`SpsError::TaskPanicked`] naming the package and phase,
+//! rather than a bare join error or a lost worker thread. Jobs keep the cancellation token of
+//! the caller, and one whose token is already cancelled doesn't start.
+
+use std::any::Any;
+use std::cell::Cell;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Condvar, Mutex};
+
+use sps_common::cancel;
+use sps_common::error::{Result, SpsError};
+use tracing::{debug, error};
+
+/// Concurrent jobs allowed until [`set_limit`] is called.
+fn default_limit() -> usize {
+    num_cpus::get_physical().max(1)
+}
+
+struct Slots {
+    /// `None` until first use or [`set_limit`].
+    limit: Option<usize>,
+    in_use: usize,
+}
+
+static SLOTS: Mutex<Slots> = Mutex::new(Slots {
+    limit: None,
+    in_use: 0,
+});
+static SLOT_FREED: Condvar = Condvar::new();
+
+thread_local! {
+    static HOLDS_SLOT: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Sets how many blocking jobs may run at once, e.g. to the number of install workers.
+pub fn set_limit(limit: usize) {
+    let mut slots = SLOTS.lock().unwrap_or_else(|e| e.into_inner());
+    slots.limit = Some(limit.max(1));
+    SLOT_FREED.notify_all();
+}
+
+/// A slot held by the current thread, returned on drop.
+struct SlotGuard {
+    acquired: bool,
+}
+
+impl SlotGuard {
+    fn acquire() -> Self {
+        if HOLDS_SLOT.with(Cell::get) {
+            return Self { acquired: false };
+        }
+        let mut slots = SLOTS.lock().unwrap_or_else(|e| e.into_inner());
+        while slots.in_use >= *slots.limit.get_or_insert_with(default_limit) {
+            slots = SLOT_FREED.wait(slots).unwrap_or_else(|e| e.into_inner());
+        }
+        slots.in_use += 1;
+        HOLDS_SLOT.with(|held| held.set(true));
+        Self { acquired: true }
+    }
+}
+
+impl Drop for SlotGuard {
+    fn drop(&mut self) {
+        if !self.acquired {
+            return;
+        }
+        HOLDS_SLOT.with(|held| held.set(false));
+        let mut slots = SLOTS.lock().unwrap_or_else(|e| e.into_inner());
+        slots.in_use = slots.in_use.saturating_sub(1);
+        SLOT_FREED.notify_one();
+    }
+}
+
+/// Runs `job` on the current thread under the shared limit, turning a panic into
+/// [`SpsError::TaskPanicked`]. For code that is already on a worker thread.
+pub fn run_sync<T>(package: &str, phase: &str, job: impl FnOnce() -> Result<T>) -> Result<T> {
+    let _slot = SlotGuard::acquire();
+    cancel::check(&format_args!("{phase} of {package}"))?;
+    panic::catch_unwind(AssertUnwindSafe(job)).unwrap_or_else(|payload| {
+        let message = panic_message(payload.as_ref());
+        error!("{} task for {} panicked: {}", phase, package, message);
+        Err(SpsError::TaskPanicked {
+            package: package.to_string(),
+            phase: phase.to_string(),
+            message,
+        })
+    })
+}
+
+/// Runs `job` on tokio's blocking threads under the shared limit, like [`run_sync`].
+pub async fn run<T, F>(package: &str, phase: &str, job: F) -> Result<T>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T> + Send + 'static,
+{
+    let (task_package, task_phase) = (package.to_string(), phase.to_string());
+    let token = cancel::current();
+    tokio::task::spawn_blocking(move || match token {
+        Some(token) => {
+            cancel::with_thread_token(token, || run_sync(&task_package, &task_phase, job))
+        }
+        None => run_sync(&task_package, &task_phase, job),
+    })
+    .await
+    .unwrap_or_else(|join_error| {
+        // `run_sync` catches panics, so only cancellation at runtime shutdown gets here.
+        debug!(
+            "{} task for {} did not finish: {}",
+            phase, package, join_error
+        );
+        Err(SpsError::Cancelled(format!(
+            "{phase} task for {package} did not finish"
+        )))
+    })
+}
+
+/// The message of a caught panic, when it has a string one.
+pub fn panic_message(payload: &(dyn Any + Send)) -> String {
+    payload
+        .downcast_ref::<&str>()
+        .map(|s| s.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "non-string panic payload".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use sps_common::cancel::CancellationToken;
+
+    use super::*;
+
+    #[test]
+    fn a_panic_becomes_task_panicked_naming_package_and_phase() {
+        let result: Result<()> = run_sync("pkg", "pour", || panic!("x"));
+        match result {
+            Err(SpsError::TaskPanicked {
+                package,
+                phase,
+                message,
+            }) => {
+                assert_eq!((package.as_str(), phase.as_str()), ("pkg", "pour"));
+                assert_eq!(message, "x");
+            }
+            other => panic!("expected TaskPanicked, got {other:?}"),
+        }
+        // The slot was given back, and the thread can take it again.
+        assert!(!HOLDS_SLOT.with(Cell::get));
+        assert_eq!(run_sync("pkg", "link", || Ok(1)).unwrap(), 1);
+    }
+
+    #[test]
+    fn nested_jobs_reuse_the_callers_slot() {
+        let nested = run_sync("pkg", "pour", || {
+            assert!(HOLDS_SLOT.with(Cell::get));
+            run_sync("pkg", "relocate", || Ok("done"))
+        });
+        assert_eq!(nested.unwrap(), "done");
+    }
+
+    #[test]
+    fn a_cancelled_job_does_not_start() {
+        let token = CancellationToken::new();
+        token.cancel();
+        let result: Result<()> =
+            cancel::with_thread_token(token, || run_sync("pkg", "pour", || panic!("must not run")));
+        assert!(matches!(result, Err(SpsError::Cancelled(_))), "{result:?}");
+    }
+
+    #[tokio::test]
+    async fn run_reports_panics_from_blocking_threads() {
+        let result: Result<()> = run("pkg", "extract", || panic!("{}", "formatted")).await;
+        match result {
+            Err(SpsError::TaskPanicked { phase, message, .. }) => {
+                assert_eq!(phase, "extract");
+                assert_eq!(message, "formatted");
+            }
+            other => panic!("expected TaskPanicked, got {other:?}"),
+        }
+    }
+}
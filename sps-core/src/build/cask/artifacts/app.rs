@@ -94,6 +94,9 @@ pub fn install_app_from_staged(
     }
 
     // --- Move/Copy from Stage ---
+    // Like Homebrew, create the app directory if it doesn't exist yet (only ever the case
+    // outside macOS, where it lives under the prefix).
+    fs::create_dir_all(&applications_dir)?;
     debug!(
         "Moving staged app {} to {}",
         staged_app_path.display(),
@@ -2,7 +2,7 @@
 
 use std::fs;
 use std::os::unix::fs::symlink;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::process::Command;
 
 use sps_common::config::Config;
@@ -90,11 +90,15 @@ pub fn install_binary(
                             continue;
                         };
 
-                        let src_path = stage_path.join(&source_rel);
-                        if !src_path.exists() {
-                            debug!("Binary source '{}' not found, skipping", src_path.display());
+                        let Some(src_path) =
+                            keep_in_caskroom(stage_path, cask_version_install_path, &source_rel)?
+                        else {
+                            debug!(
+                                "Binary source '{}' not found, skipping",
+                                stage_path.join(&source_rel).display()
+                            );
                             continue;
-                        }
+                        };
 
                         // Link into bin_dir
                         let link_path = bin_dir.join(&target_name);
@@ -116,8 +120,12 @@ pub fn install_binary(
                             target_path: src_path.clone(),
                         });
 
-                        // Also create a Caskroom symlink for reference
+                        // Also create a Caskroom symlink for reference, unless the binary
+                        // itself already sits there under that name
                         let caskroom_link = cask_version_install_path.join(&target_name);
+                        if caskroom_link == src_path {
+                            continue;
+                        }
                         let _ = fs::remove_file(&caskroom_link);
                         symlink(&link_path, &caskroom_link)?;
                         installed.push(InstalledArtifact::CaskroomLink {
@@ -135,3 +143,29 @@ pub fn install_binary(
 
     Ok(installed)
 }
+
+/// Where the binary `source_rel` lives once the install is done. The staging directory is
+/// deleted afterwards, so a staged binary is moved into the cask's Caskroom version directory, as
+/// Homebrew keeps it; a generated wrapper is there already. `None` if neither has it.
+fn keep_in_caskroom(
+    stage_path: &Path,
+    cask_version_install_path: &Path,
+    source_rel: &str,
+) -> Result<Option<PathBuf>> {
+    let kept = cask_version_install_path.join(source_rel);
+    if kept.exists() {
+        return Ok(Some(kept));
+    }
+    let staged = stage_path.join(source_rel);
+    if !staged.exists() {
+        return Ok(None);
+    }
+    if let Some(parent) = kept.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    // The staging directory may be on another filesystem.
+    if fs::rename(&staged, &kept).is_err() {
+        fs::copy(&staged, &kept)?;
+    }
+    Ok(Some(kept))
+}
@@ -1,6 +1,7 @@
 // ===== sps-core/src/build/cask/artifacts/installer.rs =====
 
-use std::path::Path;
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
 
 use sps_common::config::Config;
@@ -39,15 +40,61 @@ fn validate_argument(arg: &str) -> Result<String> {
     Ok(arg.to_string())
 }
 
+/// Copies the installer a `manual` stanza names from the stage (a temporary directory) into the
+/// Caskroom version directory, where the user can still open it once the install is done.
+fn stage_manual_installer(
+    stage_path: &Path,
+    manual: &str,
+    cask_version_install_path: &Path,
+) -> Result<PathBuf> {
+    if manual.starts_with('/') || manual.split('/').any(|c| c == "..") {
+        return Err(SpsError::Generic(format!(
+            "Invalid manual installer path: {manual}"
+        )));
+    }
+    let source = stage_path.join(manual);
+    if !source.exists() {
+        return Err(SpsError::NotFound(format!(
+            "Manual installer not found: {}",
+            source.display()
+        )));
+    }
+    let target = cask_version_install_path.join(manual);
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    if target.exists() || target.is_symlink() {
+        if target.is_dir() {
+            fs::remove_dir_all(&target)?;
+        } else {
+            fs::remove_file(&target)?;
+        }
+    }
+    let status = Command::new("cp")
+        .arg("-R")
+        .arg(&source)
+        .arg(&target)
+        .status()?;
+    if !status.success() {
+        return Err(SpsError::InstallError(format!(
+            "Failed to copy manual installer {} to {}",
+            source.display(),
+            target.display()
+        )));
+    }
+    Ok(target)
+}
+
 /// Implements the `installer` stanza:
-/// - `manual`: prints instructions to open the staged path.
+/// - `manual`: stages the installer in the Caskroom for the user to open; the pipeline reports the
+///   install as staged rather than complete.
 /// - `script`: runs the given executable with args, under sudo if requested.
 ///
 /// Mirrors Homebrew’s `Cask::Artifact::Installer` behavior :contentReference[oaicite:1]{index=1}.
 pub fn run_installer(
     cask: &Cask,
     stage_path: &Path,
-    _cask_version_install_path: &Path,
+    cask_version_install_path: &Path,
     _config: &Config,
 ) -> Result<Vec<InstalledArtifact>> {
     let mut installed = Vec::new();
@@ -60,11 +107,17 @@ pub fn run_installer(
                     for inst in insts {
                         if let Some(inst_obj) = inst.as_object() {
                             if let Some(man) = inst_obj.get("manual").and_then(|v| v.as_str()) {
+                                let path = stage_manual_installer(
+                                    stage_path,
+                                    man,
+                                    cask_version_install_path,
+                                )?;
                                 debug!(
                                     "Cask {} requires manual install. To finish:\n    open {}",
                                     cask.token,
-                                    stage_path.join(man).display()
+                                    path.display()
                                 );
+                                installed.push(InstalledArtifact::CaskroomReference { path });
                                 continue;
                             }
                             let exe_key = if inst_obj.contains_key("script") {
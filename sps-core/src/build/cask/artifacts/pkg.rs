@@ -14,7 +14,7 @@ pub fn install_pkg_from_path(
     cask: &Cask,
     pkg_path: &Path,
     cask_version_install_path: &Path, // e.g., /opt/homebrew/Caskroom/foo/1.2.3
-    _config: &Config,                 // Keep for potential future use
+    config: &Config,
 ) -> Result<Vec<InstalledArtifact>> {
     // <-- Return type changed
     debug!("Installing pkg file: {}", pkg_path.display());
@@ -71,7 +71,9 @@ pub fn install_pkg_from_path(
         "Executing: sudo installer -pkg {} -target /",
         pkg_path.display()
     );
-    let output = Command::new("sudo")
+    let mut cmd = Command::new("sudo");
+    crate::build::env::apply_subprocess_env(&mut cmd, config);
+    let output = cmd
         .arg("installer")
         .arg("-pkg")
         .arg(pkg_path)
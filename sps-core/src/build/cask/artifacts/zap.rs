@@ -109,7 +109,11 @@ pub fn install_zap(cask: &Cask, config: &Config) -> Result<Vec<InstalledArtifact
                                                     continue;
                                                 }
                                                 debug!("Forgetting pkgutil receipt {}...", item);
-                                                let _ = Command::new("pkgutil")
+                                                let mut cmd = Command::new("pkgutil");
+                                                crate::build::env::apply_subprocess_env(
+                                                    &mut cmd, config,
+                                                );
+                                                let _ = cmd
                                                     .arg("--forget")
                                                     .arg(item)
                                                     .stdout(Stdio::null())
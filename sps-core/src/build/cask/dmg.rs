@@ -5,13 +5,18 @@ use std::io::{BufRead, BufReader};
 use std::path::{Path, PathBuf};
 use std::process::Command;
 
+use sps_common::config::Config;
 use sps_common::error::{Result, SpsError};
 use tracing::{debug, error}; // Added log imports
 
+use crate::build::env::apply_subprocess_env;
+
 // --- Keep Existing Helpers ---
-pub fn mount_dmg(dmg_path: &Path) -> Result<PathBuf> {
+pub fn mount_dmg(dmg_path: &Path, config: &Config) -> Result<PathBuf> {
     debug!("Mounting DMG: {}", dmg_path.display());
-    let output = Command::new("hdiutil")
+    let mut cmd = Command::new("hdiutil");
+    apply_subprocess_env(&mut cmd, config);
+    let output = cmd
         .arg("attach")
         .arg("-plist")
         .arg("-nobrowse")
@@ -40,15 +45,13 @@ pub fn mount_dmg(dmg_path: &Path) -> Result<PathBuf> {
     Ok(mount_point)
 }
 
-pub fn unmount_dmg(mount_point: &Path) -> Result<()> {
+pub fn unmount_dmg(mount_point: &Path, config: &Config) -> Result<()> {
     debug!("Unmounting DMG from: {}", mount_point.display());
     // Add logging for commands
     debug!("Executing: hdiutil detach -force {}", mount_point.display());
-    let output = Command::new("hdiutil")
-        .arg("detach")
-        .arg("-force")
-        .arg(mount_point)
-        .output()?;
+    let mut cmd = Command::new("hdiutil");
+    apply_subprocess_env(&mut cmd, config);
+    let output = cmd.arg("detach").arg("-force").arg(mount_point).output()?;
 
     if !output.status.success() {
         let stderr = String::from_utf8_lossy(&output.stderr);
@@ -61,7 +64,9 @@ pub fn unmount_dmg(mount_point: &Path) -> Result<()> {
             "Executing: diskutil unmount force {}",
             mount_point.display()
         );
-        let diskutil_output = Command::new("diskutil")
+        let mut diskutil_cmd = Command::new("diskutil");
+        apply_subprocess_env(&mut diskutil_cmd, config);
+        let diskutil_output = diskutil_cmd
             .arg("unmount")
             .arg("force")
             .arg(mount_point)
@@ -150,8 +155,8 @@ fn parse_mount_point(output: &[u8]) -> Result<PathBuf> {
 
 // --- NEW Function ---
 /// Extracts the contents of a mounted DMG to a staging directory using `ditto`.
-pub fn extract_dmg_to_stage(dmg_path: &Path, stage_dir: &Path) -> Result<()> {
-    let mount_point = mount_dmg(dmg_path)?;
+pub fn extract_dmg_to_stage(dmg_path: &Path, stage_dir: &Path, config: &Config) -> Result<()> {
+    let mount_point = mount_dmg(dmg_path, config)?;
 
     // Ensure the stage directory exists (though TempDir should handle it)
     if !stage_dir.exists() {
@@ -170,12 +175,14 @@ pub fn extract_dmg_to_stage(dmg_path: &Path, stage_dir: &Path) -> Result<()> {
         mount_point.display(),
         stage_dir.display()
     );
-    let ditto_output = Command::new("ditto")
+    let mut ditto_cmd = Command::new("ditto");
+    apply_subprocess_env(&mut ditto_cmd, config);
+    let ditto_output = ditto_cmd
         .arg(&mount_point) // Source first
         .arg(stage_dir) // Then destination
         .output()?;
 
-    let unmount_result = unmount_dmg(&mount_point); // Unmount regardless of ditto success
+    let unmount_result = unmount_dmg(&mount_point, config); // Unmount regardless of ditto success
 
     if !ditto_output.status.success() {
         let stderr = String::from_utf8_lossy(&ditto_output.stderr);
@@ -0,0 +1,54 @@
+// sps-core/src/build/cask/lock.rs
+//! Per-cask advisory lock shared by install, upgrade and uninstall.
+//!
+//! The lock file lives directly in the Caskroom (`.<token>.lock`) rather than inside the cask's
+//! own directory, because uninstall removes that directory while still holding the lock.
+
+use std::fs::{self, File, OpenOptions};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use sps_common::config::Config;
+use sps_common::error::{Result, SpsError};
+use tracing::debug;
+
+use crate::build::flock;
+
+/// Exclusive lock on a single cask token. Released when dropped.
+#[derive(Debug)]
+pub struct CaskLock {
+    file: File,
+    path: PathBuf,
+}
+
+impl CaskLock {
+    /// Blocks until the lock for `token` is acquired.
+    pub fn acquire(token: &str, config: &Config) -> Result<Self> {
+        if token.is_empty() || token.contains('/') || token.contains("..") {
+            return Err(SpsError::Generic(format!(
+                "Invalid cask token '{token}' for lock file"
+            )));
+        }
+        let caskroom = config.caskroom_dir();
+        fs::create_dir_all(&caskroom).map_err(|e| SpsError::Io(Arc::new(e)))?;
+        let path = caskroom.join(format!(".{token}.lock"));
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&path)
+            .map_err(|e| SpsError::Io(Arc::new(e)))?;
+        debug!("Waiting for cask lock {}", path.display());
+        flock::lock_exclusive(&file).map_err(|e| SpsError::Io(Arc::new(e)))?;
+        debug!("Acquired cask lock {}", path.display());
+        Ok(Self { file, path })
+    }
+}
+
+impl Drop for CaskLock {
+    fn drop(&mut self) {
+        if let Err(e) = flock::unlock(&self.file) {
+            debug!("Failed to release cask lock {}: {}", self.path.display(), e);
+        }
+    }
+}
@@ -1,8 +1,10 @@
 pub mod artifacts;
 pub mod dmg;
+pub mod lock;
+pub mod post_install;
+pub mod preexisting;
 
 use std::fs;
-use std::io::Write;
 use std::path::{Path, PathBuf};
 use std::time::{SystemTime, SystemTimeError, UNIX_EPOCH};
 
@@ -10,14 +12,19 @@ use infer;
 use reqwest::Url;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
-use sps_common::cache::Cache;
+use sps_common::cache::{self, Cache};
+use sps_common::cancel;
 use sps_common::config::Config;
 use sps_common::error::{Result, SpsError};
-use sps_common::model::cask::{Cask, Sha256Field, UrlField};
+use sps_common::model::cask::{Cask, CaskVariant, Sha256Field, UrlField};
 use tempfile::TempDir;
-use tracing::{debug, error};
+use tracing::{debug, error, warn};
 
-use crate::build::extract;
+use crate::build::cask::post_install::{detect_post_install_actions, PostInstallAction};
+use crate::build::{downloads, extract};
+
+/// File name of the receipt in each Caskroom version directory.
+pub const CASK_MANIFEST_FILE: &str = "CASK_INSTALL_MANIFEST.json";
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 #[serde(tag = "type", rename_all = "snake_case")]
@@ -64,29 +71,212 @@ pub struct CaskInstallManifest {
     pub version: String,
     pub installed_at: u64,
     pub artifacts: Vec<InstalledArtifact>,
+    /// Steps the user still has to take before the cask is usable (approval, reboot, running a
+    /// staged installer).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub post_install_actions: Vec<PostInstallAction>,
+    /// Platform and language variation the artifact came from.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub variant: Option<CaskVariant>,
+    /// The local file or URL the definition was installed from, when it wasn't the API.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub definition_source: Option<String>,
+    /// Where the artifact was downloaded from.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_url: Option<String>,
+    /// The checksum the download was verified against; absent for `no_check` casks.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    /// Version of sps that wrote the receipt.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sps_version: Option<String>,
 }
 
-pub fn get_cask_version_path(cask: &Cask, config: &Config) -> PathBuf {
-    let version = cask.version.clone().unwrap_or_else(|| "latest".to_string());
-    config.cask_version_path(&cask.token, &version)
+impl CaskInstallManifest {
+    /// `installed_at` as an RFC 3339 timestamp.
+    pub fn installed_at_rfc3339(&self) -> Option<String> {
+        let seconds = i64::try_from(self.installed_at).ok()?;
+        chrono::DateTime::from_timestamp(seconds, 0).map(|t| t.to_rfc3339())
+    }
+
+    /// The installers that were staged but not run: while any are listed, the cask is staged
+    /// with manual action required rather than installed.
+    pub fn manual_installers(&self) -> Vec<&Path> {
+        self.post_install_actions
+            .iter()
+            .filter_map(|action| match action {
+                PostInstallAction::OpenManualInstaller { path } => Some(path.as_path()),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+/// Reads the receipt of an installed cask version. A missing receipt is `None`; an unreadable
+/// or malformed one is `None` with a warning, so callers fall back instead of failing.
+pub fn read_cask_manifest(cask_version_path: &Path) -> Option<CaskInstallManifest> {
+    let manifest_path = cask_version_path.join(CASK_MANIFEST_FILE);
+    let text = match fs::read_to_string(&manifest_path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            debug!("No cask receipt at {}", manifest_path.display());
+            return None;
+        }
+        Err(e) => {
+            warn!(
+                "Failed to read cask receipt {}: {}",
+                manifest_path.display(),
+                e
+            );
+            return None;
+        }
+    };
+    match serde_json::from_str(&text) {
+        Ok(manifest) => Some(manifest),
+        Err(e) => {
+            warn!(
+                "Ignoring malformed cask receipt {}: {}",
+                manifest_path.display(),
+                e
+            );
+            None
+        }
+    }
+}
+
+/// The Caskroom version directory of the installed cask `token` and its receipt, if one is
+/// installed with a readable receipt.
+pub fn installed_receipt(config: &Config, token: &str) -> Option<(PathBuf, CaskInstallManifest)> {
+    fs::read_dir(config.cask_dir(token))
+        .ok()?
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.join(CASK_MANIFEST_FILE).is_file())
+        .find_map(|path| read_cask_manifest(&path).map(|manifest| (path, manifest)))
 }
 
-pub async fn download_cask(cask: &Cask, cache: &Cache) -> Result<PathBuf> {
-    let url_field = cask
-        .url
-        .as_ref()
-        .ok_or_else(|| SpsError::Generic(format!("Cask {} has no URL", cask.token)))?;
-    let url_str = match url_field {
+/// The cask's download URL, if it has a usable one.
+pub fn download_url(cask: &Cask) -> Option<&str> {
+    let url = match cask.url.as_ref()? {
         UrlField::Simple(u) => u.as_str(),
         UrlField::WithSpec { url, .. } => url.as_str(),
     };
+    (!url.is_empty()).then_some(url)
+}
 
-    if url_str.is_empty() {
-        return Err(SpsError::Generic(format!(
-            "Cask {} has an empty URL",
-            cask.token
-        )));
+/// The SHA256 the download is verified against; empty when the cask has none, including the
+/// API's string form of `no_check`.
+fn expected_sha256(cask: &Cask) -> &str {
+    match cask.sha256.as_ref() {
+        Some(Sha256Field::Hex(s)) if s != "no_check" => s.as_str(),
+        _ => "",
     }
+}
+
+/// `cask` with the variation for this machine's platform and the configured languages applied;
+/// see [`Cask::resolve_variant`]. Everything downstream (download, Caskroom path, receipt) uses
+/// the result.
+pub fn resolve_for_host(cask: &Cask, config: &Config) -> Cask {
+    cask.resolve_variant(
+        crate::build::formula::host_platform(),
+        &config.cask_languages,
+    )
+}
+
+/// The variant recorded in an installed cask version's receipt, if any.
+pub fn installed_variant(cask_version_path: &Path) -> Option<CaskVariant> {
+    read_cask_manifest(cask_version_path)?.variant
+}
+
+/// The definition source recorded in an installed cask version's receipt, if it wasn't the API.
+pub fn installed_definition_source(cask_version_path: &Path) -> Option<String> {
+    read_cask_manifest(cask_version_path)?.definition_source
+}
+
+/// How a cask is present on this machine, compared with the definition being installed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CaskInstallState {
+    /// No Caskroom version directory has a receipt (an empty or stray folder doesn't count).
+    NotInstalled,
+    /// The receipt of the definition's version is there and its primary artifacts exist.
+    Installed,
+    /// Only another version has a receipt (`version`, at `path`).
+    OtherVersion { version: String, path: PathBuf },
+    /// The receipt of the definition's version lists artifacts that are gone, e.g. an app that
+    /// was deleted from /Applications by hand.
+    Broken {
+        version: String,
+        path: PathBuf,
+        missing: Vec<PathBuf>,
+    },
+}
+
+/// Checks the Caskroom for `cask`: the receipt of its current version, and whether the apps and
+/// binary links that receipt records still exist.
+pub fn install_state(cask: &Cask, config: &Config) -> CaskInstallState {
+    let current = get_cask_version_path(cask, config);
+    if let Some(missing) = missing_artifacts(&current) {
+        let version = cask.version.clone().unwrap_or_else(|| "latest".to_string());
+        return if missing.is_empty() {
+            CaskInstallState::Installed
+        } else {
+            CaskInstallState::Broken {
+                version,
+                path: current,
+                missing,
+            }
+        };
+    }
+    let Ok(entries) = fs::read_dir(config.cask_dir(&cask.token)) else {
+        return CaskInstallState::NotInstalled;
+    };
+    entries
+        .flatten()
+        .map(|entry| entry.path())
+        .find(|path| path.join(CASK_MANIFEST_FILE).is_file())
+        .map_or(CaskInstallState::NotInstalled, |path| {
+            CaskInstallState::OtherVersion {
+                version: path
+                    .file_name()
+                    .map(|n| n.to_string_lossy().to_string())
+                    .unwrap_or_default(),
+                path,
+            }
+        })
+}
+
+/// The primary artifacts (apps, Caskroom and binary links) recorded in the receipt of the cask
+/// version at `cask_version_path` that no longer exist or dangle; `None` when there is no readable
+/// receipt. Package receipts and launchd jobs aren't checked.
+pub fn missing_artifacts(cask_version_path: &Path) -> Option<Vec<PathBuf>> {
+    let manifest = read_cask_manifest(cask_version_path)?;
+    Some(
+        manifest
+            .artifacts
+            .iter()
+            .filter_map(|artifact| match artifact {
+                InstalledArtifact::App { path } => Some(path),
+                InstalledArtifact::CaskroomLink { link_path, .. }
+                | InstalledArtifact::BinaryLink { link_path, .. } => Some(link_path),
+                _ => None,
+            })
+            .filter(|path| !path.exists())
+            .cloned()
+            .collect(),
+    )
+}
+
+pub fn get_cask_version_path(cask: &Cask, config: &Config) -> PathBuf {
+    let version = cask.version.clone().unwrap_or_else(|| "latest".to_string());
+    config.cask_version_path(&cask.token, &version)
+}
+
+pub async fn download_cask(cask: &Cask, cache: &Cache, config: &Config) -> Result<PathBuf> {
+    if cask.url.is_none() {
+        return Err(SpsError::Generic(format!("Cask {} has no URL", cask.token)));
+    }
+    let url_str = download_url(cask)
+        .ok_or_else(|| SpsError::Generic(format!("Cask {} has an empty URL", cask.token)))?;
 
     debug!("Downloading cask from {}", url_str);
     let parsed = Url::parse(url_str)
@@ -101,12 +291,65 @@ pub async fn download_cask(cask: &Cask, cache: &Cache) -> Result<PathBuf> {
             debug!("URL has no filename component, using fallback name for cache based on token.");
             format!("cask-{}-download.tmp", cask.token.replace('/', "_"))
         });
-    let cache_key = format!("cask-{}-{}", cask.token, file_name);
-    let cache_path = cache.get_dir().join(&cache_key);
+    let expected_sha256 = expected_sha256(cask);
+    let cache_key = format!(
+        "cask-{}-{}-{}",
+        cask.token,
+        cache::origin_tag(expected_sha256, url_str),
+        file_name
+    );
+    let cache_path = cache.get_download_dir().join(&cache_key);
+    let path = downloads::coalesce(&cache_path, || {
+        fetch_cask(
+            cask,
+            cache,
+            config,
+            &parsed,
+            &cache_key,
+            &cache_path,
+            &file_name,
+        )
+    })
+    .await?;
+    if let Err(e) = sps_net::signature::verify_artifact(&path, url_str, &cask.token, config).await {
+        evict_cached_download(&path);
+        return Err(e);
+    }
+    Ok(path)
+}
+
+/// Returns the download cached at `cache_path` if it is still valid, else fetches it there.
+async fn fetch_cask(
+    cask: &Cask,
+    cache: &Cache,
+    config: &Config,
+    parsed: &Url,
+    cache_key: &str,
+    cache_path: &Path,
+    file_name: &str,
+) -> Result<PathBuf> {
+    let url_str = parsed.as_str();
+    let expected_sha256 = expected_sha256(cask);
+    let legacy_path = cache
+        .get_download_dir()
+        .join(format!("cask-{}-{}", cask.token, file_name));
+    cache::migrate_legacy_entry(&legacy_path, cache_path, |path| {
+        !expected_sha256.is_empty()
+            && sps_net::validation::verify_checksum(path, expected_sha256).is_ok()
+    });
 
     if cache_path.exists() {
-        debug!("Using cached download: {}", cache_path.display());
-        return Ok(cache_path);
+        if expected_sha256.is_empty()
+            || sps_net::validation::verify_checksum(cache_path, expected_sha256).is_ok()
+        {
+            debug!("Using cached download: {}", cache_path.display());
+            return Ok(cache_path.to_path_buf());
+        }
+        tracing::warn!(
+            "Cached download {} failed checksum verification. Re-downloading.",
+            cache_path.display()
+        );
+        evict_cached_download(cache_path);
     }
 
     let client = reqwest::Client::new();
@@ -122,41 +365,54 @@ pub async fn download_cask(cask: &Cask, cache: &Cache) -> Result<PathBuf> {
             format!("HTTP status {}", response.status()),
         ));
     }
-    let bytes = response
-        .bytes()
-        .await
-        .map_err(|e| SpsError::Http(std::sync::Arc::new(e)))?;
-    if let Some(parent) = cache_path.parent() {
-        fs::create_dir_all(parent)?;
-    }
-    let mut file = fs::File::create(&cache_path)?;
-    file.write_all(&bytes)?;
-    let expected_sha256 = match cask.sha256.as_ref() {
-        Some(Sha256Field::Hex(s)) => s.as_str(),
-        _ => "",
-    };
-    if !expected_sha256.is_empty() {
-        match sps_net::validation::verify_checksum(&cache_path, expected_sha256) {
-            Ok(_) => {
-                tracing::debug!("Cask download checksum verified: {}", cache_path.display());
-            }
-            Err(e) => {
-                tracing::error!(
-                    "Cask download checksum mismatch ({}). Deleting cached file.",
-                    e
-                );
-                let _ = fs::remove_file(&cache_path);
-                return Err(e);
-            }
+    let cache_dir = cache_path
+        .parent()
+        .unwrap_or_else(|| cache.get_download_dir());
+    fs::create_dir_all(cache_dir)?;
+    // Stream to a temp file next to the final path and rename once verified, so an interrupted
+    // download never leaves a truncated artifact under the cached name. The temp file is removed
+    // on drop if we bail out early.
+    let mut temp_file = tempfile::Builder::new()
+        .prefix(&format!(".{cache_key}."))
+        .suffix(".part")
+        .tempfile_in(cache_dir)?;
+    sps_net::fetch::stream::stream_response_to_file(
+        response,
+        &mut temp_file,
+        &cask.token,
+        expected_sha256,
+        config.max_download_size,
+    )
+    .await
+    .map_err(|e| match e {
+        SpsError::DownloadError(_, _, reason) => {
+            SpsError::DownloadError(cask.token.clone(), url_str.to_string(), reason)
         }
-    } else {
+        other => other,
+    })?;
+    if expected_sha256.is_empty() {
         tracing::warn!(
             "Skipping checksum verification for cask {} - none provided.",
             cache_path.display()
         );
+    } else {
+        tracing::debug!("Cask download checksum verified: {}", cache_path.display());
     }
+    temp_file
+        .persist(cache_path)
+        .map_err(|e| SpsError::Io(std::sync::Arc::new(e.error)))?;
     debug!("Download completed: {}", cache_path.display());
-    Ok(cache_path)
+    Ok(cache_path.to_path_buf())
+}
+
+/// Removes a cached cask download that turned out to be unusable.
+pub fn evict_cached_download(path: &Path) {
+    debug!("Evicting cached download: {}", path.display());
+    if let Err(e) = fs::remove_file(path) {
+        if e.kind() != std::io::ErrorKind::NotFound {
+            tracing::warn!("Failed to evict cached download {}: {}", path.display(), e);
+        }
+    }
 }
 
 pub fn install_cask(cask: &Cask, download_path: &Path, config: &Config) -> Result<()> {
@@ -178,48 +434,7 @@ pub fn install_cask(cask: &Cask, download_path: &Path, config: &Config) -> Resul
             cask_version_install_path.display()
         );
     }
-    let mut detected_extension = download_path
-        .extension()
-        .and_then(|e| e.to_str())
-        .unwrap_or("")
-        .to_lowercase();
-    let non_extensions = ["stable", "latest", "download", "bin", ""];
-    if non_extensions.contains(&detected_extension.as_str()) {
-        debug!(
-            "Download path '{}' has no definite extension ('{}'), attempting content detection.",
-            download_path.display(),
-            detected_extension
-        );
-        match infer::get_from_path(download_path) {
-            Ok(Some(kind)) => {
-                detected_extension = kind.extension().to_string();
-                debug!("Detected file type via content: {}", detected_extension);
-            }
-            Ok(None) => {
-                error!(
-                    "Could not determine file type from content for: {}",
-                    download_path.display()
-                );
-                return Err(SpsError::Generic(format!(
-                    "Could not determine file type for download: {}",
-                    download_path.display()
-                )));
-            }
-            Err(e) => {
-                error!(
-                    "Error reading file for type detection {}: {}",
-                    download_path.display(),
-                    e
-                );
-                return Err(SpsError::Io(std::sync::Arc::new(e)));
-            }
-        }
-    } else {
-        debug!(
-            "Using file extension for type detection: {}",
-            detected_extension
-        );
-    }
+    let detected_extension = container_extension(download_path)?;
     if detected_extension == "pkg" || detected_extension == "mpkg" {
         debug!("Detected PKG installer, running directly");
         match artifacts::pkg::install_pkg_from_path(
@@ -272,7 +487,7 @@ pub fn install_cask(cask: &Cask, download_path: &Path, config: &Config) -> Resul
             tracing::error!("Content type verification failed: {}", e);
             // Attempt cleanup?
             let _ = fs::remove_dir_all(&cask_version_install_path);
-            return Err(e);
+            return Err(SpsError::ArtifactUnpackError(e.to_string()));
         }
     } else {
         tracing::debug!(
@@ -281,47 +496,9 @@ pub fn install_cask(cask: &Cask, download_path: &Path, config: &Config) -> Resul
             expected_ext
         );
     }
-    match detected_extension.as_str() {
-        "dmg" => {
-            debug!(
-                "Extracting DMG {} to stage {}...",
-                download_path.display(),
-                stage_path.display()
-            );
-            dmg::extract_dmg_to_stage(download_path, stage_path)?;
-            debug!("Successfully extracted DMG to staging area.");
-        }
-        "zip" => {
-            debug!(
-                "Extracting ZIP {} to stage {}...",
-                download_path.display(),
-                stage_path.display()
-            );
-            extract::extract_archive(download_path, stage_path, 0, "zip")?;
-            debug!("Successfully extracted ZIP to staging area.");
-        }
-        "gz" | "bz2" | "xz" | "tar" => {
-            let archive_type_for_extraction = detected_extension.as_str();
-            debug!(
-                "Extracting TAR archive ({}) {} to stage {}...",
-                archive_type_for_extraction,
-                download_path.display(),
-                stage_path.display()
-            );
-            extract::extract_archive(download_path, stage_path, 0, archive_type_for_extraction)?;
-            debug!("Successfully extracted TAR archive to staging area.");
-        }
-        _ => {
-            error!(
-                "Unsupported container/installer type '{}' for staged installation derived from {}",
-                detected_extension,
-                download_path.display()
-            );
-            return Err(SpsError::Generic(format!(
-                "Unsupported file type for staged installation: {detected_extension}"
-            )));
-        }
-    }
+    extract_container(download_path, &detected_extension, stage_path, config)?;
+    // Last point to stop before anything is placed outside the staging directory.
+    cancel::check(&format_args!("Installing cask {}", cask.token))?;
     let mut all_installed_artifacts: Vec<InstalledArtifact> = Vec::new();
     let mut artifact_install_errors = Vec::new();
     if let Some(artifacts_def) = &cask.artifacts {
@@ -597,6 +774,108 @@ pub fn install_cask(cask: &Cask, download_path: &Path, config: &Config) -> Resul
     Ok(())
 }
 
+/// The container type of a cask download (`dmg`, `zip`, `pkg`, ...), from its extension or, for
+/// URLs without a useful one, its content.
+pub fn container_extension(download_path: &Path) -> Result<String> {
+    let mut detected_extension = download_path
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("")
+        .to_lowercase();
+    let non_extensions = ["stable", "latest", "download", "bin", ""];
+    if non_extensions.contains(&detected_extension.as_str()) {
+        debug!(
+            "Download path '{}' has no definite extension ('{}'), attempting content detection.",
+            download_path.display(),
+            detected_extension
+        );
+        match infer::get_from_path(download_path) {
+            Ok(Some(kind)) => {
+                detected_extension = kind.extension().to_string();
+                debug!("Detected file type via content: {}", detected_extension);
+            }
+            Ok(None) => {
+                error!(
+                    "Could not determine file type from content for: {}",
+                    download_path.display()
+                );
+                return Err(SpsError::Generic(format!(
+                    "Could not determine file type for download: {}",
+                    download_path.display()
+                )));
+            }
+            Err(e) => {
+                error!(
+                    "Error reading file for type detection {}: {}",
+                    download_path.display(),
+                    e
+                );
+                return Err(SpsError::Io(std::sync::Arc::new(e)));
+            }
+        }
+    } else {
+        debug!(
+            "Using file extension for type detection: {}",
+            detected_extension
+        );
+    }
+    Ok(detected_extension)
+}
+
+/// Unpacks a DMG (mounted read-only), zip or tar container into `dest_dir`.
+pub fn extract_container(
+    download_path: &Path,
+    extension: &str,
+    dest_dir: &Path,
+    config: &Config,
+) -> Result<()> {
+    match extension {
+        "dmg" => {
+            debug!(
+                "Extracting DMG {} to stage {}...",
+                download_path.display(),
+                dest_dir.display()
+            );
+            dmg::extract_dmg_to_stage(download_path, dest_dir, config)
+                .map_err(|e| SpsError::ArtifactUnpackError(e.to_string()))?;
+            debug!("Successfully extracted DMG to staging area.");
+        }
+        "zip" => {
+            debug!(
+                "Extracting ZIP {} to stage {}...",
+                download_path.display(),
+                dest_dir.display()
+            );
+            extract::extract_archive(download_path, dest_dir, 0, "zip")
+                .map_err(|e| SpsError::ArtifactUnpackError(e.to_string()))?;
+            debug!("Successfully extracted ZIP to staging area.");
+        }
+        "gz" | "bz2" | "xz" | "tar" => {
+            let archive_type_for_extraction = extension;
+            debug!(
+                "Extracting TAR archive ({}) {} to stage {}...",
+                archive_type_for_extraction,
+                download_path.display(),
+                dest_dir.display()
+            );
+            extract::extract_archive(download_path, dest_dir, 0, archive_type_for_extraction)
+                .map_err(|e| SpsError::ArtifactUnpackError(e.to_string()))?;
+            debug!("Successfully extracted TAR archive to staging area.");
+        }
+        _ => {
+            error!(
+                "Unsupported container/installer type '{}' for staged installation derived from {}",
+                extension,
+                download_path.display()
+            );
+            return Err(SpsError::Generic(format!(
+                "Unsupported file type for staged installation: {extension}"
+            )));
+        }
+    }
+    Ok(())
+}
+
 #[deprecated(note = "Use write_cask_manifest with detailed InstalledArtifact enum instead")]
 pub fn write_receipt(
     cask: &Cask,
@@ -630,23 +909,32 @@ pub fn write_receipt(
     Ok(())
 }
 
+/// Writes the receipt of an installed cask version, atomically: it only appears once every
+/// artifact is in place, so a receipt always describes a complete install.
 pub fn write_cask_manifest(
     cask: &Cask,
     cask_version_install_path: &Path,
     artifacts: Vec<InstalledArtifact>,
 ) -> Result<()> {
-    let manifest_path = cask_version_install_path.join("CASK_INSTALL_MANIFEST.json");
+    let manifest_path = cask_version_install_path.join(CASK_MANIFEST_FILE);
     debug!("Writing cask manifest: {}", manifest_path.display());
     let timestamp = SystemTime::now()
         .duration_since(UNIX_EPOCH)
         .map_err(|e: SystemTimeError| SpsError::Generic(format!("System time error: {e}")))?
         .as_secs();
+    let sha256 = expected_sha256(cask);
     let manifest_data = CaskInstallManifest {
         manifest_format_version: "1.0".to_string(),
         token: cask.token.clone(),
         version: cask.version.clone().unwrap_or_else(|| "latest".to_string()),
         installed_at: timestamp,
         artifacts,
+        post_install_actions: detect_post_install_actions(cask, cask_version_install_path),
+        variant: cask.variant.clone(),
+        definition_source: cask.definition_source.clone(),
+        source_url: download_url(cask).map(str::to_string),
+        sha256: (!sha256.is_empty()).then(|| sha256.to_string()),
+        sps_version: Some(env!("CARGO_PKG_VERSION").to_string()),
     };
     if let Some(parent) = manifest_path.parent() {
         fs::create_dir_all(parent).map_err(|e| {
@@ -656,23 +944,208 @@ pub fn write_cask_manifest(
             )))
         })?;
     }
-    let file = fs::File::create(&manifest_path).map_err(|e| {
-        SpsError::Io(std::sync::Arc::new(std::io::Error::new(
-            e.kind(),
-            format!("Failed create manifest {}: {}", manifest_path.display(), e),
-        )))
-    })?;
-    let writer = std::io::BufWriter::new(file);
-    serde_json::to_writer_pretty(writer, &manifest_data).map_err(|e| {
+    let json = serde_json::to_string_pretty(&manifest_data).map_err(|e| {
         error!(
             "Failed to serialize cask manifest JSON for {}: {}",
             cask.token, e
         );
         SpsError::Json(std::sync::Arc::new(e))
     })?;
+    let temp_path = manifest_path.with_extension("json.tmp");
+    fs::write(&temp_path, json)
+        .and_then(|()| fs::rename(&temp_path, &manifest_path))
+        .map_err(|e| {
+            let _ = fs::remove_file(&temp_path);
+            SpsError::Io(std::sync::Arc::new(std::io::Error::new(
+                e.kind(),
+                format!("Failed write manifest {}: {}", manifest_path.display(), e),
+            )))
+        })?;
     debug!(
         "Successfully wrote cask manifest with {} artifact entries.",
         manifest_data.artifacts.len()
     );
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHA: &str = "502de570c6a5facc3c7c4d81d979eadbf738e8ba12064406e69e996442b083c7";
+
+    fn cask(sha256: serde_json::Value) -> Cask {
+        serde_json::from_value(json!({
+            "token": "viewer",
+            "version": "1.5",
+            "url": "https://example.com/viewer-1.5.tar.gz",
+            "sha256": sha256,
+            "artifacts": [{ "binary": ["viewer"] }],
+        }))
+        .unwrap()
+    }
+
+    fn binary(dir: &Path) -> InstalledArtifact {
+        InstalledArtifact::BinaryLink {
+            link_path: dir.join("bin/viewer"),
+            target_path: dir.join("viewer"),
+        }
+    }
+
+    #[test]
+    fn the_receipt_records_source_checksum_and_version_and_reads_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let version_dir = dir.path().join("viewer/1.5");
+
+        write_cask_manifest(&cask(json!(SHA)), &version_dir, vec![binary(dir.path())]).unwrap();
+        let receipt = read_cask_manifest(&version_dir).unwrap();
+
+        assert_eq!(receipt.token, "viewer");
+        assert_eq!(receipt.version, "1.5");
+        assert_eq!(
+            receipt.source_url.as_deref(),
+            Some("https://example.com/viewer-1.5.tar.gz")
+        );
+        assert_eq!(receipt.sha256.as_deref(), Some(SHA));
+        assert_eq!(
+            receipt.sps_version.as_deref(),
+            Some(env!("CARGO_PKG_VERSION"))
+        );
+        assert_eq!(receipt.artifacts, [binary(dir.path())]);
+        assert!(receipt.installed_at_rfc3339().is_some());
+        let files: Vec<_> = fs::read_dir(&version_dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name())
+            .collect();
+        assert_eq!(files, [CASK_MANIFEST_FILE]);
+    }
+
+    #[test]
+    fn a_no_check_cask_records_no_checksum() {
+        for sha256 in [json!("no_check"), json!({ "no_check": true })] {
+            let dir = tempfile::tempdir().unwrap();
+            let cask = cask(sha256);
+
+            write_cask_manifest(&cask, dir.path(), Vec::new()).unwrap();
+
+            assert_eq!(expected_sha256(&cask), "");
+            assert_eq!(read_cask_manifest(dir.path()).unwrap().sha256, None);
+        }
+    }
+
+    #[test]
+    fn a_missing_or_malformed_receipt_reads_as_none() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(read_cask_manifest(dir.path()).is_none());
+
+        fs::write(dir.path().join(CASK_MANIFEST_FILE), "{").unwrap();
+        assert!(read_cask_manifest(dir.path()).is_none());
+
+        fs::write(
+            dir.path().join(CASK_MANIFEST_FILE),
+            r#"{"token": "viewer"}"#,
+        )
+        .unwrap();
+        assert!(read_cask_manifest(dir.path()).is_none());
+    }
+
+    #[test]
+    fn receipts_written_before_the_source_fields_still_read() {
+        let dir = tempfile::tempdir().unwrap();
+        let old = json!({
+            "manifest_format_version": "1.0",
+            "token": "viewer",
+            "version": "1.4",
+            "installed_at": 0,
+            "artifacts": [],
+        });
+        fs::write(dir.path().join(CASK_MANIFEST_FILE), old.to_string()).unwrap();
+
+        let receipt = read_cask_manifest(dir.path()).unwrap();
+
+        assert_eq!(receipt.version, "1.4");
+        assert_eq!(receipt.source_url, None);
+        assert_eq!(receipt.sps_version, None);
+    }
+
+    #[test]
+    fn the_installed_receipt_skips_version_dirs_without_a_readable_one() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            prefix: dir.path().to_path_buf(),
+            cellar: dir.path().join("Cellar"),
+            ..Config::load().unwrap()
+        };
+        let cask_dir = config.cask_dir("viewer");
+        fs::create_dir_all(cask_dir.join("1.4")).unwrap();
+        fs::write(cask_dir.join("1.4").join(CASK_MANIFEST_FILE), "not json").unwrap();
+        write_cask_manifest(&cask(json!(SHA)), &cask_dir.join("1.5"), Vec::new()).unwrap();
+
+        let (path, receipt) = installed_receipt(&config, "viewer").unwrap();
+
+        assert_eq!(path, cask_dir.join("1.5"));
+        assert_eq!(receipt.version, "1.5");
+    }
+
+    #[test]
+    fn the_four_caskroom_states_are_told_apart() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            prefix: dir.path().to_path_buf(),
+            cellar: dir.path().join("Cellar"),
+            ..Config::load().unwrap()
+        };
+        let cask = cask(json!(SHA));
+        let cask_dir = config.cask_dir("viewer");
+        let app = dir.path().join("Applications/Viewer.app");
+        let link = dir.path().join("bin/viewer");
+        let artifacts = vec![
+            InstalledArtifact::App { path: app.clone() },
+            InstalledArtifact::BinaryLink {
+                link_path: link.clone(),
+                target_path: cask_dir.join("1.5/viewer"),
+            },
+        ];
+
+        // A stray folder without a receipt is not an install.
+        fs::create_dir_all(cask_dir.join("1.5")).unwrap();
+        assert_eq!(
+            install_state(&cask, &config),
+            CaskInstallState::NotInstalled
+        );
+
+        let older: Cask = serde_json::from_value(json!({
+            "token": "viewer",
+            "version": "1.4",
+            "artifacts": [{ "binary": ["viewer"] }],
+        }))
+        .unwrap();
+        write_cask_manifest(&older, &cask_dir.join("1.4"), Vec::new()).unwrap();
+        assert_eq!(
+            install_state(&cask, &config),
+            CaskInstallState::OtherVersion {
+                version: "1.4".to_string(),
+                path: cask_dir.join("1.4"),
+            }
+        );
+
+        fs::create_dir_all(app.join("Contents")).unwrap();
+        fs::create_dir_all(link.parent().unwrap()).unwrap();
+        fs::write(cask_dir.join("1.5/viewer"), "#!/bin/sh\n").unwrap();
+        std::os::unix::fs::symlink(cask_dir.join("1.5/viewer"), &link).unwrap();
+        write_cask_manifest(&cask, &cask_dir.join("1.5"), artifacts).unwrap();
+        assert_eq!(install_state(&cask, &config), CaskInstallState::Installed);
+
+        // The app deleted by hand, and the binary link left dangling.
+        fs::remove_dir_all(&app).unwrap();
+        fs::remove_file(cask_dir.join("1.5/viewer")).unwrap();
+        assert_eq!(
+            install_state(&cask, &config),
+            CaskInstallState::Broken {
+                version: "1.5".to_string(),
+                path: cask_dir.join("1.5"),
+                missing: vec![app, link],
+            }
+        );
+    }
+}
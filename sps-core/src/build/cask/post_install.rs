@@ -0,0 +1,314 @@
+// sps-core/src/build/cask/post_install.rs
+//! Detects casks that are not fully usable until the user does something after installation:
+//! approve a kernel or system extension in System Settings, reboot, run an installer that was
+//! only staged (`installer manual:`), or carry out `postflight` steps sps does not run.
+//!
+//! Cask metadata has no dedicated field for most of this, so detection combines the artifact
+//! list (`kext` entries in `uninstall` stanzas, `system_extension`, `installer` and `postflight`
+//! keys) with well-known caveat phrasing.
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sps_common::config::Config;
+use sps_common::model::cask::Cask;
+use tracing::debug;
+
+use crate::build::env::apply_subprocess_env;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PostInstallAction {
+    /// A kernel extension must be allowed under Privacy & Security.
+    ApproveKernelExtension,
+    /// A system extension must be allowed under Privacy & Security (or Login Items & Extensions).
+    ApproveSystemExtension,
+    /// The cask only takes effect after a restart.
+    Reboot,
+    /// The cask's installer was staged in the Caskroom, not run: the user has to open it.
+    OpenManualInstaller { path: PathBuf },
+    /// `postflight` steps the cask declares, which sps does not run on its own.
+    ReviewPostflight { commands: Vec<String> },
+}
+
+impl PostInstallAction {
+    pub fn describe(&self) -> String {
+        match self {
+            PostInstallAction::ApproveKernelExtension => {
+                "Allow the kernel extension in System Settings → Privacy & Security".to_string()
+            }
+            PostInstallAction::ApproveSystemExtension => {
+                "Allow the system extension in System Settings → Privacy & Security".to_string()
+            }
+            PostInstallAction::Reboot => "Restart your Mac to finish the installation".to_string(),
+            PostInstallAction::OpenManualInstaller { path } => format!(
+                "Open {} and follow its instructions to finish the installation",
+                path.display()
+            ),
+            PostInstallAction::ReviewPostflight { .. } => {
+                "Run the postflight steps below yourself if you need them; sps did not".to_string()
+            }
+        }
+    }
+
+    /// Whether the install only staged the cask and stays incomplete until the user acts.
+    pub fn is_manual_install(&self) -> bool {
+        matches!(self, PostInstallAction::OpenManualInstaller { .. })
+    }
+}
+
+/// Returns the follow-up actions `cask` needs, in a stable order without duplicates. Paths of
+/// staged installers are resolved against `cask_version_path`, its Caskroom version directory.
+pub fn detect_post_install_actions(
+    cask: &Cask,
+    cask_version_path: &Path,
+) -> Vec<PostInstallAction> {
+    let mut kext = false;
+    let mut system_extension = false;
+    let mut reboot = false;
+    let mut manual_installers = Vec::new();
+    let mut postflight = Vec::new();
+
+    for artifact in cask.artifacts.iter().flatten() {
+        let Some(obj) = artifact.as_object() else {
+            continue;
+        };
+        if obj.contains_key("system_extension") {
+            system_extension = true;
+        }
+        for installer in obj
+            .get("installer")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+        {
+            if let Some(manual) = installer.get("manual").and_then(Value::as_str) {
+                let path = cask_version_path.join(manual);
+                if !manual_installers.contains(&path) {
+                    manual_installers.push(path);
+                }
+            }
+        }
+        if let Some(commands) = obj.get("postflight").and_then(Value::as_array) {
+            postflight.extend(
+                commands
+                    .iter()
+                    .filter_map(Value::as_str)
+                    .map(str::to_string),
+            );
+        }
+        for nested in ["uninstall", "zap"] {
+            if let Some(entries) = obj.get(nested).and_then(Value::as_array) {
+                kext |= entries
+                    .iter()
+                    .any(|e| e.as_object().is_some_and(|o| o.contains_key("kext")));
+            }
+        }
+    }
+    if let Some(uninstall) = &cask.uninstall {
+        kext |= uninstall.contains_key("kext");
+    }
+
+    if let Some(caveats) = &cask.caveats {
+        let caveats = caveats.to_lowercase();
+        kext |= caveats.contains("kernel extension") || caveats.contains("kext");
+        system_extension |= caveats.contains("system extension");
+        reboot |= caveats.contains("must reboot")
+            || caveats.contains("must restart")
+            || caveats.contains("requires a reboot")
+            || caveats.contains("requires a restart");
+    }
+
+    let mut actions: Vec<PostInstallAction> = manual_installers
+        .into_iter()
+        .map(|path| PostInstallAction::OpenManualInstaller { path })
+        .collect();
+    if kext {
+        actions.push(PostInstallAction::ApproveKernelExtension);
+    }
+    if system_extension {
+        actions.push(PostInstallAction::ApproveSystemExtension);
+    }
+    if reboot {
+        actions.push(PostInstallAction::Reboot);
+    }
+    if !postflight.is_empty() {
+        actions.push(PostInstallAction::ReviewPostflight {
+            commands: postflight,
+        });
+    }
+    actions
+}
+
+/// Polls `systemextensionsctl list` until no extension is waiting for user approval or
+/// `timeout` elapses. Returns `true` if nothing is pending anymore.
+pub fn wait_for_system_extension_approval(config: &Config, timeout: Duration) -> bool {
+    let deadline = Instant::now() + timeout;
+    loop {
+        let mut command = Command::new("systemextensionsctl");
+        command.arg("list");
+        apply_subprocess_env(&mut command, config);
+        match command.output() {
+            Ok(output) => {
+                let listing = String::from_utf8_lossy(&output.stdout);
+                if !listing.contains("waiting for user") {
+                    return true;
+                }
+            }
+            Err(e) => {
+                debug!("Could not run systemextensionsctl: {}", e);
+                return false;
+            }
+        }
+        if Instant::now() >= deadline {
+            return false;
+        }
+        thread::sleep(Duration::from_secs(2));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn cask(extra: Value) -> Cask {
+        let mut definition = json!({
+            "token": "driver",
+            "version": "2.0",
+            "url": "https://example.com/driver-2.0.dmg",
+            "sha256": "no_check",
+        });
+        definition
+            .as_object_mut()
+            .unwrap()
+            .extend(extra.as_object().unwrap().clone());
+        serde_json::from_value(definition).unwrap()
+    }
+
+    fn detect(extra: Value) -> Vec<PostInstallAction> {
+        detect_post_install_actions(&cask(extra), Path::new("/Caskroom/driver/2.0"))
+    }
+
+    #[test]
+    fn a_plain_app_needs_nothing() {
+        assert_eq!(
+            detect(json!({ "artifacts": [{ "app": ["Driver.app"] }] })),
+            []
+        );
+        assert_eq!(detect(json!({ "caveats": "Driver is fast." })), []);
+    }
+
+    #[test]
+    fn manual_installers_resolve_against_the_caskroom_once_each() {
+        let actions = detect(json!({ "artifacts": [
+            { "installer": [{ "manual": "Install Driver.app" }] },
+            { "installer": [
+                { "manual": "Install Driver.app" },
+                { "manual": "Extras/Install Tools.pkg" },
+                { "script": { "executable": "setup.sh" } },
+            ] },
+        ] }));
+
+        assert_eq!(
+            actions,
+            [
+                PostInstallAction::OpenManualInstaller {
+                    path: PathBuf::from("/Caskroom/driver/2.0/Install Driver.app"),
+                },
+                PostInstallAction::OpenManualInstaller {
+                    path: PathBuf::from("/Caskroom/driver/2.0/Extras/Install Tools.pkg"),
+                },
+            ]
+        );
+        assert!(actions.iter().all(PostInstallAction::is_manual_install));
+    }
+
+    #[test]
+    fn postflight_commands_are_collected_in_order() {
+        let actions = detect(json!({ "artifacts": [
+            { "postflight": ["defaults write com.example.driver Ready -bool true"] },
+            { "app": ["Driver.app"] },
+            { "postflight": ["killall cfprefsd", 42] },
+        ] }));
+
+        assert_eq!(
+            actions,
+            [PostInstallAction::ReviewPostflight {
+                commands: vec![
+                    "defaults write com.example.driver Ready -bool true".to_string(),
+                    "killall cfprefsd".to_string(),
+                ],
+            }]
+        );
+        assert!(!actions[0].is_manual_install());
+    }
+
+    #[test]
+    fn extensions_are_found_in_artifacts_and_uninstall_stanzas() {
+        let approve_kext = [PostInstallAction::ApproveKernelExtension];
+        assert_eq!(
+            detect(json!({ "artifacts": [{ "uninstall": [{ "kext": "com.example.driver" }] }] })),
+            approve_kext
+        );
+        assert_eq!(
+            detect(json!({ "uninstall": { "kext": ["com.example.driver"] } })),
+            approve_kext
+        );
+        assert_eq!(
+            detect(json!({ "artifacts": [{ "system_extension": "com.example.filter" }] })),
+            [PostInstallAction::ApproveSystemExtension]
+        );
+    }
+
+    #[test]
+    fn caveats_phrasing_adds_approvals_and_a_reboot() {
+        assert_eq!(
+            detect(json!({ "caveats": "Allow the System Extension, then you must restart." })),
+            [
+                PostInstallAction::ApproveSystemExtension,
+                PostInstallAction::Reboot,
+            ]
+        );
+        assert_eq!(
+            detect(json!({ "caveats": "The KEXT requires a reboot to load." })),
+            [
+                PostInstallAction::ApproveKernelExtension,
+                PostInstallAction::Reboot,
+            ]
+        );
+    }
+
+    #[test]
+    fn actions_come_in_a_stable_order() {
+        let actions = detect(json!({
+            "artifacts": [
+                { "postflight": ["touch /tmp/ready"] },
+                { "system_extension": "com.example.filter" },
+                { "installer": [{ "manual": "Install Driver.app" }] },
+            ],
+            "uninstall": { "kext": "com.example.driver" },
+            "caveats": "You must reboot for the driver to load.",
+        }));
+
+        assert_eq!(
+            actions,
+            [
+                PostInstallAction::OpenManualInstaller {
+                    path: PathBuf::from("/Caskroom/driver/2.0/Install Driver.app"),
+                },
+                PostInstallAction::ApproveKernelExtension,
+                PostInstallAction::ApproveSystemExtension,
+                PostInstallAction::Reboot,
+                PostInstallAction::ReviewPostflight {
+                    commands: vec!["touch /tmp/ready".to_string()],
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,177 @@
+// sps-core/src/build/cask/preexisting.rs
+//! What a cask install would collide with before anything is downloaded: apps already sitting
+//! in the Applications folder that no sps receipt accounts for, and installed packages the cask
+//! declares `conflicts_with`.
+
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use sps_common::config::Config;
+use sps_common::error::{Result, SpsError};
+use sps_common::model::cask::Cask;
+use tracing::{debug, warn};
+
+use crate::build::cask::{
+    get_cask_version_path, read_cask_manifest, write_cask_manifest, InstalledArtifact,
+};
+use crate::installed::{get_installed_package, PackageType};
+
+/// App bundle names the cask's `app` stanzas install, e.g. `Firefox.app`.
+pub fn declared_apps(cask: &Cask) -> Vec<String> {
+    cask.artifacts
+        .iter()
+        .flatten()
+        .filter_map(|artifact| artifact.get("app")?.as_array())
+        .flatten()
+        .filter_map(|name| name.as_str().map(str::to_string))
+        .collect()
+}
+
+/// The cask's apps that already exist in the Applications folder but are not recorded in any
+/// installed cask's receipt, i.e. copies the user put there some other way.
+pub fn unmanaged_apps(cask: &Cask, config: &Config) -> Vec<PathBuf> {
+    let applications_dir = config.applications_dir();
+    let present: Vec<PathBuf> = declared_apps(cask)
+        .into_iter()
+        .map(|name| applications_dir.join(name))
+        .filter(|path| path.symlink_metadata().is_ok())
+        .collect();
+    if present.is_empty() {
+        return present;
+    }
+    let recorded = recorded_app_paths(config);
+    present
+        .into_iter()
+        .filter(|path| !recorded.contains(path))
+        .collect()
+}
+
+/// Installed packages named in the cask's `conflicts_with` stanza, as `cask foo` / `formula bar`.
+pub async fn installed_conflicts(cask: &Cask, config: &Config) -> Result<Vec<String>> {
+    let Some(conflicts) = &cask.conflicts_with else {
+        return Ok(Vec::new());
+    };
+    let mut found = Vec::new();
+    for (names, kind, label) in [
+        (&conflicts.cask, PackageType::Cask, "cask"),
+        (&conflicts.formula, PackageType::Formula, "formula"),
+    ] {
+        for name in names {
+            if name == &cask.token && kind == PackageType::Cask {
+                continue;
+            }
+            if let Some(info) = get_installed_package(name, config).await? {
+                if info.pkg_type == kind {
+                    found.push(format!("{label} {name}"));
+                }
+            }
+        }
+    }
+    Ok(found)
+}
+
+/// Takes over management of `apps` (as returned by [`unmanaged_apps`]) without reinstalling:
+/// checks each bundle's version against the cask, then links it into the Caskroom and writes a
+/// receipt so upgrade and uninstall treat it like any other installed cask.
+pub fn adopt(cask: &Cask, apps: &[PathBuf], config: &Config) -> Result<()> {
+    let expected = cask.version.as_deref().unwrap_or("latest");
+    for app in apps {
+        check_bundle_version(app, expected)?;
+    }
+
+    let cask_version_path = get_cask_version_path(cask, config);
+    fs::create_dir_all(&cask_version_path)?;
+    let mut artifacts = Vec::new();
+    for app in apps {
+        artifacts.push(InstalledArtifact::App { path: app.clone() });
+        let Some(app_name) = app.file_name() else {
+            continue;
+        };
+        let link_path = cask_version_path.join(app_name);
+        if link_path.symlink_metadata().is_ok() {
+            fs::remove_file(&link_path)?;
+        }
+        #[cfg(unix)]
+        match std::os::unix::fs::symlink(app, &link_path) {
+            Ok(()) => artifacts.push(InstalledArtifact::CaskroomLink {
+                link_path,
+                target_path: app.clone(),
+            }),
+            Err(e) => debug!(
+                "Failed to create symlink {} -> {}: {}",
+                link_path.display(),
+                app.display(),
+                e
+            ),
+        }
+    }
+    write_cask_manifest(cask, &cask_version_path, artifacts)?;
+    debug!("Adopted {} app(s) for cask {}", apps.len(), cask.token);
+    Ok(())
+}
+
+/// Accepts the bundle if its short version string or build number matches a component of the
+/// cask version (`1.2.3,456` names both). `latest` casks carry no version to compare.
+fn check_bundle_version(app: &Path, expected: &str) -> Result<()> {
+    if expected == "latest" {
+        return Ok(());
+    }
+    let found: Vec<String> = ["CFBundleShortVersionString", "CFBundleVersion"]
+        .iter()
+        .filter_map(|key| read_info_plist_key(app, key))
+        .collect();
+    if found.is_empty() {
+        return Err(SpsError::InstallError(format!(
+            "Cannot adopt {}: its Info.plist has no version to compare with {expected}",
+            app.display()
+        )));
+    }
+    let accepted: Vec<&str> = std::iter::once(expected)
+        .chain(expected.split(','))
+        .collect();
+    if found.iter().any(|v| accepted.contains(&v.as_str())) {
+        return Ok(());
+    }
+    Err(SpsError::InstallError(format!(
+        "Cannot adopt {}: bundle version {} does not match cask version {expected}; use --force to replace it",
+        app.display(),
+        found.join(" / ")
+    )))
+}
+
+fn read_info_plist_key(app: &Path, key: &str) -> Option<String> {
+    let plist = app.join("Contents").join("Info.plist");
+    let output = Command::new("/usr/libexec/PlistBuddy")
+        .arg("-c")
+        .arg(format!("Print :{key}"))
+        .arg(&plist)
+        .output()
+        .map_err(|e| warn!("Failed to run PlistBuddy on {}: {}", plist.display(), e))
+        .ok()?;
+    let value = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (output.status.success() && !value.is_empty()).then_some(value)
+}
+
+/// App paths recorded in the receipt of every installed cask version.
+fn recorded_app_paths(config: &Config) -> HashSet<PathBuf> {
+    let mut recorded = HashSet::new();
+    let Ok(tokens) = fs::read_dir(config.caskroom_dir()) else {
+        return recorded;
+    };
+    for version_dir in tokens
+        .flatten()
+        .filter_map(|token| fs::read_dir(token.path()).ok())
+        .flatten()
+        .flatten()
+    {
+        if let Some(parsed) = read_cask_manifest(&version_dir.path()) {
+            recorded.extend(parsed.artifacts.into_iter().filter_map(|a| match a {
+                InstalledArtifact::App { path } => Some(path),
+                _ => None,
+            }));
+        }
+    }
+    recorded
+}
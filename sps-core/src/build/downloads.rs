@@ -0,0 +1,172 @@
+// sps-core/src/build/downloads.rs
+//! Coalescing of identical downloads within one process. Two plan nodes can need the same
+//! artifact (aliased names, or a formula task and the cask-dependency path), and both would
+//! otherwise fetch it to the same cache path at once. Downloads are keyed by their final cache
+//! path: the first requester runs the download, later ones wait for it and share its result,
+//! and an error reaches every waiter.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, LazyLock, Mutex};
+
+use sps_common::error::Result;
+use tokio::sync::OnceCell;
+use tracing::debug;
+
+type Outcome = OnceCell<Result<PathBuf>>;
+
+static IN_FLIGHT: LazyLock<Mutex<HashMap<PathBuf, Arc<Outcome>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Runs `download` for `cache_path` unless a download of that path is already in flight, in
+/// which case this waits for it and returns its result. Once a download finishes the key is
+/// released, so a later request goes through `download` again (normally a cache hit).
+pub async fn coalesce<Fut>(cache_path: &Path, download: impl FnOnce() -> Fut) -> Result<PathBuf>
+where
+    Fut: Future<Output = Result<PathBuf>>,
+{
+    let (outcome, joined) = {
+        let mut in_flight = IN_FLIGHT.lock().unwrap();
+        match in_flight.get(cache_path) {
+            Some(outcome) => (Arc::clone(outcome), true),
+            None => {
+                let outcome = Arc::new(Outcome::new());
+                in_flight.insert(cache_path.to_path_buf(), Arc::clone(&outcome));
+                (outcome, false)
+            }
+        }
+    };
+    if joined {
+        debug!(
+            "Waiting for the download already fetching {}",
+            cache_path.display()
+        );
+    }
+    // If the task running the download is cancelled, the next waiter runs its own instead.
+    let result = outcome.get_or_init(download).await.clone();
+
+    let mut in_flight = IN_FLIGHT.lock().unwrap();
+    if in_flight
+        .get(cache_path)
+        .is_some_and(|current| Arc::ptr_eq(current, &outcome))
+    {
+        in_flight.remove(cache_path);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    use sps_common::error::SpsError;
+
+    use super::*;
+
+    /// A download that counts its runs and takes long enough for others to join it.
+    async fn slow(runs: &AtomicUsize, result: Result<PathBuf>) -> Result<PathBuf> {
+        runs.fetch_add(1, Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        result
+    }
+
+    #[tokio::test]
+    async fn concurrent_requests_for_one_path_share_a_single_download() {
+        let path = Path::new("/coalesce-test/shared.tar.gz");
+        let runs = AtomicUsize::new(0);
+
+        let results = futures::future::join_all(
+            (0..5).map(|_| coalesce(path, || slow(&runs, Ok(path.to_path_buf())))),
+        )
+        .await;
+
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+        for result in results {
+            assert_eq!(result.unwrap(), path);
+        }
+    }
+
+    #[tokio::test]
+    async fn a_failed_download_reaches_every_waiter() {
+        let path = Path::new("/coalesce-test/failing.tar.gz");
+        let runs = AtomicUsize::new(0);
+
+        let results = futures::future::join_all((0..3).map(|_| {
+            coalesce(path, || {
+                slow(&runs, Err(SpsError::Generic("connection reset".into())))
+            })
+        }))
+        .await;
+
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+        for result in results {
+            assert!(result.unwrap_err().to_string().contains("connection reset"));
+        }
+    }
+
+    #[tokio::test]
+    async fn different_paths_download_independently() {
+        let runs = AtomicUsize::new(0);
+        let (a, b) = (
+            Path::new("/coalesce-test/a.tar.gz"),
+            Path::new("/coalesce-test/b.tar.gz"),
+        );
+
+        let (first, second) = tokio::join!(
+            coalesce(a, || slow(&runs, Ok(a.to_path_buf()))),
+            coalesce(b, || slow(&runs, Ok(b.to_path_buf()))),
+        );
+
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+        assert_eq!(first.unwrap(), a);
+        assert_eq!(second.unwrap(), b);
+    }
+
+    #[tokio::test]
+    async fn the_key_is_released_once_the_download_finishes() {
+        let path = Path::new("/coalesce-test/released.tar.gz");
+        let runs = AtomicUsize::new(0);
+
+        coalesce(path, || slow(&runs, Err(SpsError::Generic("once".into()))))
+            .await
+            .unwrap_err();
+        let retried = coalesce(path, || slow(&runs, Ok(path.to_path_buf()))).await;
+
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+        assert_eq!(retried.unwrap(), path);
+        assert!(!IN_FLIGHT.lock().unwrap().contains_key(path));
+    }
+
+    #[tokio::test]
+    async fn a_waiter_takes_over_when_the_downloading_task_is_cancelled() {
+        let path = Path::new("/coalesce-test/cancelled.tar.gz");
+        let runs = Arc::new(AtomicUsize::new(0));
+
+        let leader = {
+            let runs = Arc::clone(&runs);
+            tokio::spawn(async move {
+                coalesce(path, || async {
+                    runs.fetch_add(1, Ordering::SeqCst);
+                    std::future::pending::<Result<PathBuf>>().await
+                })
+                .await
+            })
+        };
+        while runs.load(Ordering::SeqCst) == 0 {
+            tokio::task::yield_now().await;
+        }
+        let waiter = {
+            let runs = Arc::clone(&runs);
+            tokio::spawn(
+                async move { coalesce(path, || slow(&runs, Ok(path.to_path_buf()))).await },
+            )
+        };
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        leader.abort();
+
+        assert_eq!(waiter.await.unwrap().unwrap(), path);
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+    }
+}
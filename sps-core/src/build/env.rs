@@ -3,8 +3,10 @@
 // is correct ***
 
 use std::collections::{HashMap, HashSet};
-use std::path::{Path, PathBuf};
+use std::path::PathBuf;
+use std::process::Command;
 
+use sps_common::config::{Config, EnvMode};
 use sps_common::error::{Result, SpsError};
 use sps_common::model::formula::FormulaDependencies;
 use tracing::debug;
@@ -165,10 +167,11 @@ impl BuildEnvironment {
     /// Creates a new sanitized build environment for a given formula.
     pub fn new<F: FormulaDependencies>(
         formula: &F,
-        sps_prefix: &Path,
-        cellar_path: &Path,
+        config: &Config,
         all_installed_opt_paths: &[PathBuf],
     ) -> Result<Self> {
+        let sps_prefix = config.prefix();
+        let cellar_path = config.cellar_path();
         debug!(
             "Creating BuildEnvironment for formula '{}'...",
             formula.name()
@@ -182,7 +185,7 @@ impl BuildEnvironment {
         let mut vars = HashMap::new();
         let mut path_dirs = Vec::new();
 
-        filter_initial_environment(&mut vars);
+        filter_initial_environment(&mut vars, config);
         debug!("Initial environment filtering complete.");
 
         let cc = devtools::find_compiler("cc")?;
@@ -465,8 +468,9 @@ impl BuildEnvironment {
         command.env_clear();
         command.envs(&self.vars);
         debug!(
-            "Applying sanitized environment to command: {:?}",
-            command.get_program()
+            "Applying sanitized environment to command {:?}: {:?}",
+            command.get_program(),
+            self.vars
         );
         // Avoid logging args verbosely unless needed
         // debug!("Arguments: {:?}", command.get_args().collect::<Vec<_>>());
@@ -491,10 +495,12 @@ impl BuildEnvironment {
     }
 }
 
-/// Filters the initial environment, keeping only specified safe variables.
-fn filter_initial_environment(vars: &mut HashMap<String, String>) {
-    // Unchanged
+/// Filters the initial environment, keeping only specified safe variables (plus the configured
+/// passthrough list). With `EnvMode::Inherit` everything except the known-interfering variables
+/// is kept.
+fn filter_initial_environment(vars: &mut HashMap<String, String>, config: &Config) {
     let initial_env: HashMap<String, String> = std::env::vars().collect();
+    let inherit = config.env_mode == EnvMode::Inherit;
     let vars_to_remove_set: HashSet<&str> = ENV_VARS_TO_REMOVE.iter().cloned().collect();
     let vars_to_keep_set: HashSet<&str> = ENV_VARS_TO_KEEP.iter().cloned().collect();
     *vars = HashMap::new();
@@ -509,9 +515,64 @@ fn filter_initial_environment(vars: &mut HashMap<String, String>) {
             debug!("Removing potentially interfering Homebrew env var: {}", key);
             continue;
         }
-        if vars_to_keep_set.contains(key.as_str()) {
+        if inherit
+            || vars_to_keep_set.contains(key.as_str())
+            || config.env_passthrough.contains(key)
+        {
             debug!("Keeping env var: {}", key);
             vars.insert(key.clone(), value.clone());
         }
     }
 }
+
+/// Variables carried over from the caller into installer subprocesses in `EnvMode::Std`.
+const SUBPROCESS_ENV_VARS_TO_KEEP: &[&str] = &[
+    "HOME", "USER", "LOGNAME", "TMPDIR", "LANG", "LC_ALL", "LC_CTYPE", "TZ",
+];
+
+/// Applies the controlled environment used for subprocesses launched outside of source builds
+/// (hdiutil, installer, pkgutil, ...). In `EnvMode::Std` the environment is cleared and rebuilt
+/// from a PATH limited to the prefix and system directories, the basics (HOME, TMPDIR, locale)
+/// and any allowlisted variables. In `EnvMode::Inherit` the command is left untouched.
+pub fn apply_subprocess_env(command: &mut Command, config: &Config) {
+    if config.env_mode == EnvMode::Inherit {
+        debug!(
+            "Inheriting user environment for command: {:?}",
+            command.get_program()
+        );
+        return;
+    }
+    let prefix = config.prefix();
+    let path = [
+        prefix.join("bin"),
+        prefix.join("sbin"),
+        PathBuf::from("/usr/bin"),
+        PathBuf::from("/bin"),
+        PathBuf::from("/usr/sbin"),
+        PathBuf::from("/sbin"),
+    ]
+    .iter()
+    .map(|p| p.to_string_lossy().to_string())
+    .collect::<Vec<_>>()
+    .join(":");
+
+    let mut vars: HashMap<String, String> = HashMap::new();
+    vars.insert("PATH".to_string(), path);
+    for (key, value) in std::env::vars() {
+        if SUBPROCESS_ENV_VARS_TO_KEEP.contains(&key.as_str())
+            || config.env_passthrough.contains(&key)
+        {
+            vars.insert(key, value);
+        }
+    }
+    vars.entry("TMPDIR".to_string())
+        .or_insert_with(|| std::env::temp_dir().to_string_lossy().to_string());
+
+    command.env_clear();
+    command.envs(&vars);
+    debug!(
+        "Applying controlled environment to command {:?}: {:?}",
+        command.get_program(),
+        vars
+    );
+}
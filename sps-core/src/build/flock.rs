@@ -0,0 +1,83 @@
+// sps-core/src/build/flock.rs
+//! Advisory whole-file locks through `flock(2)`. A lock belongs to the open file and is released
+//! when it is unlocked or the last descriptor for it is closed, including when the process dies.
+
+use std::fs::File;
+use std::io;
+use std::os::unix::io::AsRawFd;
+
+/// Blocks until `file` is locked exclusively.
+pub fn lock_exclusive(file: &File) -> io::Result<()> {
+    loop {
+        match flock(file, libc::LOCK_EX) {
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            result => return result,
+        }
+    }
+}
+
+/// Locks `file` exclusively without waiting. `Ok(false)` means another open file holds a lock on
+/// it.
+pub fn try_lock_exclusive(file: &File) -> io::Result<bool> {
+    match flock(file, libc::LOCK_EX | libc::LOCK_NB) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// Releases a lock taken on `file`.
+pub fn unlock(file: &File) -> io::Result<()> {
+    flock(file, libc::LOCK_UN)
+}
+
+fn flock(file: &File, operation: libc::c_int) -> io::Result<()> {
+    // SAFETY: the descriptor belongs to `file`, which outlives the call.
+    if unsafe { libc::flock(file.as_raw_fd(), operation) } == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::OpenOptions;
+
+    use super::*;
+
+    fn open(path: &std::path::Path) -> File {
+        OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(path)
+            .unwrap()
+    }
+
+    #[test]
+    fn try_lock_fails_while_another_open_file_holds_the_lock() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("run.lock");
+        let (holder, other) = (open(&path), open(&path));
+
+        lock_exclusive(&holder).unwrap();
+        assert!(!try_lock_exclusive(&other).unwrap());
+
+        unlock(&holder).unwrap();
+        assert!(try_lock_exclusive(&other).unwrap());
+    }
+
+    #[test]
+    fn closing_the_file_releases_the_lock() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("run.lock");
+        let other = open(&path);
+        {
+            let holder = open(&path);
+            lock_exclusive(&holder).unwrap();
+            assert!(!try_lock_exclusive(&other).unwrap());
+        }
+        assert!(try_lock_exclusive(&other).unwrap());
+    }
+}
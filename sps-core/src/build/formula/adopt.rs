@@ -0,0 +1,292 @@
+// sps-core/src/build/formula/adopt.rs
+//! Adopting kegs from another installation's Cellar, typically Homebrew's, so they are upgraded
+//! and uninstalled like kegs sps poured itself instead of being reinstalled.
+//!
+//! When that installation shares the sps prefix (both default to the same location), its kegs
+//! are adopted where they are. Otherwise each keg stays in the other Cellar and the sps Cellar
+//! gets a symlink to it: bottles are relocated to the prefix they were poured into, so they can't
+//! move, and the other installation has to stay in place. Uninstalling such a keg only removes the
+//! symlink.
+//!
+//! Adopting merges into the keg's `INSTALL_RECEIPT.json`, keeping what Homebrew recorded, and
+//! adds `adopted`: `from` (the other prefix), `time` and `linked`. `linked` is `sps` when sps
+//! linked the keg into its prefix, or `external` when the existing links were left alone; in a
+//! shared prefix those links go into the install manifest, so unlinking and uninstalling remove
+//! them like sps's own.
+
+use std::collections::HashMap;
+use std::fs;
+use std::os::unix::fs::symlink;
+use std::path::{Component, Path, PathBuf};
+
+use serde_json::{Map, Value};
+use sps_common::config::Config;
+use sps_common::error::Result;
+use sps_common::keg::InstalledKeg;
+use sps_common::model::formula::{Formula, CORE_TAP};
+use sps_common::model::PkgVersion;
+use tracing::debug;
+use walkdir::WalkDir;
+
+use super::{link, owners, versions};
+
+const RECEIPT_FILE: &str = "INSTALL_RECEIPT.json";
+/// Prefix directories holding the links `sps uninstall` knows how to remove.
+const LINKED_DIRS: &[&str] = &["opt", "bin", "lib", "include", "share", "etc"];
+/// Keg entries that are bookkeeping rather than contents.
+const METADATA_FILES: &[&str] = &[RECEIPT_FILE, "INSTALL_MANIFEST.json", "sbom.spdx.json"];
+
+/// How adopted kegs are linked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkMode {
+    /// Link into the sps prefix, replacing links already there.
+    Link,
+    /// Leave the links the other installation made.
+    External,
+}
+
+impl LinkMode {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Link => "sps",
+            Self::External => "external",
+        }
+    }
+}
+
+/// What surveying one keg of the other Cellar found.
+#[derive(Debug)]
+pub enum Finding {
+    Adoptable(Box<Candidate>),
+    /// Installed by sps or adopted before.
+    Managed(InstalledKeg),
+    /// Can't be adopted, and why.
+    Mismatch(InstalledKeg, String),
+}
+
+#[derive(Debug)]
+pub struct Candidate {
+    /// The keg in the other Cellar.
+    pub keg: InstalledKeg,
+    pub formula: Formula,
+    /// Where the keg is in the sps Cellar once adopted; `keg.path` in a shared prefix.
+    pub target: PathBuf,
+    /// The formulary's version, when it differs from the keg's.
+    pub available: Option<PkgVersion>,
+}
+
+/// Whether `from` is the sps prefix itself.
+pub fn is_shared_prefix(from: &Path, config: &Config) -> bool {
+    from == config.prefix()
+        || matches!(
+            (fs::canonicalize(from), fs::canonicalize(config.prefix())),
+            (Ok(a), Ok(b)) if a == b
+        )
+}
+
+/// Sorts the kegs of the installation at `from` into adoptable ones, ones sps already manages
+/// and mismatches. `lookup` finds a formula's current definition.
+pub fn survey(
+    kegs: Vec<InstalledKeg>,
+    from: &Path,
+    config: &Config,
+    lookup: impl Fn(&str) -> Option<Formula>,
+) -> Vec<Finding> {
+    let shared = is_shared_prefix(from, config);
+    kegs.into_iter()
+        .map(|keg| {
+            let receipt = match read_receipt(&keg.path) {
+                Ok(receipt) => receipt,
+                Err(reason) => return Finding::Mismatch(keg, reason),
+            };
+            if let Some(receipt) = &receipt {
+                if receipt.contains_key("adopted") || (shared && receipt.contains_key("name")) {
+                    return Finding::Managed(keg);
+                }
+                let tap = receipt
+                    .get("source")
+                    .and_then(|source| source.get("tap"))
+                    .and_then(Value::as_str);
+                if let Some(tap) = tap.filter(|tap| *tap != CORE_TAP) {
+                    return Finding::Mismatch(
+                        keg,
+                        format!("comes from the tap {tap}; install it with sps instead"),
+                    );
+                }
+            }
+            if !has_contents(&keg.path) {
+                return Finding::Mismatch(keg, "the keg is empty".to_string());
+            }
+            let Some(formula) = lookup(&keg.name) else {
+                return Finding::Mismatch(keg, "no such formula in the formulary".to_string());
+            };
+            let target = if shared {
+                keg.path.clone()
+            } else {
+                config.formula_keg_path(&keg.name, &versions::keg_version(&keg))
+            };
+            if !shared && target.symlink_metadata().is_ok() {
+                return Finding::Managed(keg);
+            }
+            let current = PkgVersion::parse(&formula.version_str_full());
+            let available = (current != keg.version).then_some(current);
+            Finding::Adoptable(Box::new(Candidate {
+                keg,
+                formula,
+                target,
+                available,
+            }))
+        })
+        .collect()
+}
+
+/// Adopts `candidate` from the installation at `from`. `external` are the links into its keg
+/// found in a shared prefix (see [`ExternalLinks`]), recorded when `mode` is
+/// [`LinkMode::External`].
+pub fn adopt(
+    candidate: &Candidate,
+    from: &Path,
+    mode: LinkMode,
+    external: &ExternalLinks,
+    config: &Config,
+) -> Result<()> {
+    let target = &candidate.target;
+    if *target != candidate.keg.path {
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        symlink(std::path::absolute(&candidate.keg.path)?, target)?;
+        debug!(
+            "Registered {} as {}",
+            candidate.keg.path.display(),
+            target.display()
+        );
+    }
+    let result = record_adoption(candidate, from, mode, external, config);
+    if result.is_err() && *target != candidate.keg.path {
+        let _ = fs::remove_file(target);
+    }
+    result
+}
+
+fn record_adoption(
+    candidate: &Candidate,
+    from: &Path,
+    mode: LinkMode,
+    external: &ExternalLinks,
+    config: &Config,
+) -> Result<()> {
+    let target = &candidate.target;
+    let version = versions::keg_version(&candidate.keg);
+    if !target.join(RECEIPT_FILE).exists() {
+        super::write_receipt(&candidate.formula, target, &[])?;
+    }
+    let receipt_path = target.join(RECEIPT_FILE);
+    let mut receipt: Map<String, Value> =
+        serde_json::from_str(&fs::read_to_string(&receipt_path)?)?;
+    // Homebrew's own fields stay as they are; sps's are only added where missing.
+    receipt
+        .entry("name")
+        .or_insert_with(|| Value::String(candidate.formula.name.clone()));
+    receipt.insert("version".to_string(), Value::String(version));
+    receipt
+        .entry("installed_on_request")
+        .or_insert(Value::Bool(true));
+    receipt.insert(
+        "adopted".to_string(),
+        serde_json::json!({
+            "from": from.display().to_string(),
+            "time": chrono::Utc::now().to_rfc3339(),
+            "linked": mode.as_str(),
+        }),
+    );
+    fs::write(&receipt_path, serde_json::to_string_pretty(&receipt)?)?;
+
+    match mode {
+        LinkMode::Link => link::link_formula_artifacts(&candidate.formula, target, config)?,
+        LinkMode::External => {
+            let links = external.of(&candidate.keg.path);
+            if !links.is_empty() && !target.join("INSTALL_MANIFEST.json").exists() {
+                link::write_install_manifest(target, &links)?;
+                owners::record_links(config, target, &links);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The links in a prefix that point into its Cellar, by the keg they point into. Only the
+/// directories sps links into are searched.
+#[derive(Debug, Default)]
+pub struct ExternalLinks {
+    by_keg: HashMap<PathBuf, Vec<String>>,
+}
+
+impl ExternalLinks {
+    pub fn scan(prefix: &Path) -> Self {
+        let Ok(cellar) = fs::canonicalize(prefix.join("Cellar")) else {
+            return Self::default();
+        };
+        let mut by_keg: HashMap<PathBuf, Vec<String>> = HashMap::new();
+        for dir in LINKED_DIRS {
+            for entry in WalkDir::new(prefix.join(dir))
+                .min_depth(1)
+                .into_iter()
+                .flatten()
+            {
+                if !entry.path_is_symlink() {
+                    continue;
+                }
+                let Ok(resolved) = fs::canonicalize(entry.path()) else {
+                    continue;
+                };
+                let Ok(inside) = resolved.strip_prefix(&cellar) else {
+                    continue;
+                };
+                let mut parts = inside.components().filter_map(|c| match c {
+                    Component::Normal(part) => Some(part),
+                    _ => None,
+                });
+                if let (Some(name), Some(version)) = (parts.next(), parts.next()) {
+                    by_keg
+                        .entry(cellar.join(name).join(version))
+                        .or_default()
+                        .push(entry.path().display().to_string());
+                }
+            }
+        }
+        Self { by_keg }
+    }
+
+    /// The links into the keg at `keg_path`.
+    pub fn of(&self, keg_path: &Path) -> Vec<String> {
+        fs::canonicalize(keg_path)
+            .ok()
+            .and_then(|keg| self.by_keg.get(&keg).cloned())
+            .unwrap_or_default()
+    }
+}
+
+/// The receipt, `None` if there is none, or why it can't be read.
+fn read_receipt(keg_path: &Path) -> std::result::Result<Option<Map<String, Value>>, String> {
+    match fs::read_to_string(keg_path.join(RECEIPT_FILE)) {
+        Ok(text) => serde_json::from_str(&text)
+            .map(Some)
+            .map_err(|e| format!("{RECEIPT_FILE} is corrupt: {e}")),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(format!("cannot read {RECEIPT_FILE}: {e}")),
+    }
+}
+
+/// Whether the keg holds anything besides bookkeeping files.
+fn has_contents(keg_path: &Path) -> bool {
+    fs::read_dir(keg_path)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .any(|entry| {
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            !name.starts_with('.') && !METADATA_FILES.contains(&name.as_ref())
+        })
+}
@@ -0,0 +1,286 @@
+// sps-core/src/build/formula/audit.rs
+//! Post-extraction audit of a poured bottle. Bottles are built by someone else, so before the keg
+//! is relocated and linked it is scanned for setuid/setgid files, world-writable files and
+//! directories, and entries owned by a different user than the keg itself. `bottle_audit` in the
+//! config decides whether findings are only reported, fixed in place, or fail the install.
+
+use std::os::unix::fs::{lchown, MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::{fmt, fs};
+
+use serde::Serialize;
+use sps_common::config::BottleAuditMode;
+use sps_common::error::{Result, SpsError};
+use tracing::{debug, warn};
+use walkdir::WalkDir;
+
+const SETUID: u32 = 0o4000;
+const SETGID: u32 = 0o2000;
+const WORLD_WRITABLE: u32 = 0o002;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditIssue {
+    Setuid,
+    Setgid,
+    WorldWritable,
+    /// Owned by this uid instead of the user installing the keg.
+    UnexpectedOwner(u32),
+}
+
+impl fmt::Display for AuditIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Setuid => write!(f, "setuid"),
+            Self::Setgid => write!(f, "setgid"),
+            Self::WorldWritable => write!(f, "world-writable"),
+            Self::UnexpectedOwner(uid) => write!(f, "owned by uid {uid}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AuditFinding {
+    /// Relative to the keg.
+    pub path: PathBuf,
+    pub issues: Vec<AuditIssue>,
+    /// Whether `fix` mode repaired the entry.
+    pub fixed: bool,
+}
+
+/// Scans the keg at `install_dir` and applies `mode` to what it finds. Returns the findings for
+/// the receipt; in strict mode any finding is an error instead.
+pub fn audit_keg(install_dir: &Path, mode: BottleAuditMode) -> Result<Vec<AuditFinding>> {
+    let expected_uid = fs::symlink_metadata(install_dir)?.uid();
+    let mut findings = Vec::new();
+    for entry in WalkDir::new(install_dir).min_depth(1) {
+        let entry = entry.map_err(|e| {
+            SpsError::InstallError(format!(
+                "Failed to scan keg {} for the bottle audit: {e}",
+                install_dir.display()
+            ))
+        })?;
+        // A symlink's own mode bits mean nothing; its target is audited where it lives.
+        if entry.path_is_symlink() {
+            continue;
+        }
+        let metadata = entry.metadata().map_err(|e| {
+            SpsError::InstallError(format!(
+                "Failed to read {} for the bottle audit: {e}",
+                entry.path().display()
+            ))
+        })?;
+        let issues = issues_for(metadata.mode(), metadata.uid(), expected_uid);
+        if issues.is_empty() {
+            continue;
+        }
+        let path = entry.path();
+        let fixed = mode == BottleAuditMode::Fix && fix_entry(path, metadata.mode(), expected_uid);
+        let relative = path.strip_prefix(install_dir).unwrap_or(path).to_path_buf();
+        warn!(
+            "Bottle audit: {} is {}{}",
+            relative.display(),
+            issues
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(", "),
+            if fixed { " (fixed)" } else { "" }
+        );
+        findings.push(AuditFinding {
+            path: relative,
+            issues,
+            fixed,
+        });
+    }
+
+    if mode == BottleAuditMode::Strict && !findings.is_empty() {
+        return Err(SpsError::InstallError(format!(
+            "Bottle audit found {} problem file(s) in {} (bottle_audit = strict)",
+            findings.len(),
+            install_dir.display()
+        )));
+    }
+    debug!(
+        "Bottle audit of {}: {} finding(s)",
+        install_dir.display(),
+        findings.len()
+    );
+    Ok(findings)
+}
+
+fn issues_for(mode: u32, uid: u32, expected_uid: u32) -> Vec<AuditIssue> {
+    let mut issues = Vec::new();
+    if mode & SETUID != 0 {
+        issues.push(AuditIssue::Setuid);
+    }
+    if mode & SETGID != 0 {
+        issues.push(AuditIssue::Setgid);
+    }
+    if mode & WORLD_WRITABLE != 0 {
+        issues.push(AuditIssue::WorldWritable);
+    }
+    if uid != expected_uid {
+        issues.push(AuditIssue::UnexpectedOwner(uid));
+    }
+    issues
+}
+
+/// Takes ownership first, since chown clears setuid/setgid on some systems and the mode is set
+/// explicitly afterwards anyway.
+fn fix_entry(path: &Path, mode: u32, expected_uid: u32) -> bool {
+    if let Err(e) = lchown(path, Some(expected_uid), None) {
+        warn!("Failed to change owner of {}: {}", path.display(), e);
+        return false;
+    }
+    let stripped = mode & 0o7777 & !(SETUID | SETGID | WORLD_WRITABLE);
+    match fs::set_permissions(path, fs::Permissions::from_mode(stripped)) {
+        Ok(()) => true,
+        Err(e) => {
+            warn!("Failed to fix permissions of {}: {}", path.display(), e);
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::build::extract::extract_tar_pipelined_hashed;
+
+    /// A bottle holding a setuid binary, a 0777 directory and an ordinary file, poured the way
+    /// `install_bottle` pours it.
+    fn poured_keg() -> (TempDir, PathBuf) {
+        let mut builder = tar::Builder::new(GzEncoder::new(Vec::new(), Compression::fast()));
+        for (path, kind, mode, data) in [
+            (
+                "tool/1.0/bin/tool",
+                tar::EntryType::Regular,
+                0o4755,
+                &b"#!/bin/sh\n"[..],
+            ),
+            (
+                "tool/1.0/share/drop",
+                tar::EntryType::Directory,
+                0o777,
+                &b""[..],
+            ),
+            (
+                "tool/1.0/README",
+                tar::EntryType::Regular,
+                0o644,
+                &b"readme\n"[..],
+            ),
+        ] {
+            let mut header = tar::Header::new_gnu();
+            header.set_entry_type(kind);
+            header.set_mode(mode);
+            header.set_size(data.len() as u64);
+            builder.append_data(&mut header, path, data).unwrap();
+        }
+        let mut gz = builder.into_inner().unwrap();
+        gz.flush().unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let bottle = dir.path().join("tool-1.0.all.bottle.tar.gz");
+        fs::write(&bottle, gz.finish().unwrap()).unwrap();
+        let keg = dir.path().join("Cellar/tool/1.0");
+        extract_tar_pipelined_hashed(&bottle, &keg, 2).unwrap();
+        (dir, keg)
+    }
+
+    fn mode_of(path: &Path) -> u32 {
+        fs::symlink_metadata(path).unwrap().mode() & 0o7777
+    }
+
+    fn issues_by_path(findings: &[AuditFinding]) -> Vec<(String, Vec<AuditIssue>)> {
+        let mut found: Vec<_> = findings
+            .iter()
+            .map(|f| (f.path.display().to_string(), f.issues.clone()))
+            .collect();
+        found.sort_by(|a, b| a.0.cmp(&b.0));
+        found
+    }
+
+    #[test]
+    fn warn_reports_the_setuid_binary_and_the_open_directory_and_changes_nothing() {
+        let (_dir, keg) = poured_keg();
+        assert_eq!(mode_of(&keg.join("bin/tool")), 0o4755);
+
+        let findings = audit_keg(&keg, BottleAuditMode::Warn).unwrap();
+
+        assert_eq!(
+            issues_by_path(&findings),
+            [
+                ("bin/tool".to_string(), vec![AuditIssue::Setuid]),
+                ("share/drop".to_string(), vec![AuditIssue::WorldWritable]),
+            ]
+        );
+        assert!(findings.iter().all(|f| !f.fixed));
+        assert_eq!(mode_of(&keg.join("bin/tool")), 0o4755);
+        assert_eq!(mode_of(&keg.join("share/drop")), 0o777);
+    }
+
+    #[test]
+    fn fix_strips_the_offending_bits() {
+        let (_dir, keg) = poured_keg();
+
+        let findings = audit_keg(&keg, BottleAuditMode::Fix).unwrap();
+
+        assert_eq!(findings.len(), 2);
+        assert!(findings.iter().all(|f| f.fixed));
+        assert_eq!(mode_of(&keg.join("bin/tool")), 0o755);
+        assert_eq!(mode_of(&keg.join("share/drop")), 0o775);
+        assert_eq!(mode_of(&keg.join("README")), 0o644);
+        assert!(audit_keg(&keg, BottleAuditMode::Strict).unwrap().is_empty());
+    }
+
+    #[test]
+    fn strict_fails_the_install_and_leaves_the_keg_as_poured() {
+        let (_dir, keg) = poured_keg();
+
+        let err = audit_keg(&keg, BottleAuditMode::Strict).unwrap_err();
+
+        assert!(matches!(err, SpsError::InstallError(_)), "{err:?}");
+        assert!(err.to_string().contains("2 problem file(s)"), "{err}");
+        assert_eq!(mode_of(&keg.join("bin/tool")), 0o4755);
+    }
+
+    #[test]
+    fn issues_cover_every_bit_and_the_owner() {
+        assert!(issues_for(0o100644, 501, 501).is_empty());
+        assert_eq!(
+            issues_for(0o106777, 0, 501),
+            [
+                AuditIssue::Setuid,
+                AuditIssue::Setgid,
+                AuditIssue::WorldWritable,
+                AuditIssue::UnexpectedOwner(0),
+            ]
+        );
+    }
+
+    #[test]
+    fn findings_serialize_for_the_receipt() {
+        let finding = AuditFinding {
+            path: PathBuf::from("bin/tool"),
+            issues: vec![AuditIssue::Setuid, AuditIssue::UnexpectedOwner(0)],
+            fixed: true,
+        };
+
+        assert_eq!(
+            serde_json::to_value(&finding).unwrap(),
+            serde_json::json!({
+                "path": "bin/tool",
+                "issues": ["setuid", { "unexpected_owner": 0 }],
+                "fixed": true
+            })
+        );
+    }
+}
@@ -7,25 +7,33 @@ use std::io::{Read, Write};
 use std::os::unix::fs::{symlink, PermissionsExt};
 use std::path::{Path, PathBuf};
 use std::process::{Command as StdCommand, Stdio};
-use std::sync::Arc; // Import Arc
+use std::sync::{Arc, OnceLock}; // Import Arc
 
 use reqwest::Client;
-use semver;
+use sps_common::cache::{origin_tag, Cache};
 use sps_common::config::Config;
 use sps_common::error::{Result, SpsError};
+use sps_common::keg;
 use sps_common::model::formula::{BottleFileSpec, Formula, FormulaDependencies};
+use sps_common::model::PkgVersion;
 use sps_net::fetch::oci;
+use sps_net::signature;
 use sps_net::validation::verify_checksum;
 use tempfile::NamedTempFile;
-use tracing::{debug, error, warn};
+use tracing::{debug, error, info, warn};
 use walkdir::WalkDir;
 
 use super::macho;
-use crate::build::formula::get_current_platform;
+use crate::build::downloads;
+use crate::build::formula::host_platform;
+
+/// Prefix of the ghcr.io URLs that Homebrew core bottles are served from.
+const HOMEBREW_CORE_BOTTLE_URL: &str = "https://ghcr.io/v2/homebrew/core/";
 
 pub async fn download_bottle(
     formula: &Formula,
     config: &Config,
+    cache: &Cache,
     client: &Client,
 ) -> Result<PathBuf> {
     debug!("Attempting to download bottle for {}", formula.name);
@@ -42,40 +50,152 @@ pub async fn download_bottle(
         ));
     }
     let standard_version_str = formula.version_str_full();
-    let filename = format!(
-        "{}-{}.{}.bottle.tar.gz",
-        formula.name, standard_version_str, platform_tag
+    let rebuild = formula.bottle.stable.as_ref().map_or(0, |s| s.rebuild);
+    let mirrored = config.bottle_domain.as_deref().and_then(|domain| {
+        mirrored_bottle_spec(formula, bottle_file_spec, domain, &platform_tag, rebuild)
+    });
+    let bottle_file_spec = mirrored.as_ref().unwrap_or(bottle_file_spec);
+    // Upstream occasionally rebuilds a bottle without bumping the version, and mirrors pick up
+    // rebuilds at different times; keying the cache entry on the rebuild number and digest (or
+    // the host when there is no digest) keeps those bottles distinct.
+    let stem = format!(
+        "{}-{}.{}.bottle",
+        formula.keg_dir_name(),
+        standard_version_str,
+        platform_tag
+    );
+    let mut filename = stem.clone();
+    if rebuild > 0 {
+        filename.push_str(&format!(".{rebuild}"));
+    }
+    filename.push_str(&format!(
+        ".{}.tar.gz",
+        origin_tag(&bottle_file_spec.sha256, &bottle_file_spec.url)
+    ));
+    // Aliased names can put the same bottle in a plan twice; it is fetched once.
+    let bottle_cache_path = cache.bottle_path(&filename)?;
+    let path = downloads::coalesce(&bottle_cache_path, || {
+        fetch_bottle(
+            formula,
+            config,
+            cache,
+            client,
+            bottle_file_spec,
+            &stem,
+            &filename,
+        )
+    })
+    .await?;
+    if let Err(e) =
+        signature::verify_artifact(&path, &bottle_file_spec.url, &formula.name, config).await
+    {
+        cache.discard_artifact(&path);
+        return Err(e);
+    }
+    Ok(path)
+}
+
+/// Where `bottle_domain` serves the core bottle `spec` points at: flat
+/// `<name>--<version>.<tag>.bottle[.<rebuild>].tar.gz` files, as Homebrew's bottle mirrors lay
+/// them out. Bottles from other hosts (taps, `artifact_domain`) are left alone.
+fn mirrored_bottle_spec(
+    formula: &Formula,
+    spec: &BottleFileSpec,
+    domain: &str,
+    platform_tag: &str,
+    rebuild: u32,
+) -> Option<BottleFileSpec> {
+    if !spec.url.starts_with(HOMEBREW_CORE_BOTTLE_URL) {
+        return None;
+    }
+    let mut url = format!(
+        "{}/{}--{}.{}.bottle",
+        domain,
+        formula.name,
+        formula.version_str_full(),
+        platform_tag
+    );
+    if rebuild > 0 {
+        url.push_str(&format!(".{rebuild}"));
+    }
+    url.push_str(".tar.gz");
+    debug!(
+        "Fetching {} bottle from bottle_domain: {}",
+        formula.name, url
     );
-    let cache_dir = config.cache_dir.join("bottles");
-    fs::create_dir_all(&cache_dir).map_err(|e| SpsError::Io(std::sync::Arc::new(e)))?;
-    let bottle_cache_path = cache_dir.join(&filename);
-    if bottle_cache_path.is_file() {
-        debug!("Bottle found in cache: {}", bottle_cache_path.display());
+    Some(BottleFileSpec {
+        url,
+        ..spec.clone()
+    })
+}
+
+/// Returns the bottle cached as `filename` if it is still valid, else downloads it there.
+async fn fetch_bottle(
+    formula: &Formula,
+    config: &Config,
+    cache: &Cache,
+    client: &Client,
+    bottle_file_spec: &BottleFileSpec,
+    stem: &str,
+    filename: &str,
+) -> Result<PathBuf> {
+    let rebuild = formula.bottle.stable.as_ref().map_or(0, |s| s.rebuild);
+    let expected = &bottle_file_spec.sha256;
+    let evicted = cache.evict_stale_bottles(stem, filename, |path| {
+        !expected.is_empty() && verify_checksum(path, expected).is_ok()
+    });
+    if evicted.legacy > 0 {
+        debug!(
+            "Removed {} cached bottle(s) of {} saved under the old unkeyed name",
+            evicted.legacy, formula.name
+        );
+    }
+    if evicted.stale > 0 {
+        if rebuild > 0 {
+            info!(
+                "{} bottle was rebuilt upstream (rebuild {}), refreshing",
+                formula.name, rebuild
+            );
+        } else {
+            info!("{} bottle was rebuilt upstream, refreshing", formula.name);
+        }
+    }
+    if let Some(cached) = cache.cached_bottle(filename)? {
+        debug!("Bottle found in cache: {}", cached.display());
+        if cache.is_verified(&cached, &bottle_file_spec.sha256) {
+            debug!(
+                "Using cached bottle verified by an earlier run: {}",
+                cached.display()
+            );
+            return Ok(cached);
+        }
         if !bottle_file_spec.sha256.is_empty() {
-            match verify_checksum(&bottle_cache_path, &bottle_file_spec.sha256) {
+            match verify_checksum(&cached, &bottle_file_spec.sha256) {
                 Ok(_) => {
-                    debug!("Using valid cached bottle: {}", bottle_cache_path.display());
-                    return Ok(bottle_cache_path);
+                    debug!("Using valid cached bottle: {}", cached.display());
+                    cache.mark_verified(&cached, &bottle_file_spec.sha256);
+                    return Ok(cached);
                 }
                 Err(e) => {
                     debug!(
                         "Cached bottle checksum mismatch ({}): {}. Redownloading.",
-                        bottle_cache_path.display(),
+                        cached.display(),
                         e
                     );
-                    let _ = fs::remove_file(&bottle_cache_path);
+                    cache.discard_artifact(&cached);
                 }
             }
         } else {
             warn!(
                 "Using cached bottle without checksum verification (checksum not specified): {}",
-                bottle_cache_path.display()
+                cached.display()
             );
-            return Ok(bottle_cache_path);
+            return Ok(cached);
         }
     } else {
         debug!("Bottle not found in cache.");
     }
+    let bottle_cache_path = cache.bottle_path(filename)?;
     let bottle_url_str = &bottle_file_spec.url;
     let registry_domain = config
         .artifact_domain
@@ -117,7 +237,7 @@ pub async fn download_bottle(
             }
             Err(e) => {
                 error!("Failed to download OCI blob from {}: {}", bottle_url_str, e);
-                let _ = fs::remove_file(&bottle_cache_path);
+                cache.discard_artifact(&bottle_cache_path);
                 return Err(SpsError::DownloadError(
                     formula.name.clone(),
                     bottle_url_str.to_string(),
@@ -130,34 +250,15 @@ pub async fn download_bottle(
             "Detected standard HTTPS URL, using direct download for: {}",
             bottle_url_str
         );
-        match sps_net::fetch::http::fetch_formula_source_or_bottle(
-            formula.name(),
+        match sps_net::fetch::http::fetch_to_path(
             bottle_url_str,
+            &bottle_cache_path,
             &bottle_file_spec.sha256,
-            &[],
             config,
         )
         .await
         {
-            Ok(downloaded_path) => {
-                if downloaded_path != bottle_cache_path {
-                    debug!(
-                        "fetch_formula_source_or_bottle returned path {}. Expected: {}. Assuming correct.",
-                        downloaded_path.display(),
-                        bottle_cache_path.display()
-                    );
-                    if !bottle_cache_path.exists() {
-                        error!(
-                            "Downloaded path {} exists, but expected final cache path {} does not!",
-                            downloaded_path.display(),
-                            bottle_cache_path.display()
-                        );
-                        return Err(SpsError::Generic(format!(
-                            "Download path mismatch and final file missing: {}",
-                            bottle_cache_path.display()
-                        )));
-                    }
-                }
+            Ok(_) => {
                 debug!(
                     "Successfully downloaded directly to {}",
                     bottle_cache_path.display()
@@ -183,11 +284,114 @@ pub async fn download_bottle(
         "Bottle download successful: {}",
         bottle_cache_path.display()
     );
+    // Both downloads check what they fetch: plain ones against the bottle's checksum, OCI blobs
+    // against the digest in their URL, which is the same checksum for Homebrew's bottles.
+    let digest = bottle_url_str.split("/blobs/sha256:").nth(1);
+    if !is_oci_blob_url || digest.is_some_and(|d| d.eq_ignore_ascii_case(expected)) {
+        cache.mark_verified(&bottle_cache_path, expected);
+    }
     Ok(bottle_cache_path)
 }
 
-// get_bottle_for_platform remains unchanged
-pub(crate) fn get_bottle_for_platform(formula: &Formula) -> Result<(String, &BottleFileSpec)> {
+/// The `bottle_tag` override, set once at startup.
+static BOTTLE_TAG_OVERRIDE: OnceLock<String> = OnceLock::new();
+
+/// Tag of architecture-independent bottles, pourable on every host.
+pub const ALL_BOTTLE_TAG: &str = "all";
+
+/// Makes bottle selection pick `tag` (`--bottle-tag`, `sps_BOTTLE_TAG`) instead of the host's own
+/// tags, e.g. where platform detection gets the host wrong. Only the first call has an effect.
+pub fn set_bottle_tag_override(tag: &str) {
+    if BOTTLE_TAG_OVERRIDE.set(tag.to_string()).is_err() {
+        debug!("Bottle tag override already set; ignoring '{}'", tag);
+    }
+}
+
+/// The bottle tags this host can pour, most preferred first: `all`, whose bottles pour anywhere,
+/// then the host's own tag, then on macOS the same architecture's older releases down to Big
+/// Sur. With a `bottle_tag` override it is that tag, then `all`.
+pub fn host_bottle_tags() -> Vec<String> {
+    bottle_tags(
+        BOTTLE_TAG_OVERRIDE.get().map(String::as_str),
+        host_platform(),
+        cfg!(target_os = "macos"),
+    )
+}
+
+/// [`host_bottle_tags`] for a host on `current_platform`, `macos` or not.
+fn bottle_tags(override_tag: Option<&str>, current_platform: &str, macos: bool) -> Vec<String> {
+    if let Some(tag) = override_tag {
+        let mut tags = vec![tag.to_string()];
+        if tag != ALL_BOTTLE_TAG {
+            tags.push(ALL_BOTTLE_TAG.to_string());
+        }
+        return tags;
+    }
+    const ARM_MACOS_VERSIONS: &[&str] = &["sequoia", "sonoma", "ventura", "monterey", "big_sur"];
+    const INTEL_MACOS_VERSIONS: &[&str] = &[
+        "sequoia", "sonoma", "ventura", "monterey", "big_sur", "catalina", "mojave",
+    ];
+    if current_platform.contains("unknown") {
+        debug!(
+            "Could not reliably determine current platform ('{}'). Bottle selection might be incorrect.",
+            current_platform
+        );
+    }
+    let mut tags = vec![ALL_BOTTLE_TAG.to_string(), current_platform.to_string()];
+    let arm = current_platform.starts_with("arm64_");
+    if macos {
+        let current_os_name = current_platform
+            .strip_prefix("arm64_")
+            .unwrap_or(current_platform);
+        let version_list = if arm {
+            ARM_MACOS_VERSIONS
+        } else {
+            INTEL_MACOS_VERSIONS
+        };
+        match version_list.iter().position(|&v| v == current_os_name) {
+            Some(current_os_index) => {
+                for target_os_name in version_list.iter().skip(current_os_index + 1) {
+                    tags.push(if arm {
+                        format!("arm64_{target_os_name}")
+                    } else {
+                        target_os_name.to_string()
+                    });
+                }
+            }
+            None => debug!(
+                "Current OS '{}' not found in known macOS version list.",
+                current_os_name
+            ),
+        }
+    }
+    let fallback = if arm {
+        Some("arm64_big_sur")
+    } else if macos {
+        Some("big_sur")
+    } else {
+        None
+    };
+    if let Some(fallback) = fallback {
+        if !tags.iter().any(|t| t == fallback) {
+            tags.push(fallback.to_string());
+        }
+    }
+    tags
+}
+
+pub fn get_bottle_for_platform(formula: &Formula) -> Result<(String, &BottleFileSpec)> {
+    let current_platform = BOTTLE_TAG_OVERRIDE
+        .get()
+        .map_or(host_platform(), String::as_str);
+    select_bottle(formula, &host_bottle_tags(), current_platform)
+}
+
+/// The bottle of `formula` for the first of `tags` it has one for.
+fn select_bottle<'a>(
+    formula: &'a Formula,
+    tags: &[String],
+    current_platform: &str,
+) -> Result<(String, &'a BottleFileSpec)> {
     let stable_spec = formula.bottle.stable.as_ref().ok_or_else(|| {
         SpsError::Generic(format!(
             "Formula '{}' has no stable bottle specification.",
@@ -200,103 +404,26 @@ pub(crate) fn get_bottle_for_platform(formula: &Formula) -> Result<(String, &Bot
             formula.name
         )));
     }
-    let current_platform = get_current_platform();
-    if current_platform == "unknown" || current_platform.contains("unknown") {
-        debug!(
-            "Could not reliably determine current platform ('{}'). Bottle selection might be incorrect.",
-            current_platform
-        );
-    }
-    debug!(
-        "Determining bottle for current platform: {}",
-        current_platform
-    );
     debug!(
         "Available bottle platforms in formula spec: {:?}",
         stable_spec.files.keys().cloned().collect::<Vec<_>>()
     );
-    if let Some(spec) = stable_spec.files.get(&current_platform) {
-        debug!(
-            "Found exact bottle match for platform: {}",
-            current_platform
-        );
-        return Ok((current_platform.clone(), spec));
-    }
-    debug!("No exact match found for {}", current_platform);
-    const ARM_MACOS_VERSIONS: &[&str] = &["sequoia", "sonoma", "ventura", "monterey", "big_sur"];
-    const INTEL_MACOS_VERSIONS: &[&str] = &[
-        "sequoia", "sonoma", "ventura", "monterey", "big_sur", "catalina", "mojave",
-    ];
-    if cfg!(target_os = "macos") {
-        if let Some(current_os_name) = current_platform
-            .strip_prefix("arm64_")
-            .or(Some(current_platform.as_str()))
-        {
-            let version_list = if current_platform.starts_with("arm64_") {
-                ARM_MACOS_VERSIONS
-            } else {
-                INTEL_MACOS_VERSIONS
-            };
-            if let Some(current_os_index) = version_list.iter().position(|&v| v == current_os_name)
-            {
-                for target_os_name in version_list.iter().skip(current_os_index) {
-                    let target_tag = if current_platform.starts_with("arm64_") {
-                        format!("arm64_{target_os_name}")
-                    } else {
-                        target_os_name.to_string()
-                    };
-                    if let Some(spec) = stable_spec.files.get(&target_tag) {
-                        debug!(
-                            "No bottle found for exact platform '{}'. Using compatible older bottle '{}'.",
-                            current_platform, target_tag
-                        );
-                        return Ok((target_tag, spec));
-                    }
-                }
+    for tag in tags {
+        if let Some(spec) = stable_spec.files.get(tag) {
+            if tag == ALL_BOTTLE_TAG {
                 debug!(
-                    "Checked compatible older macOS versions ({:?}), no suitable bottle found.",
-                    &version_list[current_os_index..]
+                    "Using the architecture-independent bottle of '{}'.",
+                    formula.name
                 );
-            } else {
+            } else if tag != current_platform {
                 debug!(
-                    "Current OS '{}' not found in known macOS version list.",
-                    current_os_name
+                    "No bottle found for exact platform '{}'. Using compatible bottle '{}'.",
+                    current_platform, tag
                 );
             }
-        } else {
-            debug!(
-                "Could not extract OS name from platform tag '{}'",
-                current_platform
-            );
+            return Ok((tag.clone(), spec));
         }
     }
-    if current_platform.starts_with("arm64_") {
-        if let Some(spec) = stable_spec.files.get("arm64_big_sur") {
-            debug!(
-                "No specific OS bottle found for {}. Falling back to 'arm64_big_sur' bottle.",
-                current_platform
-            );
-            return Ok(("arm64_big_sur".to_string(), spec));
-        }
-        debug!("No 'arm64_big_sur' fallback bottle tag found.");
-    } else if cfg!(target_os = "macos") {
-        if let Some(spec) = stable_spec.files.get("big_sur") {
-            debug!(
-                "No specific OS bottle found for {}. Falling back to 'big_sur' bottle.",
-                current_platform
-            );
-            return Ok(("big_sur".to_string(), spec));
-        }
-        debug!("No 'big_sur' fallback bottle tag found.");
-    }
-    if let Some(spec) = stable_spec.files.get("all") {
-        debug!(
-            "No platform-specific or OS-specific bottle found for {}. Using 'all' platform bottle.",
-            current_platform
-        );
-        return Ok(("all".to_string(), spec));
-    }
-    debug!("No 'all' platform bottle found.");
     Err(SpsError::DownloadError(
         formula.name.clone(),
         "".to_string(),
@@ -308,9 +435,61 @@ pub(crate) fn get_bottle_for_platform(formula: &Formula) -> Result<(String, &Bot
     ))
 }
 
+/// Fails unless every formula in `formulae` has a bottle this host can pour (see
+/// [`host_bottle_tags`]), naming each one that doesn't together with the tags it does have.
+/// Run on a plan before anything is downloaded when `bottle_only` is set.
+pub fn require_bottles<'a>(formulae: impl IntoIterator<Item = &'a Formula>) -> Result<()> {
+    let mut missing: Vec<String> = formulae
+        .into_iter()
+        .filter(|f| get_bottle_for_platform(f).is_err())
+        .map(|f| {
+            let tags: Vec<&str> = f
+                .bottle
+                .stable
+                .iter()
+                .flat_map(|spec| spec.files.keys().map(String::as_str))
+                .collect();
+            if tags.is_empty() {
+                format!("  {} (no bottles)", f.name)
+            } else {
+                format!("  {} (has {})", f.name, tags.join(", "))
+            }
+        })
+        .collect();
+    if missing.is_empty() {
+        return Ok(());
+    }
+    missing.sort();
+    missing.dedup();
+    Err(SpsError::ValidationError(format!(
+        "bottle_only: {} formula(e) have no bottle for this host (looked for {}):\n{}",
+        missing.len(),
+        host_bottle_tags().join(", "),
+        missing.join("\n")
+    )))
+}
+
+/// Pours the bottle at `bottle_path` into a new keg and returns its path. A keg of the same
+/// version that is complete (it has a receipt) is replaced. One without a valid receipt was left
+/// by a pour that never finished, so it is quarantined (see [`keg::quarantine`]) rather than
+/// deleted or poured over, and the run's summary names where it went.
 pub fn install_bottle(bottle_path: &Path, formula: &Formula, config: &Config) -> Result<PathBuf> {
     let install_dir = formula.install_prefix(&config.cellar)?;
-    if install_dir.exists() {
+    if install_dir.exists() && !keg::has_valid_receipt(&install_dir) {
+        let quarantined = keg::quarantine(&install_dir).map_err(|e| {
+            SpsError::InstallError(format!(
+                "Failed to move incomplete keg {} aside: {}",
+                install_dir.display(),
+                e
+            ))
+        })?;
+        warn!(
+            "{} has no valid install receipt (an earlier install did not finish); moved it to {} \
+             and pouring a fresh keg",
+            install_dir.display(),
+            quarantined.display()
+        );
+    } else if install_dir.exists() {
         debug!(
             "Removing existing keg directory before installing: {}",
             install_dir.display()
@@ -346,6 +525,34 @@ pub fn install_bottle(bottle_path: &Path, formula: &Formula, config: &Config) ->
             format!("Failed to create keg dir {}: {}", install_dir.display(), e),
         )))
     })?;
+    // A half-poured keg would pass for an installed one (and one the audit rejected could be
+    // linked by hand), so nothing is left behind: the next run pours it again.
+    if let Err(e) = pour_bottle(bottle_path, formula, config, &install_dir) {
+        if let Err(remove_err) = fs::remove_dir_all(&install_dir) {
+            warn!(
+                "Failed to remove incomplete keg {}: {}",
+                install_dir.display(),
+                remove_err
+            );
+        }
+        return Err(e);
+    }
+    debug!(
+        "Bottle installation complete for {} at {}",
+        formula.name(),
+        install_dir.display()
+    );
+    Ok(install_dir)
+}
+
+/// Extracts the bottle into the empty keg `install_dir` and readies it: audit, relocation,
+/// integrity manifest and receipt.
+fn pour_bottle(
+    bottle_path: &Path,
+    formula: &Formula,
+    config: &Config,
+    install_dir: &Path,
+) -> Result<()> {
     let strip_components = 2;
     debug!(
         "Extracting bottle archive {} to {} with strip_components={}",
@@ -353,22 +560,22 @@ pub fn install_bottle(bottle_path: &Path, formula: &Formula, config: &Config) ->
         install_dir.display(),
         strip_components
     );
-    crate::build::extract::extract_archive(bottle_path, &install_dir, strip_components, "gz")?;
+    let extracted = crate::build::extract::extract_tar_pipelined_hashed(
+        bottle_path,
+        install_dir,
+        strip_components,
+    )?;
     debug!(
         "Ensuring write permissions for extracted files in {}",
         install_dir.display()
     );
-    ensure_write_permissions(&install_dir)?;
+    ensure_write_permissions(install_dir)?;
+    let audit_findings = super::audit::audit_keg(install_dir, config.bottle_audit)?;
     debug!("Performing bottle relocation in {}", install_dir.display());
-    perform_bottle_relocation(formula, &install_dir, config)?;
-    ensure_llvm_symlinks(&install_dir, formula, config)?;
-    crate::build::write_receipt(formula, &install_dir)?;
-    debug!(
-        "Bottle installation complete for {} at {}",
-        formula.name(),
-        install_dir.display()
-    );
-    Ok(install_dir)
+    perform_bottle_relocation(formula, install_dir, config)?;
+    ensure_llvm_symlinks(install_dir, formula, config)?;
+    crate::build::formula::integrity::write_keg_file_manifest(install_dir, &extracted)?;
+    crate::build::write_receipt(formula, install_dir, &audit_findings)
 }
 
 // ensure_write_permissions remains unchanged
@@ -452,7 +659,7 @@ fn perform_bottle_relocation(formula: &Formula, install_dir: &Path, config: &Con
     }
 
     // Handle Python framework internal paths for Python formulae
-    if formula.name().starts_with("python@") {
+    if formula.short_name().starts_with("python@") {
         let version_full = formula.version_str_full();
         let mut parts = version_full.split('.');
         if let (Some(major), Some(minor)) = (parts.next(), parts.next()) {
@@ -648,31 +855,19 @@ fn perform_bottle_relocation(formula: &Formula, install_dir: &Path, config: &Con
             perl_path.to_string_lossy().into(),
         );
     }
-    // Safely check dependencies before accessing
-    match formula.dependencies() {
-        Ok(deps) => {
-            if let Some(openjdk) = deps
-                .iter()
-                .find(|d| d.name.starts_with("openjdk"))
-                .map(|d| d.name.clone())
-            {
-                let openjdk_opt = config.formula_opt_link_path(&openjdk);
-                repl.insert(
-                    "@@HOMEBREW_JAVA@@".into(),
-                    openjdk_opt
-                        .join("libexec/openjdk.jdk/Contents/Home")
-                        .to_string_lossy()
-                        .into(),
-                );
-            }
-        }
-        Err(e) => {
-            warn!(
-                "Could not check formula dependencies during relocation for {}: {}",
-                formula.name(),
-                e
-            );
-        }
+    if let Some(openjdk) = formula
+        .dependencies()
+        .iter()
+        .find(|d| d.name.starts_with("openjdk"))
+    {
+        let openjdk_opt = config.formula_opt_link_path(&openjdk.name);
+        repl.insert(
+            "@@HOMEBREW_JAVA@@".into(),
+            openjdk_opt
+                .join("libexec/openjdk.jdk/Contents/Home")
+                .to_string_lossy()
+                .into(),
+        );
     }
 
     // RPATH relocation support (remains unchanged)
@@ -680,7 +875,10 @@ fn perform_bottle_relocation(formula: &Formula, install_dir: &Path, config: &Con
 
     let opt_placeholder = format!(
         "@@HOMEBREW_OPT_{}@@",
-        formula.name().to_uppercase().replace(['-', '+', '.'], "_")
+        formula
+            .short_name()
+            .to_uppercase()
+            .replace(['-', '+', '.'], "_")
     );
     repl.insert(
         opt_placeholder,
@@ -691,35 +889,25 @@ fn perform_bottle_relocation(formula: &Formula, install_dir: &Path, config: &Con
     );
 
     // LLVM Handling (remains unchanged)
-    match formula.dependencies() {
-        Ok(deps) => {
-            let llvm_dep_name = deps
-                .iter()
-                .find(|d| d.name.starts_with("llvm"))
-                .map(|d| d.name.clone());
-            if let Some(name) = llvm_dep_name {
-                let llvm_opt_path = config.formula_opt_link_path(&name);
-                let llvm_lib = llvm_opt_path.join("lib");
-                if llvm_lib.is_dir() {
-                    repl.insert(
-                        "@loader_path/../lib".into(),
-                        llvm_lib.to_string_lossy().into(),
-                    );
-                    repl.insert(
-                        format!(
-                            "@@HOMEBREW_OPT_{}@@/lib",
-                            name.to_uppercase().replace(['-', '+', '.'], "_")
-                        ),
-                        llvm_lib.to_string_lossy().into(),
-                    );
-                }
-            }
-        }
-        Err(e) => {
-            warn!(
-                "Could not check formula dependencies during LLVM relocation for {}: {}",
-                formula.name(),
-                e
+    let llvm_dep_name = formula
+        .dependencies()
+        .iter()
+        .find(|d| d.name.starts_with("llvm"))
+        .map(|d| d.name.clone());
+    if let Some(name) = llvm_dep_name {
+        let llvm_opt_path = config.formula_opt_link_path(&name);
+        let llvm_lib = llvm_opt_path.join("lib");
+        if llvm_lib.is_dir() {
+            repl.insert(
+                "@loader_path/../lib".into(),
+                llvm_lib.to_string_lossy().into(),
+            );
+            repl.insert(
+                format!(
+                    "@@HOMEBREW_OPT_{}@@/lib",
+                    name.to_uppercase().replace(['-', '+', '.'], "_")
+                ),
+                llvm_lib.to_string_lossy().into(),
             );
         }
     }
@@ -1107,7 +1295,7 @@ fn find_brewed_perl(prefix: &Path) -> Option<PathBuf> {
     if !opt_dir.is_dir() {
         return None;
     }
-    let mut best: Option<(semver::Version, PathBuf)> = None;
+    let mut best: Option<(PkgVersion, PathBuf)> = None;
     match fs::read_dir(opt_dir) {
         Ok(entries) => {
             for entry_res in entries.flatten() {
@@ -1119,37 +1307,17 @@ fn find_brewed_perl(prefix: &Path) -> Option<PathBuf> {
                     continue;
                 }
                 if let Some(version_part) = s.strip_prefix("perl@") {
-                    // Pad version for proper semver comparison
-                    let version_str_padded = if version_part.contains('.') {
-                        let parts: Vec<&str> = version_part.split('.').collect();
-                        match parts.len() {
-                            1 => format!("{}.0.0", parts[0]), // e.g., perl@5 -> 5.0.0
-                            2 => format!("{}.{}.0", parts[0], parts[1]), /* e.g., perl@5.30 -> */
-                            // 5.30.0
-                            _ => version_part.to_string(), // Already 3+ parts
-                        }
-                    } else {
-                        format!("{version_part}.0.0") // e.g., perl@5 -> 5.0.0 (handles case with no
-                                                      // dot)
-                    };
-
-                    if let Ok(v) = semver::Version::parse(&version_str_padded) {
-                        let candidate_bin = entry_path.join("bin/perl");
-                        if candidate_bin.is_file()
-                            && (best.is_none() || v > best.as_ref().unwrap().0)
-                        {
-                            best = Some((v, candidate_bin));
-                        }
+                    let v = PkgVersion::parse(version_part);
+                    let candidate_bin = entry_path.join("bin/perl");
+                    if candidate_bin.is_file() && best.as_ref().is_none_or(|(b, _)| v > *b) {
+                        best = Some((v, candidate_bin));
                     }
                 } else if s == "perl" {
                     // Handle unversioned 'perl' link, assume base version for comparison if no
                     // versioned one found yet
                     let candidate_bin = entry_path.join("bin/perl");
                     if candidate_bin.is_file() && best.is_none() {
-                        if let Ok(v_base) = semver::Version::parse("5.0.0") {
-                            // Assign base version
-                            best = Some((v_base, candidate_bin));
-                        }
+                        best = Some((PkgVersion::parse("5"), candidate_bin));
                     }
                 }
             }
@@ -1176,21 +1344,11 @@ fn ensure_llvm_symlinks(install_dir: &Path, formula: &Formula, config: &Config)
         );
         return Ok(());
     }
-    // Safely check dependencies before proceeding
-    let llvm_dep_name = match formula.dependencies() {
-        Ok(deps) => deps
-            .iter()
-            .find(|d| d.name.starts_with("llvm"))
-            .map(|dep| dep.name.clone()),
-        Err(e) => {
-            warn!(
-                "Could not check formula dependencies for LLVM symlink creation in {}: {}",
-                formula.name(),
-                e
-            );
-            return Ok(()); // Don't error, just skip
-        }
-    };
+    let llvm_dep_name = formula
+        .dependencies()
+        .iter()
+        .find(|d| d.name.starts_with("llvm"))
+        .map(|dep| dep.name.clone());
 
     // Proceed only if llvm_dep_name is Some
     let llvm_dep_name = match llvm_dep_name {
@@ -1300,3 +1458,226 @@ fn ensure_llvm_symlinks(install_dir: &Path, formula: &Formula, config: &Config)
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+
+    use serde_json::json;
+    use sha2::{Digest, Sha256};
+
+    use super::*;
+
+    const BOTTLE: &[u8] = b"not really a tarball";
+
+    /// A bottle server that must never be contacted: it accepts nothing, and reports whether
+    /// anything tried to connect.
+    struct Untouched(TcpListener);
+
+    impl Untouched {
+        fn start() -> Self {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            listener.set_nonblocking(true).unwrap();
+            Self(listener)
+        }
+
+        fn url(&self) -> String {
+            format!(
+                "http://{}/bottles/jq-1.7.1.all.bottle.tar.gz",
+                self.0.local_addr().unwrap()
+            )
+        }
+
+        fn was_contacted(&self) -> bool {
+            self.0.accept().is_ok()
+        }
+    }
+
+    fn formula(url: &str) -> Formula {
+        serde_json::from_value(json!({
+            "name": "jq",
+            "versions": { "stable": "1.7.1" },
+            "bottle": { "stable": { "rebuild": 0, "files": { "all": {
+                "url": url,
+                "sha256": hex::encode(Sha256::digest(BOTTLE)),
+            } } } },
+        }))
+        .unwrap()
+    }
+
+    fn config(dir: &Path) -> Config {
+        Config {
+            prefix: dir.to_path_buf(),
+            cellar: dir.join("Cellar"),
+            cache_dir: dir.join("cache"),
+            bottle_domain: None,
+            ..Config::load().unwrap()
+        }
+    }
+
+    #[tokio::test]
+    async fn a_seeded_cache_entry_is_used_without_any_request() {
+        let dir = tempfile::tempdir().unwrap();
+        let server = Untouched::start();
+        let formula = formula(&server.url());
+        let cache = Cache::new(&dir.path().join("cache")).unwrap();
+        let filename = format!(
+            "jq-1.7.1.all.bottle.{}.tar.gz",
+            origin_tag(&hex::encode(Sha256::digest(BOTTLE)), "")
+        );
+        let seeded = cache.bottle_path(&filename).unwrap();
+        fs::write(&seeded, BOTTLE).unwrap();
+
+        let path = download_bottle(&formula, &config(dir.path()), &cache, &Client::new())
+            .await
+            .unwrap();
+
+        assert_eq!(path, seeded);
+        assert!(!server.was_contacted());
+        assert!(cache.is_verified(&seeded, &hex::encode(Sha256::digest(BOTTLE))));
+    }
+
+    #[tokio::test]
+    async fn a_bottle_cached_under_the_unkeyed_name_is_migrated_and_used() {
+        let dir = tempfile::tempdir().unwrap();
+        let server = Untouched::start();
+        let formula = formula(&server.url());
+        let cache = Cache::new(&dir.path().join("cache")).unwrap();
+        fs::write(
+            cache.bottle_path("jq-1.7.1.all.bottle.tar.gz").unwrap(),
+            BOTTLE,
+        )
+        .unwrap();
+
+        let path = download_bottle(&formula, &config(dir.path()), &cache, &Client::new())
+            .await
+            .unwrap();
+
+        assert!(!server.was_contacted());
+        assert_eq!(fs::read(&path).unwrap(), BOTTLE);
+        assert_ne!(path.file_name().unwrap(), "jq-1.7.1.all.bottle.tar.gz");
+        assert!(!cache
+            .bottle_path("jq-1.7.1.all.bottle.tar.gz")
+            .unwrap()
+            .exists());
+    }
+
+    /// `jq` with a bottle for each of `tags`.
+    fn bottled_for(tags: &[&str]) -> Formula {
+        let files: serde_json::Map<String, serde_json::Value> = tags
+            .iter()
+            .map(|tag| {
+                let url = format!("https://example.invalid/jq-1.7.1.{tag}.bottle.tar.gz");
+                (
+                    tag.to_string(),
+                    json!({ "url": url, "sha256": "0".repeat(64) }),
+                )
+            })
+            .collect();
+        serde_json::from_value(json!({
+            "name": "jq",
+            "versions": { "stable": "1.7.1" },
+            "bottle": { "stable": { "rebuild": 0, "files": files } },
+        }))
+        .unwrap()
+    }
+
+    fn selected(tags: &[&str], platform: &str, macos: bool) -> Result<String> {
+        let formula = bottled_for(tags);
+        let (tag, spec) = select_bottle(&formula, &bottle_tags(None, platform, macos), platform)?;
+        assert!(spec.url.contains(&format!(".{tag}.")), "{}", spec.url);
+        Ok(tag)
+    }
+
+    #[test]
+    fn macos_falls_back_through_older_releases_of_the_same_arch() {
+        assert_eq!(
+            bottle_tags(None, "arm64_sequoia", true),
+            [
+                "all",
+                "arm64_sequoia",
+                "arm64_sonoma",
+                "arm64_ventura",
+                "arm64_monterey",
+                "arm64_big_sur",
+            ]
+        );
+        assert_eq!(
+            bottle_tags(None, "catalina", true),
+            ["all", "catalina", "mojave", "big_sur"]
+        );
+        // A release newer than the list still ends at Big Sur.
+        assert_eq!(
+            bottle_tags(None, "arm64_tahoe", true),
+            ["all", "arm64_tahoe", "arm64_big_sur"]
+        );
+        assert_eq!(
+            bottle_tags(None, "x86_64_linux", false),
+            ["all", "x86_64_linux"]
+        );
+    }
+
+    #[test]
+    fn an_override_comes_before_all_and_replaces_the_chain() {
+        assert_eq!(
+            bottle_tags(Some("arm64_ventura"), "x86_64_linux", false),
+            ["arm64_ventura", "all"]
+        );
+        assert_eq!(bottle_tags(Some("all"), "arm64_sonoma", true), ["all"]);
+    }
+
+    #[test]
+    fn an_all_only_spec_pours_everywhere() {
+        for (platform, macos) in [
+            ("arm64_sequoia", true),
+            ("sonoma", true),
+            ("x86_64_linux", false),
+        ] {
+            assert_eq!(selected(&["all"], platform, macos).unwrap(), "all");
+        }
+    }
+
+    #[test]
+    fn a_mixed_spec_prefers_all_then_the_closest_release() {
+        assert_eq!(
+            selected(
+                &["arm64_sonoma", "all", "x86_64_linux"],
+                "arm64_sonoma",
+                true
+            )
+            .unwrap(),
+            "all"
+        );
+        let per_release = ["arm64_ventura", "arm64_big_sur", "sonoma", "x86_64_linux"];
+        assert_eq!(
+            selected(&per_release, "arm64_sequoia", true).unwrap(),
+            "arm64_ventura"
+        );
+        assert_eq!(
+            selected(&["arm64_big_sur", "sonoma"], "arm64_monterey", true).unwrap(),
+            "arm64_big_sur"
+        );
+        assert_eq!(
+            selected(&per_release, "x86_64_linux", false).unwrap(),
+            "x86_64_linux"
+        );
+
+        let formula = bottled_for(&["arm64_sonoma", "all"]);
+        let tags = bottle_tags(Some("arm64_sonoma"), "x86_64_linux", false);
+        let (tag, _) = select_bottle(&formula, &tags, "arm64_sonoma").unwrap();
+        assert_eq!(tag, "arm64_sonoma");
+    }
+
+    #[test]
+    fn no_compatible_bottle_names_the_platform_and_what_exists() {
+        // Newer releases and the other architecture never qualify.
+        let err = selected(&["arm64_sequoia", "sonoma"], "arm64_ventura", true).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("No compatible bottle found for platform 'arm64_ventura'"),
+            "{err}"
+        );
+        let err = selected(&["arm64_sonoma"], "x86_64_linux", false).unwrap_err();
+        assert!(err.to_string().contains("arm64_sonoma"), "{err}");
+    }
+}
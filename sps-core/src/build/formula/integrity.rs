@@ -0,0 +1,269 @@
+// sps-core/src/build/formula/integrity.rs
+//! Per-file integrity manifest for poured kegs, used by `sps verify`.
+//!
+//! The manifest is written after relocation, since relocation rewrites install names inside
+//! binaries and placeholders in text files. Hashes taken during extraction are reused for every
+//! file relocation left alone, so only the rewritten ones are read again.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+use sps_common::error::{Result, SpsError};
+use tracing::debug;
+use walkdir::WalkDir;
+
+use crate::build::hashing::{self, HashControl, KnownHash, TreeEntry};
+
+pub const KEG_FILES_MANIFEST: &str = "SPS_KEG_FILES.json";
+
+/// Files sps writes into the keg itself after pouring; never part of the verified set.
+const UNTRACKED_FILES: &[&str] = &[
+    KEG_FILES_MANIFEST,
+    "INSTALL_RECEIPT.json",
+    "INSTALL_MANIFEST.json",
+    sps_common::keg::UNLINKED_MARKER,
+];
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct KegFileEntry {
+    pub size: u64,
+    /// SHA256 of regular files; empty for symlinks.
+    #[serde(default)]
+    pub sha256: String,
+    /// Target of a symlink, as stored in the keg.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub symlink_target: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KegFileManifest {
+    pub manifest_format_version: String,
+    /// Keg-relative path -> recorded state.
+    pub files: BTreeMap<String, KegFileEntry>,
+}
+
+/// Result of comparing a keg against its manifest. Paths are keg-relative.
+#[derive(Debug, Clone, Default)]
+pub struct KegVerifyReport {
+    pub missing: Vec<String>,
+    pub modified: Vec<String>,
+    pub extra: Vec<String>,
+    /// Paths that could not be read (e.g. permission denied), with the reason.
+    pub unreadable: Vec<(String, String)>,
+}
+
+impl KegVerifyReport {
+    pub fn is_clean(&self) -> bool {
+        self.missing.is_empty()
+            && self.modified.is_empty()
+            && self.extra.is_empty()
+            && self.unreadable.is_empty()
+    }
+}
+
+/// Records size and SHA256 of every file (and the target of every symlink) in `keg_dir`,
+/// taking the hash of each file `known` still matches from there (see
+/// [`hashing::hash_tree_reusing`]).
+pub fn write_keg_file_manifest(keg_dir: &Path, known: &BTreeMap<String, KnownHash>) -> Result<()> {
+    // Pours already run on several install workers at once; one hashing job each is enough.
+    let control = HashControl {
+        jobs: 1,
+        ..Default::default()
+    };
+    let (files, errors) = scan_keg(keg_dir, known, &control)?;
+    if let Some((rel, e)) = errors.into_iter().next() {
+        return Err(SpsError::Generic(format!(
+            "Failed to hash {} in keg {}: {}",
+            rel,
+            keg_dir.display(),
+            e
+        )));
+    }
+    debug!(
+        "Writing integrity manifest for {} ({} entries)",
+        keg_dir.display(),
+        files.len()
+    );
+    let manifest = KegFileManifest {
+        manifest_format_version: "1.0".to_string(),
+        files,
+    };
+    let json = serde_json::to_string_pretty(&manifest)?;
+    fs::write(keg_dir.join(KEG_FILES_MANIFEST), json).map_err(|e| SpsError::Io(Arc::new(e)))
+}
+
+/// Loads the integrity manifest of `keg_dir`, or `None` if the keg predates manifests.
+pub fn read_keg_file_manifest(keg_dir: &Path) -> Result<Option<KegFileManifest>> {
+    let path = keg_dir.join(KEG_FILES_MANIFEST);
+    if !path.is_file() {
+        return Ok(None);
+    }
+    let content = fs::read_to_string(&path).map_err(|e| SpsError::Io(Arc::new(e)))?;
+    Ok(Some(serde_json::from_str(&content)?))
+}
+
+/// Keg-relative paths of the files and symlinks poured into `keg_dir`, sorted: from its
+/// manifest, or by walking the keg when it predates manifests.
+pub fn keg_files(keg_dir: &Path) -> Result<Vec<String>> {
+    if let Some(manifest) = read_keg_file_manifest(keg_dir)? {
+        return Ok(manifest.files.into_keys().collect());
+    }
+    debug!(
+        "{} has no integrity manifest; listing its files from disk",
+        keg_dir.display()
+    );
+    let mut files = Vec::new();
+    for entry in WalkDir::new(keg_dir).follow_links(false).min_depth(1) {
+        let entry = entry.map_err(|e| {
+            SpsError::Generic(format!("Failed to walk keg {}: {}", keg_dir.display(), e))
+        })?;
+        if entry.file_type().is_dir() {
+            continue;
+        }
+        let Ok(rel) = entry.path().strip_prefix(keg_dir) else {
+            continue;
+        };
+        let rel = rel.to_string_lossy().to_string();
+        if entry.depth() == 1 && UNTRACKED_FILES.contains(&rel.as_str()) {
+            continue;
+        }
+        files.push(rel);
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Re-walks `keg_dir` and compares it against `manifest`, hashing as `control` says.
+pub fn verify_keg(
+    keg_dir: &Path,
+    manifest: &KegFileManifest,
+    control: &HashControl,
+) -> Result<KegVerifyReport> {
+    let (current, errors) = scan_keg(keg_dir, &BTreeMap::new(), control)?;
+    let mut report = KegVerifyReport {
+        unreadable: errors.into_iter().collect(),
+        ..Default::default()
+    };
+    for (rel, recorded) in &manifest.files {
+        if report.unreadable.iter().any(|(path, _)| path == rel) {
+            continue;
+        }
+        match current.get(rel) {
+            None => report.missing.push(rel.clone()),
+            Some(actual) if actual != recorded => report.modified.push(rel.clone()),
+            Some(_) => {}
+        }
+    }
+    report.extra = current
+        .keys()
+        .filter(|rel| !manifest.files.contains_key(*rel))
+        .cloned()
+        .collect();
+    Ok(report)
+}
+
+/// Walks `keg_dir` and records every file and symlink in it. Unreadable paths come back
+/// separately rather than failing the scan.
+fn scan_keg(
+    keg_dir: &Path,
+    known: &BTreeMap<String, KnownHash>,
+    control: &HashControl,
+) -> Result<(BTreeMap<String, KegFileEntry>, BTreeMap<String, String>)> {
+    let tree = hashing::hash_tree_reusing(
+        keg_dir,
+        |rel, depth| depth == 1 && UNTRACKED_FILES.contains(&rel),
+        known,
+        control,
+    )?;
+    let files = tree
+        .entries
+        .into_iter()
+        .map(|(rel, entry)| {
+            let record = match entry {
+                TreeEntry::File(hash) => KegFileEntry {
+                    size: hash.size,
+                    sha256: hash.sha256,
+                    symlink_target: None,
+                },
+                TreeEntry::Symlink(target) => KegFileEntry {
+                    size: 0,
+                    sha256: String::new(),
+                    symlink_target: Some(target),
+                },
+            };
+            (rel, record)
+        })
+        .collect();
+    Ok((files, tree.errors))
+}
+
+#[cfg(test)]
+mod tests {
+    use sha2::Digest;
+
+    use super::*;
+    use crate::build::hashing::{FileHash, FileStamp};
+
+    fn known(keg: &Path, rel: &str, sha256: &str) -> (String, KnownHash) {
+        let metadata = fs::metadata(keg.join(rel)).unwrap();
+        let hash = FileHash {
+            size: metadata.len(),
+            sha256: sha256.to_string(),
+        };
+        let stamp = FileStamp::of(&metadata);
+        (rel.to_string(), KnownHash { hash, stamp })
+    }
+
+    #[test]
+    fn only_files_changed_since_extraction_are_hashed_again() {
+        let keg = tempfile::tempdir().unwrap();
+        let keg = keg.path();
+        fs::create_dir_all(keg.join("bin")).unwrap();
+        fs::write(keg.join("bin/tool"), "untouched").unwrap();
+        fs::write(keg.join("bin/script"), "#!@@HOMEBREW_PREFIX@@/bin/sh").unwrap();
+        // Recorded hashes no real file has, so the manifest shows which ones were reused.
+        let extracted: BTreeMap<String, KnownHash> = [
+            known(keg, "bin/tool", "recorded-tool"),
+            known(keg, "bin/script", "recorded-script"),
+        ]
+        .into();
+        // Relocation replaces the placeholder by writing a new file over the old one.
+        fs::write(keg.join("bin/script.tmp"), "#!/opt/sps/bin/sh").unwrap();
+        fs::rename(keg.join("bin/script.tmp"), keg.join("bin/script")).unwrap();
+        std::os::unix::fs::symlink("tool", keg.join("bin/tool-link")).unwrap();
+
+        write_keg_file_manifest(keg, &extracted).unwrap();
+
+        let files = read_keg_file_manifest(keg).unwrap().unwrap().files;
+        assert_eq!(files["bin/tool"].sha256, "recorded-tool");
+        let rehashed = hex::encode(sha2::Sha256::digest(b"#!/opt/sps/bin/sh"));
+        assert_eq!(files["bin/script"].sha256, rehashed);
+        assert_eq!(
+            files["bin/tool-link"].symlink_target.as_deref(),
+            Some("tool")
+        );
+        assert!(!files.contains_key(KEG_FILES_MANIFEST));
+    }
+
+    #[test]
+    fn a_manifest_from_extraction_hashes_verifies_clean() {
+        let keg = tempfile::tempdir().unwrap();
+        let keg = keg.path();
+        fs::create_dir_all(keg.join("share")).unwrap();
+        fs::write(keg.join("share/data"), "data").unwrap();
+        let sha256 = hex::encode(sha2::Sha256::digest(b"data"));
+        let extracted: BTreeMap<String, KnownHash> = [known(keg, "share/data", &sha256)].into();
+
+        write_keg_file_manifest(keg, &extracted).unwrap();
+        let manifest = read_keg_file_manifest(keg).unwrap().unwrap();
+        let report = verify_keg(keg, &manifest, &HashControl::default()).unwrap();
+
+        assert!(report.is_clean(), "{report:?}");
+        fs::write(keg.join("share/data"), "changed").unwrap();
+        let report = verify_keg(keg, &manifest, &HashControl::default()).unwrap();
+        assert_eq!(report.modified, ["share/data"]);
+    }
+}
@@ -1,25 +1,105 @@
 // ===== sps-core/src/build/formula/link.rs =====
+use std::collections::BTreeSet;
 use std::fs;
 use std::io::Write;
 use std::os::unix::fs as unix_fs;
+use std::os::unix::fs::MetadataExt;
 #[cfg(unix)]
 use std::os::unix::fs::PermissionsExt;
 use std::path::{Path, PathBuf};
+use std::sync::Mutex;
 
-use serde_json;
-use sps_common::config::Config; // Import Config
+use serde_json::{self, Map, Value};
+use sps_common::config::{Config, LinkStrategy}; // Import Config
 use sps_common::error::{Result, SpsError};
+use sps_common::keg;
 use sps_common::model::formula::Formula;
-use tracing::{debug, error};
+use tracing::{debug, error, warn};
+use walkdir::WalkDir;
+
+use super::owners;
 
 const STANDARD_KEG_DIRS: [&str; 6] = ["bin", "lib", "share", "include", "etc", "Frameworks"];
 
-/// Link all artifacts from a formula's installation directory.
+/// Keg directories whose entries are linked into the same directory of the prefix.
+const LINKED_PREFIX_DIRS: [&str; 4] = ["lib", "include", "share", "etc"];
+
+/// Directories more than one formula installs into, relative to the keg and the prefix. These
+/// (and the directories above them) are real directories in the prefix that each keg's entries
+/// are linked into one by one; a `*` component matches any name. Every other directory is
+/// private to its formula (`share/<formula>`, `share/doc/<formula>`, `include/openssl`) and is
+/// linked whole.
+const SHARED_DIRS: &[&str] = &[
+    "etc/bash_completion.d",
+    "lib/cmake",
+    "lib/pkgconfig",
+    "share/aclocal",
+    "share/bash-completion/completions",
+    "share/doc",
+    "share/fish/vendor_completions.d",
+    "share/info",
+    "share/locale/*/LC_MESSAGES",
+    "share/man/*",
+    "share/pkgconfig",
+    "share/zsh/site-functions",
+];
+
+/// Serializes turning a directory link into a real directory between concurrent install
+/// workers linking into it.
+static MERGE_LOCK: Mutex<()> = Mutex::new(());
+
+/// Links left behind by an earlier keg of the same formula, read from its install manifest
+/// before the keg is replaced. Linking the new keg reuses whatever still fits and removes the
+/// rest, instead of tearing everything down first.
+#[derive(Debug, Clone, Default)]
+pub struct PreviousLinks {
+    keg_path: PathBuf,
+    links: Vec<String>,
+}
+
+impl PreviousLinks {
+    /// Reads the manifest of the keg at `keg_path`. A missing or unreadable manifest means
+    /// there is nothing to reconcile.
+    pub fn read(keg_path: &Path) -> Self {
+        let links = fs::read_to_string(keg_path.join("INSTALL_MANIFEST.json"))
+            .ok()
+            .and_then(|text| serde_json::from_str::<Vec<String>>(&text).ok())
+            .unwrap_or_default();
+        Self {
+            keg_path: keg_path.to_path_buf(),
+            links,
+        }
+    }
+
+    /// Removes the links outright, for when the replacement keg never got linked.
+    pub fn remove(&self, config: &Config) {
+        remove_manifest_links(&self.links, &self.keg_path, config, &[]);
+        owners::forget_keg(config, &self.keg_path);
+    }
+}
+
+/// Link all artifacts from a formula's installation directory, placing `lib`, `include`, `share`
+/// and `etc` entries as `config.link_strategy` says. Directories listed in [`SHARED_DIRS`] are
+/// merged file by file, so two formulae installing into `share/man/man1` both keep their pages.
+/// Relinking a keg that is already linked leaves the filesystem untouched.
 // Added Config parameter
 pub fn link_formula_artifacts(
     formula: &Formula,
     installed_keg_path: &Path,
     config: &Config, // Added config
+) -> Result<()> {
+    let previous = PreviousLinks::read(installed_keg_path);
+    relink_formula_artifacts(formula, installed_keg_path, config, &previous)
+}
+
+/// Links `installed_keg_path` in place of the links in `previous`. Links that already point
+/// where they should are left alone, changed ones are swapped atomically, and links the new keg
+/// no longer provides are removed at the end.
+pub fn relink_formula_artifacts(
+    formula: &Formula,
+    installed_keg_path: &Path,
+    config: &Config,
+    previous: &PreviousLinks,
 ) -> Result<()> {
     debug!(
         "Linking artifacts for {} from {}",
@@ -34,69 +114,35 @@ pub fn link_formula_artifacts(
     let opt_link_path = config.formula_opt_link_path(formula.name());
     let target_keg_dir = &formula_content_root;
 
-    remove_existing_link_target(&opt_link_path)?;
-    unix_fs::symlink(target_keg_dir, &opt_link_path).map_err(|e| {
-        SpsError::Io(std::sync::Arc::new(std::io::Error::new(
-            e.kind(),
-            format!("Failed to create opt symlink for {}: {}", formula.name(), e),
-        )))
+    // A fresh prefix has no opt directory yet.
+    fs::create_dir_all(config.opt_dir())?;
+    let changed = ensure_symlink(target_keg_dir, &opt_link_path).map_err(|e| {
+        SpsError::Io(std::sync::Arc::new(std::io::Error::other(format!(
+            "Failed to create opt symlink for {}: {}",
+            formula.name(),
+            e
+        ))))
     })?;
     symlinks_created.push(opt_link_path.to_string_lossy().to_string());
     debug!(
-        "  Linked opt path: {} -> {}",
+        "  {} opt path: {} -> {}",
+        if changed { "Linked" } else { "Kept" },
         opt_link_path.display(),
         target_keg_dir.display()
     );
 
-    if let Some((base, _version)) = formula.name().split_once('@') {
-        let alias_path = config.opt_dir().join(base); // Use config.opt_dir()
-        if !alias_path.exists() {
-            match unix_fs::symlink(target_keg_dir, &alias_path) {
-                Ok(_) => {
-                    debug!(
-                        "  Added un‑versioned opt alias: {} -> {}",
-                        alias_path.display(),
-                        target_keg_dir.display()
-                    );
-                    symlinks_created.push(alias_path.to_string_lossy().to_string());
-                }
-                Err(e) => {
-                    debug!(
-                        "  Could not create opt alias {}: {}",
-                        alias_path.display(),
-                        e
-                    );
-                }
-            }
-        }
-    }
+    link_opt_aliases(formula, target_keg_dir, config, &mut symlinks_created);
 
-    let standard_artifact_dirs = ["lib", "include", "share"];
-    for dir_name in &standard_artifact_dirs {
+    for dir_name in LINKED_PREFIX_DIRS {
         let source_subdir = formula_content_root.join(dir_name);
-        // Use config.prefix() for target base
-        let target_prefix_subdir = config.prefix().join(dir_name);
-
         if source_subdir.is_dir() {
-            fs::create_dir_all(&target_prefix_subdir)?;
-            for entry in fs::read_dir(&source_subdir)? {
-                let entry = entry?;
-                let source_item_path = entry.path();
-                let file_name = entry.file_name();
-                if file_name.to_string_lossy().starts_with('.') {
-                    continue;
-                }
-
-                let target_link = target_prefix_subdir.join(&file_name);
-                remove_existing_link_target(&target_link)?;
-                unix_fs::symlink(&source_item_path, &target_link).ok(); // ignore errors for individual links?
-                symlinks_created.push(target_link.to_string_lossy().to_string());
-                debug!(
-                    "  Linked {} -> {}",
-                    target_link.display(),
-                    source_item_path.display()
-                );
-            }
+            link_merged_dir(
+                &source_subdir,
+                &config.prefix().join(dir_name),
+                Path::new(dir_name),
+                config.link_strategy,
+                &mut symlinks_created,
+            )?;
         }
     }
 
@@ -123,7 +169,25 @@ pub fn link_formula_artifacts(
         )?;
     }
 
+    let stale: Vec<String> = previous
+        .links
+        .iter()
+        .filter(|link| !symlinks_created.contains(link))
+        .cloned()
+        .collect();
+    if !stale.is_empty() {
+        debug!(
+            "Removing {} link(s) the new keg of {} no longer provides",
+            stale.len(),
+            formula.name()
+        );
+        remove_manifest_links(&stale, &previous.keg_path, config, &symlinks_created);
+    }
+
     write_install_manifest(installed_keg_path, &symlinks_created)?;
+    owners::record_links(config, installed_keg_path, &symlinks_created);
+    record_link_strategy(installed_keg_path, config.link_strategy);
+    keg::clear_unlinked(installed_keg_path);
 
     debug!(
         "Successfully completed linking artifacts for {}",
@@ -132,7 +196,230 @@ pub fn link_formula_artifacts(
     Ok(())
 }
 
-// remove_existing_link_target, write_install_manifest remain mostly unchanged internally) ...
+/// Whether the directory at `relative` (under the keg and the prefix alike) is merged file by
+/// file rather than linked whole: the top-level linked directories, the [`SHARED_DIRS`], and
+/// every directory on the way to one.
+fn is_shared_dir(relative: &Path) -> bool {
+    let components: Vec<_> = relative.components().map(|c| c.as_os_str()).collect();
+    if components.len() == 1 {
+        return LINKED_PREFIX_DIRS.iter().any(|dir| components[0] == *dir);
+    }
+    SHARED_DIRS.iter().any(|pattern| {
+        let pattern: Vec<&str> = pattern.split('/').collect();
+        components.len() <= pattern.len()
+            && components
+                .iter()
+                .zip(&pattern)
+                .all(|(name, part)| *part == "*" || *name == *part)
+    })
+}
+
+/// Links the entries of the keg directory `source_dir` into the real prefix directory
+/// `target_dir`, descending into shared directories and placing everything else whole.
+fn link_merged_dir(
+    source_dir: &Path,
+    target_dir: &Path,
+    relative: &Path,
+    strategy: LinkStrategy,
+    symlinks_created: &mut Vec<String>,
+) -> Result<()> {
+    ensure_merge_dir(target_dir)?;
+    for entry in fs::read_dir(source_dir)? {
+        let entry = entry?;
+        let source_item_path = entry.path();
+        let file_name = entry.file_name();
+        if file_name.to_string_lossy().starts_with('.') {
+            continue;
+        }
+
+        let target_link = target_dir.join(&file_name);
+        let item_relative = relative.join(&file_name);
+        if entry.file_type().is_ok_and(|t| t.is_dir()) && is_shared_dir(&item_relative) {
+            link_merged_dir(
+                &source_item_path,
+                &target_link,
+                &item_relative,
+                strategy,
+                symlinks_created,
+            )?;
+            continue;
+        }
+        match ensure_placed(&source_item_path, &target_link, strategy) {
+            Ok(changed) => {
+                symlinks_created.push(target_link.to_string_lossy().to_string());
+                debug!(
+                    "  {} {} -> {}",
+                    if changed { "Linked" } else { "Kept" },
+                    target_link.display(),
+                    source_item_path.display()
+                );
+            }
+            // Individual links are best-effort.
+            Err(e) => debug!("  Could not link {}: {}", target_link.display(), e),
+        }
+    }
+    Ok(())
+}
+
+/// Makes `dir` a real directory to merge keg entries into. A link to a directory, as earlier
+/// versions made for `share/man` and the like, is replaced by a directory of links to what it
+/// held, so the keg it pointed into keeps its files linked.
+fn ensure_merge_dir(dir: &Path) -> Result<()> {
+    let _guard = MERGE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let metadata = match dir.symlink_metadata() {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            fs::create_dir_all(dir)?;
+            return Ok(());
+        }
+        Err(e) => return Err(SpsError::Io(std::sync::Arc::new(e))),
+    };
+    if metadata.is_dir() {
+        return Ok(());
+    }
+    if !metadata.file_type().is_symlink() {
+        return Err(SpsError::InstallError(format!(
+            "Cannot link into {}: a file is in the way of the directory",
+            dir.display()
+        )));
+    }
+
+    let target = fs::read_link(dir)?;
+    let resolved = match dir.parent() {
+        Some(parent) if target.is_relative() => parent.join(&target),
+        _ => target,
+    };
+    let entries: Vec<_> = match fs::read_dir(&resolved) {
+        Ok(entries) => entries.flatten().map(|e| e.file_name()).collect(),
+        Err(_) => Vec::new(),
+    };
+    debug!(
+        "  Replacing directory link {} -> {} with a merged directory",
+        dir.display(),
+        resolved.display()
+    );
+    fs::remove_file(dir)?;
+    fs::create_dir(dir)?;
+    for name in entries {
+        ensure_symlink(&resolved.join(&name), &dir.join(&name))?;
+    }
+    Ok(())
+}
+
+/// Removes the links anywhere under the merged directory `dir` that point into `keg_path`,
+/// except those in `keep`. The directory itself stays; other kegs' links are in it.
+fn remove_links_into(dir: &Path, keg_path: &Path, keep: &[String]) -> usize {
+    let links: Vec<PathBuf> = WalkDir::new(dir)
+        .min_depth(1)
+        .into_iter()
+        .flatten()
+        .filter(|entry| entry.path_is_symlink())
+        .map(|entry| entry.into_path())
+        .filter(|path| points_into(path, keg_path) && !keep.iter().any(|k| Path::new(k) == path))
+        .collect();
+    links
+        .iter()
+        .filter(|link| match fs::remove_file(link) {
+            Ok(()) => true,
+            Err(e) => {
+                debug!("Failed to remove link {}: {}", link.display(), e);
+                false
+            }
+        })
+        .count()
+}
+
+/// Creates the extra opt links for the formula's aliases and old names, plus the un-versioned
+/// `opt/<base>` link for `<base>@<version>` formulae. A declared alias already held by another
+/// keg is taken over (the last-linked formula wins) with a warning; the implicit un-versioned
+/// link is only added when free. Links that are not sps-managed symlinks are never replaced.
+///
+/// A tapped formula's own opt link is `opt/user--repo--name`; its short name and aliases are
+/// linked like the implicit un-versioned link, so they never take over a core formula's.
+fn link_opt_aliases(
+    formula: &Formula,
+    target_keg_dir: &Path,
+    config: &Config,
+    symlinks_created: &mut Vec<String>,
+) {
+    let tapped = formula.tap().is_some();
+    let mut aliases: Vec<(String, bool)> = formula
+        .alias_names()
+        .into_iter()
+        .map(|alias| (alias, !tapped))
+        .collect();
+    if tapped && !aliases.iter().any(|(a, _)| a == formula.short_name()) {
+        aliases.push((formula.short_name().to_string(), false));
+    }
+    if let Some((base, _version)) = formula.short_name().split_once('@') {
+        if !aliases.iter().any(|(a, _)| a == base) {
+            aliases.push((base.to_string(), false));
+        }
+    }
+
+    for (alias, declared) in aliases {
+        let alias_path = config.opt_dir().join(&alias);
+        if let Ok(metadata) = alias_path.symlink_metadata() {
+            if !metadata.file_type().is_symlink() {
+                warn!(
+                    "Not creating opt alias {}: a non-symlink is in the way",
+                    alias_path.display()
+                );
+                continue;
+            }
+            let current = fs::read_link(&alias_path).unwrap_or_default();
+            if current == target_keg_dir {
+                symlinks_created.push(alias_path.to_string_lossy().to_string());
+                continue;
+            }
+            if !declared {
+                if tapped {
+                    warn!(
+                        "opt/{} already points to {}; {} is linked only as {}",
+                        alias,
+                        current.display(),
+                        formula.name(),
+                        config.formula_opt_link_path(formula.name()).display()
+                    );
+                } else {
+                    debug!(
+                        "  Keeping existing opt alias {} -> {}",
+                        alias_path.display(),
+                        current.display()
+                    );
+                }
+                continue;
+            }
+            if !current.starts_with(config.cellar_path()) {
+                warn!(
+                    "Not replacing opt alias {}: it points outside the Cellar ({})",
+                    alias_path.display(),
+                    current.display()
+                );
+                continue;
+            }
+            warn!(
+                "opt/{} pointed to {}; relinking it to {}",
+                alias,
+                current.display(),
+                formula.name()
+            );
+        }
+        match ensure_symlink(target_keg_dir, &alias_path) {
+            Ok(_) => {
+                debug!(
+                    "  Added opt alias: {} -> {}",
+                    alias_path.display(),
+                    target_keg_dir.display()
+                );
+                symlinks_created.push(alias_path.to_string_lossy().to_string());
+            }
+            Err(e) => {
+                warn!("Could not link opt alias {}: {}", alias_path.display(), e);
+            }
+        }
+    }
+}
 fn create_wrappers_in_dir(
     source_dir: &Path,
     target_bin_dir: &Path,
@@ -168,35 +455,28 @@ fn create_wrappers_in_dir(
                                 Ok(true) => {
                                     let wrapper_path = target_bin_dir.join(&file_name);
                                     debug!("Found executable: {}", source_item_path.display());
-                                    if remove_existing_link_target(&wrapper_path).is_ok() {
-                                        debug!(
-                                            "    Creating wrapper script: {} -> {}",
-                                            wrapper_path.display(),
-                                            source_item_path.display()
-                                        );
-                                        match create_wrapper_script(
-                                            &source_item_path,
-                                            &wrapper_path,
-                                            formula_content_root,
-                                        ) {
-                                            Ok(_) => {
-                                                debug!(
-                                                    "  Created wrapper {} -> {}",
-                                                    wrapper_path.display(),
-                                                    source_item_path.display()
-                                                );
-                                                wrappers_created.push(
-                                                    wrapper_path.to_string_lossy().to_string(),
-                                                );
-                                            }
-                                            Err(e) => {
-                                                error!(
-                                                    "Failed to create wrapper script {} -> {}: {}",
-                                                    wrapper_path.display(),
-                                                    source_item_path.display(),
-                                                    e
-                                                );
-                                            }
+                                    match create_wrapper_script(
+                                        &source_item_path,
+                                        &wrapper_path,
+                                        formula_content_root,
+                                    ) {
+                                        Ok(changed) => {
+                                            debug!(
+                                                "  {} wrapper {} -> {}",
+                                                if changed { "Created" } else { "Kept" },
+                                                wrapper_path.display(),
+                                                source_item_path.display()
+                                            );
+                                            wrappers_created
+                                                .push(wrapper_path.to_string_lossy().to_string());
+                                        }
+                                        Err(e) => {
+                                            error!(
+                                                "Failed to create wrapper script {} -> {}: {}",
+                                                wrapper_path.display(),
+                                                source_item_path.display(),
+                                                e
+                                            );
                                         }
                                     }
                                 }
@@ -231,11 +511,13 @@ fn create_wrappers_in_dir(
     }
     Ok(())
 }
+/// Writes the wrapper for `target_executable`. Returns false if an identical one was already
+/// there.
 fn create_wrapper_script(
     target_executable: &Path,
     wrapper_path: &Path,
     formula_content_root: &Path,
-) -> Result<()> {
+) -> Result<bool> {
     let libexec_path = formula_content_root.join("libexec");
     let perl_lib_path = libexec_path.join("lib").join("perl5");
     let python_lib_path = libexec_path.join("vendor"); // Assuming simple vendor dir
@@ -271,39 +553,212 @@ fn create_wrapper_script(
         target_executable.display()
     ));
 
-    let mut file = fs::File::create(wrapper_path).map_err(|e| {
-        SpsError::Io(std::sync::Arc::new(std::io::Error::new(
-            e.kind(),
-            format!("Failed create wrapper {}: {}", wrapper_path.display(), e),
-        )))
-    })?;
-    file.write_all(script_content.as_bytes()).map_err(|e| {
-        SpsError::Io(std::sync::Arc::new(std::io::Error::new(
+    // An identical, executable wrapper is left as it is.
+    if let Ok(metadata) = wrapper_path.symlink_metadata() {
+        if metadata.is_file()
+            && metadata.permissions().mode() & 0o777 == 0o755
+            && fs::read(wrapper_path).is_ok_and(|current| current == script_content.as_bytes())
+        {
+            return Ok(false);
+        }
+        if metadata.is_dir() {
+            remove_existing_link_target(wrapper_path)?;
+        }
+    }
+
+    // Written under a temporary name and renamed into place, so the command never goes missing.
+    let temp_path = temp_path_for(wrapper_path);
+    let write_result = (|| -> std::io::Result<()> {
+        let mut file = fs::File::create(&temp_path)?;
+        file.write_all(script_content.as_bytes())?;
+        fs::set_permissions(&temp_path, fs::Permissions::from_mode(0o755))?;
+        fs::rename(&temp_path, wrapper_path)
+    })();
+    if let Err(e) = write_result {
+        let _ = fs::remove_file(&temp_path);
+        return Err(SpsError::Io(std::sync::Arc::new(std::io::Error::new(
             e.kind(),
             format!("Failed write wrapper {}: {}", wrapper_path.display(), e),
-        )))
-    })?;
+        ))));
+    }
 
-    #[cfg(unix)]
-    {
-        let metadata = file.metadata()?;
-        let mut permissions = metadata.permissions();
-        permissions.set_mode(0o755);
-        fs::set_permissions(wrapper_path, permissions).map_err(|e| {
-            SpsError::Io(std::sync::Arc::new(std::io::Error::new(
-                e.kind(),
-                format!(
-                    "Failed set wrapper executable {}: {}",
-                    wrapper_path.display(),
-                    e
-                ),
-            )))
-        })?;
+    Ok(true)
+}
+
+/// Points `link` at `target`, leaving it alone if it already does. Returns whether anything
+/// changed. A link or file in the way is replaced atomically (new symlink under a temporary
+/// name, renamed over the old path); only a real directory has to be removed first.
+fn ensure_symlink(target: &Path, link: &Path) -> Result<bool> {
+    match link.symlink_metadata() {
+        Ok(metadata) if metadata.file_type().is_symlink() => {
+            if fs::read_link(link).is_ok_and(|current| current == target) {
+                return Ok(false);
+            }
+        }
+        Ok(metadata) if metadata.is_dir() => remove_existing_link_target(link)?,
+        Ok(_) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(SpsError::Io(std::sync::Arc::new(e))),
+    }
+    let temp_path = temp_path_for(link);
+    let _ = fs::remove_file(&temp_path);
+    unix_fs::symlink(target, &temp_path)?;
+    if let Err(e) = fs::rename(&temp_path, link) {
+        let _ = fs::remove_file(&temp_path);
+        return Err(SpsError::Io(std::sync::Arc::new(e)));
+    }
+    Ok(true)
+}
+
+/// Puts the keg entry `source` at `target` the way `strategy` says, leaving it alone if it is
+/// there already. Returns whether anything changed. Hard links and copies are built under a
+/// temporary name and renamed into place; what is in the way is removed first only when either
+/// side is a directory.
+fn ensure_placed(source: &Path, target: &Path, strategy: LinkStrategy) -> Result<bool> {
+    if strategy == LinkStrategy::Symlink {
+        return ensure_symlink(source, target);
+    }
+    if is_placed(source, target, strategy) {
+        return Ok(false);
+    }
+    let temp_path = temp_path_for(target);
+    remove_existing_link_target(&temp_path)?;
+    if let Err(e) = place_tree(source, &temp_path, strategy) {
+        let _ = remove_existing_link_target(&temp_path);
+        return Err(e);
     }
+    let replaces_dir = source.is_dir() || target.symlink_metadata().is_ok_and(|m| m.is_dir());
+    if replaces_dir {
+        remove_existing_link_target(target)?;
+    }
+    if let Err(e) = fs::rename(&temp_path, target) {
+        let _ = remove_existing_link_target(&temp_path);
+        return Err(SpsError::Io(std::sync::Arc::new(e)));
+    }
+    Ok(true)
+}
 
+/// Recreates `source` at `dest`: symlinks as the same symlinks, directories as real
+/// directories, files as hard links (`hardlink`) or copies.
+fn place_tree(source: &Path, dest: &Path, strategy: LinkStrategy) -> Result<()> {
+    let metadata = source.symlink_metadata()?;
+    if metadata.file_type().is_symlink() {
+        unix_fs::symlink(fs::read_link(source)?, dest)?;
+    } else if metadata.is_dir() {
+        fs::create_dir(dest)?;
+        for entry in fs::read_dir(source)? {
+            let entry = entry?;
+            place_tree(&entry.path(), &dest.join(entry.file_name()), strategy)?;
+        }
+        fs::set_permissions(dest, metadata.permissions())?;
+    } else if strategy == LinkStrategy::Hardlink {
+        hard_link_or_copy(source, dest, |a, b| fs::hard_link(a, b))?;
+    } else {
+        fs::copy(source, dest)?;
+    }
     Ok(())
 }
 
+/// Hard links `source` at `dest` with `hard_link`, copying it instead when the two are on
+/// different filesystems.
+fn hard_link_or_copy(
+    source: &Path,
+    dest: &Path,
+    hard_link: impl Fn(&Path, &Path) -> std::io::Result<()>,
+) -> Result<()> {
+    match hard_link(source, dest) {
+        Ok(()) => {}
+        // The Cellar is on another filesystem than the prefix directory.
+        Err(e) if e.kind() == std::io::ErrorKind::CrossesDevices => {
+            debug!(
+                "  Cannot hard link {} across filesystems; copying it",
+                source.display()
+            );
+            fs::copy(source, dest)?;
+        }
+        Err(e) => return Err(SpsError::Io(std::sync::Arc::new(e))),
+    }
+    Ok(())
+}
+
+/// Whether `target` already is what [`place_tree`] makes of `source`. A file counts when it is
+/// a hard link to the keg's file (not for `copy`) or has the same mode and content.
+fn is_placed(source: &Path, target: &Path, strategy: LinkStrategy) -> bool {
+    let (Ok(src), Ok(dst)) = (source.symlink_metadata(), target.symlink_metadata()) else {
+        return false;
+    };
+    if src.file_type().is_symlink() || dst.file_type().is_symlink() {
+        return src.file_type().is_symlink()
+            && dst.file_type().is_symlink()
+            && fs::read_link(source).ok() == fs::read_link(target).ok();
+    }
+    if src.is_dir() || dst.is_dir() {
+        let names = |dir: &Path| -> Option<BTreeSet<std::ffi::OsString>> {
+            Some(
+                fs::read_dir(dir)
+                    .ok()?
+                    .flatten()
+                    .map(|e| e.file_name())
+                    .collect(),
+            )
+        };
+        return src.is_dir()
+            && dst.is_dir()
+            && match (names(source), names(target)) {
+                (Some(a), Some(b)) if a == b => a
+                    .iter()
+                    .all(|name| is_placed(&source.join(name), &target.join(name), strategy)),
+                _ => false,
+            };
+    }
+    let same_inode = src.dev() == dst.dev() && src.ino() == dst.ino();
+    match strategy {
+        LinkStrategy::Hardlink if same_inode => true,
+        LinkStrategy::Copy if same_inode => false,
+        _ => {
+            src.len() == dst.len()
+                && src.mode() == dst.mode()
+                && fs::read(source).is_ok_and(|a| fs::read(target).is_ok_and(|b| a == b))
+        }
+    }
+}
+
+/// Notes `strategy` as `link_strategy` in the receipt of the keg at `keg_path`. Only logged on
+/// failure; the links themselves are in place.
+fn record_link_strategy(keg_path: &Path, strategy: LinkStrategy) {
+    let receipt_path = keg_path.join("INSTALL_RECEIPT.json");
+    let Some(mut receipt) = fs::read_to_string(&receipt_path)
+        .ok()
+        .and_then(|text| serde_json::from_str::<Map<String, Value>>(&text).ok())
+    else {
+        debug!("No readable receipt at {}", receipt_path.display());
+        return;
+    };
+    if receipt.get("link_strategy").and_then(Value::as_str) == Some(strategy.as_str()) {
+        return;
+    }
+    receipt.insert("link_strategy".to_string(), Value::from(strategy.as_str()));
+    let written = serde_json::to_string_pretty(&receipt)
+        .map_err(|e| e.to_string())
+        .and_then(|json| fs::write(&receipt_path, json).map_err(|e| e.to_string()));
+    if let Err(e) = written {
+        warn!(
+            "Could not record the link strategy in {}: {}",
+            receipt_path.display(),
+            e
+        );
+    }
+}
+
+/// A hidden sibling of `path` to build a replacement under before renaming it into place.
+fn temp_path_for(path: &Path) -> PathBuf {
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    path.with_file_name(format!(".{name}.sps-tmp-{}", std::process::id()))
+}
+
 fn determine_content_root(installed_keg_path: &Path) -> Result<PathBuf> {
     let mut potential_subdirs = Vec::new();
     let mut top_level_files_found = false;
@@ -444,10 +899,18 @@ fn remove_existing_link_target(path: &Path) -> Result<()> {
     }
 }
 
-fn write_install_manifest(installed_keg_path: &Path, symlinks_created: &[String]) -> Result<()> {
+pub(crate) fn write_install_manifest(
+    installed_keg_path: &Path,
+    symlinks_created: &[String],
+) -> Result<()> {
     let manifest_path = installed_keg_path.join("INSTALL_MANIFEST.json");
     debug!("Writing install manifest to: {}", manifest_path.display());
     match serde_json::to_string_pretty(&symlinks_created) {
+        Ok(manifest_json)
+            if fs::read_to_string(&manifest_path).is_ok_and(|current| current == manifest_json) =>
+        {
+            debug!("Install manifest {} is unchanged", manifest_path.display());
+        }
         Ok(manifest_json) => match fs::write(&manifest_path, manifest_json) {
             Ok(_) => {
                 debug!(
@@ -485,6 +948,7 @@ pub fn unlink_formula_artifacts(
     // Use config method to get expected keg path based on name and version string
     let expected_keg_path = config.formula_keg_path(formula_name, version_str_full);
     let manifest_path = expected_keg_path.join("INSTALL_MANIFEST.json"); // Manifest *inside* the keg
+    owners::forget_keg(config, &expected_keg_path);
 
     if manifest_path.is_file() {
         debug!("Reading install manifest: {}", manifest_path.display());
@@ -492,70 +956,18 @@ pub fn unlink_formula_artifacts(
             Ok(manifest_str) => {
                 match serde_json::from_str::<Vec<String>>(&manifest_str) {
                     Ok(links_to_remove) => {
-                        let mut unlinked_count = 0;
-                        let mut removal_errors = 0;
                         if links_to_remove.is_empty() {
                             debug!(
                                 "Install manifest {} is empty. Cannot perform manifest-based unlink.",
                                 manifest_path.display()
                             );
                         } else {
-                            // Use Config to get base paths for checking ownership/safety
-                            let opt_base = config.opt_dir();
-                            let bin_base = config.bin_dir();
-                            let lib_base = config.prefix().join("lib");
-                            let include_base = config.prefix().join("include");
-                            let share_base = config.prefix().join("share");
-                            // Add etc, sbin etc. if needed
-
-                            for link_str in links_to_remove {
-                                let link_path = PathBuf::from(link_str);
-                                // Check if it's under a managed directory (safety check)
-                                if link_path.starts_with(&opt_base)
-                                    || link_path.starts_with(&bin_base)
-                                    || link_path.starts_with(&lib_base)
-                                    || link_path.starts_with(&include_base)
-                                    || link_path.starts_with(&share_base)
-                                {
-                                    match remove_existing_link_target(&link_path) {
-                                        // Use helper
-                                        Ok(_) => {
-                                            debug!("Removed link/wrapper: {}", link_path.display());
-                                            unlinked_count += 1;
-                                        }
-                                        Err(e) => {
-                                            // Log error but continue trying to remove others
-                                            debug!(
-                                                "Failed to remove link/wrapper {}: {}",
-                                                link_path.display(),
-                                                e
-                                            );
-                                            removal_errors += 1;
-                                        }
-                                    }
-                                } else {
-                                    // This indicates a potentially corrupted manifest or a link
-                                    // outside expected areas
-                                    error!(
-                                        "Manifest contains unexpected link path, skipping removal: {}",
-                                        link_path.display()
-                                    );
-                                    removal_errors += 1; // Count as an error/problem
-                                }
-                            }
-                        }
-                        debug!(
-                            "Attempted to unlink {} artifacts based on manifest.",
-                            unlinked_count
-                        );
-                        if removal_errors > 0 {
-                            error!(
-                                "Encountered {} errors while removing links listed in manifest.",
-                                removal_errors
+                            remove_manifest_links(
+                                &links_to_remove,
+                                &expected_keg_path,
+                                config,
+                                &[],
                             );
-                            // Decide if this should be a hard error - perhaps not if keg is being
-                            // removed anyway? For now, just log
-                            // warnings.
                         }
                         Ok(()) // Return Ok even if some links failed, keg removal will happen next
                     }
@@ -590,6 +1002,20 @@ pub fn unlink_formula_artifacts(
     }
 }
 
+/// Whether the link at `link_path` points into `keg_path`, or can't be read. Relative targets
+/// (as Homebrew writes them) are resolved first.
+fn points_into(link_path: &Path, keg_path: &Path) -> bool {
+    match fs::read_link(link_path) {
+        Ok(target) if target.starts_with(keg_path) => true,
+        Ok(target) if target.is_relative() => matches!(
+            (fs::canonicalize(link_path), fs::canonicalize(keg_path)),
+            (Ok(resolved), Ok(keg)) if resolved.starts_with(&keg)
+        ),
+        Ok(_) => false,
+        Err(_) => true,
+    }
+}
+
 fn is_executable(path: &Path) -> Result<bool> {
     if !path.try_exists().unwrap_or(false) || !path.is_file() {
         return Ok(false);
@@ -604,3 +1030,323 @@ fn is_executable(path: &Path) -> Result<bool> {
         Ok(true)
     }
 }
+
+/// Removes the links and wrappers listed in the manifest of the keg at `keg_path`, skipping
+/// anything outside the managed prefix directories and opt links another keg has claimed since.
+/// A listed directory link that has since become a merged directory only loses the links into
+/// this keg, other than those in `keep`.
+fn remove_manifest_links(links: &[String], keg_path: &Path, config: &Config, keep: &[String]) {
+    let mut unlinked_count = 0;
+    let mut removal_errors = 0;
+    // Use Config to get base paths for checking ownership/safety
+    let opt_base = config.opt_dir();
+    let bin_base = config.bin_dir();
+    let lib_base = config.prefix().join("lib");
+    let include_base = config.prefix().join("include");
+    let share_base = config.prefix().join("share");
+    let etc_base = config.prefix().join("etc");
+
+    for link_str in links {
+        let link_path = PathBuf::from(link_str);
+        // Check if it's under a managed directory (safety check)
+        if link_path.starts_with(&opt_base)
+            || link_path.starts_with(&bin_base)
+            || link_path.starts_with(&lib_base)
+            || link_path.starts_with(&include_base)
+            || link_path.starts_with(&share_base)
+            || link_path.starts_with(&etc_base)
+        {
+            // An opt alias may since have been claimed by another
+            // formula; leave it to that formula.
+            if link_path.starts_with(&opt_base) && !points_into(&link_path, keg_path) {
+                debug!("Skipping {}: now owned by another keg", link_path.display());
+                continue;
+            }
+            let merged = link_path.symlink_metadata().is_ok_and(|m| m.is_dir())
+                && link_path
+                    .strip_prefix(config.prefix())
+                    .is_ok_and(is_shared_dir);
+            if merged {
+                unlinked_count += remove_links_into(&link_path, keg_path, keep);
+                continue;
+            }
+            match remove_existing_link_target(&link_path) {
+                // Use helper
+                Ok(_) => {
+                    debug!("Removed link/wrapper: {}", link_path.display());
+                    unlinked_count += 1;
+                }
+                Err(e) => {
+                    // Log error but continue trying to remove others
+                    debug!(
+                        "Failed to remove link/wrapper {}: {}",
+                        link_path.display(),
+                        e
+                    );
+                    removal_errors += 1;
+                }
+            }
+        } else {
+            // This indicates a potentially corrupted manifest or a link
+            // outside expected areas
+            error!(
+                "Manifest contains unexpected link path, skipping removal: {}",
+                link_path.display()
+            );
+            removal_errors += 1; // Count as an error/problem
+        }
+    }
+    debug!(
+        "Attempted to unlink {} artifacts based on manifest.",
+        unlinked_count
+    );
+    if removal_errors > 0 {
+        error!(
+            "Encountered {} errors while removing links listed in manifest.",
+            removal_errors
+        );
+        // Decide if this should be a hard error - perhaps not if keg is being
+        // removed anyway? For now, just log
+        // warnings.
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use tempfile::TempDir;
+
+    use super::*;
+
+    /// A config rooted in a fresh prefix, with the directories the pipeline expects.
+    fn scratch_config() -> (TempDir, Config) {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            prefix: dir.path().to_path_buf(),
+            cellar: dir.path().join("Cellar"),
+            ..Config::load().unwrap()
+        };
+        for path in [config.cellar.clone(), config.opt_dir(), config.bin_dir()] {
+            fs::create_dir_all(path).unwrap();
+        }
+        (dir, config)
+    }
+
+    fn formula(name: &str) -> Formula {
+        serde_json::from_value(json!({ "name": name, "versions": { "stable": "1.0" } })).unwrap()
+    }
+
+    /// Creates the keg `Cellar/<name>/1.0` holding `files`, by path relative to the keg.
+    fn keg(config: &Config, name: &str, files: &[&str]) -> PathBuf {
+        let keg = config.formula_keg_path(name, "1.0");
+        for file in files {
+            let path = keg.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, file).unwrap();
+        }
+        keg
+    }
+
+    fn is_real_dir(path: &Path) -> bool {
+        path.symlink_metadata().is_ok_and(|m| m.is_dir())
+    }
+
+    #[test]
+    fn manpages_of_two_kegs_share_the_section_directory() {
+        let (_dir, config) = scratch_config();
+        let foo = keg(&config, "foo", &["share/man/man1/foo.1"]);
+        let bar = keg(&config, "bar", &["share/man/man1/bar.1", "share/bar/data"]);
+
+        link_formula_artifacts(&formula("foo"), &foo, &config).unwrap();
+        link_formula_artifacts(&formula("bar"), &bar, &config).unwrap();
+
+        let man1 = config.manpagedir().join("man1");
+        assert!(is_real_dir(&config.manpagedir()));
+        assert!(is_real_dir(&man1));
+        assert_eq!(
+            fs::read_link(man1.join("foo.1")).unwrap(),
+            foo.join("share/man/man1/foo.1")
+        );
+        assert_eq!(
+            fs::read_link(man1.join("bar.1")).unwrap(),
+            bar.join("share/man/man1/bar.1")
+        );
+        // Directories private to a formula are still linked whole.
+        let share_bar = config.prefix().join("share/bar");
+        assert_eq!(fs::read_link(share_bar).unwrap(), bar.join("share/bar"));
+
+        unlink_formula_artifacts("foo", "1.0", &config).unwrap();
+        assert!(man1.join("foo.1").symlink_metadata().is_err());
+        assert!(man1.join("bar.1").exists());
+    }
+
+    #[test]
+    fn relinking_a_keg_leaves_its_links_alone() {
+        let (_dir, config) = scratch_config();
+        let foo = keg(&config, "foo", &["share/man/man1/foo.1"]);
+        link_formula_artifacts(&formula("foo"), &foo, &config).unwrap();
+        let link = config.manpagedir().join("man1/foo.1");
+        let before = link.symlink_metadata().unwrap().ino();
+
+        link_formula_artifacts(&formula("foo"), &foo, &config).unwrap();
+
+        assert_eq!(link.symlink_metadata().unwrap().ino(), before);
+    }
+
+    /// Every entry under the prefix, the Cellar included, with its inode and change times.
+    fn snapshot(config: &Config) -> Vec<(PathBuf, u64, i64, i64, i64, i64)> {
+        WalkDir::new(config.prefix())
+            .sort_by_file_name()
+            .into_iter()
+            .map(|entry| {
+                let entry = entry.unwrap();
+                let m = entry.path().symlink_metadata().unwrap();
+                (
+                    entry.path().to_path_buf(),
+                    m.ino(),
+                    m.mtime(),
+                    m.mtime_nsec(),
+                    m.ctime(),
+                    m.ctime_nsec(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn relinking_an_unchanged_keg_mutates_nothing() {
+        let (_dir, config) = scratch_config();
+        let foo = keg(
+            &config,
+            "foo",
+            &[
+                "bin/foo",
+                "lib/libfoo.so",
+                "include/foo.h",
+                "share/man/man1/foo.1",
+                "share/foo/data",
+            ],
+        );
+        fs::set_permissions(foo.join("bin/foo"), fs::Permissions::from_mode(0o755)).unwrap();
+        link_formula_artifacts(&formula("foo"), &foo, &config).unwrap();
+        let before = snapshot(&config);
+        // Outlasts the timestamp granularity of coarse filesystems.
+        std::thread::sleep(std::time::Duration::from_millis(20));
+
+        link_formula_artifacts(&formula("foo"), &foo, &config).unwrap();
+
+        assert_eq!(snapshot(&config), before);
+    }
+
+    #[test]
+    fn a_legacy_directory_link_becomes_a_merged_directory() {
+        let (_dir, config) = scratch_config();
+        let old = keg(
+            &config,
+            "old",
+            &["share/man/man1/old.1", "share/man/man5/old.5"],
+        );
+        // Earlier versions linked the whole `share/man` of the first keg that had one.
+        fs::create_dir_all(config.prefix().join("share")).unwrap();
+        unix_fs::symlink(old.join("share/man"), config.manpagedir()).unwrap();
+        let new = keg(&config, "new", &["share/man/man1/new.1"]);
+
+        link_formula_artifacts(&formula("new"), &new, &config).unwrap();
+
+        let man = config.manpagedir();
+        assert!(is_real_dir(&man));
+        assert!(is_real_dir(&man.join("man1")));
+        assert_eq!(
+            fs::read_link(man.join("man1/old.1")).unwrap(),
+            old.join("share/man/man1/old.1")
+        );
+        assert_eq!(
+            fs::read_link(man.join("man1/new.1")).unwrap(),
+            new.join("share/man/man1/new.1")
+        );
+        // Sections only the old keg has stay a link into it.
+        assert_eq!(
+            fs::read_link(man.join("man5")).unwrap(),
+            old.join("share/man/man5")
+        );
+    }
+
+    #[test]
+    fn shared_directories_match_their_patterns() {
+        let cases = [
+            ("share", true),
+            ("share/man", true),
+            ("share/man/man1", true),
+            ("share/man/man1/foo.1", false),
+            ("share/locale/de/LC_MESSAGES", true),
+            ("share/doc", true),
+            ("share/doc/foo", false),
+            ("share/foo", false),
+            ("include/openssl", false),
+            ("bin", false),
+        ];
+        for (relative, expected) in cases {
+            assert_eq!(is_shared_dir(Path::new(relative)), expected, "{relative}");
+        }
+    }
+
+    #[test]
+    fn a_hard_link_across_filesystems_falls_back_to_a_copy() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("libfoo.so");
+        fs::write(&source, "libfoo").unwrap();
+        fs::set_permissions(&source, fs::Permissions::from_mode(0o751)).unwrap();
+        let dest = dir.path().join("placed");
+        let exdev = |_: &Path, _: &Path| Err(std::io::Error::from_raw_os_error(libc::EXDEV));
+
+        hard_link_or_copy(&source, &dest, exdev).unwrap();
+
+        let (src, dst) = (source.metadata().unwrap(), dest.metadata().unwrap());
+        assert_ne!(src.ino(), dst.ino());
+        assert_eq!(dst.mode() & 0o7777, 0o751);
+        assert_eq!(fs::read(&dest).unwrap(), b"libfoo");
+        assert!(is_placed(&source, &dest, LinkStrategy::Hardlink));
+
+        // Any other failure is reported, not papered over with a copy.
+        let denied = |_: &Path, _: &Path| Err(std::io::Error::from_raw_os_error(libc::EPERM));
+        let other = dir.path().join("other");
+        assert!(hard_link_or_copy(&source, &other, denied).is_err());
+        assert!(other.symlink_metadata().is_err());
+    }
+
+    #[test]
+    fn a_hardlinked_keg_on_another_filesystem_is_copied_and_unlinks_cleanly() {
+        // tmpfs, where the system has one separate from the temp directory.
+        let Ok(shm) = tempfile::tempdir_in("/dev/shm") else {
+            return;
+        };
+        let (_dir, mut config) = scratch_config();
+        if shm.path().metadata().unwrap().dev() == config.prefix().metadata().unwrap().dev() {
+            return;
+        }
+        config.cellar = shm.path().join("Cellar");
+        config.link_strategy = LinkStrategy::Hardlink;
+        let foo = keg(&config, "foo", &["lib/libfoo.so", "share/man/man1/foo.1"]);
+
+        link_formula_artifacts(&formula("foo"), &foo, &config).unwrap();
+
+        for file in ["lib/libfoo.so", "share/man/man1/foo.1"] {
+            let placed = config.prefix().join(file);
+            assert!(!placed.symlink_metadata().unwrap().is_symlink(), "{file}");
+            assert_eq!(fs::read(&placed).unwrap(), file.as_bytes());
+        }
+        // Relinking recognizes the copies instead of copying again.
+        let before = snapshot(&config);
+        link_formula_artifacts(&formula("foo"), &foo, &config).unwrap();
+        assert_eq!(snapshot(&config), before);
+
+        unlink_formula_artifacts("foo", "1.0", &config).unwrap();
+
+        for file in ["lib/libfoo.so", "share/man/man1/foo.1"] {
+            assert!(
+                config.prefix().join(file).symlink_metadata().is_err(),
+                "{file}"
+            );
+        }
+    }
+}
@@ -4,26 +4,37 @@ use std::fs::File;
 use std::io::Write;
 use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::sync::OnceLock;
 
+use sps_common::cache::Cache;
 use sps_common::config::Config;
+use sps_common::dependency::DependencyTag;
 use sps_common::error::{Result, SpsError};
-use sps_common::model::formula::Formula;
+use sps_common::model::formula::{Formula, CORE_TAP};
 use tracing::{debug, error};
 
 // Declare submodules
+pub mod adopt;
+pub mod audit;
 pub mod bottle;
+pub mod integrity;
 pub mod link;
 pub mod macho;
+pub mod owners;
+pub mod post_install;
+pub mod smoke;
 pub mod source;
+pub mod versions;
 
 /// Download formula resources from the internet asynchronously.
 pub async fn download_formula(
     formula: &Formula,
     config: &Config,
+    cache: &Cache,
     client: &reqwest::Client,
 ) -> Result<PathBuf> {
     if has_bottle_for_current_platform(formula) {
-        bottle::download_bottle(formula, config, client).await
+        bottle::download_bottle(formula, config, cache, client).await
     } else {
         Err(SpsError::Generic(format!(
             "No bottle available for {} on this platform",
@@ -46,8 +57,20 @@ pub fn has_bottle_for_current_platform(formula: &Formula) -> bool {
     result.is_ok()
 }
 
+/// This machine's platform tag (see [`get_current_platform`]), computed once per process.
+pub fn host_platform() -> &'static str {
+    static PLATFORM: OnceLock<String> = OnceLock::new();
+    PLATFORM.get_or_init(get_current_platform)
+}
+
+/// `formula` with the variation for this machine's platform applied; see
+/// [`Formula::for_platform`].
+pub fn resolve_for_host(formula: &Formula) -> Formula {
+    formula.for_platform(host_platform())
+}
+
 // *** Updated get_current_platform function ***
-fn get_current_platform() -> String {
+pub(crate) fn get_current_platform() -> String {
     if cfg!(target_os = "macos") {
         let arch = if std::env::consts::ARCH == "aarch64" {
             "arm64"
@@ -258,8 +281,12 @@ pub fn get_formula_cellar_path(formula: &Formula, config: &Config) -> PathBuf {
     config.formula_cellar_dir(formula.name())
 }
 
-// --- write_receipt (unchanged) ---
-pub fn write_receipt(formula: &Formula, install_dir: &Path) -> Result<()> {
+// --- write_receipt ---
+pub fn write_receipt(
+    formula: &Formula,
+    install_dir: &Path,
+    audit_findings: &[audit::AuditFinding],
+) -> Result<()> {
     let receipt_path = install_dir.join("INSTALL_RECEIPT.json");
     let receipt_file = File::create(&receipt_path);
     let mut receipt_file = match receipt_file {
@@ -286,17 +313,33 @@ pub fn write_receipt(formula: &Formula, install_dir: &Path) -> Result<()> {
         }
     };
 
+    // Record the runtime dependencies this keg was installed against, so `sps missing` can check
+    // the closure later without relying on (possibly changed) current metadata.
+    let runtime_dependencies = formula
+        .dependencies()
+        .iter()
+        .filter(|d| {
+            d.tags.contains(DependencyTag::RUNTIME) && !d.tags.contains(DependencyTag::OPTIONAL)
+        })
+        .map(|d| d.name.clone())
+        .collect::<Vec<_>>();
+
     let timestamp = chrono::Utc::now().to_rfc3339();
 
-    let receipt = serde_json::json!({
-        "name": formula.name, "version": formula.version_str_full(), "time": timestamp,
-        "source": { "type": "api", "url": formula.url, },
+    let mut receipt = serde_json::json!({
+        "name": formula.name, "version": formula.version_str_full(),
+        "revision": formula.revision, "time": timestamp,
+        "source": { "type": "api", "url": formula.url, "tap": formula.tap().unwrap_or(CORE_TAP) },
         "built_on": {
             "os": std::env::consts::OS, "arch": std::env::consts::ARCH,
             "platform_tag": get_current_platform(),
          },
         "resources_installed": resources_installed,
+        "runtime_dependencies": runtime_dependencies,
     });
+    if !audit_findings.is_empty() {
+        receipt["bottle_audit"] = serde_json::json!(audit_findings);
+    }
 
     let receipt_json = match serde_json::to_string_pretty(&receipt) {
         Ok(json) => json,
@@ -0,0 +1,266 @@
+// sps-core/src/build/formula/owners.rs
+//! Which keg owns a path under the prefix, for `sps owner`. Paths inside the Cellar belong to
+//! the keg they are in. Paths elsewhere in the prefix are links, looked up in an index of every
+//! linked keg's install manifest kept at `<prefix>/var/sps/owners.json` ([`state::OWNERS`]), so
+//! a query never has to read hundreds of manifests. Linking a keg replaces the entries of its
+//! formula and unlinking drops them; a missing index is rebuilt from the manifests of the linked
+//! kegs.
+//!
+//! The index maps prefix-relative link paths (`bin/jq`, `opt/jq`) to Cellar-relative keg paths
+//! (`jq/1.7.1`).
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+use sps_common::config::Config;
+use sps_common::error::Result;
+use sps_common::keg::{InstalledKeg, KegRegistry};
+use sps_common::model::formula::formula_name_from_keg_dir;
+use sps_common::state;
+use tracing::{debug, warn};
+
+/// Serializes read-modify-write of the index between concurrent install workers.
+static INDEX_LOCK: Mutex<()> = Mutex::new(());
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct OwnerIndex {
+    links: BTreeMap<String, String>,
+}
+
+/// The keg a path belongs to.
+#[derive(Debug, Clone)]
+pub struct PathOwner {
+    pub keg: InstalledKeg,
+    /// The prefix link the path was reached through, when it is not inside the keg itself.
+    pub link: Option<PathBuf>,
+    /// Where the path ends up inside the keg.
+    pub keg_path: PathBuf,
+}
+
+/// Where the index lives for this prefix.
+pub fn index_path(config: &Config) -> PathBuf {
+    state::OWNERS.path(config)
+}
+
+/// Records `links` (absolute paths, as in the install manifest) as belonging to the keg at
+/// `keg_path`, replacing whatever the index held for any keg of the same formula. Failures are
+/// only logged; the index can always be rebuilt.
+pub fn record_links(config: &Config, keg_path: &Path, links: &[String]) {
+    let _guard = INDEX_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let Some(keg) = cellar_relative(config, keg_path) else {
+        return;
+    };
+    let formula_dir = keg.split('/').next().unwrap_or_default().to_string();
+    let mut index = load_index(config);
+    let before = index.links.clone();
+    index
+        .links
+        .retain(|_, owner| owner.split('/').next() != Some(formula_dir.as_str()));
+    for link in links {
+        index
+            .links
+            .insert(prefix_relative(config, link), keg.clone());
+    }
+    // Relinking an unchanged keg leaves the index file alone.
+    if index.links != before {
+        if let Err(e) = store_index(config, &index) {
+            warn!("Could not update the file ownership index: {}", e);
+        }
+    }
+}
+
+/// Drops every entry pointing into the keg at `keg_path`, after it has been unlinked.
+pub fn forget_keg(config: &Config, keg_path: &Path) {
+    let _guard = INDEX_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let Some(keg) = cellar_relative(config, keg_path) else {
+        return;
+    };
+    let mut index = load_index(config);
+    let before = index.links.len();
+    index.links.retain(|_, owner| *owner != keg);
+    if index.links.len() != before {
+        if let Err(e) = store_index(config, &index) {
+            warn!("Could not update the file ownership index: {}", e);
+        }
+    }
+}
+
+/// The keg owning `path`: the keg it is inside, or the keg a prefix link on the way to it (the
+/// path itself or a linked parent directory) points into. `None` for unmanaged paths.
+pub fn owner_of(config: &Config, path: &Path) -> Result<Option<PathOwner>> {
+    let path = normalize(&std::path::absolute(path)?);
+    if let Some(keg) = keg_containing(config, &path)? {
+        return Ok(Some(PathOwner {
+            keg,
+            link: None,
+            keg_path: path,
+        }));
+    }
+
+    let index = {
+        let _guard = INDEX_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        load_index(config)
+    };
+    for link in path.ancestors() {
+        let Ok(relative) = link.strip_prefix(config.prefix()) else {
+            break;
+        };
+        let Some(keg_dir) = index.links.get(&relative.to_string_lossy().to_string()) else {
+            continue;
+        };
+        let Some(keg) = keg_at(config, &config.cellar_path().join(keg_dir))? else {
+            debug!("Ownership index points {} at a missing keg", link.display());
+            continue;
+        };
+        let keg_path = match fs::read_link(link) {
+            Ok(target) => {
+                let target = link
+                    .parent()
+                    .map_or(target.clone(), |dir| dir.join(&target));
+                match path.strip_prefix(link) {
+                    Ok(rest) if !rest.as_os_str().is_empty() => normalize(&target).join(rest),
+                    _ => normalize(&target),
+                }
+            }
+            Err(_) => keg.path.clone(),
+        };
+        return Ok(Some(PathOwner {
+            keg,
+            link: Some(link.to_path_buf()),
+            keg_path,
+        }));
+    }
+
+    // Links the index doesn't know (made before it existed, or by hand) still resolve.
+    if let Ok(resolved) = fs::canonicalize(&path) {
+        if resolved != path {
+            if let Some(keg) = keg_containing(config, &resolved)? {
+                return Ok(Some(PathOwner {
+                    keg,
+                    link: Some(path),
+                    keg_path: resolved,
+                }));
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// The installed keg whose directory contains `path`, comparing against the Cellar both as
+/// configured and canonicalized.
+fn keg_containing(config: &Config, path: &Path) -> Result<Option<InstalledKeg>> {
+    let cellar = config.cellar_path();
+    let canonical_cellar = fs::canonicalize(cellar).ok();
+    for cellar in std::iter::once(cellar).chain(canonical_cellar.as_deref()) {
+        let Ok(relative) = path.strip_prefix(cellar) else {
+            continue;
+        };
+        let mut components = relative.components();
+        let (Some(formula_dir), Some(version_dir)) = (components.next(), components.next()) else {
+            continue;
+        };
+        let keg_path = cellar.join(formula_dir).join(version_dir);
+        if let Some(keg) = keg_at(config, &keg_path)? {
+            return Ok(Some(keg));
+        }
+    }
+    Ok(None)
+}
+
+/// The installed keg at `keg_path` (`<cellar>/<formula dir>/<version>`), if there is one.
+fn keg_at(config: &Config, keg_path: &Path) -> Result<Option<InstalledKeg>> {
+    let Some(formula_dir) = keg_path
+        .parent()
+        .and_then(Path::file_name)
+        .and_then(|n| n.to_str())
+    else {
+        return Ok(None);
+    };
+    let name = formula_name_from_keg_dir(formula_dir);
+    let keg_path = fs::canonicalize(keg_path).unwrap_or_else(|_| keg_path.to_path_buf());
+    Ok(KegRegistry::new(config.clone())
+        .list_formula_kegs(&name)?
+        .into_iter()
+        .find(|keg| fs::canonicalize(&keg.path).unwrap_or_else(|_| keg.path.clone()) == keg_path))
+}
+
+fn load_index(config: &Config) -> OwnerIndex {
+    let path = index_path(config);
+    match state::load::<OwnerIndex>(config, &state::OWNERS) {
+        Ok(Some(index)) => return index,
+        Ok(None) => debug!("No ownership index yet; building {}", path.display()),
+        Err(e) => warn!("Rebuilding unreadable {}: {}", path.display(), e),
+    }
+    let index = rebuild_index(config);
+    if let Err(e) = store_index(config, &index) {
+        warn!("Could not write the file ownership index: {}", e);
+    }
+    index
+}
+
+/// The index as the install manifests of the linked kegs describe it.
+fn rebuild_index(config: &Config) -> OwnerIndex {
+    let mut index = OwnerIndex::default();
+    let registry = KegRegistry::new(config.clone());
+    let kegs = match registry.list_current_kegs() {
+        Ok(kegs) => kegs,
+        Err(e) => {
+            warn!("Could not list kegs for the ownership index: {}", e);
+            return index;
+        }
+    };
+    for keg in kegs
+        .iter()
+        .filter(|keg| registry.is_keg_linked(&keg.name, &keg.path))
+    {
+        let Some(keg_dir) = cellar_relative(config, &keg.path) else {
+            continue;
+        };
+        let links = fs::read_to_string(keg.path.join("INSTALL_MANIFEST.json"))
+            .ok()
+            .and_then(|text| serde_json::from_str::<Vec<String>>(&text).ok())
+            .unwrap_or_default();
+        for link in links {
+            index
+                .links
+                .insert(prefix_relative(config, &link), keg_dir.clone());
+        }
+    }
+    index
+}
+
+fn store_index(config: &Config, index: &OwnerIndex) -> Result<()> {
+    state::store(config, &state::OWNERS, index)
+}
+
+fn cellar_relative(config: &Config, keg_path: &Path) -> Option<String> {
+    keg_path
+        .strip_prefix(config.cellar_path())
+        .ok()
+        .map(|p| p.to_string_lossy().to_string())
+}
+
+fn prefix_relative(config: &Config, link: &str) -> String {
+    Path::new(link)
+        .strip_prefix(config.prefix())
+        .map(|p| p.to_string_lossy().to_string())
+        .unwrap_or_else(|_| link.to_string())
+}
+
+/// Resolves `.` and `..` without touching the filesystem.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            other => normalized.push(other),
+        }
+    }
+    normalized
+}
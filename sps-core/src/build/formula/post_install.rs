@@ -0,0 +1,297 @@
+// sps-core/src/build/formula/post_install.rs
+//! The post-install step of a formula, run after its keg is linked. Homebrew runs each
+//! formula's Ruby `post_install` block, which sps cannot do; what it can do is:
+//!
+//! - copy the `etc`/`var` skeleton a bottle ships under `.bottle/` into the prefix, keeping files
+//!   the user already has (a differing default is written next to it as `*.default`);
+//! - replay the steps of a few well-known formulae whose `post_install` is a plain command, symlink
+//!   or copy (see [`KNOWN_STEPS`]);
+//! - for any other formula the API marks `post_install_defined`, warn that it may be incomplete.
+//!
+//! The outcome is recorded under `post_install` in the keg's receipt.
+
+use std::fs;
+use std::os::unix::fs as unix_fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use serde::Serialize;
+use serde_json::Value;
+use sps_common::config::Config;
+use sps_common::error::{Result, SpsError};
+use sps_common::model::formula::Formula;
+use tracing::{debug, warn};
+use walkdir::WalkDir;
+
+use crate::build::env::apply_subprocess_env;
+
+/// One step of a known formula's post-install. Paths may use `{prefix}` and `{keg}`.
+enum Step {
+    MkDir(&'static str),
+    /// Runs a program with arguments and extra environment variables.
+    Run(
+        &'static str,
+        &'static [&'static str],
+        &'static [(&'static str, &'static str)],
+    ),
+    /// `Symlink(link, target)`, replacing whatever is at `link`.
+    Symlink(&'static str, &'static str),
+    /// `Copy(from, to)`, overwriting `to`.
+    Copy(&'static str, &'static str),
+}
+
+/// Formulae whose `post_install` can be expressed as plain steps.
+const KNOWN_STEPS: &[(&str, &[Step])] = &[
+    (
+        "ca-certificates",
+        // Homebrew also folds in certificates from the system keychain on macOS; this
+        // installs the bundled Mozilla set only.
+        &[
+            Step::MkDir("{prefix}/etc/ca-certificates"),
+            Step::Copy(
+                "{keg}/share/ca-certificates/cacert.pem",
+                "{prefix}/etc/ca-certificates/cert.pem",
+            ),
+        ],
+    ),
+    (
+        "openssl@3",
+        &[
+            Step::MkDir("{prefix}/etc/openssl@3"),
+            Step::Symlink(
+                "{prefix}/etc/openssl@3/cert.pem",
+                "{prefix}/etc/ca-certificates/cert.pem",
+            ),
+        ],
+    ),
+    (
+        "openssl@1.1",
+        &[
+            Step::MkDir("{prefix}/etc/openssl@1.1"),
+            Step::Symlink(
+                "{prefix}/etc/openssl@1.1/cert.pem",
+                "{prefix}/etc/ca-certificates/cert.pem",
+            ),
+        ],
+    ),
+    (
+        "glib",
+        &[
+            Step::MkDir("{prefix}/share/glib-2.0/schemas"),
+            Step::Run(
+                "{keg}/bin/glib-compile-schemas",
+                &["{prefix}/share/glib-2.0/schemas"],
+                &[],
+            ),
+        ],
+    ),
+    (
+        "gdk-pixbuf",
+        &[Step::Run(
+            "{keg}/bin/gdk-pixbuf-query-loaders",
+            &["--update-cache"],
+            &[(
+                "GDK_PIXBUF_MODULEDIR",
+                "{prefix}/lib/gdk-pixbuf-2.0/2.10.0/loaders",
+            )],
+        )],
+    ),
+    (
+        "fontconfig",
+        &[Step::Run(
+            "{keg}/bin/fc-cache",
+            &["--force", "--really-force", "--verbose"],
+            &[],
+        )],
+    ),
+];
+
+/// What the `post_install` of formulae sps can't replay is known to do, for the warning.
+const KNOWN_GAPS: &[(&str, &str)] = &[
+    (
+        "shared-mime-info",
+        "copying its MIME packages into share/mime and running update-mime-database",
+    ),
+    (
+        "gtk+3",
+        "refreshing icon caches with gtk3-update-icon-cache",
+    ),
+    ("gtk4", "refreshing icon caches with gtk4-update-icon-cache"),
+    ("texinfo", "updating the info directory in share/info"),
+    ("php", "setting up pear and the ini files under etc/php"),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Status {
+    Completed,
+    /// The formula defines a post-install step sps could not run.
+    Unsupported,
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct Record {
+    status: Status,
+    actions: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// Runs the post-install step for the linked keg at `keg_path`. A failing step is an
+/// [`SpsError::PostInstallFailed`]; a step that can't be run only warns.
+pub fn run_post_install(formula: &Formula, keg_path: &Path, config: &Config) -> Result<()> {
+    let name = formula.name();
+    let defined = formula
+        .extra
+        .get("post_install_defined")
+        .and_then(Value::as_bool)
+        .unwrap_or(false);
+    let known = KNOWN_STEPS
+        .iter()
+        .find(|(formula, _)| *formula == name)
+        .map(|(_, steps)| *steps);
+
+    let mut record = Record {
+        status: Status::Completed,
+        actions: Vec::new(),
+        error: None,
+    };
+    let result = install_skeleton(keg_path, config, &mut record.actions).and_then(|()| {
+        for step in known.unwrap_or_default() {
+            run_step(step, keg_path, config, &mut record.actions)?;
+        }
+        Ok(())
+    });
+    if let Err(e) = &result {
+        record.status = Status::Failed;
+        record.error = Some(e.to_string());
+    } else if defined && known.is_none() {
+        record.status = Status::Unsupported;
+        let needs = KNOWN_GAPS
+            .iter()
+            .find(|(formula, _)| *formula == name)
+            .map(|(_, needs)| format!(" It likely needs {needs}."))
+            .unwrap_or_default();
+        warn!(
+            "{} has a post-install step that sps cannot run, so it may not work correctly until that is done by hand.{}",
+            name, needs
+        );
+    }
+
+    if defined || !record.actions.is_empty() || record.status == Status::Failed {
+        if let Err(e) = write_record(keg_path, &record) {
+            warn!("Could not record post-install result for {}: {}", name, e);
+        }
+    }
+    result.map_err(|e| SpsError::PostInstallFailed(name.to_string(), e.to_string()))
+}
+
+/// Copies `.bottle/etc` and `.bottle/var` from the keg into the prefix.
+fn install_skeleton(keg_path: &Path, config: &Config, actions: &mut Vec<String>) -> Result<()> {
+    for dir in ["etc", "var"] {
+        let source = keg_path.join(".bottle").join(dir);
+        if !source.is_dir() {
+            continue;
+        }
+        let target_root = config.prefix().join(dir);
+        let mut copied = 0;
+        for entry in WalkDir::new(&source).min_depth(1) {
+            let entry = entry.map_err(|e| SpsError::Generic(format!("{e}")))?;
+            let relative = entry.path().strip_prefix(&source).unwrap_or(entry.path());
+            let target = target_root.join(relative);
+            if entry.file_type().is_dir() {
+                fs::create_dir_all(&target)?;
+                continue;
+            }
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            if target.symlink_metadata().is_err() {
+                fs::copy(entry.path(), &target)?;
+                copied += 1;
+            } else if fs::read(&target).ok() != fs::read(entry.path()).ok() {
+                // Keep the user's version and put the new default beside it.
+                let mut default = target.clone().into_os_string();
+                default.push(".default");
+                fs::copy(entry.path(), &default)?;
+                debug!(
+                    "Kept existing {}; wrote new default alongside",
+                    target.display()
+                );
+            }
+        }
+        actions.push(format!("installed {dir} skeleton ({copied} new file(s))"));
+    }
+    Ok(())
+}
+
+fn run_step(
+    step: &Step,
+    keg_path: &Path,
+    config: &Config,
+    actions: &mut Vec<String>,
+) -> Result<()> {
+    let expand = |s: &str| -> PathBuf {
+        PathBuf::from(
+            s.replace("{prefix}", &config.prefix().to_string_lossy())
+                .replace("{keg}", &keg_path.to_string_lossy()),
+        )
+    };
+    match step {
+        Step::MkDir(dir) => {
+            let dir = expand(dir);
+            fs::create_dir_all(&dir)?;
+            actions.push(format!("created {}", dir.display()));
+        }
+        Step::Run(program, args, env) => {
+            let program = expand(program);
+            let mut command = Command::new(&program);
+            apply_subprocess_env(&mut command, config);
+            command.args(args.iter().map(|a| expand(a)));
+            for (key, value) in *env {
+                command.env(key, expand(value));
+            }
+            debug!("Running post-install command {:?}", command);
+            let output = command
+                .output()
+                .map_err(|e| SpsError::CommandExecError(format!("{}: {e}", program.display())))?;
+            if !output.status.success() {
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                return Err(SpsError::CommandExecError(format!(
+                    "{} exited with {}: {}",
+                    program.display(),
+                    output.status,
+                    stderr.trim()
+                )));
+            }
+            actions.push(format!("ran {}", program.display()));
+        }
+        Step::Symlink(link, target) => {
+            let (link, target) = (expand(link), expand(target));
+            if fs::read_link(&link).is_ok_and(|current| current == target) {
+                return Ok(());
+            }
+            if link.symlink_metadata().is_ok() {
+                fs::remove_file(&link)?;
+            }
+            unix_fs::symlink(&target, &link)?;
+            actions.push(format!("linked {} -> {}", link.display(), target.display()));
+        }
+        Step::Copy(from, to) => {
+            let (from, to) = (expand(from), expand(to));
+            fs::copy(&from, &to)?;
+            actions.push(format!("copied {} to {}", from.display(), to.display()));
+        }
+    }
+    Ok(())
+}
+
+fn write_record(keg_path: &Path, record: &Record) -> Result<()> {
+    let receipt_path = keg_path.join("INSTALL_RECEIPT.json");
+    let mut receipt: serde_json::Map<String, Value> =
+        serde_json::from_str(&fs::read_to_string(&receipt_path)?)?;
+    receipt.insert("post_install".to_string(), serde_json::to_value(record)?);
+    fs::write(&receipt_path, serde_json::to_string_pretty(&receipt)?)?;
+    Ok(())
+}
@@ -0,0 +1,310 @@
+// sps-core/src/build/formula/smoke.rs
+//! The optional post-link smoke check (`post_install_check`, `--verify-run`): runs a few of the
+//! executables a keg linked into the prefix with `--version` (then `--help`), so a bottle that
+//! was poured but can't load its libraries or was built for another architecture fails the
+//! install instead of the first real use.
+
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use sps_common::config::Config;
+use sps_common::error::{Result, SpsError};
+use sps_common::model::formula::Formula;
+use tracing::{debug, warn};
+
+/// Executables probed per formula.
+const MAX_PROBES: usize = 3;
+/// How long one probe may run before it is killed and counted as inconclusive.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+/// stderr of a probe that could not be loaded at all (dyld on macOS, ld.so on Linux).
+const LOADER_ERRORS: [&str; 4] = [
+    "dyld",
+    "Library not loaded",
+    "error while loading shared libraries",
+    "Bad CPU type in executable",
+];
+
+enum Probe {
+    Passed,
+    Failed(String),
+    TimedOut,
+}
+
+/// Runs the executables the keg at `keg_path` linked into `bin`/`sbin`. A loader error, or an
+/// executable that fails both with `--version` and `--help`, is an
+/// [`SpsError::PostInstallFailed`]. Formulae that linked no executables are skipped.
+pub fn run_smoke_check(formula: &Formula, keg_path: &Path, config: &Config) -> Result<()> {
+    let name = formula.name();
+    let executables = linked_executables(name, keg_path, config);
+    if executables.is_empty() {
+        debug!("Smoke check: {} links no executables, skipping", name);
+        return Ok(());
+    }
+    let scratch = tempfile::Builder::new()
+        .prefix(&format!("sps-smoke-{name}-"))
+        .tempdir()
+        .map_err(|e| SpsError::IoError(format!("Failed to create smoke check directory: {e}")))?;
+
+    for executable in &executables {
+        let mut outcome = Probe::TimedOut;
+        for arg in ["--version", "--help"] {
+            outcome = probe(executable, arg, scratch.path(), config)?;
+            match &outcome {
+                Probe::Failed(message) if is_loader_error(message) => break,
+                Probe::Failed(_) => continue,
+                Probe::Passed | Probe::TimedOut => break,
+            }
+        }
+        match outcome {
+            Probe::Passed => debug!("Smoke check passed for {}", executable.display()),
+            Probe::TimedOut => warn!(
+                "Smoke check of {} was inconclusive: no exit within {}s",
+                executable.display(),
+                PROBE_TIMEOUT.as_secs()
+            ),
+            Probe::Failed(message) => {
+                return Err(SpsError::PostInstallFailed(
+                    name.to_string(),
+                    format!("smoke check failed: {message}"),
+                ))
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Up to [`MAX_PROBES`] of the `bin`/`sbin` links in the keg's install manifest, the one named
+/// after the formula first.
+fn linked_executables(name: &str, keg_path: &Path, config: &Config) -> Vec<PathBuf> {
+    let links: Vec<String> = fs::read_to_string(keg_path.join("INSTALL_MANIFEST.json"))
+        .ok()
+        .and_then(|text| serde_json::from_str(&text).ok())
+        .unwrap_or_default();
+    let dirs = [config.bin_dir(), config.prefix().join("sbin")];
+    let mut executables: Vec<PathBuf> = links
+        .iter()
+        .map(PathBuf::from)
+        .filter(|link| {
+            link.parent()
+                .is_some_and(|dir| dirs.iter().any(|d| d == dir))
+        })
+        .filter(|link| link.is_file())
+        .collect();
+    executables.sort_by_key(|link| (link.file_name() != Some(name.as_ref()), link.clone()));
+    executables.truncate(MAX_PROBES);
+    executables
+}
+
+fn probe(executable: &Path, arg: &str, scratch: &Path, config: &Config) -> Result<Probe> {
+    let path = [
+        config.bin_dir(),
+        config.prefix().join("sbin"),
+        PathBuf::from("/usr/bin"),
+        PathBuf::from("/bin"),
+        PathBuf::from("/usr/sbin"),
+        PathBuf::from("/sbin"),
+    ]
+    .iter()
+    .map(|p| p.to_string_lossy().to_string())
+    .collect::<Vec<_>>()
+    .join(":");
+    let stderr_path = scratch.join("stderr");
+    let stderr = File::create(&stderr_path)?;
+
+    debug!("Smoke check: running {} {}", executable.display(), arg);
+    let mut child = match Command::new(executable)
+        .arg(arg)
+        .current_dir(scratch)
+        .env_clear()
+        .env("PATH", path)
+        .env("HOME", scratch)
+        .env("TMPDIR", scratch)
+        .env("LANG", "en_US.UTF-8")
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(stderr)
+        .spawn()
+    {
+        Ok(child) => child,
+        Err(e) => return Ok(Probe::Failed(format!("could not be started: {e}"))),
+    };
+
+    let deadline = Instant::now() + PROBE_TIMEOUT;
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Ok(Probe::TimedOut);
+        }
+        thread::sleep(POLL_INTERVAL);
+    };
+    if status.success() {
+        return Ok(Probe::Passed);
+    }
+    let stderr = fs::read_to_string(&stderr_path).unwrap_or_default();
+    Ok(Probe::Failed(format!(
+        "`{} {arg}` {}{}",
+        executable.display(),
+        describe(status),
+        match stderr.trim() {
+            "" => String::new(),
+            text => format!(": {text}"),
+        }
+    )))
+}
+
+fn describe(status: ExitStatus) -> String {
+    match status.code() {
+        Some(code) => format!("exited with {code}"),
+        None => "was terminated by a signal".to_string(),
+    }
+}
+
+fn is_loader_error(message: &str) -> bool {
+    LOADER_ERRORS.iter().any(|marker| message.contains(marker))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::PermissionsExt;
+
+    use serde_json::json;
+    use tempfile::TempDir;
+
+    use super::*;
+
+    struct Prefix {
+        _dir: TempDir,
+        config: Config,
+        keg: PathBuf,
+    }
+
+    /// A prefix with the keg `Cellar/tool/1.0`, whose manifest lists a link in `bin` for each of
+    /// `scripts` (name, shell body) and a library link that is not probed.
+    fn prefix(scripts: &[(&str, &str)]) -> Prefix {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            prefix: dir.path().to_path_buf(),
+            cellar: dir.path().join("Cellar"),
+            ..Config::load().unwrap()
+        };
+        let keg = config.formula_keg_path("tool", "1.0");
+        fs::create_dir_all(keg.join("bin")).unwrap();
+        fs::create_dir_all(config.bin_dir()).unwrap();
+        let mut links = vec![config.prefix().join("lib/libtool.so")];
+        for (name, body) in scripts {
+            let script = keg.join("bin").join(name);
+            fs::write(&script, format!("#!/bin/sh\n{body}\n")).unwrap();
+            fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+            let link = config.bin_dir().join(name);
+            std::os::unix::fs::symlink(&script, &link).unwrap();
+            links.push(link);
+        }
+        fs::write(
+            keg.join("INSTALL_MANIFEST.json"),
+            serde_json::to_string(&links).unwrap(),
+        )
+        .unwrap();
+        Prefix {
+            _dir: dir,
+            config,
+            keg,
+        }
+    }
+
+    fn formula() -> Formula {
+        serde_json::from_value(json!({ "name": "tool", "versions": { "stable": "1.0" } })).unwrap()
+    }
+
+    fn check(prefix: &Prefix) -> Result<()> {
+        run_smoke_check(&formula(), &prefix.keg, &prefix.config)
+    }
+
+    #[test]
+    fn working_executables_pass() {
+        let prefix = prefix(&[("tool", "echo tool 1.0"), ("tool-helper", "exit 0")]);
+
+        check(&prefix).unwrap();
+    }
+
+    #[test]
+    fn an_executable_without_version_passes_on_help() {
+        let prefix = prefix(&[("tool", r#"[ "$1" = --help ] || exit 2"#)]);
+
+        check(&prefix).unwrap();
+    }
+
+    #[test]
+    fn an_executable_failing_both_probes_fails_with_its_stderr() {
+        let prefix = prefix(&[("tool", "echo 'tool: broken' >&2; exit 3")]);
+
+        let err = check(&prefix).unwrap_err();
+
+        match err {
+            SpsError::PostInstallFailed(name, message) => {
+                assert_eq!(name, "tool");
+                assert!(message.contains("exited with 3"), "{message}");
+                assert!(message.contains("tool: broken"), "{message}");
+            }
+            other => panic!("expected a post-install failure, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn a_loader_error_fails_without_trying_help() {
+        let prefix = prefix(&[(
+            "tool",
+            "echo 'tool: error while loading shared libraries: libfoo.so' >&2; exit 127",
+        )]);
+
+        let err = check(&prefix).unwrap_err();
+
+        assert!(
+            err.to_string()
+                .contains("error while loading shared libraries"),
+            "{err}"
+        );
+        // The failure is the `--version` probe's; `--help` would have failed the same way.
+        assert!(err.to_string().contains("--version"), "{err}");
+    }
+
+    #[test]
+    fn probes_run_in_a_scrubbed_environment() {
+        std::env::set_var("SPS_SMOKE_TEST_LEAK", "1");
+        let prefix = prefix(&[("tool", r#"[ -z "$SPS_SMOKE_TEST_LEAK" ] || exit 4"#)]);
+
+        check(&prefix).unwrap();
+    }
+
+    #[test]
+    fn a_library_only_formula_is_skipped() {
+        let prefix = prefix(&[]);
+
+        check(&prefix).unwrap();
+    }
+
+    #[test]
+    fn at_most_three_executables_are_probed_the_namesake_first() {
+        let prefix = prefix(&[
+            ("a", "exit 0"),
+            ("b", "exit 0"),
+            ("c", "exit 0"),
+            ("tool", "exit 0"),
+        ]);
+
+        let probed = linked_executables("tool", &prefix.keg, &prefix.config);
+
+        let names: Vec<_> = probed
+            .iter()
+            .map(|p| p.file_name().unwrap().to_string_lossy().to_string())
+            .collect();
+        assert_eq!(names, ["tool", "a", "b"]);
+    }
+}
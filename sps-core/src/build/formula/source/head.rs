@@ -0,0 +1,195 @@
+// sps-core/src/build/formula/source/head.rs
+//! `--HEAD` sources: a shallow clone of a formula's `urls.head` repository, kept in
+//! `<download_dir>/heads/<name>` and reused while the branch has not moved. The commit that was
+//! built is recorded under `head` in the keg's receipt so later runs can tell whether upstream
+//! has moved on (see [`remote_commit`]).
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use git2::build::{CheckoutBuilder, RepoBuilder};
+use git2::{Direction, FetchOptions, Remote, Repository};
+use serde_json::{json, Value};
+use sps_common::config::Config;
+use sps_common::error::{Result, SpsError};
+use sps_common::model::formula::{Formula, HeadSpec};
+use tracing::debug;
+
+/// Length of the abbreviated commit used in `HEAD-<commit>` versions.
+const SHORT_COMMIT_LEN: usize = 7;
+
+/// A checked-out head source and the commit it is at.
+#[derive(Debug, Clone)]
+pub struct HeadCheckout {
+    pub path: PathBuf,
+    pub commit: String,
+}
+
+impl HeadCheckout {
+    pub fn short_commit(&self) -> &str {
+        short_commit(&self.commit)
+    }
+}
+
+/// The abbreviated form of `commit` used in `HEAD-<commit>` versions.
+pub fn short_commit(commit: &str) -> &str {
+    &commit[..commit.len().min(SHORT_COMMIT_LEN)]
+}
+
+/// The `head` entry of a keg's receipt.
+#[derive(Debug, Clone)]
+pub struct HeadRecord {
+    pub url: String,
+    pub branch: Option<String>,
+    pub commit: String,
+}
+
+/// The formula's head spec, or an error naming the formula when it has none.
+pub fn require_head_spec(formula: &Formula) -> Result<HeadSpec> {
+    formula.head_spec().ok_or_else(|| {
+        SpsError::InstallError(format!(
+            "{} has no HEAD source (a git `head` URL), so it cannot be installed with --HEAD",
+            formula.name()
+        ))
+    })
+}
+
+/// Brings `<download_dir>/heads/<name>` to the tip of the formula's head branch, cloning it
+/// (depth 1) if it is missing, unusable or behind.
+pub fn fetch_head(formula: &Formula, config: &Config) -> Result<HeadCheckout> {
+    let spec = require_head_spec(formula)?;
+    let download_error = |e: git2::Error| {
+        SpsError::DownloadError(
+            formula.name().to_string(),
+            spec.url.clone(),
+            e.message().to_string(),
+        )
+    };
+    let path = config
+        .download_dir
+        .join("heads")
+        .join(formula.keg_dir_name());
+
+    let tip = remote_commit(&spec).map_err(download_error)?;
+    if let Some(current) = checkout_commit(&path) {
+        if current == tip {
+            debug!("Head checkout of {} is current at {}", formula.name(), tip);
+            return Ok(HeadCheckout { path, commit: tip });
+        }
+        debug!(
+            "Head checkout of {} is at {}, upstream is at {}",
+            formula.name(),
+            current,
+            tip
+        );
+    }
+
+    if path.exists() {
+        fs::remove_dir_all(&path)?;
+    }
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    debug!(
+        "Cloning {} (branch {}) into {}",
+        spec.url,
+        spec.branch.as_deref().unwrap_or("default"),
+        path.display()
+    );
+    // Local and dumb-HTTP transports can't do shallow fetches; those get a full clone.
+    let repo = match clone(&spec, &path, 1) {
+        Ok(repo) => repo,
+        Err(e) => {
+            debug!(
+                "Shallow clone of {} failed ({}); cloning fully",
+                spec.url,
+                e.message()
+            );
+            if path.exists() {
+                fs::remove_dir_all(&path)?;
+            }
+            clone(&spec, &path, 0).map_err(download_error)?
+        }
+    };
+    let commit = repo
+        .head()
+        .and_then(|head| head.peel_to_commit())
+        .map_err(download_error)?
+        .id()
+        .to_string();
+    Ok(HeadCheckout { path, commit })
+}
+
+/// Clones the head branch into `path`, `depth` commits deep (0 for the full history).
+fn clone(spec: &HeadSpec, path: &Path, depth: i32) -> std::result::Result<Repository, git2::Error> {
+    let mut fetch_options = FetchOptions::new();
+    fetch_options.depth(depth);
+    let mut builder = RepoBuilder::new();
+    builder.fetch_options(fetch_options);
+    if let Some(branch) = &spec.branch {
+        builder.branch(branch);
+    }
+    builder.clone(&spec.url, path)
+}
+
+/// The commit the head branch currently points at upstream, without fetching any objects.
+pub fn remote_commit(spec: &HeadSpec) -> std::result::Result<String, git2::Error> {
+    let mut remote = Remote::create_detached(spec.url.as_str())?;
+    remote.connect(Direction::Fetch)?;
+    let wanted = match &spec.branch {
+        Some(branch) => format!("refs/heads/{branch}"),
+        None => "HEAD".to_string(),
+    };
+    let commit = remote
+        .list()?
+        .iter()
+        .find(|head| head.name() == wanted)
+        .map(|head| head.oid().to_string());
+    commit.ok_or_else(|| git2::Error::from_str(&format!("{} has no {wanted}", spec.url)))
+}
+
+fn checkout_commit(path: &Path) -> Option<String> {
+    let repo = Repository::open(path).ok()?;
+    let commit = repo.head().ok()?.peel_to_commit().ok()?.id().to_string();
+    // A checkout whose files were touched is re-cloned rather than trusted.
+    let mut checkout = CheckoutBuilder::new();
+    checkout.force();
+    repo.checkout_head(Some(&mut checkout)).ok()?;
+    Some(commit)
+}
+
+/// Records the head source under `head` in the receipt of the keg at `keg_path`.
+pub fn record_head(keg_path: &Path, formula: &Formula, checkout: &Path) -> Result<()> {
+    let spec = require_head_spec(formula)?;
+    let commit = checkout_commit(checkout).ok_or_else(|| {
+        SpsError::Generic(format!(
+            "Head checkout {} is not a git repository",
+            checkout.display()
+        ))
+    })?;
+    let receipt_path = keg_path.join("INSTALL_RECEIPT.json");
+    let mut receipt: serde_json::Map<String, Value> =
+        serde_json::from_str(&fs::read_to_string(&receipt_path)?)?;
+    receipt.insert(
+        "head".to_string(),
+        json!({ "url": spec.url, "branch": spec.branch, "commit": commit }),
+    );
+    fs::write(&receipt_path, serde_json::to_string_pretty(&receipt)?)?;
+    Ok(())
+}
+
+/// The `head` entry of the receipt of the keg at `keg_path`, if it was built from a head.
+pub fn read_head_record(keg_path: &Path) -> Option<HeadRecord> {
+    let receipt: Value =
+        serde_json::from_str(&fs::read_to_string(keg_path.join("INSTALL_RECEIPT.json")).ok()?)
+            .ok()?;
+    let head = receipt.get("head")?;
+    Some(HeadRecord {
+        url: head.get("url")?.as_str()?.to_string(),
+        branch: head
+            .get("branch")
+            .and_then(Value::as_str)
+            .map(str::to_string),
+        commit: head.get("commit")?.as_str()?.to_string(),
+    })
+}
@@ -12,6 +12,7 @@ use sps_common::error::{Result, SpsError};
 use sps_common::model::formula::{Formula, FormulaDependencies, ResourceSpec};
 use sps_net::fetch::http as http_fetch;
 use tracing::{debug, error};
+use walkdir::WalkDir;
 
 use crate::build::env::BuildEnvironment;
 use crate::build::extract;
@@ -19,6 +20,7 @@ use crate::build::extract;
 mod cargo;
 mod cmake;
 mod go;
+pub mod head;
 mod make;
 mod meson;
 mod perl;
@@ -280,6 +282,8 @@ fn install_resource(
     Ok(())
 }
 
+/// Builds `formula` from `source_path` (a source archive, a single file, or a head checkout
+/// directory) into its keg, returning the keg path.
 pub async fn build_from_source(
     source_path: &Path,
     formula: &Formula,
@@ -288,17 +292,18 @@ pub async fn build_from_source(
 ) -> Result<PathBuf> {
     let install_dir = formula.install_prefix(&config.cellar)?;
     let formula_name = formula.name();
+    let is_checkout = source_path.is_dir();
 
     let source_extension = source_path
         .extension()
         .and_then(|s| s.to_str())
         .unwrap_or("");
 
-    if !RECOGNISED_SINGLE_FILE_EXTENSIONS.contains(&source_extension) {
+    if !is_checkout && !RECOGNISED_SINGLE_FILE_EXTENSIONS.contains(&source_extension) {
         debug!("Installing single file formula: {}", formula_name);
         create_dir_all_with_context(&install_dir, "install directory")?;
         install_single_file(source_path, formula, &install_dir)?;
-        crate::build::write_receipt(formula, &install_dir)?;
+        crate::build::write_receipt(formula, &install_dir, &[])?;
         return Ok(install_dir);
     }
 
@@ -306,7 +311,13 @@ pub async fn build_from_source(
         .extension()
         .and_then(|e| e.to_str())
         .unwrap_or("");
-    if !expected_ext.is_empty() && RECOGNISED_SINGLE_FILE_EXTENSIONS.contains(&expected_ext) {
+    if is_checkout {
+        tracing::debug!(
+            "Building from checkout {}; no content type to verify",
+            source_path.display()
+        );
+    } else if !expected_ext.is_empty() && RECOGNISED_SINGLE_FILE_EXTENSIONS.contains(&expected_ext)
+    {
         tracing::debug!(
             "Verifying source content type for {} against expected extension '{}'",
             source_path.display(),
@@ -324,31 +335,40 @@ pub async fn build_from_source(
         );
     }
 
-    let source_archive_type_str = determine_archive_type(source_path, "main source archive")?;
-    let inferred_root_dir = extract::infer_archive_root_dir(source_path, source_archive_type_str)?;
-    let strip_components = if inferred_root_dir.is_some() { 1 } else { 0 };
-
-    let temp_dir_base = config.cache_dir.join("build-temp");
-    create_dir_all_with_context(&temp_dir_base, "build temp base")?;
+    let temp_dir_base = &config.build_temp_dir;
+    create_dir_all_with_context(temp_dir_base, "build temp base")?;
     let temp_build_dir = tempfile::Builder::new()
         .prefix(&format!("{formula_name}-"))
-        .tempdir_in(&temp_dir_base)
+        .tempdir_in(temp_dir_base)
         .map_err(|e| SpsError::IoError(format!("Failed create temp build dir: {e}")))?;
     let build_dir = temp_build_dir.path();
 
-    debug!(
-        "Extracting main source {} to {} (strip={})",
-        source_path.display(),
-        build_dir.display(),
-        strip_components
-    );
-    crate::build::extract::extract_archive(
-        source_path,
-        build_dir,
-        strip_components,
-        source_archive_type_str,
-    )?;
-    debug!("Extracted main source to {}", build_dir.display());
+    if is_checkout {
+        debug!(
+            "Copying checkout {} to {}",
+            source_path.display(),
+            build_dir.display()
+        );
+        copy_checkout(source_path, build_dir)?;
+    } else {
+        let source_archive_type_str = determine_archive_type(source_path, "main source archive")?;
+        let inferred_root_dir =
+            extract::infer_archive_root_dir(source_path, source_archive_type_str)?;
+        let strip_components = if inferred_root_dir.is_some() { 1 } else { 0 };
+        debug!(
+            "Extracting main source {} to {} (strip={})",
+            source_path.display(),
+            build_dir.display(),
+            strip_components
+        );
+        crate::build::extract::extract_archive(
+            source_path,
+            build_dir,
+            strip_components,
+            source_archive_type_str,
+        )?;
+        debug!("Extracted main source to {}", build_dir.display());
+    }
 
     let resources = formula.resources()?;
     let mut resource_stage_paths = HashMap::new();
@@ -401,9 +421,7 @@ pub async fn build_from_source(
     );
 
     debug!("Setting up build environment");
-    let sps_prefix = config.prefix();
-    let build_env =
-        BuildEnvironment::new(formula, sps_prefix, &config.cellar, all_installed_paths)?;
+    let build_env = BuildEnvironment::new(formula, config, all_installed_paths)?;
 
     if !resources.is_empty() {
         debug!("Installing {} resources into libexec", resources.len());
@@ -448,7 +466,7 @@ pub async fn build_from_source(
             install_dir.display()
         );
     }
-    crate::build::write_receipt(formula, &install_dir)?;
+    crate::build::write_receipt(formula, &install_dir, &[])?;
     debug!(
         "Build completed, temporary directory {} will be cleaned up.",
         build_dir.display()
@@ -457,6 +475,29 @@ pub async fn build_from_source(
     Ok(install_dir)
 }
 
+/// Copies a checkout into the build directory without its `.git`, so the cached clone stays
+/// clean for the next build.
+fn copy_checkout(checkout: &Path, build_dir: &Path) -> Result<()> {
+    let entries = WalkDir::new(checkout)
+        .min_depth(1)
+        .into_iter()
+        .filter_entry(|e| e.depth() != 1 || e.file_name() != ".git");
+    for entry in entries {
+        let entry = entry.map_err(|e| SpsError::Generic(format!("{e}")))?;
+        let relative = entry.path().strip_prefix(checkout).unwrap_or(entry.path());
+        let target = build_dir.join(relative);
+        let file_type = entry.file_type();
+        if file_type.is_dir() {
+            create_dir_all_with_context(&target, "build directory")?;
+        } else if file_type.is_symlink() {
+            std::os::unix::fs::symlink(fs::read_link(entry.path())?, &target)?;
+        } else {
+            fs::copy(entry.path(), &target)?;
+        }
+    }
+    Ok(())
+}
+
 fn install_perl_resource(
     resource: &ResourceSpec,
     stage_path: &Path, // This is the CWD for the commands now
@@ -593,7 +634,7 @@ fn install_python_resource(
 }
 
 fn install_single_file(source_path: &Path, formula: &Formula, install_dir: &Path) -> Result<()> {
-    let target_dir = install_dir.join("share").join(&formula.name);
+    let target_dir = install_dir.join("share").join(formula.short_name());
     create_dir_all_with_context(&target_dir, "single file target directory")?;
 
     let target_filename = if formula.name == "ca-certificates" {
@@ -0,0 +1,200 @@
+// sps-core/src/build/formula/versions.rs
+//! Several installed versions of one formula. An upgrade keeps the previous keg in the Cellar
+//! and records it as `previous_version` in the new keg's receipt; [`switch_keg`] moves the opt
+//! link and the prefix links between installed kegs without downloading anything, and
+//! [`expired_kegs`] picks the kept kegs `sps cleanup` may remove.
+//!
+//! Receipt fields: `previous_version` (the version that was current before this keg),
+//! `superseded_at` (when this keg stopped being current) and `pinned` (never cleaned up).
+
+use std::fs;
+use std::path::Path;
+
+use chrono::{DateTime, Duration, Utc};
+use serde_json::{Map, Value};
+use sps_common::config::Config;
+use sps_common::error::{Result, SpsError};
+use sps_common::keg::{InstalledKeg, KegRegistry};
+use sps_common::model::formula::Formula;
+use tracing::debug;
+
+use super::link::{self, PreviousLinks};
+
+const RECEIPT_FILE: &str = "INSTALL_RECEIPT.json";
+
+/// The retention fields of a keg's receipt.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KegHistory {
+    pub previous_version: Option<String>,
+    pub superseded_at: Option<DateTime<Utc>>,
+    pub pinned: bool,
+}
+
+impl KegHistory {
+    /// Reads the fields from the receipt in `keg_path`; missing ones are left at their defaults.
+    pub fn read(keg_path: &Path) -> Self {
+        let receipt = read_receipt(keg_path).unwrap_or_default();
+        Self {
+            previous_version: receipt
+                .get("previous_version")
+                .and_then(Value::as_str)
+                .map(str::to_string),
+            superseded_at: receipt
+                .get("superseded_at")
+                .and_then(Value::as_str)
+                .and_then(|t| DateTime::parse_from_rfc3339(t).ok())
+                .map(|t| t.with_timezone(&Utc)),
+            pinned: receipt
+                .get("pinned")
+                .and_then(Value::as_bool)
+                .unwrap_or(false),
+        }
+    }
+}
+
+/// The directory name of a keg, i.e. its version with any `_revision` suffix.
+pub fn keg_version(keg: &InstalledKeg) -> String {
+    keg.path
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_else(|| keg.version.to_string())
+}
+
+/// Records that the keg at `new_keg` replaced the one at `old_keg` (version `old_version`).
+pub fn record_upgrade(new_keg: &Path, old_keg: &Path, old_version: &str) -> Result<()> {
+    update_receipt(new_keg, |receipt| {
+        receipt.insert(
+            "previous_version".to_string(),
+            Value::String(old_version.to_string()),
+        );
+        receipt.remove("superseded_at");
+    })?;
+    mark_superseded(old_keg)
+}
+
+/// Sets or clears `pinned` in the receipt of the keg at `keg_path`.
+pub fn set_pinned(keg_path: &Path, pinned: bool) -> Result<()> {
+    update_receipt(keg_path, |receipt| {
+        if pinned {
+            receipt.insert("pinned".to_string(), Value::Bool(true));
+        } else {
+            receipt.remove("pinned");
+        }
+    })
+}
+
+/// Makes `to` the current keg of `formula` in place of `from`: the opt link is swapped with a
+/// rename (so it never dangles) and the prefix links are reconciled from `from`'s manifest.
+pub fn switch_keg(
+    formula: &Formula,
+    from: &InstalledKeg,
+    to: &InstalledKeg,
+    config: &Config,
+) -> Result<()> {
+    debug!(
+        "Switching {} from {} to {}",
+        formula.name(),
+        from.path.display(),
+        to.path.display()
+    );
+    let previous = PreviousLinks::read(&from.path);
+    link::relink_formula_artifacts(formula, &to.path, config, &previous)?;
+    update_receipt(&to.path, |receipt| {
+        receipt.insert(
+            "previous_version".to_string(),
+            Value::String(keg_version(from)),
+        );
+        receipt.remove("superseded_at");
+    })?;
+    mark_superseded(&from.path)
+}
+
+/// Kegs of every formula that are not current, with their history.
+pub fn retained_kegs(config: &Config) -> Result<Vec<(InstalledKeg, KegHistory)>> {
+    let registry = KegRegistry::new(config.clone());
+    let current = registry.list_current_kegs()?;
+    let mut retained: Vec<(InstalledKeg, KegHistory)> = registry
+        .list_installed_kegs()?
+        .into_iter()
+        .filter(|keg| !current.contains(keg))
+        .map(|keg| {
+            let history = KegHistory::read(&keg.path);
+            (keg, history)
+        })
+        .collect();
+    retained.sort_by(|(a, _), (b, _)| (&a.name, &a.version).cmp(&(&b.name, &b.version)));
+    Ok(retained)
+}
+
+/// Kept kegs that stopped being current more than `keg_retention_days` ago and are not pinned.
+/// Kegs without a `superseded_at` are aged by their receipt's install time.
+pub fn expired_kegs(config: &Config) -> Result<Vec<InstalledKeg>> {
+    let now = Utc::now();
+    // A window too large to represent keeps everything.
+    let Some(window) = i64::try_from(config.keg_retention_days)
+        .ok()
+        .and_then(Duration::try_days)
+    else {
+        return Ok(Vec::new());
+    };
+    Ok(retained_kegs(config)?
+        .into_iter()
+        .filter(|(keg, history)| {
+            if history.pinned {
+                debug!("Keeping pinned keg {}", keg.path.display());
+                return false;
+            }
+            let since = history.superseded_at.or_else(|| install_time(&keg.path));
+            since.is_none_or(|since| now.signed_duration_since(since) > window)
+        })
+        .map(|(keg, _)| keg)
+        .collect())
+}
+
+/// Removes every kept (non-current) keg of `name`, e.g. when the formula is uninstalled.
+pub fn remove_retained_kegs(name: &str, config: &Config) -> Result<()> {
+    let registry = KegRegistry::new(config.clone());
+    for keg in registry.list_formula_kegs(name)? {
+        if keg.path.exists() {
+            debug!("Removing kept keg {}", keg.path.display());
+            fs::remove_dir_all(&keg.path)?;
+        }
+    }
+    Ok(())
+}
+
+fn mark_superseded(keg_path: &Path) -> Result<()> {
+    update_receipt(keg_path, |receipt| {
+        receipt.insert(
+            "superseded_at".to_string(),
+            Value::String(Utc::now().to_rfc3339()),
+        );
+    })
+}
+
+fn install_time(keg_path: &Path) -> Option<DateTime<Utc>> {
+    let receipt = read_receipt(keg_path)?;
+    let time = receipt.get("time")?.as_str()?;
+    Some(DateTime::parse_from_rfc3339(time).ok()?.with_timezone(&Utc))
+}
+
+fn read_receipt(keg_path: &Path) -> Option<Map<String, Value>> {
+    serde_json::from_str(&fs::read_to_string(keg_path.join(RECEIPT_FILE)).ok()?).ok()
+}
+
+fn update_receipt(keg_path: &Path, update: impl FnOnce(&mut Map<String, Value>)) -> Result<()> {
+    let receipt_path = keg_path.join(RECEIPT_FILE);
+    let mut receipt: Map<String, Value> = match fs::read_to_string(&receipt_path) {
+        Ok(text) => serde_json::from_str(&text)?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Err(SpsError::NotFound(format!(
+                "{} has no install receipt",
+                keg_path.display()
+            )))
+        }
+        Err(e) => return Err(e.into()),
+    };
+    update(&mut receipt);
+    fs::write(&receipt_path, serde_json::to_string_pretty(&receipt)?)?;
+    Ok(())
+}
@@ -0,0 +1,463 @@
+// sps-core/src/build/hashing.rs
+//! SHA256 of many files at once, for `sps verify` and `sps cache verify`, which can have
+//! gigabytes to get through on a large Cellar.
+//!
+//! Files are hashed on scoped threads, one per physical core unless told otherwise; with a
+//! single job everything runs on the calling thread. Either way the result is the same and
+//! comes back in input order. A file that can't be read is reported on its own and doesn't stop
+//! the others. Cancelling stops the workers between files and returns an error instead of a
+//! partial result, so nothing acts on half a scan.
+//!
+//! A tree walk can be handed hashes taken earlier (see [`KnownHash`]), e.g. while a bottle was
+//! being extracted; files whose metadata still matches are not read again.
+
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use std::{io, thread};
+
+use sha2::{Digest, Sha256};
+use sps_common::error::{Result, SpsError};
+use tracing::debug;
+use walkdir::WalkDir;
+
+/// How far a hashing run has got.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HashProgress {
+    pub files_done: u64,
+    pub files_total: u64,
+    pub bytes_done: u64,
+    pub elapsed: Duration,
+}
+
+impl HashProgress {
+    /// Throughput so far in megabytes (10^6 bytes) per second.
+    pub fn mb_per_sec(&self) -> f64 {
+        let secs = self.elapsed.as_secs_f64();
+        if secs <= 0.0 {
+            return 0.0;
+        }
+        self.bytes_done as f64 / 1_000_000.0 / secs
+    }
+}
+
+pub type HashReporter = Arc<dyn Fn(HashProgress) + Send + Sync>;
+
+/// Parallelism, progress reporting and cancellation of a hashing run.
+#[derive(Clone)]
+pub struct HashControl {
+    /// Files hashed at once; 1 hashes serially on the calling thread.
+    pub jobs: usize,
+    /// Set to stop the run; it then fails with [`SpsError::Cancelled`].
+    pub cancel: Arc<AtomicBool>,
+    pub progress: Option<HashReporter>,
+}
+
+impl Default for HashControl {
+    fn default() -> Self {
+        Self {
+            jobs: num_cpus::get_physical().max(1),
+            cancel: Arc::new(AtomicBool::new(false)),
+            progress: None,
+        }
+    }
+}
+
+impl HashControl {
+    fn is_cancelled(&self) -> bool {
+        self.cancel.load(Ordering::SeqCst)
+    }
+}
+
+/// Size and SHA256 of one regular file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileHash {
+    pub size: u64,
+    pub sha256: String,
+}
+
+/// The metadata that tells whether a file was rewritten since it was hashed: its size, mtime
+/// and inode. Permission changes don't count; rewriting in place or replacing the file does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileStamp {
+    len: u64,
+    modified: Option<SystemTime>,
+    ino: u64,
+}
+
+impl FileStamp {
+    pub fn of(metadata: &fs::Metadata) -> Self {
+        use std::os::unix::fs::MetadataExt;
+        Self {
+            len: metadata.len(),
+            modified: metadata.modified().ok(),
+            ino: metadata.ino(),
+        }
+    }
+}
+
+/// A hash taken earlier, valid while the file still has `stamp`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KnownHash {
+    pub hash: FileHash,
+    pub stamp: FileStamp,
+}
+
+/// What a tree walk found at a path, relative to the root.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TreeEntry {
+    File(FileHash),
+    /// Symlinks are recorded by target and never followed.
+    Symlink(String),
+}
+
+/// Result of [`hash_tree`]: every file and symlink found, plus the paths that could not be read
+/// with the reason.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HashedTree {
+    pub entries: BTreeMap<String, TreeEntry>,
+    pub errors: BTreeMap<String, String>,
+}
+
+/// Hashes `paths`, returning one result per path in the same order.
+pub fn hash_files(paths: &[PathBuf], control: &HashControl) -> Result<Vec<io::Result<FileHash>>> {
+    let started = Instant::now();
+    let done = AtomicU64::new(0);
+    let bytes = AtomicU64::new(0);
+    let report = |hashed: &io::Result<FileHash>| {
+        let size = hashed.as_ref().map_or(0, |h| h.size);
+        let progress = HashProgress {
+            files_done: done.fetch_add(1, Ordering::SeqCst) + 1,
+            files_total: paths.len() as u64,
+            bytes_done: bytes.fetch_add(size, Ordering::SeqCst) + size,
+            elapsed: started.elapsed(),
+        };
+        if let Some(reporter) = &control.progress {
+            reporter(progress);
+        }
+    };
+
+    let jobs = control.jobs.clamp(1, paths.len().max(1));
+    let results = if jobs == 1 {
+        let mut results = Vec::with_capacity(paths.len());
+        for path in paths {
+            if control.is_cancelled() {
+                break;
+            }
+            let hashed = hash_file(path);
+            report(&hashed);
+            results.push(hashed);
+        }
+        results
+    } else {
+        debug!("Hashing {} files with {} jobs", paths.len(), jobs);
+        let next = AtomicUsize::new(0);
+        let slots: Mutex<Vec<Option<io::Result<FileHash>>>> =
+            Mutex::new((0..paths.len()).map(|_| None).collect());
+        thread::scope(|scope| {
+            for _ in 0..jobs {
+                scope.spawn(|| loop {
+                    if control.is_cancelled() {
+                        break;
+                    }
+                    let index = next.fetch_add(1, Ordering::SeqCst);
+                    let Some(path) = paths.get(index) else {
+                        break;
+                    };
+                    let hashed = hash_file(path);
+                    report(&hashed);
+                    slots.lock().unwrap_or_else(|e| e.into_inner())[index] = Some(hashed);
+                });
+            }
+        });
+        slots
+            .into_inner()
+            .unwrap_or_else(|e| e.into_inner())
+            .into_iter()
+            .map_while(|slot| slot)
+            .collect()
+    };
+    if control.is_cancelled() {
+        return Err(SpsError::Cancelled(format!(
+            "Hashing stopped after {} of {} files",
+            done.load(Ordering::SeqCst),
+            paths.len()
+        )));
+    }
+    Ok(results)
+}
+
+/// Walks `root` without following symlinks and hashes every regular file in it. `skip` is
+/// given each root-relative path and its depth and leaves out the entries it returns `true`
+/// for. Directories are descended but not recorded; other special files are ignored.
+pub fn hash_tree(
+    root: &Path,
+    skip: impl Fn(&str, usize) -> bool,
+    control: &HashControl,
+) -> Result<HashedTree> {
+    hash_tree_reusing(root, skip, &BTreeMap::new(), control)
+}
+
+/// Like [`hash_tree`], but takes the hash of a file from `known` (by root-relative path)
+/// instead of reading it, as long as its [`FileStamp`] hasn't changed.
+pub fn hash_tree_reusing(
+    root: &Path,
+    skip: impl Fn(&str, usize) -> bool,
+    known: &BTreeMap<String, KnownHash>,
+    control: &HashControl,
+) -> Result<HashedTree> {
+    let mut tree = HashedTree::default();
+    let mut reused = 0;
+    let mut files: Vec<(String, PathBuf)> = Vec::new();
+    for entry in WalkDir::new(root).follow_links(false).min_depth(1) {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                let rel = e
+                    .path()
+                    .and_then(|p| p.strip_prefix(root).ok())
+                    .map(|p| p.to_string_lossy().to_string())
+                    .unwrap_or_default();
+                tree.errors.insert(rel, e.to_string());
+                continue;
+            }
+        };
+        let Ok(rel) = entry.path().strip_prefix(root) else {
+            continue;
+        };
+        let rel = rel.to_string_lossy().to_string();
+        if skip(&rel, entry.depth()) {
+            continue;
+        }
+        let file_type = entry.file_type();
+        if file_type.is_symlink() {
+            match fs::read_link(entry.path()) {
+                Ok(target) => {
+                    let target = target.to_string_lossy().to_string();
+                    tree.entries.insert(rel, TreeEntry::Symlink(target));
+                }
+                Err(e) => {
+                    tree.errors.insert(rel, e.to_string());
+                }
+            }
+        } else if file_type.is_file() {
+            let unchanged = known.get(&rel).filter(|known| {
+                entry
+                    .metadata()
+                    .is_ok_and(|metadata| FileStamp::of(&metadata) == known.stamp)
+            });
+            match unchanged {
+                Some(known) => {
+                    tree.entries
+                        .insert(rel, TreeEntry::File(known.hash.clone()));
+                    reused += 1;
+                }
+                None => files.push((rel, entry.into_path())),
+            }
+        }
+    }
+    if !known.is_empty() {
+        debug!(
+            "Reusing {} hashes under {}, hashing {} changed or new files",
+            reused,
+            root.display(),
+            files.len()
+        );
+    }
+
+    let paths: Vec<PathBuf> = files.iter().map(|(_, path)| path.clone()).collect();
+    let hashes = hash_files(&paths, control)?;
+    for ((rel, _), hashed) in files.into_iter().zip(hashes) {
+        match hashed {
+            Ok(hash) => {
+                tree.entries.insert(rel, TreeEntry::File(hash));
+            }
+            Err(e) => {
+                tree.errors.insert(rel, e.to_string());
+            }
+        }
+    }
+    Ok(tree)
+}
+
+fn hash_file(path: &Path) -> io::Result<FileHash> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let size = io::copy(&mut file, &mut hasher)?;
+    Ok(FileHash {
+        size,
+        sha256: hex::encode(hasher.finalize()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::symlink;
+
+    use super::*;
+
+    fn control(jobs: usize) -> HashControl {
+        HashControl {
+            jobs,
+            ..HashControl::default()
+        }
+    }
+
+    /// Nested files of assorted sizes, including an empty one, and a relative symlink.
+    fn fixture_tree(root: &Path) {
+        for i in 0..40 {
+            let path = root.join(format!("d{}/sub{}/file{i}", i % 3, i % 5));
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, vec![i as u8; i * 997]).unwrap();
+        }
+        symlink("d0/sub0/file15", root.join("latest")).unwrap();
+    }
+
+    #[test]
+    fn serial_and_parallel_runs_agree() {
+        let dir = tempfile::tempdir().unwrap();
+        fixture_tree(dir.path());
+
+        let serial = hash_tree(dir.path(), |_, _| false, &control(1)).unwrap();
+        let parallel = hash_tree(dir.path(), |_, _| false, &control(4)).unwrap();
+
+        assert_eq!(serial, parallel);
+        assert!(serial.errors.is_empty(), "{:?}", serial.errors);
+        assert_eq!(serial.entries.len(), 41);
+        assert_eq!(
+            serial.entries["d0/sub0/file0"],
+            TreeEntry::File(FileHash {
+                size: 0,
+                sha256: "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855".into(),
+            })
+        );
+        let paths: Vec<PathBuf> = serial
+            .entries
+            .keys()
+            .map(|rel| dir.path().join(rel))
+            .collect();
+        let in_order = |jobs| -> Vec<u64> {
+            hash_files(&paths[..40], &control(jobs))
+                .unwrap()
+                .into_iter()
+                .map(|hashed| hashed.unwrap().size)
+                .collect()
+        };
+        assert_eq!(in_order(1), in_order(4));
+    }
+
+    #[test]
+    fn symlinks_are_recorded_by_target_and_not_followed() {
+        let dir = tempfile::tempdir().unwrap();
+        let (root, outside) = (dir.path().join("keg"), dir.path().join("outside"));
+        fs::create_dir_all(root.join("bin")).unwrap();
+        fs::create_dir_all(&outside).unwrap();
+        fs::write(root.join("bin/tool"), "tool").unwrap();
+        fs::write(outside.join("secret"), "secret").unwrap();
+        symlink("tool", root.join("bin/alias")).unwrap();
+        symlink(&outside, root.join("share")).unwrap();
+        symlink("missing", root.join("dangling")).unwrap();
+
+        let tree = hash_tree(&root, |_, _| false, &control(2)).unwrap();
+
+        assert!(tree.errors.is_empty(), "{:?}", tree.errors);
+        let found: Vec<(&str, &TreeEntry)> = tree
+            .entries
+            .iter()
+            .map(|(rel, entry)| (rel.as_str(), entry))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                ("bin/alias", &TreeEntry::Symlink("tool".into())),
+                (
+                    "bin/tool",
+                    &TreeEntry::File(FileHash {
+                        size: 4,
+                        sha256: hex::encode(Sha256::digest("tool")),
+                    })
+                ),
+                ("dangling", &TreeEntry::Symlink("missing".into())),
+                (
+                    "share",
+                    &TreeEntry::Symlink(outside.to_string_lossy().to_string())
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn a_file_that_cannot_be_read_is_reported_without_stopping_the_others() {
+        let dir = tempfile::tempdir().unwrap();
+        let (first, last) = (dir.path().join("first"), dir.path().join("last"));
+        fs::write(&first, "first").unwrap();
+        fs::write(&last, "last").unwrap();
+        // Opening a directory succeeds and reading it fails, even for root, who could read a
+        // file without permissions.
+        let directory = dir.path().join("directory");
+        fs::create_dir(&directory).unwrap();
+        let paths = [first, directory, dir.path().join("gone"), last];
+
+        for jobs in [1, 3] {
+            let results = hash_files(&paths, &control(jobs)).unwrap();
+
+            let sizes: Vec<Option<u64>> = results
+                .iter()
+                .map(|hashed| hashed.as_ref().ok().map(|h| h.size))
+                .collect();
+            assert_eq!(sizes, [Some(5), None, None, Some(4)], "{jobs} jobs");
+            assert_eq!(
+                results[2].as_ref().unwrap_err().kind(),
+                io::ErrorKind::NotFound
+            );
+        }
+    }
+
+    #[test]
+    fn known_hashes_are_reused_only_while_the_file_is_unchanged() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("kept"), "kept").unwrap();
+        fs::write(dir.path().join("rewritten"), "old").unwrap();
+        // Hashes no file has, so a reused one is told apart from one read again.
+        let known: BTreeMap<String, KnownHash> = ["kept", "rewritten"]
+            .into_iter()
+            .map(|rel| {
+                let metadata = fs::metadata(dir.path().join(rel)).unwrap();
+                let hash = FileHash {
+                    size: metadata.len(),
+                    sha256: format!("recorded-{rel}"),
+                };
+                let stamp = FileStamp::of(&metadata);
+                (rel.to_string(), KnownHash { hash, stamp })
+            })
+            .collect();
+        fs::write(dir.path().join("rewritten"), "new contents").unwrap();
+
+        let tree = hash_tree_reusing(dir.path(), |_, _| false, &known, &control(2)).unwrap();
+
+        let sha = |rel: &str| match &tree.entries[rel] {
+            TreeEntry::File(hash) => hash.sha256.clone(),
+            other => panic!("{rel}: {other:?}"),
+        };
+        assert_eq!(sha("kept"), "recorded-kept");
+        assert_eq!(
+            sha("rewritten"),
+            hex::encode(Sha256::digest("new contents"))
+        );
+    }
+
+    #[test]
+    fn a_cancelled_run_fails_instead_of_returning_part_of_the_result() {
+        let dir = tempfile::tempdir().unwrap();
+        fixture_tree(dir.path());
+        let control = control(2);
+        control.cancel.store(true, Ordering::SeqCst);
+
+        let result = hash_tree(dir.path(), |_, _| false, &control);
+
+        assert!(matches!(result, Err(SpsError::Cancelled(_))), "{result:?}");
+    }
+}
@@ -10,9 +10,14 @@ use sps_common::model::formula::Formula;
 // --- Submodules ---
 pub mod cask;
 pub mod devtools;
+pub mod downloads;
 pub mod env;
 pub mod extract;
+pub mod flock;
 pub mod formula; // <-- Declare the extract module
+pub mod hashing;
+pub mod progress;
+pub mod reaper;
 
 // --- Re-exports ---
 pub use extract::extract_archive; // <-- Re-export the main function from extract.rs
@@ -0,0 +1,48 @@
+// sps-core/src/build/progress.rs
+//! Progress of the bottle pour the current thread is running, for callers that want to show it.
+//! Pours run synchronously on install worker threads, so a reporter is installed per thread with
+//! [`with_pour_reporter`]; pours made outside one report nothing and skip the counting pass.
+
+use std::cell::RefCell;
+use std::sync::Arc;
+
+/// How far a pour has got. `total_files` is known when the archive was small enough to count
+/// up front; otherwise `archive_read` against `archive_size` (compressed bytes) estimates it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PourProgress {
+    pub files: u64,
+    pub total_files: Option<u64>,
+    pub bytes: u64,
+    pub archive_read: u64,
+    pub archive_size: u64,
+}
+
+pub type PourReporter = Arc<dyn Fn(PourProgress) + Send + Sync>;
+
+thread_local! {
+    static REPORTER: RefCell<Option<PourReporter>> = const { RefCell::new(None) };
+}
+
+/// Runs `f` with `reporter` receiving the progress of every pour it makes on this thread.
+pub fn with_pour_reporter<T>(reporter: PourReporter, f: impl FnOnce() -> T) -> T {
+    // Restores the previous reporter even if `f` panics, as worker threads are reused.
+    struct Restore(Option<PourReporter>);
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            REPORTER.with(|r| *r.borrow_mut() = self.0.take());
+        }
+    }
+    let _restore = Restore(REPORTER.with(|r| r.replace(Some(reporter))));
+    f()
+}
+
+pub(crate) fn is_reporting() -> bool {
+    REPORTER.with(|r| r.borrow().is_some())
+}
+
+pub(crate) fn report(progress: PourProgress) {
+    let reporter = REPORTER.with(|r| r.borrow().clone());
+    if let Some(reporter) = reporter {
+        reporter(progress);
+    }
+}
@@ -0,0 +1,276 @@
+// sps-core/src/build/reaper.rs
+//! Reclaims what interrupted runs leave in the cache: the `.download` and `.part` files of
+//! downloads that never finished, source build directories in [`Config::build_temp_dir`], and
+//! the run locks of processes that are gone.
+//!
+//! Every run that installs holds a [`RunLock`], `<cache>/runs/<pid>.lock`, locked exclusively for
+//! as long as the run lasts. The OS drops the lock when the process exits, however it exits, so a
+//! lock file nobody holds belongs to a dead run. Whatever a live run creates is newer than its
+//! lock file, so [`reap`] only touches artifacts older than the oldest live lock as well as
+//! older than [`STALE_AFTER`].
+
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use sps_common::config::{Config, DEFAULT_BUILD_TEMP_SUBDIR};
+use sps_common::error::{Result, SpsError};
+use tracing::debug;
+use walkdir::WalkDir;
+
+use crate::build::flock;
+
+/// Artifacts untouched for this long are assumed abandoned even without any lock telling so,
+/// which covers downloads made by commands that don't take a run lock.
+pub const STALE_AFTER: Duration = Duration::from_secs(60 * 60);
+const RUNS_SUBDIR: &str = "runs";
+/// Download temp files sit at most at `sources/<origin>/.<file>.download`.
+const MAX_SCAN_DEPTH: usize = 3;
+
+/// Marks this process as a live run for other sps processes. Released and removed when dropped.
+#[derive(Debug)]
+pub struct RunLock {
+    file: File,
+    path: PathBuf,
+}
+
+impl RunLock {
+    pub fn acquire(config: &Config) -> Result<Self> {
+        let dir = runs_dir(config);
+        fs::create_dir_all(&dir)?;
+        let path = dir.join(format!("{}.lock", std::process::id()));
+        loop {
+            let mut file = OpenOptions::new()
+                .create(true)
+                .truncate(true)
+                .write(true)
+                .open(&path)?;
+            flock::lock_exclusive(&file).map_err(|e| SpsError::Io(Arc::new(e)))?;
+            // A reaper in another process may have taken the still unlocked file for stale and
+            // removed it in between.
+            if !path.exists() {
+                continue;
+            }
+            writeln!(file, "{}", std::process::id())?;
+            debug!("Holding run lock {}", path.display());
+            return Ok(Self { file, path });
+        }
+    }
+}
+
+impl Drop for RunLock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+        if let Err(e) = flock::unlock(&self.file) {
+            debug!("Failed to release run lock {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+/// What [`reap`] removed.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Reaped {
+    /// Interrupted downloads and build directories removed.
+    pub artifacts: usize,
+    pub bytes: u64,
+    pub stale_locks: usize,
+}
+
+/// Removes leftovers of interrupted runs, leaving alone anything a live run may still be using.
+/// Failures to remove single entries are only logged.
+pub fn reap(config: &Config) -> Reaped {
+    let mut reaped = Reaped::default();
+    let Some(cutoff) = SystemTime::now().checked_sub(STALE_AFTER) else {
+        return reaped;
+    };
+    let cutoff = match live_runs_since(config, &mut reaped) {
+        Some(oldest) => cutoff.min(oldest),
+        None => cutoff,
+    };
+
+    let mut roots = vec![config.download_dir.clone()];
+    if config.cache_dir != config.download_dir {
+        roots.push(config.cache_dir.clone());
+    }
+    for root in &roots {
+        let walker = WalkDir::new(root)
+            .min_depth(1)
+            .max_depth(MAX_SCAN_DEPTH)
+            .into_iter()
+            .filter_entry(|e| {
+                e.depth() > 1
+                    || !e.file_type().is_dir()
+                    || !matches!(
+                        e.file_name().to_str(),
+                        Some(RUNS_SUBDIR | DEFAULT_BUILD_TEMP_SUBDIR)
+                    ) && e.path() != config.build_temp_dir
+            });
+        for entry in walker.flatten() {
+            if entry.file_type().is_file() && is_download_temp(entry.file_name()) {
+                remove_if_older(entry.path(), cutoff, &mut reaped);
+            }
+        }
+    }
+    if let Ok(entries) = fs::read_dir(&config.build_temp_dir) {
+        for entry in entries.flatten() {
+            remove_if_older(&entry.path(), cutoff, &mut reaped);
+        }
+    }
+    reaped
+}
+
+fn runs_dir(config: &Config) -> PathBuf {
+    config.cache_dir.join(RUNS_SUBDIR)
+}
+
+/// When the oldest other live run started, if there is one. Lock files no process holds are
+/// removed on the way.
+fn live_runs_since(config: &Config, reaped: &mut Reaped) -> Option<SystemTime> {
+    let own = format!("{}.lock", std::process::id());
+    let mut oldest: Option<SystemTime> = None;
+    for entry in fs::read_dir(runs_dir(config)).ok()?.flatten() {
+        let path = entry.path();
+        if entry.file_name().to_string_lossy() == own {
+            continue;
+        }
+        let Ok(file) = OpenOptions::new().write(true).open(&path) else {
+            continue;
+        };
+        match flock::try_lock_exclusive(&file) {
+            Ok(true) => {
+                debug!("Removing stale run lock {}", path.display());
+                if fs::remove_file(&path).is_ok() {
+                    reaped.stale_locks += 1;
+                }
+                let _ = flock::unlock(&file);
+            }
+            Ok(false) => {
+                let pid = fs::read_to_string(&path).unwrap_or_default();
+                debug!("sps process {} is running; sparing its files", pid.trim());
+                // An unreadable start time protects everything.
+                let started = entry
+                    .metadata()
+                    .and_then(|m| m.modified())
+                    .unwrap_or(SystemTime::UNIX_EPOCH);
+                oldest = Some(oldest.map_or(started, |o| o.min(started)));
+            }
+            Err(e) => {
+                debug!("Could not check run lock {}: {}", path.display(), e);
+                oldest = Some(SystemTime::UNIX_EPOCH);
+            }
+        }
+    }
+    oldest
+}
+
+/// `.<name>.download` (bottles, sources, resources) or `.<key>.<random>.part` (casks).
+fn is_download_temp(name: &std::ffi::OsStr) -> bool {
+    let name = name.to_string_lossy();
+    name.starts_with('.') && (name.ends_with(".download") || name.ends_with(".part"))
+}
+
+fn remove_if_older(path: &Path, cutoff: SystemTime, reaped: &mut Reaped) {
+    let Ok(metadata) = fs::symlink_metadata(path) else {
+        return;
+    };
+    if metadata
+        .modified()
+        .map_or(true, |modified| modified >= cutoff)
+    {
+        return;
+    }
+    let (result, bytes) = if metadata.is_dir() {
+        let bytes = WalkDir::new(path)
+            .into_iter()
+            .flatten()
+            .filter_map(|e| e.metadata().ok())
+            .filter(|m| m.is_file())
+            .map(|m| m.len())
+            .sum();
+        (fs::remove_dir_all(path), bytes)
+    } else {
+        (fs::remove_file(path), metadata.len())
+    };
+    match result {
+        Ok(()) => {
+            debug!("Removed interrupted artifact {}", path.display());
+            reaped.artifacts += 1;
+            reaped.bytes += bytes;
+        }
+        Err(e) => debug!("Could not remove {}: {}", path.display(), e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(dir: &Path) -> Config {
+        Config {
+            prefix: dir.to_path_buf(),
+            cellar: dir.join("Cellar"),
+            cache_dir: dir.join("cache"),
+            download_dir: dir.join("cache"),
+            build_temp_dir: dir.join("build"),
+            ..Config::load().unwrap()
+        }
+    }
+
+    fn aged(path: PathBuf, age: Duration) -> PathBuf {
+        File::open(&path)
+            .unwrap()
+            .set_modified(SystemTime::now() - age)
+            .unwrap();
+        path
+    }
+
+    fn aged_file(path: &Path, age: Duration) -> PathBuf {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, "partial").unwrap();
+        aged(path.to_path_buf(), age)
+    }
+
+    fn aged_dir(path: &Path, age: Duration) -> PathBuf {
+        fs::create_dir_all(path).unwrap();
+        aged(path.to_path_buf(), age)
+    }
+
+    #[test]
+    fn a_live_run_lock_spares_what_its_run_may_still_use() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = config(dir.path());
+        let runs = runs_dir(&config);
+        let hours = |h: f64| Duration::from_secs_f64(h * 3600.0);
+        // Another process, two hours into its run, and one that died.
+        let live = aged_file(&runs.join("4242.lock"), hours(2.0));
+        let holder = OpenOptions::new().write(true).open(&live).unwrap();
+        flock::lock_exclusive(&holder).unwrap();
+        let dead = aged_file(&runs.join("4343.lock"), hours(3.0));
+        let downloads = config.cache_dir.join("sources/example.org");
+        let before_run = [
+            aged_file(&downloads.join(".old.tar.gz.download"), hours(3.0)),
+            aged_dir(&config.build_temp_dir.join("old"), hours(3.0)),
+        ];
+        let during_run = [
+            aged_file(&downloads.join(".recent.tar.gz.download"), hours(1.5)),
+            aged_dir(&config.build_temp_dir.join("recent"), hours(1.5)),
+        ];
+
+        let reaped = reap(&config);
+
+        assert_eq!((reaped.artifacts, reaped.stale_locks), (2, 1));
+        assert!(live.exists() && !dead.exists());
+        assert!(before_run.iter().all(|path| !path.exists()));
+        assert!(during_run.iter().all(|path| path.exists()));
+
+        // Once the run is over, its lock and everything stale go as well.
+        drop(holder);
+        let reaped = reap(&config);
+
+        assert_eq!((reaped.artifacts, reaped.stale_locks), (2, 1));
+        assert!(!live.exists());
+        assert!(during_run.iter().all(|path| !path.exists()));
+    }
+}
@@ -0,0 +1,70 @@
+// sps-core/src/hooks.rs
+//! Runs the user's `[hooks]` commands (see [`sps_common::config::Hooks`]). Callers run them one
+//! package at a time, outside any parallel section, so their output stays readable.
+
+use std::fmt;
+use std::path::Path;
+use std::process::Command;
+
+use sps_common::config::Config;
+use sps_common::error::{Result, SpsError};
+use tracing::{debug, warn};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HookEvent {
+    PostInstall,
+    PreUninstall,
+    PostUpgrade,
+}
+
+impl fmt::Display for HookEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::PostInstall => "post_install",
+            Self::PreUninstall => "pre_uninstall",
+            Self::PostUpgrade => "post_upgrade",
+        })
+    }
+}
+
+/// Runs the hook configured for `event`, if any, for the formula `name` at `version` whose keg
+/// is `keg_path`. A failing hook is only logged unless `hooks.strict` is set.
+pub fn run_hook(
+    event: HookEvent,
+    name: &str,
+    version: &str,
+    keg_path: &Path,
+    config: &Config,
+) -> Result<()> {
+    let command = match event {
+        HookEvent::PostInstall => &config.hooks.post_install,
+        HookEvent::PreUninstall => &config.hooks.pre_uninstall,
+        HookEvent::PostUpgrade => &config.hooks.post_upgrade,
+    };
+    let Some(command) = command.as_deref().filter(|c| !c.trim().is_empty()) else {
+        return Ok(());
+    };
+
+    debug!("Running {} hook for {}: {}", event, name, command);
+    let status = Command::new("/bin/sh")
+        .arg("-c")
+        .arg(command)
+        .env("sps_HOOK", event.to_string())
+        .env("sps_FORMULA", name)
+        .env("sps_VERSION", version)
+        .env("sps_KEG_PATH", keg_path)
+        .env("sps_OPT_PATH", config.formula_opt_link_path(name))
+        .status();
+    let failure = match status {
+        Ok(status) if status.success() => return Ok(()),
+        Ok(status) => format!("exited with {status}"),
+        Err(e) => format!("could not be started: {e}"),
+    };
+    let message = format!("{event} hook for {name} {failure}");
+    if config.hooks.strict {
+        Err(SpsError::CommandExecError(message))
+    } else {
+        warn!("{}", message);
+        Ok(())
+    }
+}
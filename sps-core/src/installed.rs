@@ -1,7 +1,7 @@
 // sps-core/src/installed.rs
 use std::fs::{self, DirEntry}; // Keep DirEntry
 use std::io; // Keep io for Error type
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc; // Keep Arc
 
 use serde::{Deserialize, Serialize};
@@ -10,6 +10,8 @@ use sps_common::error::{Result, SpsError};
 use sps_common::keg::KegRegistry;
 use tracing::{debug, warn};
 
+use crate::build::cask::{read_cask_manifest, CASK_MANIFEST_FILE};
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum PackageType {
     Formula,
@@ -36,19 +38,32 @@ fn handle_dir_entry(res: io::Result<DirEntry>, dir_path_str: &str) -> Option<Dir
     }
 }
 
+/// The version an installed cask was installed as: from its receipt, else the name of its
+/// Caskroom version directory.
+fn installed_cask_version(version_path: &Path) -> String {
+    read_cask_manifest(version_path)
+        .map(|manifest| manifest.version)
+        .unwrap_or_else(|| {
+            version_path
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or_default()
+        })
+}
+
 pub async fn get_installed_packages(config: &Config) -> Result<Vec<InstalledPackageInfo>> {
     let mut installed = Vec::new();
     let keg_registry = KegRegistry::new(config.clone());
 
     // Get Formulae (Sync)
-    match keg_registry.list_installed_kegs() {
+    match keg_registry.list_current_kegs() {
         Ok(kegs) => {
             for keg in kegs {
                 let version_str = keg
                     .path
                     .file_name()
                     .map(|n| n.to_string_lossy().to_string())
-                    .unwrap_or_else(|| format!("{}_{}", keg.version, keg.revision));
+                    .unwrap_or_else(|| keg.version.to_string());
                 installed.push(InstalledPackageInfo {
                     name: keg.name,
                     version: version_str,
@@ -86,13 +101,11 @@ pub async fn get_installed_packages(config: &Config) -> Result<Vec<InstalledPack
                             ) {
                                 let version_path = version_entry.path();
                                 if version_path.is_dir()
-                                    && version_path.join("CASK_INSTALL_MANIFEST.json").is_file()
+                                    && version_path.join(CASK_MANIFEST_FILE).is_file()
                                 {
-                                    let version_str =
-                                        version_entry.file_name().to_string_lossy().to_string();
                                     installed.push(InstalledPackageInfo {
                                         name: cask_token.clone(),
-                                        version: version_str,
+                                        version: installed_cask_version(&version_path),
                                         pkg_type: PackageType::Cask,
                                         path: version_path,
                                     });
@@ -125,7 +138,7 @@ pub async fn get_installed_package(
             .path
             .file_name()
             .map(|n| n.to_string_lossy().to_string())
-            .unwrap_or_else(|| format!("{}_{}", keg.version, keg.revision));
+            .unwrap_or_else(|| keg.version.to_string());
         return Ok(Some(InstalledPackageInfo {
             name: keg.name,
             version: version_str,
@@ -146,13 +159,10 @@ pub async fn get_installed_package(
                 cask_token_path.to_str().unwrap_or("token_path"),
             ) {
                 let version_path = version_entry.path();
-                if version_path.is_dir()
-                    && version_path.join("CASK_INSTALL_MANIFEST.json").is_file()
-                {
-                    let version_str = version_entry.file_name().to_string_lossy().to_string();
+                if version_path.is_dir() && version_path.join(CASK_MANIFEST_FILE).is_file() {
                     return Ok(Some(InstalledPackageInfo {
                         name: name.to_string(),
-                        version: version_str,
+                        version: installed_cask_version(&version_path),
                         pkg_type: PackageType::Cask,
                         path: version_path,
                     }));
@@ -162,3 +172,65 @@ pub async fn get_installed_package(
     }
     Ok(None) // Not found
 }
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use sps_common::model::cask::Cask;
+
+    use super::*;
+    use crate::build::cask::{write_cask_manifest, InstalledArtifact};
+
+    fn cask(version: &str) -> Cask {
+        serde_json::from_value(json!({
+            "token": "viewer",
+            "version": version,
+            "artifacts": [{ "app": ["Viewer.app"] }],
+        }))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn a_cask_counts_as_installed_once_a_version_has_a_receipt() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            prefix: dir.path().to_path_buf(),
+            cellar: dir.path().join("Cellar"),
+            ..Config::load().unwrap()
+        };
+        let cask_dir = config.cask_dir("viewer");
+        let found = || async {
+            let listed = get_installed_packages(&config).await.unwrap();
+            let single = get_installed_package("viewer", &config).await.unwrap();
+            assert_eq!(
+                listed.iter().map(|p| &p.path).collect::<Vec<_>>(),
+                single.iter().map(|p| &p.path).collect::<Vec<_>>()
+            );
+            single.map(|p| (p.version, p.path))
+        };
+
+        assert_eq!(found().await, None);
+        fs::create_dir_all(cask_dir.join("1.5")).unwrap();
+        assert_eq!(found().await, None, "a stray folder is not an install");
+
+        // Another version, and then the current one with its app deleted, are still installs
+        // that `uninstall` and `install` act on.
+        write_cask_manifest(&cask("1.4"), &cask_dir.join("1.4"), Vec::new()).unwrap();
+        assert_eq!(
+            found().await,
+            Some(("1.4".to_string(), cask_dir.join("1.4")))
+        );
+        fs::remove_dir_all(cask_dir.join("1.4")).unwrap();
+        let app = dir.path().join("Applications/Viewer.app");
+        write_cask_manifest(
+            &cask("1.5"),
+            &cask_dir.join("1.5"),
+            vec![InstalledArtifact::App { path: app }],
+        )
+        .unwrap();
+        assert_eq!(
+            found().await,
+            Some(("1.5".to_string(), cask_dir.join("1.5")))
+        );
+    }
+}
@@ -1,8 +1,13 @@
 // sps-core/src/lib.rs
 
 // Declare the top-level modules within the library crate
+pub mod blocking;
 pub mod build;
+pub mod hooks;
 pub mod installed; // New
+pub mod metadata;
+pub mod resolve;
+pub mod scaffold;
 pub mod tap;
 pub mod uninstall; // New
 pub mod update_check; // New
@@ -13,5 +18,6 @@ pub mod update_check; // New
 // For simplicity, let's define it here for now:
 
 pub use installed::{InstalledPackageInfo, PackageType}; // New
+pub use resolve::{normalize_name, resolve_token, KindHint, NameIndexes, Resolved};
 pub use uninstall::UninstallOptions; // New
 pub use update_check::UpdateInfo; // New
@@ -0,0 +1,322 @@
+// sps-core/src/metadata.rs
+//! Where formula definitions come from (`metadata_strategy`). `full` keeps the whole
+//! `formula.json` index in the cache and revalidates it with its ETag once it is older than the
+//! cache TTL; `lazy` fetches `formula/<name>.json` for each formula the first time it is needed,
+//! a few at a time, and keeps it under `formula/` in the cache; `auto` uses the index when it is
+//! already cached or the request is large, and fetches lazily otherwise.
+//!
+//! Both strategies read each other's cache: a lazy lookup is served from a cached index before
+//! anything is fetched, and `Formulary` falls back to the per-formula files when the index
+//! doesn't list a name. Switching strategies therefore never refetches what is already cached.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use futures::stream::{self, StreamExt};
+use serde_json::Value;
+use sps_common::cache::Cache;
+use sps_common::config::{Config, MetadataStrategy};
+use sps_common::error::{Result, SpsError};
+use sps_common::model::formula::Formula;
+use sps_net::fetch::api::{self, Revalidation};
+use tracing::{debug, warn};
+
+const INDEX_FILE: &str = "formula.json";
+/// Formula definitions fetched at once under the `lazy` strategy.
+pub const LAZY_FETCH_CONCURRENCY: usize = 8;
+/// Under `auto` with a cold cache, requests for more packages than this download the index.
+const AUTO_LAZY_MAX_NAMES: usize = 20;
+
+/// Whether a formula index is cached, however old.
+pub fn has_index(cache: &Cache) -> bool {
+    cache.get_dir().join(INDEX_FILE).is_file()
+}
+
+/// Resolves `auto` for a request needing `wanted` formula definitions.
+pub fn strategy(config: &Config, cache: &Cache, wanted: usize) -> MetadataStrategy {
+    match config.metadata_strategy {
+        MetadataStrategy::Auto if has_index(cache) => MetadataStrategy::Full,
+        MetadataStrategy::Auto if wanted > AUTO_LAZY_MAX_NAMES => MetadataStrategy::Full,
+        MetadataStrategy::Auto => MetadataStrategy::Lazy,
+        strategy => strategy,
+    }
+}
+
+/// The raw formula index: the cached copy while it is within the TTL, otherwise revalidated
+/// (or fetched) first. A stale copy is still used when the server can't be reached.
+pub async fn formula_index(cache: &Cache) -> Result<String> {
+    if cache.is_cache_valid(INDEX_FILE).unwrap_or(false) {
+        debug!("Loaded {} from cache.", INDEX_FILE);
+        return cache.load_raw(INDEX_FILE);
+    }
+    match refresh_formula_index(cache).await {
+        Ok(raw) => Ok(raw),
+        Err(e) => match cache.load_raw(INDEX_FILE) {
+            Ok(raw) => {
+                warn!(
+                    "Could not revalidate {}: {}; using the cached copy",
+                    INDEX_FILE, e
+                );
+                Ok(raw)
+            }
+            Err(_) => Err(e),
+        },
+    }
+}
+
+/// Revalidates the cached formula index with its ETag, downloading it only if it changed.
+pub async fn refresh_formula_index(cache: &Cache) -> Result<String> {
+    let cached = cache.load_raw(INDEX_FILE).ok();
+    let etag = cached.as_ref().and_then(|_| cache.load_etag(INDEX_FILE));
+    match api::fetch_all_formulas_if_changed(etag.as_deref()).await? {
+        Revalidation::NotModified => {
+            let raw = cached.ok_or_else(|| {
+                SpsError::Cache(format!("{INDEX_FILE} reported unchanged but is not cached"))
+            })?;
+            if let Err(e) = cache.touch(INDEX_FILE) {
+                warn!("Failed to mark cached {} as fresh: {}", INDEX_FILE, e);
+            }
+            Ok(raw)
+        }
+        Revalidation::Modified { body, etag } => {
+            if let Err(e) = cache
+                .store_raw(INDEX_FILE, &body)
+                .and_then(|()| cache.store_etag(INDEX_FILE, etag.as_deref()))
+            {
+                warn!("Failed to cache {} after fetching: {}", INDEX_FILE, e);
+            }
+            Ok(body)
+        }
+    }
+}
+
+/// Definitions of `names` available without a network round trip: from the cached index if
+/// there is one, else from formulae fetched on their own that are still within the TTL.
+pub fn cached_formulae(cache: &Cache, names: &[String]) -> HashMap<String, Arc<Formula>> {
+    let mut found: HashMap<String, Arc<Formula>> = match cache.load_raw(INDEX_FILE) {
+        Ok(raw) => parse_index(&raw)
+            .into_iter()
+            .filter(|(name, _)| names.contains(name))
+            .collect(),
+        Err(_) => HashMap::new(),
+    };
+    for name in names {
+        if found.contains_key(name) {
+            continue;
+        }
+        if let Some((raw, true)) = cache.load_formula(name) {
+            match serde_json::from_str::<Formula>(&raw) {
+                Ok(formula) => {
+                    found.insert(name.clone(), Arc::new(formula));
+                }
+                Err(e) => debug!("Ignoring unparsable cached formula '{}': {}", name, e),
+            }
+        }
+    }
+    found
+}
+
+/// Fetches each of `names` from the API, at most [`LAZY_FETCH_CONCURRENCY`] at a time, and
+/// caches every definition that parses.
+pub async fn fetch_formulae(cache: &Cache, names: Vec<String>) -> Vec<(String, Result<Formula>)> {
+    debug!(
+        "Fetching {} formula definition(s) individually",
+        names.len()
+    );
+    stream::iter(names)
+        .map(|name| async move {
+            let result = fetch_formula(cache, &name).await;
+            (name, result)
+        })
+        .buffer_unordered(LAZY_FETCH_CONCURRENCY)
+        .collect()
+        .await
+}
+
+async fn fetch_formula(cache: &Cache, name: &str) -> Result<Formula> {
+    let raw = api::fetch_raw_formulae_json(&format!("formula/{name}.json")).await?;
+    let formula: Formula = serde_json::from_str(&raw)?;
+    if let Err(e) = cache.store_formula(name, &raw) {
+        warn!("Failed to cache formula '{}': {}", name, e);
+    }
+    Ok(formula)
+}
+
+/// Caches a definition that was fetched by name elsewhere, so later lookups find it.
+pub fn cache_formula(cache: &Cache, formula: &Formula) {
+    let stored = serde_json::to_string(formula)
+        .map_err(SpsError::from)
+        .and_then(|raw| cache.store_formula(formula.name(), &raw));
+    if let Err(e) = stored {
+        warn!("Failed to cache formula '{}': {}", formula.name(), e);
+    }
+}
+
+/// Definitions of `names` under the configured strategy. Names the source doesn't know are
+/// missing from the map.
+pub async fn formula_definitions(
+    cache: &Cache,
+    config: &Config,
+    names: &[String],
+) -> Result<HashMap<String, Arc<Formula>>> {
+    match strategy(config, cache, names.len()) {
+        MetadataStrategy::Lazy => {
+            let mut found = cached_formulae(cache, names);
+            let missing: Vec<String> = names
+                .iter()
+                .filter(|name| !found.contains_key(*name))
+                .cloned()
+                .collect();
+            for (name, result) in fetch_formulae(cache, missing).await {
+                match result {
+                    Ok(formula) => {
+                        found.insert(name, Arc::new(formula));
+                    }
+                    Err(e) => debug!("Could not fetch formula '{}': {}", name, e),
+                }
+            }
+            Ok(found)
+        }
+        _ => Ok(parse_index(&formula_index(cache).await?)),
+    }
+}
+
+/// Parses the raw index into definitions by name, skipping entries that don't parse.
+pub fn parse_index(raw: &str) -> HashMap<String, Arc<Formula>> {
+    match serde_json::from_str::<Vec<Value>>(raw) {
+        Ok(values) => values
+            .into_iter()
+            .filter_map(|v| serde_json::from_value::<Formula>(v).ok())
+            .map(|f| (f.name.clone(), Arc::new(f)))
+            .collect(),
+        Err(e) => {
+            warn!("Failed to parse cached {}: {}", INDEX_FILE, e);
+            HashMap::new()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use serde_json::json;
+
+    use super::*;
+
+    fn config(dir: &Path, metadata_strategy: MetadataStrategy) -> Config {
+        Config {
+            prefix: dir.to_path_buf(),
+            cellar: dir.join("Cellar"),
+            cache_dir: dir.join("cache"),
+            metadata_strategy,
+            ..Config::load().unwrap()
+        }
+    }
+
+    fn definition(name: &str) -> Value {
+        json!({ "name": name, "versions": { "stable": "1.0" } })
+    }
+
+    #[test]
+    fn auto_is_lazy_on_a_cold_cache_unless_the_request_is_large() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = Cache::new(&dir.path().join("cache")).unwrap();
+        let auto = config(dir.path(), MetadataStrategy::Auto);
+
+        assert_eq!(strategy(&auto, &cache, 1), MetadataStrategy::Lazy);
+        assert_eq!(
+            strategy(&auto, &cache, AUTO_LAZY_MAX_NAMES),
+            MetadataStrategy::Lazy
+        );
+        assert_eq!(
+            strategy(&auto, &cache, AUTO_LAZY_MAX_NAMES + 1),
+            MetadataStrategy::Full
+        );
+    }
+
+    #[test]
+    fn auto_uses_a_cached_index_and_explicit_strategies_are_kept() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = Cache::new(&dir.path().join("cache")).unwrap();
+        cache.store_raw(INDEX_FILE, "[]").unwrap();
+
+        assert_eq!(
+            strategy(&config(dir.path(), MetadataStrategy::Auto), &cache, 1),
+            MetadataStrategy::Full
+        );
+        assert_eq!(
+            strategy(&config(dir.path(), MetadataStrategy::Lazy), &cache, 100),
+            MetadataStrategy::Lazy
+        );
+        assert_eq!(
+            strategy(&config(dir.path(), MetadataStrategy::Full), &cache, 0),
+            MetadataStrategy::Full
+        );
+    }
+
+    #[test]
+    fn strategies_parse_case_insensitively() {
+        assert_eq!(
+            MetadataStrategy::parse(" Lazy "),
+            Some(MetadataStrategy::Lazy)
+        );
+        assert_eq!(
+            MetadataStrategy::parse("FULL"),
+            Some(MetadataStrategy::Full)
+        );
+        assert_eq!(
+            MetadataStrategy::parse("auto"),
+            Some(MetadataStrategy::Auto)
+        );
+        assert_eq!(MetadataStrategy::parse("eager"), None);
+    }
+
+    #[test]
+    fn cached_definitions_come_from_the_index_and_single_formula_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = Cache::new(&dir.path().join("cache")).unwrap();
+        let index = json!([definition("jq"), definition("wget")]);
+        cache.store_raw(INDEX_FILE, &index.to_string()).unwrap();
+        cache
+            .store_formula("tree", &definition("tree").to_string())
+            .unwrap();
+        let names: Vec<String> = ["jq", "tree", "absent"].map(String::from).to_vec();
+
+        let found = cached_formulae(&cache, &names);
+
+        let mut found: Vec<&str> = found.keys().map(String::as_str).collect();
+        found.sort();
+        assert_eq!(found, ["jq", "tree"]);
+    }
+
+    #[test]
+    fn expired_single_formula_files_are_not_served() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = Cache::new(&dir.path().join("cache")).unwrap();
+        cache
+            .store_formula("tree", &definition("tree").to_string())
+            .unwrap();
+        std::fs::File::options()
+            .append(true)
+            .open(dir.path().join("cache/formula/tree.json"))
+            .unwrap()
+            .set_modified(
+                std::time::SystemTime::now() - std::time::Duration::from_secs(2 * 24 * 60 * 60),
+            )
+            .unwrap();
+
+        assert!(cached_formulae(&cache, &["tree".to_string()]).is_empty());
+    }
+
+    #[test]
+    fn the_index_skips_entries_that_do_not_parse() {
+        let raw = json!([definition("jq"), { "name": 42 }]).to_string();
+
+        let parsed = parse_index(&raw);
+
+        assert_eq!(parsed.len(), 1);
+        assert!(parsed.contains_key("jq"));
+        assert!(parse_index("not json").is_empty());
+    }
+}
@@ -0,0 +1,726 @@
+// sps-core/src/resolve.rs
+//! Maps a user-supplied name to a formula or cask, shared by all commands that accept either.
+//!
+//! Exact names always beat aliases and old names, so a formula alias never shadows a cask whose
+//! token matches exactly (or vice versa). A name that matches both kinds equally well is reported
+//! as [`Resolved::Ambiguous`]; [`Resolved::into_target`] turns that into the conventional
+//! "treat as formula" choice with a warning.
+//!
+//! Tapped formulae are indexed under their qualified `user/repo/name`. Their short name is only an
+//! alias, so it resolves to them when no core formula (or other exact name) claims it.
+//!
+//! Names are cleaned up before lookup, since they are often pasted: surrounding whitespace and
+//! trailing slashes go, as does a `homebrew/core/` or `homebrew/cask/` prefix, and a
+//! formulae.brew.sh page or API URL stands for the token it names. A name the index doesn't have
+//! as typed is matched ignoring case; if that finds more than one package, the name is reported
+//! as [`Resolved::CaseConflict`] rather than picking one.
+
+use std::collections::{HashMap, HashSet};
+
+use serde_json::Value;
+use sps_common::cache::Cache;
+use sps_common::error::{Result, SpsError};
+use sps_common::model::formula::{canonical_formula_name, qualified_formula_name, split_tap_name};
+use tracing::{debug, warn};
+
+use crate::installed::PackageType;
+
+/// `--formula` / `--cask` restriction passed on the command line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum KindHint {
+    #[default]
+    Any,
+    Formula,
+    Cask,
+}
+
+impl KindHint {
+    /// Builds the hint from a command's `--formula` / `--cask` flags.
+    pub fn from_flags(formula: bool, cask: bool) -> Result<Self> {
+        match (formula, cask) {
+            (true, true) => Err(SpsError::Generic(
+                "Cannot use --formula and --cask together.".to_string(),
+            )),
+            (true, false) => Ok(KindHint::Formula),
+            (false, true) => Ok(KindHint::Cask),
+            (false, false) => Ok(KindHint::Any),
+        }
+    }
+}
+
+/// Outcome of [`resolve_token`]. Names are canonical (aliases and old names already applied).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Resolved {
+    Formula(String),
+    Cask(String),
+    Ambiguous {
+        formula: String,
+        cask: String,
+    },
+    /// Several packages match when case is ignored, and none exactly.
+    CaseConflict(Vec<String>),
+    NotFound,
+}
+
+impl Resolved {
+    /// Handles the outcomes the same way for every command: ambiguity falls back to the formula
+    /// with a warning, a case conflict is an error, an unknown name becomes `SpsError::NotFound`.
+    pub fn into_target(self, name: &str) -> Result<(PackageType, String)> {
+        match self {
+            Resolved::Formula(n) => Ok((PackageType::Formula, n)),
+            Resolved::Cask(n) => Ok((PackageType::Cask, n)),
+            Resolved::Ambiguous { formula, cask } => {
+                warn!(
+                    "'{}' names both formula '{}' and cask '{}'; treating it as the formula. \
+                     Pass --cask for the cask.",
+                    name, formula, cask
+                );
+                Ok((PackageType::Formula, formula))
+            }
+            Resolved::CaseConflict(candidates) => Err(SpsError::ValidationError(format!(
+                "'{}' matches {} when case is ignored; give the exact name",
+                name.trim(),
+                candidates.join(", ")
+            ))),
+            Resolved::NotFound => Err(SpsError::NotFound(format!(
+                "No formula or cask named '{name}'"
+            ))),
+        }
+    }
+}
+
+/// How well a name matched a given index entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum MatchRank {
+    Alias,
+    Exact,
+}
+
+/// Lookup tables from every known name (canonical, alias, old name) to the canonical name.
+#[derive(Debug, Default, Clone)]
+pub struct NameIndexes {
+    formulae: HashMap<String, (String, MatchRank)>,
+    casks: HashMap<String, (String, MatchRank)>,
+}
+
+impl NameIndexes {
+    /// Builds the indexes from the cached `formula.json` / `cask.json`. Missing or unreadable
+    /// files simply leave that side empty.
+    pub fn load(cache: &Cache) -> Self {
+        let mut indexes = Self::default();
+        for (file, is_cask) in [("formula.json", false), ("cask.json", true)] {
+            let values: Vec<Value> = match cache
+                .load_raw(file)
+                .ok()
+                .and_then(|data| serde_json::from_str(&data).ok())
+            {
+                Some(v) => v,
+                None => {
+                    debug!("No usable cached {} for name resolution", file);
+                    continue;
+                }
+            };
+            for value in &values {
+                if is_cask {
+                    indexes.add_cask_value(value);
+                } else {
+                    indexes.add_formula_value(value);
+                }
+            }
+        }
+        indexes
+    }
+
+    /// Whether any names are known at all; callers without cached metadata skip resolution.
+    pub fn is_empty(&self) -> bool {
+        self.formulae.is_empty() && self.casks.is_empty()
+    }
+
+    /// Narrows the indexes to the given installed names, keeping their aliases. Installed names
+    /// missing from the metadata (e.g. from a tap) are added as exact entries.
+    pub fn restrict_to(mut self, formulae: &HashSet<String>, casks: &HashSet<String>) -> Self {
+        self.formulae
+            .retain(|_, (canonical, _)| formulae.contains(canonical));
+        self.casks
+            .retain(|_, (canonical, _)| casks.contains(canonical));
+        for name in formulae {
+            let short = split_tap_name(name).1;
+            self.add_formula(name, (short != name).then_some(short));
+        }
+        for token in casks {
+            self.add_cask(token, []);
+        }
+        self
+    }
+
+    pub fn add_formula<'a>(&mut self, name: &str, aliases: impl IntoIterator<Item = &'a str>) {
+        Self::add(&mut self.formulae, name, aliases);
+    }
+
+    pub fn add_cask<'a>(&mut self, token: &str, old_tokens: impl IntoIterator<Item = &'a str>) {
+        Self::add(&mut self.casks, token, old_tokens);
+    }
+
+    fn add_formula_value(&mut self, value: &Value) {
+        let Some(name) = qualified_formula_name(value) else {
+            return;
+        };
+        let short = split_tap_name(&name).1;
+        let aliases = string_array(value, "aliases")
+            .chain(string_array(value, "oldnames"))
+            .chain(value.get("oldname").and_then(Value::as_str))
+            .chain((short != name).then_some(short));
+        self.add_formula(&name, aliases);
+    }
+
+    fn add_cask_value(&mut self, value: &Value) {
+        if let Some(token) = value.get("token").and_then(Value::as_str) {
+            let old = string_array(value, "old_tokens").chain(string_array(value, "aliases"));
+            self.add_cask(token, old);
+        }
+    }
+
+    fn add<'a>(
+        map: &mut HashMap<String, (String, MatchRank)>,
+        canonical: &str,
+        aliases: impl IntoIterator<Item = &'a str>,
+    ) {
+        map.insert(
+            canonical.to_string(),
+            (canonical.to_string(), MatchRank::Exact),
+        );
+        for alias in aliases {
+            // Never let an alias overwrite another entry's exact name.
+            map.entry(alias.to_string())
+                .or_insert_with(|| (canonical.to_string(), MatchRank::Alias));
+        }
+    }
+}
+
+fn string_array<'a>(value: &'a Value, key: &str) -> impl Iterator<Item = &'a str> {
+    value
+        .get(key)
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(Value::as_str)
+}
+
+/// Cleans up a name as typed or pasted (see the module docs) and checks that something usable is
+/// left. The returned hint is `hint` narrowed by what the input says about its kind, such as a
+/// cask URL; one that contradicts `--formula` or `--cask` is an error.
+pub fn normalize_name(input: &str, hint: KindHint) -> Result<(String, KindHint)> {
+    let (name, implied) = clean_name(input);
+    if name.is_empty() {
+        return Err(SpsError::ValidationError(format!(
+            "'{}' is not a package name",
+            input.escape_debug()
+        )));
+    }
+    if name.contains("://") {
+        return Err(SpsError::ValidationError(format!(
+            "'{name}' is not a formula or cask URL; only {BREW_SH_HOST} formula and cask pages \
+             are accepted"
+        )));
+    }
+    if name.chars().any(|c| c.is_whitespace() || c.is_control()) {
+        return Err(SpsError::ValidationError(format!(
+            "'{}' is not a package name: it contains whitespace",
+            name.escape_debug()
+        )));
+    }
+    let hint = match (hint, implied) {
+        (KindHint::Any, implied) => implied,
+        (hint, KindHint::Any) => hint,
+        (hint, implied) if hint == implied => hint,
+        (_, implied) => {
+            let (named, flag) = match implied {
+                KindHint::Cask => ("cask", "--formula"),
+                _ => ("formula", "--cask"),
+            };
+            return Err(SpsError::ValidationError(format!(
+                "'{}' names a {named}, but {flag} was given",
+                input.trim()
+            )));
+        }
+    };
+    Ok((name, hint))
+}
+
+/// Host whose formula and cask pages (and API URLs) stand for the token they name.
+const BREW_SH_HOST: &str = "formulae.brew.sh";
+
+/// Prefixes pasted from Homebrew's qualified names, and the kind each one implies.
+const HOMEBREW_TAP_PREFIXES: [(&str, KindHint); 2] = [
+    ("homebrew/core/", KindHint::Formula),
+    ("homebrew/cask/", KindHint::Cask),
+];
+
+/// The infallible part of [`normalize_name`]: the cleaned-up name and the kind it implies.
+fn clean_name(input: &str) -> (String, KindHint) {
+    let name = input.trim();
+    if let Some((token, kind)) = brew_sh_token(name) {
+        return (token.to_string(), kind);
+    }
+    let name = name.trim_end_matches('/');
+    for (prefix, kind) in HOMEBREW_TAP_PREFIXES {
+        let matches = name
+            .get(..prefix.len())
+            .is_some_and(|head| head.eq_ignore_ascii_case(prefix));
+        if matches {
+            return (name[prefix.len()..].to_string(), kind);
+        }
+    }
+    (name.to_string(), KindHint::Any)
+}
+
+/// The token and kind of a formulae.brew.sh URL: `https://formulae.brew.sh/formula/wget`,
+/// `https://formulae.brew.sh/cask/firefox#default` or
+/// `https://formulae.brew.sh/api/formula/wget.json`.
+fn brew_sh_token(input: &str) -> Option<(&str, KindHint)> {
+    let (scheme, rest) = input.split_once("://")?;
+    if !scheme.eq_ignore_ascii_case("https") && !scheme.eq_ignore_ascii_case("http") {
+        return None;
+    }
+    let (host, path) = rest.split_once('/')?;
+    if !host.eq_ignore_ascii_case(BREW_SH_HOST) {
+        return None;
+    }
+    let path = path.split(['?', '#']).next().unwrap_or_default();
+    let mut segments = path.split('/').filter(|s| !s.is_empty());
+    let mut kind = segments.next()?;
+    if kind == "api" {
+        kind = segments.next()?;
+    }
+    let kind = match kind {
+        "formula" => KindHint::Formula,
+        "cask" => KindHint::Cask,
+        _ => return None,
+    };
+    let token = segments.next()?;
+    if segments.next().is_some() {
+        return None;
+    }
+    Some((token.strip_suffix(".json").unwrap_or(token), kind))
+}
+
+/// Looks `name` up ignoring case, for when no index has it as typed. `Err` lists the packages
+/// the match can't choose between.
+fn lookup_ignoring_case<'a>(
+    map: &'a HashMap<String, (String, MatchRank)>,
+    name: &str,
+) -> std::result::Result<Option<&'a (String, MatchRank)>, Vec<String>> {
+    let mut matches: Vec<&(String, MatchRank)> = map
+        .iter()
+        .filter(|(key, _)| key.eq_ignore_ascii_case(name))
+        .map(|(_, entry)| entry)
+        .collect();
+    matches.sort();
+    let mut candidates: Vec<String> = matches
+        .iter()
+        .map(|(canonical, _)| canonical.clone())
+        .collect();
+    candidates.dedup();
+    match candidates.len() {
+        0 => Ok(None),
+        1 => {
+            debug!("Matched '{}' to '{}' ignoring case", name, candidates[0]);
+            Ok(matches.pop())
+        }
+        _ => Err(candidates),
+    }
+}
+
+/// Resolves `name` against `indexes`, honoring a `--formula` / `--cask` hint. The name is
+/// cleaned up first (see [`normalize_name`]); `homebrew/core/<name>` is looked up as `<name>`.
+pub fn resolve_token(name: &str, hint: KindHint, indexes: &NameIndexes) -> Resolved {
+    let (name, implied) = clean_name(name);
+    let hint = match hint {
+        KindHint::Any => implied,
+        hint => hint,
+    };
+    let name = canonical_formula_name(&name);
+    let formulae = (hint != KindHint::Cask).then_some(&indexes.formulae);
+    let casks = (hint != KindHint::Formula).then_some(&indexes.casks);
+    let mut formula = formulae.and_then(|map| map.get(name));
+    let mut cask = casks.and_then(|map| map.get(name));
+    // Case is only ignored when neither kind has the name as typed, so `abc` never competes
+    // with a cask `ABC` once a formula `abc` exists.
+    if formula.is_none() && cask.is_none() {
+        let by_case = |map: Option<_>| map.map_or(Ok(None), |m| lookup_ignoring_case(m, name));
+        (formula, cask) = match (by_case(formulae), by_case(casks)) {
+            // Different packages folding to the same name are a conflict, not an ambiguity.
+            (Ok(Some((f, _))), Ok(Some((c, _)))) => {
+                return Resolved::CaseConflict(vec![f.clone(), c.clone()])
+            }
+            (Ok(formula), Ok(cask)) => (formula, cask),
+            (Err(mut candidates), other) | (other @ Ok(_), Err(mut candidates)) => {
+                if let Ok(Some((c, _))) = other {
+                    candidates.push(c.clone());
+                }
+                return Resolved::CaseConflict(candidates);
+            }
+        };
+    }
+    match (formula, cask) {
+        (Some((f, f_rank)), Some((c, c_rank))) => match f_rank.cmp(c_rank) {
+            std::cmp::Ordering::Greater => Resolved::Formula(f.clone()),
+            std::cmp::Ordering::Less => Resolved::Cask(c.clone()),
+            std::cmp::Ordering::Equal => Resolved::Ambiguous {
+                formula: f.clone(),
+                cask: c.clone(),
+            },
+        },
+        (Some((f, _)), None) => Resolved::Formula(f.clone()),
+        (None, Some((c, _))) => Resolved::Cask(c.clone()),
+        (None, None) => Resolved::NotFound,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    /// Formulae and casks as the API lists them, with the aliases, old names and taps that
+    /// matter for resolution.
+    fn indexes() -> NameIndexes {
+        let mut indexes = NameIndexes::default();
+        for formula in [
+            json!({ "name": "wget" }),
+            json!({ "name": "python@3.13", "aliases": ["python3", "python"] }),
+            json!({ "name": "yt-dlp", "oldnames": ["youtube-dl"] }),
+            json!({ "name": "docker" }),
+            json!({ "name": "gimp-cli", "aliases": ["gimp"] }),
+            json!({ "name": "jq" }),
+            json!({ "name": "jq", "tap": "mycorp/tools" }),
+            json!({ "name": "deploy", "tap": "mycorp/tools" }),
+            json!({ "name": "abc" }),
+            json!({ "name": "Pillow" }),
+            json!({ "name": "pillow-simd", "aliases": ["PILLOW"] }),
+        ] {
+            indexes.add_formula_value(&formula);
+        }
+        for cask in [
+            json!({ "token": "firefox" }),
+            json!({ "token": "docker" }),
+            json!({ "token": "gimp" }),
+            json!({ "token": "google-chrome@beta", "old_tokens": ["google-chrome-beta"] }),
+            json!({ "token": "ABC" }),
+        ] {
+            indexes.add_cask_value(&cask);
+        }
+        indexes
+    }
+
+    fn formula(name: &str) -> Resolved {
+        Resolved::Formula(name.to_string())
+    }
+
+    fn cask(token: &str) -> Resolved {
+        Resolved::Cask(token.to_string())
+    }
+
+    #[test]
+    fn resolves_names_aliases_and_old_names() {
+        let indexes = indexes();
+        let cases = [
+            ("wget", KindHint::Any, formula("wget")),
+            ("python3", KindHint::Any, formula("python@3.13")),
+            ("youtube-dl", KindHint::Any, formula("yt-dlp")),
+            ("firefox", KindHint::Any, cask("firefox")),
+            (
+                "google-chrome-beta",
+                KindHint::Any,
+                cask("google-chrome@beta"),
+            ),
+            // An exact cask token beats a formula alias.
+            ("gimp", KindHint::Any, cask("gimp")),
+            ("gimp", KindHint::Formula, formula("gimp-cli")),
+            // Core wins the short name; the tapped formula keeps its qualified one.
+            ("jq", KindHint::Any, formula("jq")),
+            ("mycorp/tools/jq", KindHint::Any, formula("mycorp/tools/jq")),
+            ("deploy", KindHint::Any, formula("mycorp/tools/deploy")),
+            ("homebrew/core/wget", KindHint::Any, formula("wget")),
+            ("nope", KindHint::Any, Resolved::NotFound),
+            ("firefox", KindHint::Formula, Resolved::NotFound),
+            ("wget", KindHint::Cask, Resolved::NotFound),
+        ];
+        for (name, hint, expected) in cases {
+            assert_eq!(
+                resolve_token(name, hint, &indexes),
+                expected,
+                "{name} {hint:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn a_name_of_both_kinds_is_ambiguous_unless_a_hint_decides() {
+        let indexes = indexes();
+        let cases = [
+            (
+                "docker",
+                KindHint::Any,
+                Resolved::Ambiguous {
+                    formula: "docker".to_string(),
+                    cask: "docker".to_string(),
+                },
+            ),
+            ("docker", KindHint::Formula, formula("docker")),
+            ("docker", KindHint::Cask, cask("docker")),
+            ("homebrew/cask/docker", KindHint::Any, cask("docker")),
+        ];
+        for (name, hint, expected) in cases {
+            assert_eq!(
+                resolve_token(name, hint, &indexes),
+                expected,
+                "{name} {hint:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn matches_ignoring_case_only_when_one_package_fits() {
+        let indexes = indexes();
+        assert_eq!(
+            resolve_token("WGet", KindHint::Any, &indexes),
+            formula("wget")
+        );
+        assert_eq!(
+            resolve_token("Python3", KindHint::Any, &indexes),
+            formula("python@3.13")
+        );
+        assert_eq!(
+            resolve_token("abc", KindHint::Any, &indexes),
+            formula("abc")
+        );
+        assert_eq!(resolve_token("ABC", KindHint::Any, &indexes), cask("ABC"));
+        match resolve_token("Abc", KindHint::Any, &indexes) {
+            Resolved::CaseConflict(mut candidates) => {
+                candidates.sort();
+                assert_eq!(candidates, ["ABC", "abc"]);
+            }
+            other => panic!("expected a case conflict, got {other:?}"),
+        }
+        assert_eq!(resolve_token("Abc", KindHint::Cask, &indexes), cask("ABC"));
+        // Two entries differing only in case: as typed picks one, anything else is a conflict.
+        assert_eq!(
+            resolve_token("Pillow", KindHint::Any, &indexes),
+            formula("Pillow")
+        );
+        assert_eq!(
+            resolve_token("PILLOW", KindHint::Any, &indexes),
+            formula("pillow-simd")
+        );
+        assert!(matches!(
+            resolve_token("pillow", KindHint::Any, &indexes),
+            Resolved::CaseConflict(_)
+        ));
+    }
+
+    #[test]
+    fn aliases_never_shadow_exact_names() {
+        let mut indexes = NameIndexes::default();
+        indexes.add_formula("node", ["nodejs"]);
+        indexes.add_formula("nodejs", []);
+        indexes.add_formula("node@20", ["node"]);
+
+        assert_eq!(
+            resolve_token("node", KindHint::Any, &indexes),
+            formula("node")
+        );
+        assert_eq!(
+            resolve_token("nodejs", KindHint::Any, &indexes),
+            formula("nodejs")
+        );
+    }
+
+    #[test]
+    fn restrict_to_keeps_installed_packages_and_their_aliases() {
+        let installed_formulae =
+            HashSet::from(["python@3.13".to_string(), "mycorp/other/tool".to_string()]);
+        let installed_casks = HashSet::from(["firefox".to_string()]);
+        let indexes = indexes().restrict_to(&installed_formulae, &installed_casks);
+
+        let cases = [
+            ("python3", formula("python@3.13")),
+            ("tool", formula("mycorp/other/tool")),
+            ("firefox", cask("firefox")),
+            ("wget", Resolved::NotFound),
+            ("docker", Resolved::NotFound),
+        ];
+        for (name, expected) in cases {
+            assert_eq!(
+                resolve_token(name, KindHint::Any, &indexes),
+                expected,
+                "{name}"
+            );
+        }
+        assert!(!indexes.is_empty());
+        assert!(NameIndexes::default().is_empty());
+    }
+
+    #[test]
+    fn into_target_settles_every_outcome() {
+        let ambiguous = Resolved::Ambiguous {
+            formula: "docker".to_string(),
+            cask: "docker".to_string(),
+        };
+        assert!(matches!(
+            ambiguous.into_target("docker"),
+            Ok((PackageType::Formula, n)) if n == "docker"
+        ));
+        assert!(matches!(
+            cask("firefox").into_target("firefox"),
+            Ok((PackageType::Cask, n)) if n == "firefox"
+        ));
+        assert!(matches!(
+            Resolved::NotFound.into_target("nope"),
+            Err(SpsError::NotFound(_))
+        ));
+        assert!(matches!(
+            Resolved::CaseConflict(vec!["ABC".into(), "abc".into()]).into_target("Abc"),
+            Err(SpsError::ValidationError(msg)) if msg.contains("ABC, abc")
+        ));
+    }
+
+    #[test]
+    fn kind_hint_from_flags() {
+        let cases = [
+            (false, false, Some(KindHint::Any)),
+            (true, false, Some(KindHint::Formula)),
+            (false, true, Some(KindHint::Cask)),
+            (true, true, None),
+        ];
+        for (formula, cask, expected) in cases {
+            assert_eq!(KindHint::from_flags(formula, cask).ok(), expected);
+        }
+    }
+
+    #[test]
+    fn normalize_name_cleans_up_pasted_names() {
+        let cases = [
+            ("wget", KindHint::Any, "wget", KindHint::Any),
+            ("  wget\n", KindHint::Any, "wget", KindHint::Any),
+            ("wget/", KindHint::Any, "wget", KindHint::Any),
+            (
+                "homebrew/core/wget",
+                KindHint::Any,
+                "wget",
+                KindHint::Formula,
+            ),
+            (
+                "Homebrew/Core/wget",
+                KindHint::Any,
+                "wget",
+                KindHint::Formula,
+            ),
+            (
+                "homebrew/cask/firefox",
+                KindHint::Any,
+                "firefox",
+                KindHint::Cask,
+            ),
+            (
+                "homebrew/cask/firefox",
+                KindHint::Cask,
+                "firefox",
+                KindHint::Cask,
+            ),
+            (
+                "mycorp/tools/jq",
+                KindHint::Any,
+                "mycorp/tools/jq",
+                KindHint::Any,
+            ),
+            (
+                "https://formulae.brew.sh/formula/wget",
+                KindHint::Any,
+                "wget",
+                KindHint::Formula,
+            ),
+            (
+                "https://formulae.brew.sh/cask/firefox#default",
+                KindHint::Any,
+                "firefox",
+                KindHint::Cask,
+            ),
+            (
+                "https://formulae.brew.sh/api/formula/python@3.13.json",
+                KindHint::Any,
+                "python@3.13",
+                KindHint::Formula,
+            ),
+            (
+                "HTTPS://FORMULAE.BREW.SH/cask/docker/",
+                KindHint::Any,
+                "docker",
+                KindHint::Cask,
+            ),
+            ("wget", KindHint::Formula, "wget", KindHint::Formula),
+        ];
+        for (input, hint, name, kind) in cases {
+            let normalized = normalize_name(input, hint).unwrap();
+            assert_eq!(normalized, (name.to_string(), kind), "{input:?} {hint:?}");
+        }
+    }
+
+    #[test]
+    fn normalize_name_rejects_what_is_not_a_name() {
+        let cases = [
+            ("", KindHint::Any, "not a package name"),
+            ("   ", KindHint::Any, "not a package name"),
+            ("/", KindHint::Any, "not a package name"),
+            ("two words", KindHint::Any, "whitespace"),
+            ("tab\there", KindHint::Any, "whitespace"),
+            (
+                "https://example.com/wget",
+                KindHint::Any,
+                "formulae.brew.sh",
+            ),
+            (
+                "https://formulae.brew.sh/analytics/",
+                KindHint::Any,
+                "formulae.brew.sh",
+            ),
+            ("homebrew/cask/firefox", KindHint::Formula, "--formula"),
+            (
+                "https://formulae.brew.sh/formula/wget",
+                KindHint::Cask,
+                "--cask",
+            ),
+        ];
+        for (input, hint, message) in cases {
+            match normalize_name(input, hint) {
+                Err(SpsError::ValidationError(msg)) => {
+                    assert!(msg.contains(message), "{input:?}: {msg}")
+                }
+                other => panic!("{input:?} {hint:?}: expected a validation error, got {other:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn resolve_token_applies_the_same_clean_up() {
+        let indexes = indexes();
+        let cases = [
+            (" wget/ ", formula("wget")),
+            (
+                "https://formulae.brew.sh/formula/python@3.13",
+                formula("python@3.13"),
+            ),
+            // The URL names the formula, so the cask of the same name is not considered.
+            ("https://formulae.brew.sh/formula/docker", formula("docker")),
+            ("https://formulae.brew.sh/cask/docker", cask("docker")),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(
+                resolve_token(input, KindHint::Any, &indexes),
+                expected,
+                "{input:?}"
+            );
+        }
+    }
+}
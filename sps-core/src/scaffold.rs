@@ -0,0 +1,248 @@
+// sps-core/src/scaffold.rs
+//! Skeleton definitions for private taps (`sps create`). The archive is downloaded once to get
+//! its checksum; name and version are guessed from the URL (`foo-1.2.3.tar.gz`, GitHub
+//! `owner/foo/archive/v1.2.3.tar.gz`), and for casks the container is opened to find the app
+//! bundle or installer package to list as the artifact. Everything else is left for the author
+//! to fill in.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+
+use regex::Regex;
+use serde_json::{json, Value};
+use sps_common::config::Config;
+use sps_common::error::{Result, SpsError};
+use sps_net::fetch::http;
+use sps_net::validation::sha256_file;
+use tempfile::TempDir;
+use tracing::{debug, warn};
+use url::Url;
+
+use crate::build::cask::{container_extension, extract_container};
+
+/// Placeholder for the fields `sps create` can't work out.
+pub const DESC_PLACEHOLDER: &str = "TODO: describe this package";
+
+const ARCHIVE_SUFFIXES: &[&str] = &[
+    ".tar.gz", ".tgz", ".tar.xz", ".txz", ".tar.bz2", ".tbz", ".tar.zst", ".tar", ".zip", ".dmg",
+    ".pkg",
+];
+
+/// `name-1.2.3`, `name_v1.2.3`, `name-1.2.3-rc1`.
+static NAME_VERSION_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^(?P<name>.+?)[-_.]v?(?P<version>\d+(?:\.\d+)*(?:[-_.+]?[A-Za-z0-9]+)*)$").unwrap()
+});
+/// A bare version, as in GitHub's `archive/v1.2.3.tar.gz`.
+static VERSION_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^v?(?P<version>\d+(?:\.\d+)*(?:[-_.+]?[A-Za-z0-9]+)*)$").unwrap()
+});
+
+/// What the URL of a download says about the package.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UrlGuess {
+    pub name: Option<String>,
+    pub version: Option<String>,
+    pub homepage: Option<String>,
+}
+
+/// Guesses name, version and homepage from `url`. GitHub archive and release URLs take the name
+/// and homepage from the repository.
+pub fn guess_from_url(url: &str) -> UrlGuess {
+    let Ok(parsed) = Url::parse(url) else {
+        return UrlGuess::default();
+    };
+    let segments: Vec<&str> = parsed
+        .path_segments()
+        .map(|s| s.filter(|s| !s.is_empty()).collect())
+        .unwrap_or_default();
+    let stem = segments.last().map(|file| strip_archive_suffix(file));
+
+    let mut guess = UrlGuess {
+        homepage: parsed
+            .has_host()
+            .then(|| parsed.origin().ascii_serialization()),
+        ..UrlGuess::default()
+    };
+    if let Some(stem) = stem {
+        if let Some(caps) = NAME_VERSION_RE.captures(stem) {
+            guess.name = Some(caps["name"].to_string());
+            guess.version = Some(caps["version"].to_string());
+        } else if let Some(caps) = VERSION_RE.captures(stem) {
+            guess.version = Some(caps["version"].to_string());
+        } else {
+            guess.name = Some(stem.to_string());
+        }
+    }
+    if parsed.host_str() == Some("github.com") && segments.len() >= 2 {
+        let (owner, repo) = (segments[0], segments[1]);
+        guess.name = Some(repo.to_string());
+        guess.homepage = Some(format!("https://github.com/{owner}/{repo}"));
+        // `releases/download/v1.2.3/foo-linux.tar.gz`: the tag is the version.
+        if let ["releases", "download", tag, ..] = &segments[2..] {
+            if let Some(caps) = VERSION_RE.captures(tag) {
+                guess.version = Some(caps["version"].to_string());
+            }
+        }
+    }
+    guess.name = guess.name.map(|name| name.to_lowercase());
+    guess
+}
+
+fn strip_archive_suffix(file: &str) -> &str {
+    let lower = file.to_lowercase();
+    ARCHIVE_SUFFIXES
+        .iter()
+        .find(|suffix| lower.ends_with(*suffix))
+        .map_or(file, |suffix| &file[..file.len() - suffix.len()])
+}
+
+/// A download kept in a temporary directory for as long as this lives.
+pub struct Download {
+    pub path: PathBuf,
+    pub sha256: String,
+    _dir: TempDir,
+}
+
+/// Downloads `url` to a temporary directory and checksums it.
+pub async fn download(url: &str, config: &Config) -> Result<Download> {
+    let file_name = Url::parse(url)
+        .ok()
+        .and_then(|u| {
+            u.path_segments()
+                .and_then(|mut s| s.next_back().map(str::to_string))
+        })
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "download".to_string());
+    let dir = tempfile::tempdir()?;
+    let path = http::fetch_unverified_to_path(url, &dir.path().join(file_name), config).await?;
+    let sha256 = sha256_file(&path)?;
+    debug!("Downloaded {} ({})", path.display(), sha256);
+    Ok(Download {
+        path,
+        sha256,
+        _dir: dir,
+    })
+}
+
+/// The new formula's definition in API format.
+pub fn formula_skeleton(
+    tap: &str,
+    name: &str,
+    version: &str,
+    homepage: &str,
+    url: &str,
+    sha256: &str,
+) -> Value {
+    json!({
+        "name": name,
+        "tap": tap,
+        "desc": DESC_PLACEHOLDER,
+        "homepage": homepage,
+        "versions": { "stable": version },
+        "urls": { "stable": { "url": url, "checksum": sha256 } },
+        "dependencies": [],
+        "build_dependencies": [],
+    })
+}
+
+/// The new cask's definition in API format.
+pub fn cask_skeleton(
+    token: &str,
+    display_name: &str,
+    version: &str,
+    homepage: &str,
+    url: &str,
+    sha256: &str,
+    artifacts: Vec<Value>,
+) -> Value {
+    json!({
+        "token": token,
+        "name": [display_name],
+        "desc": DESC_PLACEHOLDER,
+        "homepage": homepage,
+        "version": version,
+        "url": url,
+        "sha256": sha256,
+        "artifacts": artifacts,
+    })
+}
+
+/// The artifacts of a cask download: the installer package itself, or the app bundles and
+/// packages found near the top of the opened container. Empty when nothing recognisable is found
+/// or the container can't be opened here (a DMG off macOS); the author lists them by hand then.
+pub fn sniff_cask_artifacts(download: &Path, config: &Config) -> Result<Vec<Value>> {
+    let extension = container_extension(download)?;
+    if extension == "pkg" || extension == "mpkg" {
+        let file_name = download
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default();
+        return Ok(vec![json!({ "pkg": [file_name] })]);
+    }
+    let stage = tempfile::tempdir()?;
+    if let Err(e) = extract_container(download, &extension, stage.path(), config) {
+        warn!(
+            "Could not open the {} container to look for artifacts: {}",
+            extension, e
+        );
+        return Ok(Vec::new());
+    }
+    let mut artifacts = Vec::new();
+    for (kind, found) in [
+        ("app", find_bundles(stage.path(), "app")?),
+        ("pkg", find_bundles(stage.path(), "pkg")?),
+    ] {
+        if !found.is_empty() {
+            artifacts.push(json!({ kind: found }));
+        }
+    }
+    Ok(artifacts)
+}
+
+/// Paths (relative to `stage`) of entries ending in `.<extension>`, two levels deep at most.
+fn find_bundles(stage: &Path, extension: &str) -> Result<Vec<String>> {
+    let mut found = Vec::new();
+    let mut dirs = vec![(stage.to_path_buf(), 0)];
+    while let Some((dir, depth)) = dirs.pop() {
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            let is_match = path
+                .extension()
+                .is_some_and(|e| e.eq_ignore_ascii_case(extension));
+            if is_match {
+                if let Ok(relative) = path.strip_prefix(stage) {
+                    found.push(relative.to_string_lossy().to_string());
+                }
+            } else if depth < 1 && path.is_dir() && !path.is_symlink() {
+                dirs.push((path, depth + 1));
+            }
+        }
+    }
+    found.sort();
+    Ok(found)
+}
+
+/// A cask token for an app called `display_name`: `Foo Bar.app` becomes `foo-bar`.
+pub fn cask_token(display_name: &str) -> String {
+    let base = display_name.strip_suffix(".app").unwrap_or(display_name);
+    base.to_lowercase()
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// Checks a user-supplied formula name or cask token before it becomes a file name.
+pub fn validate_name(name: &str) -> Result<()> {
+    let valid = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | '+' | '@'))
+        && !name.starts_with('.');
+    if valid {
+        Ok(())
+    } else {
+        Err(SpsError::Generic(format!("Invalid package name '{name}'")))
+    }
+}
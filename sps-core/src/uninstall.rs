@@ -7,30 +7,34 @@ use std::{
     sync::Arc,
 };
 
-use serde_json;
 use sps_common::config::Config;
 use sps_common::error::{Result, SpsError};
 use tracing::{debug, error, warn};
 
 use crate::build;
-use crate::build::cask::{CaskInstallManifest, InstalledArtifact};
+use crate::build::cask::{read_cask_manifest, InstalledArtifact, CASK_MANIFEST_FILE};
 use crate::installed::InstalledPackageInfo;
 
 #[derive(Debug, Clone, Default)]
 pub struct UninstallOptions {
     pub skip_zap: bool,
+    /// Leave a formula's links in place, for an upgrade or reinstall that relinks them over
+    /// the new keg (see `link::PreviousLinks`).
+    pub keep_links: bool,
 }
 
 pub fn uninstall_formula_artifacts(
     info: &InstalledPackageInfo,
     config: &Config,
-    _options: &UninstallOptions,
+    options: &UninstallOptions,
 ) -> Result<()> {
     debug!(
         "Uninstalling Formula artifacts for {} version {}",
         info.name, info.version
     );
-    build::formula::link::unlink_formula_artifacts(&info.name, &info.version, config)?;
+    if !options.keep_links {
+        build::formula::link::unlink_formula_artifacts(&info.name, &info.version, config)?;
+    }
     if info.path.exists() {
         debug!("Removing formula keg directory: {}", info.path.display());
         fs::remove_dir_all(&info.path).map_err(|e| {
@@ -55,45 +59,29 @@ pub fn uninstall_cask_artifacts(
         "Uninstalling Cask artifacts for {} version {}",
         info.name, info.version
     );
-    let manifest_path = info.path.join("CASK_INSTALL_MANIFEST.json");
     let mut removal_errors: Vec<String> = Vec::new();
 
-    if manifest_path.is_file() {
-        debug!("Processing manifest: {}", manifest_path.display());
-        match fs::read_to_string(&manifest_path) {
-            Ok(manifest_str) => match serde_json::from_str::<CaskInstallManifest>(&manifest_str) {
-                Ok(manifest) => {
-                    debug!(
-                        "Uninstalling {} artifacts listed in manifest...",
-                        manifest.artifacts.len()
-                    );
-                    for artifact in manifest.artifacts.iter().rev() {
-                        if options.skip_zap && is_zap_artifact(artifact, config) {
-                            debug!("Skipping zap artifact: {:?}", artifact);
-                            continue;
-                        }
-                        if !process_artifact_uninstall_core(artifact, config) {
-                            removal_errors.push(format!("Failed: {artifact:?}"));
-                        }
-                    }
+    match read_cask_manifest(&info.path) {
+        Some(manifest) => {
+            debug!(
+                "Uninstalling {} artifacts listed in manifest...",
+                manifest.artifacts.len()
+            );
+            for artifact in manifest.artifacts.iter().rev() {
+                if options.skip_zap && is_zap_artifact(artifact, config) {
+                    debug!("Skipping zap artifact: {:?}", artifact);
+                    continue;
                 }
-                Err(e) => warn!(
-                    "Failed to parse cask manifest {}: {}",
-                    manifest_path.display(),
-                    e
-                ),
-            },
-            Err(e) => warn!(
-                "Failed to read cask manifest {}: {}",
-                manifest_path.display(),
-                e
-            ),
+                if !process_artifact_uninstall_core(artifact, config) {
+                    removal_errors.push(format!("Failed: {artifact:?}"));
+                }
+            }
         }
-    } else {
-        warn!(
-            "No CASK_INSTALL_MANIFEST.json found in {}. Cannot perform detailed uninstall.",
+        None => warn!(
+            "No readable {} in {}. Cannot perform detailed uninstall.",
+            CASK_MANIFEST_FILE,
             info.path.display()
-        );
+        ),
     }
 
     if info.path.exists() {
@@ -3,15 +3,18 @@ use std::collections::HashMap;
 use std::sync::Arc;
 
 use sps_common::cache::Cache;
+use sps_common::config::Config;
 use sps_common::error::{Result, SpsError};
 use sps_common::model::cask::Cask;
-use sps_common::model::formula::Formula;
-use sps_common::model::version::Version;
-use sps_common::model::InstallTargetIdentifier;
+use sps_common::model::formula::{Formula, HeadSpec, HEAD_VERSION_PREFIX};
+use sps_common::model::{CaskVersion, InstallTargetIdentifier, PkgVersion};
 use sps_net::fetch::api;
 use tracing::{debug, warn};
 
+use crate::build::cask::{installed_definition_source, installed_variant, resolve_for_host};
+use crate::build::formula::source::head::{read_head_record, remote_commit, short_commit};
 use crate::installed::{InstalledPackageInfo, PackageType};
+use crate::metadata;
 
 #[derive(Debug, Clone)]
 pub struct UpdateInfo {
@@ -51,21 +54,31 @@ async fn load_or_fetch_json(
     }
 }
 
+/// Compares installed packages with the latest API definitions. Kegs built with `--HEAD` are
+/// only checked when `fetch_head` is set, by asking their repository for its current commit.
+///
+/// Casks are compared as [`CaskVersion`]s. As in Homebrew, casks that update themselves
+/// (`auto_updates`) and `version :latest` casks are skipped unless `greedy` is set; a greedy
+/// check reports every `:latest` cask, since its version can't tell whether it changed.
 pub async fn check_for_updates(
     installed_packages: &[InstalledPackageInfo],
     cache: &Cache,
+    config: &Config,
+    fetch_head: bool,
+    greedy: bool,
 ) -> Result<Vec<UpdateInfo>> {
-    let (formula_values_res, cask_values_res) = tokio::join!(
-        load_or_fetch_json(cache, "formula.json", api::fetch_all_formulas()),
+    let formula_names: Vec<String> = installed_packages
+        .iter()
+        .filter(|p| p.pkg_type == PackageType::Formula)
+        .map(|p| p.name.clone())
+        .collect();
+    let (formulae_res, cask_values_res) = tokio::join!(
+        metadata::formula_definitions(cache, config, &formula_names),
         load_or_fetch_json(cache, "cask.json", api::fetch_all_casks())
     );
 
-    let formulae_map: HashMap<String, Arc<Formula>> = match formula_values_res {
-        Ok(values) => values
-            .into_iter()
-            .filter_map(|v| serde_json::from_value::<Formula>(v).ok())
-            .map(|f| (f.name.clone(), Arc::new(f)))
-            .collect(),
+    let formulae_map: HashMap<String, Arc<Formula>> = match formulae_res {
+        Ok(map) => map,
         Err(e) => {
             warn!("Failed to load formula map for update check: {}", e);
             HashMap::new()
@@ -76,7 +89,7 @@ pub async fn check_for_updates(
         Ok(values) => values
             .into_iter()
             .filter_map(|v| serde_json::from_value::<Cask>(v).ok())
-            .map(|c| (c.token.clone(), Arc::new(c)))
+            .map(|c| (c.token.clone(), Arc::new(resolve_for_host(&c, config))))
             .collect(),
         Err(e) => {
             warn!("Failed to load cask map for update check: {}", e);
@@ -88,25 +101,63 @@ pub async fn check_for_updates(
 
     for installed in installed_packages {
         match installed.pkg_type {
+            PackageType::Formula if installed.version.starts_with(HEAD_VERSION_PREFIX) => {
+                if !fetch_head {
+                    debug!(
+                        "Skipping HEAD keg {} {} (pass --fetch-HEAD to check it)",
+                        installed.name, installed.version
+                    );
+                    continue;
+                }
+                let (Some(latest_formula_arc), Some(record)) = (
+                    formulae_map.get(&installed.name),
+                    read_head_record(&installed.path),
+                ) else {
+                    warn!(
+                        "Cannot check {} {} for upstream commits: no formula or no recorded head",
+                        installed.name, installed.version
+                    );
+                    continue;
+                };
+                let spec = HeadSpec {
+                    url: record.url.clone(),
+                    branch: record.branch.clone(),
+                };
+                let upstream = tokio::task::spawn_blocking(move || remote_commit(&spec))
+                    .await
+                    .map_err(|e| SpsError::Generic(format!("Head check task failed: {e}")))?;
+                match upstream {
+                    Ok(commit) if commit != record.commit => {
+                        let available_version =
+                            format!("{HEAD_VERSION_PREFIX}{}", short_commit(&commit));
+                        debug!(
+                            "Upstream commit found for {}: {} -> {}",
+                            installed.name, installed.version, available_version
+                        );
+                        updates_available.push(UpdateInfo {
+                            name: installed.name.clone(),
+                            installed_version: installed.version.clone(),
+                            available_version,
+                            pkg_type: PackageType::Formula,
+                            target_definition: InstallTargetIdentifier::Formula(
+                                latest_formula_arc.clone(),
+                            ),
+                        });
+                    }
+                    Ok(_) => {}
+                    Err(e) => warn!(
+                        "Could not check {} for upstream commits at {}: {}",
+                        installed.name,
+                        record.url,
+                        e.message()
+                    ),
+                }
+            }
             PackageType::Formula => {
                 if let Some(latest_formula_arc) = formulae_map.get(&installed.name) {
                     let latest_version_str = latest_formula_arc.version_str_full();
-                    let installed_v_res = Version::parse(&installed.version);
-                    let latest_v_res = Version::parse(&latest_version_str);
-                    let installed_revision = installed
-                        .version
-                        .split('_')
-                        .nth(1)
-                        .and_then(|s| s.parse::<u32>().ok())
-                        .unwrap_or(0);
-                    let needs_update = match (installed_v_res, latest_v_res) {
-                        (Ok(iv), Ok(lv)) => {
-                            lv > iv
-                                || (lv == iv && latest_formula_arc.revision > installed_revision)
-                        }
-                        _ => installed.version != latest_version_str,
-                    };
-                    if needs_update {
+                    // The keg directory name carries the revision, as does the formula's.
+                    if latest_formula_arc.pkg_version() > PkgVersion::parse(&installed.version) {
                         debug!(
                             "Update found for Formula {}: {} -> {}",
                             installed.name, installed.version, latest_version_str
@@ -129,9 +180,36 @@ pub async fn check_for_updates(
                 }
             }
             PackageType::Cask => {
+                if let Some(source) = installed_definition_source(&installed.path) {
+                    warn!(
+                        "Not checking cask '{}' for upgrades: it was installed from {} rather than the API. Reinstall it by token to track the API again.",
+                        installed.name, source
+                    );
+                    continue;
+                }
                 if let Some(latest_cask_arc) = casks_map.get(&installed.name) {
                     if let Some(available_version) = latest_cask_arc.version.as_ref() {
-                        if &installed.version != available_version {
+                        let available = CaskVersion::parse(available_version);
+                        let auto_updates = latest_cask_arc.auto_updates == Some(true);
+                        if !greedy && (auto_updates || available.is_latest()) {
+                            debug!(
+                                "Skipping cask {} ({}; pass --greedy to check it)",
+                                installed.name,
+                                if auto_updates {
+                                    "auto_updates"
+                                } else {
+                                    "version :latest"
+                                }
+                            );
+                            continue;
+                        }
+                        // A receipt without a variant predates variant selection; only the
+                        // version can be compared then.
+                        let variant_changed = installed_variant(&installed.path)
+                            .is_some_and(|v| Some(&v) != latest_cask_arc.variant.as_ref());
+                        let newer = available.is_latest()
+                            || available > CaskVersion::parse(&installed.version);
+                        if newer || variant_changed {
                             debug!(
                                 "Update found for Cask {}: {} -> {}",
                                 installed.name, installed.version, available_version
@@ -0,0 +1,163 @@
+//! Cancelling a download or a pour part way: the work stops with `Cancelled` and leaves neither a
+//! temp file in the cache nor a partial keg in the Cellar.
+
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use reqwest::Client;
+use sps_common::cache::Cache;
+use sps_common::cancel::{self, CancellationToken};
+use sps_common::config::Config;
+use sps_common::error::SpsError;
+use sps_common::model::formula::Formula;
+use sps_core::build::formula::bottle::{download_bottle, install_bottle};
+use sps_testkit::{FormulaFixture, MockServer, Response};
+use walkdir::WalkDir;
+
+fn config(dir: &Path) -> Config {
+    Config {
+        prefix: dir.to_path_buf(),
+        cellar: dir.join("Cellar"),
+        cache_dir: dir.join("cache"),
+        bottle_domain: None,
+        ..Config::load().unwrap()
+    }
+}
+
+/// Bytes gzip can't shrink, so the bottle stays as large as its contents.
+fn noise(len: usize) -> Vec<u8> {
+    let mut state = 0x2545_f491_4f6c_dd1du64;
+    (0..len)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as u8
+        })
+        .collect()
+}
+
+fn files_under(dir: &Path) -> Vec<String> {
+    WalkDir::new(dir)
+        .into_iter()
+        .flatten()
+        .filter(|entry| entry.file_type().is_file())
+        .map(|entry| entry.path().display().to_string())
+        .collect()
+}
+
+#[tokio::test]
+async fn a_cancelled_download_stops_at_the_next_chunk_and_leaves_no_temp_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let server = MockServer::start();
+    let fixture = FormulaFixture::new("big", "1.0").file("share/big/blob", noise(256 * 1024));
+    let bottle = fixture.bottle_bytes();
+    // About five seconds for the whole bottle.
+    let chunks = bottle.len() / 4096;
+    server.serve(
+        &fixture.bottle_path(),
+        Response::ok(bottle.clone()).throttle(4096, Duration::from_secs(5) / chunks as u32),
+    );
+    let formula: Formula =
+        serde_json::from_value(fixture.api_json(&server.base_url(), &bottle)).unwrap();
+    let config = config(dir.path());
+    let cache = Cache::new(&config.cache_dir).unwrap();
+    let client = Client::new();
+    let token = CancellationToken::new();
+
+    let started = Instant::now();
+    let canceller = {
+        let token = token.clone();
+        let path = fixture.bottle_path();
+        async move {
+            while server.first_request_at(&path).is_none() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            // Some of the body is on disk by now.
+            tokio::time::sleep(Duration::from_millis(300)).await;
+            token.cancel();
+            server
+        }
+    };
+    let (result, _server) = tokio::join!(
+        cancel::with_token(
+            token.clone(),
+            download_bottle(&formula, &config, &cache, &client)
+        ),
+        canceller
+    );
+
+    match result {
+        Err(SpsError::Cancelled(msg)) => assert!(msg.contains("big"), "{msg}"),
+        other => panic!("expected the download to be cancelled, got {other:?}"),
+    }
+    assert!(
+        started.elapsed() < Duration::from_secs(3),
+        "stopped after {:?}, not at the next chunk",
+        started.elapsed()
+    );
+    assert_eq!(files_under(&config.cache_dir), Vec::<String>::new());
+}
+
+/// A bottle of `count` small files, and the formula for it.
+fn many_files(count: usize) -> (FormulaFixture, Vec<u8>, Formula) {
+    let mut fixture = FormulaFixture::new("many", "1.0");
+    for i in 0..count {
+        fixture = fixture.file(&format!("share/many/{i:05}.txt"), format!("file {i}\n"));
+    }
+    let bottle = fixture.bottle_bytes();
+    let formula = serde_json::from_value(fixture.api_json("http://127.0.0.1:1", &bottle)).unwrap();
+    (fixture, bottle, formula)
+}
+
+#[test]
+fn a_cancelled_pour_removes_the_partial_keg() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = config(dir.path());
+    let (_fixture, bottle, formula) = many_files(20_000);
+    let bottle_path = dir.path().join("many-1.0.all.bottle.tar.gz");
+    std::fs::write(&bottle_path, &bottle).unwrap();
+    let keg = config.cellar.join("many").join("1.0");
+    let token = CancellationToken::new();
+
+    let result = thread::scope(|scope| {
+        let watcher_token = token.clone();
+        let first_file = keg.join("bin/many");
+        scope.spawn(move || {
+            // Cancels once extraction has written its first file.
+            let deadline = Instant::now() + Duration::from_secs(30);
+            while !first_file.exists() && Instant::now() < deadline {
+                thread::sleep(Duration::from_millis(1));
+            }
+            watcher_token.cancel();
+        });
+        cancel::with_thread_token(token.clone(), || {
+            install_bottle(&bottle_path, &formula, &config)
+        })
+    });
+
+    match result {
+        Err(SpsError::Cancelled(msg)) => assert!(msg.contains("Extracting"), "{msg}"),
+        other => panic!("expected the pour to be cancelled, got {other:?}"),
+    }
+    assert!(!keg.exists(), "the partial keg {} is left", keg.display());
+}
+
+#[test]
+fn a_pour_cancelled_before_it_starts_writes_nothing() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = config(dir.path());
+    let (_fixture, bottle, formula) = many_files(3);
+    let bottle_path = dir.path().join("many-1.0.all.bottle.tar.gz");
+    std::fs::write(&bottle_path, &bottle).unwrap();
+    let token = CancellationToken::new();
+    token.cancel();
+
+    let result =
+        cancel::with_thread_token(token, || install_bottle(&bottle_path, &formula, &config));
+
+    assert!(matches!(result, Err(SpsError::Cancelled(_))), "{result:?}");
+    assert_eq!(files_under(&config.cellar), Vec::<String>::new());
+    assert!(!config.cellar.join("many").join("1.0").exists());
+}
@@ -0,0 +1,114 @@
+//! Two tasks downloading the same artifact at once share one request to the server.
+
+use std::path::Path;
+use std::time::Duration;
+
+use reqwest::Client;
+use sps_common::cache::Cache;
+use sps_common::config::Config;
+use sps_common::model::cask::Cask;
+use sps_common::model::formula::Formula;
+use sps_core::build::cask::download_cask;
+use sps_core::build::formula::bottle::download_bottle;
+use sps_testkit::{BottleServing, CaskFixture, FormulaFixture, MockServer, Response};
+
+/// Long enough that the second task asks while the first is still downloading.
+const DELAY: Duration = Duration::from_millis(300);
+
+fn config(dir: &Path) -> Config {
+    Config {
+        prefix: dir.to_path_buf(),
+        cellar: dir.join("Cellar"),
+        cache_dir: dir.join("cache"),
+        bottle_domain: None,
+        ..Config::load().unwrap()
+    }
+}
+
+fn serve_formula(server: &MockServer, fixture: &FormulaFixture) -> Formula {
+    let bottle = fixture.bottle_bytes();
+    let response = match fixture.bottle {
+        BottleServing::Missing => Response::status(404),
+        _ => Response::ok(bottle.clone()),
+    };
+    server.serve(&fixture.bottle_path(), response.delay(DELAY));
+    serde_json::from_value(fixture.api_json(&server.base_url(), &bottle)).unwrap()
+}
+
+#[tokio::test]
+async fn two_tasks_downloading_one_bottle_make_a_single_request() {
+    let dir = tempfile::tempdir().unwrap();
+    let server = MockServer::start();
+    let fixture = FormulaFixture::new("jq", "1.7.1");
+    let formula = serve_formula(&server, &fixture);
+    let config = config(dir.path());
+    let cache = Cache::new(&config.cache_dir).unwrap();
+    let client = Client::new();
+
+    let (first, second) = tokio::join!(
+        download_bottle(&formula, &config, &cache, &client),
+        download_bottle(&formula, &config, &cache, &client),
+    );
+
+    assert_eq!(server.hits(&fixture.bottle_path()), 1);
+    let (first, second) = (first.unwrap(), second.unwrap());
+    assert_eq!(first, second);
+    assert_eq!(std::fs::read(first).unwrap(), fixture.bottle_bytes());
+}
+
+/// How many requests one download of `fixture`'s bottle makes on its own, retries included.
+async fn requests_for_one_download(fixture: &FormulaFixture) -> usize {
+    let dir = tempfile::tempdir().unwrap();
+    let server = MockServer::start();
+    let formula = serve_formula(&server, fixture);
+    let config = config(dir.path());
+    let cache = Cache::new(&config.cache_dir).unwrap();
+    let _ = download_bottle(&formula, &config, &cache, &Client::new()).await;
+    server.hits(&fixture.bottle_path())
+}
+
+#[tokio::test]
+async fn a_failed_shared_download_fails_both_tasks() {
+    let fixture = FormulaFixture::new("gone", "1.0").bottle(BottleServing::Missing);
+    let alone = requests_for_one_download(&fixture).await;
+    let dir = tempfile::tempdir().unwrap();
+    let server = MockServer::start();
+    let formula = serve_formula(&server, &fixture);
+    let config = config(dir.path());
+    let cache = Cache::new(&config.cache_dir).unwrap();
+    let client = Client::new();
+
+    let (first, second) = tokio::join!(
+        download_bottle(&formula, &config, &cache, &client),
+        download_bottle(&formula, &config, &cache, &client),
+    );
+
+    let (first, second) = (first.unwrap_err(), second.unwrap_err());
+    assert_eq!(first.to_string(), second.to_string());
+    // The second task adds no requests to the first one's.
+    assert_eq!(server.hits(&fixture.bottle_path()), alone);
+}
+
+#[tokio::test]
+async fn two_tasks_downloading_one_cask_make_a_single_request() {
+    let dir = tempfile::tempdir().unwrap();
+    let server = MockServer::start();
+    let fixture = CaskFixture::new("viewer", "2.0");
+    let archive = fixture.archive_bytes();
+    server.serve(
+        &fixture.archive_path(),
+        Response::ok(archive.clone()).delay(DELAY),
+    );
+    let cask: Cask =
+        serde_json::from_value(fixture.api_json(&server.base_url(), &archive)).unwrap();
+    let config = config(dir.path());
+    let cache = Cache::new(&config.cache_dir).unwrap();
+
+    let (first, second) = tokio::join!(
+        download_cask(&cask, &cache, &config),
+        download_cask(&cask, &cache, &config),
+    );
+
+    assert_eq!(server.hits(&fixture.archive_path()), 1);
+    assert_eq!(first.unwrap(), second.unwrap());
+}
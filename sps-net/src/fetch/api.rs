@@ -1,7 +1,7 @@
 use std::sync::Arc;
 
-use reqwest::header::{ACCEPT, AUTHORIZATION, USER_AGENT};
-use reqwest::Client;
+use reqwest::header::{ACCEPT, AUTHORIZATION, ETAG, IF_NONE_MATCH, USER_AGENT};
+use reqwest::{Client, StatusCode};
 use serde_json::Value;
 use sps_common::config::Config;
 use sps_common::error::{Result, SpsError};
@@ -13,6 +13,16 @@ const FORMULAE_API_BASE_URL: &str = "https://formulae.brew.sh/api";
 const GITHUB_API_BASE_URL: &str = "https://api.github.com";
 const USER_AGENT_STRING: &str = "sps Package Manager (Rust; +https://github.com/your/sp)";
 
+/// Base URL of the formulae API. `HOMEBREW_API_DOMAIN` overrides it (like
+/// `HOMEBREW_ARTIFACT_DOMAIN` for bottles), e.g. to point at a mirror or a local fixture server.
+fn formulae_api_base_url() -> String {
+    std::env::var("HOMEBREW_API_DOMAIN")
+        .ok()
+        .filter(|d| !d.is_empty())
+        .map(|d| d.trim_end_matches('/').to_string())
+        .unwrap_or_else(|| FORMULAE_API_BASE_URL.to_string())
+}
+
 fn build_api_client(config: &Config) -> Result<Client> {
     let mut headers = reqwest::header::HeaderMap::new();
     headers.insert(USER_AGENT, USER_AGENT_STRING.parse().unwrap());
@@ -34,12 +44,30 @@ fn build_api_client(config: &Config) -> Result<Client> {
 }
 
 pub async fn fetch_raw_formulae_json(endpoint: &str) -> Result<String> {
-    let url = format!("{FORMULAE_API_BASE_URL}/{endpoint}");
+    let url = format!("{}/{endpoint}", formulae_api_base_url());
     debug!("Fetching data from Homebrew Formulae API: {}", url);
+    fetch_text(&url).await
+}
+
+/// Fetches a single cask definition from an arbitrary URL (`install --cask-url`).
+pub async fn fetch_cask_definition(url: &str) -> Result<Value> {
+    debug!("Fetching cask definition from {}", url);
+    let body = fetch_text(url).await?;
+    serde_json::from_str(&body)
+        .map_err(|e| SpsError::ValidationError(format!("{url}: response is not valid JSON: {e}")))
+}
+
+/// Downloads the OSV advisory feed configured as `advisory_feed`.
+pub async fn fetch_advisory_feed(url: &str) -> Result<String> {
+    debug!("Fetching advisory feed from {}", url);
+    fetch_text(url).await
+}
+
+async fn fetch_text(url: &str) -> Result<String> {
     let client = reqwest::Client::builder()
         .user_agent(USER_AGENT_STRING)
         .build()?;
-    let response = client.get(&url).send().await.map_err(|e| {
+    let response = client.get(url).send().await.map_err(|e| {
         error!("HTTP request failed for {}: {}", url, e);
         SpsError::Http(Arc::new(e))
     })?;
@@ -70,6 +98,55 @@ pub async fn fetch_all_formulas() -> Result<String> {
     fetch_raw_formulae_json("formula.json").await
 }
 
+/// Outcome of a conditional fetch of an API index.
+#[derive(Debug)]
+pub enum Revalidation {
+    /// The copy with the ETag that was sent is still current.
+    NotModified,
+    /// The index changed (or no ETag was sent); `etag` is the one to send next time.
+    Modified { body: String, etag: Option<String> },
+}
+
+/// Fetches `formula.json`, sending `etag` as `If-None-Match` so an unchanged index costs a
+/// single 304 instead of the whole download.
+pub async fn fetch_all_formulas_if_changed(etag: Option<&str>) -> Result<Revalidation> {
+    let url = format!("{}/formula.json", formulae_api_base_url());
+    debug!("Revalidating {} (ETag {:?})", url, etag);
+    let client = reqwest::Client::builder()
+        .user_agent(USER_AGENT_STRING)
+        .build()?;
+    let mut request = client.get(&url);
+    if let Some(etag) = etag {
+        request = request.header(IF_NONE_MATCH, etag);
+    }
+    let response = request.send().await.map_err(|e| {
+        error!("HTTP request failed for {}: {}", url, e);
+        SpsError::Http(Arc::new(e))
+    })?;
+    if response.status() == StatusCode::NOT_MODIFIED {
+        debug!("{} is unchanged", url);
+        return Ok(Revalidation::NotModified);
+    }
+    if !response.status().is_success() {
+        return Err(SpsError::Api(format!(
+            "HTTP status {} from {url}",
+            response.status()
+        )));
+    }
+    let etag = response
+        .headers()
+        .get(ETAG)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string);
+    let body = response.text().await?;
+    if body.trim().is_empty() {
+        return Err(SpsError::Api(format!(
+            "Empty response body received from {url}"
+        )));
+    }
+    Ok(Revalidation::Modified { body, etag })
+}
+
 pub async fn fetch_all_casks() -> Result<String> {
     fetch_raw_formulae_json("cask.json").await
 }
@@ -156,6 +233,18 @@ async fn fetch_github_api_json(endpoint: &str, config: &Config) -> Result<Value>
     Ok(value)
 }
 
+/// The latest published release of `owner/repo`, as GitHub's releases API returns it.
+pub async fn fetch_latest_release(owner: &str, repo: &str, config: &Config) -> Result<Value> {
+    let endpoint = format!("/repos/{owner}/{repo}/releases/latest");
+    fetch_github_api_json(&endpoint, config).await
+}
+
+/// Downloads a small text asset, such as a release's checksum file.
+pub async fn fetch_release_text(url: &str) -> Result<String> {
+    debug!("Fetching release asset {}", url);
+    fetch_text(url).await
+}
+
 #[allow(dead_code)]
 async fn fetch_github_repo_info(owner: &str, repo: &str, config: &Config) -> Result<Value> {
     let endpoint = format!("/repos/{owner}/{repo}");
@@ -163,7 +252,7 @@ async fn fetch_github_repo_info(owner: &str, repo: &str, config: &Config) -> Res
 }
 
 pub async fn get_formula(name: &str) -> Result<Formula> {
-    let url = format!("{FORMULAE_API_BASE_URL}/formula/{name}.json");
+    let url = format!("{}/formula/{name}.json", formulae_api_base_url());
     debug!(
         "Fetching and parsing formula data for '{}' from {}",
         name, url
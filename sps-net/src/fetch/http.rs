@@ -4,13 +4,13 @@ use std::time::Duration;
 
 use reqwest::header::{HeaderMap, ACCEPT, USER_AGENT};
 use reqwest::{Client, StatusCode};
+use sps_common::cache::{migrate_legacy_entry, origin_tag};
 use sps_common::config::Config;
 use sps_common::error::{Result, SpsError};
 use sps_common::model::formula::ResourceSpec;
-use tokio::fs::File as TokioFile;
-use tokio::io::AsyncWriteExt;
 use tracing::{debug, error};
 
+use crate::fetch::stream::stream_response_to_file;
 use crate::validation::verify_checksum;
 
 const DOWNLOAD_TIMEOUT_SECS: u64 = 300;
@@ -29,7 +29,20 @@ pub async fn fetch_formula_source_or_bottle(
         .next_back()
         .map(|s| s.to_string())
         .unwrap_or_else(|| format!("{formula_name}-download"));
-    let cache_path = config.cache_dir.join(&filename);
+    // One directory per origin keeps the file name intact (single-file sources are installed
+    // under it) while keeping downloads from different mirrors apart.
+    let source_cache_dir = config
+        .download_dir
+        .join("sources")
+        .join(origin_tag(sha256_expected, url));
+    let cache_path = source_cache_dir.join(&filename);
+    let legacy_path = config.download_dir.join(&filename);
+    if legacy_path.is_file() {
+        fs::create_dir_all(&source_cache_dir)?;
+        migrate_legacy_entry(&legacy_path, &cache_path, |path| {
+            !sha256_expected.is_empty() && verify_checksum(path, sha256_expected).is_ok()
+        });
+    }
 
     tracing::debug!(
         "Preparing to fetch main resource for '{}' from URL: {}",
@@ -73,10 +86,10 @@ pub async fn fetch_formula_source_or_bottle(
         tracing::debug!("File not found in cache.");
     }
 
-    fs::create_dir_all(&config.cache_dir).map_err(|e| {
+    fs::create_dir_all(&source_cache_dir).map_err(|e| {
         SpsError::IoError(format!(
             "Failed to create cache directory {}: {}",
-            config.cache_dir.display(),
+            source_cache_dir.display(),
             e
         ))
     })?;
@@ -92,7 +105,16 @@ pub async fn fetch_formula_source_or_bottle(
         // Validate mirror URL
         crate::validation::validate_url(current_url)?;
         tracing::debug!("Attempting download from: {}", current_url);
-        match download_and_verify(&client, current_url, &cache_path, sha256_expected).await {
+        match download_and_verify(
+            &client,
+            current_url,
+            &cache_path,
+            sha256_expected,
+            config.max_download_size,
+            true,
+        )
+        .await
+        {
             Ok(path) => {
                 tracing::debug!("Successfully downloaded and verified: {}", path.display());
                 return Ok(path);
@@ -113,12 +135,54 @@ pub async fn fetch_formula_source_or_bottle(
     }))
 }
 
+/// Downloads `url` straight to `destination`, verifying `sha256_expected` while streaming. The
+/// caller owns the cache layout; no cache lookup happens here.
+pub async fn fetch_to_path(
+    url: &str,
+    destination: &Path,
+    sha256_expected: &str,
+    config: &Config,
+) -> Result<PathBuf> {
+    crate::validation::validate_url(url)?;
+    let client = build_http_client()?;
+    download_and_verify(
+        &client,
+        url,
+        destination,
+        sha256_expected,
+        config.max_download_size,
+        true,
+    )
+    .await
+}
+
+/// Downloads `url` to `destination` for a caller that has no checksum yet and computes one from
+/// the file (e.g. `sps create`), so the missing checksum isn't worth a warning.
+pub async fn fetch_unverified_to_path(
+    url: &str,
+    destination: &Path,
+    config: &Config,
+) -> Result<PathBuf> {
+    crate::validation::validate_url(url)?;
+    let client = build_http_client()?;
+    download_and_verify(
+        &client,
+        url,
+        destination,
+        "",
+        config.max_download_size,
+        false,
+    )
+    .await
+}
+
 pub async fn fetch_resource(
     formula_name: &str,
     resource: &ResourceSpec,
     config: &Config,
 ) -> Result<PathBuf> {
-    let resource_cache_dir = config.cache_dir.join("resources");
+    let legacy_cache_dir = config.download_dir.join("resources");
+    let resource_cache_dir = legacy_cache_dir.join(origin_tag(&resource.sha256, &resource.url));
     fs::create_dir_all(&resource_cache_dir).map_err(|e| {
         SpsError::IoError(format!(
             "Failed to create resource cache directory {}: {}",
@@ -137,6 +201,11 @@ pub async fn fetch_resource(
         .unwrap_or_else(|| format!("{}-download", resource.name));
     let cache_filename = format!("{}-{}", resource.name, url_filename);
     let cache_path = resource_cache_dir.join(&cache_filename);
+    migrate_legacy_entry(
+        &legacy_cache_dir.join(&cache_filename),
+        &cache_path,
+        |path| verify_checksum(path, &resource.sha256).is_ok(),
+    );
 
     tracing::debug!(
         "Preparing to fetch resource '{}' for formula '{}' from URL: {}",
@@ -174,7 +243,16 @@ pub async fn fetch_resource(
     }
 
     let client = build_http_client()?;
-    match download_and_verify(&client, &resource.url, &cache_path, &resource.sha256).await {
+    match download_and_verify(
+        &client,
+        &resource.url,
+        &cache_path,
+        &resource.sha256,
+        config.max_download_size,
+        true,
+    )
+    .await
+    {
         Ok(path) => {
             tracing::debug!(
                 "Successfully downloaded and verified resource: {}",
@@ -194,7 +272,7 @@ pub async fn fetch_resource(
     }
 }
 
-fn build_http_client() -> Result<Client> {
+pub(crate) fn build_http_client() -> Result<Client> {
     let mut headers = HeaderMap::new();
     headers.insert(USER_AGENT, USER_AGENT_STRING.parse().unwrap());
     headers.insert(ACCEPT, "*/*".parse().unwrap());
@@ -212,6 +290,8 @@ async fn download_and_verify(
     url: &str,
     final_path: &Path,
     sha256_expected: &str,
+    max_bytes: u64,
+    warn_unverified: bool,
 ) -> Result<PathBuf> {
     let temp_filename = format!(
         ".{}.download",
@@ -266,38 +346,35 @@ async fn download_and_verify(
         };
     }
 
-    let mut temp_file = TokioFile::create(&temp_path).await.map_err(|e| {
+    let mut temp_file = fs::File::create(&temp_path).map_err(|e| {
         SpsError::IoError(format!(
             "Failed to create temp file {}: {}",
             temp_path.display(),
             e
         ))
     })?;
-    let content = response
-        .bytes()
-        .await
-        .map_err(|e| SpsError::HttpError(format!("Failed to read response body bytes: {e}")))?;
-    temp_file.write_all(&content).await.map_err(|e| {
-        SpsError::IoError(format!(
-            "Failed to write download stream to {}: {}",
-            temp_path.display(),
-            e
-        ))
-    })?;
+    let label = final_path
+        .file_name()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_else(|| url.to_string());
+    if let Err(e) =
+        stream_response_to_file(response, &mut temp_file, &label, sha256_expected, max_bytes).await
+    {
+        drop(temp_file);
+        let _ = fs::remove_file(&temp_path);
+        return Err(e);
+    }
     drop(temp_file);
-    tracing::debug!("Finished writing download stream to temp file.");
-
-    if !sha256_expected.is_empty() {
-        crate::validation::verify_checksum(&temp_path, sha256_expected)?;
-        tracing::debug!(
-            "Checksum verified for temporary file: {}",
-            temp_path.display()
-        );
-    } else {
+    if sha256_expected.is_empty() && warn_unverified {
         tracing::warn!(
             "Skipping checksum verification for {} - none provided.",
             temp_path.display()
         );
+    } else if !sha256_expected.is_empty() {
+        tracing::debug!(
+            "Checksum verified for temporary file: {}",
+            temp_path.display()
+        );
     }
 
     fs::rename(&temp_path, final_path).map_err(|e| {
@@ -314,3 +391,100 @@ async fn download_and_verify(
     );
     Ok(final_path.to_path_buf())
 }
+
+#[cfg(test)]
+mod tests {
+    use sha2::{Digest, Sha256};
+
+    use super::*;
+
+    const SOURCE: &[u8] = b"jq source tarball";
+    /// Nothing listens on port 1, so a download attempt fails at once.
+    const URL: &str = "http://127.0.0.1:1/src/jq-1.7.1.tar.gz";
+
+    fn config(dir: &Path) -> Config {
+        Config {
+            download_dir: dir.to_path_buf(),
+            ..Config::load().unwrap()
+        }
+    }
+
+    fn sha256_hex(data: &[u8]) -> String {
+        hex::encode(Sha256::digest(data))
+    }
+
+    #[tokio::test]
+    async fn a_verified_legacy_source_moves_under_its_origin_and_is_used() {
+        let dir = tempfile::tempdir().unwrap();
+        let legacy = dir.path().join("jq-1.7.1.tar.gz");
+        fs::write(&legacy, SOURCE).unwrap();
+        let sha = sha256_hex(SOURCE);
+
+        let path = fetch_formula_source_or_bottle("jq", URL, &sha, &[], &config(dir.path()))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            path,
+            dir.path()
+                .join("sources")
+                .join(&sha[..12])
+                .join("jq-1.7.1.tar.gz")
+        );
+        assert_eq!(fs::read(&path).unwrap(), SOURCE);
+        assert!(!legacy.exists());
+    }
+
+    #[tokio::test]
+    async fn a_legacy_source_that_does_not_match_is_evicted_not_reused() {
+        let dir = tempfile::tempdir().unwrap();
+        let legacy = dir.path().join("jq-1.7.1.tar.gz");
+        fs::write(&legacy, b"the other mirror's tarball").unwrap();
+
+        let result = fetch_formula_source_or_bottle(
+            "jq",
+            URL,
+            &sha256_hex(SOURCE),
+            &[],
+            &config(dir.path()),
+        )
+        .await;
+
+        assert!(result.is_err(), "{result:?}");
+        assert!(!legacy.exists());
+        assert!(!dir
+            .path()
+            .join("sources")
+            .join(&sha256_hex(SOURCE)[..12])
+            .join("jq-1.7.1.tar.gz")
+            .exists());
+    }
+
+    #[tokio::test]
+    async fn sources_without_a_checksum_are_kept_apart_per_host() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = config(dir.path());
+        let cached = |host: &str| {
+            dir.path()
+                .join("sources")
+                .join(host)
+                .join("jq-1.7.1.tar.gz")
+        };
+        fs::create_dir_all(cached("mirror-a.example").parent().unwrap()).unwrap();
+        fs::write(cached("mirror-a.example"), SOURCE).unwrap();
+
+        let hit = fetch_formula_source_or_bottle(
+            "jq",
+            "https://mirror-a.example/jq-1.7.1.tar.gz",
+            "",
+            &[],
+            &config,
+        )
+        .await
+        .unwrap();
+        let other_mirror = fetch_formula_source_or_bottle("jq", URL, "", &[], &config).await;
+
+        assert_eq!(hit, cached("mirror-a.example"));
+        assert!(other_mirror.is_err(), "{other_mirror:?}");
+    }
+}
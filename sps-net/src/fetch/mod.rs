@@ -1,6 +1,8 @@
 pub mod api;
 pub mod http;
 pub mod oci;
+pub mod progress;
+pub mod stream;
 
 pub use api::*;
 pub use oci::*;
@@ -4,7 +4,6 @@ use std::path::Path;
 use std::sync::Arc;
 use std::time::Duration;
 
-use futures::StreamExt;
 use rand::rngs::SmallRng;
 use rand::{Rng, SeedableRng};
 use reqwest::header::{ACCEPT, AUTHORIZATION};
@@ -15,6 +14,8 @@ use sps_common::error::{Result, SpsError};
 use tracing::{debug, error};
 use url::Url;
 
+use crate::fetch::stream::stream_response_to_file;
+
 const OCI_MANIFEST_V1_TYPE: &str = "application/vnd.oci.image.index.v1+json";
 const OCI_LAYER_V1_TYPE: &str = "application/vnd.oci.image.layer.v1.tar+gzip";
 const DEFAULT_GHCR_TOKEN_ENDPOINT: &str = "https://ghcr.io/token";
@@ -112,33 +113,37 @@ pub async fn download_oci_blob(
         destination_path.file_name().unwrap().to_string_lossy()
     ));
     let mut out = File::create(&tmp).map_err(|e| SpsError::Io(Arc::new(e)))?;
-
-    let mut stream = resp.bytes_stream();
-    while let Some(chunk) = stream.next().await {
-        let b = chunk.map_err(|e| SpsError::Http(Arc::new(e)))?;
-        std::io::Write::write_all(&mut out, &b).map_err(|e| SpsError::Io(Arc::new(e)))?;
+    let label = destination_path
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_else(|| blob_url.to_string());
+    let streamed = stream_response_to_file(
+        resp,
+        &mut out,
+        &label,
+        expected_digest,
+        config.max_download_size,
+    )
+    .await;
+    drop(out);
+    if let Err(e) = streamed {
+        if matches!(e, SpsError::ChecksumError(_)) {
+            tracing::error!(
+                "OCI Blob checksum mismatch ({}). Deleting downloaded file.",
+                e
+            );
+        }
+        let _ = remove_file(&tmp);
+        return Err(e);
     }
     std::fs::rename(&tmp, destination_path).map_err(|e| SpsError::Io(Arc::new(e)))?;
-
-    if !expected_digest.is_empty() {
-        match crate::validation::verify_checksum(destination_path, expected_digest) {
-            Ok(_) => {
-                tracing::debug!("OCI Blob checksum verified: {}", destination_path.display());
-            }
-            Err(e) => {
-                tracing::error!(
-                    "OCI Blob checksum mismatch ({}). Deleting downloaded file.",
-                    e
-                );
-                let _ = remove_file(destination_path);
-                return Err(e);
-            }
-        }
-    } else {
+    if expected_digest.is_empty() {
         tracing::warn!(
             "Skipping checksum verification for OCI blob {} - no checksum provided.",
             destination_path.display()
         );
+    } else {
+        tracing::debug!("OCI Blob checksum verified: {}", destination_path.display());
     }
 
     debug!("Blob saved to {}", destination_path.display());
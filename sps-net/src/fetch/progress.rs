@@ -0,0 +1,22 @@
+//! Byte counts for the download the current task is running, for callers that want to show
+//! them. A reporter is installed per task with [`with_reporter`]; downloads made outside one
+//! report nothing.
+
+use std::future::Future;
+use std::sync::Arc;
+
+/// Called with the bytes received so far and the declared total, if the server sent one.
+pub type Reporter = Arc<dyn Fn(u64, Option<u64>) + Send + Sync>;
+
+tokio::task_local! {
+    static REPORTER: Reporter;
+}
+
+/// Runs `fut` with `reporter` receiving the progress of every download it streams.
+pub async fn with_reporter<F: Future>(reporter: Reporter, fut: F) -> F::Output {
+    REPORTER.scope(reporter, fut).await
+}
+
+pub(crate) fn report(received: u64, total: Option<u64>) {
+    let _ = REPORTER.try_with(|reporter| reporter(received, total));
+}
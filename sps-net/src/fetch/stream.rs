@@ -0,0 +1,238 @@
+//! Chunked download of a response body to disk, hashing as it goes.
+//!
+//! Artifacts can be several gigabytes, so the body is never buffered in memory and the SHA256 is
+//! computed on the fly instead of re-reading the file afterwards.
+
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+use futures::StreamExt;
+use reqwest::Response;
+use sha2::{Digest, Sha256};
+use sps_common::cancel;
+use sps_common::error::{Result, SpsError};
+use tracing::debug;
+
+use crate::fetch::progress;
+
+/// How often a progress event is emitted while streaming.
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(2);
+/// How often the task's progress reporter, if any, is updated.
+const REPORT_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Streams `response` into `out` and returns the number of bytes written. Fails before writing
+/// anything if the declared length exceeds `max_bytes`, and mid-stream if the body does. When
+/// `expected_sha256` is non-empty the computed digest must match it. Cancelling the task's
+/// token stops the download at the next chunk, even one the server is slow to send.
+pub async fn stream_response_to_file(
+    response: Response,
+    out: &mut impl Write,
+    label: &str,
+    expected_sha256: &str,
+    max_bytes: u64,
+) -> Result<u64> {
+    let declared_len = response.content_length();
+    if let Some(len) = declared_len {
+        if len > max_bytes {
+            return Err(size_limit_error(label, len, max_bytes));
+        }
+    }
+
+    let mut hasher = Sha256::new();
+    let mut written: u64 = 0;
+    let mut last_progress = Instant::now();
+    let mut last_report = Instant::now();
+    progress::report(0, declared_len);
+    let mut stream = response.bytes_stream();
+    let cancel = cancel::current();
+    loop {
+        let next = match &cancel {
+            Some(token) => tokio::select! {
+                biased;
+                _ = token.cancelled() => {
+                    return Err(SpsError::Cancelled(format!("Download of {label} was cancelled")));
+                }
+                chunk = stream.next() => chunk,
+            },
+            None => stream.next().await,
+        };
+        let Some(chunk) = next else {
+            break;
+        };
+        let chunk = chunk.map_err(|e| {
+            SpsError::HttpError(format!("Failed to read response body for {label}: {e}"))
+        })?;
+        written += chunk.len() as u64;
+        if written > max_bytes {
+            return Err(size_limit_error(label, written, max_bytes));
+        }
+        hasher.update(&chunk);
+        out.write_all(&chunk)?;
+        if last_report.elapsed() >= REPORT_INTERVAL {
+            last_report = Instant::now();
+            progress::report(written, declared_len);
+        }
+        if last_progress.elapsed() >= PROGRESS_INTERVAL {
+            last_progress = Instant::now();
+            match declared_len {
+                Some(total) => debug!(
+                    "Downloading {}: {} / {} bytes ({}%)",
+                    label,
+                    written,
+                    total,
+                    written * 100 / total.max(1)
+                ),
+                None => debug!("Downloading {}: {} bytes", label, written),
+            }
+        }
+    }
+    out.flush()?;
+    progress::report(written, declared_len);
+
+    if let Some(expected_len) = declared_len {
+        if written != expected_len {
+            return Err(SpsError::DownloadError(
+                label.to_string(),
+                String::new(),
+                format!("Incomplete download: got {written} of {expected_len} bytes"),
+            ));
+        }
+    }
+
+    let actual = hex::encode(hasher.finalize());
+    if !expected_sha256.is_empty() && !actual.eq_ignore_ascii_case(expected_sha256) {
+        return Err(SpsError::ChecksumError(format!(
+            "Checksum mismatch for {label}: expected {expected_sha256}, got {actual}"
+        )));
+    }
+    debug!(
+        "Streamed {} bytes for {} (sha256 {})",
+        written, label, actual
+    );
+    Ok(written)
+}
+
+fn size_limit_error(label: &str, size: u64, max_bytes: u64) -> SpsError {
+    SpsError::ValidationError(format!(
+        "{label} is larger than the download size limit ({size} > {max_bytes} bytes); \
+         raise it with --max-download-size if this is expected"
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use futures::stream;
+    use reqwest::Body;
+
+    use super::*;
+
+    /// A response whose length is declared up front.
+    fn sized(body: &'static [u8]) -> Response {
+        Response::from(http::Response::new(Body::from(body)))
+    }
+
+    /// A response sent in `chunks` pieces of `chunk_len` bytes, with no declared length.
+    fn chunked(chunks: usize, chunk_len: usize) -> Response {
+        let pieces = (0..chunks).map(move |_| Ok::<_, std::io::Error>(vec![b'x'; chunk_len]));
+        Response::from(http::Response::new(Body::wrap_stream(stream::iter(pieces))))
+    }
+
+    fn sha256_hex(data: &[u8]) -> String {
+        hex::encode(Sha256::digest(data))
+    }
+
+    #[tokio::test]
+    async fn writes_the_body_and_checks_its_hash() {
+        let mut out = Vec::new();
+        let expected = sha256_hex(b"bottle bytes").to_ascii_uppercase();
+
+        let written =
+            stream_response_to_file(sized(b"bottle bytes"), &mut out, "jq", &expected, 100)
+                .await
+                .unwrap();
+
+        assert_eq!(written, 12);
+        assert_eq!(out, b"bottle bytes");
+    }
+
+    #[tokio::test]
+    async fn a_hash_mismatch_is_a_checksum_error() {
+        let mut out = Vec::new();
+
+        let result = stream_response_to_file(
+            sized(b"bottle bytes"),
+            &mut out,
+            "jq",
+            &sha256_hex(b"x"),
+            100,
+        )
+        .await;
+
+        assert!(
+            matches!(result, Err(SpsError::ChecksumError(_))),
+            "{result:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn no_expected_hash_skips_the_check() {
+        let mut out = Vec::new();
+
+        let written = stream_response_to_file(chunked(3, 1000), &mut out, "jq", "", 10_000)
+            .await
+            .unwrap();
+
+        assert_eq!(written, 3000);
+        assert_eq!(out.len(), 3000);
+    }
+
+    #[tokio::test]
+    async fn a_declared_length_over_the_limit_fails_before_writing() {
+        let mut out = Vec::new();
+
+        let result = stream_response_to_file(sized(b"0123456789"), &mut out, "jq", "", 9).await;
+
+        assert!(
+            matches!(&result, Err(SpsError::ValidationError(msg)) if msg.contains("10 > 9")),
+            "{result:?}"
+        );
+        assert!(out.is_empty());
+    }
+
+    #[tokio::test]
+    async fn an_undeclared_body_over_the_limit_fails_mid_stream() {
+        let mut out = Vec::new();
+
+        let result = stream_response_to_file(chunked(10, 1000), &mut out, "jq", "", 2500).await;
+
+        assert!(
+            matches!(&result, Err(SpsError::ValidationError(msg)) if msg.contains("3000 > 2500")),
+            "{result:?}"
+        );
+        // Stops at the chunk that crossed the limit without writing it.
+        assert_eq!(out.len(), 2000);
+    }
+
+    #[tokio::test]
+    async fn the_task_reporter_sees_the_start_and_the_final_count() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let reporter: progress::Reporter = {
+            let seen = Arc::clone(&seen);
+            Arc::new(move |received, total| seen.lock().unwrap().push((received, total)))
+        };
+        let mut out = Vec::new();
+
+        progress::with_reporter(
+            reporter,
+            stream_response_to_file(sized(b"bottle bytes"), &mut out, "jq", "", 100),
+        )
+        .await
+        .unwrap();
+
+        let seen = seen.lock().unwrap();
+        assert_eq!(seen.first(), Some(&(0, Some(12))));
+        assert_eq!(seen.last(), Some(&(12, Some(12))));
+    }
+}
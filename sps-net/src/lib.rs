@@ -1,5 +1,6 @@
 // spm-fetch/src/lib.rs
 pub mod fetch;
+pub mod signature;
 pub mod validation;
 
 // Re-export necessary types from sps-core IF using Option A from Step 3
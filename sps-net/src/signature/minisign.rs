@@ -0,0 +1,227 @@
+//! minisign public keys and signature files.
+//!
+//! A public key is `Ed`, an 8-byte key id and the 32-byte Ed25519 key. A signature file has an
+//! untrusted comment, then `Ed` (signs the file) or `ED` (signs its BLAKE2b-512), the key id and
+//! the 64-byte signature, then a trusted comment and a second signature over the first one and
+//! that comment. Binary parts are base64.
+
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use blake2::{Blake2b512, Digest};
+use ring::signature::{UnparsedPublicKey, ED25519};
+
+use super::SignatureVerifier;
+
+const KEY_LEN: usize = 2 + 8 + 32;
+const SIGNATURE_LEN: usize = 2 + 8 + 64;
+
+pub struct MinisignKey {
+    key_id: [u8; 8],
+    key: [u8; 32],
+}
+
+impl MinisignKey {
+    /// The key from the decoded second line of a `.pub` file; `None` if it isn't one.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() != KEY_LEN || &bytes[..2] != b"Ed" {
+            return None;
+        }
+        Some(Self {
+            key_id: bytes[2..10].try_into().ok()?,
+            key: bytes[10..].try_into().ok()?,
+        })
+    }
+}
+
+impl SignatureVerifier for MinisignKey {
+    fn describe(&self) -> String {
+        format!("minisign key {}", key_id_text(&self.key_id))
+    }
+
+    fn verify(&self, artifact: &Path, signature: &[u8]) -> Result<(), String> {
+        let not_minisign = || "not a minisign signature".to_string();
+        let text = std::str::from_utf8(signature).map_err(|_| not_minisign())?;
+        let mut lines = text.lines();
+        if !lines
+            .next()
+            .is_some_and(|line| line.starts_with("untrusted comment:"))
+        {
+            return Err(not_minisign());
+        }
+        let sig = lines
+            .next()
+            .and_then(|line| BASE64.decode(line.trim()).ok())
+            .filter(|sig| sig.len() == SIGNATURE_LEN)
+            .ok_or_else(not_minisign)?;
+        let trusted = lines
+            .next()
+            .and_then(|line| line.strip_prefix("trusted comment: "))
+            .ok_or_else(not_minisign)?;
+        let global = lines
+            .next()
+            .and_then(|line| BASE64.decode(line.trim()).ok())
+            .filter(|sig| sig.len() == 64)
+            .ok_or_else(not_minisign)?;
+        if sig[2..10] != self.key_id {
+            return Err(format!(
+                "signed by key {}",
+                key_id_text(sig[2..10].try_into().unwrap())
+            ));
+        }
+        let message = match &sig[..2] {
+            b"ED" => prehash(artifact)?.to_vec(),
+            b"Ed" => std::fs::read(artifact)
+                .map_err(|e| format!("cannot read {}: {e}", artifact.display()))?,
+            other => {
+                return Err(format!(
+                    "unsupported signature algorithm '{}'",
+                    String::from_utf8_lossy(other)
+                ))
+            }
+        };
+        let key = UnparsedPublicKey::new(&ED25519, &self.key);
+        key.verify(&message, &sig[10..])
+            .map_err(|_| "signature does not match".to_string())?;
+        let mut signed_comment = sig[10..].to_vec();
+        signed_comment.extend_from_slice(trusted.as_bytes());
+        key.verify(&signed_comment, &global)
+            .map_err(|_| "trusted comment signature does not match".to_string())?;
+        tracing::debug!("Trusted comment: {}", trusted);
+        Ok(())
+    }
+}
+
+/// The BLAKE2b-512 of the file, streamed.
+fn prehash(artifact: &Path) -> Result<[u8; 64], String> {
+    let mut file =
+        File::open(artifact).map_err(|e| format!("cannot read {}: {e}", artifact.display()))?;
+    let mut hasher = Blake2b512::new();
+    io::copy(&mut file, &mut hasher)
+        .map_err(|e| format!("cannot read {}: {e}", artifact.display()))?;
+    Ok(hasher.finalize().into())
+}
+
+/// As minisign shows key ids: the little-endian number in upper-case hex.
+fn key_id_text(key_id: &[u8; 8]) -> String {
+    format!("{:016X}", u64::from_le_bytes(*key_id))
+}
+
+#[cfg(test)]
+mod tests {
+    use ring::signature::{Ed25519KeyPair, KeyPair};
+
+    use super::*;
+
+    const KEY_ID: [u8; 8] = [1, 2, 3, 4, 5, 6, 7, 8];
+
+    fn keypair() -> Ed25519KeyPair {
+        Ed25519KeyPair::from_seed_unchecked(&[9; 32]).unwrap()
+    }
+
+    fn key() -> MinisignKey {
+        let mut bytes = b"Ed".to_vec();
+        bytes.extend_from_slice(&KEY_ID);
+        bytes.extend_from_slice(keypair().public_key().as_ref());
+        MinisignKey::from_bytes(&bytes).unwrap()
+    }
+
+    /// A legacy (`Ed`) signature file over `data`, with `algorithm` written in its place and
+    /// `shown_comment` as the trusted comment, which is signed as `trusted_comment`.
+    fn signature_file(
+        data: &[u8],
+        algorithm: &[u8],
+        trusted_comment: &str,
+        shown_comment: &str,
+    ) -> String {
+        let pair = keypair();
+        let signature = pair.sign(data);
+        let mut sig = algorithm.to_vec();
+        sig.extend_from_slice(&KEY_ID);
+        sig.extend_from_slice(signature.as_ref());
+        let mut signed_comment = signature.as_ref().to_vec();
+        signed_comment.extend_from_slice(trusted_comment.as_bytes());
+        format!(
+            "untrusted comment: test\n{}\ntrusted comment: {}\n{}\n",
+            BASE64.encode(sig),
+            shown_comment,
+            BASE64.encode(pair.sign(&signed_comment))
+        )
+    }
+
+    fn artifact(data: &[u8]) -> (tempfile::TempDir, std::path::PathBuf) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("artifact");
+        std::fs::write(&path, data).unwrap();
+        (dir, path)
+    }
+
+    #[test]
+    fn only_ed_keys_of_the_right_length_parse() {
+        let mut bytes = b"Ed".to_vec();
+        bytes.extend_from_slice(&[0; 40]);
+        assert!(MinisignKey::from_bytes(&bytes).is_some());
+        assert!(MinisignKey::from_bytes(&bytes[..KEY_LEN - 1]).is_none());
+        bytes[..2].copy_from_slice(b"RW");
+        assert!(MinisignKey::from_bytes(&bytes).is_none());
+    }
+
+    #[test]
+    fn key_ids_read_as_minisign_prints_them() {
+        assert_eq!(key().describe(), "minisign key 0807060504030201");
+    }
+
+    #[test]
+    fn verifies_a_signature_and_its_trusted_comment() {
+        let (_dir, path) = artifact(b"payload");
+        let good = signature_file(b"payload", b"Ed", "file:artifact", "file:artifact");
+        let forged = signature_file(b"payload", b"Ed", "file:artifact", "file:other");
+
+        assert_eq!(key().verify(&path, good.as_bytes()), Ok(()));
+        assert_eq!(
+            key().verify(&path, forged.as_bytes()).unwrap_err(),
+            "trusted comment signature does not match"
+        );
+    }
+
+    #[test]
+    fn a_prehashed_signature_signs_the_blake2b_512_of_the_file() {
+        let (_dir, path) = artifact(b"abc");
+        // The BLAKE2b-512 of "abc" from RFC 7693.
+        let digest = hex::decode(
+            "ba80a53f981c4d0d6a2797b69f12f6e94c212f14685ac4b74b12bb6fdbffa2d1\
+             7d87c5392aab792dc252d5de4533cc9518d38aa8dbf1925ab92386edd4009923",
+        )
+        .unwrap();
+        let prehashed = signature_file(&digest, b"ED", "file:artifact", "file:artifact");
+        let over_the_file = signature_file(b"abc", b"ED", "file:artifact", "file:artifact");
+
+        assert_eq!(key().verify(&path, prehashed.as_bytes()), Ok(()));
+        assert!(key().verify(&path, over_the_file.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn rejects_other_algorithms_and_files_that_are_not_signatures() {
+        let (_dir, path) = artifact(b"payload");
+        let unknown = signature_file(b"payload", b"EX", "c", "c");
+
+        assert_eq!(
+            key().verify(&path, unknown.as_bytes()).unwrap_err(),
+            "unsupported signature algorithm 'EX'"
+        );
+        for text in [
+            "",
+            "untrusted comment: x\nnot base64\n",
+            "trusted comment: x\n",
+        ] {
+            assert_eq!(
+                key().verify(&path, text.as_bytes()).unwrap_err(),
+                "not a minisign signature",
+                "{text:?}"
+            );
+        }
+    }
+}
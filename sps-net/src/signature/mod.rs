@@ -0,0 +1,383 @@
+//! Detached signatures over downloaded artifacts, for mirrors that sign what they serve.
+//!
+//! With `signature_mode` set, a bottle or cask download is only used once a signature by one of
+//! `signature_keys` verifies over its bytes. The signature is fetched from the artifact's URL with
+//! `.minisig`, else `.sig`, appended, and kept beside the cached artifact so reinstalling from the
+//! cache works offline. Two kinds of key are understood, each a [`SignatureVerifier`]:
+//! - minisign public keys, for `minisign -S` signatures (prehashed or not);
+//! - raw Ed25519 keys, for a bare 64-byte signature of the whole file (raw, hex or base64).
+//!
+//! GPG signatures are not supported.
+
+mod minisign;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use reqwest::StatusCode;
+use ring::signature::{UnparsedPublicKey, ED25519};
+use sps_common::config::{Config, SignatureMode};
+use sps_common::error::{Result, SpsError};
+use tracing::{debug, warn};
+
+pub use self::minisign::MinisignKey;
+
+/// Appended to an artifact's URL (and cache path) to find its signature, in order.
+pub const SIGNATURE_SUFFIXES: &[&str] = &[".minisig", ".sig"];
+/// Signature files are a few hundred bytes; anything much larger isn't one.
+const MAX_SIGNATURE_SIZE: usize = 64 * 1024;
+
+/// A public key that signatures over artifacts can be checked against.
+pub trait SignatureVerifier: Send + Sync {
+    /// The key, for messages: its kind and an identifier.
+    fn describe(&self) -> String;
+
+    /// Checks `signature`, the contents of a signature file, over the file at `artifact`.
+    fn verify(&self, artifact: &Path, signature: &[u8]) -> std::result::Result<(), String>;
+}
+
+/// A raw Ed25519 public key; signatures are over the whole file.
+pub struct Ed25519Key {
+    key: [u8; 32],
+}
+
+impl Ed25519Key {
+    pub fn new(key: [u8; 32]) -> Self {
+        Self { key }
+    }
+}
+
+impl SignatureVerifier for Ed25519Key {
+    fn describe(&self) -> String {
+        format!("Ed25519 key {}", hex::encode(&self.key[..8]))
+    }
+
+    fn verify(&self, artifact: &Path, signature: &[u8]) -> std::result::Result<(), String> {
+        let signature = decode_raw_signature(signature)
+            .ok_or_else(|| "not a raw Ed25519 signature".to_string())?;
+        let data =
+            fs::read(artifact).map_err(|e| format!("cannot read {}: {e}", artifact.display()))?;
+        UnparsedPublicKey::new(&ED25519, &self.key)
+            .verify(&data, &signature)
+            .map_err(|_| "signature does not match".to_string())
+    }
+}
+
+/// The 64 signature bytes of a raw signature file: the bytes themselves, or hex or base64 text.
+fn decode_raw_signature(signature: &[u8]) -> Option<Vec<u8>> {
+    if signature.len() == 64 {
+        return Some(signature.to_vec());
+    }
+    let text = std::str::from_utf8(signature).ok()?.trim();
+    hex::decode(text)
+        .ok()
+        .or_else(|| BASE64.decode(text).ok())
+        .filter(|bytes| bytes.len() == 64)
+}
+
+/// Parses one `signature_keys` entry: a minisign public key, a raw Ed25519 key in hex or
+/// base64, or the path of a file holding either (such as minisign's `.pub` file).
+pub fn parse_key(entry: &str) -> Result<Box<dyn SignatureVerifier>> {
+    let path = Path::new(entry);
+    let text = if path.is_file() {
+        let contents = fs::read_to_string(path).map_err(|e| {
+            SpsError::Config(format!(
+                "Cannot read signature key file {}: {e}",
+                path.display()
+            ))
+        })?;
+        contents
+            .lines()
+            .map(str::trim)
+            .find(|line| !line.is_empty() && !line.starts_with("untrusted comment:"))
+            .unwrap_or_default()
+            .to_string()
+    } else {
+        entry.trim().to_string()
+    };
+    if text.len() == 64 {
+        if let Ok(bytes) = hex::decode(&text) {
+            return Ok(Box::new(Ed25519Key::new(bytes.try_into().unwrap())));
+        }
+    }
+    match BASE64.decode(&text) {
+        Ok(bytes) if bytes.len() == 32 => Ok(Box::new(Ed25519Key::new(bytes.try_into().unwrap()))),
+        Ok(bytes) => MinisignKey::from_bytes(&bytes)
+            .map(|key| Box::new(key) as Box<dyn SignatureVerifier>)
+            .ok_or_else(|| invalid_key(entry)),
+        Err(_) => Err(invalid_key(entry)),
+    }
+}
+
+fn invalid_key(entry: &str) -> SpsError {
+    SpsError::Config(format!(
+        "Signature key '{entry}' is neither a minisign public key nor a raw Ed25519 key"
+    ))
+}
+
+/// The verifiers for `config.signature_keys`.
+pub fn verifiers_from_config(config: &Config) -> Result<Vec<Box<dyn SignatureVerifier>>> {
+    config
+        .signature_keys
+        .iter()
+        .map(|entry| parse_key(entry))
+        .collect()
+}
+
+/// Checks the signature of the artifact downloaded from `url` to `artifact`, as
+/// `config.signature_mode` says. Under `require` a missing or bad signature is a
+/// [`SpsError::SignatureInvalid`]; under `warn` it is only logged.
+pub async fn verify_artifact(
+    artifact: &Path,
+    url: &str,
+    label: &str,
+    config: &Config,
+) -> Result<()> {
+    if config.signature_mode == SignatureMode::Off {
+        return Ok(());
+    }
+    let verifiers = verifiers_from_config(config)?;
+    let outcome = if verifiers.is_empty() {
+        Err("signature_mode is set but no signature_keys are configured".to_string())
+    } else {
+        signature_outcome(&verifiers, artifact, url).await?
+    };
+    match outcome {
+        Ok(key) => {
+            debug!("Signature of {} verified with {}", label, key);
+            Ok(())
+        }
+        Err(reason) if config.signature_mode == SignatureMode::Warn => {
+            warn!("Signature check failed for {}: {}", label, reason);
+            Ok(())
+        }
+        Err(reason) => Err(SpsError::SignatureInvalid(format!("{label}: {reason}"))),
+    }
+}
+
+/// The key that signed `artifact`, or why none did. A cached signature is tried first; one
+/// that doesn't verify (say the artifact was downloaded again since) is replaced by the
+/// server's. A fetched signature that doesn't verify isn't kept.
+async fn signature_outcome(
+    verifiers: &[Box<dyn SignatureVerifier>],
+    artifact: &Path,
+    url: &str,
+) -> Result<std::result::Result<String, String>> {
+    for suffix in SIGNATURE_SUFFIXES {
+        let path = signature_path(artifact, suffix);
+        let Ok(signature) = fs::read(&path) else {
+            continue;
+        };
+        match check(verifiers, artifact, &signature) {
+            Ok(key) => return Ok(Ok(key)),
+            Err(reason) => {
+                debug!(
+                    "Cached signature {} does not verify ({}); fetching it again",
+                    path.display(),
+                    reason
+                );
+                let _ = fs::remove_file(&path);
+            }
+        }
+    }
+    let Some((path, signature)) = fetch_signature(artifact, url).await? else {
+        return Ok(Err(format!(
+            "no signature found at {url}{}",
+            SIGNATURE_SUFFIXES.join(" or ")
+        )));
+    };
+    let outcome = check(verifiers, artifact, &signature);
+    if outcome.is_err() {
+        let _ = fs::remove_file(&path);
+    }
+    Ok(outcome)
+}
+
+/// The first key that accepts `signature`, or why none did.
+fn check(
+    verifiers: &[Box<dyn SignatureVerifier>],
+    artifact: &Path,
+    signature: &[u8],
+) -> std::result::Result<String, String> {
+    let mut reasons = Vec::new();
+    for verifier in verifiers {
+        match verifier.verify(artifact, signature) {
+            Ok(()) => return Ok(verifier.describe()),
+            Err(reason) => reasons.push(format!("{}: {reason}", verifier.describe())),
+        }
+    }
+    Err(reasons.join("; "))
+}
+
+/// Where the signature with `suffix` is cached: beside `artifact`.
+fn signature_path(artifact: &Path, suffix: &str) -> PathBuf {
+    let mut name = artifact.file_name().unwrap_or_default().to_os_string();
+    name.push(suffix);
+    artifact.with_file_name(name)
+}
+
+/// The first signature found at `url` plus a suffix, cached beside `artifact`. `None` when the
+/// server has none.
+async fn fetch_signature(artifact: &Path, url: &str) -> Result<Option<(PathBuf, Vec<u8>)>> {
+    let client = crate::fetch::http::build_http_client()?;
+    for suffix in SIGNATURE_SUFFIXES {
+        let signature_url = format!("{url}{suffix}");
+        crate::validation::validate_url(&signature_url)?;
+        debug!("Fetching signature {}", signature_url);
+        let response = client.get(&signature_url).send().await.map_err(|e| {
+            SpsError::HttpError(format!("HTTP request failed for {signature_url}: {e}"))
+        })?;
+        match response.status() {
+            StatusCode::NOT_FOUND | StatusCode::FORBIDDEN => continue,
+            status if !status.is_success() => {
+                return Err(SpsError::HttpError(format!(
+                    "HTTP error {status} for URL {signature_url}"
+                )))
+            }
+            _ => {}
+        }
+        if response
+            .content_length()
+            .is_some_and(|len| len > MAX_SIGNATURE_SIZE as u64)
+        {
+            return Err(oversized(&signature_url));
+        }
+        let signature = response
+            .bytes()
+            .await
+            .map_err(|e| SpsError::HttpError(format!("Failed to read {signature_url}: {e}")))?;
+        if signature.len() > MAX_SIGNATURE_SIZE {
+            return Err(oversized(&signature_url));
+        }
+        let path = signature_path(artifact, suffix);
+        if let Err(e) = fs::write(&path, &signature) {
+            debug!("Could not cache signature {}: {}", path.display(), e);
+        }
+        return Ok(Some((path, signature.to_vec())));
+    }
+    Ok(None)
+}
+
+fn oversized(url: &str) -> SpsError {
+    SpsError::SignatureInvalid(format!(
+        "{url} is larger than {MAX_SIGNATURE_SIZE} bytes, which no signature is"
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use ring::signature::{Ed25519KeyPair, KeyPair};
+
+    use super::*;
+
+    fn keypair(seed: u8) -> Ed25519KeyPair {
+        Ed25519KeyPair::from_seed_unchecked(&[seed; 32]).unwrap()
+    }
+
+    fn artifact(data: &[u8]) -> (tempfile::TempDir, PathBuf) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("jq-1.7.1.bottle.tar.gz");
+        fs::write(&path, data).unwrap();
+        (dir, path)
+    }
+
+    #[test]
+    fn raw_signatures_are_read_as_bytes_hex_or_base64() {
+        let signature = [7u8; 64];
+
+        assert_eq!(decode_raw_signature(&signature).unwrap(), signature);
+        let hex_text = format!("{}\n", hex::encode(signature));
+        assert_eq!(
+            decode_raw_signature(hex_text.as_bytes()).unwrap(),
+            signature
+        );
+        let base64_text = BASE64.encode(signature);
+        assert_eq!(
+            decode_raw_signature(base64_text.as_bytes()).unwrap(),
+            signature
+        );
+        assert!(decode_raw_signature(hex::encode([7u8; 48]).as_bytes()).is_none());
+        assert!(decode_raw_signature(b"not a signature").is_none());
+    }
+
+    #[test]
+    fn keys_parse_from_hex_base64_minisign_and_key_files() {
+        let public = keypair(1).public_key().as_ref().to_vec();
+        let mut minisign = b"Ed".to_vec();
+        minisign.extend_from_slice(&[0x42; 8]);
+        minisign.extend_from_slice(&public);
+        let dir = tempfile::tempdir().unwrap();
+        let key_file = dir.path().join("mirror.pub");
+        fs::write(
+            &key_file,
+            format!(
+                "untrusted comment: minisign public key\n\n{}\n",
+                BASE64.encode(&minisign)
+            ),
+        )
+        .unwrap();
+
+        let described = |entry: &str| parse_key(entry).unwrap().describe();
+        let ed25519 = format!("Ed25519 key {}", hex::encode(&public[..8]));
+        assert_eq!(described(&hex::encode(&public)), ed25519);
+        assert_eq!(described(&format!(" {} ", BASE64.encode(&public))), ed25519);
+        assert_eq!(
+            described(&BASE64.encode(&minisign)),
+            "minisign key 4242424242424242"
+        );
+        assert_eq!(
+            described(key_file.to_str().unwrap()),
+            "minisign key 4242424242424242"
+        );
+    }
+
+    #[test]
+    fn unparseable_keys_are_config_errors() {
+        for entry in ["", "zz", &BASE64.encode([0u8; 20]), "/no/such/key.pub"] {
+            match parse_key(entry) {
+                Err(SpsError::Config(msg)) => assert!(msg.contains("neither"), "{msg}"),
+                other => panic!(
+                    "expected a config error for {entry:?}, got {:?}",
+                    other.err()
+                ),
+            }
+        }
+    }
+
+    #[test]
+    fn the_first_key_that_accepts_a_signature_wins_and_failures_name_every_key() {
+        let (_dir, path) = artifact(b"bottle");
+        let signer = keypair(2);
+        let signature = signer.sign(b"bottle");
+        let verifiers: Vec<Box<dyn SignatureVerifier>> = vec![
+            Box::new(Ed25519Key::new(
+                keypair(1).public_key().as_ref().try_into().unwrap(),
+            )),
+            Box::new(Ed25519Key::new(
+                signer.public_key().as_ref().try_into().unwrap(),
+            )),
+        ];
+
+        assert_eq!(
+            check(&verifiers, &path, signature.as_ref()),
+            Ok(verifiers[1].describe())
+        );
+        let reasons = check(&verifiers[..1], &path, signature.as_ref()).unwrap_err();
+        assert_eq!(
+            reasons,
+            format!("{}: signature does not match", verifiers[0].describe())
+        );
+        let reasons = check(&verifiers, &path, b"junk").unwrap_err();
+        assert_eq!(reasons.matches("not a raw Ed25519 signature").count(), 2);
+    }
+
+    #[test]
+    fn signatures_are_kept_beside_the_artifact() {
+        assert_eq!(
+            signature_path(Path::new("/cache/jq.tar.gz"), ".minisig"),
+            Path::new("/cache/jq.tar.gz.minisig")
+        );
+    }
+}
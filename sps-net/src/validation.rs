@@ -2,23 +2,29 @@ use std::fs::File;
 use std::io;
 use std::path::Path;
 
+use hex;
+use infer;
 use sha2::{Digest, Sha256};
 use sps_common::error::{Result, SpsError};
 use url::Url;
-use {hex, infer};
 
-pub fn verify_checksum(path: &Path, expected: &str) -> Result<()> {
-    tracing::debug!("Verifying checksum for: {}", path.display());
+/// The hex-encoded SHA256 of the file at `path`.
+pub fn sha256_file(path: &Path) -> Result<String> {
     let mut file = File::open(path)?;
     let mut hasher = Sha256::new();
     let bytes_copied = io::copy(&mut file, &mut hasher)?;
-    let hash_bytes = hasher.finalize();
-    let actual = hex::encode(hash_bytes);
+    let actual = hex::encode(hasher.finalize());
     tracing::debug!(
         "Calculated SHA256: {} ({} bytes read)",
         actual,
         bytes_copied
     );
+    Ok(actual)
+}
+
+pub fn verify_checksum(path: &Path, expected: &str) -> Result<()> {
+    tracing::debug!("Verifying checksum for: {}", path.display());
+    let actual = sha256_file(path)?;
     tracing::debug!("Expected SHA256:   {}", expected);
     if actual.eq_ignore_ascii_case(expected) {
         Ok(())
@@ -60,11 +66,12 @@ pub fn verify_content_type(path: &Path, expected_ext: &str) -> Result<()> {
     }
 }
 
-/// Validates a URL, ensuring it uses the HTTPS scheme.
+/// Validates a URL, ensuring it uses the HTTPS scheme. Plain HTTP is accepted only for loopback
+/// hosts, where nothing crosses the network: local mirrors and the test fixture server.
 pub fn validate_url(url_str: &str) -> Result<()> {
     let url = Url::parse(url_str)
         .map_err(|e| SpsError::Generic(format!("Failed to parse URL '{url_str}': {e}")))?;
-    if url.scheme() == "https" {
+    if url.scheme() == "https" || (url.scheme() == "http" && is_loopback(&url)) {
         Ok(())
     } else {
         Err(SpsError::ValidationError(format!(
@@ -74,3 +81,38 @@ pub fn validate_url(url_str: &str) -> Result<()> {
         )))
     }
 }
+
+fn is_loopback(url: &Url) -> bool {
+    match url.host() {
+        Some(url::Host::Domain(domain)) => domain.eq_ignore_ascii_case("localhost"),
+        Some(url::Host::Ipv4(ip)) => ip.is_loopback(),
+        Some(url::Host::Ipv6(ip)) => ip.is_loopback(),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validate_url_requires_https_except_on_loopback() {
+        for ok in [
+            "https://ghcr.io/v2/homebrew/core/jq/blobs/sha256:00",
+            "http://127.0.0.1:8080/bottles/jq.tar.gz",
+            "http://localhost/api/formula.json",
+            "http://[::1]:9000/x",
+        ] {
+            assert!(validate_url(ok).is_ok(), "{ok} should be accepted");
+        }
+        for bad in [
+            "http://example.com/jq.tar.gz",
+            "http://127.0.0.1.example.com/x",
+            "ftp://127.0.0.1/x",
+            "file:///etc/passwd",
+            "not a url",
+        ] {
+            assert!(validate_url(bad).is_err(), "{bad} should be rejected");
+        }
+    }
+}
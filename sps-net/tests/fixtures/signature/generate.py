@@ -0,0 +1,70 @@
+"""Regenerates the signature fixtures: an artifact, minisign and raw Ed25519 public keys, and
+signatures over the artifact. Keys come from fixed seeds, so the output is stable.
+
+Needs the `cryptography` package. Run from this directory: python3 generate.py
+"""
+
+import base64
+import hashlib
+import struct
+
+from cryptography.hazmat.primitives import serialization
+from cryptography.hazmat.primitives.asymmetric.ed25519 import Ed25519PrivateKey
+
+ARTIFACT = b"jq 1.7.1 bottle contents\n" * 40
+
+
+def keypair(seed):
+    secret = Ed25519PrivateKey.from_private_bytes(bytes([seed]) * 32)
+    public = secret.public_key().public_bytes(
+        serialization.Encoding.Raw, serialization.PublicFormat.Raw
+    )
+    return secret, public
+
+
+def write_minisign_key(name, seed, key_id):
+    secret, public = keypair(seed)
+    key_id_text = "%016X" % struct.unpack("<Q", key_id)[0]
+    with open(name, "w") as f:
+        f.write(f"untrusted comment: minisign public key {key_id_text}\n")
+        f.write(base64.b64encode(b"Ed" + key_id + public).decode() + "\n")
+    return secret
+
+
+def minisign_signature(secret, key_id, prehash, trusted_comment):
+    message = hashlib.blake2b(ARTIFACT).digest() if prehash else ARTIFACT
+    signature = secret.sign(message)
+    global_signature = secret.sign(signature + trusted_comment.encode())
+    algorithm = b"ED" if prehash else b"Ed"
+    return (
+        "untrusted comment: signature from minisign secret key\n"
+        + base64.b64encode(algorithm + key_id + signature).decode() + "\n"
+        + f"trusted comment: {trusted_comment}\n"
+        + base64.b64encode(global_signature).decode() + "\n"
+    )
+
+
+def main():
+    with open("artifact.txt", "wb") as f:
+        f.write(ARTIFACT)
+
+    key_id = bytes([0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88])
+    secret = write_minisign_key("minisign.pub", 1, key_id)
+    with open("artifact.txt.minisig", "w") as f:
+        f.write(minisign_signature(
+            secret, key_id, True, "timestamp:1700000000\tfile:artifact.txt\thashed"))
+    with open("artifact.txt.legacy.minisig", "w") as f:
+        f.write(minisign_signature(
+            secret, key_id, False, "timestamp:1700000000\tfile:artifact.txt"))
+
+    write_minisign_key("other-minisign.pub", 2, bytes([0xAA] * 8))
+
+    secret, public = keypair(3)
+    with open("ed25519.hex", "w") as f:
+        f.write(public.hex() + "\n")
+    with open("artifact.txt.sig", "wb") as f:
+        f.write(secret.sign(ARTIFACT))
+
+
+if __name__ == "__main__":
+    main()
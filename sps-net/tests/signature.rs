@@ -0,0 +1,277 @@
+//! Signature verification against the keys and signatures in `fixtures/signature`, made by
+//! `generate.py` with an independent Ed25519 and BLAKE2b implementation, and the fetch and cache
+//! behavior of `verify_artifact` against a mock mirror.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use sps_common::config::{Config, SignatureMode};
+use sps_common::error::SpsError;
+use sps_net::signature::{parse_key, verify_artifact};
+use sps_testkit::{MockServer, Response};
+
+const ARTIFACT_PATH: &str = "/bottles/artifact.txt";
+
+fn fixture(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures/signature")
+        .join(name)
+}
+
+fn read(name: &str) -> Vec<u8> {
+    fs::read(fixture(name)).unwrap()
+}
+
+fn key(name: &str) -> String {
+    fixture(name).to_str().unwrap().to_string()
+}
+
+/// A copy of the fixture artifact in a fresh cache directory, as a download leaves it.
+fn downloaded(contents: &[u8]) -> (tempfile::TempDir, PathBuf) {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("artifact.txt");
+    fs::write(&path, contents).unwrap();
+    (dir, path)
+}
+
+fn config(dir: &Path, mode: SignatureMode, keys: &[String]) -> Config {
+    Config {
+        prefix: dir.to_path_buf(),
+        cellar: dir.join("Cellar"),
+        cache_dir: dir.join("cache"),
+        signature_mode: mode,
+        signature_keys: keys.to_vec(),
+        ..Config::load().unwrap()
+    }
+}
+
+#[test]
+fn fixture_signatures_verify_with_their_keys() {
+    let (_dir, artifact) = downloaded(&read("artifact.txt"));
+    let minisign = parse_key(&key("minisign.pub")).unwrap();
+    let ed25519 = parse_key(&key("ed25519.hex")).unwrap();
+
+    assert_eq!(minisign.describe(), "minisign key 8877665544332211");
+    assert_eq!(
+        minisign.verify(&artifact, &read("artifact.txt.minisig")),
+        Ok(())
+    );
+    assert_eq!(
+        minisign.verify(&artifact, &read("artifact.txt.legacy.minisig")),
+        Ok(())
+    );
+    assert_eq!(ed25519.verify(&artifact, &read("artifact.txt.sig")), Ok(()));
+    // The key also parses from the file's contents, not just its path.
+    let hex = fs::read_to_string(fixture("ed25519.hex")).unwrap();
+    let inline = parse_key(hex.trim()).unwrap();
+    assert_eq!(inline.verify(&artifact, &read("artifact.txt.sig")), Ok(()));
+}
+
+#[test]
+fn fixture_signatures_fail_over_changed_bytes() {
+    let mut changed = read("artifact.txt");
+    changed[0] ^= 1;
+    let (_dir, artifact) = downloaded(&changed);
+    let minisign = parse_key(&key("minisign.pub")).unwrap();
+    let ed25519 = parse_key(&key("ed25519.hex")).unwrap();
+
+    for signature in ["artifact.txt.minisig", "artifact.txt.legacy.minisig"] {
+        assert_eq!(
+            minisign.verify(&artifact, &read(signature)).unwrap_err(),
+            "signature does not match",
+            "{signature}"
+        );
+    }
+    assert_eq!(
+        ed25519
+            .verify(&artifact, &read("artifact.txt.sig"))
+            .unwrap_err(),
+        "signature does not match"
+    );
+}
+
+#[test]
+fn fixture_signatures_fail_with_other_keys() {
+    let (_dir, artifact) = downloaded(&read("artifact.txt"));
+    let other = parse_key(&key("other-minisign.pub")).unwrap();
+    let minisign = parse_key(&key("minisign.pub")).unwrap();
+    let ed25519 = parse_key(&key("ed25519.hex")).unwrap();
+
+    assert_eq!(
+        other
+            .verify(&artifact, &read("artifact.txt.minisig"))
+            .unwrap_err(),
+        "signed by key 8877665544332211"
+    );
+    assert_eq!(
+        minisign
+            .verify(&artifact, &read("artifact.txt.sig"))
+            .unwrap_err(),
+        "not a minisign signature"
+    );
+    assert_eq!(
+        ed25519
+            .verify(&artifact, &read("artifact.txt.minisig"))
+            .unwrap_err(),
+        "not a raw Ed25519 signature"
+    );
+}
+
+#[test]
+fn a_tampered_trusted_comment_fails() {
+    let (_dir, artifact) = downloaded(&read("artifact.txt"));
+    let minisign = parse_key(&key("minisign.pub")).unwrap();
+    let signature = String::from_utf8(read("artifact.txt.minisig"))
+        .unwrap()
+        .replace("file:artifact.txt", "file:other.txt");
+
+    assert_eq!(
+        minisign
+            .verify(&artifact, signature.as_bytes())
+            .unwrap_err(),
+        "trusted comment signature does not match"
+    );
+}
+
+#[tokio::test]
+async fn off_checks_nothing_and_fetches_nothing() {
+    let server = MockServer::start();
+    let (dir, artifact) = downloaded(b"unsigned");
+    let config = config(dir.path(), SignatureMode::Off, &[]);
+
+    verify_artifact(&artifact, &server.url(ARTIFACT_PATH), "jq", &config)
+        .await
+        .unwrap();
+
+    assert!(server.requests().is_empty());
+}
+
+#[tokio::test]
+async fn a_signature_from_the_mirror_is_verified_and_cached() {
+    let server = MockServer::start();
+    let signature_path = format!("{ARTIFACT_PATH}.minisig");
+    server.serve(&signature_path, Response::ok(read("artifact.txt.minisig")));
+    let (dir, artifact) = downloaded(&read("artifact.txt"));
+    let config = config(dir.path(), SignatureMode::Require, &[key("minisign.pub")]);
+    let url = server.url(ARTIFACT_PATH);
+
+    verify_artifact(&artifact, &url, "jq", &config)
+        .await
+        .unwrap();
+    verify_artifact(&artifact, &url, "jq", &config)
+        .await
+        .unwrap();
+
+    assert_eq!(
+        server.hits(&signature_path),
+        1,
+        "the second check is offline"
+    );
+    assert_eq!(
+        fs::read(dir.path().join("artifact.txt.minisig")).unwrap(),
+        read("artifact.txt.minisig")
+    );
+}
+
+#[tokio::test]
+async fn a_raw_signature_is_fetched_when_there_is_no_minisig() {
+    let server = MockServer::start();
+    server.serve(
+        &format!("{ARTIFACT_PATH}.sig"),
+        Response::ok(read("artifact.txt.sig")),
+    );
+    let (dir, artifact) = downloaded(&read("artifact.txt"));
+    let keys = [key("minisign.pub"), key("ed25519.hex")];
+    let config = config(dir.path(), SignatureMode::Require, &keys);
+
+    verify_artifact(&artifact, &server.url(ARTIFACT_PATH), "jq", &config)
+        .await
+        .unwrap();
+
+    assert_eq!(
+        server.requests(),
+        [
+            format!("{ARTIFACT_PATH}.minisig"),
+            format!("{ARTIFACT_PATH}.sig")
+        ]
+    );
+}
+
+#[tokio::test]
+async fn a_missing_signature_fails_under_require_and_is_logged_under_warn() {
+    let server = MockServer::start();
+    let (dir, artifact) = downloaded(&read("artifact.txt"));
+    let keys = [key("minisign.pub")];
+    let url = server.url(ARTIFACT_PATH);
+
+    let require = config(dir.path(), SignatureMode::Require, &keys);
+    match verify_artifact(&artifact, &url, "jq", &require).await {
+        Err(SpsError::SignatureInvalid(msg)) => {
+            assert!(msg.starts_with("jq: no signature found at"), "{msg}")
+        }
+        other => panic!("expected SignatureInvalid, got {other:?}"),
+    }
+    let warn = config(dir.path(), SignatureMode::Warn, &keys);
+    verify_artifact(&artifact, &url, "jq", &warn).await.unwrap();
+}
+
+#[tokio::test]
+async fn a_bad_signature_from_the_mirror_fails_and_is_not_kept() {
+    let server = MockServer::start();
+    server.serve(
+        &format!("{ARTIFACT_PATH}.minisig"),
+        Response::ok(read("artifact.txt.minisig")),
+    );
+    let (dir, artifact) = downloaded(&read("artifact.txt"));
+    let config = config(
+        dir.path(),
+        SignatureMode::Require,
+        &[key("other-minisign.pub")],
+    );
+
+    match verify_artifact(&artifact, &server.url(ARTIFACT_PATH), "jq", &config).await {
+        Err(SpsError::SignatureInvalid(msg)) => assert!(
+            msg.ends_with("minisign key AAAAAAAAAAAAAAAA: signed by key 8877665544332211"),
+            "{msg}"
+        ),
+        other => panic!("expected SignatureInvalid, got {other:?}"),
+    }
+    assert!(!dir.path().join("artifact.txt.minisig").exists());
+}
+
+#[tokio::test]
+async fn a_stale_cached_signature_is_replaced_by_the_mirrors() {
+    let server = MockServer::start();
+    let signature_path = format!("{ARTIFACT_PATH}.minisig");
+    server.serve(&signature_path, Response::ok(read("artifact.txt.minisig")));
+    let (dir, artifact) = downloaded(&read("artifact.txt"));
+    let cached = dir.path().join("artifact.txt.minisig");
+    // A signature over an earlier download of the same artifact.
+    let stale = String::from_utf8(read("artifact.txt.minisig"))
+        .unwrap()
+        .replace("hashed", "stale");
+    fs::write(&cached, stale).unwrap();
+    let config = config(dir.path(), SignatureMode::Require, &[key("minisign.pub")]);
+
+    verify_artifact(&artifact, &server.url(ARTIFACT_PATH), "jq", &config)
+        .await
+        .unwrap();
+
+    assert_eq!(server.hits(&signature_path), 1);
+    assert_eq!(fs::read(&cached).unwrap(), read("artifact.txt.minisig"));
+}
+
+#[tokio::test]
+async fn require_without_keys_fails() {
+    let server = MockServer::start();
+    let (dir, artifact) = downloaded(&read("artifact.txt"));
+    let config = config(dir.path(), SignatureMode::Require, &[]);
+
+    match verify_artifact(&artifact, &server.url(ARTIFACT_PATH), "jq", &config).await {
+        Err(SpsError::SignatureInvalid(msg)) => {
+            assert!(msg.contains("no signature_keys are configured"), "{msg}")
+        }
+        other => panic!("expected SignatureInvalid, got {other:?}"),
+    }
+    assert!(server.requests().is_empty());
+}
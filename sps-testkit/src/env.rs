@@ -0,0 +1,95 @@
+// sps-testkit/src/env.rs
+//! A throwaway home, cache and prefix for running the `sps` binary against a [`MockServer`].
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+use tempfile::TempDir;
+
+use crate::fixture::Fixtures;
+use crate::server::MockServer;
+
+#[derive(Debug)]
+pub struct TestEnv {
+    root: TempDir,
+    pub server: MockServer,
+}
+
+impl TestEnv {
+    /// Starts a server publishing `fixtures` and creates an empty prefix, laid out the way an
+    /// installed prefix is so the pipeline's preflight checks pass.
+    pub fn new(fixtures: &Fixtures) -> Self {
+        let server = MockServer::start();
+        fixtures.publish(&server);
+        let root = tempfile::tempdir().expect("create test root");
+        for dir in [
+            "home",
+            "cache",
+            "config",
+            "prefix/Cellar",
+            "prefix/Caskroom",
+            "prefix/opt",
+            "prefix/bin",
+        ] {
+            fs::create_dir_all(root.path().join(dir)).expect("create test dir");
+        }
+        Self { root, server }
+    }
+
+    pub fn prefix(&self) -> PathBuf {
+        self.root.path().join("prefix")
+    }
+
+    pub fn cellar(&self) -> PathBuf {
+        self.prefix().join("Cellar")
+    }
+
+    pub fn cache_dir(&self) -> PathBuf {
+        self.root.path().join("cache").join("sps")
+    }
+
+    /// The keg `Cellar/<name>/<version>`.
+    pub fn keg(&self, name: &str, version: &str) -> PathBuf {
+        self.cellar().join(name).join(version)
+    }
+
+    pub fn bin(&self, name: &str) -> PathBuf {
+        self.prefix().join("bin").join(name)
+    }
+
+    /// `program` (the `sps` binary) with an environment that keeps it inside this env: its own
+    /// HOME, XDG dirs and prefix, the API pointed at the server, and no auto-update.
+    pub fn command(&self, program: impl AsRef<Path>) -> Command {
+        let root = self.root.path();
+        let mut command = Command::new(program.as_ref());
+        command
+            .env_clear()
+            .env("PATH", std::env::var_os("PATH").unwrap_or_default())
+            .env("HOME", root.join("home"))
+            .env("XDG_CACHE_HOME", root.join("cache"))
+            .env("XDG_CONFIG_HOME", root.join("config"))
+            .env("XDG_DATA_HOME", root.join("home"))
+            .env("TMPDIR", std::env::temp_dir())
+            .env("sps_PREFIX", self.prefix())
+            .env("sps_NO_AUTO_UPDATE", "1")
+            .env("HOMEBREW_API_DOMAIN", Fixtures::api_domain(&self.server))
+            .env("NO_COLOR", "1");
+        command
+    }
+
+    /// Runs `program` with `args` and returns its output.
+    pub fn run(&self, program: impl AsRef<Path>, args: &[&str]) -> Output {
+        self.command(program).args(args).output().expect("run sps")
+    }
+}
+
+/// Stdout and stderr of `output`, for assertion messages.
+pub fn describe(output: &Output) -> String {
+    format!(
+        "status: {}\n--- stdout ---\n{}\n--- stderr ---\n{}",
+        output.status,
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    )
+}
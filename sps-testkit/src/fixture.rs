@@ -0,0 +1,442 @@
+// sps-testkit/src/fixture.rs
+//! Formulae and casks shaped like the Homebrew API's JSON, with bottles built on the fly, served
+//! from a [`MockServer`] at the paths sps requests them from.
+//!
+//! The API is served under `/api` (point `HOMEBREW_API_DOMAIN` at [`Fixtures::api_domain`]) and
+//! bottles and cask archives under `/bottles` and `/casks`. Bottles carry the `all` tag, which
+//! pours on any host.
+
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::time::Duration;
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+
+use crate::server::{MockServer, Response};
+
+/// How a formula's bottle is served.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BottleServing {
+    Ok,
+    /// The bottle URL answers 404.
+    Missing,
+    /// The bottle is served, but its bytes don't match the sha256 in the API.
+    ChecksumMismatch,
+}
+
+#[derive(Debug, Clone)]
+pub struct FormulaFixture {
+    pub name: String,
+    pub version: String,
+    pub dependencies: Vec<String>,
+    /// Extra files in the keg, by path relative to it; `bin/<name>` is always there.
+    pub files: BTreeMap<String, Vec<u8>>,
+    pub bottle: BottleServing,
+    /// Held back before the bottle is answered, to keep downloads in flight.
+    pub bottle_delay: Duration,
+    /// Extra top-level fields merged into the API JSON, e.g. `disabled`.
+    pub extra: Value,
+}
+
+impl FormulaFixture {
+    pub fn new(name: &str, version: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            version: version.to_string(),
+            dependencies: Vec::new(),
+            files: BTreeMap::new(),
+            bottle: BottleServing::Ok,
+            bottle_delay: Duration::ZERO,
+            extra: json!({}),
+        }
+    }
+
+    pub fn depends_on(mut self, deps: &[&str]) -> Self {
+        self.dependencies.extend(deps.iter().map(|d| d.to_string()));
+        self
+    }
+
+    pub fn file(mut self, path: &str, contents: impl Into<Vec<u8>>) -> Self {
+        self.files.insert(path.to_string(), contents.into());
+        self
+    }
+
+    pub fn bottle(mut self, serving: BottleServing) -> Self {
+        self.bottle = serving;
+        self
+    }
+
+    pub fn bottle_delay(mut self, delay: Duration) -> Self {
+        self.bottle_delay = delay;
+        self
+    }
+
+    pub fn extra(mut self, fields: Value) -> Self {
+        self.extra = fields;
+        self
+    }
+
+    /// `user/repo/name` for a fixture given a `tap` other than core in [`Self::extra`], the
+    /// short name otherwise; its API JSON is served under this name.
+    pub fn full_name(&self) -> String {
+        match self.extra.get("tap").and_then(Value::as_str) {
+            Some(tap) if tap != "homebrew/core" => format!("{tap}/{}", self.name),
+            _ => self.name.clone(),
+        }
+    }
+
+    /// Server path of the bottle.
+    pub fn bottle_path(&self) -> String {
+        format!("/bottles/{}-{}.all.bottle.tar.gz", self.name, self.version)
+    }
+
+    /// The bottle: a gzipped tarball of `<name>/<version>/…`, as Homebrew lays them out.
+    pub fn bottle_bytes(&self) -> Vec<u8> {
+        let mut files = self.files.clone();
+        files
+            .entry(format!("bin/{}", self.name))
+            .or_insert_with(|| format!("#!/bin/sh\necho {} {}\n", self.name, self.version).into());
+        let root = format!("{}/{}", self.name, self.version);
+        tarball(
+            files
+                .iter()
+                .map(|(path, data)| (format!("{root}/{path}"), data.as_slice())),
+        )
+    }
+
+    /// The formula's API JSON as [`Fixtures::publish`] serves it from `base_url`, for a bottle
+    /// with the given bytes.
+    pub fn api_json(&self, base_url: &str, bottle: &[u8]) -> Value {
+        let sha256 = match self.bottle {
+            BottleServing::ChecksumMismatch => sha256_hex(b"not the bottle"),
+            _ => sha256_hex(bottle),
+        };
+        let mut value = json!({
+            "name": self.name,
+            "full_name": self.name,
+            "tap": "homebrew/core",
+            "desc": format!("{} test fixture", self.name),
+            "homepage": "https://example.com",
+            "versions": { "stable": self.version, "head": null, "bottle": true },
+            "urls": { "stable": {
+                "url": format!("https://example.com/{}-{}.tar.gz", self.name, self.version),
+                "checksum": sha256_hex(self.name.as_bytes())
+            } },
+            "revision": 0,
+            "bottle": { "stable": {
+                "rebuild": 0,
+                "root_url": format!("{base_url}/bottles"),
+                "files": { "all": {
+                    "cellar": ":any_skip_relocation",
+                    "url": format!("{}{}", base_url, self.bottle_path()),
+                    "sha256": sha256
+                } }
+            } },
+            "keg_only": false,
+            "dependencies": self.dependencies,
+            "build_dependencies": [],
+            "test_dependencies": [],
+            "recommended_dependencies": [],
+            "optional_dependencies": [],
+            "requirements": [],
+            "conflicts_with": [],
+            "caveats": null,
+            "deprecated": false,
+            "disabled": false
+        });
+        if let (Some(target), Some(extra)) = (value.as_object_mut(), self.extra.as_object()) {
+            target.extend(extra.clone());
+        }
+        value
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CaskFixture {
+    pub token: String,
+    pub version: String,
+    /// Formulae the cask depends on.
+    pub formula_dependencies: Vec<String>,
+    /// Platform variations: a bottle-style tag and the version published for it, with its own
+    /// archive.
+    pub platform_versions: Vec<(String, String)>,
+    /// Languages offered besides the default `en`, each with its own archive.
+    pub languages: Vec<String>,
+    /// Publish `sha256 "no_check"` instead of the archive's digest.
+    pub skip_checksum: bool,
+    /// Serve the archive as a disk image instead of a tarball.
+    pub dmg: bool,
+}
+
+impl CaskFixture {
+    pub fn new(token: &str, version: &str) -> Self {
+        Self {
+            token: token.to_string(),
+            version: version.to_string(),
+            formula_dependencies: Vec::new(),
+            platform_versions: Vec::new(),
+            languages: Vec::new(),
+            skip_checksum: false,
+            dmg: false,
+        }
+    }
+
+    pub fn depends_on_formulae(mut self, deps: &[&str]) -> Self {
+        self.formula_dependencies
+            .extend(deps.iter().map(|d| d.to_string()));
+        self
+    }
+
+    /// Publishes `version` for the `platform` tag, as a `variations` entry.
+    pub fn platform_variation(mut self, platform: &str, version: &str) -> Self {
+        self.platform_versions
+            .push((platform.to_string(), version.to_string()));
+        self
+    }
+
+    /// Offers `language` besides `en`, as a `language_variations` entry with its own archive.
+    pub fn language(mut self, language: &str) -> Self {
+        self.languages.push(language.to_string());
+        self
+    }
+
+    /// Publishes the cask with `sha256 "no_check"`, so a cached archive is used unverified.
+    pub fn without_checksum(mut self) -> Self {
+        self.skip_checksum = true;
+        self
+    }
+
+    /// Serves the archive as a disk image built with `hdiutil`, so only on macOS.
+    pub fn dmg(mut self) -> Self {
+        self.dmg = true;
+        self
+    }
+
+    /// Server path of the cask's archive.
+    pub fn archive_path(&self) -> String {
+        self.variant_archive_path(&self.version)
+    }
+
+    /// Server path of the archive of a platform variation's version or an offered language.
+    pub fn variant_archive_path(&self, label: &str) -> String {
+        let extension = if self.dmg { "dmg" } else { "tar.gz" };
+        format!("/casks/{}-{}.{}", self.token, label, extension)
+    }
+
+    /// A tarball (or disk image) holding a single executable named after the cask, installed as
+    /// its `binary`.
+    pub fn archive_bytes(&self) -> Vec<u8> {
+        self.variant_archive_bytes(&self.version)
+    }
+
+    /// Like [`CaskFixture::archive_bytes`], for the executable printing `label` instead of the
+    /// version.
+    pub fn variant_archive_bytes(&self, label: &str) -> Vec<u8> {
+        let script = format!("#!/bin/sh\necho {} {}\n", self.token, label);
+        if self.dmg {
+            return disk_image(&self.token, script.as_bytes());
+        }
+        tarball([(self.token.clone(), script.as_bytes())])
+    }
+
+    /// The labels of every archive besides the default one: platform versions and languages.
+    fn variant_labels(&self) -> impl Iterator<Item = &str> {
+        self.platform_versions
+            .iter()
+            .map(|(_, version)| version.as_str())
+            .chain(self.languages.iter().map(String::as_str))
+    }
+
+    fn published_sha256(&self, archive: &[u8]) -> String {
+        if self.skip_checksum {
+            "no_check".to_string()
+        } else {
+            sha256_hex(archive)
+        }
+    }
+
+    /// The cask's API JSON as [`Fixtures::publish`] serves it from `base_url`, for an archive
+    /// with the given bytes.
+    pub fn api_json(&self, base_url: &str, archive: &[u8]) -> Value {
+        let mut depends_on = json!({});
+        if !self.formula_dependencies.is_empty() {
+            depends_on = json!({ "formula": self.formula_dependencies });
+        }
+        let source = |label: &str| {
+            json!({
+                "url": format!("{}{}", base_url, self.variant_archive_path(label)),
+                "sha256": self.published_sha256(&self.variant_archive_bytes(label)),
+            })
+        };
+        let variations: serde_json::Map<String, Value> = self
+            .platform_versions
+            .iter()
+            .map(|(platform, version)| {
+                let mut variation = source(version);
+                variation["version"] = json!(version);
+                (platform.clone(), variation)
+            })
+            .collect();
+        let language_variations: serde_json::Map<String, Value> = self
+            .languages
+            .iter()
+            .map(|language| (language.clone(), source(language)))
+            .collect();
+        // `en` is the default archive; it is only listed alongside other languages.
+        let mut languages: Vec<&str> = self.languages.iter().map(String::as_str).collect();
+        if !languages.is_empty() {
+            languages.insert(0, "en");
+        }
+        json!({
+            "token": self.token,
+            "full_token": self.token,
+            "tap": "homebrew/cask",
+            "name": [self.token],
+            "desc": format!("{} test fixture", self.token),
+            "homepage": "https://example.com",
+            "url": format!("{}{}", base_url, self.archive_path()),
+            "version": self.version,
+            "sha256": self.published_sha256(archive),
+            "artifacts": [ { "binary": [self.token] } ],
+            "depends_on": depends_on,
+            "conflicts_with": null,
+            "caveats": null,
+            "auto_updates": null,
+            "deprecated": false,
+            "disabled": false,
+            "variations": variations,
+            "languages": languages,
+            "language_variations": language_variations
+        })
+    }
+}
+
+/// A set of formulae and casks to publish on a server.
+#[derive(Debug, Default)]
+pub struct Fixtures {
+    pub formulae: Vec<FormulaFixture>,
+    pub casks: Vec<CaskFixture>,
+}
+
+impl Fixtures {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn formula(mut self, formula: FormulaFixture) -> Self {
+        self.formulae.push(formula);
+        self
+    }
+
+    pub fn cask(mut self, cask: CaskFixture) -> Self {
+        self.casks.push(cask);
+        self
+    }
+
+    /// The value for `HOMEBREW_API_DOMAIN`.
+    pub fn api_domain(server: &MockServer) -> String {
+        server.url("/api")
+    }
+
+    /// Serves the API indexes, the per-package JSON, and the bottles and archives. Publishing
+    /// again replaces what an earlier call served under the same paths.
+    pub fn publish(&self, server: &MockServer) {
+        let base_url = server.base_url();
+        let mut formula_index = Vec::new();
+        for formula in &self.formulae {
+            let bottle = formula.bottle_bytes();
+            let value = formula.api_json(&base_url, &bottle);
+            server.serve(
+                &format!("/api/formula/{}.json", formula.full_name()),
+                Response::json(&value),
+            );
+            formula_index.push(value);
+            let response = match formula.bottle {
+                BottleServing::Missing => Response::status(404),
+                _ => Response::ok(bottle),
+            };
+            server.serve(&formula.bottle_path(), response.delay(formula.bottle_delay));
+        }
+        let mut cask_index = Vec::new();
+        for cask in &self.casks {
+            let archive = cask.archive_bytes();
+            let value = cask.api_json(&base_url, &archive);
+            server.serve(
+                &format!("/api/cask/{}.json", cask.token),
+                Response::json(&value),
+            );
+            cask_index.push(value);
+            server.serve(&cask.archive_path(), Response::ok(archive));
+            for label in cask.variant_labels() {
+                server.serve(
+                    &cask.variant_archive_path(label),
+                    Response::ok(cask.variant_archive_bytes(label)),
+                );
+            }
+        }
+        // Tagged by content, so publishing a changed set on the same server invalidates the
+        // copies sps cached.
+        for (path, index) in [
+            ("/api/formula.json", formula_index),
+            ("/api/cask.json", cask_index),
+        ] {
+            let index = Value::Array(index);
+            let etag = format!("\"{}\"", &sha256_hex(index.to_string().as_bytes())[..16]);
+            server.serve(path, Response::json(&index).etag(etag));
+        }
+    }
+}
+
+pub fn sha256_hex(data: &[u8]) -> String {
+    hex::encode(Sha256::digest(data))
+}
+
+/// A compressed disk image holding one executable `name`, built with `hdiutil`.
+pub fn disk_image(name: &str, contents: &[u8]) -> Vec<u8> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = tempfile::tempdir().expect("create image dir");
+    let source = dir.path().join("source");
+    std::fs::create_dir(&source).expect("create image source");
+    let file = source.join(name);
+    std::fs::write(&file, contents).expect("write image file");
+    std::fs::set_permissions(&file, std::fs::Permissions::from_mode(0o755)).expect("chmod");
+    let image = dir.path().join("image.dmg");
+    let status = std::process::Command::new("hdiutil")
+        .args([
+            "create",
+            "-quiet",
+            "-format",
+            "UDZO",
+            "-volname",
+            name,
+            "-srcfolder",
+        ])
+        .arg(&source)
+        .arg(&image)
+        .status()
+        .expect("run hdiutil");
+    assert!(status.success(), "hdiutil create failed");
+    std::fs::read(image).expect("read disk image")
+}
+
+/// A gzipped tarball of regular files, all mode 0755 so scripts stay executable.
+pub fn tarball<'a, P: AsRef<str>>(files: impl IntoIterator<Item = (P, &'a [u8])>) -> Vec<u8> {
+    let mut builder = tar::Builder::new(GzEncoder::new(Vec::new(), Compression::fast()));
+    for (path, data) in files {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o755);
+        header.set_mtime(0);
+        header.set_entry_type(tar::EntryType::Regular);
+        builder
+            .append_data(&mut header, path.as_ref(), data)
+            .expect("append fixture file");
+    }
+    let mut gz = builder.into_inner().expect("finish fixture tarball");
+    gz.flush().expect("flush fixture tarball");
+    gz.finish().expect("finish fixture gzip stream")
+}
@@ -0,0 +1,10 @@
+// sps-testkit/src/lib.rs
+//! Test support for sps: a mock Homebrew API and bottle server, fixture formulae and casks, and
+//! isolated prefixes to install into.
+pub mod env;
+pub mod fixture;
+pub mod server;
+
+pub use env::{describe, TestEnv};
+pub use fixture::{BottleServing, CaskFixture, Fixtures, FormulaFixture};
+pub use server::{MockServer, Response};
@@ -0,0 +1,357 @@
+// sps-testkit/src/server.rs
+//! A small HTTP/1.1 server on a loopback port, answering GET and HEAD requests from a table of
+//! canned responses and counting the requests each path receives.
+//!
+//! Every connection is served on its own thread and closed after one response, which keeps the
+//! parsing trivial; reqwest opens a new connection when its pooled one has been closed.
+
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// A canned response. Built with [`Response::ok`], [`Response::json`] or [`Response::status`].
+#[derive(Debug, Clone)]
+pub struct Response {
+    status: u16,
+    body: Vec<u8>,
+    content_type: &'static str,
+    etag: Option<String>,
+    delay: Duration,
+    /// Sends the body in pieces of this size, pausing between them.
+    throttle: Option<(usize, Duration)>,
+}
+
+impl Response {
+    pub fn ok(body: impl Into<Vec<u8>>) -> Self {
+        Self {
+            status: 200,
+            body: body.into(),
+            content_type: "application/octet-stream",
+            etag: None,
+            delay: Duration::ZERO,
+            throttle: None,
+        }
+    }
+
+    pub fn json(value: &serde_json::Value) -> Self {
+        Self {
+            content_type: "application/json",
+            ..Self::ok(value.to_string())
+        }
+    }
+
+    /// An empty response with the given status, e.g. 404 or 500.
+    pub fn status(status: u16) -> Self {
+        Self {
+            status,
+            ..Self::ok(Vec::new())
+        }
+    }
+
+    /// Sends this `ETag`, and answers 304 to requests whose `If-None-Match` matches it.
+    pub fn etag(mut self, etag: impl Into<String>) -> Self {
+        self.etag = Some(etag.into());
+        self
+    }
+
+    /// Waits this long before answering.
+    pub fn delay(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+
+    /// Sends the body `chunk` bytes at a time with `pause` in between, so a download stays in
+    /// flight long enough to be interrupted.
+    pub fn throttle(mut self, chunk: usize, pause: Duration) -> Self {
+        self.throttle = Some((chunk.max(1), pause));
+        self
+    }
+}
+
+/// One request as the server saw it.
+#[derive(Debug)]
+struct Request {
+    path: String,
+    arrived: Instant,
+    /// When the response was fully written; `None` while it is in flight.
+    answered: Option<Instant>,
+    not_modified: bool,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    routes: HashMap<String, Response>,
+    /// Requests in the order they arrived.
+    log: Vec<Request>,
+}
+
+/// The server; it stops when dropped.
+#[derive(Debug)]
+pub struct MockServer {
+    addr: SocketAddr,
+    state: Arc<Mutex<State>>,
+    stop: Arc<AtomicBool>,
+}
+
+impl MockServer {
+    pub fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind mock server");
+        let addr = listener.local_addr().expect("mock server address");
+        let state = Arc::new(Mutex::new(State::default()));
+        let stop = Arc::new(AtomicBool::new(false));
+        let (accept_state, accept_stop) = (Arc::clone(&state), Arc::clone(&stop));
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                if accept_stop.load(Ordering::SeqCst) {
+                    break;
+                }
+                let Ok(stream) = stream else { continue };
+                let state = Arc::clone(&accept_state);
+                thread::spawn(move || serve_connection(stream, &state));
+            }
+        });
+        Self { addr, state, stop }
+    }
+
+    /// `http://127.0.0.1:<port>`, without a trailing slash.
+    pub fn base_url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    /// The full URL of `path`, which starts with `/`.
+    pub fn url(&self, path: &str) -> String {
+        format!("{}{}", self.base_url(), path)
+    }
+
+    /// Answers requests for `path` with `response` from now on.
+    pub fn serve(&self, path: &str, response: Response) {
+        self.lock().routes.insert(path.to_string(), response);
+    }
+
+    /// How many requests `path` has received.
+    pub fn hits(&self, path: &str) -> usize {
+        self.lock().log.iter().filter(|r| r.path == path).count()
+    }
+
+    /// How many requests for `path` were answered 304 Not Modified.
+    pub fn not_modified(&self, path: &str) -> usize {
+        self.lock()
+            .log
+            .iter()
+            .filter(|r| r.path == path && r.not_modified)
+            .count()
+    }
+
+    /// When `path` was first requested.
+    pub fn first_request_at(&self, path: &str) -> Option<Instant> {
+        self.lock()
+            .log
+            .iter()
+            .find(|r| r.path == path)
+            .map(|r| r.arrived)
+    }
+
+    /// Every path requested so far, in order.
+    pub fn requests(&self) -> Vec<String> {
+        self.lock().log.iter().map(|r| r.path.clone()).collect()
+    }
+
+    /// The most requests for paths starting with `prefix` that were in flight at once, from
+    /// arrival until their response was written.
+    pub fn peak_in_flight(&self, prefix: &str) -> usize {
+        let now = Instant::now();
+        // On a tie a departure (`false`) sorts before an arrival, so back-to-back requests
+        // don't count as overlapping.
+        let mut edges: Vec<(Instant, bool)> = self
+            .lock()
+            .log
+            .iter()
+            .filter(|r| r.path.starts_with(prefix))
+            .flat_map(|r| [(r.arrived, true), (r.answered.unwrap_or(now), false)])
+            .collect();
+        edges.sort();
+        let (mut current, mut peak) = (0usize, 0);
+        for (_, arrival) in edges {
+            if arrival {
+                current += 1;
+                peak = peak.max(current);
+            } else {
+                current -= 1;
+            }
+        }
+        peak
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        // Wakes the accept loop so it sees the flag.
+        let _ = TcpStream::connect(self.addr);
+    }
+}
+
+fn serve_connection(stream: TcpStream, state: &Mutex<State>) {
+    let mut reader = BufReader::new(match stream.try_clone() {
+        Ok(s) => s,
+        Err(_) => return,
+    });
+    let mut request_line = String::new();
+    if reader.read_line(&mut request_line).unwrap_or(0) == 0 {
+        return;
+    }
+    let mut if_none_match = None;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).unwrap_or(0) == 0 || line.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("if-none-match") {
+                if_none_match = Some(value.trim().to_string());
+            }
+        }
+    }
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or_default().to_string();
+    // Query strings are ignored for routing.
+    let path = parts
+        .next()
+        .unwrap_or("/")
+        .split('?')
+        .next()
+        .unwrap_or("/")
+        .to_string();
+
+    let (response, not_modified, index) = {
+        let mut state = state.lock().unwrap_or_else(|e| e.into_inner());
+        let response = state
+            .routes
+            .get(&path)
+            .cloned()
+            .unwrap_or_else(|| Response::status(404));
+        let not_modified = response.etag.is_some() && response.etag == if_none_match;
+        state.log.push(Request {
+            path: path.clone(),
+            arrived: Instant::now(),
+            answered: None,
+            not_modified,
+        });
+        (response, not_modified, state.log.len() - 1)
+    };
+    respond(stream, &method, &response, not_modified);
+    let mut state = state.lock().unwrap_or_else(|e| e.into_inner());
+    state.log[index].answered = Some(Instant::now());
+}
+
+fn respond(stream: TcpStream, method: &str, response: &Response, not_modified: bool) {
+    if !response.delay.is_zero() {
+        thread::sleep(response.delay);
+    }
+    let (status, body): (u16, &[u8]) = if not_modified {
+        (304, &[])
+    } else {
+        (response.status, &response.body)
+    };
+    let mut head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n",
+        status,
+        reason(status),
+        response.content_type,
+        body.len()
+    );
+    if let Some(etag) = &response.etag {
+        head.push_str(&format!("ETag: {etag}\r\n"));
+    }
+    head.push_str("\r\n");
+
+    let mut stream = stream;
+    if stream.write_all(head.as_bytes()).is_err() || method == "HEAD" {
+        return;
+    }
+    match response.throttle {
+        Some((chunk, pause)) => {
+            for piece in body.chunks(chunk) {
+                if stream
+                    .write_all(piece)
+                    .and_then(|_| stream.flush())
+                    .is_err()
+                {
+                    return;
+                }
+                thread::sleep(pause);
+            }
+        }
+        None => {
+            let _ = stream.write_all(body);
+        }
+    }
+    let _ = stream.flush();
+    let _ = stream.shutdown(Shutdown::Write);
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        304 => "Not Modified",
+        404 => "Not Found",
+        500 => "Internal Server Error",
+        _ => "Status",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use super::*;
+
+    fn get(server: &MockServer, path: &str, extra_headers: &str) -> String {
+        let mut stream = TcpStream::connect(server.addr).unwrap();
+        write!(
+            stream,
+            "GET {path} HTTP/1.1\r\nHost: x\r\n{extra_headers}\r\n"
+        )
+        .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn serves_routes_answers_404_otherwise_and_counts_hits() {
+        let server = MockServer::start();
+        server.serve("/a", Response::ok("hello"));
+
+        let found = get(&server, "/a?x=1", "");
+        let missing = get(&server, "/b", "");
+
+        assert!(found.starts_with("HTTP/1.1 200"), "{found}");
+        assert!(found.ends_with("\r\n\r\nhello"), "{found}");
+        assert!(missing.starts_with("HTTP/1.1 404"), "{missing}");
+        assert_eq!(server.hits("/a"), 1);
+        assert_eq!(server.requests(), ["/a", "/b"]);
+    }
+
+    #[test]
+    fn answers_304_when_the_etag_matches() {
+        let server = MockServer::start();
+        server.serve("/index", Response::ok("body").etag("\"v1\""));
+
+        let fresh = get(&server, "/index", "If-None-Match: \"v1\"\r\n");
+        let stale = get(&server, "/index", "If-None-Match: \"v0\"\r\n");
+
+        assert!(fresh.starts_with("HTTP/1.1 304"), "{fresh}");
+        assert!(stale.starts_with("HTTP/1.1 200"), "{stale}");
+        assert_eq!(server.hits("/index"), 2);
+        assert_eq!(server.not_modified("/index"), 1);
+    }
+}
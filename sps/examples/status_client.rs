@@ -0,0 +1,86 @@
+//! Minimal consumer of `sps install --status-socket PATH`: connects, then prints one line per
+//! event until the install finishes.
+//!
+//! ```sh
+//! sps install --status-socket /tmp/sps.sock wget &
+//! cargo run --example status_client -- /tmp/sps.sock
+//! ```
+
+use std::io::{BufRead, BufReader};
+use std::os::unix::net::UnixStream;
+use std::time::Duration;
+use std::{env, process, thread};
+
+use serde_json::Value;
+
+fn main() {
+    let Some(path) = env::args().nth(1) else {
+        eprintln!("usage: status_client <socket-path>");
+        process::exit(2);
+    };
+
+    // The socket appears once planning is done; wait briefly for it.
+    let stream = (0..50)
+        .find_map(|_| {
+            UnixStream::connect(&path)
+                .map_err(|_| thread::sleep(Duration::from_millis(200)))
+                .ok()
+        })
+        .unwrap_or_else(|| {
+            eprintln!("could not connect to {path}");
+            process::exit(1);
+        });
+
+    for line in BufReader::new(stream).lines() {
+        let Ok(line) = line else { break };
+        let Ok(event) = serde_json::from_str::<Value>(&line) else {
+            eprintln!("unparseable event: {line}");
+            continue;
+        };
+        let field = |key: &str| event[key].as_str().unwrap_or_default().to_string();
+        match field("event").as_str() {
+            "status" => {
+                for node in event["nodes"].as_array().into_iter().flatten() {
+                    println!(
+                        "{:<24} {:<8} {:<10} {}",
+                        node["name"].as_str().unwrap_or_default(),
+                        node["kind"].as_str().unwrap_or_default(),
+                        node["action"].as_str().unwrap_or_default(),
+                        node["state"].as_str().unwrap_or_default()
+                    );
+                }
+            }
+            "started" | "finished" => {
+                println!("{} {} {}", field("event"), field("phase"), field("name"))
+            }
+            "failed" => println!(
+                "failed {} {}: {}",
+                field("phase"),
+                field("name"),
+                field("error")
+            ),
+            "progress" => println!(
+                "progress {} {}/{}",
+                field("name"),
+                event["downloaded"],
+                event["total"]
+            ),
+            "pour" => println!(
+                "pour {} {}/{} files, {} bytes",
+                field("name"),
+                event["files"],
+                event["total_files"],
+                event["bytes"]
+            ),
+            "skipped" => println!("skipped {}", field("name")),
+            "done" => {
+                println!(
+                    "done: {} succeeded, {} failed",
+                    event["succeeded"], event["failed"]
+                );
+                break;
+            }
+            other => println!("{other}: {line}"),
+        }
+    }
+}
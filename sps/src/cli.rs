@@ -1,26 +1,86 @@
 //! Defines the command-line argument structure using clap.
+use std::path::PathBuf;
 use std::sync::Arc;
 
 use clap::{ArgAction, Parser, Subcommand};
 use sps_common::error::Result;
 use sps_common::{Cache, Config};
 
+use crate::cli::adopt::Adopt;
+use crate::cli::api::Api;
+use crate::cli::autoremove::Autoremove;
+use crate::cli::bug_report::BugReport;
+use crate::cli::cache::CacheArgs;
+use crate::cli::cleanup::Cleanup;
+use crate::cli::create::Create;
+use crate::cli::deps::Deps;
+use crate::cli::doctor::Doctor;
+use crate::cli::files::{Files, Owner};
 use crate::cli::info::Info;
 use crate::cli::install::InstallArgs;
+use crate::cli::list::List;
+use crate::cli::missing::Missing;
+use crate::cli::options::Options;
+use crate::cli::outdated::Outdated;
+use crate::cli::overrides::OverrideArgs;
+use crate::cli::pin::{Pin, Unpin};
+use crate::cli::prefix::{CaskroomPath, CellarPath, Prefix};
 use crate::cli::reinstall::ReinstallArgs;
+use crate::cli::resolve::Resolve;
 use crate::cli::search::Search;
+use crate::cli::self_update::SelfUpdate;
+use crate::cli::shellenv::Shellenv;
+use crate::cli::stats::Stats;
+use crate::cli::switch::Switch;
+use crate::cli::test::Test;
 use crate::cli::uninstall::Uninstall;
+use crate::cli::unpack::Unpack;
 use crate::cli::update::Update;
 use crate::cli::upgrade::UpgradeArgs;
+use crate::cli::verify::Verify;
+use crate::cli::which::Which;
+use crate::ui::ColorChoice;
 
+pub mod adopt;
+pub mod api;
+pub mod autoremove;
+pub mod bug_report;
+pub mod cache;
+pub mod changes;
+pub mod cleanup;
+pub mod create;
+pub mod deps;
+pub mod doctor;
+pub mod files;
+pub mod graph;
 pub mod info;
 pub mod install;
+pub mod list;
+pub mod missing;
+pub mod options;
+pub mod outdated;
+pub mod output;
+pub mod overrides;
+pub mod path_check;
+pub mod pin;
 pub mod pipeline;
+pub mod plan;
+pub mod prefix;
 pub mod reinstall;
+pub mod resolve;
 pub mod search;
+pub mod self_update;
+pub mod shellenv;
+pub mod stats;
+pub mod status;
+pub mod switch;
+pub mod test;
 pub mod uninstall;
+pub mod unpack;
 pub mod update;
 pub mod upgrade;
+pub mod verify;
+pub mod which;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None, name = "sps", bin_name = "sps")]
@@ -30,10 +90,103 @@ pub struct CliArgs {
     #[arg(short, long, action = ArgAction::Count, global = true)]
     pub verbose: u8,
 
+    /// Store downloaded bottles, sources and cask archives here instead of the cache directory
+    #[arg(long, value_name = "PATH", global = true)]
+    pub download_dir: Option<PathBuf>,
+
+    /// Environment for builds and installer subprocesses: a minimal controlled one (std) or the
+    /// caller's own (inherit)
+    #[arg(long, value_name = "MODE", value_parser = ["std", "inherit"], global = true)]
+    pub env: Option<String>,
+
+    /// Refuse downloads larger than this (e.g. 500M, 30G; default 20G)
+    #[arg(long, value_name = "SIZE", value_parser = parse_download_size, global = true)]
+    pub max_download_size: Option<u64>,
+
+    /// Packages to install in parallel: a positive number or `auto` (default)
+    #[arg(long, value_name = "N|auto", value_parser = parse_concurrent_installs, global = true)]
+    pub max_concurrent_installs: Option<usize>,
+
+    /// Artifacts to download in parallel, ahead of the installs (default: as many as installs)
+    #[arg(long, value_name = "N|auto", value_parser = parse_concurrent_installs, global = true)]
+    pub max_concurrent_downloads: Option<usize>,
+
+    /// When to color output: auto (terminals only, honoring NO_COLOR and CLICOLOR_FORCE),
+    /// always or never
+    #[arg(long, value_name = "WHEN", value_enum, default_value_t, global = true)]
+    pub color: ColorChoice,
+
+    /// Print ASCII status marks instead of emoji (implied by --color never)
+    #[arg(long, global = true)]
+    pub no_emoji: bool,
+
+    /// Print tables (search, outdated, info, stats) tab-separated, without a header, padding or
+    /// truncation, as when stdout isn't a terminal
+    #[arg(long, global = true)]
+    pub plain: bool,
+
+    /// Don't run the `[hooks]` commands from the config file for this run
+    #[arg(long, global = true)]
+    pub no_hooks: bool,
+
+    /// After installing, run a few of each formula's linked executables with `--version` and
+    /// fail the install if they can't start
+    #[arg(long, global = true)]
+    pub verify_run: bool,
+
+    /// Fail before downloading anything if a formula in the plan has no bottle for this host,
+    /// instead of building it from source
+    #[arg(long, global = true)]
+    pub bottle_only: bool,
+
+    /// Install packages with HIGH or CRITICAL advisories although `advisories = block`; they are
+    /// still listed
+    #[arg(long, global = true)]
+    pub allow_vulnerable: bool,
+
+    /// Pour bottles of this tag (e.g. `arm64_sonoma`) instead of detecting the host's; a bottle
+    /// for exactly this tag wins over an `all` one
+    #[arg(long, value_name = "TAG", global = true)]
+    pub bottle_tag: Option<String>,
+
+    /// Preferred cask languages, most preferred first (e.g. `de,en-GB`; default: system locale)
+    #[arg(long, value_name = "LANG[,LANG...]", global = true)]
+    pub language: Option<String>,
+
+    /// For install, upgrade and reinstall: print events to stdout as JSON, one object per line,
+    /// and all other output to stderr
+    #[arg(
+        long,
+        global = true,
+        long_help = "For install, upgrade and reinstall: print events to stdout as JSON, one \
+object per line, as they happen; all other output goes to stderr.
+
+Every object has a `type`:
+  plan      version (1), nodes: [{name, kind, action, state}] - once resolution is complete
+  phase     name, phase (download|install|null), state (started|finished|failed|skipped),
+            error (failures only)
+  progress  name, phase, unit (bytes|files), current, total (null if unknown) - at most one
+            per package per second
+  summary   succeeded, failed, skipped, nodes: [{name, kind, action, state, error?}],
+            targets: [{name, state, failed?}]
+  changes   installed: [{name, version}], upgraded: [{name, from, to}],
+            new_bins: [{name, shadows}], disk_delta - after the summary, if anything changed"
+    )]
+    pub json_lines: bool,
+
     #[command(subcommand)]
     pub command: Command,
 }
 
+fn parse_download_size(s: &str) -> std::result::Result<u64, String> {
+    sps_common::config::parse_size(s).ok_or_else(|| format!("invalid size '{s}'"))
+}
+
+fn parse_concurrent_installs(s: &str) -> std::result::Result<usize, String> {
+    sps_common::config::parse_concurrency(s)
+        .ok_or_else(|| format!("expected a positive number or 'auto', got '{s}'"))
+}
+
 #[derive(Subcommand, Debug)]
 pub enum Command {
     /// Search for available formulas and casks
@@ -51,11 +204,108 @@ pub enum Command {
     /// Uninstall one or more formulas or casks
     Uninstall(Uninstall),
 
+    /// List installed formulas and casks
+    #[command(alias = "ls")]
+    List(List),
+
+    /// Uninstall formulas installed only as dependencies of packages that are gone
+    Autoremove(Autoremove),
+
     /// Reinstall one or more formulas or casks
     Reinstall(ReinstallArgs),
 
     /// Upgrade one or more formulas or casks
     Upgrade(UpgradeArgs),
+
+    /// List installed formulas and casks that have a newer version available
+    Outdated(Outdated),
+
+    /// Make another installed version of a formula the current one, without downloading
+    Switch(Switch),
+
+    /// Keep an installed keg through `cleanup`
+    Pin(Pin),
+
+    /// Let `cleanup` remove a keg again
+    Unpin(Unpin),
+
+    /// Take over the kegs of a Homebrew installation instead of reinstalling them
+    Adopt(Adopt),
+
+    /// Remove kegs kept from earlier versions once they are past the retention window, and
+    /// incomplete kegs an install moved aside
+    Cleanup(Cleanup),
+
+    /// List a formula's options, per-platform variations and related formulae
+    Options(Options),
+
+    /// Print the parsed model of a formula or cask as JSON
+    Api(Api),
+
+    /// Check installed kegs against the file manifest recorded at pour time
+    Verify(Verify),
+
+    /// Inspect the download and metadata cache
+    Cache(CacheArgs),
+
+    /// Show installed formulae whose runtime dependencies are missing
+    Missing(Missing),
+
+    /// Show which installed keg provides an executable in the prefix
+    Which(Which),
+
+    /// List the files a formula's keg was poured with
+    Files(Files),
+
+    /// Show which keg owns a path under the prefix
+    Owner(Owner),
+
+    /// Run the tests of an installed formula, installing its test dependencies first
+    Test(Test),
+
+    /// Manage local formula/cask definitions that take precedence over the API
+    Override(OverrideArgs),
+
+    /// Scaffold a formula or cask definition in a local tap
+    Create(Create),
+
+    /// List the dependencies of formulae, or print them as a DOT or Mermaid graph
+    Deps(Deps),
+
+    /// Show how the resolver plans an install, node by node, without downloading anything
+    Resolve(Resolve),
+
+    /// Show local install/upgrade counts and install time per package (`metrics = local`)
+    Stats(Stats),
+
+    /// Check the prefix and PATH for problems, such as system commands shadowing installed ones
+    Doctor(Doctor),
+
+    /// Write a redacted diagnostics report for filing an issue to the current directory
+    #[command(alias = "gist")]
+    BugReport(BugReport),
+
+    /// Download and extract a bottle or cask artifact without installing it
+    #[command(alias = "extract")]
+    Unpack(Unpack),
+
+    /// Print the prefix, or the opt path of an installed formula
+    #[command(long_flag = "prefix")]
+    Prefix(Prefix),
+
+    /// Print the Cellar, or the keg directory of an installed formula
+    #[command(long_flag = "cellar")]
+    Cellar(CellarPath),
+
+    /// Print the Caskroom, or the version directory of an installed cask
+    #[command(long_flag = "caskroom")]
+    Caskroom(CaskroomPath),
+
+    /// Print shell commands that put the prefix on PATH, MANPATH and INFOPATH
+    Shellenv(Shellenv),
+
+    /// Replace this sps binary with the latest release, after verifying its checksum
+    SelfUpdate(SelfUpdate),
 }
 
 impl Command {
@@ -66,8 +316,38 @@ impl Command {
             Self::Update(command) => command.run(config, cache).await,
             Self::Install(command) => command.run(config, cache).await,
             Self::Uninstall(command) => command.run(config, cache).await,
+            Self::List(command) => command.run(config, cache).await,
+            Self::Autoremove(command) => command.run(config, cache).await,
             Self::Reinstall(command) => command.run(config, cache).await,
             Self::Upgrade(command) => command.run(config, cache).await,
+            Self::Outdated(command) => command.run(config, cache).await,
+            Self::Switch(command) => command.run(config, cache).await,
+            Self::Pin(command) => command.run(config, cache).await,
+            Self::Unpin(command) => command.run(config, cache).await,
+            Self::Adopt(command) => command.run(config, cache).await,
+            Self::Cleanup(command) => command.run(config, cache).await,
+            Self::Options(command) => command.run(config, cache).await,
+            Self::Api(command) => command.run(config, cache).await,
+            Self::Verify(command) => command.run(config, cache).await,
+            Self::Cache(command) => command.run(config, cache).await,
+            Self::Missing(command) => command.run(config, cache).await,
+            Self::Which(command) => command.run(config, cache).await,
+            Self::Files(command) => command.run(config, cache).await,
+            Self::Owner(command) => command.run(config, cache).await,
+            Self::Test(command) => command.run(config, cache).await,
+            Self::Override(command) => command.run(config, cache).await,
+            Self::Create(command) => command.run(config, cache).await,
+            Self::Deps(command) => command.run(config, cache).await,
+            Self::Resolve(command) => command.run(config, cache).await,
+            Self::Stats(command) => command.run(config, cache).await,
+            Self::Doctor(command) => command.run(config, cache).await,
+            Self::BugReport(command) => command.run(config, cache).await,
+            Self::Unpack(command) => command.run(config, cache).await,
+            Self::Prefix(command) => command.run(config, cache).await,
+            Self::Cellar(command) => command.run(config, cache).await,
+            Self::Caskroom(command) => command.run(config, cache).await,
+            Self::Shellenv(command) => command.run(config, cache).await,
+            Self::SelfUpdate(command) => command.run(config, cache).await,
         }
     }
 }
@@ -0,0 +1,162 @@
+//! Contains the logic for the `adopt` command: taking over kegs another installation (such as
+//! Homebrew) poured, so they are upgraded and uninstalled like sps's own.
+
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use clap::Args;
+use colored::Colorize;
+use sps_common::cache::Cache;
+use sps_common::config::Config;
+use sps_common::error::{Result, SpsError};
+use sps_common::keg::KegRegistry;
+use sps_common::overrides;
+use sps_core::build::formula::adopt::{self, ExternalLinks, Finding, LinkMode};
+use sps_core::build::formula::versions;
+use sps_core::metadata;
+use tracing::error;
+
+use crate::ui;
+
+#[derive(Args, Debug)]
+pub struct Adopt {
+    /// Only adopt these formulae (default: every keg found)
+    pub names: Vec<String>,
+
+    /// Prefix of the installation to adopt from (default: the sps prefix, where Homebrew
+    /// installs by default)
+    #[arg(long, value_name = "PREFIX")]
+    pub from: Option<PathBuf>,
+
+    /// Link adopted kegs into the sps prefix instead of keeping the existing links
+    #[arg(long)]
+    pub link: bool,
+
+    /// List what would be adopted without changing anything
+    #[arg(long)]
+    pub dry_run: bool,
+}
+
+impl Adopt {
+    /// Adopts every keg of the other Cellar that matches a known formula and reports the ones
+    /// that don't. Fails when adopting any matching keg failed.
+    pub async fn run(&self, config: &Config, cache: Arc<Cache>) -> Result<()> {
+        let from = self
+            .from
+            .clone()
+            .unwrap_or_else(|| config.prefix().to_path_buf());
+        let registry = KegRegistry::for_prefix(config, &from);
+        if !registry.cellar_path().is_dir() {
+            return Err(SpsError::NotFound(format!(
+                "No Cellar at {}",
+                registry.cellar_path().display()
+            )));
+        }
+        let mut kegs: Vec<_> = registry
+            .list_installed_kegs()?
+            .into_iter()
+            .filter(|keg| self.names.is_empty() || self.names.contains(&keg.name))
+            .collect();
+        kegs.sort_by(|a, b| a.name.cmp(&b.name).then_with(|| a.version.cmp(&b.version)));
+        let local = overrides::formulae(config);
+        let mut names: Vec<String> = kegs
+            .iter()
+            .map(|keg| keg.name.clone())
+            .filter(|name| !local.contains_key(name))
+            .collect();
+        names.dedup();
+        let definitions = metadata::formula_definitions(&cache, config, &names).await?;
+        let findings = adopt::survey(kegs, &from, config, |name| {
+            local
+                .get(name)
+                .cloned()
+                .or_else(|| definitions.get(name).map(|f| f.as_ref().clone()))
+        });
+
+        let mode = if self.link {
+            LinkMode::Link
+        } else {
+            LinkMode::External
+        };
+        let external = if mode == LinkMode::External && adopt::is_shared_prefix(&from, config) {
+            ExternalLinks::scan(&from)
+        } else {
+            ExternalLinks::default()
+        };
+        let (mut adopted, mut managed, mut mismatched, mut failed) = (0, 0, 0, 0);
+        for finding in &findings {
+            match finding {
+                Finding::Adoptable(candidate) => {
+                    let version = versions::keg_version(&candidate.keg);
+                    let note = candidate
+                        .available
+                        .as_ref()
+                        .map(|available| {
+                            if *available > candidate.keg.version {
+                                format!(" ({available} available)")
+                            } else {
+                                format!(" (newer than the formulary's {available})")
+                            }
+                        })
+                        .unwrap_or_default();
+                    if self.dry_run {
+                        println!(
+                            "Would adopt {} {}{}",
+                            candidate.keg.name.cyan(),
+                            version,
+                            note
+                        );
+                        adopted += 1;
+                        continue;
+                    }
+                    match adopt::adopt(candidate, &from, mode, &external, config) {
+                        Ok(()) => {
+                            println!(
+                                "{} Adopted {} {}{}",
+                                ui::ok_mark(),
+                                candidate.keg.name.cyan(),
+                                version,
+                                note
+                            );
+                            adopted += 1;
+                        }
+                        Err(e) => {
+                            error!(
+                                "{} Failed to adopt {} {}: {}",
+                                ui::fail_mark(),
+                                candidate.keg.name,
+                                version,
+                                e
+                            );
+                            failed += 1;
+                        }
+                    }
+                }
+                Finding::Managed(_) => managed += 1,
+                Finding::Mismatch(keg, reason) => {
+                    println!(
+                        "{} {} {}: {}",
+                        "Skipped:".yellow(),
+                        keg.name,
+                        versions::keg_version(keg),
+                        reason
+                    );
+                    mismatched += 1;
+                }
+            }
+        }
+
+        let verb = if self.dry_run {
+            "Would adopt"
+        } else {
+            "Adopted"
+        };
+        println!("{verb} {adopted} keg(s); {managed} already managed by sps, {mismatched} skipped");
+        if failed > 0 {
+            return Err(SpsError::Generic(format!(
+                "{failed} keg(s) could not be adopted"
+            )));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,162 @@
+//! Contains the logic for the `api` command.
+
+use std::sync::Arc;
+
+use clap::{Args, Subcommand};
+use serde_json::Value;
+use sps_common::cache::Cache;
+use sps_common::config::Config;
+use sps_common::error::{Result, SpsError};
+use sps_common::formulary::Formulary;
+use sps_common::model::{Cask, Formula};
+use sps_common::overrides;
+use sps_net::fetch::api;
+
+#[derive(Args, Debug)]
+pub struct Api {
+    #[command(subcommand)]
+    pub target: ApiTarget,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ApiTarget {
+    /// Print the parsed formula model as JSON
+    Formula {
+        /// Name of the formula
+        name: String,
+    },
+    /// Print the parsed cask model as JSON
+    Cask {
+        /// Token of the cask
+        token: String,
+    },
+    /// Fetch the live formula and cask indexes and report entries that fail to parse
+    #[command(hide = true)]
+    Validate,
+}
+
+impl Api {
+    /// Prints the fully parsed Formula/Cask model back as pretty JSON.
+    pub async fn run(&self, config: &Config, cache: Arc<Cache>) -> Result<()> {
+        let value = match &self.target {
+            ApiTarget::Validate => return validate().await,
+            ApiTarget::Formula { name } => {
+                let formulary = Formulary::new(config.clone());
+                let formula = match formulary.load_formula(name) {
+                    Ok(f) => f,
+                    Err(e) => {
+                        tracing::debug!(
+                            "Formula '{}' not loaded from cache ({}). Fetching from API.",
+                            name,
+                            e
+                        );
+                        api::get_formula(name).await?
+                    }
+                };
+                serde_json::to_value(&formula)?
+            }
+            ApiTarget::Cask { token } => {
+                let cask = load_cask(config, &cache, token).await?;
+                serde_json::to_value(&cask)?
+            }
+        };
+        let output = serde_json::to_string_pretty(&value)?;
+        println!("{output}");
+        Ok(())
+    }
+}
+
+/// Parses every entry of the live indexes with the same models the rest of sps uses, printing
+/// the name, the serde error and the field it occurred in for each failure.
+async fn validate() -> Result<()> {
+    let mut failures = 0;
+    for (index, raw, key) in [
+        ("formula.json", api::fetch_all_formulas().await?, "name"),
+        ("cask.json", api::fetch_all_casks().await?, "token"),
+    ] {
+        let entries: Vec<Value> = serde_json::from_str(&raw)?;
+        let before = failures;
+        for entry in &entries {
+            // Parsing from pretty text lets the error's line number point back at a field.
+            let text = serde_json::to_string_pretty(entry)?;
+            let result = if key == "name" {
+                serde_json::from_str::<Formula>(&text).map(drop)
+            } else {
+                serde_json::from_str::<Cask>(&text).map(drop)
+            };
+            if let Err(e) = result {
+                failures += 1;
+                let name = entry
+                    .get(key)
+                    .and_then(Value::as_str)
+                    .unwrap_or("<unnamed>");
+                println!(
+                    "{index}: {name}: {e} (at {})",
+                    field_path_at_line(&text, e.line())
+                );
+            }
+        }
+        println!(
+            "{index}: {} of {} entries parsed",
+            entries.len() - (failures - before),
+            entries.len()
+        );
+    }
+    if failures > 0 {
+        return Err(SpsError::Generic(format!(
+            "{failures} API entries failed to parse"
+        )));
+    }
+    Ok(())
+}
+
+/// The dotted path of object keys enclosing `line` (1-based) of pretty-printed JSON, e.g.
+/// `depends_on.macos`. Array positions are not included.
+fn field_path_at_line(pretty: &str, line: usize) -> String {
+    let mut keys: Vec<(usize, &str)> = Vec::new();
+    let mut path = Vec::new();
+    for text in pretty.lines().take(line) {
+        let indent = text.len() - text.trim_start().len();
+        keys.retain(|(depth, _)| *depth < indent);
+        let trimmed = text.trim_start();
+        path = keys.iter().map(|(_, key)| *key).collect();
+        if let Some(rest) = trimmed.strip_prefix('"') {
+            if let Some(end) = rest.find("\": ") {
+                keys.push((indent, &rest[..end]));
+                path.push(&rest[..end]);
+            }
+        }
+    }
+    if path.is_empty() {
+        "top level".to_string()
+    } else {
+        path.join(".")
+    }
+}
+
+/// Loads a cask from a local override, the cached `cask.json`, or the API, in that order.
+pub(crate) async fn load_cask(config: &Config, cache: &Cache, token: &str) -> Result<Cask> {
+    if let Some(cask) = overrides::cask(config, token)? {
+        return Ok(cask);
+    }
+    match load_cached_cask(cache, token) {
+        Some(cask) => Ok(cask),
+        None => api::get_cask(token).await,
+    }
+}
+
+/// Looks up a cask by token in the cached `cask.json`.
+fn load_cached_cask(cache: &Cache, token: &str) -> Option<Cask> {
+    let data = cache.load_raw("cask.json").ok()?;
+    let casks: Vec<Value> = serde_json::from_str(&data).ok()?;
+    let raw = casks
+        .into_iter()
+        .find(|c| c.get("token").and_then(Value::as_str) == Some(token))?;
+    match serde_json::from_value::<Cask>(raw) {
+        Ok(cask) => Some(cask),
+        Err(e) => {
+            tracing::debug!("Failed to parse cached cask '{}': {}", token, e);
+            None
+        }
+    }
+}
@@ -0,0 +1,216 @@
+//! Contains the logic for the `autoremove` command.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::Arc;
+
+use clap::Args;
+use colored::Colorize;
+use sps_common::cache::Cache;
+use sps_common::config::Config;
+use sps_common::dependency::{FailurePolicy, Outcome, ReverseDependencyGraph};
+use sps_common::error::{Result, SpsError};
+use sps_common::formulary::Formulary;
+use sps_common::keg::{InstallReason, KegRegistry};
+use sps_core::{installed, InstalledPackageInfo, PackageType};
+use tracing::error;
+
+use crate::cli::uninstall::uninstall_package;
+use crate::ui;
+
+#[derive(Args, Debug)]
+pub struct Autoremove {
+    /// List what would be removed without removing it
+    #[arg(long)]
+    pub dry_run: bool,
+}
+
+impl Autoremove {
+    /// Uninstalls formulae that were only installed as dependencies of targets that are gone.
+    pub async fn run(&self, config: &Config, _cache: Arc<Cache>) -> Result<()> {
+        let packages = installed::get_installed_packages(config).await?;
+        let casks: HashSet<String> = packages
+            .iter()
+            .filter(|p| p.pkg_type == PackageType::Cask)
+            .map(|p| p.name.clone())
+            .collect();
+        let formulae: HashMap<String, InstalledPackageInfo> = packages
+            .into_iter()
+            .filter(|p| p.pkg_type == PackageType::Formula)
+            .map(|p| (p.name.clone(), p))
+            .collect();
+        let reasons: BTreeMap<String, Option<InstallReason>> = formulae
+            .iter()
+            .map(|(name, info)| (name.clone(), InstallReason::read(&info.path)))
+            .collect();
+        let graph = ReverseDependencyGraph::from_installed(
+            &KegRegistry::new(config.clone()),
+            &Formulary::new(config.clone()),
+        )?;
+
+        let names = removable(&reasons, &casks, &graph);
+        if names.is_empty() {
+            println!("{} Nothing to remove.", ui::ok_mark());
+            return Ok(());
+        }
+        let scheduler = graph.removal_scheduler(&names, FailurePolicy::SkipBlocked);
+        if self.dry_run {
+            let order = scheduler.order();
+            println!("Would uninstall {} formula(e), in order:", order.len());
+            for (i, name) in order.iter().enumerate() {
+                println!("  {}. {}", i + 1, name.cyan());
+            }
+            return Ok(());
+        }
+
+        let mut failed = Vec::new();
+        for (name, outcome) in scheduler.run(|name| uninstall_package(&formulae[name], config)) {
+            match outcome {
+                Outcome::Done(()) => {}
+                Outcome::Failed(_) => failed.push(name),
+                Outcome::Skipped(cause) => {
+                    error!(
+                        "{} Kept {}: uninstalling its dependent {} failed",
+                        ui::fail_mark(),
+                        name,
+                        cause
+                    );
+                    failed.push(name);
+                }
+            }
+        }
+        if failed.is_empty() {
+            Ok(())
+        } else {
+            Err(SpsError::Generic(format!(
+                "Could not remove {}.",
+                failed.join(", ")
+            )))
+        }
+    }
+}
+
+/// The installed formulae `autoremove` takes away: those installed only as a dependency, whose
+/// recorded roots are neither an installed cask nor an installed formula that was requested,
+/// and that nothing staying installed depends on. Formulae without a recorded reason stay.
+fn removable(
+    reasons: &BTreeMap<String, Option<InstallReason>>,
+    casks: &HashSet<String>,
+    graph: &ReverseDependencyGraph,
+) -> HashSet<String> {
+    let is_live_root = |root: &String| {
+        casks.contains(root)
+            || reasons
+                .get(root)
+                .is_some_and(|reason| reason.as_ref().is_none_or(|r| r.on_request))
+    };
+    let mut removable: HashSet<String> = reasons
+        .iter()
+        .filter(|(_, reason)| {
+            reason
+                .as_ref()
+                .is_some_and(|r| !r.on_request && !r.dependency_of.iter().any(is_live_root))
+        })
+        .map(|(name, _)| name.clone())
+        .collect();
+    // A formula stays while anything that stays needs it.
+    loop {
+        let needed: Vec<String> = removable
+            .iter()
+            .filter(|name| graph.dependents_of(name).any(|d| !removable.contains(d)))
+            .cloned()
+            .collect();
+        if needed.is_empty() {
+            return removable;
+        }
+        for name in needed {
+            removable.remove(&name);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dependency_of(roots: &[&str]) -> Option<InstallReason> {
+        Some(InstallReason {
+            on_request: false,
+            dependency_of: roots.iter().map(|r| r.to_string()).collect(),
+        })
+    }
+
+    fn sorted(names: HashSet<String>) -> Vec<String> {
+        let mut names: Vec<String> = names.into_iter().collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn keeps_dependencies_while_any_root_is_requested() {
+        let reasons = BTreeMap::from([
+            ("tool".to_string(), Some(InstallReason::requested())),
+            ("lib".to_string(), dependency_of(&["app", "tool"])),
+            ("orphan".to_string(), dependency_of(&["app"])),
+        ]);
+        let mut graph = ReverseDependencyGraph::default();
+        graph.add_edge("tool", "lib");
+
+        let removable = removable(&reasons, &HashSet::new(), &graph);
+
+        assert_eq!(sorted(removable), ["orphan"]);
+    }
+
+    #[test]
+    fn a_root_installed_only_as_a_dependency_does_not_keep_anything() {
+        let reasons = BTreeMap::from([
+            ("app".to_string(), dependency_of(&["gone"])),
+            ("lib".to_string(), dependency_of(&["app"])),
+        ]);
+        let mut graph = ReverseDependencyGraph::default();
+        graph.add_edge("app", "lib");
+
+        let removable = removable(&reasons, &HashSet::new(), &graph);
+
+        assert_eq!(sorted(removable), ["app", "lib"]);
+    }
+
+    #[test]
+    fn an_installed_cask_keeps_its_formulae() {
+        let reasons = BTreeMap::from([("runtime".to_string(), dependency_of(&["viewer"]))]);
+        let casks = HashSet::from(["viewer".to_string()]);
+
+        let removable = removable(&reasons, &casks, &ReverseDependencyGraph::default());
+
+        assert!(removable.is_empty());
+    }
+
+    #[test]
+    fn formulae_without_a_recorded_reason_stay_and_keep_their_dependencies() {
+        let reasons = BTreeMap::from([
+            ("adopted".to_string(), None),
+            ("lib".to_string(), dependency_of(&["gone"])),
+        ]);
+        let mut graph = ReverseDependencyGraph::default();
+        graph.add_edge("adopted", "lib");
+
+        let removable = removable(&reasons, &HashSet::new(), &graph);
+
+        assert!(removable.is_empty());
+    }
+
+    #[test]
+    fn a_dependency_of_an_orphan_is_kept_if_something_else_needs_it() {
+        let reasons = BTreeMap::from([
+            ("orphan".to_string(), dependency_of(&["gone"])),
+            ("base".to_string(), dependency_of(&["gone"])),
+            ("stays".to_string(), Some(InstallReason::requested())),
+        ]);
+        let mut graph = ReverseDependencyGraph::default();
+        graph.add_edge("orphan", "base");
+        graph.add_edge("stays", "base");
+
+        let removable = removable(&reasons, &HashSet::new(), &graph);
+
+        assert_eq!(sorted(removable), ["orphan"]);
+    }
+}
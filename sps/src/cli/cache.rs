@@ -0,0 +1,117 @@
+//! Contains the logic for the `cache` command.
+
+use std::path::PathBuf;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+use clap::{Args, Subcommand};
+use colored::Colorize;
+use sps_common::cache::Cache;
+use sps_common::config::Config;
+use sps_common::error::{Result, SpsError};
+use sps_core::blocking;
+use sps_core::build::hashing::{self, HashControl};
+
+use crate::cli::verify::{cancel_on_ctrl_c, hash_progress_reporter};
+use crate::ui;
+
+#[derive(Args, Debug)]
+pub struct CacheArgs {
+    #[command(subcommand)]
+    pub command: CacheCommand,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum CacheCommand {
+    /// Print the resolved cache locations for API metadata and downloaded artifacts
+    Path,
+    /// Re-hash cached downloads against the checksums they matched when fetched, removing
+    /// those that no longer match
+    Verify {
+        /// Files hashed at once (defaults to the number of physical cores)
+        #[arg(long, value_name = "N")]
+        jobs: Option<usize>,
+    },
+}
+
+impl CacheArgs {
+    pub async fn run(&self, _config: &Config, cache: Arc<Cache>) -> Result<()> {
+        match self.command {
+            CacheCommand::Verify { jobs } => verify_cache(cache, jobs).await,
+            CacheCommand::Path => {
+                let downloads = cache.get_download_dir();
+                println!("API metadata:     {}", cache.get_dir().display());
+                println!("Bottles:          {}", downloads.join("bottles").display());
+                println!("Cask downloads:   {}", downloads.display());
+                println!("Formula sources:  {}", downloads.join("sources").display());
+                println!(
+                    "Resources:        {}",
+                    downloads.join("resources").display()
+                );
+                println!("HEAD checkouts:   {}", downloads.join("heads").display());
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Hashes every cached artifact whose checksum was recorded when it was verified. Mismatches
+/// are only removed once every file is hashed, so an interrupted run changes nothing.
+async fn verify_cache(cache: Arc<Cache>, jobs: Option<usize>) -> Result<()> {
+    let artifacts = cache.verified_artifacts();
+    if artifacts.is_empty() {
+        println!("No verified downloads in the cache.");
+        return Ok(());
+    }
+    let mut control = HashControl::default();
+    if let Some(jobs) = jobs {
+        control.jobs = jobs.max(1);
+    }
+    cancel_on_ctrl_c(&control);
+    let label = "Verifying cached downloads".to_string();
+    let spinner = ui::create_spinner(&label);
+    control.progress = Some(hash_progress_reporter(spinner.clone(), label));
+
+    let paths: Vec<PathBuf> = artifacts.iter().map(|(path, _)| path.clone()).collect();
+    let hashes = {
+        let control = control.clone();
+        blocking::run("cached downloads", "verify", move || {
+            hashing::hash_files(&paths, &control)
+        })
+        .await
+    };
+    spinner.finish_and_clear();
+    let hashes = hashes?;
+    control.cancel.store(true, Ordering::SeqCst);
+
+    let mut removed = 0;
+    let mut unreadable = 0;
+    for ((path, expected), hashed) in artifacts.iter().zip(hashes) {
+        match hashed {
+            Ok(hash) if hash.sha256.eq_ignore_ascii_case(expected) => {
+                println!("{} {}", ui::ok_mark(), path.display());
+            }
+            Ok(_) => {
+                println!(
+                    "{} {} {}",
+                    ui::fail_mark(),
+                    path.display(),
+                    "checksum mismatch, removed".red()
+                );
+                cache.discard_artifact(path);
+                removed += 1;
+            }
+            Err(e) => {
+                println!("{} {} ({})", "?".yellow(), path.display(), e);
+                unreadable += 1;
+            }
+        }
+    }
+    if removed + unreadable > 0 {
+        return Err(SpsError::ChecksumMismatch(format!(
+            "{removed} cached download(s) no longer matched their checksum and were removed, \
+             {unreadable} could not be read"
+        )));
+    }
+    Ok(())
+}
@@ -0,0 +1,323 @@
+//! Before/after comparison of the prefix, printed as a "changes" section after install and
+//! upgrade, and written as the `changes` object with `--json-lines`.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use colored::Colorize;
+use serde::Serialize;
+use sps_common::config::Config;
+use sps_common::keg::KegRegistry;
+use sps_common::model::pkg_version::PkgVersion;
+use tracing::warn;
+
+use crate::cli::output;
+use crate::cli::path_check::PathCheck;
+use crate::cli::uninstall::format_size;
+
+/// Installed keg versions and `bin/` entries at one point in time.
+#[derive(Debug, Default)]
+pub struct PrefixSnapshot {
+    /// Formula name -> keg directory names (version plus revision suffix).
+    kegs: BTreeMap<String, BTreeSet<String>>,
+    /// Sizes of the kegs of the formulae the operation is about to touch, so removed kegs can be
+    /// accounted for in the disk delta.
+    sizes: HashMap<PathBuf, u64>,
+    bins: BTreeSet<String>,
+}
+
+impl PrefixSnapshot {
+    /// Records the Cellar and `bin/`. Only the kegs of `touched` formulae are sized; walking
+    /// every keg would dominate the run for large prefixes.
+    pub fn capture(config: &Config, touched: &[&str]) -> Self {
+        let registry = KegRegistry::new(config.clone());
+        let mut snapshot = Self::default();
+        for keg in registry.list_installed_kegs().unwrap_or_default() {
+            let dir_name = keg_dir_name(&keg.path);
+            if touched.contains(&keg.name.as_str()) {
+                snapshot.sizes.insert(keg.path.clone(), dir_size(&keg.path));
+            }
+            snapshot.kegs.entry(keg.name).or_default().insert(dir_name);
+        }
+        if let Ok(entries) = fs::read_dir(config.bin_dir()) {
+            snapshot.bins = entries
+                .flatten()
+                .map(|e| e.file_name().to_string_lossy().to_string())
+                .collect();
+        }
+        snapshot
+    }
+
+    /// Compares against a snapshot taken after the operation and prints the differences, then
+    /// warns about new commands the shell won't run (see [`PathCheck`]).
+    pub fn print_changes(&self, config: &Config, after: &PrefixSnapshot) {
+        let path_check = PathCheck::from_env(config);
+        let changes = self.changes(config, after, &path_check);
+        if changes.is_empty() {
+            return;
+        }
+        output::write_changes(&changes);
+
+        output::println(format!("\n{}", "==> Changes".bold()));
+        if !changes.installed.is_empty() {
+            let installed: Vec<String> = changes
+                .installed
+                .iter()
+                .map(|k| format!("{} {}", k.name, k.version))
+                .collect();
+            output::println(format!(
+                "  {} {}",
+                "Installed:".green(),
+                installed.join(", ")
+            ));
+        }
+        if !changes.upgraded.is_empty() {
+            let upgraded: Vec<String> = changes
+                .upgraded
+                .iter()
+                .map(|k| format!("{} {} -> {}", k.name, k.from, k.to))
+                .collect();
+            output::println(format!("  {} {}", "Upgraded: ".cyan(), upgraded.join(", ")));
+        }
+        if !changes.new_bins.is_empty() {
+            let labels: Vec<String> = changes
+                .new_bins
+                .iter()
+                .map(|bin| match &bin.shadows {
+                    Some(other) => format!(
+                        "{} {}",
+                        bin.name,
+                        format!("(also at {})", other.display()).yellow()
+                    ),
+                    None => bin.name.clone(),
+                })
+                .collect();
+            output::println(format!(
+                "  {} {}",
+                "New in bin/:".green(),
+                labels.join(", ")
+            ));
+        }
+        let sign = if changes.disk_delta < 0 { "-" } else { "+" };
+        output::println(format!(
+            "  {} {}{}",
+            "Disk:".bold(),
+            sign,
+            format_size(changes.disk_delta.unsigned_abs())
+        ));
+        if !changes.new_bins.is_empty() {
+            let names = changes.new_bins.iter().map(|b| b.name.as_str());
+            for warning in path_check.warnings(names) {
+                warn!("{}", warning);
+            }
+        }
+    }
+
+    /// The differences between this snapshot and `after`, taken once the operation finished.
+    fn changes(
+        &self,
+        config: &Config,
+        after: &PrefixSnapshot,
+        path_check: &PathCheck,
+    ) -> PrefixChanges {
+        let (installed, upgraded, added) = self.keg_changes(after);
+        let mut delta: i128 = added
+            .iter()
+            .map(|(name, dir)| dir_size(&config.formula_keg_path(name, dir)) as i128)
+            .sum();
+        for (path, size) in &self.sizes {
+            if !path.exists() {
+                delta -= *size as i128;
+            }
+        }
+        let new_bins = after
+            .bins
+            .difference(&self.bins)
+            .map(|name| NewBin {
+                name: name.clone(),
+                shadows: path_check.elsewhere(name),
+            })
+            .collect();
+        PrefixChanges {
+            installed,
+            upgraded,
+            new_bins,
+            disk_delta: delta.clamp(i64::MIN.into(), i64::MAX.into()) as i64,
+        }
+    }
+
+    /// Formulae with kegs that weren't there before, split into fresh installs and upgrades,
+    /// plus every added keg as (formula, keg directory). Versions are ordered as Homebrew orders
+    /// them, so `1.10` is newer than `1.9`.
+    fn keg_changes(
+        &self,
+        after: &PrefixSnapshot,
+    ) -> (Vec<InstalledKeg>, Vec<UpgradedKeg>, Vec<(String, String)>) {
+        let (mut installed, mut upgraded, mut added_kegs) = (Vec::new(), Vec::new(), Vec::new());
+        for (name, versions) in &after.kegs {
+            let before = self.kegs.get(name);
+            let added: Vec<&String> = versions
+                .iter()
+                .filter(|v| before.is_none_or(|b| !b.contains(*v)))
+                .collect();
+            let Some(newest) = added.iter().max_by_key(|v| PkgVersion::parse(v)) else {
+                continue;
+            };
+            added_kegs.extend(added.iter().map(|v| (name.clone(), v.to_string())));
+            let previous = before.and_then(|b| b.iter().max_by_key(|v| PkgVersion::parse(v)));
+            match previous {
+                Some(old) => upgraded.push(UpgradedKeg {
+                    name: name.clone(),
+                    from: old.clone(),
+                    to: newest.to_string(),
+                }),
+                None => installed.push(InstalledKeg {
+                    name: name.clone(),
+                    version: newest.to_string(),
+                }),
+            }
+        }
+        (installed, upgraded, added_kegs)
+    }
+}
+
+/// What an operation changed in the prefix.
+#[derive(Debug, Default, Serialize)]
+pub struct PrefixChanges {
+    pub installed: Vec<InstalledKeg>,
+    pub upgraded: Vec<UpgradedKeg>,
+    pub new_bins: Vec<NewBin>,
+    /// Bytes added to the Cellar, negative when more was removed than added.
+    pub disk_delta: i64,
+}
+
+impl PrefixChanges {
+    fn is_empty(&self) -> bool {
+        self.installed.is_empty() && self.upgraded.is_empty() && self.new_bins.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct InstalledKeg {
+    pub name: String,
+    pub version: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct UpgradedKeg {
+    pub name: String,
+    pub from: String,
+    pub to: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct NewBin {
+    pub name: String,
+    /// Another command of the same name earlier on `PATH`.
+    pub shadows: Option<PathBuf>,
+}
+
+fn keg_dir_name(path: &Path) -> String {
+    path.file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+fn dir_size(path: &Path) -> u64 {
+    walkdir::WalkDir::new(path)
+        .follow_links(false)
+        .into_iter()
+        .flatten()
+        .filter(|e| e.file_type().is_file())
+        .filter_map(|e| e.metadata().ok())
+        .map(|m| m.len())
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(kegs: &[(&str, &[&str])]) -> PrefixSnapshot {
+        PrefixSnapshot {
+            kegs: kegs
+                .iter()
+                .map(|(name, dirs)| {
+                    (
+                        name.to_string(),
+                        dirs.iter().map(|d| d.to_string()).collect(),
+                    )
+                })
+                .collect(),
+            ..PrefixSnapshot::default()
+        }
+    }
+
+    fn upgraded(name: &str, from: &str, to: &str) -> UpgradedKeg {
+        UpgradedKeg {
+            name: name.into(),
+            from: from.into(),
+            to: to.into(),
+        }
+    }
+
+    #[test]
+    fn versions_compare_as_versions_not_strings() {
+        let before = snapshot(&[("jq", &["1.9"]), ("node", &["9.11.2", "10.0.0"])]);
+        let after = snapshot(&[
+            ("jq", &["1.9", "1.10"]),
+            ("node", &["9.11.2", "10.0.0", "10.1.0_1"]),
+        ]);
+
+        let (installed, upgraded_kegs, added) = before.keg_changes(&after);
+
+        assert!(installed.is_empty());
+        assert_eq!(
+            upgraded_kegs,
+            [
+                upgraded("jq", "1.9", "1.10"),
+                upgraded("node", "10.0.0", "10.1.0_1")
+            ]
+        );
+        assert_eq!(added.len(), 2);
+    }
+
+    #[test]
+    fn a_formula_without_earlier_kegs_is_installed() {
+        let before = snapshot(&[("jq", &["1.7.1"])]);
+        let after = snapshot(&[("jq", &["1.7.1"]), ("wget", &["1.24.5", "1.9"])]);
+
+        let (installed, upgraded_kegs, added) = before.keg_changes(&after);
+
+        assert_eq!(
+            installed,
+            [InstalledKeg {
+                name: "wget".into(),
+                version: "1.24.5".into()
+            }]
+        );
+        assert!(upgraded_kegs.is_empty());
+        assert_eq!(added.len(), 2);
+    }
+
+    #[test]
+    fn a_rebuilt_revision_counts_as_an_upgrade() {
+        let before = snapshot(&[("git", &["2.45.0"])]);
+        let after = snapshot(&[("git", &["2.45.0", "2.45.0_1"])]);
+
+        let (_, upgraded_kegs, _) = before.keg_changes(&after);
+
+        assert_eq!(upgraded_kegs, [upgraded("git", "2.45.0", "2.45.0_1")]);
+    }
+
+    #[test]
+    fn unchanged_kegs_are_not_reported() {
+        let before = snapshot(&[("jq", &["1.7.1"])]);
+
+        let (installed, upgraded_kegs, added) =
+            before.keg_changes(&snapshot(&[("jq", &["1.7.1"])]));
+
+        assert!(installed.is_empty() && upgraded_kegs.is_empty() && added.is_empty());
+    }
+}
@@ -0,0 +1,137 @@
+//! Contains the logic for the `cleanup` command.
+
+use std::fs;
+use std::sync::Arc;
+
+use clap::Args;
+use colored::Colorize;
+use sps_common::cache::Cache;
+use sps_common::config::Config;
+use sps_common::error::{Result, SpsError};
+use sps_common::keg;
+use sps_core::build::formula::versions;
+use sps_core::build::reaper;
+use tracing::{error, info};
+
+use crate::cli::uninstall::{count_files_and_size, format_size};
+use crate::ui;
+
+#[derive(Args, Debug)]
+pub struct Cleanup {
+    /// Only remove kept and incomplete kegs of these formulae (default: all)
+    pub names: Vec<String>,
+
+    /// Override `keg_retention_days` for this run (0 removes every kept keg)
+    #[arg(long, value_name = "DAYS")]
+    pub prune: Option<u64>,
+
+    /// List what would be removed without removing it
+    #[arg(long)]
+    pub dry_run: bool,
+}
+
+impl Cleanup {
+    /// Removes kegs kept from earlier versions once they have been out of use for longer than
+    /// the retention window, and incomplete kegs an install moved aside. Current and pinned
+    /// kegs are never removed.
+    pub async fn run(&self, config: &Config, _cache: Arc<Cache>) -> Result<()> {
+        if !self.dry_run {
+            reap_interrupted(config);
+        }
+        let mut config = config.clone();
+        if let Some(days) = self.prune {
+            config.keg_retention_days = days;
+        }
+        let expired: Vec<_> = versions::expired_kegs(&config)?
+            .into_iter()
+            .filter(|keg| self.names.is_empty() || self.names.contains(&keg.name))
+            .collect();
+        let quarantined: Vec<_> = keg::quarantined_kegs(&config)?
+            .into_iter()
+            .filter(|(name, _)| self.names.is_empty() || self.names.contains(name))
+            .collect();
+        if expired.is_empty() && quarantined.is_empty() {
+            println!(
+                "Nothing to clean up (kept kegs are removed {} days after they stop being current)",
+                config.keg_retention_days
+            );
+            return Ok(());
+        }
+
+        let mut failed = 0;
+        for keg in &expired {
+            let version = versions::keg_version(keg);
+            if self.dry_run {
+                println!("Would remove {} {}", keg.name.cyan(), version);
+                continue;
+            }
+            let (_, size) = count_files_and_size(&keg.path).unwrap_or((0, 0));
+            match fs::remove_dir_all(&keg.path) {
+                Ok(()) => println!(
+                    "{} Removed {} {} ({})",
+                    ui::ok_mark(),
+                    keg.name.cyan(),
+                    version,
+                    format_size(size)
+                ),
+                Err(e) => {
+                    error!(
+                        "{} Failed to remove {}: {}",
+                        ui::fail_mark(),
+                        keg.path.display(),
+                        e
+                    );
+                    failed += 1;
+                }
+            }
+        }
+        for (name, path) in &quarantined {
+            if self.dry_run {
+                println!(
+                    "Would remove incomplete keg of {} at {}",
+                    name.cyan(),
+                    path.display()
+                );
+                continue;
+            }
+            let (_, size) = count_files_and_size(path).unwrap_or((0, 0));
+            match fs::remove_dir_all(path) {
+                Ok(()) => println!(
+                    "{} Removed incomplete keg of {} at {} ({})",
+                    ui::ok_mark(),
+                    name.cyan(),
+                    path.display(),
+                    format_size(size)
+                ),
+                Err(e) => {
+                    error!(
+                        "{} Failed to remove {}: {}",
+                        ui::fail_mark(),
+                        path.display(),
+                        e
+                    );
+                    failed += 1;
+                }
+            }
+        }
+        if failed > 0 {
+            return Err(SpsError::Generic(format!(
+                "{failed} keg(s) could not be removed"
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Removes what interrupted runs left in the cache, reporting what that reclaimed. Run at the
+/// start of `install`, `upgrade` and `cleanup`.
+pub(crate) fn reap_interrupted(config: &Config) {
+    let reaped = reaper::reap(config);
+    if reaped.artifacts > 0 {
+        info!(
+            "Reclaimed {} from {} interrupted download(s)",
+            format_size(reaped.bytes),
+            reaped.artifacts
+        );
+    }
+}
@@ -0,0 +1,198 @@
+//! Contains the logic for the `create` command, which scaffolds a formula or cask definition in a
+//! local tap (see [`sps_core::scaffold`]).
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use clap::Args;
+use colored::Colorize;
+use serde_json::Value;
+use sps_common::cache::Cache;
+use sps_common::config::Config;
+use sps_common::error::{Result, SpsError};
+use sps_common::formulary::{self, Formulary};
+use sps_core::scaffold;
+use tracing::warn;
+
+use crate::ui;
+
+#[derive(Args, Debug)]
+pub struct Create {
+    /// URL of the source tarball (or, with --cask, of the app download)
+    #[arg(long)]
+    pub url: String,
+
+    /// Local tap to write the definition into
+    #[arg(long, value_name = "USER/REPO")]
+    pub tap: String,
+
+    /// Formula name or cask token (default: guessed from the URL or the app bundle)
+    #[arg(long)]
+    pub name: Option<String>,
+
+    /// Version of the download (default: guessed from the URL)
+    #[arg(long, value_name = "VERSION")]
+    pub set_version: Option<String>,
+
+    /// Scaffold a cask instead of a formula
+    #[arg(long)]
+    pub cask: bool,
+
+    /// Replace an existing definition
+    #[arg(long)]
+    pub force: bool,
+}
+
+impl Create {
+    /// Downloads the URL to checksum it, writes a skeleton definition into the tap and checks
+    /// that it loads. Description and dependencies are left for the author to fill in.
+    pub async fn run(&self, config: &Config, _cache: Arc<Cache>) -> Result<()> {
+        validate_tap(&self.tap)?;
+        let guess = scaffold::guess_from_url(&self.url);
+        let version = self
+            .set_version
+            .clone()
+            .or_else(|| guess.version.clone())
+            .ok_or_else(|| {
+                SpsError::Generic(format!(
+                    "Can't tell the version from {}; pass --set-version",
+                    self.url
+                ))
+            })?;
+        let homepage = guess.homepage.clone().unwrap_or_else(|| self.url.clone());
+
+        // Fail on an existing definition before downloading anything.
+        if let Some(name) = &self.name {
+            self.check_target(config, name)?;
+        }
+
+        let download = scaffold::download(&self.url, config).await?;
+        let sha256 = download.sha256.clone();
+
+        let (name, definition) = if self.cask {
+            let artifacts = scaffold::sniff_cask_artifacts(&download.path, config)?;
+            let app = first_app(&artifacts);
+            let display_name = app
+                .clone()
+                .or_else(|| self.name.clone())
+                .or_else(|| guess.name.clone())
+                .ok_or_else(|| {
+                    SpsError::Generic("Can't tell the cask's name; pass --name".to_string())
+                })?;
+            let token = self
+                .name
+                .clone()
+                .unwrap_or_else(|| scaffold::cask_token(&display_name));
+            if artifacts.is_empty() {
+                warn!(
+                    "No app bundle or installer package found in the download; add the \
+                     artifacts to {}.json by hand",
+                    token
+                );
+            }
+            let definition = scaffold::cask_skeleton(
+                &token,
+                &display_name,
+                &version,
+                &homepage,
+                &self.url,
+                &sha256,
+                artifacts,
+            );
+            (token, definition)
+        } else {
+            let name = self.name.clone().or(guess.name).ok_or_else(|| {
+                SpsError::Generic(format!(
+                    "Can't tell the formula name from {}; pass --name",
+                    self.url
+                ))
+            })?;
+            let definition = scaffold::formula_skeleton(
+                &self.tap, &name, &version, &homepage, &self.url, &sha256,
+            );
+            (name, definition)
+        };
+
+        let path = self.check_target(config, &name)?;
+        let full_name = format!("{}/{name}", self.tap);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&path, serde_json::to_string_pretty(&definition)? + "\n")?;
+        if let Err(e) = self.validate(config, &full_name) {
+            let _ = fs::remove_file(&path);
+            return Err(e);
+        }
+
+        println!(
+            "{} Created {} {} in {}",
+            ui::ok_mark(),
+            if self.cask { "cask" } else { "formula" },
+            full_name.cyan(),
+            path.display()
+        );
+        if self.cask {
+            println!("  Fill in \"desc\" before publishing the tap.");
+        } else {
+            println!("  Fill in \"desc\" and the dependencies before publishing the tap.");
+        }
+        Ok(())
+    }
+
+    /// The file for `name` in the tap, refusing an existing one without `--force`.
+    fn check_target(&self, config: &Config, name: &str) -> Result<PathBuf> {
+        scaffold::validate_name(name)?;
+        let full_name = format!("{}/{name}", self.tap);
+        let path = if self.cask {
+            formulary::tap_cask_path(config, &full_name)
+        } else {
+            formulary::tap_formula_path(config, &full_name)
+        }
+        .ok_or_else(|| SpsError::Generic(format!("Invalid tap name '{}'", self.tap)))?;
+        if path.exists() && !self.force {
+            return Err(SpsError::Generic(format!(
+                "{} already exists; pass --force to replace it",
+                path.display()
+            )));
+        }
+        Ok(path)
+    }
+
+    /// Loads the written definition back the way `install` would.
+    fn validate(&self, config: &Config, full_name: &str) -> Result<()> {
+        if self.cask {
+            formulary::tap_cask(config, full_name)?;
+        } else {
+            Formulary::new(config.clone()).load_formula(full_name)?;
+        }
+        Ok(())
+    }
+}
+
+fn validate_tap(tap: &str) -> Result<()> {
+    match tap.split('/').collect::<Vec<_>>().as_slice() {
+        [user, repo]
+            if scaffold::validate_name(user).is_ok() && scaffold::validate_name(repo).is_ok() =>
+        {
+            Ok(())
+        }
+        _ => Err(SpsError::Generic(format!(
+            "Invalid tap name '{tap}'; expected USER/REPO"
+        ))),
+    }
+}
+
+/// The name of the first app bundle among the sniffed artifacts, without `.app`.
+fn first_app(artifacts: &[Value]) -> Option<String> {
+    let app = artifacts
+        .iter()
+        .find_map(|a| a.get("app")?.as_array()?.first()?.as_str())?;
+    let file_name = Path::new(app).file_name()?.to_string_lossy().to_string();
+    Some(
+        file_name
+            .strip_suffix(".app")
+            .unwrap_or(&file_name)
+            .to_string(),
+    )
+}
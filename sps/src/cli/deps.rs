@@ -0,0 +1,90 @@
+//! Contains the logic for the `deps` command.
+
+use std::sync::Arc;
+
+use clap::Args;
+use sps_common::cache::Cache;
+use sps_common::config::Config;
+use sps_common::dependency::ResolutionStatus;
+use sps_common::error::Result;
+use tracing::error;
+
+use crate::cli::graph::{DependencyGraph, GraphFormat};
+use crate::cli::pipeline::{PipelineExecutor, ResolveOptions};
+
+#[derive(Args, Debug)]
+pub struct Deps {
+    /// Formulae whose dependencies to show
+    #[arg(required = true)]
+    pub names: Vec<String>,
+
+    /// Print the dependency graph as Graphviz DOT or Mermaid instead of a list
+    #[arg(long, value_enum, value_name = "FORMAT")]
+    pub graph: Option<GraphFormat>,
+
+    /// With --graph, show installed dependencies as leaves without their own dependencies
+    #[arg(long, requires = "graph")]
+    pub prune_installed: bool,
+
+    /// Include optional dependencies
+    #[arg(long)]
+    pub include_optional: bool,
+
+    /// Leave out recommended dependencies
+    #[arg(long)]
+    pub skip_recommended: bool,
+}
+
+impl Deps {
+    /// Resolves the dependencies as `install` would and prints them, one per line in name order,
+    /// or as a graph. No network access beyond fetching missing definitions.
+    pub async fn run(&self, config: &Config, cache: Arc<Cache>) -> Result<()> {
+        let (resolved, mut errors) = PipelineExecutor::resolve_dependency_graph(
+            &self.names,
+            config,
+            cache,
+            ResolveOptions {
+                include_optional: self.include_optional,
+                skip_recommended: self.skip_recommended,
+                ..Default::default()
+            },
+        )
+        .await?;
+
+        match self.graph {
+            Some(format) => {
+                let mut graph = DependencyGraph::from_resolved(&resolved);
+                for (name, _) in &errors {
+                    graph.add_failed(name);
+                }
+                if self.prune_installed {
+                    graph.prune_installed(&self.names);
+                }
+                print!("{}", graph.render(format));
+            }
+            None => {
+                let mut names: Vec<&str> = resolved
+                    .resolution_details
+                    .iter()
+                    .filter(|(name, dep)| {
+                        !self.names.iter().any(|n| **n == ***name)
+                            && dep.status != ResolutionStatus::SkippedOptional
+                    })
+                    .map(|(name, _)| &**name)
+                    .collect();
+                names.sort_unstable();
+                for name in names {
+                    println!("{name}");
+                }
+            }
+        }
+
+        for (name, e) in &errors {
+            error!("{}: {}", name, e);
+        }
+        match errors.len() {
+            0 => Ok(()),
+            _ => Err(errors.remove(0).1),
+        }
+    }
+}
@@ -0,0 +1,35 @@
+//! Contains the logic for the `doctor` command: the prefix checks of `bug-report`, plus whether
+//! the shell finds the commands installed in the prefix.
+
+use std::sync::Arc;
+
+use clap::Args;
+use colored::Colorize;
+use sps_common::cache::Cache;
+use sps_common::config::Config;
+use sps_common::error::{Result, SpsError};
+
+use crate::cli::{bug_report, path_check};
+use crate::ui;
+
+#[derive(Args, Debug)]
+pub struct Doctor {}
+
+impl Doctor {
+    /// Prints every problem found; fails when there is any, so scripts can gate on it.
+    pub async fn run(&self, config: &Config, _cache: Arc<Cache>) -> Result<()> {
+        let mut problems = bug_report::prefix_problems(config);
+        problems.extend(path_check::path_problems(config));
+        if problems.is_empty() {
+            println!("{} No problems found.", ui::ok_mark());
+            return Ok(());
+        }
+        for problem in &problems {
+            println!("{} {}", "Warning:".yellow(), problem);
+        }
+        Err(SpsError::Generic(format!(
+            "{} problem(s) found",
+            problems.len()
+        )))
+    }
+}
@@ -0,0 +1,59 @@
+//! Contains the logic for the `files` and `owner` commands.
+
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use clap::Args;
+use colored::Colorize;
+use sps_common::cache::Cache;
+use sps_common::config::Config;
+use sps_common::error::{Result, SpsError};
+use sps_common::keg::KegRegistry;
+use sps_core::build::formula::{integrity, owners, versions};
+
+#[derive(Args, Debug)]
+pub struct Files {
+    /// Name of the formula
+    pub name: String,
+}
+
+#[derive(Args, Debug)]
+pub struct Owner {
+    /// Path under the prefix (a file in a keg, or a link into one)
+    pub path: PathBuf,
+}
+
+impl Files {
+    /// Lists every file the current keg of the formula was poured with, as absolute paths.
+    pub async fn run(&self, config: &Config, _cache: Arc<Cache>) -> Result<()> {
+        let keg = KegRegistry::new(config.clone())
+            .get_installed_keg(&self.name)?
+            .ok_or_else(|| SpsError::NotFound(format!("'{}' is not installed", self.name)))?;
+        for file in integrity::keg_files(&keg.path)? {
+            println!("{}", keg.path.join(file).display());
+        }
+        Ok(())
+    }
+}
+
+impl Owner {
+    /// Maps a path back to the keg that provides it, without network access.
+    pub async fn run(&self, config: &Config, _cache: Arc<Cache>) -> Result<()> {
+        let Some(owner) = owners::owner_of(config, &self.path)? else {
+            return Err(SpsError::Generic(format!(
+                "{} is not owned by any keg",
+                self.path.display()
+            )));
+        };
+        println!(
+            "{} {}",
+            owner.keg.name.cyan(),
+            versions::keg_version(&owner.keg)
+        );
+        match &owner.link {
+            Some(link) => println!("  {} (via {})", owner.keg_path.display(), link.display()),
+            None => println!("  {}", owner.keg_path.display()),
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,417 @@
+//! The resolved dependency graph as Graphviz DOT or Mermaid, for `sps deps --graph` and
+//! `install --dry-run --graph`.
+//!
+//! Nodes are labelled `name@version` and colored by state: installed green, to be installed
+//! blue, failed or not found red, left out (optional dependencies not asked for) gray. Edges are
+//! labelled with the tags of the dependency. Nodes and edges are sorted by name, so the output of
+//! an unchanged graph is byte-for-byte the same and can be committed and diffed.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+use clap::ValueEnum;
+use sps_common::dependency::{DependencyTag, ResolutionStatus, ResolvedGraph};
+use sps_common::model::Cask;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum GraphFormat {
+    Dot,
+    Mermaid,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NodeState {
+    Installed,
+    ToInstall,
+    Failed,
+    Skipped,
+}
+
+impl NodeState {
+    fn from_status(status: ResolutionStatus) -> Self {
+        match status {
+            ResolutionStatus::Installed | ResolutionStatus::InstalledUnlinked => Self::Installed,
+            ResolutionStatus::Outdated
+            | ResolutionStatus::Missing
+            | ResolutionStatus::Requested => Self::ToInstall,
+            ResolutionStatus::NotFound | ResolutionStatus::Failed => Self::Failed,
+            ResolutionStatus::SkippedOptional => Self::Skipped,
+        }
+    }
+
+    /// Class name in the Mermaid output.
+    fn class(self) -> &'static str {
+        match self {
+            Self::Installed => "installed",
+            Self::ToInstall => "toinstall",
+            Self::Failed => "failed",
+            Self::Skipped => "skipped",
+        }
+    }
+
+    fn color(self) -> &'static str {
+        match self {
+            Self::Installed => "#2e7d32",
+            Self::ToInstall => "#1565c0",
+            Self::Failed => "#c62828",
+            Self::Skipped => "#757575",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Node {
+    label: String,
+    state: NodeState,
+}
+
+/// A dependency graph ready to be rendered.
+#[derive(Debug, Clone, Default)]
+pub struct DependencyGraph {
+    nodes: BTreeMap<String, Node>,
+    /// (dependent, dependency) -> tags of the dependency.
+    edges: BTreeMap<(String, String), DependencyTag>,
+}
+
+impl DependencyGraph {
+    /// Every formula the resolver looked at, with an edge for each dependency it also resolved.
+    /// Names it could not resolve are failed nodes.
+    pub fn from_resolved(graph: &ResolvedGraph) -> Self {
+        let mut result = Self::default();
+        for (name, dep) in &graph.resolution_details {
+            let state = if graph.errors.contains_key(name) {
+                NodeState::Failed
+            } else {
+                NodeState::from_status(dep.status)
+            };
+            result.nodes.insert(
+                name.to_string(),
+                Node {
+                    label: format!("{name}@{}", dep.formula.version_str_full()),
+                    state,
+                },
+            );
+        }
+        for name in graph.errors.keys() {
+            result.add_failed(name);
+        }
+        for (name, dep) in &graph.resolution_details {
+            for child in dep.formula.dependencies() {
+                if result.nodes.contains_key(&child.name) {
+                    result
+                        .edges
+                        .insert((name.to_string(), child.name.clone()), child.tags);
+                }
+            }
+        }
+        result
+    }
+
+    /// Adds a cask to be installed, with an edge to each of its formula and cask dependencies
+    /// already in the graph.
+    pub fn add_cask(&mut self, cask: &Cask) {
+        let version = cask.version.as_deref().unwrap_or("latest");
+        self.nodes.insert(
+            cask.token.clone(),
+            Node {
+                label: format!("{}@{version}", cask.token),
+                state: NodeState::ToInstall,
+            },
+        );
+        if let Some(deps) = &cask.depends_on {
+            for dep in deps.formula.iter().chain(&deps.cask) {
+                if self.nodes.contains_key(dep) {
+                    self.edges
+                        .insert((cask.token.clone(), dep.clone()), DependencyTag::RUNTIME);
+                }
+            }
+        }
+    }
+
+    /// Marks `name` as failed, adding it when it isn't in the graph yet.
+    pub fn add_failed(&mut self, name: &str) {
+        self.nodes
+            .entry(name.to_string())
+            .and_modify(|node| node.state = NodeState::Failed)
+            .or_insert_with(|| Node {
+                label: name.to_string(),
+                state: NodeState::Failed,
+            });
+    }
+
+    /// Keeps what is reachable from `roots` without passing through an installed node: installed
+    /// dependencies stay as leaves, their own dependencies are dropped.
+    pub fn prune_installed(&mut self, roots: &[String]) {
+        let mut kept: BTreeSet<String> = BTreeSet::new();
+        let mut stack: Vec<String> = roots
+            .iter()
+            .filter(|name| self.nodes.contains_key(*name))
+            .cloned()
+            .collect();
+        while let Some(name) = stack.pop() {
+            if !kept.insert(name.clone()) {
+                continue;
+            }
+            if self.nodes[&name].state == NodeState::Installed {
+                continue;
+            }
+            stack.extend(
+                self.edges
+                    .keys()
+                    .filter(|(from, _)| *from == name)
+                    .map(|(_, to)| to.clone()),
+            );
+        }
+        let installed: BTreeSet<String> = self
+            .nodes
+            .iter()
+            .filter(|(_, node)| node.state == NodeState::Installed)
+            .map(|(name, _)| name.clone())
+            .collect();
+        self.nodes.retain(|name, _| kept.contains(name));
+        self.edges.retain(|(from, to), _| {
+            kept.contains(from) && kept.contains(to) && !installed.contains(from)
+        });
+    }
+
+    pub fn render(&self, format: GraphFormat) -> String {
+        match format {
+            GraphFormat::Dot => self.render_dot(),
+            GraphFormat::Mermaid => self.render_mermaid(),
+        }
+    }
+
+    fn render_dot(&self) -> String {
+        let mut out = String::from("digraph dependencies {\n");
+        out.push_str("  node [shape=box, style=filled, fontcolor=white];\n");
+        for (name, node) in &self.nodes {
+            let style = if node.state == NodeState::Skipped {
+                ", style=\"filled,dashed\""
+            } else {
+                ""
+            };
+            let _ = writeln!(
+                out,
+                "  {} [label={}, fillcolor=\"{}\"{style}];",
+                dot_id(name),
+                dot_id(&node.label),
+                node.state.color()
+            );
+        }
+        for ((from, to), tags) in &self.edges {
+            let _ = writeln!(
+                out,
+                "  {} -> {} [label=\"{}\"];",
+                dot_id(from),
+                dot_id(to),
+                tags.api_names().join(",")
+            );
+        }
+        out.push_str("}\n");
+        out
+    }
+
+    fn render_mermaid(&self) -> String {
+        // Mermaid ids can't hold `@` or `+`; nodes are numbered in name order instead.
+        let ids: BTreeMap<&str, String> = self
+            .nodes
+            .keys()
+            .enumerate()
+            .map(|(i, name)| (name.as_str(), format!("n{i}")))
+            .collect();
+        let mut out = String::from("flowchart TD\n");
+        for (name, node) in &self.nodes {
+            let _ = writeln!(
+                out,
+                "  {}[\"{}\"]:::{}",
+                ids[name.as_str()],
+                node.label.replace('"', "#quot;"),
+                node.state.class()
+            );
+        }
+        for ((from, to), tags) in &self.edges {
+            let _ = writeln!(
+                out,
+                "  {} -->|{}| {}",
+                ids[from.as_str()],
+                tags.api_names().join(","),
+                ids[to.as_str()]
+            );
+        }
+        for state in [
+            NodeState::Installed,
+            NodeState::ToInstall,
+            NodeState::Failed,
+            NodeState::Skipped,
+        ] {
+            let _ = writeln!(
+                out,
+                "  classDef {} fill:{},color:#fff",
+                state.class(),
+                state.color()
+            );
+        }
+        out
+    }
+}
+
+/// A quoted DOT identifier.
+fn dot_id(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn graph(
+        nodes: &[(&str, NodeState)],
+        edges: &[(&str, &str, DependencyTag)],
+    ) -> DependencyGraph {
+        let mut graph = DependencyGraph::default();
+        for (name, state) in nodes {
+            graph.nodes.insert(
+                name.to_string(),
+                Node {
+                    label: format!("{name}@1.0"),
+                    state: *state,
+                },
+            );
+        }
+        for (from, to, tags) in edges {
+            graph
+                .edges
+                .insert((from.to_string(), to.to_string()), *tags);
+        }
+        graph
+    }
+
+    /// app -> lib, tool -> base, with base already installed and tool built from source.
+    fn diamond() -> DependencyGraph {
+        graph(
+            &[
+                ("app", NodeState::ToInstall),
+                ("lib", NodeState::ToInstall),
+                ("tool", NodeState::ToInstall),
+                ("base", NodeState::Installed),
+            ],
+            &[
+                ("tool", "base", DependencyTag::RUNTIME),
+                ("app", "lib", DependencyTag::RUNTIME),
+                ("lib", "base", DependencyTag::RUNTIME),
+                ("app", "tool", DependencyTag::BUILD | DependencyTag::TEST),
+            ],
+        )
+    }
+
+    #[test]
+    fn dot_lists_nodes_and_edges_in_name_order() {
+        assert_eq!(
+            diamond().render(GraphFormat::Dot),
+            r##"digraph dependencies {
+  node [shape=box, style=filled, fontcolor=white];
+  "app" [label="app@1.0", fillcolor="#1565c0"];
+  "base" [label="base@1.0", fillcolor="#2e7d32"];
+  "lib" [label="lib@1.0", fillcolor="#1565c0"];
+  "tool" [label="tool@1.0", fillcolor="#1565c0"];
+  "app" -> "lib" [label="runtime"];
+  "app" -> "tool" [label="build,test"];
+  "lib" -> "base" [label="runtime"];
+  "tool" -> "base" [label="runtime"];
+}
+"##
+        );
+    }
+
+    #[test]
+    fn mermaid_numbers_nodes_and_styles_them_by_state() {
+        assert_eq!(
+            diamond().render(GraphFormat::Mermaid),
+            r##"flowchart TD
+  n0["app@1.0"]:::toinstall
+  n1["base@1.0"]:::installed
+  n2["lib@1.0"]:::toinstall
+  n3["tool@1.0"]:::toinstall
+  n0 -->|runtime| n2
+  n0 -->|build,test| n3
+  n2 -->|runtime| n1
+  n3 -->|runtime| n1
+  classDef installed fill:#2e7d32,color:#fff
+  classDef toinstall fill:#1565c0,color:#fff
+  classDef failed fill:#c62828,color:#fff
+  classDef skipped fill:#757575,color:#fff
+"##
+        );
+    }
+
+    #[test]
+    fn skipped_nodes_are_dashed_and_names_are_escaped() {
+        let mut graph = graph(&[("opt", NodeState::Skipped)], &[]);
+        graph.add_failed("we\"ird\\name");
+
+        let dot = graph.render(GraphFormat::Dot);
+
+        assert!(
+            dot.contains(
+                r##""opt" [label="opt@1.0", fillcolor="#757575", style="filled,dashed"];"##
+            ),
+            "{dot}"
+        );
+        assert!(
+            dot.contains(r##""we\"ird\\name" [label="we\"ird\\name", fillcolor="#c62828"];"##),
+            "{dot}"
+        );
+    }
+
+    #[test]
+    fn add_failed_marks_an_existing_node_without_relabelling_it() {
+        let mut graph = diamond();
+
+        graph.add_failed("lib");
+
+        assert_eq!(graph.nodes["lib"].state, NodeState::Failed);
+        assert_eq!(graph.nodes["lib"].label, "lib@1.0");
+    }
+
+    #[test]
+    fn pruning_keeps_installed_dependencies_as_leaves() {
+        // base is installed and pulls in zlib, which nothing else needs.
+        let mut graph = graph(
+            &[
+                ("app", NodeState::ToInstall),
+                ("base", NodeState::Installed),
+                ("zlib", NodeState::Installed),
+                ("other", NodeState::ToInstall),
+            ],
+            &[
+                ("app", "base", DependencyTag::RUNTIME),
+                ("base", "zlib", DependencyTag::RUNTIME),
+                ("other", "base", DependencyTag::RUNTIME),
+            ],
+        );
+
+        graph.prune_installed(&["app".to_string()]);
+
+        assert_eq!(graph.nodes.keys().collect::<Vec<_>>(), ["app", "base"]);
+        assert_eq!(
+            graph.edges.keys().cloned().collect::<Vec<_>>(),
+            [("app".to_string(), "base".to_string())]
+        );
+    }
+
+    #[test]
+    fn resolution_statuses_map_to_node_states() {
+        for (status, state) in [
+            (ResolutionStatus::Installed, NodeState::Installed),
+            (ResolutionStatus::InstalledUnlinked, NodeState::Installed),
+            (ResolutionStatus::Outdated, NodeState::ToInstall),
+            (ResolutionStatus::Missing, NodeState::ToInstall),
+            (ResolutionStatus::Requested, NodeState::ToInstall),
+            (ResolutionStatus::NotFound, NodeState::Failed),
+            (ResolutionStatus::Failed, NodeState::Failed),
+            (ResolutionStatus::SkippedOptional, NodeState::Skipped),
+        ] {
+            assert_eq!(NodeState::from_status(status), state, "{status:?}");
+        }
+    }
+}
@@ -1,5 +1,6 @@
 //! Contains the logic for the `info` command.
 
+use std::path::Path;
 use std::sync::Arc;
 
 use clap::Args;
@@ -8,8 +9,19 @@ use serde_json::Value;
 use sps_common::cache::Cache;
 use sps_common::config::Config;
 use sps_common::error::{Result, SpsError};
+use sps_common::keg::{InstallReason, KegRegistry};
+use sps_common::macos::{self, Comparator, MacOSConstraint};
+use sps_common::model::cask::MacOSReq;
+use sps_common::model::formula::{
+    canonical_formula_name, qualified_formula_name, FormulaLifecycle,
+};
+use sps_common::model::PkgVersion;
+use sps_common::overrides::{self, OverrideKind};
+use sps_core::build::cask::{self, CaskInstallManifest, InstalledArtifact};
+use sps_core::{resolve_token, KindHint, NameIndexes, PackageType, Resolved};
 use sps_net::fetch::api;
 
+use crate::table::{Column, Table};
 use crate::ui;
 
 #[derive(Args, Debug)]
@@ -17,6 +29,10 @@ pub struct Info {
     /// Name of the formula or cask
     pub name: String,
 
+    /// Show information for a formula, not a cask
+    #[arg(long, conflicts_with = "cask")]
+    pub formula: bool,
+
     /// Show information for a cask, not a formula
     #[arg(long)]
     pub cask: bool,
@@ -24,57 +40,70 @@ pub struct Info {
 
 impl Info {
     /// Displays detailed information about a formula or cask.
-    pub async fn run(&self, _config: &Config, cache: Arc<Cache>) -> Result<()> {
+    pub async fn run(&self, config: &Config, cache: Arc<Cache>) -> Result<()> {
         let name = &self.name;
-        let is_cask = self.cask;
-        tracing::debug!("Getting info for package: {name}, is_cask: {is_cask}",);
+        let hint = KindHint::from_flags(self.formula, self.cask)?;
+        tracing::debug!("Getting info for package: {name}, hint: {hint:?}");
 
         // Use the ui utility function to create the spinner
         let pb = ui::create_spinner(&format!("Loading info for {name}")); // <-- CHANGED
 
-        if self.cask {
-            match get_cask_info(Arc::clone(&cache), name).await {
-                Ok(info) => {
-                    pb.finish_and_clear();
-                    print_cask_info(name, &info);
-                    Ok(())
-                }
-                Err(e) => {
-                    pb.finish_and_clear(); // Ensure spinner is cleared on error
-                    Err(e)
-                }
-            }
-        } else {
-            match get_formula_info_raw(Arc::clone(&cache), name).await {
-                Ok(info) => {
-                    // Removed bottle check logic here as it was complex and potentially racy.
-                    // We'll try formula first, then cask if formula fails.
-                    pb.finish_and_clear(); // Clear spinner after successful fetch
-                    print_formula_info(name, &info);
-                    return Ok(());
-                }
+        let local_override = overrides::find(config, name)?.filter(|entry| match hint {
+            KindHint::Formula => entry.kind == OverrideKind::Formula,
+            KindHint::Cask => entry.kind == OverrideKind::Cask,
+            KindHint::Any => true,
+        });
+        let is_override = local_override.is_some();
+        let indexes = NameIndexes::load(&cache);
+        let result = match local_override {
+            Some(entry) => Ok(match entry.kind {
+                OverrideKind::Formula => (PackageType::Formula, entry.value),
+                OverrideKind::Cask => (PackageType::Cask, entry.value),
+            }),
+            None => match resolve_token(name, hint, &indexes) {
+                Resolved::NotFound => Self::lookup_unindexed(name, hint, &cache).await,
+                resolved => match resolved.into_target(name)? {
+                    (PackageType::Formula, canonical) => {
+                        get_formula_info_raw(Arc::clone(&cache), &canonical)
+                            .await
+                            .map(|info| (PackageType::Formula, info))
+                    }
+                    (PackageType::Cask, canonical) => get_cask_info(Arc::clone(&cache), &canonical)
+                        .await
+                        .map(|info| (PackageType::Cask, info)),
+                },
+            },
+        };
+        pb.finish_and_clear();
+
+        match result? {
+            (PackageType::Formula, info) => print_formula_info(name, &info, config, is_override),
+            (PackageType::Cask, info) => print_cask_info(name, &info, config, is_override),
+        }
+        Ok(())
+    }
+
+    /// Names unknown to the cached metadata: ask the API, trying the formula first unless a hint
+    /// rules it out.
+    async fn lookup_unindexed(
+        name: &str,
+        hint: KindHint,
+        cache: &Arc<Cache>,
+    ) -> Result<(PackageType, Value)> {
+        if hint != KindHint::Cask {
+            match get_formula_info_raw(Arc::clone(cache), name).await {
+                Ok(info) => return Ok((PackageType::Formula, info)),
+                Err(e) if hint == KindHint::Formula => return Err(e),
                 Err(SpsError::NotFound(_)) | Err(SpsError::Generic(_)) => {
                     // If formula lookup failed (not found or generic error), try cask.
                     tracing::debug!("Formula '{}' info failed, trying cask.", name);
                 }
-                Err(e) => {
-                    pb.finish_and_clear(); // Ensure spinner is cleared on other errors
-                    return Err(e); // Propagate other errors (API, JSON, etc.)
-                }
-            }
-            // --- Cask Fallback ---
-            match get_cask_info(Arc::clone(&cache), name).await {
-                Ok(info) => {
-                    pb.finish_and_clear();
-                    print_cask_info(name, &info);
-                    Ok(())
-                }
-                Err(e) => {
-                    pb.finish_and_clear(); // Clear spinner on cask error too
-                    Err(e) // Return the cask error if both formula and cask fail
-                }
+                Err(e) => return Err(e), // Propagate other errors (API, JSON, etc.)
             }
         }
+        get_cask_info(Arc::clone(cache), name)
+            .await
+            .map(|info| (PackageType::Cask, info))
     }
 }
 
@@ -84,11 +113,10 @@ async fn get_formula_info_raw(cache: Arc<Cache>, name: &str) -> Result<Value> {
         Ok(formula_data) => {
             let formulas: Vec<Value> =
                 serde_json::from_str(&formula_data).map_err(SpsError::from)?;
+            let name = canonical_formula_name(name);
             for formula in formulas {
-                if let Some(fname) = formula.get("name").and_then(Value::as_str) {
-                    if fname == name {
-                        return Ok(formula);
-                    }
+                if qualified_formula_name(&formula).as_deref() == Some(name) {
+                    return Ok(formula);
                 }
                 // Also check aliases if needed
                 if let Some(aliases) = formula.get("aliases").and_then(|a| a.as_array()) {
@@ -156,12 +184,22 @@ async fn get_cask_info(cache: Arc<Cache>, name: &str) -> Result<Value> {
     Ok(value)
 }
 
+fn override_marker(local_override: bool) -> String {
+    if local_override {
+        format!(" {}", "(local override)".yellow())
+    } else {
+        String::new()
+    }
+}
+
 /// Prints formula information in a formatted table
-fn print_formula_info(_name: &str, formula: &Value) {
+fn print_formula_info(_name: &str, formula: &Value, config: &Config, local_override: bool) {
     // Basic info extraction
+    let qualified_name = qualified_formula_name(formula);
     let full_name = formula
         .get("full_name")
         .and_then(|f| f.as_str())
+        .or(qualified_name.as_deref())
         .unwrap_or("N/A");
     let version = formula
         .get("versions")
@@ -172,11 +210,9 @@ fn print_formula_info(_name: &str, formula: &Value) {
         .get("revision")
         .and_then(|r| r.as_u64())
         .unwrap_or(0);
-    let version_str = if revision > 0 {
-        format!("{version}_{revision}")
-    } else {
-        version.to_string()
-    };
+    let version_str = PkgVersion::parse(version)
+        .with_revision(u32::try_from(revision).unwrap_or(u32::MAX))
+        .describe();
     let license = formula
         .get("license")
         .and_then(|l| l.as_str())
@@ -187,15 +223,25 @@ fn print_formula_info(_name: &str, formula: &Value) {
         .unwrap_or("N/A");
 
     // Header
-    println!("{}", format!("Formula: {full_name}").green().bold());
+    println!(
+        "{}{}",
+        format!("Formula: {full_name}").green().bold(),
+        override_marker(local_override)
+    );
+    if let Some(obj) = formula.as_object() {
+        match FormulaLifecycle::from_json(full_name, obj) {
+            FormulaLifecycle::Disabled(msg) => println!("{}", msg.red()),
+            FormulaLifecycle::Deprecated(msg) => println!("{}", msg.yellow()),
+            FormulaLifecycle::Active => {}
+        }
+    }
 
     // Summary table
-    let mut table = prettytable::Table::new();
-    table.set_format(*prettytable::format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
-    table.add_row(prettytable::row!["Version", version_str]);
-    table.add_row(prettytable::row!["License", license]);
-    table.add_row(prettytable::row!["Homepage", homepage]);
-    table.printstd();
+    let mut table = summary_table();
+    table.add_row(["Version", &version_str]);
+    table.add_row(["License", license]);
+    table.add_row(["Homepage", homepage]);
+    table.print();
 
     // Detailed sections
     if let Some(desc) = formula.get("desc").and_then(|d| d.as_str()) {
@@ -212,8 +258,8 @@ fn print_formula_info(_name: &str, formula: &Value) {
     }
 
     // Combined Dependencies Section
-    let mut dep_table = prettytable::Table::new();
-    dep_table.set_format(*prettytable::format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
+    let mut dep_table =
+        Table::new(vec![Column::left(""), Column::left(""), Column::left("")]).without_header();
     let mut has_deps = false;
 
     let mut add_deps = |title: &str, key: &str, tag: &str| {
@@ -228,7 +274,7 @@ fn print_formula_info(_name: &str, formula: &Value) {
                     } else {
                         "".to_string()
                     };
-                    dep_table.add_row(prettytable::row![display_title, d, display_tag]);
+                    dep_table.add_row([display_title.to_string(), d.to_string(), display_tag]);
                 }
             }
         }
@@ -246,112 +292,191 @@ fn print_formula_info(_name: &str, formula: &Value) {
 
     if has_deps {
         println!("\n{}", "Dependencies".blue().bold());
-        dep_table.printstd();
+        dep_table.print();
     }
 
-    // Installation hint
+    let macos_constraints: Vec<MacOSConstraint> = formula
+        .get("requirements")
+        .and_then(|r| r.as_array())
+        .into_iter()
+        .flatten()
+        .filter(|r| r.get("name").and_then(|n| n.as_str()) == Some("macos"))
+        .filter_map(|r| r.get("version").and_then(|v| v.as_str()))
+        .filter_map(|v| MacOSConstraint::parse(v, Comparator::Ge))
+        .collect();
+    if !macos_constraints.is_empty() {
+        println!("\n{}", "Requirements".blue().bold());
+        println!("  {}", macos_requirement_label(&macos_constraints));
+    }
+
+    // Installation hint; tapped formulae are installed (and looked up) by their qualified name.
+    let install_name = qualified_name.as_deref().unwrap_or(full_name);
     println!("\n{}", "Installation".blue().bold());
-    println!(
-        "  {} install {}",
-        "sps".cyan(),
-        formula
-            .get("name")
-            .and_then(|n| n.as_str())
-            .unwrap_or(full_name) // Use short name if available
-    );
+    match KegRegistry::new(config.clone()).get_installed_keg(install_name) {
+        Ok(Some(keg)) => {
+            println!(
+                "  Installed {} at {}",
+                keg.version.describe(),
+                keg.path.display()
+            );
+            if let Some(reason) = InstallReason::read(&keg.path) {
+                println!("  {}", reason.describe());
+            }
+        }
+        _ => println!("  {} install {}", "sps".cyan(), install_name),
+    }
+}
+
+/// The key/value table at the top of `info`, never truncated.
+fn summary_table() -> Table {
+    Table::new(vec![Column::left(""), Column::left("")]).without_header()
 }
 
 /// Prints cask information in a formatted table
-fn print_cask_info(name: &str, cask: &Value) {
+fn print_cask_info(name: &str, cask: &Value, config: &Config, local_override: bool) {
     // Header
-    println!("{}", format!("Cask: {name}").green().bold());
+    println!(
+        "{}{}",
+        format!("Cask: {name}").green().bold(),
+        override_marker(local_override)
+    );
 
     // Summary table
-    let mut table = prettytable::Table::new();
-    table.set_format(*prettytable::format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
+    let mut table = summary_table();
     if let Some(names) = cask.get("name").and_then(|n| n.as_array()) {
         if let Some(first) = names.first().and_then(|s| s.as_str()) {
-            table.add_row(prettytable::row!["Name", first]);
+            table.add_row(["Name", first]);
         }
     }
     if let Some(desc) = cask.get("desc").and_then(|d| d.as_str()) {
-        table.add_row(prettytable::row!["Description", desc]);
+        table.add_row(["Description", desc]);
     }
     if let Some(homepage) = cask.get("homepage").and_then(|h| h.as_str()) {
-        table.add_row(prettytable::row!["Homepage", homepage]);
+        table.add_row(["Homepage", homepage]);
     }
     if let Some(version) = cask.get("version").and_then(|v| v.as_str()) {
-        table.add_row(prettytable::row!["Version", version]);
+        table.add_row(["Version", version]);
     }
     if let Some(url) = cask.get("url").and_then(|u| u.as_str()) {
-        table.add_row(prettytable::row!["Download URL", url]);
+        table.add_row(["Download URL", url]);
     }
     // Add SHA if present
     if let Some(sha) = cask.get("sha256").and_then(|s| s.as_str()) {
         if !sha.is_empty() {
-            table.add_row(prettytable::row!["SHA256", sha]);
+            table.add_row(["SHA256", sha]);
         }
     }
-    table.printstd();
+    table.print();
 
     // Dependencies Section
     if let Some(deps) = cask.get("depends_on").and_then(|d| d.as_object()) {
-        let mut dep_table = prettytable::Table::new();
-        dep_table.set_format(*prettytable::format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
+        let mut dep_table = Table::new(vec![
+            Column::left("").style(|s| s.yellow()),
+            Column::left(""),
+        ])
+        .without_header();
         let mut has_deps = false;
 
         if let Some(formulas) = deps.get("formula").and_then(|f| f.as_array()) {
             if !formulas.is_empty() {
                 has_deps = true;
-                dep_table.add_row(prettytable::row![
-                    "Formula".yellow(),
+                dep_table.add_row([
+                    "Formula".to_string(),
                     formulas
                         .iter()
                         .map(|v| v.as_str().unwrap_or(""))
                         .collect::<Vec<_>>()
-                        .join(", ")
+                        .join(", "),
                 ]);
             }
         }
         if let Some(casks) = deps.get("cask").and_then(|c| c.as_array()) {
             if !casks.is_empty() {
                 has_deps = true;
-                dep_table.add_row(prettytable::row![
-                    "Cask".yellow(),
+                dep_table.add_row([
+                    "Cask".to_string(),
                     casks
                         .iter()
                         .map(|v| v.as_str().unwrap_or(""))
                         .collect::<Vec<_>>()
-                        .join(", ")
+                        .join(", "),
                 ]);
             }
         }
         if let Some(macos) = deps.get("macos") {
             has_deps = true;
-            let macos_str = match macos {
-                Value::String(s) => s.clone(),
-                Value::Array(arr) => arr
-                    .iter()
-                    .map(|v| v.as_str().unwrap_or(""))
-                    .collect::<Vec<_>>()
-                    .join(" or "),
-                _ => "Unknown".to_string(),
+            let constraints = serde_json::from_value::<MacOSReq>(macos.clone())
+                .map(|req| macos::cask_constraints(&req))
+                .unwrap_or_default();
+            let macos_str = if constraints.is_empty() {
+                macos.to_string()
+            } else {
+                macos_requirement_label(&constraints)
             };
-            dep_table.add_row(prettytable::row!["macOS".yellow(), macos_str]);
+            dep_table.add_row(["macOS".to_string(), macos_str]);
         }
 
         if has_deps {
             println!("\n{}", "Dependencies".blue().bold());
-            dep_table.printstd();
+            dep_table.print();
         }
     }
 
-    // Installation hint
+    // Installation hint, or what the receipt of the installed version records.
     println!("\n{}", "Installation".blue().bold());
-    println!(
-        "  {} install --cask {}", // Always use --cask for clarity
-        "sps".cyan(),
-        name // Use the token 'name' passed to the function
-    );
+    let token = cask.get("token").and_then(Value::as_str).unwrap_or(name);
+    match cask::installed_receipt(config, token) {
+        Some((path, receipt)) => print_cask_receipt(&path, &receipt),
+        None => println!(
+            "  {} install --cask {}", // Always use --cask for clarity
+            "sps".cyan(),
+            name // Use the token 'name' passed to the function
+        ),
+    }
+}
+
+/// The installed version of a cask as its receipt describes it.
+fn print_cask_receipt(path: &Path, receipt: &CaskInstallManifest) {
+    println!("  Installed at {}", path.display());
+    match receipt.installed_at_rfc3339() {
+        Some(time) => println!("  Version {} (installed {})", receipt.version, time),
+        None => println!("  Version {}", receipt.version),
+    }
+    if let Some(url) = &receipt.source_url {
+        println!("  From {url}");
+    }
+    if let Some(sha) = &receipt.sha256 {
+        println!("  SHA256 {sha}");
+    }
+    for installer in receipt.manual_installers() {
+        println!(
+            "  {} open {} to finish the installation",
+            "Staged, manual action required:".yellow(),
+            installer.display()
+        );
+    }
+    for artifact in &receipt.artifacts {
+        match artifact {
+            InstalledArtifact::App { path } => println!("  App {}", path.display()),
+            InstalledArtifact::BinaryLink { link_path, .. } => {
+                println!("  Binary {}", link_path.display())
+            }
+            InstalledArtifact::PkgUtilReceipt { id } => println!("  Package {id}"),
+            _ => {}
+        }
+    }
 }
 // Removed is_bottle_available check
+
+/// `constraints` joined with "or", marked when the running system does not satisfy them.
+fn macos_requirement_label(constraints: &[MacOSConstraint]) -> String {
+    let label = constraints
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(" or ");
+    match macos::unmet_requirement(constraints) {
+        Some(_) => format!("{} {}", label, "(not met on this system)".red()),
+        None => label,
+    }
+}
@@ -1,27 +1,47 @@
 // sps-cli/src/cli/install.rs
 
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 use clap::Args;
 use sps_common::cache::Cache;
+use sps_common::cancel::CancellationToken;
 use sps_common::config::Config;
-use sps_common::error::Result;
+use sps_common::error::{Result, SpsError};
+use sps_common::model::Cask;
+use sps_core::{normalize_name, resolve_token, KindHint, NameIndexes, Resolved};
+use sps_net::fetch::api;
 use tracing::instrument;
 
 // Import pipeline components from the new module
+use crate::cli::cleanup::reap_interrupted;
+use crate::cli::graph::GraphFormat;
+use crate::cli::output;
 use crate::cli::pipeline::{CommandType, PipelineExecutor, PipelineFlags};
 
 // Keep the Args struct specific to 'install' if needed, or reuse a common one
 #[derive(Debug, Args)]
 pub struct InstallArgs {
-    #[arg(required = true)]
+    #[arg(required_unless_present = "from_plan")]
     names: Vec<String>,
 
     // Keep flags relevant to install/pipeline
     #[arg(long)]
     skip_deps: bool, // Note: May not be fully supported by core resolution yet
-    #[arg(long, help = "Force install specified targets as casks")]
+    #[arg(
+        long,
+        help = "Force install specified targets as casks; a target may also be a path to a cask JSON definition"
+    )]
     cask: bool,
+    #[arg(
+        long,
+        value_name = "URL",
+        requires = "cask",
+        help = "With --cask and one token, fetch the cask definition from this URL instead of the API"
+    )]
+    cask_url: Option<String>,
     #[arg(long, help = "Force install specified targets as formulas")]
     formula: bool,
     #[arg(long)]
@@ -33,6 +53,105 @@ pub struct InstallArgs {
         help = "Force building the formula from source, even if a bottle is available"
     )]
     build_from_source: bool,
+    #[arg(
+        long = "HEAD",
+        conflicts_with_all = ["cask", "only_dependencies", "from_plan"],
+        help = "Build the formula from the latest commit of its git repository (its `head` source)"
+    )]
+    head: bool,
+    #[arg(
+        long,
+        help = "Install the dependencies of the specified targets, but not the targets themselves"
+    )]
+    only_dependencies: bool,
+    #[arg(
+        long,
+        help = "Don't wait for system extension approval after installing casks that need it"
+    )]
+    no_wait: bool,
+    #[arg(
+        long,
+        conflicts_with = "only_missing",
+        help = "Plan as if nothing were installed, producing the complete set of packages"
+    )]
+    ignore_installed: bool,
+    #[arg(
+        long,
+        help = "Only install what is missing; never reinstall, upgrade or relink existing kegs"
+    )]
+    only_missing: bool,
+    #[arg(
+        long,
+        help = "Warn instead of failing when the host does not meet a package's macOS requirement"
+    )]
+    ignore_requirements: bool,
+    #[arg(
+        long,
+        help = "Give up a target at its first failed package instead of installing the rest of it"
+    )]
+    fail_fast: bool,
+    #[arg(
+        long,
+        help = "Also fail packages whose optional or recommended dependency failed, instead of \
+                installing them without it"
+    )]
+    strict_deps: bool,
+    #[arg(
+        long,
+        help = "Resolve and print the plan without downloading or installing"
+    )]
+    dry_run: bool,
+    #[arg(
+        long,
+        value_name = "PATH",
+        requires = "dry_run",
+        help = "With --dry-run, write the resolved plan as JSON for a later --from-plan"
+    )]
+    emit_plan: Option<PathBuf>,
+    #[arg(
+        long,
+        value_enum,
+        value_name = "FORMAT",
+        requires = "dry_run",
+        help = "With --dry-run, print the resolved dependency graph as Graphviz DOT or Mermaid"
+    )]
+    graph: Option<GraphFormat>,
+    #[arg(
+        long,
+        requires = "graph",
+        help = "With --graph, show installed dependencies as leaves without their own dependencies"
+    )]
+    prune_installed: bool,
+    #[arg(
+        long,
+        value_name = "PATH",
+        conflicts_with_all = ["names", "dry_run"],
+        help = "Install exactly the packages recorded in a plan file, without re-resolving"
+    )]
+    from_plan: Option<PathBuf>,
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "Serve live progress as newline-delimited JSON on this Unix socket"
+    )]
+    status_socket: Option<PathBuf>,
+    #[arg(
+        long,
+        conflicts_with = "force",
+        help = "Take over casks whose apps already exist in /Applications instead of reinstalling them"
+    )]
+    adopt: bool,
+    #[arg(
+        long,
+        help = "Replace apps in /Applications that were not installed by sps"
+    )]
+    force: bool,
+    #[arg(
+        long,
+        value_name = "DIR",
+        help = "Install into this prefix instead of the configured one"
+    )]
+    prefix: Option<PathBuf>,
     // Worker/Queue size flags might belong here or be global CLI flags
     // #[arg(long, value_name = "sps_WORKERS")]
     // max_workers: Option<usize>,
@@ -43,27 +162,120 @@ pub struct InstallArgs {
 impl InstallArgs {
     #[instrument(skip(self, config, cache), fields(targets = ?self.names))]
     pub async fn run(&self, config: &Config, cache: Arc<Cache>) -> Result<()> {
-        println!("Installing: {:?}", self.names); // User feedback
-
+        let relocated;
+        let config = match &self.prefix {
+            Some(prefix) => {
+                let mut config = config.clone();
+                config.set_prefix(prefix.clone());
+                relocated = config;
+                &relocated
+            }
+            None => config,
+        };
         // --- Argument Validation (moved from old run) ---
-        if self.formula && self.cask {
-            return Err(sps_common::error::SpsError::Generic(
-                "Cannot use --formula and --cask together.".to_string(),
-            ));
+        let kind_hint = KindHint::from_flags(self.formula, self.cask)?;
+        if self.graph.is_some() {
+            if output::json_lines() {
+                return Err(SpsError::Generic(
+                    "--graph and --json-lines both write to stdout; pick one".to_string(),
+                ));
+            }
+            output::reserve_stdout();
+        }
+        if !self.dry_run {
+            reap_interrupted(config);
         }
         // Add validation for skip_deps if needed
 
+        // --- Casks defined by a local file or a URL ---
+        // Other names are cleaned up as pasted (whitespace, `homebrew/cask/`, brew.sh URLs);
+        // a URL or prefix naming the kind narrows the lookup of that name.
+        let mut cask_definitions = HashMap::new();
+        let mut names = Vec::with_capacity(self.names.len());
+        let mut name_hints = Vec::with_capacity(self.names.len());
+        for name in &self.names {
+            if kind_hint != KindHint::Formula && name.ends_with(".json") && !name.contains("://") {
+                let cask = load_cask_file(Path::new(name))?;
+                names.push(cask.token.clone());
+                name_hints.push(KindHint::Cask);
+                cask_definitions.insert(cask.token.clone(), Arc::new(cask));
+            } else {
+                let (name, hint) = normalize_name(name, kind_hint)?;
+                names.push(name);
+                name_hints.push(hint);
+            }
+        }
+        // The pipeline takes one hint for all targets, so a kind implied per name only carries
+        // over when every name implies the same one.
+        let kind_hint = match name_hints.split_first() {
+            Some((&first, rest)) if rest.iter().all(|&hint| hint == first) => first,
+            _ => kind_hint,
+        };
+        if let Some(url) = &self.cask_url {
+            let [token] = names.as_slice() else {
+                return Err(SpsError::Generic(
+                    "--cask-url takes exactly one cask token".to_string(),
+                ));
+            };
+            let cask = Cask::from_definition(api::fetch_cask_definition(url).await?, url)?;
+            if &cask.token != token {
+                return Err(SpsError::ValidationError(format!(
+                    "{url}: defines cask '{}', not '{token}'",
+                    cask.token
+                )));
+            }
+            cask_definitions.insert(token.clone(), Arc::new(cask));
+        }
+
         // --- Prepare Pipeline Flags ---
         let flags = PipelineFlags {
             build_from_source: self.build_from_source,
             include_optional: self.include_optional,
             skip_recommended: self.skip_recommended,
+            only_dependencies: self.only_dependencies,
+            kind_hint,
+            no_wait: self.no_wait,
+            ignore_installed: self.ignore_installed,
+            only_missing: self.only_missing,
+            ignore_requirements: self.ignore_requirements,
+            fail_fast: self.fail_fast,
+            strict_deps: self.strict_deps,
+            dry_run: self.dry_run,
+            emit_plan: self.emit_plan.clone(),
+            graph: self.graph,
+            prune_installed: self.prune_installed,
+            status_socket: self.status_socket.clone(),
+            adopt: self.adopt,
+            force: self.force,
+            cask_definitions,
+            head: self.head,
+            fetch_head: false,
+            cancel: CancellationToken::new(),
             // Add other flags...
         };
 
+        if let Some(plan_path) = &self.from_plan {
+            return PipelineExecutor::execute_plan_file(plan_path, config, cache, &flags).await;
+        }
+        output::println(format!("Installing: {names:?}")); // User feedback
+
         // --- Determine Initial Targets based on --formula/--cask flags ---
-        // (This logic might be better inside plan_package_operations based on CommandType)
-        let initial_targets = self.names.clone(); // For install, all names are initial targets
+        // Aliases and old names are mapped to their canonical name here. Names the cached
+        // metadata doesn't know are passed through so the pipeline can still ask the API.
+        let indexes = NameIndexes::load(&cache);
+        let mut initial_targets = Vec::with_capacity(names.len());
+        for (name, &hint) in names.iter().zip(&name_hints) {
+            if flags.cask_definitions.contains_key(name) {
+                initial_targets.push(name.clone());
+                continue;
+            }
+            match resolve_token(name, hint, &indexes) {
+                // Formula names and cask tokens are lowercase, so `WGET` still finds `wget`
+                // when it has to be asked of the API.
+                Resolved::NotFound => initial_targets.push(name.to_ascii_lowercase()),
+                resolved => initial_targets.push(resolved.into_target(name)?.1),
+            }
+        }
 
         // --- Execute the Pipeline ---
         PipelineExecutor::execute_pipeline(
@@ -76,3 +288,18 @@ impl InstallArgs {
         .await
     }
 }
+
+/// Reads a cask definition from a local JSON file given as an install target.
+fn load_cask_file(path: &Path) -> Result<Cask> {
+    let source = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let source = source.display().to_string();
+    let text = fs::read_to_string(path).map_err(|e| {
+        SpsError::NotFound(format!(
+            "Cannot read cask definition {}: {e}",
+            path.display()
+        ))
+    })?;
+    let value = serde_json::from_str(&text)
+        .map_err(|e| SpsError::ValidationError(format!("{source}: not valid JSON: {e}")))?;
+    Cask::from_definition(value, &source)
+}
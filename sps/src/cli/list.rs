@@ -0,0 +1,84 @@
+//! Contains the logic for the `list` command.
+
+use std::sync::Arc;
+
+use clap::Args;
+use sps_common::cache::Cache;
+use sps_common::config::Config;
+use sps_common::error::Result;
+use sps_common::keg::{InstallReason, KegRegistry};
+use sps_core::{installed, InstalledPackageInfo, KindHint, PackageType};
+
+use crate::table::{Column, Table};
+
+#[derive(Args, Debug)]
+pub struct List {
+    /// Only list formulae
+    #[arg(long, conflicts_with = "cask")]
+    pub formula: bool,
+
+    /// Only list casks
+    #[arg(long)]
+    pub cask: bool,
+
+    /// Show why each formula is installed: on request, or as a dependency of which targets
+    #[arg(long)]
+    pub why: bool,
+}
+
+impl List {
+    /// Lists the current keg of every installed formula and every installed cask, by name.
+    /// Formula kegs whose opt link is missing are marked `(unlinked)`; `install` relinks them.
+    pub async fn run(&self, config: &Config, _cache: Arc<Cache>) -> Result<()> {
+        let hint = KindHint::from_flags(self.formula, self.cask)?;
+        let mut packages = installed::get_installed_packages(config).await?;
+        packages.retain(|p| match hint {
+            KindHint::Formula => p.pkg_type == PackageType::Formula,
+            KindHint::Cask => p.pkg_type == PackageType::Cask,
+            KindHint::Any => true,
+        });
+        // Formulae first, then casks, each by name.
+        packages.sort_by_key(|p| (p.pkg_type == PackageType::Cask, p.name.clone()));
+
+        let mut columns = vec![
+            Column::left("Name"),
+            Column::right("Version"),
+            Column::left("Type"),
+        ];
+        if self.why {
+            columns.push(Column::left("Installed because").truncate());
+        }
+        let mut table = Table::new(columns);
+        let keg_registry = KegRegistry::new(config.clone());
+        for package in &packages {
+            let kind = match package.pkg_type {
+                PackageType::Formula => "formula",
+                PackageType::Cask => "cask",
+            };
+            let version = if package.pkg_type == PackageType::Formula
+                && !keg_registry.is_keg_linked(&package.name, &package.path)
+            {
+                format!("{} (unlinked)", package.version)
+            } else {
+                package.version.clone()
+            };
+            let mut row = vec![package.name.clone(), version, kind.to_string()];
+            if self.why {
+                row.push(why(package));
+            }
+            table.add_row(row);
+        }
+        table.print();
+        Ok(())
+    }
+}
+
+/// The recorded install reason of a formula keg. Casks are only ever installed on request.
+fn why(package: &InstalledPackageInfo) -> String {
+    match package.pkg_type {
+        PackageType::Cask => "installed on request".to_string(),
+        PackageType::Formula => InstallReason::read(&package.path)
+            .map(|reason| reason.describe())
+            .unwrap_or_else(|| "not recorded".to_string()),
+    }
+}
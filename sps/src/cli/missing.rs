@@ -0,0 +1,124 @@
+//! Contains the logic for the `missing` command.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::Arc;
+
+use clap::Args;
+use colored::Colorize;
+use sps_common::cache::Cache;
+use sps_common::cancel::CancellationToken;
+use sps_common::config::Config;
+use sps_common::dependency::runtime_dependencies;
+use sps_common::error::{Result, SpsError};
+use sps_common::formulary::Formulary;
+use sps_common::keg::{InstalledKeg, KegRegistry};
+use sps_core::KindHint;
+
+use crate::cli::pipeline::{CommandType, PipelineExecutor, PipelineFlags};
+use crate::ui;
+
+#[derive(Args, Debug)]
+pub struct Missing {
+    /// Installed formulae to check (defaults to all installed formulae)
+    pub names: Vec<String>,
+
+    /// Install the missing dependencies right away
+    #[arg(long)]
+    pub install: bool,
+}
+
+impl Missing {
+    /// Reports runtime dependencies of installed kegs that are no longer installed.
+    pub async fn run(&self, config: &Config, cache: Arc<Cache>) -> Result<()> {
+        let keg_registry = KegRegistry::new(config.clone());
+        let formulary = Formulary::new(config.clone());
+
+        let kegs: Vec<InstalledKeg> = if self.names.is_empty() {
+            keg_registry.list_current_kegs()?
+        } else {
+            let mut kegs = Vec::new();
+            for name in &self.names {
+                match keg_registry.get_installed_keg(name)? {
+                    Some(keg) => kegs.push(keg),
+                    None => {
+                        return Err(SpsError::NotFound(format!(
+                            "Formula '{name}' is not installed."
+                        )))
+                    }
+                }
+            }
+            kegs
+        };
+
+        // dependent -> missing dependencies
+        let mut missing_by_dependent: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+        for keg in &kegs {
+            for dep in runtime_dependencies(keg, &formulary) {
+                if keg_registry.get_installed_keg(&dep)?.is_none() {
+                    missing_by_dependent
+                        .entry(keg.name.clone())
+                        .or_default()
+                        .insert(dep);
+                }
+            }
+        }
+
+        if missing_by_dependent.is_empty() {
+            println!("{} No missing dependencies.", ui::ok_mark());
+            return Ok(());
+        }
+
+        for (dependent, deps) in &missing_by_dependent {
+            let deps: Vec<&str> = deps.iter().map(String::as_str).collect();
+            println!("{}: {}", dependent.cyan(), deps.join(" ").red());
+        }
+
+        let all_missing: Vec<String> = missing_by_dependent
+            .into_values()
+            .flatten()
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect();
+
+        if self.install {
+            let flags = PipelineFlags {
+                build_from_source: false,
+                include_optional: false,
+                skip_recommended: false,
+                only_dependencies: false,
+                kind_hint: KindHint::Any,
+                no_wait: false,
+                ignore_installed: false,
+                only_missing: false,
+                ignore_requirements: false,
+                fail_fast: false,
+                strict_deps: false,
+                dry_run: false,
+                emit_plan: None,
+                graph: None,
+                prune_installed: false,
+                status_socket: None,
+                adopt: false,
+                force: false,
+                cask_definitions: HashMap::new(),
+                head: false,
+                fetch_head: false,
+                cancel: CancellationToken::new(),
+            };
+            return PipelineExecutor::execute_pipeline(
+                &all_missing,
+                CommandType::Install,
+                config,
+                cache,
+                &flags,
+            )
+            .await;
+        }
+
+        Err(SpsError::DependencyError(format!(
+            "{} missing dependencies: {}",
+            all_missing.len(),
+            all_missing.join(", ")
+        )))
+    }
+}
@@ -0,0 +1,174 @@
+//! Contains the logic for the `options` command.
+
+use std::sync::Arc;
+
+use clap::Args;
+use colored::Colorize;
+use serde_json::Value;
+use sps_common::cache::Cache;
+use sps_common::config::Config;
+use sps_common::error::Result;
+use sps_common::formulary::Formulary;
+use sps_common::keg::KegRegistry;
+use sps_common::model::formula::{qualified_formula_name, split_tap_name, Formula};
+use sps_core::{build, resolve_token, KindHint, NameIndexes, Resolved};
+
+/// Leading description words two formulae must share to count as variants of each other.
+const DESC_STEM_WORDS: usize = 3;
+
+#[derive(Args, Debug)]
+pub struct Options {
+    /// Name of the formula
+    pub name: String,
+}
+
+impl Options {
+    /// Lists a formula's declared options, its per-platform variations and its sibling formulae
+    /// (`foo@lts`, `foo-mini`, ...), marking installed siblings.
+    pub async fn run(&self, config: &Config, cache: Arc<Cache>) -> Result<()> {
+        let indexes = NameIndexes::load(&cache);
+        let name = match resolve_token(&self.name, KindHint::Formula, &indexes) {
+            Resolved::NotFound => self.name.clone(),
+            resolved => resolved.into_target(&self.name)?.1,
+        };
+        let formula = Formulary::new(config.clone()).load_formula(&name)?;
+        let keg_registry = KegRegistry::new(config.clone());
+
+        println!("{}", format!("Formula: {}", formula.name()).green().bold());
+
+        println!("{}", "Options".bold());
+        let options = declared_options(&formula);
+        if options.is_empty() {
+            println!("  (none)");
+        }
+        for (option, description) in options {
+            match description {
+                Some(description) => println!("  {}  {}", option.cyan(), description),
+                None => println!("  {}", option.cyan()),
+            }
+        }
+
+        println!("{}", "Platform variations".bold());
+        let host = build::formula::host_platform();
+        let variations = platform_variations(&formula);
+        if variations.is_empty() {
+            println!("  (none)");
+        }
+        for (platform, fields) in variations {
+            let marker = if platform == host {
+                format!(" {}", "(this machine)".yellow())
+            } else {
+                String::new()
+            };
+            println!("  {}{}: {}", platform.cyan(), marker, fields.join(", "));
+        }
+
+        println!("{}", "Related formulae".bold());
+        let siblings = sibling_formulae(&formula, &cache);
+        if siblings.is_empty() {
+            println!("  (none)");
+        }
+        for (sibling, desc) in siblings {
+            let installed = matches!(keg_registry.get_installed_keg(&sibling), Ok(Some(_)));
+            let marker = if installed {
+                format!(" {}", "(installed)".green())
+            } else {
+                String::new()
+            };
+            match desc {
+                Some(desc) => println!("  {}{}  {}", sibling.cyan(), marker, desc),
+                None => println!("  {}{}", sibling.cyan(), marker),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The `options` array of the formula JSON as `(flag, description)` pairs.
+fn declared_options(formula: &Formula) -> Vec<(String, Option<String>)> {
+    formula
+        .extra
+        .get("options")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| {
+            let option = entry.get("option").and_then(Value::as_str)?;
+            let description = entry
+                .get("description")
+                .and_then(Value::as_str)
+                .filter(|d| !d.is_empty());
+            Some((option.to_string(), description.map(str::to_string)))
+        })
+        .collect()
+}
+
+/// Each platform tag in `variations` with the fields its entry overrides, sorted by tag.
+fn platform_variations(formula: &Formula) -> Vec<(String, Vec<String>)> {
+    let mut variations: Vec<(String, Vec<String>)> = formula
+        .extra
+        .get("variations")
+        .and_then(Value::as_object)
+        .into_iter()
+        .flatten()
+        .map(|(platform, entry)| {
+            let fields = entry
+                .as_object()
+                .map(|fields| fields.keys().cloned().collect())
+                .unwrap_or_default();
+            (platform.clone(), fields)
+        })
+        .collect();
+    variations.sort();
+    variations
+}
+
+/// The name up to any `@version` suffix, without the tap.
+fn base_name(name: &str) -> &str {
+    let short = split_tap_name(name).1;
+    short.split_once('@').map_or(short, |(base, _)| base)
+}
+
+/// The first few lowercase words of a description, ignoring punctuation.
+fn desc_stem(desc: &str) -> Vec<String> {
+    desc.split_whitespace()
+        .map(|word| {
+            word.trim_matches(|c: char| !c.is_alphanumeric())
+                .to_lowercase()
+        })
+        .filter(|word| !word.is_empty())
+        .take(DESC_STEM_WORDS)
+        .collect()
+}
+
+/// Formulae in the cached index that are versions of this one (same name before `@`) or
+/// variants of it (`<base>-<suffix>`, either way round, with the same description stem).
+fn sibling_formulae(formula: &Formula, cache: &Cache) -> Vec<(String, Option<String>)> {
+    let values: Vec<Value> = cache
+        .load_raw("formula.json")
+        .ok()
+        .and_then(|data| serde_json::from_str(&data).ok())
+        .unwrap_or_default();
+    let base = base_name(formula.name());
+    let stem = formula.desc.as_deref().map(desc_stem);
+
+    let mut siblings: Vec<(String, Option<String>)> = values
+        .iter()
+        .filter_map(|value| {
+            let name = qualified_formula_name(value)?;
+            if name == formula.name() {
+                return None;
+            }
+            let desc = value.get("desc").and_then(Value::as_str);
+            let other = base_name(&name);
+            let versioned = other == base;
+            let variant = (other.strip_prefix(base).is_some_and(|s| s.starts_with('-'))
+                || base.strip_prefix(other).is_some_and(|s| s.starts_with('-')))
+                && stem.is_some()
+                && desc.map(desc_stem) == stem;
+            (versioned || variant).then(|| (name, desc.map(str::to_string)))
+        })
+        .collect();
+    siblings.sort();
+    siblings
+}
@@ -0,0 +1,107 @@
+//! Contains the logic for the `outdated` command.
+
+use std::sync::Arc;
+
+use clap::Args;
+use colored::Colorize;
+use sps_common::cache::Cache;
+use sps_common::config::Config;
+use sps_common::error::{Result, SpsError};
+use sps_common::model::formula::FormulaLifecycle;
+use sps_common::model::InstallTargetIdentifier;
+use sps_core::{installed, update_check, PackageType};
+
+use crate::table::{Column, Table};
+
+#[derive(Args, Debug)]
+pub struct Outdated {
+    /// Only check these packages (default: everything installed)
+    pub names: Vec<String>,
+
+    /// Also check formulae installed with --HEAD for new upstream commits
+    #[arg(long = "fetch-HEAD")]
+    pub fetch_head: bool,
+
+    /// Only list formulae
+    #[arg(long, conflicts_with = "cask")]
+    pub formula: bool,
+
+    /// Only list casks
+    #[arg(long)]
+    pub cask: bool,
+
+    /// Also list casks that update themselves (`auto_updates`) or are `version :latest`
+    #[arg(long)]
+    pub greedy: bool,
+}
+
+impl Outdated {
+    /// Lists installed packages with a newer version as a table of name, installed and available
+    /// version. With `--greedy`, casks that update themselves are marked `auto_updates`.
+    /// Deprecated and disabled formulae are called out below the table, as `info` does.
+    pub async fn run(&self, config: &Config, cache: Arc<Cache>) -> Result<()> {
+        let mut packages = if self.names.is_empty() {
+            installed::get_installed_packages(config).await?
+        } else {
+            let mut packages = Vec::with_capacity(self.names.len());
+            for name in &self.names {
+                let package = installed::get_installed_package(name, config).await?;
+                packages.push(
+                    package
+                        .ok_or_else(|| SpsError::NotFound(format!("'{name}' is not installed")))?,
+                );
+            }
+            packages
+        };
+        if self.formula {
+            packages.retain(|p| p.pkg_type == PackageType::Formula);
+        } else if self.cask {
+            packages.retain(|p| p.pkg_type == PackageType::Cask);
+        }
+        let mut updates = update_check::check_for_updates(
+            &packages,
+            &cache,
+            config,
+            self.fetch_head,
+            self.greedy,
+        )
+        .await?;
+        updates.sort_by(|a, b| a.name.cmp(&b.name));
+        let mut columns = vec![
+            Column::left("Name").style(|s| s.bold()),
+            Column::right("Installed"),
+            Column::right("Available").style(|s| s.green()),
+        ];
+        if self.greedy {
+            columns.push(Column::left("Note").style(|s| s.yellow()));
+        }
+        let mut table = Table::new(columns);
+        let mut lifecycles = Vec::new();
+        for update in updates {
+            let auto_updates = match &update.target_definition {
+                InstallTargetIdentifier::Cask(cask) => cask.auto_updates == Some(true),
+                InstallTargetIdentifier::Formula(formula) => {
+                    lifecycles.push(formula.lifecycle());
+                    false
+                }
+            };
+            table.add_row([
+                update.name,
+                update.installed_version,
+                update.available_version,
+                if auto_updates { "auto_updates" } else { "" }.to_string(),
+            ]);
+        }
+        if !table.is_empty() {
+            table.print();
+        }
+        for lifecycle in lifecycles {
+            match lifecycle {
+                FormulaLifecycle::Disabled(msg) => println!("{}", msg.red()),
+                FormulaLifecycle::Deprecated(msg) => println!("{}", msg.yellow()),
+                FormulaLifecycle::Active => {}
+            }
+        }
+        Ok(())
+    }
+}
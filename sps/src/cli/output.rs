@@ -0,0 +1,670 @@
+//! Owns the terminal while the pipeline runs, so sixteen concurrent tasks don't print over each
+//! other.
+//!
+//! On a terminal, each package that is downloading or installing gets a line that updates in
+//! place (name, phase, percent). Otherwise a one-line count of packages per phase is printed every
+//! few seconds. With `-v` neither is used and every per-package line is printed as it happens.
+//! Tracing output goes through [`StderrWriter`], which hides the status lines while it writes.
+//!
+//! With `--json-lines`, stdout carries only newline-delimited JSON objects, one per event, and
+//! every human-readable line goes to stderr instead. Each object has a `type`:
+//!
+//! - `plan`: `version` (currently 1) and `nodes`, each with `name`, `kind` (`formula`/`cask`),
+//!   `action` (`install`/`upgrade`/`reinstall`) and `state` (`pending`). Printed once resolution is
+//!   complete.
+//! - `phase`: `name`, `phase` (`download`/`install`, `null` when skipped), `state`
+//!   (`started`/`finished`/`failed`/`skipped`) and, for failures, `error`.
+//! - `progress`: `name`, `phase`, `unit` (`bytes`/`files`), `current` and `total` (`null` when
+//!   unknown). At most one per package every [`PROGRESS_INTERVAL`].
+//! - `summary`: `succeeded`, `failed`, `skipped`, the final `nodes`, now carrying their end `state`
+//!   (`finished`/`failed`/`skipped`/`pending`) and any `error`, and `targets`: one per requested
+//!   target with its `name`, `state` (`finished`/`failed`/`skipped`) and the `failed` packages it
+//!   needed.
+//! - `changes`: what the run changed in the prefix: `installed` (`name`, `version`), `upgraded`
+//!   (`name`, `from`, `to`), `new_bins` (`name`, and `shadows`: the command of the same name
+//!   elsewhere on `PATH`, or `null`) and `disk_delta` in bytes. Follows the `summary`, and only
+//!   when something changed.
+
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, IsTerminal, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use colored::Colorize;
+use indicatif::{HumanBytes, MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+use serde::Serialize;
+use tracing::warn;
+use tracing_subscriber::fmt::MakeWriter;
+
+use crate::cli::changes::PrefixChanges;
+use crate::cli::status::{InstallEvent, InstallState, NodeStatus, Phase, TargetStatus};
+use crate::ui;
+
+/// How often the non-terminal summary line is printed.
+const SUMMARY_INTERVAL: Duration = Duration::from_secs(5);
+/// Minimum time between two `progress` objects for the same package with `--json-lines`.
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+/// The `version` of the `--json-lines` schema, bumped on incompatible changes.
+const JSON_LINES_VERSION: u32 = 1;
+
+static VERBOSE: AtomicBool = AtomicBool::new(false);
+static JSON_LINES: AtomicBool = AtomicBool::new(false);
+/// Set when stdout carries something else to be piped on (`install --dry-run --graph`).
+static STDOUT_RESERVED: AtomicBool = AtomicBool::new(false);
+/// Set while a coordinator is active and per-package lines are folded into its display.
+static CONDENSED: AtomicBool = AtomicBool::new(false);
+/// The live status lines, if any are being drawn.
+static TERMINAL: Mutex<Option<MultiProgress>> = Mutex::new(None);
+
+/// Records whether `-v` and `--json-lines` were given; call once at startup.
+pub fn init(verbose: bool, json_lines: bool) {
+    VERBOSE.store(verbose, Ordering::Relaxed);
+    JSON_LINES.store(json_lines, Ordering::Relaxed);
+}
+
+/// Whether stdout is reserved for `--json-lines` events.
+pub fn json_lines() -> bool {
+    JSON_LINES.load(Ordering::Relaxed)
+}
+
+/// Sends every human-readable line to stderr from now on, as with `--json-lines`, so the command
+/// can print machine-readable output alone on stdout.
+pub fn reserve_stdout() {
+    STDOUT_RESERVED.store(true, Ordering::Relaxed);
+}
+
+fn terminal() -> Option<MultiProgress> {
+    TERMINAL.lock().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+/// Prints a line to stdout above any live status lines, or to stderr with `--json-lines`.
+pub fn println(line: impl AsRef<str>) {
+    match terminal() {
+        Some(multi) => {
+            let _ = multi.println(line.as_ref());
+        }
+        None if json_lines() || STDOUT_RESERVED.load(Ordering::Relaxed) => {
+            eprintln!("{}", line.as_ref())
+        }
+        None => println!("{}", line.as_ref()),
+    }
+}
+
+/// Whether a per-package progress line ("Downloading bottle foo") should be printed itself.
+/// False while a coordinator shows the same information in condensed form.
+pub fn package_lines_enabled() -> bool {
+    !CONDENSED.load(Ordering::Relaxed)
+}
+
+/// Tracing writer for stderr that keeps log lines from tearing through the status lines.
+#[derive(Clone, Copy, Default)]
+pub struct StderrWriter;
+
+impl<'a> MakeWriter<'a> for StderrWriter {
+    type Writer = StderrWriter;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        *self
+    }
+}
+
+impl Write for StderrWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match terminal() {
+            Some(multi) => multi.suspend(|| io::stderr().write(buf)),
+            None => io::stderr().write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stderr().flush()
+    }
+}
+
+enum Mode {
+    /// `-v`: lines are printed by whoever produces them.
+    Lines,
+    Live {
+        multi: MultiProgress,
+        bars: HashMap<String, ProgressBar>,
+    },
+    Summary {
+        last: Instant,
+    },
+    /// `--json-lines`: events go to stdout as JSON; per-package lines are printed to stderr.
+    JsonLines {
+        last_progress: HashMap<String, Instant>,
+    },
+}
+
+/// One `--json-lines` object; see the module docs for the schema.
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum JsonLine<'a> {
+    Plan {
+        version: u32,
+        nodes: Vec<&'a NodeStatus>,
+    },
+    Phase {
+        name: &'a str,
+        phase: Option<Phase>,
+        state: Transition,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        error: Option<&'a str>,
+    },
+    Progress {
+        name: &'a str,
+        phase: Phase,
+        unit: &'static str,
+        current: u64,
+        total: Option<u64>,
+    },
+    Changes(&'a PrefixChanges),
+    Summary {
+        succeeded: usize,
+        failed: usize,
+        skipped: usize,
+        nodes: Vec<&'a NodeStatus>,
+        targets: &'a [TargetStatus],
+    },
+}
+
+#[derive(Serialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+enum Transition {
+    Started,
+    Finished,
+    Failed,
+    Skipped,
+}
+
+/// Writes `line` to stdout as one line of JSON.
+fn write_json_line(line: &JsonLine) {
+    let mut stdout = io::stdout().lock();
+    let written = serde_json::to_writer(&mut stdout, line)
+        .map_err(io::Error::from)
+        .and_then(|()| writeln!(stdout))
+        .and_then(|()| stdout.flush());
+    if let Err(e) = written {
+        warn!("Could not write a --json-lines event: {}", e);
+    }
+}
+
+/// Writes the `changes` object with `--json-lines`; does nothing otherwise.
+pub fn write_changes(changes: &PrefixChanges) {
+    if json_lines() {
+        write_json_line(&JsonLine::Changes(changes));
+    }
+}
+
+/// Turns [`InstallEvent`]s into terminal output for one pipeline run.
+pub struct OutputCoordinator {
+    mode: Mode,
+}
+
+impl OutputCoordinator {
+    /// Takes over the display for a run of `nodes`; with `--json-lines` this prints the `plan`.
+    pub fn start(nodes: &BTreeMap<String, NodeStatus>) -> Self {
+        let mode = if json_lines() {
+            write_json_line(&JsonLine::Plan {
+                version: JSON_LINES_VERSION,
+                nodes: nodes.values().collect(),
+            });
+            Mode::JsonLines {
+                last_progress: HashMap::new(),
+            }
+        } else if VERBOSE.load(Ordering::Relaxed) {
+            Mode::Lines
+        } else if io::stdout().is_terminal() {
+            let multi = MultiProgress::with_draw_target(ProgressDrawTarget::stdout());
+            *TERMINAL.lock().unwrap_or_else(|e| e.into_inner()) = Some(multi.clone());
+            Mode::Live {
+                multi,
+                bars: HashMap::new(),
+            }
+        } else {
+            Mode::Summary {
+                last: Instant::now(),
+            }
+        };
+        CONDENSED.store(
+            !matches!(mode, Mode::Lines | Mode::JsonLines { .. }),
+            Ordering::Relaxed,
+        );
+        Self { mode }
+    }
+
+    /// Updates the display for `event`; `nodes` already reflects it.
+    pub fn handle(&mut self, event: &InstallEvent, nodes: &BTreeMap<String, NodeStatus>) {
+        if let InstallEvent::Done {
+            succeeded,
+            failed,
+            targets,
+        } = event
+        {
+            if let Mode::JsonLines { .. } = self.mode {
+                write_json_line(&JsonLine::Summary {
+                    succeeded: *succeeded,
+                    failed: *failed,
+                    skipped: nodes
+                        .values()
+                        .filter(|n| n.state == InstallState::Skipped)
+                        .count(),
+                    nodes: nodes.values().collect(),
+                    targets,
+                });
+            }
+            return self.finish();
+        }
+        match &mut self.mode {
+            Mode::Lines => {}
+            Mode::JsonLines { last_progress } => match json_line(event) {
+                // A progress object that is due falls through and is written.
+                Some(JsonLine::Progress { name, .. })
+                    if !progress_due(last_progress, name, Instant::now()) => {}
+                Some(line) => {
+                    if let JsonLine::Phase { name, .. } = line {
+                        last_progress.remove(name);
+                    }
+                    write_json_line(&line);
+                }
+                None => {}
+            },
+            Mode::Live { multi, bars } => match event {
+                InstallEvent::Started { name, phase } => {
+                    let bar = bars
+                        .entry(name.clone())
+                        .or_insert_with(|| multi.add(new_bar(name)));
+                    bar.set_message(phase_label(*phase));
+                }
+                InstallEvent::Progress {
+                    name,
+                    downloaded,
+                    total,
+                } => {
+                    if let Some(bar) = bars.get(name) {
+                        bar.set_message(match total {
+                            Some(total) => {
+                                format!("downloading {:>3}%", downloaded * 100 / (*total).max(1))
+                            }
+                            None => format!("downloading {}", HumanBytes(*downloaded)),
+                        });
+                    }
+                }
+                InstallEvent::Pour {
+                    name,
+                    files,
+                    total_files,
+                    archive_read,
+                    archive_size,
+                    ..
+                } => {
+                    if let Some(bar) = bars.get(name) {
+                        bar.set_message(match total_files {
+                            Some(total) => format!(
+                                "pouring {:>3}% ({files}/{total} files)",
+                                files * 100 / (*total).max(1)
+                            ),
+                            None => format!(
+                                "pouring ~{:>2}% ({files} files)",
+                                archive_read * 100 / (*archive_size).max(1)
+                            ),
+                        });
+                    }
+                }
+                InstallEvent::Finished { name, phase } => match phase {
+                    Phase::Download => {
+                        if let Some(bar) = bars.get(name) {
+                            bar.set_message("queued");
+                        }
+                    }
+                    Phase::Install => {
+                        if let Some(bar) = bars.remove(name) {
+                            bar.finish_and_clear();
+                            multi.remove(&bar);
+                        }
+                        let _ = multi.println(format!("{} {}", ui::ok_mark(), name.cyan()));
+                    }
+                },
+                // The failure itself is logged where it happens.
+                InstallEvent::Failed { name, .. } | InstallEvent::Skipped { name } => {
+                    if let Some(bar) = bars.remove(name) {
+                        bar.finish_and_clear();
+                        multi.remove(&bar);
+                    }
+                }
+                InstallEvent::Status { .. } | InstallEvent::Done { .. } => {}
+            },
+            Mode::Summary { last } => {
+                if last.elapsed() >= SUMMARY_INTERVAL {
+                    *last = Instant::now();
+                    println!("{} sps::pipeline: {}", "INFO".green(), summary_line(nodes));
+                }
+            }
+        }
+    }
+
+    fn finish(&mut self) {
+        if let Mode::Live { multi, bars } = &mut self.mode {
+            for (_, bar) in bars.drain() {
+                bar.finish_and_clear();
+            }
+            let _ = multi.clear();
+            *TERMINAL.lock().unwrap_or_else(|e| e.into_inner()) = None;
+        }
+        self.mode = Mode::Lines;
+        CONDENSED.store(false, Ordering::Relaxed);
+    }
+}
+
+impl Drop for OutputCoordinator {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+
+/// Whether a `progress` object for `name` may be written at `now`, at most one per package every
+/// [`PROGRESS_INTERVAL`]; records it in `last_progress` if so.
+fn progress_due(last_progress: &mut HashMap<String, Instant>, name: &str, now: Instant) -> bool {
+    let due = last_progress
+        .get(name)
+        .is_none_or(|last| now.duration_since(*last) >= PROGRESS_INTERVAL);
+    if due {
+        last_progress.insert(name.to_string(), now);
+    }
+    due
+}
+
+/// The `--json-lines` object for a per-package event.
+fn json_line(event: &InstallEvent) -> Option<JsonLine<'_>> {
+    let phase = |name, phase, state, error| JsonLine::Phase {
+        name,
+        phase,
+        state,
+        error,
+    };
+    Some(match event {
+        InstallEvent::Started { name, phase: p } => {
+            phase(name, Some(*p), Transition::Started, None)
+        }
+        InstallEvent::Finished { name, phase: p } => {
+            phase(name, Some(*p), Transition::Finished, None)
+        }
+        InstallEvent::Failed {
+            name,
+            phase: p,
+            error,
+        } => phase(name, Some(*p), Transition::Failed, Some(error)),
+        InstallEvent::Skipped { name } => phase(name, None, Transition::Skipped, None),
+        InstallEvent::Progress {
+            name,
+            downloaded,
+            total,
+        } => JsonLine::Progress {
+            name,
+            phase: Phase::Download,
+            unit: "bytes",
+            current: *downloaded,
+            total: *total,
+        },
+        // Without a file count, the compressed bytes read are the best measure of the pour.
+        InstallEvent::Pour {
+            name,
+            files,
+            total_files,
+            archive_read,
+            archive_size,
+            ..
+        } => match total_files {
+            Some(total) => JsonLine::Progress {
+                name,
+                phase: Phase::Install,
+                unit: "files",
+                current: *files,
+                total: Some(*total),
+            },
+            None => JsonLine::Progress {
+                name,
+                phase: Phase::Install,
+                unit: "bytes",
+                current: *archive_read,
+                total: Some(*archive_size),
+            },
+        },
+        InstallEvent::Status { .. } | InstallEvent::Done { .. } => return None,
+    })
+}
+
+fn new_bar(name: &str) -> ProgressBar {
+    let bar = ProgressBar::new_spinner();
+    bar.set_style(
+        ProgressStyle::with_template("{spinner:.blue} {prefix:<28!} {msg}")
+            .unwrap_or_else(|_| ProgressStyle::default_spinner()),
+    );
+    bar.set_prefix(name.to_string());
+    bar.enable_steady_tick(Duration::from_millis(120));
+    bar
+}
+
+fn phase_label(phase: Phase) -> &'static str {
+    match phase {
+        Phase::Download => "downloading",
+        Phase::Install => "installing",
+    }
+}
+
+/// e.g. "12 downloading, 3 installing, 5 done".
+fn summary_line(nodes: &BTreeMap<String, NodeStatus>) -> String {
+    let mut counts: Vec<(&str, usize)> = [
+        ("pending", InstallState::Pending),
+        ("downloading", InstallState::Downloading),
+        ("queued", InstallState::Downloaded),
+        ("installing", InstallState::Installing),
+        ("done", InstallState::Finished),
+        ("failed", InstallState::Failed),
+        ("skipped", InstallState::Skipped),
+    ]
+    .into_iter()
+    .map(|(label, state)| (label, nodes.values().filter(|n| n.state == state).count()))
+    .collect();
+    counts.retain(|(_, count)| *count > 0);
+    counts
+        .iter()
+        .map(|(label, count)| format!("{count} {label}"))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use super::*;
+
+    fn nodes(states: &[InstallState]) -> BTreeMap<String, NodeStatus> {
+        states
+            .iter()
+            .enumerate()
+            .map(|(i, state)| {
+                let name = format!("pkg{i}");
+                let node = NodeStatus {
+                    name: name.clone(),
+                    kind: "formula",
+                    action: "install",
+                    state: *state,
+                    error: None,
+                };
+                (name, node)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn the_summary_counts_packages_per_phase_in_pipeline_order() {
+        use InstallState::*;
+        let nodes = nodes(&[
+            Finished,
+            Downloading,
+            Installing,
+            Downloading,
+            Finished,
+            Failed,
+        ]);
+
+        assert_eq!(
+            summary_line(&nodes),
+            "2 downloading, 1 installing, 2 done, 1 failed"
+        );
+    }
+
+    #[test]
+    fn the_summary_of_nothing_is_empty() {
+        assert_eq!(summary_line(&BTreeMap::new()), "");
+    }
+
+    fn to_json(event: &InstallEvent) -> Option<Value> {
+        json_line(event).map(|line| serde_json::to_value(&line).unwrap())
+    }
+
+    #[test]
+    fn phase_transitions_carry_name_phase_state_and_any_error() {
+        let started = InstallEvent::Started {
+            name: "jq".into(),
+            phase: Phase::Download,
+        };
+        let failed = InstallEvent::Failed {
+            name: "jq".into(),
+            phase: Phase::Install,
+            error: "disk full".into(),
+        };
+        let skipped = InstallEvent::Skipped { name: "jq".into() };
+
+        assert_eq!(
+            to_json(&started).unwrap(),
+            json!({"type": "phase", "name": "jq", "phase": "download", "state": "started"})
+        );
+        assert_eq!(
+            to_json(&failed).unwrap(),
+            json!({
+                "type": "phase", "name": "jq", "phase": "install", "state": "failed",
+                "error": "disk full"
+            })
+        );
+        assert_eq!(
+            to_json(&skipped).unwrap(),
+            json!({"type": "phase", "name": "jq", "phase": null, "state": "skipped"})
+        );
+    }
+
+    #[test]
+    fn progress_is_in_bytes_while_downloading_and_files_while_pouring() {
+        let download = InstallEvent::Progress {
+            name: "jq".into(),
+            downloaded: 10,
+            total: None,
+        };
+        let pour = |total_files| InstallEvent::Pour {
+            name: "jq".into(),
+            files: 3,
+            total_files,
+            bytes: 4096,
+            archive_read: 700,
+            archive_size: 1000,
+        };
+
+        assert_eq!(
+            to_json(&download).unwrap(),
+            json!({
+                "type": "progress", "name": "jq", "phase": "download", "unit": "bytes",
+                "current": 10, "total": null
+            })
+        );
+        assert_eq!(
+            to_json(&pour(Some(8))).unwrap(),
+            json!({
+                "type": "progress", "name": "jq", "phase": "install", "unit": "files",
+                "current": 3, "total": 8
+            })
+        );
+        // Without a file count the compressed bytes read stand in.
+        assert_eq!(
+            to_json(&pour(None)).unwrap(),
+            json!({
+                "type": "progress", "name": "jq", "phase": "install", "unit": "bytes",
+                "current": 700, "total": 1000
+            })
+        );
+    }
+
+    #[test]
+    fn status_and_done_have_no_per_package_object() {
+        assert!(to_json(&InstallEvent::Status { nodes: Vec::new() }).is_none());
+        assert!(to_json(&InstallEvent::Done {
+            succeeded: 1,
+            failed: 0,
+            targets: Vec::new(),
+        })
+        .is_none());
+    }
+
+    #[test]
+    fn progress_is_throttled_per_package() {
+        let mut last = HashMap::new();
+        let start = Instant::now();
+
+        assert!(progress_due(&mut last, "jq", start));
+        assert!(!progress_due(
+            &mut last,
+            "jq",
+            start + PROGRESS_INTERVAL / 2
+        ));
+        // Another package has its own interval.
+        assert!(progress_due(
+            &mut last,
+            "wget",
+            start + PROGRESS_INTERVAL / 2
+        ));
+        assert!(progress_due(&mut last, "jq", start + PROGRESS_INTERVAL));
+        assert!(!progress_due(
+            &mut last,
+            "jq",
+            start + PROGRESS_INTERVAL + PROGRESS_INTERVAL / 2
+        ));
+    }
+
+    #[test]
+    fn the_summary_counts_and_lists_every_node_and_target() {
+        let node = NodeStatus {
+            name: "jq".into(),
+            kind: "formula",
+            action: "install",
+            state: InstallState::Failed,
+            error: Some("boom".into()),
+        };
+        let targets = [TargetStatus {
+            name: "jq".into(),
+            state: InstallState::Failed,
+            failed: vec!["jq".into()],
+        }];
+        let line = JsonLine::Summary {
+            succeeded: 0,
+            failed: 1,
+            skipped: 0,
+            nodes: vec![&node],
+            targets: &targets,
+        };
+
+        assert_eq!(
+            serde_json::to_value(&line).unwrap(),
+            json!({
+                "type": "summary", "succeeded": 0, "failed": 1, "skipped": 0,
+                "nodes": [{
+                    "name": "jq", "kind": "formula", "action": "install", "state": "failed",
+                    "error": "boom"
+                }],
+                "targets": [{"name": "jq", "state": "failed", "failed": ["jq"]}]
+            })
+        );
+    }
+}
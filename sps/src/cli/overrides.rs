@@ -0,0 +1,63 @@
+//! Contains the logic for the `override` command, which manages local formula/cask definitions
+//! that take precedence over the API (see [`sps_common::overrides`]).
+
+use std::sync::Arc;
+
+use clap::{Args, Subcommand};
+use colored::Colorize;
+use sps_common::cache::Cache;
+use sps_common::config::Config;
+use sps_common::error::{Result, SpsError};
+use sps_common::overrides;
+
+use crate::ui;
+
+#[derive(Args, Debug)]
+pub struct OverrideArgs {
+    #[command(subcommand)]
+    pub command: OverrideCommand,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum OverrideCommand {
+    /// List local overrides and where they live
+    List,
+    /// Delete the override for a formula or cask, going back to the API definition
+    Remove {
+        /// Name of the formula or cask
+        name: String,
+    },
+}
+
+impl OverrideArgs {
+    pub async fn run(&self, config: &Config, _cache: Arc<Cache>) -> Result<()> {
+        match &self.command {
+            OverrideCommand::List => {
+                let entries = overrides::list(config)?;
+                if entries.is_empty() {
+                    println!("No local overrides in {}", config.overrides_dir.display());
+                    return Ok(());
+                }
+                println!("Local overrides in {}:", config.overrides_dir.display());
+                for entry in entries {
+                    println!("  {} ({})", entry.name.cyan(), entry.kind.as_str());
+                }
+                Ok(())
+            }
+            OverrideCommand::Remove { name } => {
+                if !overrides::remove(config, name)? {
+                    return Err(SpsError::NotFound(format!(
+                        "No local override for '{name}' in {}",
+                        config.overrides_dir.display()
+                    )));
+                }
+                println!(
+                    "{} Removed local override for {}",
+                    ui::ok_mark(),
+                    name.cyan()
+                );
+                Ok(())
+            }
+        }
+    }
+}
@@ -0,0 +1,280 @@
+//! Whether the commands linked into the prefix's `bin` are the ones a shell runs: the directory
+//! has to be on `PATH`, and no entry before it may provide the same name (typically an older
+//! system copy in `/usr/bin`). Checked for new commands after installs and for all of them by
+//! `sps doctor`; `sps shellenv` fixes both.
+
+use std::collections::BTreeSet;
+use std::ffi::OsStr;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::{env, fs};
+
+use sps_common::config::Config;
+
+/// The fix suggested by every warning.
+pub const SHELLENV_HINT: &str = "eval \"$(sps shellenv)\"";
+
+/// A `PATH` value, split, and where the prefix's `bin` is in it.
+#[derive(Debug)]
+pub struct PathCheck {
+    bin_dir: PathBuf,
+    entries: Vec<PathBuf>,
+    /// Index of the first entry that is `bin_dir`.
+    position: Option<usize>,
+}
+
+impl PathCheck {
+    /// Checks `path_var` (a `PATH` value) for `bin_dir`. Empty entries are ignored.
+    pub fn new(path_var: &OsStr, bin_dir: &Path) -> Self {
+        let entries: Vec<PathBuf> = env::split_paths(path_var)
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .collect();
+        let position = entries.iter().position(|dir| same_dir(dir, bin_dir));
+        Self {
+            bin_dir: bin_dir.to_path_buf(),
+            entries,
+            position,
+        }
+    }
+
+    /// Checks the `PATH` of this process for the prefix of `config`.
+    pub fn from_env(config: &Config) -> Self {
+        Self::new(&env::var_os("PATH").unwrap_or_default(), &config.bin_dir())
+    }
+
+    pub fn in_path(&self) -> bool {
+        self.position.is_some()
+    }
+
+    /// The entries searched before the prefix's `bin`; empty when it comes first or is missing.
+    pub fn ahead(&self) -> &[PathBuf] {
+        match self.position {
+            Some(position) => &self.entries[..position],
+            None => &[],
+        }
+    }
+
+    /// The executable an entry before the prefix's `bin` provides as `name`, which the shell
+    /// runs instead of the prefix's.
+    pub fn shadowed_by(&self, name: &str) -> Option<PathBuf> {
+        self.ahead()
+            .iter()
+            .filter(|dir| !same_dir(dir, &self.bin_dir))
+            .map(|dir| dir.join(name))
+            .find(|candidate| is_executable(candidate))
+    }
+
+    /// Any other entry providing `name`, before or after the prefix's `bin`.
+    pub fn elsewhere(&self, name: &str) -> Option<PathBuf> {
+        self.entries
+            .iter()
+            .filter(|dir| !same_dir(dir, &self.bin_dir))
+            .map(|dir| dir.join(name))
+            .find(|candidate| is_executable(candidate))
+    }
+
+    /// One warning if the prefix's `bin` is not on `PATH` at all, otherwise one per name in
+    /// `names` that an earlier entry shadows, naming that entry.
+    pub fn warnings<'a>(&self, names: impl IntoIterator<Item = &'a str>) -> Vec<String> {
+        if !self.in_path() {
+            return vec![format!(
+                "{} is not in PATH, so the commands installed there are not found; add it with: \
+                 {SHELLENV_HINT}",
+                self.bin_dir.display()
+            )];
+        }
+        names
+            .into_iter()
+            .filter_map(|name| {
+                let other = self.shadowed_by(name)?;
+                Some(format!(
+                    "`{name}` runs {}, which comes before {} in PATH; put the prefix first with: \
+                     {SHELLENV_HINT}",
+                    other.display(),
+                    self.bin_dir.join(name).display()
+                ))
+            })
+            .collect()
+    }
+}
+
+/// What `sps doctor` finds about `PATH`: the prefix's `bin` missing from it or not first, and
+/// each command in it that an earlier entry shadows.
+pub fn path_problems(config: &Config) -> Vec<String> {
+    problems_in(&PathCheck::from_env(config))
+}
+
+/// [`path_problems`] for the `PATH` and `bin` of `check`.
+fn problems_in(check: &PathCheck) -> Vec<String> {
+    if !check.in_path() {
+        return check.warnings([]);
+    }
+    let mut problems = Vec::new();
+    if !check.ahead().is_empty() {
+        problems.push(format!(
+            "{} is not first in PATH (it comes after {}); put it first with: {SHELLENV_HINT}",
+            check.bin_dir.display(),
+            check
+                .ahead()
+                .iter()
+                .map(|dir| dir.display().to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ));
+    }
+    let names: BTreeSet<String> = fs::read_dir(&check.bin_dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| entry.file_name().to_string_lossy().to_string())
+        .collect();
+    problems.extend(check.warnings(names.iter().map(String::as_str)));
+    problems
+}
+
+/// Equal as written, or once symlinks are resolved.
+fn same_dir(a: &Path, b: &Path) -> bool {
+    a == b
+        || matches!(
+            (fs::canonicalize(a), fs::canonicalize(b)),
+            (Ok(a), Ok(b)) if a == b
+        )
+}
+
+fn is_executable(path: &Path) -> bool {
+    fs::metadata(path).is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::OsString;
+
+    use super::*;
+
+    /// A prefix `bin` holding `jq` and `yq`, and a `usr/bin` beside it holding an older `jq`, a
+    /// `yq` that isn't executable and a directory called `fd`.
+    struct Dirs {
+        _root: tempfile::TempDir,
+        bin: PathBuf,
+        usr_bin: PathBuf,
+    }
+
+    fn dirs() -> Dirs {
+        let root = tempfile::tempdir().unwrap();
+        let bin = root.path().join("prefix/bin");
+        let usr_bin = root.path().join("usr/bin");
+        for dir in [&bin, &usr_bin] {
+            fs::create_dir_all(dir).unwrap();
+        }
+        for (path, mode) in [
+            (bin.join("jq"), 0o755),
+            (bin.join("yq"), 0o755),
+            (usr_bin.join("jq"), 0o755),
+            (usr_bin.join("yq"), 0o644),
+        ] {
+            fs::write(&path, "#!/bin/sh\n").unwrap();
+            fs::set_permissions(&path, fs::Permissions::from_mode(mode)).unwrap();
+        }
+        fs::create_dir(usr_bin.join("fd")).unwrap();
+        Dirs {
+            _root: root,
+            bin,
+            usr_bin,
+        }
+    }
+
+    fn path(entries: &[&Path]) -> OsString {
+        env::join_paths(entries).unwrap()
+    }
+
+    #[test]
+    fn an_earlier_entry_with_the_same_name_shadows_the_prefix() {
+        let dirs = dirs();
+        let check = PathCheck::new(&path(&[&dirs.usr_bin, &dirs.bin]), &dirs.bin);
+
+        assert!(check.in_path());
+        assert_eq!(check.ahead(), std::slice::from_ref(&dirs.usr_bin));
+        assert_eq!(check.shadowed_by("jq"), Some(dirs.usr_bin.join("jq")));
+        // Not executable, a directory, or not there at all: nothing runs instead.
+        for name in ["yq", "fd", "rg"] {
+            assert_eq!(check.shadowed_by(name), None, "{name}");
+        }
+        assert_eq!(
+            check.warnings(["jq", "yq"]),
+            [format!(
+                "`jq` runs {}, which comes before {} in PATH; put the prefix first with: \
+                 {SHELLENV_HINT}",
+                dirs.usr_bin.join("jq").display(),
+                dirs.bin.join("jq").display()
+            )]
+        );
+    }
+
+    #[test]
+    fn a_later_entry_with_the_same_name_shadows_nothing() {
+        let dirs = dirs();
+        let check = PathCheck::new(&path(&[&dirs.bin, &dirs.usr_bin]), &dirs.bin);
+
+        assert!(check.ahead().is_empty());
+        assert_eq!(check.shadowed_by("jq"), None);
+        assert_eq!(check.elsewhere("jq"), Some(dirs.usr_bin.join("jq")));
+        assert!(check.warnings(["jq", "yq"]).is_empty());
+        assert!(problems_in(&check).is_empty());
+    }
+
+    #[test]
+    fn empty_entries_and_a_symlinked_bin_are_understood() {
+        let dirs = dirs();
+        let alias = dirs.bin.parent().unwrap().join("bin-link");
+        std::os::unix::fs::symlink(&dirs.bin, &alias).unwrap();
+        let value = format!("::{}::{}:", alias.display(), dirs.usr_bin.display());
+
+        let check = PathCheck::new(OsStr::new(&value), &dirs.bin);
+
+        assert!(check.in_path());
+        assert!(check.ahead().is_empty());
+        assert!(problems_in(&check).is_empty());
+    }
+
+    #[test]
+    fn a_bin_missing_from_path_is_the_only_problem_reported() {
+        let dirs = dirs();
+        let check = PathCheck::new(&path(&[&dirs.usr_bin, Path::new("/nowhere")]), &dirs.bin);
+
+        assert!(!check.in_path());
+        assert!(check.ahead().is_empty());
+        assert_eq!(check.shadowed_by("jq"), None);
+        assert_eq!(
+            problems_in(&check),
+            [format!(
+                "{} is not in PATH, so the commands installed there are not found; add it with: \
+                 {SHELLENV_HINT}",
+                dirs.bin.display()
+            )]
+        );
+        assert_eq!(check.warnings(["jq"]), problems_in(&check));
+    }
+
+    #[test]
+    fn doctor_names_the_entries_ahead_and_each_shadowed_command() {
+        let dirs = dirs();
+        let value = path(&[Path::new("/nowhere"), &dirs.usr_bin, &dirs.bin]);
+
+        let problems = problems_in(&PathCheck::new(&value, &dirs.bin));
+
+        assert_eq!(problems.len(), 2, "{problems:#?}");
+        assert_eq!(
+            problems[0],
+            format!(
+                "{} is not first in PATH (it comes after /nowhere, {}); put it first with: \
+                 {SHELLENV_HINT}",
+                dirs.bin.display(),
+                dirs.usr_bin.display()
+            )
+        );
+        assert!(
+            problems[1].starts_with(&format!("`jq` runs {}", dirs.usr_bin.join("jq").display())),
+            "{problems:#?}"
+        );
+    }
+}
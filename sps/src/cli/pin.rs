@@ -0,0 +1,75 @@
+//! Contains the logic for the `pin` and `unpin` commands.
+
+use std::sync::Arc;
+
+use clap::Args;
+use colored::Colorize;
+use sps_common::cache::Cache;
+use sps_common::config::Config;
+use sps_common::error::{Result, SpsError};
+use sps_common::keg::{InstalledKeg, KegRegistry};
+use sps_common::model::PkgVersion;
+use sps_core::build::formula::versions;
+
+use crate::ui;
+
+#[derive(Args, Debug)]
+pub struct Pin {
+    /// Name of the formula
+    pub name: String,
+
+    /// Installed version to pin (default: the current one)
+    #[arg(value_name = "VERSION")]
+    pub keg_version: Option<String>,
+}
+
+#[derive(Args, Debug)]
+pub struct Unpin {
+    /// Name of the formula
+    pub name: String,
+
+    /// Installed version to unpin (default: the current one)
+    #[arg(value_name = "VERSION")]
+    pub keg_version: Option<String>,
+}
+
+impl Pin {
+    /// Marks a keg as pinned, so `sps cleanup` keeps it after it stops being current.
+    pub async fn run(&self, config: &Config, _cache: Arc<Cache>) -> Result<()> {
+        set_pinned(&self.name, self.keg_version.as_deref(), true, config)
+    }
+}
+
+impl Unpin {
+    /// Clears a keg's pin, letting `sps cleanup` remove it once it is out of the retention window.
+    pub async fn run(&self, config: &Config, _cache: Arc<Cache>) -> Result<()> {
+        set_pinned(&self.name, self.keg_version.as_deref(), false, config)
+    }
+}
+
+fn set_pinned(name: &str, version: Option<&str>, pinned: bool, config: &Config) -> Result<()> {
+    let keg = find_keg(name, version, config)?;
+    versions::set_pinned(&keg.path, pinned)?;
+    println!(
+        "{} {} {} {}",
+        ui::ok_mark(),
+        if pinned { "Pinned" } else { "Unpinned" },
+        keg.name.cyan(),
+        versions::keg_version(&keg)
+    );
+    Ok(())
+}
+
+fn find_keg(name: &str, version: Option<&str>, config: &Config) -> Result<InstalledKeg> {
+    let keg_registry = KegRegistry::new(config.clone());
+    let Some(version) = version else {
+        return keg_registry
+            .get_installed_keg(name)?
+            .ok_or_else(|| SpsError::NotFound(format!("'{name}' is not installed")));
+    };
+    keg_registry
+        .list_formula_kegs(name)?
+        .into_iter()
+        .find(|keg| keg.version.matches(&PkgVersion::parse(version)))
+        .ok_or_else(|| SpsError::NotFound(format!("{name} {version} is not installed")))
+}
@@ -1,25 +1,30 @@
 // sps-cli/src/cli/pipeline.rs
 
-use std::collections::{HashMap, HashSet, VecDeque};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
 use std::fs;
-use std::path::PathBuf;
-use std::sync::Arc;
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 // use tokio::sync::Mutex; // For async-aware locking if needed later
 use colored::Colorize;
-use crossbeam_channel::{bounded, Receiver, Sender};
+use crossbeam_channel::{bounded, unbounded, Receiver, Sender};
 use futures::executor::block_on;
-use num_cpus;
 use serde_json::Value;
 use sps_common::cache::Cache;
-use sps_common::config::Config;
+use sps_common::cancel::{CancellationToken, DropGuard};
+use sps_common::config::{AdvisoryMode, Config, MetadataStrategy};
 use sps_common::dependency::{
-    DependencyResolver, ResolutionContext, ResolutionStatus, ResolvedGraph,
+    DependencyResolver, DependencyTag, Direction, FailurePolicy, ResolutionContext,
+    ResolutionStatus, ResolvedGraph, Scheduler,
 };
-use sps_common::error::{Result, SpsError};
-use sps_common::formulary::Formulary;
-use sps_common::keg::KegRegistry;
-use sps_common::model::formula::{Formula, FormulaDependencies};
+use sps_common::error::{combined_exit_code, exit_code, Result, SpsError};
+use sps_common::formulary::{self, Formulary};
+use sps_common::keg::{self, InstallReason, KegRegistry, KegSnapshot, SkippedDependency};
+use sps_common::metrics::{self, MetricEvent};
+use sps_common::model::formula::{Formula, FormulaDependencies, HEAD_VERSION_PREFIX};
 use sps_common::model::Cask;
 // --- Shared Data Structures ---
 
@@ -27,16 +32,33 @@ use sps_common::model::Cask;
 // Or defined locally here if InstallTargetIdentifier from core isn't suitable.
 // Assuming we use the one from core for now:
 use sps_common::model::InstallTargetIdentifier;
+use sps_common::{advisory, macos, overrides};
+use sps_core::build::cask::post_install::{self, PostInstallAction};
+use sps_core::build::cask::preexisting;
+use sps_core::build::formula::link::PreviousLinks;
+use sps_core::build::reaper::RunLock;
 use sps_core::build::{self};
-use sps_core::installed::{InstalledPackageInfo, PackageType}; /* Needs implementing in
-                                                                * sps-core */
+use sps_core::hooks::{self, HookEvent};
+use sps_core::installed::{InstalledPackageInfo, PackageType};
 use sps_core::uninstall as core_uninstall; // Alias for the new module
 use sps_core::uninstall::UninstallOptions; // Needs implementing in sps-core
 use sps_core::update_check::{self, UpdateInfo}; // Needs implementing in sps-core
-use sps_net::fetch::api;
+use sps_core::{blocking, metadata, KindHint};
+use sps_net::fetch::{api, progress};
 use threadpool::ThreadPool;
 use tokio::task::JoinSet;
-use tracing::{debug, error, instrument, warn, Instrument}; // Placeholder: Ensure this is accessible
+use tracing::{debug, error, instrument, warn, Instrument}; /* Placeholder: Ensure this is
+                                                            * accessible */
+
+use crate::cli::changes::PrefixSnapshot;
+use crate::cli::graph::{DependencyGraph, GraphFormat};
+use crate::cli::output;
+use crate::cli::plan::{self, PlanKind};
+use crate::cli::status::{self, InstallEvent, InstallState, Phase, StatusHub, TargetStatus};
+use crate::ui;
+
+/// How long interactive installs wait for a system extension to be approved.
+const SYSTEM_EXTENSION_WAIT: Duration = Duration::from_secs(5 * 60);
 
 // Represents the specific action for a pipeline job
 #[derive(Debug, Clone)]
@@ -61,6 +83,8 @@ pub struct PipelineJob {
     // Graph needed for source builds to know dependencies
     pub resolved_graph: Option<Arc<ResolvedGraph>>,
     pub is_source_build: bool,
+    // Build from the formula's head; the target gets its `HEAD-<commit>` version once fetched
+    pub head: bool,
 }
 
 // Represents the outcome of processing a PipelineJob
@@ -69,6 +93,9 @@ pub enum PipelineJobResult {
     InstallOk(String, PackageType),
     UpgradeOk(String, PackageType, String), // Name, Type, OldVersion
     ReinstallOk(String, PackageType),       // Name, Type
+    AlreadyInstalled(String, PackageType),  // Installed concurrently by another process
+    // Installed/upgraded/reinstalled, but not usable until the user acts (approval, reboot)
+    ActionRequired(String, PackageType, Vec<PostInstallAction>),
     InstallErr(String, PackageType, SpsError),
     UpgradeErr(String, PackageType, String, SpsError), // Include old version
     ReinstallErr(String, PackageType, SpsError),
@@ -82,17 +109,200 @@ pub enum CommandType {
     Upgrade { all: bool },
 }
 
+/// State shared by the download, install and result phases of one run.
+#[derive(Clone)]
+struct RunSignals {
+    /// Set under --fail-fast once every target has been given up; downloads and workers stop
+    /// picking up work.
+    abort: Arc<AtomicBool>,
+    /// The run's cancellation token ([`PipelineFlags::cancel`]).
+    cancel: CancellationToken,
+    status: Arc<StatusHub>,
+    targets: Arc<TargetScope>,
+}
+
+impl RunSignals {
+    /// Whether new downloads and installs are no longer started: after --fail-fast gave up
+    /// every target, or once the run is cancelled.
+    fn stopped(&self) -> bool {
+        self.abort.load(Ordering::SeqCst) || self.cancel.is_cancelled()
+    }
+}
+
+/// Which requested targets each planned package is part of, and the targets given up after a
+/// failure under --fail-fast. Work only given-up targets need is not started.
+struct TargetScope {
+    targets_of: BTreeMap<Arc<str>, BTreeSet<Arc<str>>>,
+    given_up: Mutex<BTreeSet<Arc<str>>>,
+}
+
+impl TargetScope {
+    fn new(targets_of: BTreeMap<Arc<str>, BTreeSet<Arc<str>>>) -> Self {
+        Self {
+            targets_of,
+            given_up: Mutex::new(BTreeSet::new()),
+        }
+    }
+
+    /// Every target, in name order.
+    fn targets(&self) -> BTreeSet<&Arc<str>> {
+        self.targets_of.values().flatten().collect()
+    }
+
+    /// The targets `name` is part of, for messages.
+    fn describe(&self, name: &str) -> String {
+        self.targets_of.get(name).map_or_else(
+            || name.to_string(),
+            |targets| targets.iter().cloned().collect::<Vec<_>>().join(", "),
+        )
+    }
+
+    /// Gives up the targets `name` is part of. Returns whether every target is now given up.
+    fn give_up(&self, name: &str) -> bool {
+        let mut given_up = self.given_up.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(targets) = self.targets_of.get(name) {
+            given_up.extend(targets.iter().cloned());
+        }
+        self.targets_of
+            .values()
+            .all(|targets| targets.is_subset(&given_up))
+    }
+
+    /// Whether every target `name` is part of has been given up.
+    fn is_given_up(&self, name: &str) -> bool {
+        let given_up = self.given_up.lock().unwrap_or_else(|e| e.into_inner());
+        self.targets_of
+            .get(name)
+            .is_some_and(|targets| targets.is_subset(&given_up))
+    }
+}
+
+/// A download task's result: the job with its download path, or the failure for that package.
+type DownloadTaskResult = std::result::Result<(PipelineJob, String), (String, SpsError)>;
+
+/// Size of a queue at which [`Watermark`] first logs.
+const WATERMARK_START: usize = 64;
+
+/// Logs at debug level each time a queue first reaches another doubling of
+/// [`WATERMARK_START`], so a backed-up run shows where it is waiting without a line per change.
+struct Watermark {
+    label: &'static str,
+    next: usize,
+}
+
+impl Watermark {
+    fn new(label: &'static str) -> Self {
+        Self {
+            label,
+            next: WATERMARK_START,
+        }
+    }
+
+    fn observe(&mut self, len: usize) {
+        if len < self.next {
+            return;
+        }
+        debug!("{}: {}", self.label, len);
+        while self.next <= len {
+            self.next *= 2;
+        }
+    }
+}
+
+/// Runs `jobs` with at most `slots` tasks in flight. `spawn` puts a job's task on the set (or
+/// passes over it), and each finished task goes to `finish` as soon as it is collected; all that
+/// finished together are handled before more are spawned, so neither the set nor the results
+/// waiting in it grow with the number of jobs. `finish` returning true aborts the tasks still
+/// running, and once `stopped` says so nothing more is spawned. `state` is lent to both.
+async fn run_windowed<J, T: Send + 'static, S>(
+    jobs: impl IntoIterator<Item = J>,
+    slots: usize,
+    state: &mut S,
+    stopped: impl Fn() -> bool,
+    mut spawn: impl FnMut(&mut S, &mut JoinSet<T>, J),
+    mut finish: impl FnMut(
+        &mut S,
+        std::result::Result<(tokio::task::Id, T), tokio::task::JoinError>,
+    ) -> bool,
+) {
+    let mut join_set = JoinSet::new();
+    let mut queued = jobs.into_iter();
+    let mut in_flight_mark = Watermark::new("Downloads in flight");
+    loop {
+        while join_set.len() < slots && !stopped() {
+            let Some(job) = queued.next() else {
+                break;
+            };
+            spawn(state, &mut join_set, job);
+        }
+        in_flight_mark.observe(join_set.len());
+        let Some(first) = join_set.join_next_with_id().await else {
+            break;
+        };
+        // Everything else that finished meanwhile is handled before spawning more.
+        let mut next = Some(first);
+        while let Some(result) = next.take() {
+            if finish(state, result) {
+                join_set.abort_all();
+            }
+            next = join_set.try_join_next_with_id();
+        }
+    }
+}
+
 // Flags affecting pipeline behavior
 #[derive(Debug, Clone)]
 pub struct PipelineFlags {
     pub build_from_source: bool,
     pub include_optional: bool,
     pub skip_recommended: bool,
-    // Add other common flags like --force if needed
+    /// Install only the dependencies of the initial targets
+    pub only_dependencies: bool,
+    pub kind_hint: KindHint, // --formula / --cask restriction for the initial targets
+    pub no_wait: bool,       // Don't pause for post-install approvals (system extensions)
+    pub ignore_installed: bool, // Plan as if nothing were installed
+    pub only_missing: bool,  // Never reinstall, upgrade or relink existing kegs
+    pub ignore_requirements: bool, // Warn instead of failing on unmet macOS requirements
+    pub fail_fast: bool,     // Stop scheduling downloads and installs after a failure
+    pub strict_deps: bool,   // Fail dependents of a failed optional/recommended dependency too
+    pub dry_run: bool,       // Plan and print, but don't download or install
+    pub emit_plan: Option<PathBuf>, // With dry_run: write the resolved plan here
+    pub graph: Option<GraphFormat>, // With dry_run: print the dependency graph in this format
+    pub prune_installed: bool, // With graph: leave out what installed dependencies need
+    pub status_socket: Option<PathBuf>, // Serve live progress as JSON lines on this socket
+    pub adopt: bool,         // Take over apps already in /Applications instead of installing casks
+    pub force: bool,         // Replace apps already in /Applications that sps didn't install
+    pub cask_definitions: HashMap<String, Arc<Cask>>, // Casks given as a file or URL, by token
+    pub head: bool,          // Build the initial formula targets from their head (git) source
+    pub fetch_head: bool,    // Check kegs built from a head for new upstream commits
+    pub cancel: CancellationToken, // Stops the run at the next safe point once cancelled
+}
+
+/// The install flags that change what the resolver computes, for commands that resolve without
+/// installing (`sps deps`, `sps resolve`).
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ResolveOptions {
+    pub include_optional: bool,
+    pub skip_recommended: bool,
+    pub build_from_source: bool,
+    pub ignore_installed: bool,
+    pub only_missing: bool,
 }
 
 // Add this after the PipelineFlags struct, before PipelineExecutor
-type PlanResult = Result<(Vec<PipelineJob>, Vec<(String, SpsError)>, HashSet<String>)>;
+type PlanResult = Result<(
+    Vec<PipelineJob>,
+    Vec<(String, SpsError)>,
+    HashSet<String>,
+    InstallReasons,
+)>;
+// Install reason to merge into each formula's receipt once the run is over
+type InstallReasons = HashMap<String, InstallReason>;
+// Packages installed successfully that still need user action, with the actions
+type PendingActions = Vec<(String, Vec<PostInstallAction>)>;
+// Packages started without an optional/recommended dependency that failed: (package, dependency,
+// tags of the edge)
+type MissingSoftDeps = Vec<(String, String, DependencyTag)>;
 
 // The main orchestrator struct
 pub struct PipelineExecutor;
@@ -107,138 +317,610 @@ impl PipelineExecutor {
         cache: Arc<Cache>,
         flags: &PipelineFlags,
     ) -> Result<()> {
-        // Define worker/queue size (same logic as before)
-        let worker_count = std::cmp::max(1, num_cpus::get_physical().saturating_sub(1)).min(6); // Example sizing
-        let queue_size = worker_count * 2;
+        // --- 0. Preflight: fail early if the prefix is not writable ---
+        if !flags.dry_run {
+            check_write_permissions(config)?;
+        }
+        let _run_lock = (!flags.dry_run).then(|| hold_run_lock(config));
+
+        // Read the Cellar once; planning and workers query this instead of re-scanning it.
+        let keg_snapshot = KegSnapshot::load(config)?;
 
         // --- 1. Plan Operations ---
         debug!("Planning package operations...");
-        let (planned_jobs, mut overall_errors, already_installed) = Self::plan_package_operations(
-            initial_targets,
-            command_type.clone(),
-            config,
-            cache.clone(),
-            flags,
-        )
-        .await?;
+        let (planned_jobs, overall_errors, already_installed, install_reasons) =
+            Self::plan_package_operations(
+                initial_targets,
+                command_type.clone(),
+                config,
+                cache.clone(),
+                flags,
+                &keg_snapshot,
+            )
+            .await?;
 
         // Report planning errors and already installed packages
+        if flags.only_dependencies {
+            info_line(format!(
+                "Skipping {} itself (--only-dependencies); installing dependencies only.",
+                initial_targets.join(", ").cyan()
+            ));
+        }
         for name in already_installed {
             info_line(format!(
                 "{} {} is already installed.",
-                "✓".green(),
+                ui::ok_mark(),
                 name.cyan()
             ));
         }
         for (name, err) in &overall_errors {
-            error!("✖ Error during planning for '{}': {}", name.cyan(), err);
+            error!(
+                "{} Error during planning for '{}': {}",
+                ui::fail_mark(),
+                name.cyan(),
+                err
+            );
         }
 
         if planned_jobs.is_empty() {
+            // Nothing to pour, but reused kegs may still have gained a requester or a root.
+            record_install_reasons(&install_reasons, &keg_snapshot);
+            status::report_unexecuted(&planned_jobs, overall_errors.len());
             if overall_errors.is_empty() {
                 info_line("No packages need to be installed, upgraded, or reinstalled.");
                 return Ok(());
             } else {
                 error!("No operations possible due to planning errors.");
+                let code = combined_exit_code(overall_errors.iter().map(|(_, e)| e));
                 // Combine errors into a single message for returning
                 let final_error_msg = overall_errors
                     .into_iter()
                     .map(|(name, err)| format!("'{name}': {err}"))
                     .collect::<Vec<_>>()
                     .join("; ");
-                return Err(SpsError::InstallError(format!(
-                    "Operation failed during planning: {final_error_msg}"
-                )));
+                return Err(SpsError::OperationFailed(
+                    code,
+                    format!("planning failed: {final_error_msg}"),
+                ));
             }
         }
         debug!("Planning complete. {} jobs generated.", planned_jobs.len());
+        let mode = if flags.ignore_installed {
+            " (--ignore-installed: resolved as if nothing were installed)"
+        } else if flags.only_missing {
+            " (--only-missing: existing kegs left untouched)"
+        } else {
+            ""
+        };
+        let planned_names: Vec<&str> = planned_jobs
+            .iter()
+            .map(|j| match &j.target {
+                InstallTargetIdentifier::Formula(f) => f.name(),
+                InstallTargetIdentifier::Cask(c) => c.token.as_str(),
+            })
+            .collect();
+        info_line(format!(
+            "Plan: {} package(s): {}{}",
+            planned_names.len(),
+            planned_names.join(", "),
+            mode
+        ));
+        for job in &planned_jobs {
+            let (name, kind) = match &job.target {
+                InstallTargetIdentifier::Formula(f) => (f.name(), overrides::OverrideKind::Formula),
+                InstallTargetIdentifier::Cask(c) => {
+                    (c.token.as_str(), overrides::OverrideKind::Cask)
+                }
+            };
+            if overrides::is_overridden(config, name, kind) {
+                info_line(format!("  {} (local override)", name.cyan()));
+            }
+        }
+        for (name, versions, reason) in forced_upgrades(&planned_jobs) {
+            info_line(format!(
+                "  {} {} {} (upgrade forced by a dependent: {})",
+                ui::upgrade_mark(),
+                name.cyan(),
+                versions,
+                reason
+            ));
+        }
+
+        if let Err(e) = check_bottle_only(&planned_jobs, config)
+            .and_then(|()| check_advisories(&planned_jobs, config))
+        {
+            status::report_unexecuted(&planned_jobs, overall_errors.len());
+            return Err(e);
+        }
+
+        if flags.dry_run {
+            status::report_unexecuted(&planned_jobs, overall_errors.len());
+            if let Some(format) = flags.graph {
+                print_plan_graph(
+                    &planned_jobs,
+                    &overall_errors,
+                    initial_targets,
+                    format,
+                    flags,
+                );
+            }
+            return Self::finish_dry_run(&planned_jobs, &install_reasons, overall_errors, flags);
+        }
+        Self::execute_jobs(
+            planned_jobs,
+            initial_targets,
+            overall_errors,
+            install_reasons,
+            config,
+            cache,
+            flags,
+            keg_snapshot,
+        )
+        .await
+    }
+
+    /// Resolves the dependencies of the formulae `names` the way an install would, without
+    /// planning any work (`sps deps`, `sps resolve`). Names that can't be loaded are returned
+    /// separately.
+    pub async fn resolve_dependency_graph(
+        names: &[String],
+        config: &Config,
+        cache: Arc<Cache>,
+        options: ResolveOptions,
+    ) -> Result<(ResolvedGraph, Vec<(String, SpsError)>)> {
+        let mut targets: HashMap<String, InstallTargetIdentifier> = HashMap::new();
+        let mut errors: Vec<(String, SpsError)> = Vec::new();
+        for (name, result) in
+            Self::fetch_target_definitions(names, &cache, config, KindHint::Formula).await
+        {
+            match result {
+                Ok(target) => {
+                    targets.insert(name, target);
+                }
+                Err(e) => errors.push((name, e)),
+            }
+        }
+        errors.sort_by(|a, b| a.0.cmp(&b.0));
+        let formulary = Formulary::new(config.clone());
+        Self::prefetch_formula_definitions(&formulary, &cache, &targets).await;
+        let keg_registry = KegRegistry::new(config.clone());
+        let ctx = ResolutionContext {
+            formulary: &formulary,
+            keg_registry: &keg_registry,
+            sps_prefix: config.prefix(),
+            include_optional: options.include_optional,
+            include_test: false,
+            skip_recommended: options.skip_recommended,
+            force_build: options.build_from_source,
+            ignore_installed: options.ignore_installed,
+            only_missing: options.only_missing,
+            platform: Some(build::formula::host_platform()),
+        };
+        let resolution_targets: Vec<String> = targets.keys().cloned().collect();
+        let graph = DependencyResolver::new(ctx).resolve_targets(&resolution_targets)?;
+        Ok((graph, errors))
+    }
+
+    /// Executes a plan written by `install --dry-run --emit-plan`: the recorded packages are
+    /// installed in the recorded order, without re-resolving. Fails before downloading anything
+    /// if current metadata no longer matches a recorded version, bottle or checksum.
+    #[instrument(skip(config, cache, flags), fields(plan = %path.display()))]
+    pub async fn execute_plan_file(
+        path: &Path,
+        config: &Config,
+        cache: Arc<Cache>,
+        flags: &PipelineFlags,
+    ) -> Result<()> {
+        check_write_permissions(config)?;
+        let _run_lock = hold_run_lock(config);
+        let keg_snapshot = KegSnapshot::load(config)?;
+        let plan_file = plan::read(path)?;
+
+        let mut definitions = HashMap::new();
+        for (kind, hint) in [
+            (PlanKind::Formula, KindHint::Formula),
+            (PlanKind::Cask, KindHint::Cask),
+        ] {
+            let names: Vec<String> = plan_file
+                .packages
+                .iter()
+                .filter(|e| e.kind == kind)
+                .map(|e| e.name.clone())
+                .collect();
+            if !names.is_empty() {
+                definitions
+                    .extend(Self::fetch_target_definitions(&names, &cache, config, hint).await);
+            }
+        }
+
+        // Source builds get the opt paths of everything the plan recorded as a dependency.
+        let graph = Arc::new(ResolvedGraph {
+            install_plan: Vec::new(),
+            build_dependency_opt_paths: Vec::new(),
+            runtime_dependency_opt_paths: plan_file
+                .packages
+                .iter()
+                .flat_map(|e| &e.dependencies)
+                .collect::<HashSet<_>>()
+                .into_iter()
+                .map(|dep| config.formula_opt_link_path(dep))
+                .collect(),
+            resolution_details: HashMap::new(),
+            errors: HashMap::new(),
+        });
+
+        let mut jobs = Vec::new();
+        let mut errors: Vec<(String, SpsError)> = Vec::new();
+        let mut install_reasons = InstallReasons::new();
+        for entry in &plan_file.packages {
+            let target = match definitions.remove(&entry.name) {
+                Some(Ok(target)) => target,
+                Some(Err(e)) => {
+                    errors.push((entry.name.clone(), e));
+                    continue;
+                }
+                None => {
+                    errors.push((
+                        entry.name.clone(),
+                        SpsError::NotFound(format!("{} is no longer available", entry.name)),
+                    ));
+                    continue;
+                }
+            };
+            if let Some(installed) =
+                sps_core::installed::get_installed_package(&entry.name, config).await?
+            {
+                info_line(format!(
+                    "{} {} is already installed ({}).",
+                    ui::ok_mark(),
+                    entry.name.cyan(),
+                    installed.version
+                ));
+                continue;
+            }
+            match plan::check_entry(entry, &target) {
+                Ok(is_source_build) => {
+                    if entry.kind == PlanKind::Formula {
+                        install_reasons.insert(entry.name.clone(), entry.install_reason());
+                    }
+                    if let InstallTargetIdentifier::Cask(cask) = &target {
+                        match check_preexisting_cask(cask, config, flags).await {
+                            Ok(true) => {}
+                            Ok(false) => continue,
+                            Err(e) => {
+                                errors.push((entry.name.clone(), e));
+                                continue;
+                            }
+                        }
+                    }
+                    jobs.push(PipelineJob {
+                        target,
+                        download_path: PathBuf::new(),
+                        action: PipelineActionType::Install,
+                        resolved_graph: Some(Arc::clone(&graph)),
+                        is_source_build,
+                        head: false,
+                    });
+                }
+                Err(e) => errors.push((entry.name.clone(), e)),
+            }
+        }
+
+        if !errors.is_empty() {
+            status::report_unexecuted(&jobs, errors.len());
+            for (name, err) in &errors {
+                error!(
+                    "{} Plan entry '{}' cannot be executed: {}",
+                    ui::fail_mark(),
+                    name.cyan(),
+                    err
+                );
+            }
+            let code = combined_exit_code(errors.iter().map(|(_, e)| e));
+            let message = errors
+                .into_iter()
+                .map(|(name, err)| format!("'{name}': {err}"))
+                .collect::<Vec<_>>()
+                .join("; ");
+            return Err(SpsError::OperationFailed(
+                code,
+                format!("plan {} is stale: {message}", path.display()),
+            ));
+        }
+        if jobs.is_empty() {
+            status::report_unexecuted(&jobs, 0);
+            info_line("No packages need to be installed, upgraded, or reinstalled.");
+            return Ok(());
+        }
+        if let Err(e) =
+            check_bottle_only(&jobs, config).and_then(|()| check_advisories(&jobs, config))
+        {
+            status::report_unexecuted(&jobs, 0);
+            return Err(e);
+        }
+        info_line(format!(
+            "Plan ({}): {} package(s): {}",
+            path.display(),
+            jobs.len(),
+            plan_file
+                .packages
+                .iter()
+                .map(|e| e.name.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ));
+        let requested: Vec<String> = plan_file
+            .packages
+            .iter()
+            .filter(|e| e.requested)
+            .map(|e| e.name.clone())
+            .collect();
+        Self::execute_jobs(
+            jobs,
+            &requested,
+            Vec::new(),
+            install_reasons,
+            config,
+            cache,
+            flags,
+            keg_snapshot,
+        )
+        .await
+    }
+
+    /// `--dry-run`: the plan has been printed; optionally write it out and stop.
+    fn finish_dry_run(
+        planned_jobs: &[PipelineJob],
+        install_reasons: &InstallReasons,
+        planning_errors: Vec<(String, SpsError)>,
+        flags: &PipelineFlags,
+    ) -> Result<()> {
+        if !planning_errors.is_empty() {
+            let code = combined_exit_code(planning_errors.iter().map(|(_, e)| e));
+            let message = planning_errors
+                .into_iter()
+                .map(|(name, err)| format!("'{name}': {err}"))
+                .collect::<Vec<_>>()
+                .join("; ");
+            return Err(SpsError::OperationFailed(
+                code,
+                format!("planning failed: {message}"),
+            ));
+        }
+        if let Some(path) = &flags.emit_plan {
+            plan::write(path, planned_jobs, install_reasons)?;
+            info_line(format!("Wrote plan to {}", path.display()));
+        }
+        info_line("Dry run: nothing was installed.");
+        Ok(())
+    }
+
+    /// Downloads and installs `planned_jobs`, then reports the outcome of the whole run and of
+    /// each of the requested `targets`.
+    #[allow(clippy::too_many_arguments)]
+    async fn execute_jobs(
+        planned_jobs: Vec<PipelineJob>,
+        targets: &[String],
+        mut overall_errors: Vec<(String, SpsError)>,
+        mut install_reasons: InstallReasons,
+        config: &Config,
+        cache: Arc<Cache>,
+        flags: &PipelineFlags,
+        keg_snapshot: Arc<KegSnapshot>,
+    ) -> Result<()> {
+        // Validated and capped when the config is built, so never zero.
+        let worker_count = config.max_concurrent_installs.max(1);
+        let queue_size = worker_count * 2;
+        let planned_names: Vec<String> = planned_jobs
+            .iter()
+            .map(|j| match &j.target {
+                InstallTargetIdentifier::Formula(f) => f.name().to_string(),
+                InstallTargetIdentifier::Cask(c) => c.token.clone(),
+            })
+            .collect();
+        let prefix_before = PrefixSnapshot::capture(
+            config,
+            &planned_names.iter().map(String::as_str).collect::<Vec<_>>(),
+        );
+        // Reinstalls and upgrades replace the keg and its receipt; carry the old reason over.
+        for job in &planned_jobs {
+            let (InstallTargetIdentifier::Formula(formula), previous_keg) =
+                (&job.target, &job.action)
+            else {
+                continue;
+            };
+            let previous_keg = match previous_keg {
+                PipelineActionType::Upgrade {
+                    old_install_path, ..
+                } => old_install_path,
+                PipelineActionType::Reinstall {
+                    current_install_path,
+                    ..
+                } => current_install_path,
+                PipelineActionType::Install => continue,
+            };
+            if let Some(previous) = InstallReason::read(previous_keg) {
+                install_reasons
+                    .entry(formula.name().to_string())
+                    .or_default()
+                    .merge(&previous);
+            }
+        }
+        // Hooks run after the whole run, serially, in planned (dependency) order.
+        let hook_jobs: Vec<(String, HookEvent)> = planned_jobs
+            .iter()
+            .filter_map(|job| {
+                let InstallTargetIdentifier::Formula(formula) = &job.target else {
+                    return None;
+                };
+                let event = match job.action {
+                    PipelineActionType::Upgrade { .. } => HookEvent::PostUpgrade,
+                    _ => HookEvent::PostInstall,
+                };
+                Some((formula.name().to_string(), event))
+            })
+            .collect();
+        let mut scheduler = install_scheduler(&planned_jobs, flags.fail_fast, flags.strict_deps);
+        scheduler.set_targets(targets.iter().map(String::as_str));
+        let signals = RunSignals {
+            abort: Arc::new(AtomicBool::new(false)),
+            cancel: flags.cancel.clone(),
+            status: StatusHub::new(&planned_jobs, flags.status_socket.as_deref())?,
+            targets: Arc::new(TargetScope::new(scheduler.targets_of())),
+        };
+
+        // Shared by every download task and worker instead of cloned into each.
+        let shared_config = Arc::new(config.clone());
+        let downloads_and_installs = cancel_on_ctrl_c(&flags.cancel);
 
         // --- 2. Setup Channels & Worker Pool ---
         let (job_tx, job_rx): (Sender<PipelineJob>, Receiver<PipelineJob>) = bounded(queue_size);
+        // Unbounded: results are only collected once downloads finish, and the coordinator must
+        // never block on reporting a skipped package while downloads still feed it.
         let (result_tx, result_rx): (Sender<PipelineJobResult>, Receiver<PipelineJobResult>) =
-            bounded(queue_size);
+            unbounded();
         let pool = ThreadPool::new(worker_count);
+        // Pours on the workers and any other blocking filesystem job share this many slots.
+        blocking::set_limit(worker_count);
         let client = Arc::new(reqwest::Client::new()); // HTTP client for downloads
 
-        // --- 3. Coordinate Downloads ---
+        // --- 3. Coordinate Workers ---
+        // Started first so installs begin while other packages are still downloading, and so
+        // the bounded job channel is always drained.
+        debug!("Coordinating workers...");
+        let pump_handle = Self::coordinate_workers(
+            pool,   // Pass the pool
+            job_rx, // Pass the Receiver
+            result_tx.clone(),
+            scheduler,
+            Arc::clone(&shared_config),
+            cache.clone(),
+            Arc::clone(&keg_snapshot),
+            signals.clone(),
+        );
+        drop(result_tx); // Drop the original Sender for results
+
+        // --- 4. Coordinate Downloads & Collect Results ---
         debug!("Coordinating downloads...");
-        let download_errors_count = Self::coordinate_downloads(
+        let download_errors = Self::coordinate_downloads(
             planned_jobs, // Pass the Vec directly
-            config,
+            &shared_config,
             cache.clone(),
             client,
             job_tx.clone(), // Clone Sender for the download coordinator
             flags,
+            &signals,
         )
         .await?;
         drop(job_tx); // Signal that no more download jobs will be sent
         debug!(
             "Download coordination finished. {} errors.",
-            download_errors_count
-        );
-        if download_errors_count > 0 {
-            // Add generic error if specific ones weren't captured during download
-            overall_errors.push((
-                "[Download Phase]".to_string(),
-                SpsError::Generic(format!(
-                    "Encountered {download_errors_count} download errors."
-                )),
-            ));
-        }
-
-        // --- 4. Coordinate Workers & Collect Results ---
-        debug!("Coordinating workers...");
-        let pump_handle = Self::coordinate_workers(
-            pool,              // Pass the pool
-            job_rx,            // Pass the Receiver
-            result_tx.clone(), // Clone Sender for workers
-            config,
-            cache.clone(),
-            // No flags needed directly by worker coordinator? Flags are in job.
+            download_errors.len()
         );
-        drop(result_tx); // Drop the original Sender for results
+        overall_errors.extend(download_errors);
         debug!("Collecting results...");
-        let install_errors = Self::collect_results(result_rx); // Collect results from the Receiver
-
-        if let Err(e) = pump_handle.await {
-            error!("Worker coordination task panicked: {}", e);
-            overall_errors.push((
-                "[Worker Pool]".to_string(),
-                SpsError::Generic(format!("Worker coordination failed: {e}")),
-            ));
-        }
+        let (succeeded, install_errors, pending_actions) =
+            Self::collect_results(result_rx, flags.fail_fast, &signals);
+
+        let missing_soft_deps = match pump_handle.await {
+            Ok(missing) => missing,
+            Err(e) => {
+                error!("Worker coordination task panicked: {}", e);
+                overall_errors.push((
+                    "[Worker Pool]".to_string(),
+                    SpsError::Generic(format!("Worker coordination failed: {e}")),
+                ));
+                Vec::new()
+            }
+        };
         debug!("Result collection finished.");
+        drop(downloads_and_installs);
 
         // --- 5. Combine and Report Final Status ---
         overall_errors.extend(install_errors); // Add errors collected from workers
-
+        let skipped_deps = skipped_dependencies(&missing_soft_deps, &succeeded, &overall_errors);
+        record_skipped_dependencies(&skipped_deps, &keg_snapshot);
+        let target_statuses =
+            target_statuses(&signals.targets, targets, &succeeded, &overall_errors);
+        signals.status.emit(InstallEvent::Done {
+            succeeded: succeeded.len(),
+            failed: overall_errors.len(),
+            targets: target_statuses.clone(),
+        });
+        drop(signals); // Closes status clients and removes the socket
+        record_install_reasons(&install_reasons, &keg_snapshot);
+        overall_errors.extend(run_install_hooks(&hook_jobs, &succeeded, config));
+        let all_actions_done = Self::report_pending_actions(&pending_actions, config, flags).await;
+        let prefix_after = PrefixSnapshot::capture(config, &[]);
+        report_quarantined_kegs();
+
+        if flags.cancel.is_cancelled() {
+            print_summary(
+                &planned_names,
+                &succeeded,
+                &overall_errors,
+                &skipped_deps,
+                &target_statuses,
+                flags.fail_fast,
+            );
+            prefix_before.print_changes(config, &prefix_after);
+            return Err(SpsError::Cancelled(format!(
+                "{} of {} package(s) installed before the run was cancelled",
+                succeeded.len(),
+                planned_names.len()
+            )));
+        }
         if overall_errors.is_empty() {
-            info_line("Pipeline execution completed successfully.");
+            if all_actions_done {
+                info_line("Pipeline execution completed successfully.");
+            } else {
+                info_line(format!(
+                    "Pipeline execution completed; {} package(s) installed but need further action.",
+                    pending_actions.len()
+                ));
+            }
+            prefix_before.print_changes(config, &prefix_after);
             Ok(())
         } else {
             error!(
                 "Pipeline execution completed with {} error(s).",
                 overall_errors.len()
             );
+            print_summary(
+                &planned_names,
+                &succeeded,
+                &overall_errors,
+                &skipped_deps,
+                &target_statuses,
+                flags.fail_fast,
+            );
+            prefix_before.print_changes(config, &prefix_after);
+            let code = if target_statuses
+                .iter()
+                .any(|t| matches!(t.state, InstallState::Finished))
+            {
+                exit_code::PARTIAL_SUCCESS
+            } else {
+                combined_exit_code(overall_errors.iter().map(|(_, e)| e))
+            };
             let final_error_msg = overall_errors
                 .into_iter()
                 .map(|(name, err)| format!("'{name}': {err}"))
                 .collect::<Vec<_>>()
                 .join("; ");
-            Err(SpsError::InstallError(format!(
-                "Operation failed: {final_error_msg}"
-            )))
+            Err(SpsError::OperationFailed(code, final_error_msg))
         }
     }
 
     /// Determines the set of operations (Install, Upgrade, Reinstall) needed.
-    #[instrument(skip(config, cache, flags), fields(cmd = ?command_type))]
+    #[instrument(skip(config, cache, flags, keg_snapshot), fields(cmd = ?command_type))]
     async fn plan_package_operations(
         initial_targets: &[String],
         command_type: CommandType,
         config: &Config,
         cache: Arc<Cache>,
         flags: &PipelineFlags,
+        keg_snapshot: &Arc<KegSnapshot>,
     ) -> PlanResult {
         let mut jobs: Vec<PipelineJob> = Vec::new();
         let mut errors: Vec<(String, SpsError)> = Vec::new();
@@ -255,30 +937,22 @@ impl PipelineExecutor {
         match command_type {
             CommandType::Install => {
                 debug!("Planning for INSTALL command");
+                let keg_registry =
+                    KegRegistry::with_snapshot(config.clone(), Arc::clone(keg_snapshot));
                 for name in initial_targets {
                     if processed.contains(name) {
                         continue;
                     }
-                    match sps_core::installed::get_installed_package(name, config).await? {
-                        Some(_installed_info) => {
-                            already_installed.insert(name.clone());
-                            processed.insert(name.clone());
-                        }
-                        None => {
-                            // Mark for install, need to fetch definition later
-                            initial_ops.insert(name.clone(), (PipelineActionType::Install, None));
-                        }
-                    }
-                }
-            }
-            CommandType::Reinstall => {
-                debug!("Planning for REINSTALL command");
-                for name in initial_targets {
-                    if processed.contains(name) {
-                        continue;
-                    }
-                    match sps_core::installed::get_installed_package(name, config).await? {
-                        Some(installed_info) => {
+                    let installed = if flags.ignore_installed {
+                        None
+                    } else {
+                        sps_core::installed::get_installed_package(name, config).await?
+                    };
+                    match installed {
+                        // --HEAD replaces an installed keg with one built from the head.
+                        Some(installed_info)
+                            if flags.head && installed_info.pkg_type == PackageType::Formula =>
+                        {
                             initial_ops.insert(
                                 name.clone(),
                                 (
@@ -288,17 +962,104 @@ impl PipelineExecutor {
                                     },
                                     None,
                                 ),
-                            ); // Need to fetch definition
+                            );
                         }
-                        None => {
-                            let msg = format!("Cannot reinstall '{name}': not installed.");
-                            error!("✖ {msg}");
-                            errors.push((name.clone(), SpsError::NotFound(msg)));
+                        // An unlinked keg without a receipt is what a pour that never finished
+                        // leaves; installing again moves it aside and pours a fresh one.
+                        Some(installed_info)
+                            if installed_info.pkg_type == PackageType::Formula
+                                && !keg_registry.is_keg_linked(name, &installed_info.path)
+                                && !keg::has_valid_receipt(&installed_info.path) =>
+                        {
+                            initial_ops.insert(name.clone(), (PipelineActionType::Install, None));
+                        }
+                        // An installed formula whose opt link is gone is relinked rather than
+                        // skipped, so there is a way to repair it short of deleting the keg.
+                        Some(installed_info)
+                            if installed_info.pkg_type == PackageType::Formula
+                                && !flags.only_dependencies
+                                && !flags.only_missing
+                                && !flags.dry_run
+                                && !keg_registry.is_keg_linked(name, &installed_info.path) =>
+                        {
                             processed.insert(name.clone());
+                            let formulary = Formulary::new(config.clone());
+                            let formula = match formulary.load_formula(name) {
+                                Ok(f) => Ok(f),
+                                Err(_) => api::get_formula(name).await,
+                            };
+                            match formula.and_then(|f| relink_keg(&f, &installed_info.path, config))
+                            {
+                                Ok(()) => {
+                                    already_installed.insert(name.clone());
+                                }
+                                Err(e) => errors.push((name.clone(), e)),
+                            }
                         }
-                    }
-                }
-            }
+                        // A cask whose receipt lists artifacts that are gone (say, an app deleted
+                        // from /Applications by hand) is reinstalled to repair it.
+                        Some(installed_info)
+                            if installed_info.pkg_type == PackageType::Cask
+                                && !flags.only_dependencies
+                                && build::cask::missing_artifacts(&installed_info.path)
+                                    .is_some_and(|missing| !missing.is_empty()) =>
+                        {
+                            warn!(
+                                "{} {} is broken (artifacts missing); reinstalling it",
+                                name, installed_info.version
+                            );
+                            initial_ops.insert(
+                                name.clone(),
+                                (
+                                    PipelineActionType::Reinstall {
+                                        version: installed_info.version.clone(),
+                                        current_install_path: installed_info.path.clone(),
+                                    },
+                                    None,
+                                ),
+                            );
+                        }
+                        // With --only-dependencies the target itself is dropped later, but its
+                        // dependencies still need resolving even if it is installed.
+                        Some(_installed_info) if !flags.only_dependencies => {
+                            already_installed.insert(name.clone());
+                            processed.insert(name.clone());
+                        }
+                        _ => {
+                            // Mark for install, need to fetch definition later
+                            initial_ops.insert(name.clone(), (PipelineActionType::Install, None));
+                        }
+                    }
+                }
+            }
+            CommandType::Reinstall => {
+                debug!("Planning for REINSTALL command");
+                for name in initial_targets {
+                    if processed.contains(name) {
+                        continue;
+                    }
+                    match sps_core::installed::get_installed_package(name, config).await? {
+                        Some(installed_info) => {
+                            initial_ops.insert(
+                                name.clone(),
+                                (
+                                    PipelineActionType::Reinstall {
+                                        version: installed_info.version.clone(),
+                                        current_install_path: installed_info.path.clone(),
+                                    },
+                                    None,
+                                ),
+                            ); // Need to fetch definition
+                        }
+                        None => {
+                            let msg = format!("Cannot reinstall '{name}': not installed.");
+                            error!("{} {msg}", ui::fail_mark());
+                            errors.push((name.clone(), SpsError::NotFound(msg)));
+                            processed.insert(name.clone());
+                        }
+                    }
+                }
+            }
             CommandType::Upgrade { all } => {
                 debug!("Planning for UPGRADE command (all={})", all);
                 let packages_to_check = if all {
@@ -324,10 +1085,20 @@ impl PipelineExecutor {
                         info_line("No installed packages found to check for upgrades.");
                     }
                     // else: warnings about specific packages already printed
-                    return Ok((jobs, errors, already_installed)); // No ops needed
+                    return Ok((jobs, errors, already_installed, HashMap::new()));
+                    // No ops needed
                 }
 
-                let updates = update_check::check_for_updates(&packages_to_check, &cache).await?;
+                // Casks named explicitly are upgraded even if they update themselves or are
+                // `:latest`; `--all` leaves those alone, as Homebrew does without `--greedy`.
+                let updates = update_check::check_for_updates(
+                    &packages_to_check,
+                    &cache,
+                    config,
+                    flags.fetch_head,
+                    !all,
+                )
+                .await?;
                 let update_map: HashMap<String, UpdateInfo> =
                     updates.into_iter().map(|u| (u.name.clone(), u)).collect();
 
@@ -356,6 +1127,15 @@ impl PipelineExecutor {
             }
         }
 
+        // Definitions given on the command line win over overrides and the API.
+        for (name, (_, def)) in initial_ops.iter_mut() {
+            if let (None, Some(cask)) = (&def, flags.cask_definitions.get(name)) {
+                *def = Some(InstallTargetIdentifier::Cask(Arc::new(
+                    build::cask::resolve_for_host(cask, config),
+                )));
+            }
+        }
+
         // --- Fetch Definitions for Install/Reinstall targets ---
         let definitions_to_fetch: Vec<String> = initial_ops
             .iter()
@@ -368,7 +1148,13 @@ impl PipelineExecutor {
                 "Fetching definitions for initial targets: {:?}",
                 definitions_to_fetch
             );
-            let fetched_defs = Self::fetch_target_definitions(&definitions_to_fetch, &cache).await;
+            let fetched_defs = Self::fetch_target_definitions(
+                &definitions_to_fetch,
+                &cache,
+                config,
+                flags.kind_hint,
+            )
+            .await;
 
             for (name, result) in fetched_defs {
                 match result {
@@ -379,7 +1165,8 @@ impl PipelineExecutor {
                     }
                     Err(e) => {
                         error!(
-                            "✖ Failed to get definition for target '{}': {}",
+                            "{} Failed to get definition for target '{}': {}",
+                            ui::fail_mark(),
                             name.cyan(),
                             e
                         );
@@ -391,6 +1178,31 @@ impl PipelineExecutor {
             }
         }
 
+        // Head builds need a git head source; fail such targets before resolving anything.
+        let mut without_head = Vec::new();
+        for (name, (action, def)) in &initial_ops {
+            if !builds_head(action, flags) {
+                continue;
+            }
+            let error = match def {
+                Some(InstallTargetIdentifier::Formula(f)) => {
+                    build::formula::source::head::require_head_spec(f).err()
+                }
+                Some(InstallTargetIdentifier::Cask(_)) => Some(SpsError::InstallError(format!(
+                    "{name} is a cask; only formulae can be installed with --HEAD"
+                ))),
+                None => None,
+            };
+            if let Some(e) = error {
+                errors.push((name.clone(), e));
+                without_head.push(name.clone());
+            }
+        }
+        for name in without_head {
+            initial_ops.remove(&name);
+            processed.insert(name);
+        }
+
         // --- Initial Dependency Resolution Setup ---
         let mut formulae_for_resolution: HashMap<String, InstallTargetIdentifier> = HashMap::new();
         let mut cask_queue: VecDeque<String> = VecDeque::new();
@@ -417,7 +1229,7 @@ impl PipelineExecutor {
                         // Avoid duplicate errors
                         let msg =
                             format!("Definition missing for target '{name}' after fetch attempt.");
-                        error!("✖ {msg}");
+                        error!("{} {msg}", ui::fail_mark());
                         errors.push((name.clone(), SpsError::Generic(msg)));
                     }
                     processed.insert(name.clone());
@@ -437,9 +1249,14 @@ impl PipelineExecutor {
                 // pre-fetching all needed cask defs. For simplicity sketch, assume pre-fetched.
                 // In reality, you might need another async fetch loop here or integrate into the
                 // initial fetch.
-                match block_on(api::get_cask(&token)) {
+                let lookup = match overrides::cask(config, &token) {
+                    Ok(Some(cask)) => Ok(cask),
+                    Ok(None) => block_on(api::get_cask(&token)),
+                    Err(e) => Err(e),
+                };
+                match lookup {
                     // block_on is suboptimal here
-                    Ok(c) => Arc::new(c),
+                    Ok(c) => Arc::new(build::cask::resolve_for_host(&c, config)),
                     Err(e) => {
                         if !errors.iter().any(|(n, _)| n == &token) {
                             errors.push((token.clone(), e));
@@ -462,9 +1279,14 @@ impl PipelineExecutor {
                 for formula_dep in &deps.formula {
                     if !formulae_for_resolution.contains_key(formula_dep) {
                         // Need to fetch formula definition before adding
-                        match Self::fetch_target_definitions(&[formula_dep.clone()], &cache)
-                            .await
-                            .remove(formula_dep)
+                        match Self::fetch_target_definitions(
+                            std::slice::from_ref(formula_dep),
+                            &cache,
+                            config,
+                            KindHint::Formula,
+                        )
+                        .await
+                        .remove(formula_dep)
                         {
                             Some(Ok(target_def @ InstallTargetIdentifier::Formula(_))) => {
                                 debug!(
@@ -513,7 +1335,8 @@ impl PipelineExecutor {
                 resolution_target_names
             );
             let formulary = Formulary::new(config.clone());
-            let keg_registry = KegRegistry::new(config.clone());
+            Self::prefetch_formula_definitions(&formulary, &cache, &formulae_for_resolution).await;
+            let keg_registry = KegRegistry::with_snapshot(config.clone(), Arc::clone(keg_snapshot));
             let ctx = ResolutionContext {
                 formulary: &formulary,
                 keg_registry: &keg_registry,
@@ -522,17 +1345,40 @@ impl PipelineExecutor {
                 include_test: false, // Typically false for install/upgrade
                 skip_recommended: flags.skip_recommended,
                 force_build: flags.build_from_source, // Pass build flag here
+                ignore_installed: flags.ignore_installed,
+                only_missing: flags.only_missing,
+                platform: Some(build::formula::host_platform()),
             };
             let mut resolver = DependencyResolver::new(ctx);
 
             match resolver.resolve_targets(&resolution_target_names) {
                 Ok(graph) => {
                     debug!("Dependency resolution successful.");
+                    // Disabled formulae (and their dependents) fail here, before any download.
+                    for (name, e) in &graph.errors {
+                        if matches!(e.as_ref(), SpsError::FormulaDisabled(_))
+                            && !errors.iter().any(|(n, _)| **n == **name)
+                        {
+                            errors.push((name.to_string(), e.as_ref().clone()));
+                        }
+                    }
+                    // Present-but-unlinked kegs are repaired in place instead of reinstalled.
+                    for dep in graph.resolution_details.values() {
+                        if flags.dry_run || dep.status != ResolutionStatus::InstalledUnlinked {
+                            continue;
+                        }
+                        if let Some(keg_path) = &dep.keg_path {
+                            if let Err(e) = relink_keg(&dep.formula, keg_path, config) {
+                                errors.push((dep.formula.name().to_string(), e));
+                            }
+                        }
+                    }
                     resolved_formula_graph = Some(Arc::new(graph));
                 }
                 Err(e) => {
                     error!(
-                        "✖ Fatal dependency resolution error: {}. Aborting operation.",
+                        "{} Fatal dependency resolution error: {}. Aborting operation.",
+                        ui::fail_mark(),
                         e
                     );
                     // Add error for all requested formulae
@@ -542,11 +1388,18 @@ impl PipelineExecutor {
                         }
                     }
                     // Return early as resolution is fundamental
-                    return Ok((jobs, errors, already_installed));
+                    return Ok((jobs, errors, already_installed, HashMap::new()));
                 }
             }
         }
 
+        let install_reasons = install_reasons(
+            initial_targets,
+            command_type == CommandType::Install && !flags.only_dependencies,
+            resolved_formula_graph.as_deref(),
+            &cask_deps_map,
+        );
+
         // --- Construct Final Job List ---
         let final_graph = resolved_formula_graph.clone(); // Arc clone
 
@@ -556,6 +1409,7 @@ impl PipelineExecutor {
                 continue;
             } // Skip errored targets
             if let Some(target_def) = opt_def {
+                let head = builds_head(&action, flags);
                 jobs.push(PipelineJob {
                     target: target_def.clone(),    // Clone here
                     download_path: PathBuf::new(), // Will be filled by download coordinator
@@ -584,22 +1438,29 @@ impl PipelineExecutor {
                             }
                         }
                     },
+                    head,
                 });
             }
         }
 
         // Add dependency installs from the graph
         if let Some(graph) = resolved_formula_graph {
+            // The plan lists each formula once, so only the initial targets can collide.
+            let initial_formulae: HashSet<String> = jobs
+                .iter()
+                .filter_map(|j| match &j.target {
+                    InstallTargetIdentifier::Formula(f) => Some(f.name().to_string()),
+                    _ => None,
+                })
+                .collect();
+            let errored: HashSet<&str> = errors.iter().map(|(n, _)| n.as_str()).collect();
             for dep in &graph.install_plan {
                 let name = dep.formula.name();
-                if errors.iter().any(|(n, _)| n == name) {
+                if errored.contains(name) {
                     continue;
                 } // Skip errored deps
                   // Add only if it wasn't an initial target already added
-                if !jobs.iter().any(|j| match &j.target {
-                    InstallTargetIdentifier::Formula(f) => f.name() == name,
-                    _ => false,
-                }) {
+                if !initial_formulae.contains(name) {
                     if matches!(
                         dep.status,
                         ResolutionStatus::Missing | ResolutionStatus::Requested
@@ -611,6 +1472,26 @@ impl PipelineExecutor {
                             resolved_graph: Some(graph.clone()),
                             is_source_build: flags.build_from_source
                                 || !build::formula::has_bottle_for_current_platform(&dep.formula),
+                            head: false,
+                        });
+                    } else if let (ResolutionStatus::Outdated, Some(keg_path)) =
+                        (dep.status, &dep.keg_path)
+                    {
+                        // Too old for a dependent being installed: upgraded alongside it.
+                        jobs.push(PipelineJob {
+                            target: InstallTargetIdentifier::Formula(dep.formula.clone()),
+                            download_path: PathBuf::new(),
+                            action: PipelineActionType::Upgrade {
+                                from_version: keg_path
+                                    .file_name()
+                                    .map(|v| v.to_string_lossy().into_owned())
+                                    .unwrap_or_default(),
+                                old_install_path: keg_path.clone(),
+                            },
+                            resolved_graph: Some(graph.clone()),
+                            is_source_build: flags.build_from_source
+                                || !build::formula::has_bottle_for_current_platform(&dep.formula),
+                            head: false,
                         });
                     }
                 } else {
@@ -620,7 +1501,8 @@ impl PipelineExecutor {
                         InstallTargetIdentifier::Formula(f) => f.name() == name,
                         _ => false,
                     }) {
-                        initial_job.is_source_build = flags.build_from_source
+                        initial_job.is_source_build = initial_job.head
+                            || flags.build_from_source
                             || !build::formula::has_bottle_for_current_platform(&dep.formula);
                     }
                 }
@@ -645,6 +1527,7 @@ impl PipelineExecutor {
                             action: PipelineActionType::Install,
                             resolved_graph: None,
                             is_source_build: false,
+                            head: false,
                         });
                     } else {
                         // Mark as already installed if it's just a dependency and present
@@ -660,27 +1543,184 @@ impl PipelineExecutor {
             }
         }
 
-        Ok((jobs, errors, already_installed))
+        // A bottle tag can match while the formula still needs a newer macOS than the host's, so
+        // `depends_on macos` is enforced here, before anything is downloaded.
+        jobs.retain(|job| {
+            let (name, constraints) = match &job.target {
+                InstallTargetIdentifier::Formula(f) => (
+                    f.name(),
+                    f.requirements
+                        .iter()
+                        .filter_map(|r| r.macos_constraint())
+                        .collect::<Vec<_>>(),
+                ),
+                InstallTargetIdentifier::Cask(c) => (
+                    c.token.as_str(),
+                    c.depends_on
+                        .as_ref()
+                        .and_then(|d| d.macos.as_ref())
+                        .map(macos::cask_constraints)
+                        .unwrap_or_default(),
+                ),
+            };
+            let Some(reason) = macos::unmet_requirement(&constraints) else {
+                return true;
+            };
+            if flags.ignore_requirements {
+                warn!("{} {} (continuing: --ignore-requirements)", name, reason);
+                true
+            } else {
+                errors.push((
+                    name.to_string(),
+                    SpsError::DependencyError(format!(
+                        "{name} {reason}; pass --ignore-requirements to install anyway"
+                    )),
+                ));
+                false
+            }
+        });
+
+        // Casks are checked against what is already on disk before anything is downloaded.
+        let mut checked_jobs = Vec::with_capacity(jobs.len());
+        for job in jobs {
+            let cask = match (&job.target, &job.action) {
+                (InstallTargetIdentifier::Cask(cask), PipelineActionType::Install) => cask,
+                _ => {
+                    checked_jobs.push(job);
+                    continue;
+                }
+            };
+            match check_preexisting_cask(cask, config, flags).await {
+                Ok(true) => checked_jobs.push(job),
+                Ok(false) => {
+                    already_installed.insert(cask.token.clone());
+                }
+                Err(e) => errors.push((cask.token.clone(), e)),
+            }
+        }
+        let mut jobs = checked_jobs;
+
+        // --only-dependencies: drop the requested targets, keep everything they pull in
+        if flags.only_dependencies {
+            jobs.retain(|j| {
+                let name = match &j.target {
+                    InstallTargetIdentifier::Formula(f) => f.name(),
+                    InstallTargetIdentifier::Cask(c) => c.token.as_str(),
+                };
+                !initial_targets.iter().any(|t| t == name)
+            });
+        }
+
+        Ok((jobs, errors, already_installed, install_reasons))
+    }
+
+    /// Warms `formulary` with the definitions the resolver is about to ask for: the resolution
+    /// targets and, level by level, their dependencies (optional and test dependencies are
+    /// loaded but not followed, as the resolver does). Names missing from the cache are fetched
+    /// concurrently (see [`metadata::fetch_formulae`]) so the resolver's sequential walk doesn't
+    /// stall on them one by one. Names the API doesn't serve are looked up once in the formula
+    /// index, which knows aliases. Other failures are ignored; the resolver reports those names
+    /// exactly as it would without the prefetch.
+    async fn prefetch_formula_definitions(
+        formulary: &Formulary,
+        cache: &Cache,
+        targets: &HashMap<String, InstallTargetIdentifier>,
+    ) {
+        let mut level: Vec<Formula> = Vec::new();
+        let mut seen: HashSet<String> = targets.keys().cloned().collect();
+        for (name, target) in targets {
+            if let InstallTargetIdentifier::Formula(formula) = target {
+                if !formulary.has_formula(name) {
+                    formulary.insert_formula(formula.as_ref().clone());
+                }
+                level.push(formula.as_ref().clone());
+            }
+        }
+        let mut fetched = 0;
+        let mut index_loaded = metadata::has_index(cache);
+        while !level.is_empty() {
+            let mut follow: Vec<String> = Vec::new();
+            let mut missing: Vec<String> = Vec::new();
+            for formula in &level {
+                for dep in formula.dependencies() {
+                    if !seen.insert(dep.name.clone()) {
+                        continue;
+                    }
+                    let followed = !dep
+                        .tags
+                        .intersects(DependencyTag::OPTIONAL | DependencyTag::TEST);
+                    if formulary.has_formula(&dep.name) {
+                        if followed {
+                            follow.push(dep.name.clone());
+                        }
+                    } else {
+                        missing.push(dep.name.clone());
+                    }
+                }
+            }
+            let mut next: Vec<Formula> = follow
+                .iter()
+                .filter_map(|name| formulary.load_formula(name).ok())
+                .collect();
+            fetched += missing.len();
+            let mut unknown: Vec<String> = Vec::new();
+            for (name, result) in metadata::fetch_formulae(cache, missing).await {
+                match result {
+                    Ok(formula) => {
+                        formulary.insert_formula(formula.clone());
+                        next.push(formula);
+                    }
+                    Err(e) => {
+                        debug!("Prefetch of '{}' failed: {}", name, e);
+                        unknown.push(name);
+                    }
+                }
+            }
+            // The API only serves formulae under their own names, so a dependency declared by
+            // an alias or old name can only be found through the index.
+            if !unknown.is_empty() && !index_loaded {
+                index_loaded = true;
+                match metadata::formula_index(cache).await {
+                    Ok(_) => next.extend(
+                        unknown
+                            .iter()
+                            .filter_map(|name| formulary.load_formula(name).ok())
+                            .filter(|formula| seen.insert(formula.name.clone())),
+                    ),
+                    Err(e) => debug!("Could not load the formula index for aliases: {}", e),
+                }
+            }
+            level = next;
+        }
+        if fetched > 0 {
+            debug!(
+                "Prefetched {} formula definition(s) not in the local cache",
+                fetched
+            );
+        }
     }
 
-    /// Fetches Formula or Cask definitions for a list of names.
+    /// Fetches Formula or Cask definitions for a list of names. `kind_hint` restricts the lookup
+    /// to formulae or casks.
     async fn fetch_target_definitions(
         names: &[String],
         cache: &Cache,
+        config: &Config,
+        kind_hint: KindHint,
     ) -> HashMap<String, Result<InstallTargetIdentifier>> {
         let mut results = HashMap::new();
         let mut futures = JoinSet::new();
 
-        // Attempt to load full lists first to minimize API calls
-        let formulae_map_res = load_or_fetch_json(cache, "formula.json", api::fetch_all_formulas())
-            .await
-            .map(|values| {
-                values
-                    .into_iter()
-                    .filter_map(|v| serde_json::from_value::<Formula>(v).ok())
-                    .map(|f| (f.name.clone(), Arc::new(f)))
-                    .collect::<HashMap<_, _>>()
-            });
+        // Attempt to load full lists first to minimize API calls. Under the lazy metadata
+        // strategy only what is cached is used; the rest is fetched by name below.
+        let lazy = metadata::strategy(config, cache, names.len()) == MetadataStrategy::Lazy;
+        let formulae_map_res = if lazy {
+            Ok(metadata::cached_formulae(cache, names))
+        } else {
+            metadata::formula_index(cache)
+                .await
+                .map(|raw| metadata::parse_index(&raw))
+        };
 
         let casks_map_res = load_or_fetch_json(cache, "cask.json", api::fetch_all_casks())
             .await
@@ -692,10 +1732,52 @@ impl PipelineExecutor {
                     .collect::<HashMap<_, _>>()
             });
 
+        let formula_overrides = match kind_hint {
+            KindHint::Cask => HashMap::new(),
+            _ => overrides::formulae(config),
+        };
+        let cask_overrides = match kind_hint {
+            KindHint::Formula => HashMap::new(),
+            _ => overrides::casks(config),
+        };
+
         for name in names {
             let name = name.clone();
-            let formulae_map_clone = formulae_map_res.as_ref().ok().cloned();
-            let casks_map_clone = casks_map_res.as_ref().ok().cloned();
+            // Local overrides win over both indexes, formulae first as below.
+            if let Some(formula) = formula_overrides.get(&name) {
+                let formula = build::formula::resolve_for_host(formula);
+                results.insert(
+                    name,
+                    Ok(InstallTargetIdentifier::Formula(Arc::new(formula))),
+                );
+                continue;
+            }
+            // Then definitions kept in a local tap checkout (`sps create`).
+            let tapped = tap_target(config, &name, kind_hint);
+            match tapped {
+                Ok(Some(target)) => {
+                    results.insert(name, Ok(target));
+                    continue;
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    results.insert(name, Err(e));
+                    continue;
+                }
+            }
+            if let Some(cask) = cask_overrides.get(&name) {
+                let cask = build::cask::resolve_for_host(cask, config);
+                results.insert(name, Ok(InstallTargetIdentifier::Cask(Arc::new(cask))));
+                continue;
+            }
+            let formulae_map_clone = match kind_hint {
+                KindHint::Cask => None,
+                _ => formulae_map_res.as_ref().ok().cloned(),
+            };
+            let casks_map_clone = match kind_hint {
+                KindHint::Formula => None,
+                _ => casks_map_res.as_ref().ok().cloned(),
+            };
 
             futures.spawn(async move {
                 let formulae_map = formulae_map_clone; // Use the cloned map
@@ -715,8 +1797,21 @@ impl PipelineExecutor {
                 // If not found in maps (maybe maps failed to load, or item is obscure), try direct
                 // API fetch This adds redundancy but makes it more robust if full
                 // list fetch fails
+                if kind_hint == KindHint::Cask {
+                    return match api::get_cask(&name).await {
+                        Ok(cask) => (name, Ok(InstallTargetIdentifier::Cask(Arc::new(cask)))),
+                        Err(e) => (name, Err(e)),
+                    };
+                }
                 match api::get_formula(&name).await {
                     // Using get_formula which returns Formula
+                    Ok(formula) if kind_hint == KindHint::Formula => {
+                        return (
+                            name,
+                            Ok(InstallTargetIdentifier::Formula(Arc::new(formula))),
+                        );
+                    }
+                    Err(e) if kind_hint == KindHint::Formula => return (name, Err(e)),
                     Ok(formula) => {
                         return (
                             name,
@@ -739,6 +1834,25 @@ impl PipelineExecutor {
         while let Some(res) = futures.join_next().await {
             match res {
                 Ok((name, result)) => {
+                    if let Ok(InstallTargetIdentifier::Formula(formula)) = &result {
+                        let from_map = formulae_map_res
+                            .as_ref()
+                            .is_ok_and(|map| map.contains_key(&name));
+                        if lazy && !from_map {
+                            metadata::cache_formula(cache, formula);
+                        }
+                    }
+                    // Packages are used as they install on this machine from here on.
+                    let result = result.map(|target| match target {
+                        InstallTargetIdentifier::Cask(cask) => InstallTargetIdentifier::Cask(
+                            Arc::new(build::cask::resolve_for_host(&cask, config)),
+                        ),
+                        InstallTargetIdentifier::Formula(formula) => {
+                            InstallTargetIdentifier::Formula(Arc::new(
+                                build::formula::resolve_for_host(&formula),
+                            ))
+                        }
+                    });
                     results.insert(name, result);
                 }
                 Err(e) => {
@@ -751,138 +1865,593 @@ impl PipelineExecutor {
     }
 
     /// Coordinates the download phase.
-    #[instrument(skip(planned_jobs, config, cache, client, job_tx, flags))]
+    #[instrument(skip(planned_jobs, config, cache, client, job_tx, flags, signals))]
     async fn coordinate_downloads(
         planned_jobs: Vec<PipelineJob>, // Takes ownership of the jobs Vec
-        config: &Config,
+        config: &Arc<Config>,
         cache: Arc<Cache>,
         client: Arc<reqwest::Client>,
         job_tx: Sender<PipelineJob>, // Sender for jobs ready to be installed
         flags: &PipelineFlags,
-    ) -> Result<usize> {
-        // Returns count of download errors
-        let mut download_join_set: JoinSet<Result<(PipelineJob, String)>> = JoinSet::new();
-        let mut download_errors_count = 0;
-
-        // Spawn download tasks
-        for mut job in planned_jobs {
-            // Mutate job to set is_source_build
-            let name = match &job.target {
-                InstallTargetIdentifier::Formula(f) => f.name().to_string(),
-                InstallTargetIdentifier::Cask(c) => c.token.clone(),
-            };
-            let name_clone = name.clone();
-            let target_type = job.target.clone(); // Clone Arc for the task
-            let cfg_clone = config.clone();
-            let cache_clone = Arc::clone(&cache);
-            let client_clone = Arc::clone(&client);
-            // Determine source build requirement *before* spawning download task
-            job.is_source_build = match &target_type {
-                InstallTargetIdentifier::Formula(f) => {
-                    flags.build_from_source || !build::formula::has_bottle_for_current_platform(f)
-                }
-                InstallTargetIdentifier::Cask(_) => false,
-            };
-            let is_source_build = job.is_source_build; // Copy bool for task
-
-            download_join_set.spawn(
-                async move {
-                    // Now call download_target with the pre-determined is_source_build flag
-                    let download_path = download_target_file(
-                        &name,
-                        &target_type,
-                        &cfg_clone,
-                        cache_clone,
-                        client_clone,
-                        is_source_build,
-                    )
-                    .await?;
-                    job.download_path = download_path; // Update job with download path
-                    Ok((job, name)) // Return the modified job
+        signals: &RunSignals,
+    ) -> Result<Vec<(String, SpsError)>> {
+        // Returns the download errors, keyed by package name
+        let mut download_errors: Vec<(String, SpsError)> = Vec::new();
+        // Every planned job is queued here straight away, in dependency order, whether or not
+        // its dependencies are installed yet: the workers pour each package once both its
+        // download and its dependencies are done, so later bottles arrive while earlier ones are
+        // still being poured. At most `download_concurrency` downloads are spawned at a time, so
+        // large plans neither open hundreds of connections nor hold a finished task per package
+        // until it is collected.
+        let download_slots = config.download_concurrency();
+        // Lets a panicked task be reported against the package it was downloading.
+        let mut task_names: HashMap<tokio::task::Id, String> = HashMap::new();
+        run_windowed(
+            planned_jobs,
+            download_slots,
+            &mut task_names,
+            || signals.stopped(),
+            |task_names, join_set, job| {
+                if signals.targets.is_given_up(job_name(&job)) {
+                    debug!("Not downloading {} (--fail-fast)", job_name(&job));
+                    signals.status.emit(InstallEvent::Skipped {
+                        name: job_name(&job).to_string(),
+                    });
+                    return;
                 }
-                .instrument(tracing::info_span!("download_task", pkg = %name_clone)), // Use name_clone here
-            );
-        }
+                Self::spawn_download(
+                    join_set,
+                    task_names,
+                    job,
+                    config,
+                    &cache,
+                    &client,
+                    flags,
+                    &signals.status,
+                );
+            },
+            |task_names, result| {
+                Self::handle_download_result(
+                    result,
+                    &job_tx,
+                    flags,
+                    signals,
+                    task_names,
+                    &mut download_errors,
+                )
+            },
+        )
+        .await;
 
-        // Process download results
-        while let Some(result) = download_join_set.join_next().await {
-            match result {
-                Ok(Ok((install_job, _name))) => {
-                    // Send the job with download_path populated
-                    if job_tx.send(install_job).is_err() {
-                        error!(
-                            "Job channel closed while sending download result for {}",
-                            _name
-                        );
-                        download_errors_count += 1; // Treat send error as a download phase error
-                    }
-                }
-                Ok(Err(e)) => {
-                    // Log error, name extraction might be needed if not DownloadError
-                    let name = match &e {
-                        SpsError::DownloadError(n, _, _) => n.clone(),
-                        _ => "[unknown]".to_string(),
+        Ok(download_errors)
+    }
+
+    /// Spawns the download (or head fetch) of one planned job onto `join_set`.
+    #[allow(clippy::too_many_arguments)]
+    fn spawn_download(
+        join_set: &mut JoinSet<DownloadTaskResult>,
+        task_names: &mut HashMap<tokio::task::Id, String>,
+        mut job: PipelineJob,
+        config: &Arc<Config>,
+        cache: &Arc<Cache>,
+        client: &Arc<reqwest::Client>,
+        flags: &PipelineFlags,
+        status: &Arc<StatusHub>,
+    ) {
+        // Mutate job to set is_source_build
+        let name = match &job.target {
+            InstallTargetIdentifier::Formula(f) => f.name().to_string(),
+            InstallTargetIdentifier::Cask(c) => c.token.clone(),
+        };
+        let name_clone = name.clone();
+        let target_type = job.target.clone(); // Clone Arc for the task
+        let cfg_clone = Arc::clone(config);
+        let cache_clone = Arc::clone(cache);
+        let client_clone = Arc::clone(client);
+        // Determine source build requirement *before* spawning download task
+        job.is_source_build = match &target_type {
+            InstallTargetIdentifier::Formula(f) => {
+                job.head
+                    || flags.build_from_source
+                    || !build::formula::has_bottle_for_current_platform(f)
+            }
+            InstallTargetIdentifier::Cask(_) => false,
+        };
+        let is_source_build = job.is_source_build; // Copy bool for task
+        let task_status = Arc::clone(status);
+
+        let handle = join_set.spawn(sps_common::cancel::with_token(
+            flags.cancel.clone(),
+            async move {
+                task_status.emit(InstallEvent::Started {
+                    name: name.clone(),
+                    phase: Phase::Download,
+                });
+                let reporter: progress::Reporter = {
+                    let (status, name) = (Arc::clone(&task_status), name.clone());
+                    Arc::new(move |downloaded, total| {
+                        status.emit(InstallEvent::Progress {
+                            name: name.clone(),
+                            downloaded,
+                            total,
+                        })
+                    })
+                };
+                if let (true, InstallTargetIdentifier::Formula(formula)) = (job.head, &target_type)
+                {
+                    // git2 is blocking, and reports no byte progress for a shallow clone.
+                    let (formula, cfg) = (Arc::clone(formula), Arc::clone(&cfg_clone));
+                    let fetched = blocking::run(&name, "download", move || {
+                        build::formula::source::head::fetch_head(&formula, &cfg)
+                            .map(|checkout| (formula.for_head(checkout.short_commit()), checkout))
+                    })
+                    .await;
+                    let (head_formula, checkout) = match fetched {
+                        Ok(fetched) => fetched,
+                        Err(e) => return Err((name, e)),
                     };
-                    error!("✖ Download failed for '{}': {}", name.cyan(), e);
-                    download_errors_count += 1;
+                    debug!("Fetched {} at {}", name, checkout.commit);
+                    task_status.emit(InstallEvent::Finished {
+                        name: name.clone(),
+                        phase: Phase::Download,
+                    });
+                    job.target = InstallTargetIdentifier::Formula(Arc::new(head_formula));
+                    job.download_path = checkout.path;
+                    return Ok((job, name));
+                }
+                // Now call download_target with the pre-determined is_source_build flag
+                let download = download_target_file(
+                    &name,
+                    &target_type,
+                    &cfg_clone,
+                    cache_clone,
+                    client_clone,
+                    is_source_build,
+                );
+                let download_path = match progress::with_reporter(reporter, download).await {
+                    Ok(path) => path,
+                    Err(e) => return Err((name, e)),
+                };
+                task_status.emit(InstallEvent::Finished {
+                    name: name.clone(),
+                    phase: Phase::Download,
+                });
+                job.download_path = download_path; // Update job with download path
+                Ok((job, name)) // Return the modified job
+            }
+            .instrument(tracing::info_span!("download_task", pkg = %name_clone)), // Use name_clone here
+        ));
+        task_names.insert(handle.id(), name_clone);
+    }
+
+    /// Forwards a finished download to the workers or records its failure. Returns whether the
+    /// remaining downloads should be aborted (--fail-fast).
+    fn handle_download_result(
+        result: std::result::Result<(tokio::task::Id, DownloadTaskResult), tokio::task::JoinError>,
+        job_tx: &Sender<PipelineJob>,
+        flags: &PipelineFlags,
+        signals: &RunSignals,
+        task_names: &mut HashMap<tokio::task::Id, String>,
+        download_errors: &mut Vec<(String, SpsError)>,
+    ) -> bool {
+        // Dropped for every finished task, so the map only holds the ones in flight.
+        let task_name = match &result {
+            Ok((id, _)) => task_names.remove(id),
+            Err(join_error) => task_names.remove(&join_error.id()),
+        };
+        match result {
+            Ok((_, Ok((install_job, name)))) => {
+                // Send the job with download_path populated
+                if job_tx.send(install_job).is_err() {
+                    error!(
+                        "Job channel closed while sending download result for {}",
+                        name
+                    );
+                    // Treat send error as a download phase error
+                    download_errors
+                        .push((name, SpsError::Generic("Job channel closed".to_string())));
+                }
+            }
+            Ok((_, Err((name, e)))) => {
+                error!(
+                    "{} Download failed for '{}': {}",
+                    ui::fail_mark(),
+                    name.cyan(),
+                    e
+                );
+                signals.status.emit(InstallEvent::Failed {
+                    name: name.clone(),
+                    phase: Phase::Download,
+                    error: e.to_string(),
+                });
+                if flags.fail_fast {
+                    if !signals.targets.give_up(&name) {
+                        info_line(format!(
+                            "Giving up {} (--fail-fast); other targets continue.",
+                            signals.targets.describe(&name)
+                        ));
+                    } else if !signals.abort.swap(true, Ordering::SeqCst) {
+                        info_line("Stopping remaining downloads (--fail-fast).");
+                        download_errors.push((name, e));
+                        return true;
+                    }
                 }
-                Err(join_error) => {
-                    error!("✖ Download task panicked: {}", join_error);
-                    download_errors_count += 1;
+                download_errors.push((name, e));
+            }
+            Err(join_error) if join_error.is_cancelled() => {
+                debug!("Download task {} cancelled", join_error.id());
+            }
+            Err(join_error) => {
+                let name = task_name.unwrap_or_else(|| "[Download Phase]".to_string());
+                let reason = match join_error.try_into_panic() {
+                    Ok(payload) => blocking::panic_message(payload.as_ref()),
+                    Err(e) => e.to_string(),
+                };
+                error!(
+                    "{} Download task for '{}' panicked: {}",
+                    ui::fail_mark(),
+                    name.cyan(),
+                    reason
+                );
+                signals.status.emit(InstallEvent::Failed {
+                    name: name.clone(),
+                    phase: Phase::Download,
+                    error: format!("download task panicked: {reason}"),
+                });
+                let all_given_up = flags.fail_fast && signals.targets.give_up(&name);
+                download_errors.push((
+                    name.clone(),
+                    SpsError::TaskPanicked {
+                        package: name,
+                        phase: "download".to_string(),
+                        message: reason,
+                    },
+                ));
+                if all_given_up && !signals.abort.swap(true, Ordering::SeqCst) {
+                    return true;
                 }
             }
         }
-
-        Ok(download_errors_count)
+        false
     }
 
-    /// Spawns a task to coordinate worker threads.
+    /// Spawns the task that hands downloaded jobs to the worker pool. A job starts once the
+    /// scheduler has seen the jobs for its in-plan dependencies install; if a required one fails,
+    /// the job is reported as failed without being attempted. A failed optional or recommended
+    /// dependency is only warned about (unless `--strict-deps`); the task returns those.
+    #[allow(clippy::too_many_arguments)]
     fn coordinate_workers(
         pool: ThreadPool,
         job_rx: Receiver<PipelineJob>,
         result_tx: Sender<PipelineJobResult>,
-        config: &Config,
+        mut scheduler: Scheduler,
+        config: Arc<Config>,
         cache: Arc<Cache>,
-        // flags are passed within the PipelineJob
-    ) -> tokio::task::JoinHandle<()> {
-        let cfg_clone = config.clone(); // Clone config once for the coordinator task
-        tokio::spawn(
-            async move {
-                while let Ok(job) = job_rx.recv() {
-                    let pkg_name = match &job.target {
-                        InstallTargetIdentifier::Formula(f) => f.name().to_string(),
-                        InstallTargetIdentifier::Cask(c) => c.token.clone(),
-                    };
-                    let res_tx = result_tx.clone();
-                    let worker_cfg = cfg_clone.clone(); // Clone config again for the worker thread
-                    let worker_cache = Arc::clone(&cache);
-                    let install_span = tracing::info_span!("install_worker", pkg = %pkg_name);
+        keg_snapshot: Arc<KegSnapshot>,
+        signals: RunSignals,
+    ) -> tokio::task::JoinHandle<MissingSoftDeps> {
+        let span = tracing::Span::current();
+        tokio::task::spawn_blocking(move || {
+            let _entered = span.enter();
+            let (done_tx, done_rx) = unbounded::<(String, bool)>();
+            let mut job_rx = job_rx;
+            let mut downloads_open = true;
+            // Downloaded and waiting for dependencies to install.
+            let mut waiting: HashMap<Arc<str>, PipelineJob> = HashMap::new();
+            // Skipped before their download arrived, with the package that caused it.
+            let mut skipped_early: HashMap<Arc<str>, Arc<str>> = HashMap::new();
+            // Only as many jobs as there are workers are handed to the pool, so its queue stays
+            // proportional to the concurrency rather than to the plan.
+            let worker_slots = config.max_concurrent_installs.max(1);
+            let mut waiting_mark = Watermark::new("Downloaded jobs waiting to install");
+            let mut missing_soft_deps: MissingSoftDeps = Vec::new();
+
+            loop {
+                // In-flight installs still report back, so the loop ends once they have.
+                if signals.cancel.is_cancelled()
+                    && !downloads_open
+                    && scheduler.progress().running == 0
+                {
+                    break;
+                }
+                if !signals.stopped() {
+                    let free = worker_slots.saturating_sub(scheduler.progress().running);
+                    let accept =
+                        |n: &str| waiting.contains_key(n) && !signals.targets.is_given_up(n);
+                    for name in scheduler.ready_matching(free, accept) {
+                        // Validated against the node state at the moment it is taken, so a job
+                        // skipped in the meantime is never spawned.
+                        if !scheduler.start(&name) {
+                            continue;
+                        }
+                        for (dep, tags) in scheduler.missing_soft_dependencies(&name) {
+                            warn!(
+                                "Installing {} without {} dependency {}, which failed",
+                                name,
+                                soft_tag_label(tags),
+                                dep
+                            );
+                            missing_soft_deps.push((name.to_string(), dep.to_string(), tags));
+                        }
+                        if let Some(job) = waiting.remove(&name) {
+                            Self::spawn_install(
+                                &pool,
+                                job,
+                                result_tx.clone(),
+                                done_tx.clone(),
+                                Arc::clone(&config),
+                                Arc::clone(&cache),
+                                Arc::clone(&keg_snapshot),
+                                Arc::clone(&signals.status),
+                                signals.cancel.clone(),
+                            );
+                        }
+                    }
+                }
+                if !downloads_open && scheduler.is_finished() {
+                    break;
+                }
 
-                    pool.execute(move || {
-                        // Run the potentially blocking install logic in the thread pool
-                        let result = install_span
-                            .in_scope(|| Self::run_pipeline_job(job, &worker_cfg, worker_cache));
+                let skipped = crossbeam_channel::select! {
+                    recv(job_rx) -> msg => match msg {
+                        Ok(job) => {
+                            let name: Arc<str> = Arc::from(job_name(&job));
+                            match skipped_early.remove(&name) {
+                                Some(cause) => vec![(name, cause, Some(job))],
+                                None => {
+                                    waiting.insert(name, job);
+                                    waiting_mark.observe(waiting.len());
+                                    Vec::new()
+                                }
+                            }
+                        }
+                        Err(_) => {
+                            downloads_open = false;
+                            job_rx = crossbeam_channel::never();
+                            // Whatever never arrived failed to download (already reported).
+                            let mut skipped = Vec::new();
+                            while let Some(name) = scheduler
+                                .pending()
+                                .into_iter()
+                                .find(|n| !waiting.contains_key(n))
+                            {
+                                skipped.extend(scheduler.complete(&name, false));
+                            }
+                            skipped
+                                .into_iter()
+                                .map(|(node, cause)| {
+                                    let job = waiting.remove(&node);
+                                    (node, cause, job)
+                                })
+                                .collect()
+                        }
+                    },
+                    recv(done_rx) -> msg => match msg {
+                        Ok((name, success)) => {
+                            // Results are collected after the downloads; stop fetching for the
+                            // given-up targets now.
+                            if !success && scheduler.policy() == FailurePolicy::StopAll {
+                                signals.targets.give_up(&name);
+                            }
+                            scheduler
+                            .complete(&name, success)
+                            .into_iter()
+                            .map(|(node, cause)| {
+                                let job = waiting.remove(&node);
+                                (node, cause, job)
+                            })
+                            .collect()
+                        }
+                        Err(_) => Vec::new(),
+                    },
+                };
 
-                        if res_tx.send(result).is_err() {
-                            warn!(
-                                "Result channel closed, could not send install result for {}.",
-                                pkg_name
+                for (name, cause, job) in skipped {
+                    match job {
+                        // --fail-fast or cancelled: left out of the run rather than failed.
+                        Some(_)
+                            if scheduler.policy() == FailurePolicy::StopAll
+                                || signals.cancel.is_cancelled() =>
+                        {
+                            debug!("Skipping {} after {} did not install", name, cause);
+                            signals.status.emit(InstallEvent::Skipped {
+                                name: name.to_string(),
+                            });
+                        }
+                        Some(job) => {
+                            let pkg_type = match job.target {
+                                InstallTargetIdentifier::Formula(_) => PackageType::Formula,
+                                InstallTargetIdentifier::Cask(_) => PackageType::Cask,
+                            };
+                            let failure = job_failed(
+                                name.to_string(),
+                                pkg_type,
+                                job.action,
+                                SpsError::DependencyError(format!(
+                                    "not installed because its dependency {cause} failed"
+                                )),
                             );
+                            if result_tx.send(failure).is_err() {
+                                warn!("Result channel closed while reporting a skipped package.");
+                            }
                         }
-                    });
+                        None => {
+                            skipped_early.insert(name, cause);
+                        }
+                    }
                 }
-                debug!("Job channel closed, worker coordinator task finishing.");
             }
-            .in_current_span(), // Inherit span context
-        )
-    }
 
-    /// Collects results from worker threads.
-    fn collect_results(result_rx: Receiver<PipelineJobResult>) -> Vec<(String, SpsError)> {
-        let mut install_errors: Vec<(String, SpsError)> = Vec::new();
+            // Only left behind when the run stopped early (--fail-fast or cancelled).
+            for (name, _) in waiting {
+                debug!("Skipping {} as the run stopped early", name);
+                signals.status.emit(InstallEvent::Skipped {
+                    name: name.to_string(),
+                });
+            }
+            debug!(
+                "Job channel closed, worker coordinator task finishing: {:?}",
+                scheduler.progress()
+            );
+            missing_soft_deps
+        })
+    }
+
+    /// Runs one job on the pool, reporting its result and whether it succeeded.
+    #[allow(clippy::too_many_arguments)]
+    fn spawn_install(
+        pool: &ThreadPool,
+        job: PipelineJob,
+        res_tx: Sender<PipelineJobResult>,
+        done_tx: Sender<(String, bool)>,
+        worker_cfg: Arc<Config>,
+        worker_cache: Arc<Cache>,
+        worker_kegs: Arc<KegSnapshot>,
+        worker_status: Arc<StatusHub>,
+        cancel: CancellationToken,
+    ) {
+        let pkg_name = job_name(&job).to_string();
+        let install_span = tracing::info_span!("install_worker", pkg = %pkg_name);
+        pool.execute(move || {
+            // Run the potentially blocking install logic in the thread pool
+            let result = install_span.in_scope(|| {
+                let is_formula = matches!(job.target, InstallTargetIdentifier::Formula(_));
+                let pkg_type = if is_formula {
+                    PackageType::Formula
+                } else {
+                    PackageType::Cask
+                };
+                let action = job.action.clone();
+                worker_status.emit(InstallEvent::Started {
+                    name: pkg_name.clone(),
+                    phase: Phase::Install,
+                });
+                let reporter: build::progress::PourReporter = {
+                    let (status, name) = (Arc::clone(&worker_status), pkg_name.clone());
+                    Arc::new(move |pour: build::progress::PourProgress| {
+                        status.emit(InstallEvent::Pour {
+                            name: name.clone(),
+                            files: pour.files,
+                            total_files: pour.total_files,
+                            bytes: pour.bytes,
+                            archive_read: pour.archive_read,
+                            archive_size: pour.archive_size,
+                        })
+                    })
+                };
+                // A panic would otherwise kill the pool thread without a result,
+                // losing the package from the report.
+                let result = sps_common::cancel::with_thread_token(cancel, || {
+                    blocking::run_sync(&pkg_name, "install", || {
+                        Ok(build::progress::with_pour_reporter(reporter, || {
+                            Self::run_pipeline_job(job, &worker_cfg, worker_cache)
+                        }))
+                    })
+                })
+                .unwrap_or_else(|e| job_failed(pkg_name.clone(), pkg_type, action, e));
+                // Keep the shared snapshot in line with what this task just poured.
+                if is_formula {
+                    if let Err(e) = worker_kegs.invalidate(&pkg_name) {
+                        debug!("Failed to refresh keg snapshot for {}: {}", pkg_name, e);
+                    }
+                }
+                result
+            });
+
+            // The coordinator hears first, so dependents can start while the result waits to be
+            // collected.
+            let _ = done_tx.send((pkg_name.clone(), job_succeeded(&result)));
+            if res_tx.send(result).is_err() {
+                warn!(
+                    "Result channel closed, could not send install result for {}.",
+                    pkg_name
+                );
+            }
+        });
+    }
+
+    /// Prints the post-install action section and, in interactive mode, waits for pending system
+    /// extension approvals. Returns whether nothing is left for the user to do.
+    async fn report_pending_actions(
+        pending: &PendingActions,
+        config: &Config,
+        flags: &PipelineFlags,
+    ) -> bool {
+        if pending.is_empty() {
+            return true;
+        }
+        output::println(format!("\n{}", "==> Action required".yellow().bold()));
+        for (name, actions) in pending {
+            output::println(format!("  {}:", name.cyan()));
+            for action in actions {
+                output::println(format!("    {} {}", ui::bullet(), action.describe()));
+            }
+        }
+        // Printed verbatim, as the cask declares them, so they can be reviewed and copied.
+        let postflight: Vec<_> = pending
+            .iter()
+            .flat_map(|(name, actions)| {
+                actions.iter().filter_map(move |action| match action {
+                    PostInstallAction::ReviewPostflight { commands } => Some((name, commands)),
+                    _ => None,
+                })
+            })
+            .collect();
+        if !postflight.is_empty() {
+            output::println(format!(
+                "\n{}",
+                "==> Postflight steps (not run by sps)".yellow().bold()
+            ));
+            for (name, commands) in postflight {
+                output::println(format!("  {}:", name.cyan()));
+                for command in commands {
+                    output::println(format!("    {command}"));
+                }
+            }
+        }
+
+        let needs_approval = pending
+            .iter()
+            .any(|(_, a)| a.contains(&PostInstallAction::ApproveSystemExtension));
+        let needs_other = pending.iter().any(|(_, a)| {
+            a.iter()
+                .any(|a| *a != PostInstallAction::ApproveSystemExtension)
+        });
+        if !needs_approval || flags.no_wait || !std::io::stdin().is_terminal() {
+            return false;
+        }
+
+        info_line(format!(
+            "Waiting up to {} minutes for the system extension to be approved (use --no-wait to skip)...",
+            SYSTEM_EXTENSION_WAIT.as_secs() / 60
+        ));
+        let cfg = config.clone();
+        let approved = tokio::task::spawn_blocking(move || {
+            post_install::wait_for_system_extension_approval(&cfg, SYSTEM_EXTENSION_WAIT)
+        })
+        .await
+        .unwrap_or(false);
+        if approved {
+            info_line(format!("{} System extension approved.", ui::ok_mark()));
+        } else {
+            warn!("System extension approval still pending.");
+        }
+        approved && !needs_other
+    }
+
+    /// Collects results from worker threads, returning the packages that succeeded, the errors
+    /// and the packages that still need user action.
+    fn collect_results(
+        result_rx: Receiver<PipelineJobResult>,
+        fail_fast: bool,
+        signals: &RunSignals,
+    ) -> (Vec<String>, Vec<(String, SpsError)>, PendingActions) {
+        let mut succeeded: Vec<String> = Vec::new();
+        let mut install_errors: Vec<(String, SpsError)> = Vec::new();
+        let mut pending_actions: PendingActions = Vec::new();
         for result in result_rx {
             // Drains the channel
-            let (_result, was_success, message) = match result {
+            let (name, was_success, message) = match result {
                 PipelineJobResult::InstallOk(name, pkg_type) => {
                     let pkg_type_str = match pkg_type {
                         PackageType::Formula => "Formula",
@@ -921,6 +2490,46 @@ impl PipelineExecutor {
                         format!("Reinstalled {} {}", pkg_type_str, name.green()),
                     )
                 }
+                PipelineJobResult::AlreadyInstalled(name, pkg_type) => {
+                    let pkg_type_str = match pkg_type {
+                        PackageType::Formula => "Formula",
+                        PackageType::Cask => "Cask",
+                    };
+                    (
+                        name.clone(),
+                        true,
+                        format!(
+                            "{} {} already installed by another process",
+                            pkg_type_str,
+                            name.green()
+                        ),
+                    )
+                }
+                PipelineJobResult::ActionRequired(name, pkg_type, actions) => {
+                    let pkg_type_str = match pkg_type {
+                        PackageType::Formula => "Formula",
+                        PackageType::Cask => "Cask",
+                    };
+                    // A staged installer means nothing is installed yet, so it isn't reported
+                    // as one.
+                    let message = if actions.iter().any(PostInstallAction::is_manual_install) {
+                        format!(
+                            "Staged {} {} ({})",
+                            pkg_type_str,
+                            name.green(),
+                            "manual action required".yellow()
+                        )
+                    } else {
+                        format!(
+                            "Installed {} {} ({})",
+                            pkg_type_str,
+                            name.green(),
+                            "action required".yellow()
+                        )
+                    };
+                    pending_actions.push((name.clone(), actions));
+                    (name.clone(), true, message)
+                }
                 PipelineJobResult::InstallErr(name, pkg_type, e) => {
                     let pkg_type_str = match pkg_type {
                         PackageType::Formula => "Formula",
@@ -958,12 +2567,35 @@ impl PipelineExecutor {
             };
 
             if !was_success {
-                error!("✖ {}", message);
+                signals.status.emit(InstallEvent::Failed {
+                    name: name.clone(),
+                    phase: Phase::Install,
+                    error: install_errors
+                        .last()
+                        .map(|(_, e)| e.to_string())
+                        .unwrap_or_default(),
+                });
+                error!("{} {}", ui::fail_mark(), message);
+                if fail_fast {
+                    if !signals.targets.give_up(&name) {
+                        info_line(format!(
+                            "Giving up {} (--fail-fast); other targets continue.",
+                            signals.targets.describe(&name)
+                        ));
+                    } else if !signals.abort.swap(true, Ordering::SeqCst) {
+                        info_line("Not starting further installs (--fail-fast).");
+                    }
+                }
             } else {
+                signals.status.emit(InstallEvent::Finished {
+                    name: name.clone(),
+                    phase: Phase::Install,
+                });
                 info_line(message);
+                succeeded.push(name);
             }
         }
-        install_errors
+        (succeeded, install_errors, pending_actions)
     }
 
     /// The actual worker function performing pre-uninstall and installation.
@@ -977,8 +2609,59 @@ impl PipelineExecutor {
             InstallTargetIdentifier::Cask(c) => (c.token.clone(), PackageType::Cask),
         };
 
+        // --- 0. Serialize cask operations on the same token ---
+        // Held until this function returns, so it covers both the pre-install uninstall and the
+        // install itself. Another sps process may have installed the cask while we waited.
+        let _cask_lock = match &job.target {
+            InstallTargetIdentifier::Cask(cask) => {
+                match build::cask::lock::CaskLock::acquire(&cask.token, config) {
+                    Ok(lock) => {
+                        if matches!(job.action, PipelineActionType::Install)
+                            && build::cask::install_state(cask, config)
+                                == build::cask::CaskInstallState::Installed
+                        {
+                            debug!("Cask {} was installed while waiting for its lock", name);
+                            return PipelineJobResult::AlreadyInstalled(name, pkg_type);
+                        }
+                        Some(lock)
+                    }
+                    Err(e) => return job_failed(name, pkg_type, job.action, e),
+                }
+            }
+            InstallTargetIdentifier::Formula(_) => None,
+        };
+
         // --- 1. Pre-Install Step (Uninstall for Upgrade/Reinstall) ---
+        // A formula's old links stay up until the new keg is linked over them. An upgraded
+        // formula's old keg stays in the Cellar too (see `versions`), so the opt link always
+        // points at a complete keg and `sps switch` can go back to it.
+        let mut previous_links = None;
+        let retained_keg = match (&job.target, &job.action) {
+            (
+                InstallTargetIdentifier::Formula(formula),
+                PipelineActionType::Upgrade {
+                    from_version,
+                    old_install_path,
+                },
+            ) if formula
+                .install_prefix(&config.cellar)
+                .is_ok_and(|new_path| &new_path != old_install_path) =>
+            {
+                Some((old_install_path.clone(), from_version.clone()))
+            }
+            _ => None,
+        };
         let pre_install_result = match &job.action {
+            PipelineActionType::Upgrade {
+                old_install_path, ..
+            } if retained_keg.is_some() => {
+                debug!(
+                    "Keeping {} for a later switch back",
+                    old_install_path.display()
+                );
+                previous_links = Some(PreviousLinks::read(old_install_path));
+                Ok(())
+            }
             PipelineActionType::Upgrade {
                 from_version,
                 old_install_path,
@@ -997,7 +2680,13 @@ impl PipelineExecutor {
                     pkg_type: pkg_type.clone(),
                     path: old_install_path.clone(),
                 };
-                let uninstall_opts = UninstallOptions { skip_zap: true }; // CRUCIAL
+                let uninstall_opts = UninstallOptions {
+                    skip_zap: true, // CRUCIAL
+                    keep_links: pkg_type == PackageType::Formula,
+                };
+                if uninstall_opts.keep_links {
+                    previous_links = Some(PreviousLinks::read(old_install_path));
+                }
 
                 // Call the appropriate core uninstall function
                 match pkg_type {
@@ -1042,9 +2731,59 @@ impl PipelineExecutor {
             pkg_type_str(pkg_type.clone()),
             name
         ));
-        let install_result = Self::perform_actual_installation(&job, config, cache); // Pass job by ref
+        let started = Instant::now();
+        let install_result =
+            Self::perform_actual_installation(&job, config, cache, previous_links.as_ref()) // Pass job by ref
+                .map_err(|e| map_permission_error(e, &job, config));
+        if install_result.is_ok() {
+            let event = match job.action {
+                PipelineActionType::Install => MetricEvent::Install,
+                PipelineActionType::Upgrade { .. } => MetricEvent::Upgrade,
+                PipelineActionType::Reinstall { .. } => MetricEvent::Reinstall,
+            };
+            metrics::record(config, &name, event, Some(started.elapsed()));
+        }
+        match (&retained_keg, &install_result, &job.target) {
+            (Some((old_path, old_version)), Ok(()), InstallTargetIdentifier::Formula(formula)) => {
+                let recorded = formula.install_prefix(&config.cellar).and_then(|new_path| {
+                    build::formula::versions::record_upgrade(&new_path, old_path, old_version)
+                });
+                if let Err(e) = recorded {
+                    warn!("Could not record the previous version of {}: {}", name, e);
+                }
+            }
+            // The old keg is still there; put its links back over whatever the new one got to.
+            (Some((old_path, _)), Err(e), InstallTargetIdentifier::Formula(formula))
+                if !matches!(e, SpsError::PostInstallFailed(..)) =>
+            {
+                if let Err(e) =
+                    build::formula::link::link_formula_artifacts(formula, old_path, config)
+                {
+                    warn!("Could not relink {} {}: {}", name, old_path.display(), e);
+                }
+            }
+            (None, Err(e), _) => {
+                // The old keg is gone; don't leave its links dangling. After a failed
+                // post-install step the new keg is already linked over them.
+                if let Some(previous) = &previous_links {
+                    if !matches!(e, SpsError::PostInstallFailed(..)) {
+                        previous.remove(config);
+                    }
+                }
+            }
+            _ => {}
+        }
 
         // --- 3. Return result based on action type and install outcome ---
+        if let (InstallTargetIdentifier::Cask(cask), Ok(_)) = (&job.target, &install_result) {
+            let actions = post_install::detect_post_install_actions(
+                cask,
+                &build::cask::get_cask_version_path(cask, config),
+            );
+            if !actions.is_empty() {
+                return PipelineJobResult::ActionRequired(name, pkg_type, actions);
+            }
+        }
         match (job.action, install_result) {
             (PipelineActionType::Install, Ok(_)) => PipelineJobResult::InstallOk(name, pkg_type),
             (PipelineActionType::Install, Err(e)) => {
@@ -1066,15 +2805,37 @@ impl PipelineExecutor {
     }
 
     /// Extracted core install logic (previously part of run_install).
-    #[instrument(skip(job, config, _cache), fields(pkg = %match &job.target {
+    #[instrument(skip(job, config, cache), fields(pkg = %match &job.target {
         InstallTargetIdentifier::Formula(f) => f.name().to_string(),
         InstallTargetIdentifier::Cask(c) => c.token.clone(),
     }))]
     fn perform_actual_installation(
         job: &PipelineJob,
         config: &Config,
-        _cache: Arc<Cache>,
+        cache: Arc<Cache>,
+        previous_links: Option<&PreviousLinks>,
     ) -> Result<()> {
+        // Linking is followed by the formula's post-install step and, when configured, the smoke
+        // check of its linked executables, in this same blocking task.
+        let link = |formula: &Formula, installed_dir: &Path| {
+            keg::mark_unlinked(installed_dir)?;
+            match previous_links {
+                Some(previous) => build::formula::link::relink_formula_artifacts(
+                    formula,
+                    installed_dir,
+                    config,
+                    previous,
+                ),
+                None => {
+                    build::formula::link::link_formula_artifacts(formula, installed_dir, config)
+                }
+            }?;
+            build::formula::post_install::run_post_install(formula, installed_dir, config)?;
+            if config.post_install_check {
+                build::formula::smoke::run_smoke_check(formula, installed_dir, config)?;
+            }
+            Ok(())
+        };
         match &job.target {
             InstallTargetIdentifier::Formula(formula) => {
                 let install_dir = formula.install_prefix(&config.cellar)?;
@@ -1085,7 +2846,7 @@ impl PipelineExecutor {
 
                 if job.is_source_build {
                     // Source Build Logic
-                    info_line(format!("Building {} from source", formula.name()));
+                    package_line(format!("Building {} from source", formula.name()));
                     let resolved_graph = job.resolved_graph.as_ref().ok_or_else(|| {
                         SpsError::Generic("Missing resolved graph for source build".to_string())
                     })?;
@@ -1099,29 +2860,49 @@ impl PipelineExecutor {
                         config,
                         &all_dep_paths,
                     ));
-                    match build_result {
-                        Ok(installed_dir) => build::formula::link::link_formula_artifacts(
-                            formula,
+                    let installed_dir = build_result?;
+                    if job.head {
+                        build::formula::source::head::record_head(
                             &installed_dir,
-                            config,
-                        ),
-                        Err(e) => Err(e),
+                            formula,
+                            &job.download_path,
+                        )?;
                     }
+                    link(formula, &installed_dir)
                 } else {
                     // Bottle Install Logic
-                    info_line(format!("Installing bottle for {}", formula.name()));
+                    package_line(format!("Installing bottle for {}", formula.name()));
                     let installed_dir = build::formula::bottle::install_bottle(
                         &job.download_path,
                         formula, // Pass the Arc<Formula> by ref
                         config,
                     )?;
-                    build::formula::link::link_formula_artifacts(formula, &installed_dir, config)
+                    link(formula, &installed_dir)
                 }
             }
             InstallTargetIdentifier::Cask(cask) => {
                 // Cask Install Logic
-                info_line(format!("Installing cask {}", cask.token));
-                build::cask::install_cask(cask, &job.download_path, config)
+                package_line(format!("Installing cask {}", cask.token));
+                match build::cask::install_cask(cask, &job.download_path, config) {
+                    // A cached archive that cannot be mounted/extracted is most likely truncated
+                    // or corrupt: evict it and retry with a fresh download once.
+                    Err(SpsError::ArtifactUnpackError(msg)) => {
+                        warn!(
+                            "Cached download for cask {} is unusable ({}). Re-downloading.",
+                            cask.token, msg
+                        );
+                        build::cask::evict_cached_download(&job.download_path);
+                        // Workers are plain threads; reqwest needs a tokio reactor to run on.
+                        let runtime = tokio::runtime::Builder::new_current_thread()
+                            .enable_all()
+                            .build()
+                            .map_err(|e| SpsError::Io(Arc::new(e)))?;
+                        let fresh_path =
+                            runtime.block_on(build::cask::download_cask(cask, &cache, config))?;
+                        build::cask::install_cask(cask, &fresh_path, config)
+                    }
+                    other => other,
+                }
             }
         }
     }
@@ -1129,6 +2910,104 @@ impl PipelineExecutor {
 
 // --- Helper Functions (Moved from old install.rs or new) ---
 
+/// Whether an initial target is built from its head: with `--HEAD`, or when it replaces a keg
+/// that was itself built from a head (reinstalls, and upgrades found by `--fetch-HEAD`).
+fn builds_head(action: &PipelineActionType, flags: &PipelineFlags) -> bool {
+    let replaced_version = match action {
+        PipelineActionType::Install => return flags.head,
+        PipelineActionType::Upgrade { from_version, .. } => from_version,
+        PipelineActionType::Reinstall { version, .. } => version,
+    };
+    flags.head || replaced_version.starts_with(HEAD_VERSION_PREFIX)
+}
+
+/// Verifies that every directory the pipeline writes to is writable (or creatable), so that a
+/// read-only prefix is reported up front instead of failing halfway through an install.
+/// The definition of `name` in a local tap checkout: its formula, or else its cask.
+fn tap_target(
+    config: &Config,
+    name: &str,
+    kind_hint: KindHint,
+) -> Result<Option<InstallTargetIdentifier>> {
+    if kind_hint != KindHint::Cask {
+        if let Some(formula) = formulary::tap_formula(config, name)? {
+            let formula = build::formula::resolve_for_host(&formula);
+            return Ok(Some(InstallTargetIdentifier::Formula(Arc::new(formula))));
+        }
+    }
+    if kind_hint != KindHint::Formula {
+        if let Some(cask) = formulary::tap_cask(config, name)? {
+            let cask = build::cask::resolve_for_host(&cask, config);
+            return Ok(Some(InstallTargetIdentifier::Cask(Arc::new(cask))));
+        }
+    }
+    Ok(None)
+}
+
+fn check_write_permissions(config: &Config) -> Result<()> {
+    let paths = [
+        config.cellar_path().to_path_buf(),
+        config.opt_dir(),
+        config.bin_dir(),
+        config.caskroom_dir(),
+        config.cache_dir.clone(),
+    ];
+    let not_writable: Vec<String> = paths
+        .iter()
+        .filter(|p| !is_writable(p))
+        .map(|p| p.display().to_string())
+        .collect();
+    if not_writable.is_empty() {
+        return Ok(());
+    }
+    Err(SpsError::PermissionDenied(format!(
+        "The following paths are not writable: {}. Fix ownership (e.g. `sudo chown -R $(whoami) {}`) or install into a writable prefix with `sps install --prefix <dir>`.",
+        not_writable.join(", "),
+        config.prefix().display()
+    )))
+}
+
+/// Checks writability by creating a probe file in `path`, or in its nearest existing ancestor
+/// if `path` does not exist yet.
+fn is_writable(path: &std::path::Path) -> bool {
+    let Some(existing) = path.ancestors().find(|p| p.exists()) else {
+        return false;
+    };
+    let probe = existing.join(format!(".sps_write_probe_{}", std::process::id()));
+    match fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&probe)
+    {
+        Ok(_) => {
+            let _ = fs::remove_file(&probe);
+            true
+        }
+        Err(e) => {
+            debug!("Write probe failed in {}: {}", existing.display(), e);
+            e.kind() != std::io::ErrorKind::PermissionDenied
+                && e.kind() != std::io::ErrorKind::ReadOnlyFilesystem
+        }
+    }
+}
+
+/// Turns a raw `PermissionDenied` I/O error from a job into `SpsError::PermissionDenied` naming
+/// the install location, so the final summary is actionable.
+fn map_permission_error(err: SpsError, job: &PipelineJob, config: &Config) -> SpsError {
+    match &err {
+        SpsError::Io(io_err) if io_err.kind() == std::io::ErrorKind::PermissionDenied => {
+            let path = match &job.target {
+                InstallTargetIdentifier::Formula(f) => f
+                    .install_prefix(&config.cellar)
+                    .unwrap_or_else(|_| config.formula_cellar_dir(f.name())),
+                InstallTargetIdentifier::Cask(c) => config.cask_dir(&c.token),
+            };
+            SpsError::PermissionDenied(format!("{} ({})", path.display(), io_err))
+        }
+        _ => err,
+    }
+}
+
 /// Downloads the target file (bottle, source, cask archive).
 #[instrument(skip(cfg, cache, client), fields(name=%target_name))]
 async fn download_target_file(
@@ -1146,23 +3025,31 @@ async fn download_target_file(
     match target_type {
         InstallTargetIdentifier::Formula(formula) => {
             if is_source_build {
-                info_line(format!("Downloading source for {}", formula.name));
+                package_line(format!("Downloading source for {}", formula.name));
                 build::formula::source::download_source(formula, cfg).await
             } else {
-                info_line(format!("Downloading bottle {}", formula.name));
-                build::formula::bottle::download_bottle(formula, cfg, client.as_ref()).await
+                package_line(format!("Downloading bottle {}", formula.name));
+                build::formula::bottle::download_bottle(
+                    formula,
+                    cfg,
+                    cache.as_ref(),
+                    client.as_ref(),
+                )
+                .await
             }
         }
         InstallTargetIdentifier::Cask(cask) => {
-            info_line(format!("Downloading cask {}", cask.token));
-            build::cask::download_cask(cask, cache.as_ref()).await
+            package_line(format!("Downloading cask {}", cask.token));
+            build::cask::download_cask(cask, cache.as_ref(), cfg).await
         }
     }
     .map_err(|e| {
         // Wrap errors nicely
         error!("Download failed for {}: {}", target_name, e);
-        // Add more context if it's not already a DownloadError
-        if matches!(e, SpsError::DownloadError(_, _, _)) {
+        // Add more context only to errors without a more specific kind. A checksum mismatch or
+        // bad signature keeps its own, so the run exits with the checksum code rather than the
+        // network one.
+        if e.exit_code() != exit_code::GENERIC {
             e
         } else {
             SpsError::DownloadError(
@@ -1174,9 +3061,653 @@ async fn download_target_file(
     })
 }
 
+/// Re-creates the opt link and prefix symlinks of an installed keg. A keg an earlier run poured
+/// but failed to link is finished instead of poured again: linked, then its post-install step.
+fn relink_keg(formula: &Formula, keg_path: &Path, config: &Config) -> Result<()> {
+    let version = keg_path
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let resumed = keg::is_marked_unlinked(keg_path);
+    if resumed {
+        info_line(format!(
+            "Linking {} {} (poured by an earlier run)",
+            formula.name(),
+            version
+        ));
+    } else {
+        info_line(format!("Relinking {} {}", formula.name(), version));
+    }
+    build::formula::link::link_formula_artifacts(formula, keg_path, config)?;
+    if resumed {
+        build::formula::post_install::run_post_install(formula, keg_path, config)?;
+    }
+    Ok(())
+}
+
 // Simple green INFO logger for install actions (copied from old install.rs)
+/// For each formula the run touches: whether the user asked for it, and which requested targets
+/// pull it in. Cask dependencies are walked too, so a formula a cask needs names that cask.
+fn install_reasons(
+    initial_targets: &[String],
+    requested: bool,
+    graph: Option<&ResolvedGraph>,
+    casks: &HashMap<String, Arc<Cask>>,
+) -> InstallReasons {
+    let is_formula = |name: &str| graph.is_some_and(|g| g.resolution_details.contains_key(name));
+    let children = |name: &str| -> Vec<String> {
+        if let Some(dep) = graph.and_then(|g| g.resolution_details.get(name)) {
+            return dep
+                .formula
+                .dependencies()
+                .iter()
+                .map(|d| d.name.clone())
+                .collect();
+        }
+        casks
+            .get(name)
+            .and_then(|c| c.depends_on.as_ref())
+            .map(|d| d.formula.iter().chain(&d.cask).cloned().collect())
+            .unwrap_or_default()
+    };
+    let mut reasons = InstallReasons::new();
+    for root in initial_targets {
+        if requested && !casks.contains_key(root) {
+            reasons.entry(root.clone()).or_default().on_request = true;
+        }
+        let mut seen: HashSet<String> = HashSet::new();
+        let mut stack = children(root);
+        while let Some(name) = stack.pop() {
+            if name == *root || !seen.insert(name.clone()) {
+                continue;
+            }
+            if is_formula(&name) {
+                reasons
+                    .entry(name.clone())
+                    .or_default()
+                    .dependency_of
+                    .insert(root.clone());
+            }
+            stack.extend(children(&name));
+        }
+    }
+    reasons
+}
+
+/// Runs the post-install/post-upgrade hook of each formula that succeeded, returning the
+/// failures of strict hooks.
+fn run_install_hooks(
+    hook_jobs: &[(String, HookEvent)],
+    succeeded: &[String],
+    config: &Config,
+) -> Vec<(String, SpsError)> {
+    let keg_registry = KegRegistry::new(config.clone());
+    let mut errors = Vec::new();
+    for (name, event) in hook_jobs {
+        if !succeeded.contains(name) {
+            continue;
+        }
+        let keg = match keg_registry.get_installed_keg(name) {
+            Ok(Some(keg)) => keg,
+            Ok(None) => continue,
+            Err(e) => {
+                warn!("Skipping {} hook for {}: {}", event, name, e);
+                continue;
+            }
+        };
+        let version = keg
+            .path
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_else(|| keg.version.to_string());
+        if let Err(e) = hooks::run_hook(*event, name, &version, &keg.path, config) {
+            error!("{} {}", ui::fail_mark(), e);
+            errors.push((name.clone(), e));
+        }
+    }
+    errors
+}
+
+/// Merges each reason into the receipt of the formula's current keg, whether it was poured in
+/// this run or already installed and reused.
+fn record_install_reasons(reasons: &InstallReasons, keg_snapshot: &KegSnapshot) {
+    for (name, reason) in reasons {
+        let Some(keg) = keg_snapshot.current(name) else {
+            continue;
+        };
+        if let Err(e) = reason.record(&keg.path) {
+            debug!("Failed to record install reason for {}: {}", name, e);
+        }
+    }
+}
+
+/// Dependency upgrades the resolver added because a dependent needs a newer keg, as
+/// `(name, "old -> new", reason)`.
+fn forced_upgrades(jobs: &[PipelineJob]) -> Vec<(String, String, String)> {
+    jobs.iter()
+        .filter_map(|job| {
+            let (
+                InstallTargetIdentifier::Formula(formula),
+                PipelineActionType::Upgrade { from_version, .. },
+            ) = (&job.target, &job.action)
+            else {
+                return None;
+            };
+            let node = job
+                .resolved_graph
+                .as_ref()?
+                .resolution_details
+                .get(formula.name())?;
+            if node.status != ResolutionStatus::Outdated {
+                return None;
+            }
+            Some((
+                formula.name().to_string(),
+                format!("{from_version} -> {}", formula.version_str_full()),
+                node.upgrade_reason.clone().unwrap_or_default(),
+            ))
+        })
+        .collect()
+}
+
+fn job_name(job: &PipelineJob) -> &str {
+    match &job.target {
+        InstallTargetIdentifier::Formula(f) => f.name(),
+        InstallTargetIdentifier::Cask(c) => c.token.as_str(),
+    }
+}
+
+fn job_succeeded(result: &PipelineJobResult) -> bool {
+    !matches!(
+        result,
+        PipelineJobResult::InstallErr(..)
+            | PipelineJobResult::UpgradeErr(..)
+            | PipelineJobResult::ReinstallErr(..)
+    )
+}
+
+/// Install order for the plan: a job waits for the jobs of its dependencies that are part of the
+/// same plan. Under `--fail-fast` a failure gives up every target the failed package is part of
+/// (grouped by the caller with [`Scheduler::set_targets`]); otherwise only what depends on the
+/// failed package is given up.
+fn install_scheduler(jobs: &[PipelineJob], fail_fast: bool, strict_deps: bool) -> Scheduler {
+    let policy = if fail_fast {
+        FailurePolicy::StopAll
+    } else {
+        FailurePolicy::SkipBlocked
+    };
+    let mut scheduler = Scheduler::new(Direction::DependenciesFirst, policy);
+    for job in jobs {
+        scheduler.add_node(job_name(job));
+    }
+    // Definitions the resolver didn't load (the requested targets, casks) can name a dependency
+    // by an alias or old name; the edge has to reach the job under its own name.
+    let mut aliases: HashMap<String, &str> = HashMap::new();
+    for job in jobs {
+        if let InstallTargetIdentifier::Formula(formula) = &job.target {
+            for alias in formula.alias_names() {
+                aliases.entry(alias).or_insert(formula.name());
+            }
+        }
+    }
+    let names: HashSet<&str> = jobs.iter().map(job_name).collect();
+    let node = |name: &str| -> String {
+        match aliases.get(name) {
+            Some(canonical) if !names.contains(name) => canonical.to_string(),
+            _ => name.to_string(),
+        }
+    };
+    for job in jobs {
+        match &job.target {
+            InstallTargetIdentifier::Formula(formula) => {
+                for dep in formula.dependencies() {
+                    if strict_deps {
+                        scheduler.add_edge(formula.name(), &node(&dep.name));
+                    } else {
+                        scheduler.add_tagged_edge(formula.name(), &node(&dep.name), dep.tags);
+                    }
+                }
+            }
+            InstallTargetIdentifier::Cask(cask) => {
+                if let Some(deps) = &cask.depends_on {
+                    for dep in deps.formula.iter().chain(&deps.cask) {
+                        scheduler.add_edge(&cask.token, &node(dep));
+                    }
+                }
+            }
+        }
+    }
+    scheduler
+}
+
+/// `--dry-run --graph`: the resolved graph of the plan on stdout, with planning failures in red.
+fn print_plan_graph(
+    jobs: &[PipelineJob],
+    errors: &[(String, SpsError)],
+    targets: &[String],
+    format: GraphFormat,
+    flags: &PipelineFlags,
+) {
+    let mut graph = jobs
+        .iter()
+        .find_map(|job| job.resolved_graph.as_deref())
+        .map(DependencyGraph::from_resolved)
+        .unwrap_or_default();
+    for job in jobs {
+        if let InstallTargetIdentifier::Cask(cask) = &job.target {
+            graph.add_cask(cask);
+        }
+    }
+    for (name, _) in errors {
+        graph.add_failed(name);
+    }
+    if flags.prune_installed {
+        graph.prune_installed(targets);
+    }
+    print!("{}", graph.render(format));
+}
+
+/// `recommended` or `optional`, for a dependency the scheduler let a dependent do without.
+fn soft_tag_label(tags: DependencyTag) -> &'static str {
+    if tags.contains(DependencyTag::RECOMMENDED) {
+        "recommended"
+    } else {
+        "optional"
+    }
+}
+
+/// The optional and recommended dependencies each succeeded package was installed without, with
+/// the error that kept them out.
+fn skipped_dependencies(
+    missing: &MissingSoftDeps,
+    succeeded: &[String],
+    errors: &[(String, SpsError)],
+) -> Vec<(String, SkippedDependency)> {
+    missing
+        .iter()
+        .filter(|(name, _, _)| succeeded.contains(name))
+        .map(|(name, dep, tags)| {
+            let reason = errors.iter().find(|(failed, _)| failed == dep).map_or_else(
+                || "it failed to install".to_string(),
+                |(_, e)| e.to_string(),
+            );
+            (
+                name.clone(),
+                SkippedDependency {
+                    name: dep.clone(),
+                    tag: soft_tag_label(*tags),
+                    reason,
+                },
+            )
+        })
+        .collect()
+}
+
+/// Lists the skipped dependencies in the receipt of each keg installed without them.
+fn record_skipped_dependencies(
+    skipped: &[(String, SkippedDependency)],
+    keg_snapshot: &KegSnapshot,
+) {
+    let mut by_package: BTreeMap<&str, Vec<SkippedDependency>> = BTreeMap::new();
+    for (name, dep) in skipped {
+        by_package.entry(name).or_default().push(dep.clone());
+    }
+    for (name, deps) in by_package {
+        let Some(keg) = keg_snapshot.current(name) else {
+            continue;
+        };
+        if let Err(e) = SkippedDependency::record(&keg.path, &deps) {
+            debug!("Failed to record skipped dependencies for {}: {}", name, e);
+        }
+    }
+}
+
+/// The failure result matching the job's action.
+fn job_failed(
+    name: String,
+    pkg_type: PackageType,
+    action: PipelineActionType,
+    e: SpsError,
+) -> PipelineJobResult {
+    match action {
+        PipelineActionType::Upgrade { from_version, .. } => {
+            PipelineJobResult::UpgradeErr(name, pkg_type, from_version, e)
+        }
+        PipelineActionType::Reinstall { .. } => PipelineJobResult::ReinstallErr(name, pkg_type, e),
+        PipelineActionType::Install => PipelineJobResult::InstallErr(name, pkg_type, e),
+    }
+}
+
+/// Cancels `token` on the first Ctrl-C while the returned guard is alive, i.e. during downloads
+/// and installs. A Ctrl-C after the guard is dropped, or a second one, exits at once with the
+/// interrupted exit code.
+fn cancel_on_ctrl_c(token: &CancellationToken) -> DropGuard {
+    let phase_over = CancellationToken::new();
+    let (token, over) = (token.clone(), phase_over.clone());
+    tokio::spawn(async move {
+        while tokio::signal::ctrl_c().await.is_ok() {
+            if token.is_cancelled() || over.is_cancelled() {
+                std::process::exit(exit_code::INTERRUPTED);
+            }
+            warn!(
+                "Cancelling: finishing the current step of each package (Ctrl-C again to exit now)"
+            );
+            token.cancel();
+        }
+    });
+    phase_over.drop_guard()
+}
+
+/// Lists the incomplete kegs this run found and moved aside before pouring fresh ones.
+fn report_quarantined_kegs() {
+    for path in keg::take_quarantined() {
+        output::println(format!(
+            "{} Moved an incomplete keg aside to {}; inspect it, or remove it with `sps cleanup`",
+            "!".yellow(),
+            path.display()
+        ));
+    }
+}
+
+/// Prints which packages succeeded, a table of failures with their errors, and what was never
+/// attempted, so a partially failed run can be picked up again.
+fn print_summary(
+    planned: &[String],
+    succeeded: &[String],
+    errors: &[(String, SpsError)],
+    skipped_deps: &[(String, SkippedDependency)],
+    targets: &[TargetStatus],
+    fail_fast: bool,
+) {
+    output::println(format!("\n{}", "==> Summary".bold()));
+    if targets.len() > 1 {
+        output::println("  Targets:");
+        for target in targets {
+            let line = match target.state {
+                InstallState::Finished => format!("{} {}", ui::ok_mark(), target.name),
+                InstallState::Failed => format!(
+                    "{} {} (failed: {})",
+                    ui::fail_mark(),
+                    target.name.red(),
+                    target.failed.join(", ")
+                ),
+                _ => format!("- {} (not attempted)", target.name),
+            };
+            output::println(format!("    {line}"));
+        }
+    }
+    if !succeeded.is_empty() {
+        output::println(format!(
+            "  {} {} succeeded: {}",
+            ui::ok_mark(),
+            succeeded.len(),
+            succeeded.join(", ")
+        ));
+    }
+    let width = errors.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+    output::println(format!("  {} {} failed:", ui::fail_mark(), errors.len()));
+    for (name, err) in errors {
+        output::println(format!("    {}  {}", format!("{name:<width$}").red(), err));
+    }
+    for (name, dep) in skipped_deps {
+        output::println(format!(
+            "  {} {} installed without {} dep {}: {}",
+            "!".yellow(),
+            name,
+            dep.tag,
+            dep.name,
+            dep.reason
+        ));
+    }
+    let skipped: Vec<&str> = planned
+        .iter()
+        .filter(|name| {
+            !succeeded.contains(name) && !errors.iter().any(|(failed, _)| failed == *name)
+        })
+        .map(String::as_str)
+        .collect();
+    if !skipped.is_empty() {
+        output::println(format!(
+            "  - {} not attempted{}: {}",
+            skipped.len(),
+            if fail_fast { " (--fail-fast)" } else { "" },
+            skipped.join(", ")
+        ));
+    }
+}
+
+/// The outcome of each target: [`TargetScope::targets`], plus the requested targets that failed
+/// to plan. A target failed when any package of it failed, and finished when all of them
+/// succeeded.
+fn target_statuses(
+    scope: &TargetScope,
+    requested: &[String],
+    succeeded: &[String],
+    errors: &[(String, SpsError)],
+) -> Vec<TargetStatus> {
+    let mut statuses: Vec<TargetStatus> = scope
+        .targets()
+        .into_iter()
+        .map(|target| {
+            let packages: Vec<&str> = scope
+                .targets_of
+                .iter()
+                .filter(|(_, targets)| targets.contains(target))
+                .map(|(name, _)| &**name)
+                .collect();
+            let failed: Vec<String> = packages
+                .iter()
+                .filter(|name| errors.iter().any(|(failed, _)| failed == *name))
+                .map(|name| name.to_string())
+                .collect();
+            let state = if !failed.is_empty() {
+                InstallState::Failed
+            } else if packages
+                .iter()
+                .all(|name| succeeded.iter().any(|s| s == name))
+            {
+                InstallState::Finished
+            } else {
+                InstallState::Skipped
+            };
+            TargetStatus {
+                name: target.to_string(),
+                state,
+                failed,
+            }
+        })
+        .collect();
+    for name in requested {
+        if !scope.targets_of.contains_key(name.as_str())
+            && errors.iter().any(|(failed, _)| failed == name)
+        {
+            statuses.push(TargetStatus {
+                name: name.clone(),
+                state: InstallState::Failed,
+                failed: vec![name.clone()],
+            });
+        }
+    }
+    statuses
+}
+
+/// Refuses a cask install that `conflicts_with` something installed, or that would overwrite
+/// an app sps didn't install (unless `--force`). With `--adopt` such apps are taken over in
+/// place instead; returns `false` when that leaves nothing to install.
+async fn check_preexisting_cask(
+    cask: &Cask,
+    config: &Config,
+    flags: &PipelineFlags,
+) -> Result<bool> {
+    let conflicts = preexisting::installed_conflicts(cask, config).await?;
+    if !conflicts.is_empty() {
+        return Err(SpsError::DependencyError(format!(
+            "{} conflicts with installed {}; uninstall it first",
+            cask.token,
+            conflicts.join(", ")
+        )));
+    }
+    let unmanaged = preexisting::unmanaged_apps(cask, config);
+    if unmanaged.is_empty() || flags.force {
+        return Ok(true);
+    }
+    let listed = unmanaged
+        .iter()
+        .map(|p| p.display().to_string())
+        .collect::<Vec<_>>()
+        .join(", ");
+    if !flags.adopt {
+        return Err(SpsError::InstallError(format!(
+            "{listed} already exists and was not installed by sps; pass --adopt to manage the existing copy or --force to replace it"
+        )));
+    }
+    if unmanaged.len() < preexisting::declared_apps(cask).len() {
+        return Err(SpsError::InstallError(format!(
+            "Cannot adopt {}: only some of its apps are present ({listed}); use --force to reinstall",
+            cask.token
+        )));
+    }
+    if flags.dry_run {
+        info_line(format!("Would adopt {} from {listed}", cask.token.cyan()));
+    } else {
+        preexisting::adopt(cask, &unmanaged, config)?;
+        info_line(format!(
+            "{} Adopted {} from {listed}",
+            ui::ok_mark(),
+            cask.token.cyan()
+        ));
+    }
+    Ok(false)
+}
+
+/// With `bottle_only`, fails unless every formula in `jobs` will be poured from a bottle: those
+/// without a bottle for this host are listed with the tags they have, and those with one but
+/// built from source anyway (`--build-from-source`, `--HEAD`, or a plan that recorded a source
+/// build) are named. Runs before anything is downloaded, so dry runs and emitted plans get the
+/// same answer as an install.
+fn check_bottle_only(jobs: &[PipelineJob], config: &Config) -> Result<()> {
+    if !config.bottle_only {
+        return Ok(());
+    }
+    let source_builds: Vec<&Formula> = jobs
+        .iter()
+        .filter(|job| job.is_source_build)
+        .filter_map(|job| match &job.target {
+            InstallTargetIdentifier::Formula(f) => Some(f.as_ref()),
+            InstallTargetIdentifier::Cask(_) => None,
+        })
+        .collect();
+    build::formula::bottle::require_bottles(source_builds.iter().copied())?;
+    if source_builds.is_empty() {
+        return Ok(());
+    }
+    Err(SpsError::ValidationError(format!(
+        "bottle_only: {} would be built from source (--build-from-source, --HEAD or a plan \
+         recording a source build) although a bottle exists",
+        source_builds
+            .iter()
+            .map(|f| f.name())
+            .collect::<Vec<_>>()
+            .join(", ")
+    )))
+}
+
+/// With `advisories` on, prints the advisories of the cached feed that affect a planned package
+/// version, with their CVE IDs and severity. Under `block`, HIGH and CRITICAL ones fail the
+/// plan before anything is downloaded. Without a cached feed this only warns.
+fn check_advisories(jobs: &[PipelineJob], config: &Config) -> Result<()> {
+    if config.advisories == AdvisoryMode::Off {
+        return Ok(());
+    }
+    let index = match advisory::load_index(config) {
+        Ok(Some(index)) => index,
+        Ok(None) => {
+            warn!("No advisory feed cached yet (run `sps update`); skipping the advisory check");
+            return Ok(());
+        }
+        Err(e) => {
+            warn!(
+                "Could not read the cached advisory feed: {}; skipping the advisory check",
+                e
+            );
+            return Ok(());
+        }
+    };
+    let mut blocking: Vec<String> = Vec::new();
+    for job in jobs {
+        let (name, version) = match &job.target {
+            InstallTargetIdentifier::Formula(f) => (f.name().to_string(), f.version_str_full()),
+            InstallTargetIdentifier::Cask(c) => match &c.version {
+                Some(version) => (c.token.clone(), version.clone()),
+                None => continue,
+            },
+        };
+        let matches = index.matches(&name, &version);
+        if matches.is_empty() {
+            continue;
+        }
+        info_line(format!(
+            "  {} {} {} has known advisories:",
+            "!".red().bold(),
+            name.cyan(),
+            version
+        ));
+        for found in &matches {
+            let severity = found.severity.to_string();
+            let severity = if found.severity.blocks() {
+                severity.red().bold()
+            } else {
+                severity.yellow()
+            };
+            info_line(format!(
+                "      {} {} {}",
+                severity,
+                found.advisory.cve_ids().join(", "),
+                found.advisory.summary.as_deref().unwrap_or_default()
+            ));
+        }
+        if matches.iter().any(|found| found.severity.blocks()) {
+            blocking.push(name);
+        }
+    }
+    if config.advisories != AdvisoryMode::Block || blocking.is_empty() {
+        return Ok(());
+    }
+    Err(SpsError::ValidationError(format!(
+        "advisories = block: {} {} HIGH or CRITICAL advisories (pass --allow-vulnerable to \
+         install anyway)",
+        blocking.join(", "),
+        if blocking.len() == 1 { "has" } else { "have" }
+    )))
+}
+
+/// Marks this run as live so that the reaper of a concurrent sps leaves its downloads alone.
+/// Without it the run still goes ahead, its downloads then only protected for
+/// [`STALE_AFTER`](sps_core::build::reaper::STALE_AFTER).
+fn hold_run_lock(config: &Config) -> Option<RunLock> {
+    match RunLock::acquire(config) {
+        Ok(lock) => Some(lock),
+        Err(e) => {
+            warn!("Could not take the run lock: {}", e);
+            None
+        }
+    }
+}
+
 fn info_line(message: impl AsRef<str>) {
-    println!("{} sps::pipeline: {}", "INFO".green(), message.as_ref()); // Indicate pipeline source
+    output::println(format!(
+        "{} sps::pipeline: {}",
+        "INFO".green(),
+        message.as_ref()
+    )); // Indicate pipeline source
+}
+
+/// A line about one package's progress, which the terminal display shows in condensed form
+/// unless `-v` was given.
+fn package_line(message: impl AsRef<str>) {
+    if output::package_lines_enabled() {
+        info_line(message);
+    }
 }
 
 // Helper to get string representation of PackageType
@@ -1218,11 +3749,11 @@ async fn load_or_fetch_json(
 
 // Add helper function for sorting jobs by dependency order
 fn sort_jobs_by_dependency_order(jobs: &mut [PipelineJob], graph: &ResolvedGraph) {
-    let formula_order: HashMap<String, usize> = graph
+    let formula_order: HashMap<&str, usize> = graph
         .install_plan
         .iter()
         .enumerate()
-        .map(|(idx, dep)| (dep.formula.name().to_string(), idx))
+        .map(|(idx, dep)| (dep.formula.name(), idx))
         .collect();
 
     jobs.sort_by_key(|job| match &job.target {
@@ -1232,3 +3763,204 @@ fn sort_jobs_by_dependency_order(jobs: &mut [PipelineJob], graph: &ResolvedGraph
         InstallTargetIdentifier::Cask(_) => usize::MAX, // Install casks after formulae
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::cell::Cell;
+
+    use super::*;
+
+    /// Tracks the bytes live on the current thread, and their peak. The runs below use a
+    /// current-thread runtime, so every task allocates and frees on the test's own thread.
+    struct Tracking;
+
+    thread_local! {
+        static LIVE: Cell<isize> = const { Cell::new(0) };
+        static PEAK: Cell<isize> = const { Cell::new(0) };
+    }
+
+    fn account(delta: isize) {
+        let _ = LIVE.try_with(|live| {
+            live.set(live.get() + delta);
+            let _ = PEAK.try_with(|peak| peak.set(peak.get().max(live.get())));
+        });
+    }
+
+    unsafe impl GlobalAlloc for Tracking {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            account(layout.size() as isize);
+            System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            account(-(layout.size() as isize));
+            System.dealloc(ptr, layout)
+        }
+
+        unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+            account(new_size as isize - layout.size() as isize);
+            System.realloc(ptr, layout, new_size)
+        }
+    }
+
+    #[global_allocator]
+    static GLOBAL: Tracking = Tracking;
+
+    const SLOTS: usize = 8;
+    /// What each no-op task returns, standing in for a job and its error strings.
+    const RESULT_BYTES: usize = 4096;
+
+    struct Run {
+        finished: Vec<usize>,
+        max_in_flight: usize,
+        /// Peak bytes live during the run, above what was live when it started.
+        peak: isize,
+    }
+
+    /// Runs `jobs` no-op tasks through [`run_windowed`] on a current-thread runtime.
+    fn run(jobs: usize, abort_after: Option<usize>) -> Run {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let mut finished = Vec::with_capacity(jobs);
+        let mut max_in_flight = 0;
+        // Set on abort, as the pipeline's own abort flag is.
+        let stopped = Cell::new(false);
+        let base = LIVE.with(Cell::get);
+        PEAK.with(|peak| peak.set(base));
+        runtime.block_on(run_windowed(
+            0..jobs,
+            SLOTS,
+            &mut finished,
+            || stopped.get(),
+            |_, join_set, job| {
+                join_set.spawn(async move {
+                    tokio::task::yield_now().await;
+                    (job, vec![0u8; RESULT_BYTES])
+                });
+                max_in_flight = max_in_flight.max(join_set.len());
+            },
+            |finished, result| {
+                if let Ok((_, (job, _))) = result {
+                    finished.push(job);
+                }
+                let abort = abort_after.is_some_and(|n| finished.len() >= n);
+                stopped.set(stopped.get() || abort);
+                abort
+            },
+        ));
+        Run {
+            peak: PEAK.with(Cell::get) - base,
+            finished,
+            max_in_flight,
+        }
+    }
+
+    #[test]
+    fn a_thousand_jobs_all_finish_with_a_bounded_window() {
+        let mut run = run(1000, None);
+
+        assert!(run.max_in_flight <= SLOTS, "{}", run.max_in_flight);
+        run.finished.sort_unstable();
+        assert_eq!(run.finished, (0..1000).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn peak_memory_follows_the_window_not_the_plan() {
+        let small = run(100, None);
+        let large = run(1000, None);
+
+        // Holding every result until the end would take 1000 * 4 KiB; the window keeps about
+        // SLOTS of them alive.
+        assert!(
+            large.peak < (100 * RESULT_BYTES) as isize,
+            "peak {} bytes for 1000 jobs",
+            large.peak
+        );
+        assert!(
+            large.peak < small.peak * 2,
+            "peak grew from {} to {} bytes",
+            small.peak,
+            large.peak
+        );
+    }
+
+    #[test]
+    fn an_abort_cancels_the_tasks_in_flight_and_spawns_no_more() {
+        let run = run(1000, Some(10));
+
+        assert!(run.finished.len() < 10 + SLOTS, "{}", run.finished.len());
+    }
+
+    #[test]
+    fn watermarks_log_once_per_doubling() {
+        let mut mark = Watermark::new("queue");
+
+        mark.observe(10);
+        assert_eq!(mark.next, WATERMARK_START);
+        mark.observe(WATERMARK_START);
+        assert_eq!(mark.next, 2 * WATERMARK_START);
+        mark.observe(1000);
+        assert_eq!(mark.next, 1024);
+    }
+
+    fn job(target: InstallTargetIdentifier) -> PipelineJob {
+        PipelineJob {
+            target,
+            download_path: PathBuf::new(),
+            action: PipelineActionType::Install,
+            resolved_graph: None,
+            is_source_build: false,
+            head: false,
+        }
+    }
+
+    fn formula_job(name: &str, deps: &[&str], aliases: &[&str]) -> PipelineJob {
+        let formula: Formula = serde_json::from_value(serde_json::json!({
+            "name": name,
+            "versions": { "stable": "1.0" },
+            "dependencies": deps,
+            "aliases": aliases,
+        }))
+        .unwrap();
+        job(InstallTargetIdentifier::Formula(Arc::new(formula)))
+    }
+
+    #[test]
+    fn dependencies_declared_by_alias_wait_for_the_formula_they_name() {
+        let cask: Cask = serde_json::from_value(serde_json::json!({
+            "token": "viewer",
+            "depends_on": { "formula": ["openssl"] },
+        }))
+        .unwrap();
+        let jobs = [
+            formula_job("app", &["openssl"], &[]),
+            formula_job("openssl@3", &[], &["openssl"]),
+            job(InstallTargetIdentifier::Cask(Arc::new(cask))),
+        ];
+
+        let mut scheduler = install_scheduler(&jobs, false, false);
+
+        assert_eq!(scheduler.ready(), [Arc::from("openssl@3")]);
+        scheduler.start("openssl@3");
+        scheduler.complete("openssl@3", true);
+        assert_eq!(scheduler.ready(), [Arc::from("app"), Arc::from("viewer")]);
+    }
+
+    #[test]
+    fn a_job_of_the_exact_name_wins_over_an_alias() {
+        let jobs = [
+            formula_job("app", &["openssl"], &[]),
+            formula_job("openssl", &[], &[]),
+            formula_job("openssl@3", &[], &["openssl"]),
+        ];
+
+        let scheduler = install_scheduler(&jobs, false, false);
+
+        assert_eq!(
+            scheduler.ready(),
+            [Arc::from("openssl"), Arc::from("openssl@3")]
+        );
+    }
+}
@@ -0,0 +1,343 @@
+//! Resolved install plans, written by `install --dry-run --emit-plan` and executed by
+//! `install --from-plan`.
+//!
+//! A plan pins every package to the version, artifact URL and SHA256 chosen at resolution time,
+//! in install order. Executing it fetches current metadata only to check that these still match;
+//! nothing is re-resolved.
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sps_common::dependency::DependencyTag;
+use sps_common::error::{Result, SpsError};
+use sps_common::keg::InstallReason;
+use sps_common::model::cask::{Sha256Field, UrlField};
+use sps_common::model::{Cask, InstallTargetIdentifier};
+use sps_core::build::formula::bottle::get_bottle_for_platform;
+
+use crate::cli::pipeline::PipelineJob;
+
+/// Bumped whenever a field changes meaning; older or newer plans are refused.
+pub const PLAN_SCHEMA_VERSION: u64 = 1;
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PlanFile {
+    pub schema_version: u64,
+    /// Seconds since the Unix epoch when the plan was resolved.
+    pub created_at: u64,
+    /// Packages in install order: dependencies before their dependents.
+    pub packages: Vec<PlanEntry>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PlanEntry {
+    pub kind: PlanKind,
+    pub name: String,
+    pub version: String,
+    /// Bottle chosen for the resolving host; absent for source builds and casks.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bottle_tag: Option<String>,
+    pub url: String,
+    /// Empty for casks that opt out of checksum verification.
+    pub sha256: String,
+    #[serde(default)]
+    pub requested: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dependency_of: Vec<String>,
+    /// Formula dependencies, whose opt paths source builds are given.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dependencies: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PlanKind {
+    Formula,
+    Cask,
+}
+
+impl PlanEntry {
+    pub fn install_reason(&self) -> InstallReason {
+        InstallReason {
+            on_request: self.requested,
+            dependency_of: self.dependency_of.iter().cloned().collect(),
+        }
+    }
+}
+
+/// Writes the planned jobs, in order, to `path`.
+pub fn write(
+    path: &Path,
+    jobs: &[PipelineJob],
+    reasons: &HashMap<String, InstallReason>,
+) -> Result<()> {
+    let packages = jobs
+        .iter()
+        .map(|job| entry_for_job(job, reasons))
+        .collect::<Result<Vec<_>>>()?;
+    let plan = PlanFile {
+        schema_version: PLAN_SCHEMA_VERSION,
+        created_at: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default(),
+        packages,
+    };
+    fs::write(path, serde_json::to_string_pretty(&plan)?)?;
+    Ok(())
+}
+
+fn entry_for_job(job: &PipelineJob, reasons: &HashMap<String, InstallReason>) -> Result<PlanEntry> {
+    match &job.target {
+        InstallTargetIdentifier::Formula(formula) => {
+            let (bottle_tag, url, sha256) = if job.is_source_build {
+                (None, formula.url.clone(), formula.sha256.clone())
+            } else {
+                let (tag, spec) = get_bottle_for_platform(formula)?;
+                (Some(tag), spec.url.clone(), spec.sha256.clone())
+            };
+            let reason = reasons.get(formula.name()).cloned().unwrap_or_default();
+            Ok(PlanEntry {
+                kind: PlanKind::Formula,
+                name: formula.name().to_string(),
+                version: formula.version_str_full(),
+                bottle_tag,
+                url,
+                sha256,
+                requested: reason.on_request,
+                dependency_of: reason.dependency_of.into_iter().collect(),
+                dependencies: formula
+                    .dependencies
+                    .iter()
+                    .filter(|d| {
+                        !d.tags
+                            .intersects(DependencyTag::OPTIONAL | DependencyTag::TEST)
+                    })
+                    .map(|d| d.name.clone())
+                    .collect(),
+            })
+        }
+        InstallTargetIdentifier::Cask(cask) => Ok(PlanEntry {
+            kind: PlanKind::Cask,
+            name: cask.token.clone(),
+            version: cask_version(cask),
+            bottle_tag: None,
+            url: cask_url(cask),
+            sha256: cask_sha256(cask),
+            requested: false,
+            dependency_of: Vec::new(),
+            dependencies: Vec::new(),
+        }),
+    }
+}
+
+/// Reads and validates a plan file.
+pub fn read(path: &Path) -> Result<PlanFile> {
+    let invalid = |msg: String| SpsError::ValidationError(format!("{}: {msg}", path.display()));
+    let text =
+        fs::read_to_string(path).map_err(|e| invalid(format!("cannot read plan file: {e}")))?;
+    // Check the version first so a plan from another sps release gets a clear message rather
+    // than a complaint about some field.
+    let raw: Value =
+        serde_json::from_str(&text).map_err(|e| invalid(format!("not valid JSON: {e}")))?;
+    match raw.get("schema_version").and_then(Value::as_u64) {
+        Some(PLAN_SCHEMA_VERSION) => {}
+        Some(other) => {
+            return Err(invalid(format!(
+                "plan schema version {other} is not supported (expected {PLAN_SCHEMA_VERSION}); re-create it with `sps install --dry-run --emit-plan`"
+            )))
+        }
+        None => return Err(invalid("missing `schema_version`".to_string())),
+    }
+    let plan: PlanFile =
+        serde_json::from_value(raw).map_err(|e| invalid(format!("malformed plan: {e}")))?;
+
+    if plan.packages.is_empty() {
+        return Err(invalid("plan lists no packages".to_string()));
+    }
+    let mut seen = HashSet::new();
+    for entry in &plan.packages {
+        if entry.name.is_empty() || entry.name.contains('/') || entry.name.contains("..") {
+            return Err(invalid(format!("invalid package name '{}'", entry.name)));
+        }
+        if !seen.insert((entry.kind, entry.name.as_str())) {
+            return Err(invalid(format!("'{}' is listed twice", entry.name)));
+        }
+        if entry.url.is_empty() {
+            return Err(invalid(format!("'{}' has no URL", entry.name)));
+        }
+        let sha_ok =
+            entry.sha256.len() == 64 && entry.sha256.chars().all(|c| c.is_ascii_hexdigit());
+        let unverified_cask = entry.kind == PlanKind::Cask && entry.sha256.is_empty();
+        if !(sha_ok || unverified_cask) {
+            return Err(invalid(format!(
+                "'{}' has an invalid sha256 '{}'",
+                entry.name, entry.sha256
+            )));
+        }
+        if entry.kind == PlanKind::Cask && entry.bottle_tag.is_some() {
+            return Err(invalid(format!(
+                "cask '{}' cannot have a bottle_tag",
+                entry.name
+            )));
+        }
+    }
+    Ok(plan)
+}
+
+/// Checks that current metadata for `target` still matches the plan entry. Returns whether the
+/// entry is a source build.
+pub fn check_entry(entry: &PlanEntry, target: &InstallTargetIdentifier) -> Result<bool> {
+    let stale = |what: String| SpsError::ValidationError(format!("{}: {what}", entry.name));
+    match (entry.kind, target) {
+        (PlanKind::Formula, InstallTargetIdentifier::Formula(formula)) => {
+            let current = formula.version_str_full();
+            if current != entry.version {
+                return Err(stale(format!(
+                    "plan pins version {}, but the current version is {current}",
+                    entry.version
+                )));
+            }
+            let Some(tag) = &entry.bottle_tag else {
+                if !formula.sha256.eq_ignore_ascii_case(&entry.sha256) {
+                    return Err(SpsError::ChecksumError(format!(
+                        "{}: plan records source sha256 {}, the server now provides {}",
+                        entry.name, entry.sha256, formula.sha256
+                    )));
+                }
+                return Ok(true);
+            };
+            let (current_tag, spec) = get_bottle_for_platform(formula)?;
+            if current_tag != *tag {
+                return Err(stale(format!(
+                    "plan selected the {tag} bottle, but this host uses {current_tag}"
+                )));
+            }
+            if !spec.sha256.eq_ignore_ascii_case(&entry.sha256) {
+                return Err(SpsError::ChecksumError(format!(
+                    "{}: plan records bottle sha256 {}, the server now provides {}",
+                    entry.name, entry.sha256, spec.sha256
+                )));
+            }
+            Ok(false)
+        }
+        (PlanKind::Cask, InstallTargetIdentifier::Cask(cask)) => {
+            let current = cask_version(cask);
+            if current != entry.version {
+                return Err(stale(format!(
+                    "plan pins version {}, but the current version is {current}",
+                    entry.version
+                )));
+            }
+            let sha256 = cask_sha256(cask);
+            if !sha256.eq_ignore_ascii_case(&entry.sha256) {
+                return Err(SpsError::ChecksumError(format!(
+                    "{}: plan records sha256 {}, the server now provides {}",
+                    entry.name, entry.sha256, sha256
+                )));
+            }
+            if sha256.is_empty() && cask_url(cask) != entry.url {
+                return Err(stale(format!(
+                    "unverified download URL changed from {} to {}",
+                    entry.url,
+                    cask_url(cask)
+                )));
+            }
+            Ok(false)
+        }
+        (PlanKind::Formula, InstallTargetIdentifier::Cask(_)) => Err(stale(
+            "plan lists a formula, but only a cask of that name exists".to_string(),
+        )),
+        (PlanKind::Cask, InstallTargetIdentifier::Formula(_)) => Err(stale(
+            "plan lists a cask, but only a formula of that name exists".to_string(),
+        )),
+    }
+}
+
+fn cask_version(cask: &Cask) -> String {
+    cask.version.clone().unwrap_or_else(|| "latest".to_string())
+}
+
+fn cask_url(cask: &Cask) -> String {
+    match &cask.url {
+        Some(UrlField::Simple(url)) | Some(UrlField::WithSpec { url, .. }) => url.clone(),
+        None => String::new(),
+    }
+}
+
+fn cask_sha256(cask: &Cask) -> String {
+    match &cask.sha256 {
+        Some(Sha256Field::Hex(sha)) => sha.clone(),
+        _ => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn read_value(plan: Value) -> Result<PlanFile> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("plan.json");
+        fs::write(&path, plan.to_string()).unwrap();
+        read(&path)
+    }
+
+    fn formula(name: &str, sha256: &str) -> Value {
+        json!({
+            "kind": "formula",
+            "name": name,
+            "version": "1.0",
+            "bottle_tag": "all",
+            "url": format!("https://example.com/{name}.tar.gz"),
+            "sha256": sha256,
+        })
+    }
+
+    fn plan(packages: Vec<Value>) -> Value {
+        json!({ "schema_version": PLAN_SCHEMA_VERSION, "created_at": 0, "packages": packages })
+    }
+
+    fn error_of(plan: Value) -> String {
+        read_value(plan).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn reads_a_valid_plan() {
+        let plan = read_value(plan(vec![formula("jq", &"a".repeat(64))])).unwrap();
+
+        assert_eq!(plan.packages.len(), 1);
+        assert_eq!(plan.packages[0].bottle_tag.as_deref(), Some("all"));
+    }
+
+    #[test]
+    fn refuses_entries_that_could_not_have_been_resolved() {
+        let sha = "a".repeat(64);
+        assert!(error_of(plan(vec![])).contains("lists no packages"));
+        assert!(
+            error_of(plan(vec![formula("jq", &sha), formula("jq", &sha)]))
+                .contains("'jq' is listed twice")
+        );
+        assert!(error_of(plan(vec![formula("../jq", &sha)])).contains("invalid package name"));
+        assert!(error_of(plan(vec![formula("jq", "abc")])).contains("invalid sha256"));
+        let mut cask = formula("firefox", &sha);
+        cask["kind"] = json!("cask");
+        assert!(error_of(plan(vec![cask])).contains("cannot have a bottle_tag"));
+    }
+
+    #[test]
+    fn refuses_unknown_fields() {
+        let mut entry = formula("jq", &"a".repeat(64));
+        entry["mirror"] = json!("https://elsewhere.example");
+
+        assert!(error_of(plan(vec![entry])).contains("malformed plan"));
+    }
+}
@@ -0,0 +1,99 @@
+//! Contains the logic for the `prefix`, `cellar` and `caskroom` path queries.
+//!
+//! These are called from build scripts in tight loops, so they only consult `Config` and the
+//! one formula or cask directory in question: no API cache, no name index, no network.
+
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use clap::Args;
+use sps_common::cache::Cache;
+use sps_common::config::Config;
+use sps_common::error::{exit_code, Result, SpsError};
+use sps_common::keg::KegRegistry;
+
+#[derive(Args, Debug)]
+pub struct Prefix {
+    /// Print the opt path of this installed formula instead of the prefix
+    pub formula: Option<String>,
+}
+
+impl Prefix {
+    pub async fn run(&self, config: &Config, _cache: Arc<Cache>) -> Result<()> {
+        match &self.formula {
+            None => println!("{}", config.prefix().display()),
+            Some(name) => {
+                installed_keg_path(config, name)?;
+                println!("{}", config.formula_opt_link_path(name).display());
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Args, Debug)]
+pub struct CellarPath {
+    /// Print the installed keg directory of this formula instead of the Cellar
+    pub formula: Option<String>,
+}
+
+impl CellarPath {
+    pub async fn run(&self, config: &Config, _cache: Arc<Cache>) -> Result<()> {
+        match &self.formula {
+            None => println!("{}", config.cellar_path().display()),
+            Some(name) => println!("{}", installed_keg_path(config, name)?.display()),
+        }
+        Ok(())
+    }
+}
+
+#[derive(Args, Debug)]
+pub struct CaskroomPath {
+    /// Print the installed version directory of this cask instead of the Caskroom
+    pub cask: Option<String>,
+}
+
+impl CaskroomPath {
+    pub async fn run(&self, config: &Config, _cache: Arc<Cache>) -> Result<()> {
+        let Some(token) = &self.cask else {
+            println!("{}", config.caskroom_dir().display());
+            return Ok(());
+        };
+        validate_name(token)?;
+        let version_dir = fs::read_dir(config.cask_dir(token))
+            .into_iter()
+            .flatten()
+            .flatten()
+            .map(|entry| entry.path())
+            .find(|path| path.join("CASK_INSTALL_MANIFEST.json").is_file())
+            .ok_or_else(|| not_installed("Cask", token))?;
+        println!("{}", version_dir.display());
+        Ok(())
+    }
+}
+
+/// Latest keg of `name`, read from that formula's Cellar directory only.
+fn installed_keg_path(config: &Config, name: &str) -> Result<PathBuf> {
+    validate_name(name)?;
+    KegRegistry::new(config.clone())
+        .get_installed_keg(name)?
+        .map(|keg| keg.path)
+        .ok_or_else(|| not_installed("Formula", name))
+}
+
+fn validate_name(name: &str) -> Result<()> {
+    if name.is_empty() || name.contains('/') || name.contains("..") {
+        return Err(SpsError::Generic(format!("Invalid name '{name}'")));
+    }
+    Ok(())
+}
+
+/// Scripts test these commands with `if sps prefix foo; then`, so "not installed" is a plain
+/// exit status 1 like Homebrew's rather than the not-found code.
+fn not_installed(kind: &str, name: &str) -> SpsError {
+    SpsError::OperationFailed(
+        exit_code::GENERIC,
+        format!("{kind} '{name}' is not installed."),
+    )
+}
@@ -1,11 +1,15 @@
 // sps-cli/src/cli/reinstall.rs
+use std::collections::HashMap;
 use std::sync::Arc;
 
 use clap::Args;
 use sps_common::cache::Cache;
+use sps_common::cancel::CancellationToken;
 use sps_common::config::Config;
 use sps_common::error::Result;
+use sps_core::KindHint;
 
+use crate::cli::output;
 use crate::cli::pipeline::{CommandType, PipelineExecutor, PipelineFlags};
 
 #[derive(Args, Debug)]
@@ -22,13 +26,32 @@ pub struct ReinstallArgs {
 
 impl ReinstallArgs {
     pub async fn run(&self, config: &Config, cache: Arc<Cache>) -> Result<()> {
-        println!("Reinstalling: {:?}", self.names); // User feedback
+        output::println(format!("Reinstalling: {:?}", self.names)); // User feedback
         let flags = PipelineFlags {
             // Populate flags from args
             build_from_source: self.build_from_source,
             include_optional: false, // Reinstall usually doesn't change optional deps
             skip_recommended: true,  /* Reinstall usually doesn't change recommended deps
                                       * ... add other common flags if needed ... */
+            only_dependencies: false,
+            kind_hint: KindHint::Any,
+            no_wait: false,
+            ignore_installed: false,
+            only_missing: false,
+            ignore_requirements: false,
+            fail_fast: false,
+            strict_deps: false,
+            dry_run: false,
+            emit_plan: None,
+            graph: None,
+            prune_installed: false,
+            status_socket: None,
+            adopt: false,
+            force: false,
+            cask_definitions: HashMap::new(),
+            head: false,
+            fetch_head: false,
+            cancel: CancellationToken::new(),
         };
         PipelineExecutor::execute_pipeline(
             &self.names,
@@ -0,0 +1,255 @@
+//! Contains the logic for the `resolve` command: the resolver's view of an install, node by node,
+//! for diagnosing plans. Takes the install flags that change resolution and never downloads.
+
+use std::collections::BTreeSet;
+use std::sync::Arc;
+
+use clap::Args;
+use colored::Colorize;
+use serde::Serialize;
+use sps_common::cache::Cache;
+use sps_common::config::Config;
+use sps_common::dependency::{ResolutionStatus, ResolvedDependency, ResolvedGraph};
+use sps_common::error::{Result, SpsError};
+use sps_core::build::formula::bottle::get_bottle_for_platform;
+
+use crate::cli::pipeline::{PipelineExecutor, ResolveOptions};
+
+#[derive(Args, Debug)]
+pub struct Resolve {
+    /// Formulae to resolve, as they would be given to `install`
+    #[arg(required = true)]
+    pub names: Vec<String>,
+
+    /// Include optional dependencies
+    #[arg(long)]
+    pub include_optional: bool,
+
+    /// Leave out recommended dependencies
+    #[arg(long)]
+    pub skip_recommended: bool,
+
+    /// Resolve as `install --build-from-source` would
+    #[arg(long, alias = "force-build")]
+    pub build_from_source: bool,
+
+    /// Resolve as if nothing were installed
+    #[arg(long, conflicts_with = "only_missing")]
+    pub ignore_installed: bool,
+
+    /// Resolve as `install --only-missing` would
+    #[arg(long)]
+    pub only_missing: bool,
+
+    /// Print the nodes as JSON
+    #[arg(long)]
+    pub json: bool,
+}
+
+/// One node of the resolved graph.
+#[derive(Debug, Serialize)]
+struct Node {
+    name: String,
+    status: &'static str,
+    /// The formulary's version.
+    version: String,
+    /// The keg the resolver found, when its version differs.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    installed_version: Option<String>,
+    /// Position in the install plan, 1-based; `None` for nodes with nothing to do.
+    position: Option<usize>,
+    /// The host's bottle tag, `None` when there is no bottle for it.
+    bottle: Option<String>,
+    source_build: bool,
+    target: bool,
+    /// The resolved formulae that depend on this one directly.
+    required_by: Vec<String>,
+    tags: Vec<&'static str>,
+    /// Why the node failed or needs an upgrade.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct NodeError {
+    name: String,
+    error: String,
+}
+
+#[derive(Debug, Serialize)]
+struct Report {
+    nodes: Vec<Node>,
+    errors: Vec<NodeError>,
+}
+
+impl Resolve {
+    /// Resolves the targets as `install` would with the same flags and prints every node, those
+    /// in the install plan first in plan order, then the rest by name.
+    pub async fn run(&self, config: &Config, cache: Arc<Cache>) -> Result<()> {
+        let options = ResolveOptions {
+            include_optional: self.include_optional,
+            skip_recommended: self.skip_recommended,
+            build_from_source: self.build_from_source,
+            ignore_installed: self.ignore_installed,
+            only_missing: self.only_missing,
+        };
+        let (graph, fetch_errors) =
+            PipelineExecutor::resolve_dependency_graph(&self.names, config, cache, options).await?;
+        let report = self.report(&graph, fetch_errors);
+
+        if self.json {
+            println!("{}", serde_json::to_string_pretty(&report)?);
+        } else {
+            print_report(&report);
+        }
+        match report.errors.len() {
+            0 => Ok(()),
+            n => Err(SpsError::DependencyError(format!(
+                "{n} package(s) could not be resolved"
+            ))),
+        }
+    }
+
+    fn report(&self, graph: &ResolvedGraph, fetch_errors: Vec<(String, SpsError)>) -> Report {
+        let mut order: Vec<&str> = graph
+            .install_plan
+            .iter()
+            .map(|dep| dep.formula.name())
+            .collect();
+        let mut rest: Vec<&str> = graph
+            .resolution_details
+            .keys()
+            .map(|name| &**name)
+            .filter(|name| !order.contains(name))
+            .collect();
+        rest.sort_unstable();
+        let planned = order.len();
+        order.extend(rest);
+
+        let nodes = order
+            .iter()
+            .enumerate()
+            .filter_map(|(index, name)| {
+                let dep = graph.resolution_details.get(*name)?;
+                Some(self.node(dep, (index < planned).then_some(index + 1), graph))
+            })
+            .collect();
+        let mut errors: Vec<NodeError> = fetch_errors
+            .into_iter()
+            .map(|(name, e)| NodeError {
+                name,
+                error: e.to_string(),
+            })
+            .chain(graph.errors.iter().map(|(name, e)| NodeError {
+                name: name.to_string(),
+                error: e.to_string(),
+            }))
+            .collect();
+        errors.sort_by(|a, b| a.name.cmp(&b.name));
+        Report { nodes, errors }
+    }
+
+    fn node(
+        &self,
+        dep: &ResolvedDependency,
+        position: Option<usize>,
+        graph: &ResolvedGraph,
+    ) -> Node {
+        let formula = &dep.formula;
+        let name = formula.name().to_string();
+        let version = formula.version_str_full();
+        let installed_version = dep
+            .keg_path
+            .as_ref()
+            .and_then(|path| path.file_name())
+            .map(|version| version.to_string_lossy().into_owned())
+            .filter(|installed| *installed != version);
+        let bottle = get_bottle_for_platform(formula).ok().map(|(tag, _)| tag);
+        let required_by: BTreeSet<String> = graph
+            .resolution_details
+            .values()
+            .filter(|other| other.formula.dependencies.iter().any(|d| d.name == name))
+            .map(|other| other.formula.name().to_string())
+            .collect();
+        Node {
+            status: status_name(dep.status),
+            installed_version,
+            position,
+            source_build: self.build_from_source || bottle.is_none(),
+            bottle,
+            target: self.names.contains(&name),
+            required_by: required_by.into_iter().collect(),
+            tags: dep.tags.api_names(),
+            reason: dep
+                .failure_reason
+                .clone()
+                .or_else(|| dep.upgrade_reason.clone()),
+            name,
+            version,
+        }
+    }
+}
+
+fn status_name(status: ResolutionStatus) -> &'static str {
+    match status {
+        ResolutionStatus::Installed => "installed",
+        ResolutionStatus::InstalledUnlinked => "installed_unlinked",
+        ResolutionStatus::Outdated => "outdated",
+        ResolutionStatus::Missing => "missing",
+        ResolutionStatus::Requested => "requested",
+        ResolutionStatus::SkippedOptional => "skipped_optional",
+        ResolutionStatus::NotFound => "not_found",
+        ResolutionStatus::Failed => "failed",
+    }
+}
+
+fn print_report(report: &Report) {
+    let name_width = report.nodes.iter().map(|n| n.name.len()).max().unwrap_or(0);
+    let version_width = report
+        .nodes
+        .iter()
+        .map(|n| n.version.len())
+        .max()
+        .unwrap_or(0);
+    for node in &report.nodes {
+        let position = node
+            .position
+            .map(|p| format!("{p:>3}."))
+            .unwrap_or_else(|| "    ".to_string());
+        let status = format!("{:<18}", node.status);
+        let status = match node.status {
+            "installed" | "installed_unlinked" => status.green(),
+            "missing" | "requested" | "outdated" => status.blue(),
+            "skipped_optional" => status.dimmed(),
+            _ => status.red(),
+        };
+        let artifact = match (&node.bottle, node.source_build) {
+            (Some(tag), false) => format!("bottle {tag}"),
+            (Some(_), true) => "source (forced)".to_string(),
+            (None, _) => "source (no bottle)".to_string(),
+        };
+        let mut why = Vec::new();
+        if node.target {
+            why.push("target".to_string());
+        }
+        if !node.required_by.is_empty() {
+            why.push(format!("required by {}", node.required_by.join(", ")));
+        }
+        if let Some(installed) = &node.installed_version {
+            why.push(format!("{installed} installed"));
+        }
+        if let Some(reason) = &node.reason {
+            why.push(reason.clone());
+        }
+        println!(
+            "{position} {:<name_width$} {:<version_width$} {status} {:<20} {}",
+            node.name,
+            node.version,
+            artifact,
+            why.join("; ")
+        );
+    }
+    for error in &report.errors {
+        println!("     {} {}: {}", "error".red(), error.name, error.error);
+    }
+}
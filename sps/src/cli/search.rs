@@ -4,15 +4,13 @@ use std::sync::Arc;
 
 use clap::Args;
 use colored::Colorize;
-use prettytable::{format, Cell, Row, Table}; // Make sure this is imported
 use serde_json::Value;
 use sps_common::cache::Cache;
 use sps_common::config::Config;
 use sps_common::error::Result;
 use sps_net::fetch::api;
-use terminal_size::{terminal_size, Width};
-use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
 
+use crate::table::{self, Column, Table};
 use crate::ui;
 
 #[derive(Args, Debug)]
@@ -262,124 +260,45 @@ fn is_cask_match(cask: &Value, query: &str) -> bool {
     false
 }
 
-/// Truncates to max visible width, adding '…' if cut.
-fn truncate_vis(s: &str, max: usize) -> String {
-    if UnicodeWidthStr::width(s) <= max {
-        return s.to_string();
-    }
-    let mut w = 0;
-    let mut out = String::new();
-    // Ensure max is at least 1 for the ellipsis
-    let effective_max = if max > 0 { max } else { 1 };
-
-    for ch in s.chars() {
-        let cw = UnicodeWidthChar::width(ch).unwrap_or(0);
-        // Check if adding the next char *including* ellipsis fits
-        if w + cw >= effective_max.saturating_sub(1) {
-            break;
-        }
-        out.push(ch);
-        w += cw;
-    }
-    out.push('…');
-    out
-}
-
-/// Width‑aware search results with Name:Desc = 1:2 truncation and Name coloured.
+/// Prints the matches as a table of type, name, version and description, the description
+/// truncated to fit the terminal.
 pub fn print_search_results(query: &str, formula_matches: &[Value], cask_matches: &[Value]) {
     let total = formula_matches.len() + cask_matches.len();
     if total == 0 {
         println!("{}", format!("No matches found for '{query}'").yellow());
         return;
     }
-    println!(
-        "{}",
-        format!("Found {total} result(s) for '{query}'").bold()
-    );
-
-    // 1) Terminal width
-    let term_cols = terminal_size()
-        .map(|(Width(w), _)| w as usize)
-        .unwrap_or(120);
-
-    // Fixed columns
-    let type_col = 7;
-    let version_col = 10;
-    let sep_width = 3 * 3; // 3 separators of " | "
-    let total_fixed = type_col + version_col + sep_width;
-
-    // Minimums
-    let name_min_width = 10;
-    let desc_min_width = 20;
-
-    let leftover = term_cols.saturating_sub(total_fixed);
-
-    // Calculate widths
-    let name_prop_width = leftover / 3;
-
-    // TODO: not sure what it is, and what is the purpose of this.
-    // let _desc_prop_width = leftover.saturating_sub(name_prop_width);
-
-    let name_max = std::cmp::max(name_min_width, name_prop_width);
-    let desc_max = std::cmp::max(desc_min_width, leftover.saturating_sub(name_max));
-
-    let name_max = std::cmp::min(name_max, leftover.saturating_sub(desc_min_width));
-    let desc_max = std::cmp::min(desc_max, leftover.saturating_sub(name_max));
-
-    // Build table with header
-    let mut tbl = Table::new();
-    tbl.set_format(*format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
-    // Don't set titles, we'll manually handle the header coloring later if desired
-    // tbl.set_titles(prettytable::row!["Type", "Name", "Description"]);
+    if !table::plain() {
+        println!(
+            "{}",
+            format!("Found {total} result(s) for '{query}'").bold()
+        );
+    }
 
+    let mut tbl = Table::new(vec![
+        Column::left("Type").style(|s| s.cyan()),
+        Column::left("Name").style(|s| s.bold()),
+        Column::right("Version"),
+        Column::left("Description").truncate(),
+    ]);
     for formula in formula_matches {
-        let raw_name = formula
+        let name = formula
             .get("name")
             .and_then(|n| n.as_str())
             .unwrap_or("Unknown");
-        let raw_desc = formula.get("desc").and_then(|d| d.as_str()).unwrap_or("");
-        let _name = truncate_vis(raw_name, name_max);
-        let desc = truncate_vis(raw_desc, desc_max);
-
-        // Extract version
-        let version = get_version(formula);
-
-        tbl.add_row(Row::new(vec![
-            Cell::new("Formula").style_spec("FgC"),
-            Cell::new(&_name).style_spec("Fb"),
-            Cell::new(version),
-            Cell::new(&desc),
-        ]));
-    }
-
-    // Add a separation row if both formulas and casks exist
-    if !formula_matches.is_empty() && !cask_matches.is_empty() {
-        // Use a light horizontal line, spanning all columns
-        tbl.add_row(Row::new(vec![Cell::new(" ").with_hspan(4)]));
+        let desc = formula.get("desc").and_then(|d| d.as_str()).unwrap_or("");
+        tbl.add_row(["Formula", name, get_version(formula), desc]);
     }
-
-    // Add cask rows
     for cask in cask_matches {
-        let raw_name = cask
+        let token = cask
             .get("token")
             .and_then(|t| t.as_str())
             .unwrap_or("Unknown");
-        let raw_desc = cask.get("desc").and_then(|d| d.as_str()).unwrap_or("");
-        let desc = truncate_vis(raw_desc, desc_max);
-
-        // Extract version
-        let version = get_version(cask);
-
-        tbl.add_row(Row::new(vec![
-            Cell::new("Cask").style_spec("FgG"),
-            Cell::new(raw_name).style_spec("Fb"),
-            Cell::new(version),
-            Cell::new(&desc),
-        ]));
+        let desc = cask.get("desc").and_then(|d| d.as_str()).unwrap_or("");
+        let version = cask.get("version").and_then(|v| v.as_str());
+        tbl.add_row(["Cask", token, version.unwrap_or("-"), desc]);
     }
-
-    // Print the table directly (coloring is done during row creation)
-    tbl.printstd();
+    tbl.print();
 }
 
 fn get_version(formula: &Value) -> &str {
@@ -0,0 +1,423 @@
+//! Contains the logic for the `self-update` command, and the cached release check behind the
+//! "new version available" hint printed after other commands.
+//!
+//! Releases come from the project's GitHub releases. The asset for this OS and architecture is
+//! only installed once it matches the SHA256 the release publishes for it, and its signature when
+//! one is published and `signature_keys` are configured. It replaces the running executable by a
+//! rename, never by writing into the old file.
+use std::io::IsTerminal;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command as StdCommand, Stdio};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use std::{env, fs, io};
+
+use clap::Args;
+use colored::Colorize;
+use serde::Deserialize;
+use sps_common::cache::Cache;
+use sps_common::config::{Config, SignatureMode};
+use sps_common::error::{Result, SpsError};
+use sps_common::model::PkgVersion;
+use sps_core::build::extract;
+use sps_net::fetch::{api, http};
+use sps_net::signature;
+use tracing::debug;
+use walkdir::WalkDir;
+
+use crate::cli::output;
+use crate::ui;
+
+const RELEASE_OWNER: &str = "alexykn";
+const RELEASE_REPO: &str = "sps";
+const BINARY_NAME: &str = "sps";
+/// Cache file holding the latest release version seen; its mtime is when it was checked.
+const RELEASE_CHECK_FILE: &str = ".sps_latest_release";
+const RELEASE_CHECK_INTERVAL: Duration = Duration::from_secs(86400);
+/// Cache subdirectory for downloaded releases, and for the new binary when it can't be
+/// installed in place.
+const DOWNLOAD_SUBDIR: &str = "self-update";
+/// Release-wide checksum files, tried when an asset has no `<asset>.sha256` of its own.
+const CHECKSUM_ASSETS: &[&str] = &["SHA256SUMS", "sha256sums.txt", "checksums.txt"];
+
+#[derive(Args, Debug)]
+pub struct SelfUpdate {
+    /// Only report whether a newer release is available
+    #[arg(long)]
+    pub check: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct Release {
+    tag_name: String,
+    #[serde(default)]
+    assets: Vec<Asset>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct Asset {
+    name: String,
+    browser_download_url: String,
+}
+
+impl Release {
+    fn version(&self) -> &str {
+        self.tag_name.trim_start_matches('v')
+    }
+
+    fn asset(&self, name: &str) -> Option<&Asset> {
+        self.assets.iter().find(|asset| asset.name == name)
+    }
+}
+
+impl SelfUpdate {
+    pub async fn run(&self, config: &Config, _cache: Arc<Cache>) -> Result<()> {
+        let pb = ui::create_spinner("Checking for a newer sps release");
+        let release = fetch_release(config).await;
+        pb.finish_and_clear();
+        let release = release?;
+        let current = env!("CARGO_PKG_VERSION");
+        if !is_newer(release.version()) {
+            output::println(format!(
+                "{} sps {} is the latest release",
+                ui::ok_mark(),
+                current
+            ));
+            return Ok(());
+        }
+        if self.check {
+            output::println(format!(
+                "sps {} is available (installed: {}); run `sps self-update` to install it",
+                release.version().green(),
+                current
+            ));
+            return Ok(());
+        }
+
+        let asset = host_asset(&release)?;
+        let sha256 = published_sha256(&release, asset).await?;
+        let download_dir = config.cache_dir.join(DOWNLOAD_SUBDIR);
+        if download_dir.exists() {
+            fs::remove_dir_all(&download_dir)?;
+        }
+        fs::create_dir_all(&download_dir)?;
+
+        let pb = ui::create_spinner(&format!("Downloading sps {}", release.version()));
+        let downloaded = http::fetch_to_path(
+            &asset.browser_download_url,
+            &download_dir.join(&asset.name),
+            &sha256,
+            config,
+        )
+        .await;
+        pb.finish_and_clear();
+        let downloaded = downloaded?;
+        verify_signature(&release, asset, &downloaded, config).await?;
+
+        let new_binary = unpack_binary(&downloaded, &download_dir)?;
+        fs::set_permissions(&new_binary, fs::Permissions::from_mode(0o755))?;
+        check_runs(&new_binary)?;
+
+        let exe = env::current_exe()?.canonicalize()?;
+        match replace_executable(&new_binary, &exe) {
+            Ok(()) => {
+                record_latest(config, release.version());
+                output::println(format!(
+                    "{} Updated sps {} -> {} ({})",
+                    ui::ok_mark(),
+                    current,
+                    release.version().green(),
+                    exe.display()
+                ));
+                Ok(())
+            }
+            Err(e) if is_not_writable(&e) => {
+                let kept = download_dir.join(format!("{BINARY_NAME}-{}", release.version()));
+                fs::rename(&new_binary, &kept)?;
+                output::println(format!(
+                    "Cannot write {} ({}). sps {} was verified and saved as {}; install it with:\n  \
+                     sudo mv {} {}",
+                    exe.display(),
+                    e,
+                    release.version(),
+                    kept.display(),
+                    kept.display(),
+                    exe.display()
+                ));
+                Ok(())
+            }
+            Err(e) => Err(SpsError::IoError(format!(
+                "Failed to replace {}: {e}",
+                exe.display()
+            ))),
+        }
+    }
+}
+
+async fn fetch_release(config: &Config) -> Result<Release> {
+    let value = api::fetch_latest_release(RELEASE_OWNER, RELEASE_REPO, config).await?;
+    serde_json::from_value(value)
+        .map_err(|e| SpsError::ApiRequestError(format!("Unexpected GitHub release response: {e}")))
+}
+
+fn is_newer(latest: &str) -> bool {
+    PkgVersion::parse(latest) > PkgVersion::parse(env!("CARGO_PKG_VERSION"))
+}
+
+/// The release asset built for this OS and architecture, judged by the usual target names in
+/// the asset's file name (`aarch64-apple-darwin`, `macos-arm64`, `linux-x86_64`, ...). A
+/// `universal` macOS build matches either architecture.
+fn host_asset(release: &Release) -> Result<&Asset> {
+    let os_names: &[&str] = match env::consts::OS {
+        "macos" => &["apple-darwin", "darwin", "macos", "osx"],
+        other => &[other],
+    };
+    let arch_names: &[&str] = match env::consts::ARCH {
+        "aarch64" => &["aarch64", "arm64"],
+        "x86_64" => &["x86_64", "amd64", "x64"],
+        other => &[other],
+    };
+    release
+        .assets
+        .iter()
+        .filter(|asset| !is_sidecar(&asset.name))
+        .find(|asset| {
+            let name = asset.name.to_ascii_lowercase();
+            os_names.iter().any(|os| name.contains(os))
+                && (arch_names.iter().any(|arch| name.contains(arch))
+                    || (env::consts::OS == "macos" && name.contains("universal")))
+        })
+        .ok_or_else(|| {
+            SpsError::NotFound(format!(
+                "Release {} has no build for {}-{} (assets: {})",
+                release.tag_name,
+                env::consts::OS,
+                env::consts::ARCH,
+                release
+                    .assets
+                    .iter()
+                    .map(|asset| asset.name.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ))
+        })
+}
+
+/// Checksum and signature files published next to the builds.
+fn is_sidecar(name: &str) -> bool {
+    CHECKSUM_ASSETS.contains(&name)
+        || [".sha256", ".minisig", ".sig", ".asc"]
+            .iter()
+            .any(|suffix| name.ends_with(suffix))
+}
+
+/// The SHA256 the release publishes for `asset`, from `<asset>.sha256` or a checksum file
+/// listing it. A release without one is refused.
+async fn published_sha256(release: &Release, asset: &Asset) -> Result<String> {
+    let candidates = std::iter::once(format!("{}.sha256", asset.name))
+        .chain(CHECKSUM_ASSETS.iter().map(|name| name.to_string()));
+    for candidate in candidates {
+        let Some(sums) = release.asset(&candidate) else {
+            continue;
+        };
+        let text = api::fetch_release_text(&sums.browser_download_url).await?;
+        if let Some(sha256) = find_sha256(&text, &asset.name) {
+            return Ok(sha256);
+        }
+        debug!("{} does not list {}", candidate, asset.name);
+    }
+    Err(SpsError::ChecksumError(format!(
+        "Release {} publishes no SHA256 for {}; not installing it",
+        release.tag_name, asset.name
+    )))
+}
+
+/// Finds the checksum of `file_name` in `sha256sum` output (`<hex>  <name>`, `*<name>` for
+/// binary mode). A line holding only a checksum, as in a per-asset `.sha256` file, matches too.
+fn find_sha256(text: &str, file_name: &str) -> Option<String> {
+    text.lines().find_map(|line| {
+        let mut fields = line.split_whitespace();
+        let sha256 = fields.next()?;
+        let listed = fields.next().map(|name| name.trim_start_matches('*'));
+        let names_file = listed.is_none_or(|name| {
+            Path::new(name).file_name().and_then(|n| n.to_str()) == Some(file_name)
+        });
+        (names_file && sha256.len() == 64 && sha256.chars().all(|c| c.is_ascii_hexdigit()))
+            .then(|| sha256.to_ascii_lowercase())
+    })
+}
+
+/// Checks the release's `<asset>.minisig` or `<asset>.sig` against `signature_keys`. Without
+/// keys a published signature can't be checked and is skipped; a missing one only fails under
+/// `signature_mode = require`.
+async fn verify_signature(
+    release: &Release,
+    asset: &Asset,
+    downloaded: &Path,
+    config: &Config,
+) -> Result<()> {
+    let published = signature::SIGNATURE_SUFFIXES
+        .iter()
+        .find_map(|suffix| release.asset(&format!("{}{suffix}", asset.name)));
+    let Some(published) = published else {
+        if config.signature_mode == SignatureMode::Require {
+            return Err(SpsError::SignatureInvalid(format!(
+                "Release {} publishes no signature for {}",
+                release.tag_name, asset.name
+            )));
+        }
+        debug!(
+            "Release {} has no signature for {}",
+            release.tag_name, asset.name
+        );
+        return Ok(());
+    };
+    let verifiers = signature::verifiers_from_config(config)?;
+    if verifiers.is_empty() {
+        debug!(
+            "Not checking {}: no signature_keys configured",
+            published.name
+        );
+        return Ok(());
+    }
+    let signature_path = downloaded.with_file_name(&published.name);
+    http::fetch_unverified_to_path(&published.browser_download_url, &signature_path, config)
+        .await?;
+    let signature = fs::read(&signature_path)?;
+    let mut reasons = Vec::new();
+    for verifier in &verifiers {
+        match verifier.verify(downloaded, &signature) {
+            Ok(()) => {
+                debug!("{} verified with {}", asset.name, verifier.describe());
+                return Ok(());
+            }
+            Err(reason) => reasons.push(format!("{}: {reason}", verifier.describe())),
+        }
+    }
+    Err(SpsError::SignatureInvalid(format!(
+        "{}: {}",
+        asset.name,
+        reasons.join("; ")
+    )))
+}
+
+/// The `sps` executable in a downloaded asset: the asset itself, or the file of that name in a
+/// `.tar.gz`, `.tar.xz` or `.zip` archive.
+fn unpack_binary(downloaded: &Path, download_dir: &Path) -> Result<PathBuf> {
+    let name = downloaded
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or_default();
+    let archive_type = if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+        "gz"
+    } else if name.ends_with(".tar.xz") {
+        "xz"
+    } else if name.ends_with(".zip") {
+        "zip"
+    } else {
+        return Ok(downloaded.to_path_buf());
+    };
+    let unpacked = download_dir.join("unpacked");
+    extract::extract_archive(downloaded, &unpacked, 0, archive_type)?;
+    WalkDir::new(&unpacked)
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .find(|entry| entry.file_type().is_file() && entry.file_name() == BINARY_NAME)
+        .map(|entry| entry.into_path())
+        .ok_or_else(|| SpsError::NotFound(format!("{name} contains no `{BINARY_NAME}` executable")))
+}
+
+/// Runs the new binary with `--version`, so a build that can't start on this host is never
+/// installed.
+fn check_runs(binary: &Path) -> Result<()> {
+    let output = StdCommand::new(binary)
+        .arg("--version")
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|e| SpsError::CommandExecError(format!("Cannot run the downloaded sps: {e}")))?;
+    if !output.status.success() {
+        return Err(SpsError::CommandExecError(format!(
+            "The downloaded sps failed to start ({}): {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(())
+}
+
+/// Copies `new_binary` next to `exe` and renames it over `exe`. The rename swaps the directory
+/// entry atomically; the running process keeps its old file, and writing into that file instead
+/// would get a signed binary killed on macOS.
+fn replace_executable(new_binary: &Path, exe: &Path) -> io::Result<()> {
+    let dir = exe
+        .parent()
+        .ok_or_else(|| io::Error::other("executable has no parent directory"))?;
+    let staged = dir.join(format!(".{BINARY_NAME}.new"));
+    fs::copy(new_binary, &staged)?;
+    fs::set_permissions(&staged, fs::Permissions::from_mode(0o755))?;
+    if let Err(e) = fs::rename(&staged, exe) {
+        let _ = fs::remove_file(&staged);
+        return Err(e);
+    }
+    Ok(())
+}
+
+fn is_not_writable(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::PermissionDenied | io::ErrorKind::ReadOnlyFilesystem
+    )
+}
+
+fn check_disabled() -> bool {
+    env::var("sps_NO_SELF_UPDATE_CHECK").is_ok_and(|v| v == "1")
+}
+
+fn record_latest(config: &Config, version: &str) {
+    let path = config.cache_dir.join(RELEASE_CHECK_FILE);
+    if let Err(e) = fs::write(&path, version) {
+        debug!("Failed to write {}: {}", path.display(), e);
+    }
+}
+
+/// Looks up the latest release if the cached answer is over a day old. Failures are only
+/// logged; the hint just stays as it was.
+pub async fn refresh_release_check(config: &Config) {
+    if check_disabled() {
+        return;
+    }
+    let path = config.cache_dir.join(RELEASE_CHECK_FILE);
+    let fresh = fs::metadata(&path)
+        .and_then(|meta| meta.modified())
+        .ok()
+        .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+        .is_some_and(|age| age < RELEASE_CHECK_INTERVAL);
+    if fresh {
+        return;
+    }
+    match fetch_release(config).await {
+        Ok(release) => record_latest(config, release.version()),
+        Err(e) => debug!("Could not check for a newer sps release: {}", e),
+    }
+}
+
+/// Prints a one-line hint to stderr when the cached release check found a newer version. Reads
+/// only the cache, so it costs no network round trip.
+pub fn print_update_hint(config: &Config) {
+    if check_disabled() || output::json_lines() || !io::stderr().is_terminal() {
+        return;
+    }
+    let Ok(latest) = fs::read_to_string(config.cache_dir.join(RELEASE_CHECK_FILE)) else {
+        return;
+    };
+    let latest = latest.trim();
+    if !latest.is_empty() && is_newer(latest) {
+        eprintln!(
+            "{} sps {} is available (installed: {}); run `sps self-update`",
+            "Note:".cyan(),
+            latest,
+            env!("CARGO_PKG_VERSION")
+        );
+    }
+}
@@ -0,0 +1,248 @@
+//! Contains the logic for the `shellenv` command, meant for `eval "$(sps shellenv)"` in a shell
+//! startup file.
+//!
+//! Like the path queries it only consults `Config`. When the environment already has everything
+//! pointed at the prefix, with the prefix first in PATH, it prints nothing, so nested shells
+//! don't grow PATH on every start.
+
+use std::env;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use clap::{Args, ValueEnum};
+use sps_common::cache::Cache;
+use sps_common::config::Config;
+use sps_common::error::Result;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+impl Shell {
+    /// The shell named by `$SHELL`; anything that isn't fish gets POSIX syntax.
+    fn detect() -> Self {
+        let shell = env::var_os("SHELL").map(PathBuf::from);
+        match shell
+            .as_deref()
+            .and_then(Path::file_name)
+            .and_then(|n| n.to_str())
+        {
+            Some("fish") => Self::Fish,
+            Some("zsh") => Self::Zsh,
+            _ => Self::Bash,
+        }
+    }
+}
+
+#[derive(Args, Debug)]
+pub struct Shellenv {
+    /// Syntax to print (default: from $SHELL)
+    #[arg(long, value_enum)]
+    pub shell: Option<Shell>,
+}
+
+impl Shellenv {
+    /// Prints the variables and search paths for the prefix, unless they are all set already.
+    pub async fn run(&self, config: &Config, _cache: Arc<Cache>) -> Result<()> {
+        let env = ShellEnv::new(config);
+        if env.is_active() {
+            return Ok(());
+        }
+        print!("{}", env.render(self.shell.unwrap_or_else(Shell::detect)));
+        Ok(())
+    }
+}
+
+struct ShellEnv {
+    /// `SPS_PREFIX`/`SPS_CELLAR`, and `HOMEBREW_PREFIX`/`HOMEBREW_CELLAR` for tools that look
+    /// for a Homebrew installation.
+    variables: Vec<(&'static str, String)>,
+    path: Vec<String>,
+    manpath: String,
+    infopath: String,
+}
+
+impl ShellEnv {
+    fn new(config: &Config) -> Self {
+        let prefix = config.prefix().display().to_string();
+        let cellar = config.cellar_path().display().to_string();
+        let show = |path: PathBuf| path.display().to_string();
+        Self {
+            variables: vec![
+                ("SPS_PREFIX", prefix.clone()),
+                ("SPS_CELLAR", cellar.clone()),
+                ("HOMEBREW_PREFIX", prefix),
+                ("HOMEBREW_CELLAR", cellar),
+            ],
+            path: vec![show(config.bin_dir()), show(config.prefix().join("sbin"))],
+            manpath: show(config.manpagedir()),
+            infopath: show(config.prefix().join("share").join("info")),
+        }
+    }
+
+    /// Whether the current environment already carries all of it. PATH has to start with the
+    /// prefix's directories, so commands there win over system ones of the same name.
+    fn is_active(&self) -> bool {
+        let leads_path = env::var_os("PATH").is_some_and(|value| {
+            let mut present = env::split_paths(&value);
+            self.path
+                .iter()
+                .all(|dir| present.next().is_some_and(|p| p == Path::new(dir)))
+        });
+        let contains = |var: &str, dirs: &[String]| {
+            let Some(value) = env::var_os(var) else {
+                return false;
+            };
+            let present: Vec<PathBuf> = env::split_paths(&value).collect();
+            dirs.iter()
+                .all(|dir| present.iter().any(|p| p == Path::new(dir)))
+        };
+        self.variables
+            .iter()
+            .all(|(name, value)| env::var(name).is_ok_and(|v| v == *value))
+            && leads_path
+            && contains("MANPATH", std::slice::from_ref(&self.manpath))
+            && contains("INFOPATH", std::slice::from_ref(&self.infopath))
+    }
+
+    /// The setup in `shell` syntax. MANPATH keeps a trailing empty entry so `man` still searches
+    /// its default directories after the prefix.
+    fn render(&self, shell: Shell) -> String {
+        let mut out = String::new();
+        match shell {
+            Shell::Bash | Shell::Zsh => {
+                for (name, value) in &self.variables {
+                    out.push_str(&format!("export {name}={};\n", sh_quote(value)));
+                }
+                out.push_str(&format!(
+                    "export PATH={}\"${{PATH+:$PATH}}\";\n",
+                    sh_quote(&self.path.join(":"))
+                ));
+                out.push_str(&format!(
+                    "export MANPATH={}\"${{MANPATH:+:${{MANPATH#:}}}}:\";\n",
+                    sh_quote(&self.manpath)
+                ));
+                out.push_str(&format!(
+                    "export INFOPATH={}\"${{INFOPATH:+:$INFOPATH}}\";\n",
+                    sh_quote(&self.infopath)
+                ));
+            }
+            Shell::Fish => {
+                for (name, value) in &self.variables {
+                    out.push_str(&format!(
+                        "set --global --export {name} {};\n",
+                        fish_quote(value)
+                    ));
+                }
+                let path: Vec<String> = self.path.iter().map(|p| fish_quote(p)).collect();
+                out.push_str(&format!(
+                    "set --global --export PATH {} $PATH;\n",
+                    path.join(" ")
+                ));
+                out.push_str(&format!(
+                    "set --global --export MANPATH {} (string match --invert '' -- $MANPATH) '';\n",
+                    fish_quote(&self.manpath)
+                ));
+                out.push_str(&format!(
+                    "set --global --export INFOPATH {} $INFOPATH;\n",
+                    fish_quote(&self.infopath)
+                ));
+            }
+        }
+        out
+    }
+}
+
+/// Single-quoted for POSIX shells.
+fn sh_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+/// Single-quoted for fish, where only `\` and `'` need escaping inside.
+fn fish_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\\', r"\\").replace('\'', r"\'"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::Command;
+
+    use super::*;
+
+    /// The setup for a prefix whose path needs quoting in every shell.
+    fn shell_env() -> ShellEnv {
+        let prefix = PathBuf::from(r"/opt/it's \sps");
+        ShellEnv::new(&Config {
+            cellar: prefix.join("Cellar"),
+            prefix,
+            ..Config::load().unwrap()
+        })
+    }
+
+    #[test]
+    fn quoting_survives_quotes_and_backslashes() {
+        assert_eq!(sh_quote("plain"), "'plain'");
+        assert_eq!(sh_quote(r"it's \n"), r"'it'\''s \n'");
+        assert_eq!(fish_quote("plain"), "'plain'");
+        assert_eq!(fish_quote(r"it's \n"), r"'it\'s \\n'");
+    }
+
+    #[test]
+    fn posix_shells_get_exports_that_keep_the_existing_paths() {
+        let expected = r#"export SPS_PREFIX='/opt/it'\''s \sps';
+export SPS_CELLAR='/opt/it'\''s \sps/Cellar';
+export HOMEBREW_PREFIX='/opt/it'\''s \sps';
+export HOMEBREW_CELLAR='/opt/it'\''s \sps/Cellar';
+export PATH='/opt/it'\''s \sps/bin:/opt/it'\''s \sps/sbin'"${PATH+:$PATH}";
+export MANPATH='/opt/it'\''s \sps/share/man'"${MANPATH:+:${MANPATH#:}}:";
+export INFOPATH='/opt/it'\''s \sps/share/info'"${INFOPATH:+:$INFOPATH}";
+"#;
+        assert_eq!(shell_env().render(Shell::Bash), expected);
+        assert_eq!(shell_env().render(Shell::Zsh), expected);
+    }
+
+    #[test]
+    fn fish_gets_global_exports_with_its_own_quoting() {
+        assert_eq!(
+            shell_env().render(Shell::Fish),
+            r#"set --global --export SPS_PREFIX '/opt/it\'s \\sps';
+set --global --export SPS_CELLAR '/opt/it\'s \\sps/Cellar';
+set --global --export HOMEBREW_PREFIX '/opt/it\'s \\sps';
+set --global --export HOMEBREW_CELLAR '/opt/it\'s \\sps/Cellar';
+set --global --export PATH '/opt/it\'s \\sps/bin' '/opt/it\'s \\sps/sbin' $PATH;
+set --global --export MANPATH '/opt/it\'s \\sps/share/man' (string match --invert '' -- $MANPATH) '';
+set --global --export INFOPATH '/opt/it\'s \\sps/share/info' $INFOPATH;
+"#
+        );
+    }
+
+    #[test]
+    fn a_posix_shell_evaluates_the_output_to_the_intended_values() {
+        let script = format!(
+            "{}printf '%s\\n' \"$SPS_PREFIX\" \"$PATH\" \"$MANPATH\" \"$INFOPATH\"",
+            shell_env().render(Shell::Bash)
+        );
+
+        let output = Command::new("/bin/sh")
+            .arg("-c")
+            .arg(script)
+            .env_clear()
+            .env("PATH", "/usr/bin:/bin")
+            .env("MANPATH", ":/usr/share/man")
+            .output()
+            .unwrap();
+
+        assert!(output.status.success(), "{output:?}");
+        let prefix = r"/opt/it's \sps";
+        assert_eq!(
+            String::from_utf8(output.stdout).unwrap(),
+            format!(
+                "{prefix}\n{prefix}/bin:{prefix}/sbin:/usr/bin:/bin\n\
+                 {prefix}/share/man:/usr/share/man:\n{prefix}/share/info\n"
+            )
+        );
+    }
+}
@@ -0,0 +1,112 @@
+//! Contains the logic for the `stats` command, which shows the local usage counters kept when
+//! `metrics = local` (see [`sps_common::metrics`]).
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use clap::{Args, ValueEnum};
+use colored::Colorize;
+use sps_common::cache::Cache;
+use sps_common::config::{Config, MetricsMode};
+use sps_common::error::Result;
+use sps_common::metrics;
+
+use crate::table::{Column, Table};
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum StatsSort {
+    /// Installs plus upgrades
+    Installs,
+    /// Cumulative install time
+    Time,
+}
+
+#[derive(Args, Debug)]
+pub struct Stats {
+    /// Order packages by
+    #[arg(long, value_enum, default_value = "installs")]
+    pub sort: StatsSort,
+
+    /// Show at most this many packages
+    #[arg(long, default_value_t = 20)]
+    pub limit: usize,
+}
+
+impl Stats {
+    pub async fn run(&self, config: &Config, _cache: Arc<Cache>) -> Result<()> {
+        let metrics = metrics::load(config)?;
+        if metrics.packages.is_empty() {
+            if config.metrics == MetricsMode::Off {
+                println!(
+                    "Local metrics are off. Set `metrics = \"local\"` in the config file or \
+                     sps_METRICS=local to start counting."
+                );
+            } else {
+                println!(
+                    "Nothing recorded yet in {}",
+                    metrics::store_path(config).display()
+                );
+            }
+            return Ok(());
+        }
+
+        let mut packages: Vec<_> = metrics.packages.iter().collect();
+        match self.sort {
+            StatsSort::Installs => packages.sort_by(|(an, a), (bn, b)| {
+                (b.installs + b.upgrades)
+                    .cmp(&(a.installs + a.upgrades))
+                    .then_with(|| an.cmp(bn))
+            }),
+            StatsSort::Time => packages.sort_by(|(an, a), (bn, b)| {
+                b.install_seconds
+                    .total_cmp(&a.install_seconds)
+                    .then_with(|| an.cmp(bn))
+            }),
+        }
+
+        let mut table = Table::new(vec![
+            Column::left("Package").style(|s| s.cyan()),
+            Column::right("Installs"),
+            Column::right("Upgrades"),
+            Column::right("Uninstalls"),
+            Column::right("Install time"),
+        ]);
+        for (name, stats) in packages.iter().take(self.limit) {
+            table.add_row([
+                name.to_string(),
+                stats.installs.to_string(),
+                stats.upgrades.to_string(),
+                stats.uninstalls.to_string(),
+                format_seconds(stats.install_seconds),
+            ]);
+        }
+        table.print();
+
+        let total: f64 = metrics.packages.values().map(|s| s.install_seconds).sum();
+        println!(
+            "{} package(s), {} spent installing in total",
+            metrics.packages.len(),
+            format_seconds(total)
+        );
+        if config.metrics == MetricsMode::Off {
+            println!(
+                "{}",
+                "Local metrics are currently off; these counts are not being updated.".yellow()
+            );
+        }
+        Ok(())
+    }
+}
+
+/// `1h 2m 3s`, dropping leading zero units.
+fn format_seconds(seconds: f64) -> String {
+    let total = Duration::from_secs_f64(seconds.max(0.0)).as_secs();
+    let (hours, minutes, secs) = (total / 3600, total % 3600 / 60, total % 60);
+    if hours > 0 {
+        format!("{hours}h {minutes}m {secs}s")
+    } else if minutes > 0 {
+        format!("{minutes}m {secs}s")
+    } else {
+        format!("{secs}s")
+    }
+}
@@ -0,0 +1,376 @@
+//! Live status of a pipeline run. Every [`InstallEvent`] updates the terminal display (see
+//! [`crate::cli::output`]) and, for UIs built on sps, is published on the opt-in status socket:
+//! with `--status-socket PATH`, install and upgrade serve newline-delimited JSON over a Unix domain
+//! socket for as long as the pipeline runs.
+//!
+//! Every client first gets a `status` message listing each planned package and its current
+//! [`InstallState`], then one [`InstallEvent`] per line as packages move through download and
+//! install. A client that falls behind is sent a fresh `status` snapshot instead of the events it
+//! missed. The stream ends with a `done` event, after which the socket is removed.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::os::unix::fs::{DirBuilderExt, FileTypeExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use serde::Serialize;
+use sps_common::error::{Result, SpsError};
+use sps_common::model::InstallTargetIdentifier;
+use tokio::io::AsyncWriteExt;
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::broadcast::{self};
+use tokio::task::JoinHandle;
+use tracing::{debug, warn};
+
+use crate::cli::output::{self, OutputCoordinator};
+use crate::cli::pipeline::{PipelineActionType, PipelineJob};
+
+/// Events buffered per client before it is considered lagging.
+const CLIENT_BACKLOG: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Phase {
+    Download,
+    Install,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InstallState {
+    Pending,
+    Downloading,
+    Downloaded,
+    Installing,
+    Finished,
+    Failed,
+    /// Not attempted because an earlier failure stopped the run (`--fail-fast`).
+    Skipped,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct NodeStatus {
+    pub name: String,
+    pub kind: &'static str,
+    pub action: &'static str,
+    pub state: InstallState,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// How a requested target fared: `finished` once every planned package it needs succeeded,
+/// `failed` when any of them failed, `skipped` when its packages were given up or not reached.
+#[derive(Debug, Clone, Serialize)]
+pub struct TargetStatus {
+    pub name: String,
+    pub state: InstallState,
+    /// The packages of the target that failed, the target itself included.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub failed: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum InstallEvent {
+    Status {
+        nodes: Vec<NodeStatus>,
+    },
+    Started {
+        name: String,
+        phase: Phase,
+    },
+    Finished {
+        name: String,
+        phase: Phase,
+    },
+    /// Bytes downloaded so far; `total` is absent when the server doesn't declare a length.
+    Progress {
+        name: String,
+        downloaded: u64,
+        total: Option<u64>,
+    },
+    /// Entries and (uncompressed) bytes of a bottle written so far. `total_files` is absent for
+    /// bottles too large to count up front; `archive_read` of `archive_size` compressed bytes
+    /// then tracks how far the pour has got.
+    Pour {
+        name: String,
+        files: u64,
+        total_files: Option<u64>,
+        bytes: u64,
+        archive_read: u64,
+        archive_size: u64,
+    },
+    Failed {
+        name: String,
+        phase: Phase,
+        error: String,
+    },
+    Skipped {
+        name: String,
+    },
+    Done {
+        succeeded: usize,
+        failed: usize,
+        targets: Vec<TargetStatus>,
+    },
+}
+
+type Nodes = Arc<Mutex<BTreeMap<String, NodeStatus>>>;
+
+struct Server {
+    tx: broadcast::Sender<Arc<str>>,
+    path: PathBuf,
+    accept_task: JoinHandle<()>,
+}
+
+/// Tracks each planned package's state and fans events out to the terminal display and, if one
+/// was requested, the status socket.
+pub struct StatusHub {
+    nodes: Nodes,
+    display: Mutex<OutputCoordinator>,
+    server: Option<Server>,
+}
+
+impl StatusHub {
+    /// Starts tracking `jobs`, serving them on `socket` if given.
+    pub fn new(jobs: &[PipelineJob], socket: Option<&Path>) -> Result<Arc<Self>> {
+        let nodes: Nodes = Arc::new(Mutex::new(initial_nodes(jobs)));
+        let server = match socket {
+            Some(path) => Some(serve(path, &nodes)?),
+            None => None,
+        };
+        let display = Mutex::new(OutputCoordinator::start(
+            &nodes.lock().unwrap_or_else(|e| e.into_inner()),
+        ));
+        Ok(Arc::new(Self {
+            nodes,
+            display,
+            server,
+        }))
+    }
+
+    /// Updates the node's state and reports `event` to the display and every connected client.
+    pub fn emit(&self, event: InstallEvent) {
+        // Held while sending so a client's snapshot and its later events never overlap.
+        let mut nodes = self.nodes.lock().unwrap_or_else(|e| e.into_inner());
+        let update = match &event {
+            InstallEvent::Started { name, phase } => Some((name, started_state(*phase), None)),
+            InstallEvent::Finished { name, phase } => Some((name, finished_state(*phase), None)),
+            InstallEvent::Failed { name, error, .. } => {
+                Some((name, InstallState::Failed, Some(error.clone())))
+            }
+            InstallEvent::Skipped { name } => Some((name, InstallState::Skipped, None)),
+            InstallEvent::Progress { .. }
+            | InstallEvent::Pour { .. }
+            | InstallEvent::Status { .. }
+            | InstallEvent::Done { .. } => None,
+        };
+        if let Some((name, state, error)) = update {
+            if let Some(node) = nodes.get_mut(name) {
+                node.state = state;
+                node.error = error;
+            }
+        }
+        self.display
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .handle(&event, &nodes);
+        if let Some(server) = &self.server {
+            broadcast_line(&server.tx, &event);
+        }
+    }
+}
+
+/// With `--json-lines`, reports a run that ends before anything is executed (nothing to do, a
+/// dry run, or planning errors): the `plan` of `jobs`, then a `summary` counting `failed`.
+pub fn report_unexecuted(jobs: &[PipelineJob], failed: usize) {
+    if !output::json_lines() {
+        return;
+    }
+    let nodes = initial_nodes(jobs);
+    OutputCoordinator::start(&nodes).handle(
+        &InstallEvent::Done {
+            succeeded: 0,
+            failed,
+            targets: Vec::new(),
+        },
+        &nodes,
+    );
+}
+
+fn initial_nodes(jobs: &[PipelineJob]) -> BTreeMap<String, NodeStatus> {
+    jobs.iter()
+        .map(|job| {
+            let (name, kind) = match &job.target {
+                InstallTargetIdentifier::Formula(f) => (f.name().to_string(), "formula"),
+                InstallTargetIdentifier::Cask(c) => (c.token.clone(), "cask"),
+            };
+            let action = match job.action {
+                PipelineActionType::Install => "install",
+                PipelineActionType::Upgrade { .. } => "upgrade",
+                PipelineActionType::Reinstall { .. } => "reinstall",
+            };
+            let node = NodeStatus {
+                name: name.clone(),
+                kind,
+                action,
+                state: InstallState::Pending,
+                error: None,
+            };
+            (name, node)
+        })
+        .collect()
+}
+
+/// Binds `path` (owner-only permissions, see [`bind_private`]) and starts accepting clients. An
+/// existing socket at `path` is assumed stale and replaced; any other kind of file is left alone
+/// and refused.
+fn serve(path: &Path, nodes: &Nodes) -> Result<Server> {
+    match fs::symlink_metadata(path) {
+        Ok(meta) if meta.file_type().is_socket() => fs::remove_file(path)?,
+        Ok(_) => {
+            return Err(SpsError::ValidationError(format!(
+                "--status-socket {} exists and is not a socket",
+                path.display()
+            )))
+        }
+        Err(_) => {}
+    }
+    let listener = bind_private(path).map_err(|e| {
+        SpsError::Generic(format!(
+            "Cannot listen on status socket {}: {e}",
+            path.display()
+        ))
+    })?;
+    debug!("Serving install status on {}", path.display());
+    let tx = broadcast::channel(CLIENT_BACKLOG).0;
+
+    // Clients only hold receivers, so once the hub and this task are gone they see the
+    // channel close after the last event and disconnect.
+    let (accept_nodes, accept_tx) = (Arc::clone(nodes), tx.clone());
+    let accept_task = tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    let (snapshot, rx) = {
+                        let nodes = accept_nodes.lock().unwrap_or_else(|e| e.into_inner());
+                        (snapshot_line(&nodes), accept_tx.subscribe())
+                    };
+                    tokio::spawn(serve_client(
+                        stream,
+                        snapshot,
+                        rx,
+                        Arc::clone(&accept_nodes),
+                    ));
+                }
+                Err(e) => {
+                    warn!("Status socket stopped accepting clients: {}", e);
+                    break;
+                }
+            }
+        }
+    });
+    Ok(Server {
+        tx,
+        path: path.to_path_buf(),
+        accept_task,
+    })
+}
+
+/// Binds the socket in a fresh owner-only directory next to `path`, restricts it to the owner
+/// and only then renames it into place, so it is never reachable with the umask's permissions.
+fn bind_private(path: &Path) -> std::io::Result<UnixListener> {
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "not a file path"))?;
+    let staging = path.with_file_name(format!(
+        ".{}.{}",
+        file_name.to_string_lossy(),
+        std::process::id()
+    ));
+    fs::DirBuilder::new().mode(0o700).create(&staging)?;
+    let staged = staging.join("socket");
+    let bound = UnixListener::bind(&staged).and_then(|listener| {
+        fs::set_permissions(&staged, fs::Permissions::from_mode(0o600))?;
+        fs::rename(&staged, path)?;
+        Ok(listener)
+    });
+    if let Err(e) = fs::remove_dir_all(&staging) {
+        debug!("Failed to remove {}: {}", staging.display(), e);
+    }
+    bound
+}
+
+impl Drop for StatusHub {
+    fn drop(&mut self) {
+        if let Some(server) = &self.server {
+            server.accept_task.abort();
+            if let Err(e) = fs::remove_file(&server.path) {
+                debug!(
+                    "Failed to remove status socket {}: {}",
+                    server.path.display(),
+                    e
+                );
+            }
+        }
+    }
+}
+
+fn started_state(phase: Phase) -> InstallState {
+    match phase {
+        Phase::Download => InstallState::Downloading,
+        Phase::Install => InstallState::Installing,
+    }
+}
+
+fn finished_state(phase: Phase) -> InstallState {
+    match phase {
+        Phase::Download => InstallState::Downloaded,
+        Phase::Install => InstallState::Finished,
+    }
+}
+
+fn broadcast_line(tx: &broadcast::Sender<Arc<str>>, event: &InstallEvent) {
+    match serde_json::to_string(event) {
+        // No receivers just means no client is connected right now.
+        Ok(line) => drop(tx.send(Arc::from(line + "\n"))),
+        Err(e) => debug!("Failed to serialize status event: {}", e),
+    }
+}
+
+fn snapshot_line(nodes: &BTreeMap<String, NodeStatus>) -> String {
+    let event = InstallEvent::Status {
+        nodes: nodes.values().cloned().collect(),
+    };
+    serde_json::to_string(&event).unwrap_or_default() + "\n"
+}
+
+async fn serve_client(
+    mut stream: UnixStream,
+    snapshot: String,
+    mut rx: broadcast::Receiver<Arc<str>>,
+    nodes: Nodes,
+) {
+    if stream.write_all(snapshot.as_bytes()).await.is_err() {
+        return;
+    }
+    loop {
+        let line: Arc<str> = match rx.recv().await {
+            Ok(line) => line,
+            Err(RecvError::Lagged(missed)) => {
+                debug!(
+                    "Status client lagged by {} events; resending snapshot",
+                    missed
+                );
+                let nodes = nodes.lock().unwrap_or_else(|e| e.into_inner());
+                Arc::from(snapshot_line(&nodes))
+            }
+            Err(RecvError::Closed) => break,
+        };
+        if stream.write_all(line.as_bytes()).await.is_err() {
+            break;
+        }
+    }
+}
@@ -0,0 +1,115 @@
+//! Contains the logic for the `switch` command.
+
+use std::sync::Arc;
+
+use clap::Args;
+use colored::Colorize;
+use sps_common::cache::Cache;
+use sps_common::config::Config;
+use sps_common::error::{Result, SpsError};
+use sps_common::formulary::Formulary;
+use sps_common::keg::KegRegistry;
+use sps_common::model::formula::Formula;
+use sps_common::model::PkgVersion;
+use sps_core::build::formula::versions::{self, KegHistory};
+use tracing::debug;
+
+use crate::ui;
+
+#[derive(Args, Debug)]
+pub struct Switch {
+    /// Name of the formula
+    pub name: String,
+
+    /// Installed version to make current (default: the version recorded as previous)
+    #[arg(value_name = "VERSION")]
+    pub keg_version: Option<String>,
+}
+
+impl Switch {
+    /// Points the formula's opt link and prefix links at another installed keg. Nothing is
+    /// downloaded; the keg has to be in the Cellar already (upgrades keep the previous one).
+    pub async fn run(&self, config: &Config, _cache: Arc<Cache>) -> Result<()> {
+        let keg_registry = KegRegistry::new(config.clone());
+        let current = keg_registry
+            .get_installed_keg(&self.name)?
+            .ok_or_else(|| SpsError::NotFound(format!("'{}' is not installed", self.name)))?;
+        let kegs = keg_registry.list_formula_kegs(&self.name)?;
+
+        let wanted = match &self.keg_version {
+            Some(version) => version.clone(),
+            None => KegHistory::read(&current.path)
+                .previous_version
+                .ok_or_else(|| {
+                    SpsError::Generic(format!(
+                        "{} has no recorded previous version; name one of: {}",
+                        self.name,
+                        kegs.iter()
+                            .map(versions::keg_version)
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    ))
+                })?,
+        };
+        // `1.2` matches the keg `1.2_1` when no other revision of it is installed.
+        let wanted_version = PkgVersion::parse(&wanted);
+        let matching: Vec<_> = kegs
+            .iter()
+            .filter(|keg| keg.version.matches(&wanted_version))
+            .collect();
+        let target = match matching.as_slice() {
+            [keg] => (*keg).clone(),
+            [] => {
+                return Err(SpsError::NotFound(format!(
+                    "{} {} is not installed (installed: {})",
+                    self.name,
+                    wanted,
+                    kegs.iter()
+                        .map(versions::keg_version)
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )))
+            }
+            _ => {
+                return Err(SpsError::Generic(format!(
+                    "{} {} matches several kegs; give the revision too",
+                    self.name, wanted
+                )))
+            }
+        };
+        if target == current {
+            println!(
+                "{} {} is already the current version",
+                self.name,
+                versions::keg_version(&current)
+            );
+            return Ok(());
+        }
+
+        let formula = formula_for(&current.name, &versions::keg_version(&target), config)?;
+        versions::switch_keg(&formula, &current, &target, config)?;
+        println!(
+            "{} Switched {} from {} to {}",
+            ui::ok_mark(),
+            current.name.cyan(),
+            versions::keg_version(&current),
+            versions::keg_version(&target).green()
+        );
+        Ok(())
+    }
+}
+
+/// The formula definition to link with: the cached one, or a bare stand-in when the formula is
+/// gone from the index (links then cover the keg but not its opt aliases).
+fn formula_for(name: &str, version: &str, config: &Config) -> Result<Formula> {
+    match Formulary::new(config.clone()).load_formula(name) {
+        Ok(formula) => Ok(formula),
+        Err(e) => {
+            debug!("No definition for {} ({}); linking without it", name, e);
+            Ok(serde_json::from_value(serde_json::json!({
+                "name": name,
+                "versions": { "stable": version },
+            }))?)
+        }
+    }
+}
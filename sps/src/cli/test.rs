@@ -0,0 +1,302 @@
+//! Contains the logic for the `test` command.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::Duration;
+use std::{env, fs};
+
+use clap::Args;
+use colored::Colorize;
+use serde_json::Value;
+use sps_common::cache::Cache;
+use sps_common::cancel::CancellationToken;
+use sps_common::config::Config;
+use sps_common::dependency::{DependencyResolver, ResolutionContext, ResolutionStatus};
+use sps_common::error::{Result, SpsError};
+use sps_common::formulary::Formulary;
+use sps_common::keg::{InstalledKeg, KegRegistry};
+use sps_common::model::formula::{split_tap_name, Formula};
+use sps_core::{build, KindHint};
+use tokio::process::Command;
+use tracing::{debug, warn};
+
+use crate::cli::pipeline::{CommandType, PipelineExecutor, PipelineFlags};
+use crate::ui;
+
+const TEST_TIMEOUT: Duration = Duration::from_secs(300);
+
+#[derive(Args, Debug)]
+pub struct Test {
+    /// Installed formula to test
+    pub name: String,
+
+    /// Install missing test dependencies into the prefix instead of a throwaway scratch prefix
+    #[arg(long)]
+    pub no_scratch: bool,
+}
+
+impl Test {
+    /// Installs the formula's test dependencies and runs its tests against the installed keg.
+    pub async fn run(&self, config: &Config, cache: Arc<Cache>) -> Result<()> {
+        let keg_registry = KegRegistry::new(config.clone());
+        let keg = keg_registry.get_installed_keg(&self.name)?.ok_or_else(|| {
+            SpsError::NotFound(format!("Formula '{}' is not installed.", self.name))
+        })?;
+        let formulary = Formulary::new(config.clone());
+        let formula = formulary.load_formula(&self.name)?;
+
+        let missing = missing_test_closure(&formulary, &keg_registry, config, &self.name)?;
+        let scratch = if missing.is_empty() || self.no_scratch {
+            None
+        } else {
+            Some(env::temp_dir().join(format!("sps-test-{}-{}", self.name, std::process::id())))
+        };
+
+        let outcome = self
+            .install_and_test(config, cache, &formula, &keg, &missing, scratch.as_deref())
+            .await;
+
+        if let Some(scratch) = &scratch {
+            if let Err(e) = fs::remove_dir_all(scratch) {
+                warn!(
+                    "Failed to remove scratch prefix {}: {}",
+                    scratch.display(),
+                    e
+                );
+            }
+        }
+        outcome
+    }
+
+    async fn install_and_test(
+        &self,
+        config: &Config,
+        cache: Arc<Cache>,
+        formula: &Formula,
+        keg: &InstalledKeg,
+        missing: &[String],
+        scratch: Option<&Path>,
+    ) -> Result<()> {
+        let mut search_path = vec![keg.path.join("bin"), keg.path.join("sbin")];
+        if !missing.is_empty() {
+            let install_config = match scratch {
+                Some(dir) => scratch_config(config, dir)?,
+                None => config.clone(),
+            };
+            println!(
+                "Installing test dependencies{}: {}",
+                scratch
+                    .map(|_| " into a scratch prefix")
+                    .unwrap_or_default(),
+                missing.join(", ").cyan()
+            );
+            let flags = PipelineFlags {
+                build_from_source: false,
+                include_optional: false,
+                skip_recommended: true,
+                only_dependencies: false,
+                kind_hint: KindHint::Formula,
+                no_wait: false,
+                ignore_installed: false,
+                only_missing: true,
+                ignore_requirements: false,
+                fail_fast: false,
+                strict_deps: false,
+                dry_run: false,
+                emit_plan: None,
+                graph: None,
+                prune_installed: false,
+                status_socket: None,
+                adopt: false,
+                force: false,
+                cask_definitions: HashMap::new(),
+                head: false,
+                fetch_head: false,
+                cancel: CancellationToken::new(),
+            };
+            PipelineExecutor::execute_pipeline(
+                missing,
+                CommandType::Install,
+                &install_config,
+                cache,
+                &flags,
+            )
+            .await?;
+            if scratch.is_some() {
+                search_path.push(install_config.bin_dir());
+                search_path.push(install_config.prefix().join("sbin"));
+            }
+        }
+        search_path.push(config.bin_dir());
+        search_path.push(config.prefix().join("sbin"));
+
+        let tests = match declared_tests(formula) {
+            Some(commands) => commands,
+            None => {
+                let binary = primary_binary(keg).ok_or_else(|| {
+                    SpsError::Generic(format!(
+                        "{} declares no test and has no executable to smoke test",
+                        self.name
+                    ))
+                })?;
+                debug!(
+                    "No declared test for {}; smoke testing {}",
+                    self.name, binary
+                );
+                vec![format!("{binary} --version")]
+            }
+        };
+
+        let work_dir = env::temp_dir().join(format!("sps-test-run-{}", std::process::id()));
+        fs::create_dir_all(&work_dir).map_err(|e| SpsError::Io(Arc::new(e)))?;
+        let mut failed = 0;
+        for test in &tests {
+            match run_test_command(test, &search_path, &work_dir).await {
+                Ok(()) => println!("{} {}: {}", ui::ok_mark(), self.name.cyan(), test),
+                Err(reason) => {
+                    println!(
+                        "{} {}: {} ({})",
+                        ui::fail_mark(),
+                        self.name.cyan(),
+                        test,
+                        reason
+                    );
+                    failed += 1;
+                }
+            }
+        }
+        let _ = fs::remove_dir_all(&work_dir);
+
+        if failed > 0 {
+            return Err(SpsError::Generic(format!(
+                "{} of {} test(s) failed for {}",
+                failed,
+                tests.len(),
+                self.name
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Names of the formulae the test needs (test dependencies of `name` and their runtime
+/// closure) that are not installed yet.
+fn missing_test_closure(
+    formulary: &Formulary,
+    keg_registry: &KegRegistry,
+    config: &Config,
+    name: &str,
+) -> Result<Vec<String>> {
+    let ctx = ResolutionContext {
+        formulary,
+        keg_registry,
+        sps_prefix: config.prefix(),
+        include_optional: false,
+        include_test: true,
+        skip_recommended: true,
+        force_build: false,
+        ignore_installed: false,
+        only_missing: true,
+        platform: Some(build::formula::host_platform()),
+    };
+    let graph = DependencyResolver::new(ctx).resolve_targets(&[name.to_string()])?;
+    let mut missing: Vec<String> = graph
+        .resolution_details
+        .values()
+        .filter(|dep| dep.status == ResolutionStatus::Missing)
+        .map(|dep| dep.formula.name().to_string())
+        .collect();
+    missing.sort();
+    Ok(missing)
+}
+
+/// A copy of `config` rooted at `dir`, with the prefix layout created so the pipeline's
+/// preflight checks pass.
+fn scratch_config(config: &Config, dir: &Path) -> Result<Config> {
+    let mut scratch = config.clone();
+    scratch.prefix = dir.to_path_buf();
+    scratch.cellar = dir.join("Cellar");
+    for path in [scratch.cellar.clone(), scratch.opt_dir(), scratch.bin_dir()] {
+        fs::create_dir_all(&path).map_err(|e| SpsError::Io(Arc::new(e)))?;
+    }
+    Ok(scratch)
+}
+
+/// Shell commands from a `test` entry in the formula JSON. The Homebrew API never ships test
+/// blocks, but tap JSON may carry them as a string or an array of strings.
+fn declared_tests(formula: &Formula) -> Option<Vec<String>> {
+    let commands: Vec<String> = match formula.extra.get("test")? {
+        Value::String(s) => vec![s.clone()],
+        Value::Array(items) => items
+            .iter()
+            .filter_map(Value::as_str)
+            .map(str::to_string)
+            .collect(),
+        _ => Vec::new(),
+    };
+    (!commands.is_empty()).then_some(commands)
+}
+
+/// `bin/<name>` if the keg has it, otherwise the first executable in `bin/` or `sbin/`.
+fn primary_binary(keg: &InstalledKeg) -> Option<String> {
+    let short_name = split_tap_name(&keg.name).1;
+    if keg.path.join("bin").join(short_name).is_file() {
+        return Some(short_name.to_string());
+    }
+    ["bin", "sbin"].iter().find_map(|dir| {
+        let mut names: Vec<String> = fs::read_dir(keg.path.join(dir))
+            .ok()?
+            .flatten()
+            .filter(|e| e.path().is_file())
+            .map(|e| e.file_name().to_string_lossy().to_string())
+            .collect();
+        names.sort();
+        names.into_iter().next()
+    })
+}
+
+/// Runs `test` through `sh -c` with a cleared environment: PATH limited to the keg, the
+/// dependency prefixes and system directories, and HOME pointed at a scratch directory.
+async fn run_test_command(
+    test: &str,
+    search_path: &[PathBuf],
+    work_dir: &Path,
+) -> std::result::Result<(), String> {
+    let mut path: Vec<PathBuf> = search_path.to_vec();
+    path.extend(["/usr/bin", "/bin", "/usr/sbin", "/sbin"].map(PathBuf::from));
+    let path = env::join_paths(path).map_err(|e| e.to_string())?;
+
+    let mut command = Command::new("/bin/sh");
+    command
+        .arg("-c")
+        .arg(test)
+        .current_dir(work_dir)
+        .env_clear()
+        .env("PATH", path)
+        .env("HOME", work_dir)
+        .env("TMPDIR", work_dir)
+        .env("LANG", "en_US.UTF-8")
+        .stdin(Stdio::null())
+        .kill_on_drop(true);
+
+    let output = match tokio::time::timeout(TEST_TIMEOUT, command.output()).await {
+        Ok(result) => result.map_err(|e| e.to_string())?,
+        Err(_) => return Err(format!("timed out after {}s", TEST_TIMEOUT.as_secs())),
+    };
+    debug!(
+        "Test output for `{}`:\n{}{}",
+        test,
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(match output.status.code() {
+            Some(code) => format!("exit status {code}"),
+            None => "terminated by signal".to_string(),
+        })
+    }
+}
@@ -1,11 +1,24 @@
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 
 use clap::Args;
 use colored::Colorize;
 use sps_common::config::Config;
+use sps_common::dependency::{
+    Direction, FailurePolicy, Outcome, ReverseDependencyGraph, Scheduler,
+};
 use sps_common::error::{Result, SpsError};
+use sps_common::formulary::Formulary;
+use sps_common::keg::KegRegistry;
+use sps_common::metrics::{self, MetricEvent};
+use sps_common::model::formula::split_tap_name;
 use sps_common::Cache;
-use sps_core::{installed, uninstall as core_uninstall, PackageType, UninstallOptions};
+use sps_core::build::cask::lock::CaskLock;
+use sps_core::hooks::{self, HookEvent};
+use sps_core::{
+    build, installed, resolve_token, uninstall as core_uninstall, InstalledPackageInfo, KindHint,
+    NameIndexes, PackageType, Resolved, UninstallOptions,
+};
 use tracing::{debug, error}; // Removed warn
 use walkdir;
 
@@ -16,69 +29,114 @@ pub struct Uninstall {
     /// The names of the formulas or casks to uninstall
     #[arg(required = true)] // Ensure at least one name is given
     pub names: Vec<String>,
+
+    /// Treat all names as formulae
+    #[arg(long, conflicts_with = "cask")]
+    pub formula: bool,
+
+    /// Treat all names as casks
+    #[arg(long)]
+    pub cask: bool,
+
+    /// Also uninstall every installed formula that depends on the given formulae
+    #[arg(long)]
+    pub cascade: bool,
+
+    /// Print the ordered removal plan without uninstalling anything
+    #[arg(long)]
+    pub dry_run: bool,
 }
 
 impl Uninstall {
-    pub async fn run(&self, config: &Config, _cache: Arc<Cache>) -> Result<()> {
+    pub async fn run(&self, config: &Config, cache: Arc<Cache>) -> Result<()> {
         let names = &self.names;
+        let hint = KindHint::from_flags(self.formula, self.cask)?;
         let mut errors: Vec<(String, SpsError)> = Vec::new();
 
+        // Only installed packages can be uninstalled, so resolve against those (aliases included).
+        let installed_packages = installed::get_installed_packages(config).await?;
+        let collect = |kind: PackageType| -> HashSet<String> {
+            installed_packages
+                .iter()
+                .filter(|p| p.pkg_type == kind)
+                .map(|p| p.name.clone())
+                .collect()
+        };
+        let indexes = NameIndexes::load(&cache)
+            .restrict_to(&collect(PackageType::Formula), &collect(PackageType::Cask));
+
+        // --- Resolve every requested name before touching anything ---
+        let mut requested: Vec<InstalledPackageInfo> = Vec::new();
         for name in names {
-            // Basic name validation to prevent path traversal
-            if name.contains('/') || name.contains("..") {
+            // Basic name validation to prevent path traversal; `user/repo/name` is allowed.
+            if name.contains("..")
+                || split_tap_name(name).1.contains('/')
+                || name.split('/').any(str::is_empty)
+            {
                 let msg = format!("Invalid package name '{name}' contains disallowed characters");
-                error!("✖ {msg}");
+                error!("{} {msg}", ui::fail_mark());
                 errors.push((name.to_string(), SpsError::Generic(msg)));
                 continue;
             }
 
-            let pb = ui::create_spinner(&format!("Uninstalling {name}"));
-
-            match installed::get_installed_package(name, config).await? {
-                Some(installed_info) => {
-                    let (file_count, size_bytes) =
-                        count_files_and_size(&installed_info.path).unwrap_or((0, 0));
-                    let uninstall_opts = UninstallOptions { skip_zap: false }; // Explicit uninstall includes zap
-                    debug!(
-                        "Attempting uninstall for {} ({:?})",
-                        name, installed_info.pkg_type
-                    );
-                    let uninstall_result = match installed_info.pkg_type {
-                        PackageType::Formula => core_uninstall::uninstall_formula_artifacts(
-                            &installed_info,
-                            config,
-                            &uninstall_opts,
-                        ),
-                        PackageType::Cask => core_uninstall::uninstall_cask_artifacts(
-                            &installed_info,
-                            config,
-                            &uninstall_opts,
-                        ),
-                    };
-
-                    if let Err(e) = uninstall_result {
-                        error!("✖ Failed to uninstall '{}': {}", name.cyan(), e);
-                        errors.push((name.to_string(), e));
-                        pb.finish_and_clear();
-                    } else {
-                        pb.finish_with_message(format!(
-                            "✓ Uninstalled {:?} {} ({} files, {})",
-                            installed_info.pkg_type,
-                            name.green(),
-                            file_count,
-                            format_size(size_bytes)
-                        ));
-                    }
+            let target = match resolve_token(name, hint, &indexes) {
+                Resolved::NotFound => None,
+                resolved => Some(resolved.into_target(name)?),
+            };
+            let installed_info = target.and_then(|(kind, canonical)| {
+                installed_packages
+                    .iter()
+                    .find(|p| p.pkg_type == kind && p.name == canonical)
+                    .cloned()
+            });
+            match installed_info {
+                Some(info) if !requested.iter().any(|r| r.name == info.name) => {
+                    requested.push(info)
                 }
+                Some(_) => {}
                 None => {
                     let msg = format!("Package '{name}' is not installed.");
-                    error!("✖ {msg}");
+                    error!("{} {msg}", ui::fail_mark());
                     errors.push((name.to_string(), SpsError::NotFound(msg)));
-                    pb.finish_and_clear();
                 }
             }
         }
 
+        let plan = self.removal_plan(config, requested, &installed_packages)?;
+
+        if self.dry_run {
+            let ordered = plan.ordered();
+            println!("Would uninstall {} package(s), in order:", ordered.len());
+            for (i, info) in ordered.iter().enumerate() {
+                println!("  {}. {} ({:?})", i + 1, info.name.cyan(), info.pkg_type);
+            }
+            return Ok(());
+        }
+
+        // A formula whose dependent could not be removed stays, since the dependent still needs it.
+        let RemovalPlan {
+            formulae,
+            formula_infos,
+            casks,
+        } = plan;
+        for (name, outcome) in formulae.run(|name| uninstall_package(&formula_infos[name], config))
+        {
+            match outcome {
+                Outcome::Done(()) => {}
+                Outcome::Failed(e) => errors.push((name.to_string(), e)),
+                Outcome::Skipped(cause) => {
+                    let msg = format!("Kept {name}: uninstalling its dependent {cause} failed");
+                    error!("{} {msg}", ui::fail_mark());
+                    errors.push((name.to_string(), SpsError::DependencyError(msg)));
+                }
+            }
+        }
+        for info in &casks {
+            if let Err(e) = uninstall_package(info, config) {
+                errors.push((info.name.clone(), e));
+            }
+        }
+
         if errors.is_empty() {
             Ok(())
         } else {
@@ -103,10 +161,154 @@ impl Uninstall {
             ))
         }
     }
+
+    /// Orders the requested packages for removal. Formulae still needed by installed formulae
+    /// outside the request abort the whole uninstall, unless `--cascade` pulls those dependents
+    /// in too. Formulae go dependents-first; casks follow in request order.
+    fn removal_plan(
+        &self,
+        config: &Config,
+        requested: Vec<InstalledPackageInfo>,
+        installed_packages: &[InstalledPackageInfo],
+    ) -> Result<RemovalPlan> {
+        let (formulae, casks): (Vec<_>, Vec<_>) = requested
+            .into_iter()
+            .partition(|info| info.pkg_type == PackageType::Formula);
+        if formulae.is_empty() {
+            return Ok(RemovalPlan {
+                formulae: Scheduler::new(Direction::DependentsFirst, FailurePolicy::SkipBlocked),
+                formula_infos: HashMap::new(),
+                casks,
+            });
+        }
+
+        let graph = ReverseDependencyGraph::from_installed(
+            &KegRegistry::new(config.clone()),
+            &Formulary::new(config.clone()),
+        )?;
+        let mut targets: HashSet<String> = formulae.into_iter().map(|info| info.name).collect();
+        if self.cascade {
+            targets = graph.with_all_dependents(&targets);
+        } else {
+            let blocked = graph.outside_dependents(&targets);
+            if !blocked.is_empty() {
+                for (target, dependents) in &blocked {
+                    error!(
+                        "{} {} is required by: {}",
+                        ui::fail_mark(),
+                        target.cyan(),
+                        dependents.join(", ")
+                    );
+                }
+                return Err(SpsError::DependencyError(format!(
+                    "Refusing to uninstall {} still required by other installed formulae; \
+                     pass --cascade to remove the dependents as well.",
+                    blocked.keys().cloned().collect::<Vec<_>>().join(", ")
+                )));
+            }
+        }
+
+        let formula_infos: HashMap<String, InstalledPackageInfo> = installed_packages
+            .iter()
+            .filter(|p| p.pkg_type == PackageType::Formula && targets.contains(&p.name))
+            .map(|p| (p.name.clone(), p.clone()))
+            .collect();
+        // Cascaded names without an installed keg have nothing to remove.
+        targets.retain(|name| formula_infos.contains_key(name));
+        Ok(RemovalPlan {
+            formulae: graph.removal_scheduler(&targets, FailurePolicy::SkipBlocked),
+            formula_infos,
+            casks,
+        })
+    }
+}
+
+/// What `uninstall` removes: formulae in dependency order, then casks.
+struct RemovalPlan {
+    formulae: Scheduler,
+    formula_infos: HashMap<String, InstalledPackageInfo>,
+    casks: Vec<InstalledPackageInfo>,
+}
+
+impl RemovalPlan {
+    /// Everything in the order it would be removed if nothing fails.
+    fn ordered(&self) -> Vec<&InstalledPackageInfo> {
+        self.formulae
+            .order()
+            .iter()
+            .filter_map(|name| self.formula_infos.get(&**name))
+            .chain(&self.casks)
+            .collect()
+    }
+}
+
+/// Removes one installed package, reporting the result on its own spinner line.
+pub(crate) fn uninstall_package(
+    installed_info: &InstalledPackageInfo,
+    config: &Config,
+) -> Result<()> {
+    let name = installed_info.name.as_str();
+    let pb = ui::create_spinner(&format!("Uninstalling {name}"));
+
+    let (file_count, size_bytes) = count_files_and_size(&installed_info.path).unwrap_or((0, 0));
+    let uninstall_opts = UninstallOptions {
+        skip_zap: false, // Explicit uninstall includes zap
+        keep_links: false,
+    };
+    debug!(
+        "Attempting uninstall for {} ({:?})",
+        name, installed_info.pkg_type
+    );
+    let uninstall_result = match installed_info.pkg_type {
+        PackageType::Formula => hooks::run_hook(
+            HookEvent::PreUninstall,
+            name,
+            &installed_info.version,
+            &installed_info.path,
+            config,
+        )
+        .and_then(|()| {
+            core_uninstall::uninstall_formula_artifacts(installed_info, config, &uninstall_opts)
+        })
+        // Kegs kept from earlier versions go with the formula.
+        .and_then(|()| build::formula::versions::remove_retained_kegs(name, config)),
+        PackageType::Cask => CaskLock::acquire(name, config).and_then(|_lock| {
+            if !installed_info.path.exists() {
+                return Err(SpsError::NotFound(format!(
+                    "Cask '{name}' was uninstalled by another process."
+                )));
+            }
+            core_uninstall::uninstall_cask_artifacts(installed_info, config, &uninstall_opts)
+        }),
+    };
+
+    match uninstall_result {
+        Err(e) => {
+            error!(
+                "{} Failed to uninstall '{}': {}",
+                ui::fail_mark(),
+                name.cyan(),
+                e
+            );
+            pb.finish_and_clear();
+            Err(e)
+        }
+        Ok(()) => {
+            metrics::record(config, name, MetricEvent::Uninstall, None);
+            pb.finish_with_message(format!(
+                "{} Uninstalled {:?} {} ({} files, {})",
+                ui::ok_mark(),
+                installed_info.pkg_type,
+                name.green(),
+                file_count,
+                format_size(size_bytes)
+            ));
+            Ok(())
+        }
+    }
 }
 
-// --- Unchanged Helper Functions ---
-fn count_files_and_size(path: &std::path::Path) -> Result<(usize, u64)> {
+pub(crate) fn count_files_and_size(path: &std::path::Path) -> Result<(usize, u64)> {
     let mut file_count = 0;
     let mut total_size = 0;
     for entry in walkdir::WalkDir::new(path) {
@@ -138,7 +340,7 @@ fn count_files_and_size(path: &std::path::Path) -> Result<(usize, u64)> {
     Ok((file_count, total_size))
 }
 
-fn format_size(size: u64) -> String {
+pub(crate) fn format_size(size: u64) -> String {
     const KB: u64 = 1024;
     const MB: u64 = KB * 1024;
     const GB: u64 = MB * 1024;
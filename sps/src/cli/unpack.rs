@@ -0,0 +1,123 @@
+//! Contains the logic for the `unpack` command.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use clap::Args;
+use sps_common::cache::Cache;
+use sps_common::config::Config;
+use sps_common::error::{Result, SpsError};
+use sps_common::formulary::Formulary;
+use sps_core::build;
+use sps_net::fetch::api;
+
+use crate::cli::api::load_cask;
+
+#[derive(Args, Debug)]
+pub struct Unpack {
+    /// Name of the formula (or, with --cask, token of the cask)
+    pub name: String,
+
+    /// Unpack the cask's download instead of a formula bottle
+    #[arg(long)]
+    pub cask: bool,
+
+    /// Extract here instead of ./<name>-<version>
+    #[arg(long, value_name = "DIR")]
+    pub destdir: Option<PathBuf>,
+
+    /// Replace the destination if it exists and is not empty
+    #[arg(long)]
+    pub force: bool,
+}
+
+impl Unpack {
+    /// Downloads (or reuses from the cache) the verified bottle or cask artifact and extracts it,
+    /// without touching the Cellar, Caskroom, links or receipts. Bottles are extracted as shipped,
+    /// with their install-path placeholders left in place. Prints the extracted path.
+    pub async fn run(&self, config: &Config, cache: Arc<Cache>) -> Result<()> {
+        let dest = if self.cask {
+            self.unpack_cask(config, &cache).await?
+        } else {
+            self.unpack_formula(config, &cache).await?
+        };
+        println!("{}", dest.display());
+        Ok(())
+    }
+
+    async fn unpack_formula(&self, config: &Config, cache: &Cache) -> Result<PathBuf> {
+        let formulary = Formulary::new(config.clone());
+        let formula = match formulary.load_formula(&self.name) {
+            Ok(f) => f,
+            Err(e) => {
+                tracing::debug!(
+                    "Formula '{}' not loaded from cache ({}). Fetching from API.",
+                    self.name,
+                    e
+                );
+                api::get_formula(&self.name).await?
+            }
+        };
+        let dest = self.destination(&formula.version_str_full())?;
+        let client = reqwest::Client::new();
+        let bottle =
+            build::formula::bottle::download_bottle(&formula, config, cache, &client).await?;
+        fs::create_dir_all(&dest)?;
+        // Bottles are laid out as <name>/<version>/...
+        build::extract::extract_tar_pipelined(&bottle, &dest, 2)?;
+        Ok(dest)
+    }
+
+    async fn unpack_cask(&self, config: &Config, cache: &Cache) -> Result<PathBuf> {
+        let cask = load_cask(config, cache, &self.name).await?;
+        let cask = build::cask::resolve_for_host(&cask, config);
+        let dest = self.destination(cask.version.as_deref().unwrap_or("latest"))?;
+        let download = build::cask::download_cask(&cask, cache, config).await?;
+        let extension = build::cask::container_extension(&download)?;
+        fs::create_dir_all(&dest)?;
+        if extension == "pkg" || extension == "mpkg" {
+            // Installer packages are not containers; hand over the package itself.
+            let file_name = download
+                .file_name()
+                .map(PathBuf::from)
+                .unwrap_or_else(|| PathBuf::from(format!("{}.{extension}", cask.token)));
+            fs::copy(&download, dest.join(file_name))?;
+        } else {
+            build::cask::extract_container(&download, &extension, &dest, config)?;
+        }
+        Ok(dest)
+    }
+
+    /// `--destdir` or `./<name>-<version>`, emptied first if `--force` allows it.
+    fn destination(&self, version: &str) -> Result<PathBuf> {
+        if self.name.is_empty() || self.name.contains('/') {
+            return Err(SpsError::Generic(format!(
+                "Invalid package name '{}'",
+                self.name
+            )));
+        }
+        let dest = match &self.destdir {
+            Some(dir) => dir.clone(),
+            None => std::env::current_dir()?.join(format!("{}-{version}", self.name)),
+        };
+        if !is_empty_or_missing(&dest)? {
+            if !self.force {
+                return Err(SpsError::Generic(format!(
+                    "{} already exists and is not empty; pass --force to replace it",
+                    dest.display()
+                )));
+            }
+            fs::remove_dir_all(&dest)?;
+        }
+        Ok(dest)
+    }
+}
+
+fn is_empty_or_missing(path: &Path) -> Result<bool> {
+    match fs::read_dir(path) {
+        Ok(mut entries) => Ok(entries.next().is_none()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(true),
+        Err(e) => Err(e.into()),
+    }
+}
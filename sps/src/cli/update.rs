@@ -3,10 +3,13 @@ use std::fs;
 use std::sync::Arc;
 
 use sps_common::cache::Cache;
-use sps_common::config::Config;
+use sps_common::config::{AdvisoryMode, Config, MetadataStrategy};
 use sps_common::error::Result;
+use sps_common::{advisory, overrides};
+use sps_core::metadata;
 use sps_net::fetch::api;
 
+use crate::cli::{output, self_update};
 use crate::ui;
 
 #[derive(clap::Args, Debug)]
@@ -21,18 +24,26 @@ impl Update {
 
         tracing::debug!("Using cache directory: {:?}", config.cache_dir);
 
-        // Fetch and store raw formula data
-        match api::fetch_all_formulas().await {
-            Ok(raw_data) => {
-                cache.store_raw("formula.json", &raw_data)?;
-                tracing::debug!("✓ Successfully cached formulas data");
-                pb.set_message("Cached formulas data");
-            }
-            Err(e) => {
-                let err_msg = format!("Failed to fetch/store formulas from API: {e}");
-                tracing::error!("{}", err_msg);
-                pb.finish_and_clear(); // Clear spinner on error
-                return Err(e);
+        // Fetch and store raw formula data. Under the lazy metadata strategy an index that isn't
+        // cached yet is not downloaded; formulae fetched one at a time are dropped instead, so
+        // they are refetched when next needed.
+        if metadata::strategy(config, &cache, 0) == MetadataStrategy::Lazy
+            && !metadata::has_index(&cache)
+        {
+            cache.clear_formulae()?;
+            tracing::debug!("Cleared formulae cached individually (lazy metadata strategy)");
+        } else {
+            match metadata::refresh_formula_index(&cache).await {
+                Ok(_) => {
+                    tracing::debug!("{} Successfully cached formulas data", ui::ok_mark());
+                    pb.set_message("Cached formulas data");
+                }
+                Err(e) => {
+                    let err_msg = format!("Failed to fetch/store formulas from API: {e}");
+                    tracing::error!("{}", err_msg);
+                    pb.finish_and_clear(); // Clear spinner on error
+                    return Err(e);
+                }
             }
         }
 
@@ -40,7 +51,7 @@ impl Update {
         match api::fetch_all_casks().await {
             Ok(raw_data) => {
                 cache.store_raw("cask.json", &raw_data)?;
-                tracing::debug!("✓ Successfully cached casks data");
+                tracing::debug!("{} Successfully cached casks data", ui::ok_mark());
                 pb.set_message("Cached casks data");
             }
             Err(e) => {
@@ -51,6 +62,9 @@ impl Update {
             }
         }
 
+        refresh_advisories(config).await;
+        self_update::refresh_release_check(config).await;
+
         // Update timestamp file
         let timestamp_file = config.cache_dir.join(".sps_last_update_check");
         tracing::debug!(
@@ -71,6 +85,44 @@ impl Update {
         }
 
         pb.finish_with_message("Update completed successfully!");
+
+        // Overrides live outside the cache, so the refreshed index doesn't replace them.
+        let local = overrides::list(config)?;
+        if !local.is_empty() {
+            output::println(format!(
+                "{} local override(s) still take precedence: {}",
+                local.len(),
+                local
+                    .iter()
+                    .map(|entry| entry.name.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ));
+        }
         Ok(())
     }
 }
+
+/// Downloads the advisory feed when one is configured. Failing to get it only warns: the
+/// previously cached copy, if any, stays in use.
+async fn refresh_advisories(config: &Config) {
+    let Some(url) = config
+        .advisory_feed
+        .as_deref()
+        .filter(|_| config.advisories != AdvisoryMode::Off)
+    else {
+        return;
+    };
+    let stored = match api::fetch_advisory_feed(url).await {
+        Ok(json) => advisory::store_index(config, &json),
+        Err(e) => Err(e),
+    };
+    match stored {
+        Ok(index) => tracing::debug!("Cached {} advisories from {}", index.len(), url),
+        Err(e) => tracing::warn!(
+            "Could not update the advisory feed from {}: {}; keeping the cached copy",
+            url,
+            e
+        ),
+    }
+}
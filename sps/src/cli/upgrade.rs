@@ -1,11 +1,16 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
 use std::sync::Arc;
 
 use clap::Args;
 use sps_common::cache::Cache;
+use sps_common::cancel::CancellationToken;
 use sps_common::config::Config;
 use sps_common::error::Result;
-use sps_core::installed;
+use sps_core::{installed, KindHint};
 
+use crate::cli::cleanup::reap_interrupted;
+use crate::cli::output;
 use crate::cli::pipeline::{CommandType, PipelineExecutor, PipelineFlags};
 
 #[derive(Args, Debug)]
@@ -18,25 +23,46 @@ pub struct UpgradeArgs {
 
     #[arg(long)]
     pub build_from_source: bool,
+
+    /// Also rebuild formulae installed with --HEAD whose branch has new upstream commits
+    #[arg(long = "fetch-HEAD")]
+    pub fetch_head: bool,
+
+    /// Stop at the first failed package instead of upgrading the rest
+    #[arg(long)]
+    pub fail_fast: bool,
+
+    /// Also fail packages whose optional or recommended dependency failed, instead of upgrading
+    /// them without it
+    #[arg(long)]
+    pub strict_deps: bool,
+
+    /// Serve live progress as newline-delimited JSON on this Unix socket
+    #[arg(long, value_name = "PATH")]
+    pub status_socket: Option<PathBuf>,
 }
 
 impl UpgradeArgs {
     pub async fn run(&self, config: &Config, cache: Arc<Cache>) -> Result<()> {
+        reap_interrupted(config);
         let targets = if self.all {
-            println!("Checking all installed packages for upgrades...");
+            output::println("Checking all installed packages for upgrades...");
             // Get all installed package names
             let installed = installed::get_installed_packages(config).await?;
             installed.into_iter().map(|p| p.name).collect()
         } else {
-            println!("Checking specified packages for upgrades: {:?}", self.names);
+            output::println(format!(
+                "Checking specified packages for upgrades: {:?}",
+                self.names
+            ));
             self.names.clone()
         };
 
         if targets.is_empty() && !self.all {
-            println!("No packages specified to upgrade.");
+            output::println("No packages specified to upgrade.");
             return Ok(());
         } else if targets.is_empty() && self.all {
-            println!("No packages installed to upgrade.");
+            output::println("No packages installed to upgrade.");
             return Ok(());
         }
 
@@ -48,6 +74,25 @@ impl UpgradeArgs {
             // by reading install receipts.
             include_optional: false,
             skip_recommended: false,
+            only_dependencies: false,
+            kind_hint: KindHint::Any,
+            no_wait: false,
+            ignore_installed: false,
+            only_missing: false,
+            ignore_requirements: false,
+            fail_fast: self.fail_fast,
+            strict_deps: self.strict_deps,
+            dry_run: false,
+            emit_plan: None,
+            graph: None,
+            prune_installed: false,
+            status_socket: self.status_socket.clone(),
+            adopt: false,
+            force: false,
+            cask_definitions: HashMap::new(),
+            head: false,
+            fetch_head: self.fetch_head,
+            cancel: CancellationToken::new(),
             // ... add other common flags if needed ...
         };
 
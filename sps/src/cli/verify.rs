@@ -0,0 +1,196 @@
+//! Contains the logic for the `verify` command.
+
+use std::collections::HashMap;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+use clap::Args;
+use colored::Colorize;
+use indicatif::ProgressBar;
+use sps_common::cache::Cache;
+use sps_common::cancel::CancellationToken;
+use sps_common::config::Config;
+use sps_common::error::{exit_code, Result, SpsError};
+use sps_core::build::formula::integrity::{self, KegFileManifest, KegVerifyReport};
+use sps_core::build::hashing::{HashControl, HashProgress, HashReporter};
+use sps_core::{blocking, installed, InstalledPackageInfo, KindHint, PackageType};
+use tracing::debug;
+
+use crate::cli::pipeline::{CommandType, PipelineExecutor, PipelineFlags};
+use crate::ui;
+
+#[derive(Args, Debug)]
+pub struct Verify {
+    /// Formulae to verify (defaults to all installed formulae)
+    pub names: Vec<String>,
+
+    /// Offer to reinstall formulae whose kegs do not match their manifest
+    #[arg(long)]
+    pub repair: bool,
+}
+
+impl Verify {
+    /// Compares installed kegs against the integrity manifest recorded at pour time.
+    pub async fn run(&self, config: &Config, cache: Arc<Cache>) -> Result<()> {
+        let kegs = self.collect_kegs(config).await?;
+        let mut broken: Vec<String> = Vec::new();
+        let control = HashControl::default();
+        cancel_on_ctrl_c(&control);
+
+        for keg in &kegs {
+            let manifest = match integrity::read_keg_file_manifest(&keg.path)? {
+                Some(m) => m,
+                None => {
+                    println!(
+                        "{} {} {}: no integrity manifest (installed before verify support)",
+                        "?".yellow(),
+                        keg.name.cyan(),
+                        keg.version
+                    );
+                    continue;
+                }
+            };
+            let report = verify_with_progress(keg, manifest, &control).await?;
+            if report.is_clean() {
+                println!("{} {} {}", ui::ok_mark(), keg.name.cyan(), keg.version);
+                continue;
+            }
+            println!("{} {} {}", ui::fail_mark(), keg.name.cyan(), keg.version);
+            for path in &report.missing {
+                println!("    {} {}", "missing: ".red(), path);
+            }
+            for path in &report.modified {
+                println!("    {} {}", "modified:".yellow(), path);
+            }
+            for path in &report.extra {
+                println!("    {} {}", "extra:   ".blue(), path);
+            }
+            for (path, reason) in &report.unreadable {
+                println!("    {} {} ({})", "unreadable:".red(), path, reason);
+            }
+            broken.push(keg.name.clone());
+        }
+        control.cancel.store(true, Ordering::SeqCst);
+
+        if broken.is_empty() {
+            return Ok(());
+        }
+
+        if self.repair {
+            let confirmed = dialoguer::Confirm::new()
+                .with_prompt(format!("Reinstall {}?", broken.join(", ")))
+                .default(true)
+                .interact()
+                .map_err(|e| SpsError::Generic(format!("Failed to read confirmation: {e}")))?;
+            if confirmed {
+                let flags = PipelineFlags {
+                    build_from_source: false,
+                    include_optional: false,
+                    skip_recommended: true,
+                    only_dependencies: false,
+                    kind_hint: KindHint::Any,
+                    no_wait: false,
+                    ignore_installed: false,
+                    only_missing: false,
+                    ignore_requirements: false,
+                    fail_fast: false,
+                    strict_deps: false,
+                    dry_run: false,
+                    emit_plan: None,
+                    graph: None,
+                    prune_installed: false,
+                    status_socket: None,
+                    adopt: false,
+                    force: false,
+                    cask_definitions: HashMap::new(),
+                    head: false,
+                    fetch_head: false,
+                    cancel: CancellationToken::new(),
+                };
+                return PipelineExecutor::execute_pipeline(
+                    &broken,
+                    CommandType::Reinstall,
+                    config,
+                    cache,
+                    &flags,
+                )
+                .await;
+            }
+        }
+
+        Err(SpsError::Generic(format!(
+            "{} keg(s) failed verification: {}",
+            broken.len(),
+            broken.join(", ")
+        )))
+    }
+
+    async fn collect_kegs(&self, config: &Config) -> Result<Vec<InstalledPackageInfo>> {
+        if self.names.is_empty() {
+            let all = installed::get_installed_packages(config).await?;
+            return Ok(all
+                .into_iter()
+                .filter(|p| p.pkg_type == PackageType::Formula)
+                .collect());
+        }
+        let mut kegs = Vec::new();
+        for name in &self.names {
+            match installed::get_installed_package(name, config).await? {
+                Some(info) if info.pkg_type == PackageType::Formula => kegs.push(info),
+                Some(_) => debug!("Skipping cask '{}': verify only supports formulae", name),
+                None => {
+                    return Err(SpsError::NotFound(format!(
+                        "Formula '{name}' is not installed."
+                    )))
+                }
+            }
+        }
+        Ok(kegs)
+    }
+}
+
+/// Sets `control`'s cancel flag on Ctrl-C, so a running scan stops between files and nothing
+/// acts on its partial result. A Ctrl-C that finds the flag already set (a second one, or one
+/// after the caller set it when done scanning) ends the process as it would without a handler.
+pub(crate) fn cancel_on_ctrl_c(control: &HashControl) {
+    let cancel = control.cancel.clone();
+    tokio::spawn(async move {
+        while tokio::signal::ctrl_c().await.is_ok() {
+            if cancel.swap(true, Ordering::SeqCst) {
+                std::process::exit(exit_code::INTERRUPTED);
+            }
+        }
+    });
+}
+
+/// Verifies one keg off the async runtime, showing files hashed and throughput meanwhile.
+async fn verify_with_progress(
+    keg: &InstalledPackageInfo,
+    manifest: KegFileManifest,
+    control: &HashControl,
+) -> Result<KegVerifyReport> {
+    let label = format!("Verifying {} {}", keg.name, keg.version);
+    let spinner = ui::create_spinner(&label);
+    let mut control = control.clone();
+    control.progress = Some(hash_progress_reporter(spinner.clone(), label));
+    let path = keg.path.clone();
+    let report = blocking::run(&keg.name, "verify", move || {
+        integrity::verify_keg(&path, &manifest, &control)
+    })
+    .await;
+    spinner.finish_and_clear();
+    report
+}
+
+/// Shows hashing progress as "<label>: done/total files, MB/s" on `bar`.
+pub(crate) fn hash_progress_reporter(bar: ProgressBar, label: String) -> HashReporter {
+    Arc::new(move |progress: HashProgress| {
+        bar.set_message(format!(
+            "{}: {}/{} files, {:.1} MB/s",
+            label,
+            progress.files_done,
+            progress.files_total,
+            progress.mb_per_sec()
+        ));
+    })
+}
@@ -0,0 +1,127 @@
+//! Contains the logic for the `which` command.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use clap::Args;
+use colored::Colorize;
+use sps_common::cache::Cache;
+use sps_common::config::Config;
+use sps_common::error::{Result, SpsError};
+use sps_common::keg::{InstalledKeg, KegRegistry};
+use tracing::debug;
+
+#[derive(Args, Debug)]
+pub struct Which {
+    /// Name of the executable in the prefix's bin directory
+    pub name: String,
+}
+
+impl Which {
+    /// Maps `<prefix>/bin/<name>` back to the keg currently providing it.
+    pub async fn run(&self, config: &Config, _cache: Arc<Cache>) -> Result<()> {
+        if self.name.is_empty() || self.name.contains('/') {
+            return Err(SpsError::Generic(format!(
+                "Invalid executable name '{}'",
+                self.name
+            )));
+        }
+        let keg_registry = KegRegistry::new(config.clone());
+        let kegs = keg_registry.list_installed_kegs()?;
+        let bin_path = config.bin_dir().join(&self.name);
+
+        let owner = if bin_path.symlink_metadata().is_ok() {
+            let resolved = fs::canonicalize(&bin_path).map_err(|e| {
+                SpsError::Generic(format!(
+                    "Failed to resolve {}: {} (dangling symlink?)",
+                    bin_path.display(),
+                    e
+                ))
+            })?;
+            debug!("{} resolves to {}", bin_path.display(), resolved.display());
+            match owning_keg(&resolved, &kegs, keg_registry.cellar_path()) {
+                Some(keg) => {
+                    println!(
+                        "{} -> {} {}",
+                        bin_path.display(),
+                        keg.name.cyan(),
+                        keg_version_label(keg)
+                    );
+                    println!("  {}", resolved.display());
+                    Some(keg)
+                }
+                None => {
+                    println!(
+                        "{} {}",
+                        bin_path.display(),
+                        format!("not managed by sps, points to {}", resolved.display()).yellow()
+                    );
+                    None
+                }
+            }
+        } else {
+            None
+        };
+
+        let unlinked: Vec<&InstalledKeg> = kegs
+            .iter()
+            .filter(|k| Some(*k) != owner)
+            .filter(|k| {
+                k.path
+                    .join("bin")
+                    .join(&self.name)
+                    .symlink_metadata()
+                    .is_ok()
+            })
+            .collect();
+
+        if owner.is_none() && bin_path.symlink_metadata().is_err() {
+            if unlinked.is_empty() {
+                return Err(SpsError::NotFound(format!(
+                    "No executable named '{}' in {} or any installed keg",
+                    self.name,
+                    config.bin_dir().display()
+                )));
+            }
+            println!(
+                "{} is not linked into {}",
+                self.name,
+                config.bin_dir().display()
+            );
+        }
+
+        if !unlinked.is_empty() {
+            println!("Also provided by (unlinked):");
+            for keg in unlinked {
+                println!("  {} {}", keg.name.cyan(), keg_version_label(keg));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Finds the installed keg containing `resolved`, comparing canonical paths so that symlinked
+/// prefixes (e.g. `/usr/local` -> elsewhere) still match.
+fn owning_keg<'a>(
+    resolved: &Path,
+    kegs: &'a [InstalledKeg],
+    cellar: &Path,
+) -> Option<&'a InstalledKeg> {
+    let cellar = fs::canonicalize(cellar).unwrap_or_else(|_| cellar.to_path_buf());
+    if !resolved.starts_with(&cellar) {
+        return None;
+    }
+    kegs.iter().find(|keg| {
+        let keg_path: PathBuf = fs::canonicalize(&keg.path).unwrap_or_else(|_| keg.path.clone());
+        resolved.starts_with(keg_path)
+    })
+}
+
+/// Version as it appears in the keg directory name, including any revision suffix.
+fn keg_version_label(keg: &InstalledKeg) -> String {
+    keg.path
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_else(|| keg.version.to_string())
+}
@@ -3,19 +3,21 @@
 
 use std::sync::Arc;
 use std::time::{Duration, SystemTime};
-use std::{env, fs, process};
+use std::{fs, process};
 
 use clap::Parser;
 use colored::Colorize;
 use sps_common::cache::Cache;
-use sps_common::config::Config;
+use sps_common::config::{AdvisoryMode, Config, EnvMode};
 use sps_common::error::{Result as spResult, SpsError};
+use sps_common::{homebrew_env, state};
 use tracing::level_filters::LevelFilter;
 use tracing::Level; // Import the Level type
 use tracing_subscriber::fmt::writer::MakeWriterExt;
 use tracing_subscriber::EnvFilter;
 
 mod cli;
+mod table;
 mod ui;
 
 use cli::{CliArgs, Command};
@@ -23,11 +25,47 @@ use cli::{CliArgs, Command};
 #[tokio::main]
 async fn main() -> spResult<()> {
     let cli_args = CliArgs::parse();
+    ui::init(cli_args.color, cli_args.no_emoji);
+    table::init(cli_args.plain);
+    cli::output::init(cli_args.verbose > 0, cli_args.json_lines);
 
     // Initialize config *before* logging setup, as we need the cache path for logs
-    let config =
+    let mut config =
         Config::load().map_err(|e| SpsError::Config(format!("Could not load config: {e}")))?;
 
+    if let Some(download_dir) = &cli_args.download_dir {
+        config.download_dir = download_dir.clone();
+    }
+    if let Some(max_download_size) = cli_args.max_download_size {
+        config.max_download_size = max_download_size;
+    }
+    if let Some(language) = &cli_args.language {
+        config.cask_languages = sps_common::config::parse_language_list(language);
+    }
+    if cli_args.no_hooks {
+        config.hooks = Default::default();
+    }
+    if cli_args.verify_run {
+        config.post_install_check = true;
+    }
+    if cli_args.bottle_only {
+        config.bottle_only = true;
+    }
+    if let Some(bottle_tag) = &cli_args.bottle_tag {
+        config.bottle_tag = Some(bottle_tag.clone());
+    }
+    if cli_args.allow_vulnerable && config.advisories == AdvisoryMode::Block {
+        config.advisories = AdvisoryMode::Warn;
+    }
+    if let Some(bottle_tag) = &config.bottle_tag {
+        sps_core::build::formula::bottle::set_bottle_tag_override(bottle_tag);
+    }
+    match cli_args.env.as_deref() {
+        Some("inherit") => config.env_mode = EnvMode::Inherit,
+        Some("std") => config.env_mode = EnvMode::Std,
+        _ => {}
+    }
+
     // --- Logging Setup ---
     let level_filter = match cli_args.verbose {
         0 => LevelFilter::INFO,
@@ -58,8 +96,8 @@ async fn main() -> spResult<()> {
         // Fallback to stderr logging
         tracing_subscriber::fmt()
             .with_env_filter(env_filter)
-            .with_writer(std::io::stderr)
-            .with_ansi(true)
+            .with_writer(cli::output::StderrWriter)
+            .with_ansi(ui::color_enabled())
             .without_time()
             .init();
     } else {
@@ -70,13 +108,13 @@ async fn main() -> spResult<()> {
 
             // Log DEBUG/TRACE to file, INFO+ still goes to stderr
             // Use the converted Level type here
-            let stderr_writer = std::io::stderr.with_max_level(info_level);
+            let stderr_writer = cli::output::StderrWriter.with_max_level(info_level);
             let file_writer = non_blocking_appender.with_max_level(max_log_level); // Use the calculated Level
 
             tracing_subscriber::fmt()
                 .with_env_filter(env_filter)
                 .with_writer(stderr_writer.and(file_writer)) // Combine writers
-                .with_ansi(true) // Keep ANSI codes for stderr
+                .with_ansi(ui::color_enabled()) // ANSI codes for stderr follow --color
                 .without_time() // Keep time disabled for CLI feel
                 .init();
 
@@ -92,17 +130,25 @@ async fn main() -> spResult<()> {
             // Default: INFO+ to stderr only
             tracing_subscriber::fmt()
                 .with_env_filter(env_filter)
-                .with_writer(std::io::stderr)
-                .with_ansi(true)
+                .with_writer(cli::output::StderrWriter)
+                .with_ansi(ui::color_enabled())
                 .without_time()
                 .init();
         }
     }
     // --- End Logging Setup ---
 
+    // Applied after logging so the cap warning is visible.
+    if let Some(max_concurrent_installs) = cli_args.max_concurrent_installs {
+        config.set_max_concurrent_installs(max_concurrent_installs);
+    }
+    if let Some(max_concurrent_downloads) = cli_args.max_concurrent_downloads {
+        config.set_max_concurrent_downloads(max_concurrent_downloads);
+    }
+
     // Create Cache once and wrap in Arc (after config load)
     let cache = Arc::new(
-        Cache::new(&config.cache_dir)
+        Cache::with_download_dir(&config.cache_dir, &config.download_dir)
             .map_err(|e| SpsError::Cache(format!("Could not initialize cache: {e}")))?,
     );
 
@@ -111,6 +157,14 @@ async fn main() -> spResult<()> {
         Command::Install(_) | Command::Search { .. } | Command::Info { .. }
     );
 
+    // Older state files are brought forward, and newer ones refused, before any command reads
+    // them.
+    if let Err(e) = state::migrate(&config) {
+        tracing::error!("Command failed: {:#}", e);
+        eprintln!("{}: {:#}", "Error".red().bold(), e);
+        process::exit(e.exit_code());
+    }
+
     if needs_update_check {
         if let Err(e) = check_and_run_auto_update(&config, Arc::clone(&cache)).await {
             tracing::error!("Error during auto-update check: {}", e);
@@ -126,7 +180,11 @@ async fn main() -> spResult<()> {
         // Log error using tracing *before* printing to stderr, so it goes to file too if verbose
         tracing::error!("Command failed: {:#}", e);
         eprintln!("{}: {:#}", "Error".red().bold(), e);
-        process::exit(1);
+        process::exit(e.exit_code());
+    }
+
+    if !matches!(cli_args.command, Command::SelfUpdate(_)) {
+        cli::self_update::print_update_hint(&config);
     }
 
     tracing::debug!("Command completed successfully."); // Add success debug log
@@ -136,15 +194,14 @@ async fn main() -> spResult<()> {
 // check_and_run_auto_update function remains the same
 async fn check_and_run_auto_update(config: &Config, cache: Arc<Cache>) -> spResult<()> {
     // 1. Check if auto-update is disabled
-    if env::var("sps_NO_AUTO_UPDATE").is_ok_and(|v| v == "1") {
+    if homebrew_env::var("sps_NO_AUTO_UPDATE").is_some_and(|v| v == "1") {
         tracing::debug!("Auto-update disabled via sps_NO_AUTO_UPDATE=1.");
         return Ok(());
     }
 
     // 2. Determine update interval
     let default_interval_secs: u64 = 86400; // 24 hours
-    let update_interval_secs = env::var("sps_AUTO_UPDATE_SECS")
-        .ok()
+    let update_interval_secs = homebrew_env::var("sps_AUTO_UPDATE_SECS")
         .and_then(|s| s.parse::<u64>().ok())
         .unwrap_or(default_interval_secs);
     let update_interval = Duration::from_secs(update_interval_secs);
@@ -189,12 +246,12 @@ async fn check_and_run_auto_update(config: &Config, cache: Arc<Cache>) -> spResu
 
     // 4. Run update if needed
     if needs_update {
-        println!("Running auto-update..."); // Keep user feedback on stderr
-                                            // Use the existing update command logic
+        cli::output::println("Running auto-update...");
+        // Use the existing update command logic
         match cli::update::Update.run(config, cache).await {
             Ok(_) => {
-                println!("Auto-update successful."); // Keep user feedback on stderr
-                                                     // 5. Update timestamp file on success
+                cli::output::println("Auto-update successful.");
+                // 5. Update timestamp file on success
                 match fs::File::create(&timestamp_file) {
                     Ok(_) => {
                         tracing::debug!("Updated timestamp file: {}", timestamp_file.display());
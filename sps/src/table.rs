@@ -0,0 +1,382 @@
+//! Tables for the listing commands (`search`, `list`, `outdated`, `info`, `stats`).
+//!
+//! On a terminal, columns are padded by display width, so descriptions with CJK characters or
+//! emoji still line up, and right-aligned columns (versions, counts, sizes) line up on their
+//! last digit. When the table is wider than the terminal, the truncatable columns are cut at a
+//! word boundary and end in `…` instead of wrapping. When stdout isn't a terminal, or with
+//! `--plain`, rows are printed tab-separated without a header, padding, truncation or color, so
+//! `cut -f` and `awk -F'\t'` get whole fields.
+
+use std::io::{self, IsTerminal};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use colored::{ColoredString, Colorize};
+use terminal_size::{terminal_size, Width};
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+/// Width assumed when the terminal doesn't report one.
+const DEFAULT_WIDTH: usize = 80;
+/// Narrowest a truncated column is cut to, however little room the others leave.
+const MIN_TRUNCATED_WIDTH: usize = 10;
+/// Spaces between two columns.
+const COLUMN_GAP: usize = 2;
+
+static PLAIN: AtomicBool = AtomicBool::new(false);
+
+/// Records whether `--plain` was given; call once at startup.
+pub fn init(plain: bool) {
+    PLAIN.store(plain, Ordering::Relaxed);
+}
+
+/// Whether tables are printed in the tab-separated form.
+pub fn plain() -> bool {
+    PLAIN.load(Ordering::Relaxed) || !io::stdout().is_terminal()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Align {
+    Left,
+    Right,
+}
+
+/// A column's header and how its cells are laid out.
+#[derive(Debug, Clone)]
+pub struct Column {
+    header: &'static str,
+    align: Align,
+    truncate: bool,
+    style: Option<fn(&str) -> ColoredString>,
+}
+
+impl Column {
+    pub fn left(header: &'static str) -> Self {
+        Self {
+            header,
+            align: Align::Left,
+            truncate: false,
+            style: None,
+        }
+    }
+
+    pub fn right(header: &'static str) -> Self {
+        Self {
+            align: Align::Right,
+            ..Self::left(header)
+        }
+    }
+
+    /// Lets the column be cut to fit the terminal.
+    pub fn truncate(mut self) -> Self {
+        self.truncate = true;
+        self
+    }
+
+    /// Colors the column's cells on a terminal.
+    pub fn style(mut self, style: fn(&str) -> ColoredString) -> Self {
+        self.style = Some(style);
+        self
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Table {
+    columns: Vec<Column>,
+    rows: Vec<Vec<String>>,
+    header: bool,
+}
+
+impl Table {
+    pub fn new(columns: Vec<Column>) -> Self {
+        Self {
+            columns,
+            rows: Vec::new(),
+            header: true,
+        }
+    }
+
+    /// Leaves out the header line, for key/value tables.
+    pub fn without_header(mut self) -> Self {
+        self.header = false;
+        self
+    }
+
+    /// Adds a row; missing cells are empty and extra ones are dropped.
+    pub fn add_row<I, S>(&mut self, cells: I)
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let mut row: Vec<String> = cells
+            .into_iter()
+            .map(Into::into)
+            .take(self.columns.len())
+            .collect();
+        row.resize(self.columns.len(), String::new());
+        self.rows.push(row);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    /// Prints the table to stdout in the form [`plain`] picks.
+    pub fn print(&self) {
+        let width = (!plain()).then(terminal_width);
+        print!("{}", self.render(width));
+    }
+
+    /// The table laid out for a terminal `width` columns wide, or tab-separated when `None`.
+    /// Every line ends in a newline.
+    pub fn render(&self, width: Option<usize>) -> String {
+        match width {
+            Some(width) => self.render_aligned(width),
+            None => self.render_plain(),
+        }
+    }
+
+    fn render_plain(&self) -> String {
+        let mut out = String::new();
+        for row in &self.rows {
+            let fields: Vec<String> = row
+                .iter()
+                .map(|cell| cell.replace(['\t', '\n', '\r'], " "))
+                .collect();
+            out.push_str(&fields.join("\t"));
+            out.push('\n');
+        }
+        out
+    }
+
+    fn render_aligned(&self, width: usize) -> String {
+        let widths = self.column_widths(width);
+        let mut out = String::new();
+        if self.header {
+            let headers: Vec<String> = self.columns.iter().map(|c| c.header.to_string()).collect();
+            self.push_line(&mut out, &headers, &widths, true);
+        }
+        for row in &self.rows {
+            self.push_line(&mut out, row, &widths, false);
+        }
+        out
+    }
+
+    /// The natural width of each column, with the truncatable ones narrowed (widest first)
+    /// until the table fits in `width` or they reach [`MIN_TRUNCATED_WIDTH`].
+    fn column_widths(&self, width: usize) -> Vec<usize> {
+        let mut widths: Vec<usize> = self
+            .columns
+            .iter()
+            .enumerate()
+            .map(|(i, column)| {
+                let header = if self.header {
+                    column.header.width()
+                } else {
+                    0
+                };
+                self.rows
+                    .iter()
+                    .map(|row| row[i].width())
+                    .max()
+                    .unwrap_or(0)
+                    .max(header)
+            })
+            .collect();
+        let gaps = COLUMN_GAP * widths.len().saturating_sub(1);
+        let mut total: usize = widths.iter().sum::<usize>() + gaps;
+        while total > width {
+            let Some(widest) = (0..widths.len())
+                .filter(|&i| self.columns[i].truncate && widths[i] > MIN_TRUNCATED_WIDTH)
+                .max_by_key(|&i| widths[i])
+            else {
+                break;
+            };
+            widths[widest] -= 1;
+            total -= 1;
+        }
+        widths
+    }
+
+    fn push_line(&self, out: &mut String, cells: &[String], widths: &[usize], header: bool) {
+        let mut line = String::new();
+        for (i, (column, cell)) in self.columns.iter().zip(cells).enumerate() {
+            if i > 0 {
+                line.push_str(&" ".repeat(COLUMN_GAP));
+            }
+            let text = if column.truncate {
+                truncate(cell, widths[i])
+            } else {
+                cell.clone()
+            };
+            let padding = " ".repeat(widths[i].saturating_sub(text.width()));
+            let styled = match (header, column.style) {
+                (true, _) => text.bold().to_string(),
+                (false, Some(style)) if !text.is_empty() => style(&text).to_string(),
+                _ => text,
+            };
+            match column.align {
+                Align::Left => {
+                    line.push_str(&styled);
+                    line.push_str(&padding);
+                }
+                Align::Right => {
+                    line.push_str(&padding);
+                    line.push_str(&styled);
+                }
+            }
+        }
+        out.push_str(line.trim_end_matches(' '));
+        out.push('\n');
+    }
+}
+
+/// Shortens `text` to at most `max` columns of display width, ending in `…` when anything was
+/// cut. The cut goes at the last space in reach, so words aren't split; text without one (most
+/// CJK, or a single long word) is cut between characters.
+pub fn truncate(text: &str, max: usize) -> String {
+    if text.width() <= max {
+        return text.to_string();
+    }
+    if max == 0 {
+        return String::new();
+    }
+    let budget = max - 1;
+    let (mut end, mut used) = (0, 0);
+    for (i, ch) in text.char_indices() {
+        let w = ch.width().unwrap_or(0);
+        if used + w > budget {
+            break;
+        }
+        used += w;
+        end = i + ch.len_utf8();
+    }
+    let head = &text[..end];
+    let at_word_end = text[end..].starts_with(char::is_whitespace);
+    let head = match head.rfind(char::is_whitespace) {
+        Some(space) if !at_word_end && !head[..space].trim_end().is_empty() => &head[..space],
+        _ => head,
+    };
+    format!("{}…", head.trim_end())
+}
+
+fn terminal_width() -> usize {
+    terminal_size()
+        .map(|(Width(w), _)| w as usize)
+        .filter(|&w| w > 0)
+        .unwrap_or(DEFAULT_WIDTH)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Three formulae, one described in Japanese with an emoji.
+    fn formulae() -> Table {
+        colored::control::set_override(false);
+        let mut table = Table::new(vec![
+            Column::left("Name"),
+            Column::right("Version"),
+            Column::left("Description").truncate(),
+        ]);
+        table.add_row([
+            "jq",
+            "1.7.1",
+            "Lightweight and flexible command-line JSON processor",
+        ]);
+        table.add_row(["wget", "1.24.5", "Internet file retriever"]);
+        table.add_row(["nkf", "2.1.5", "日本語の文字コード変換 🍺"]);
+        table
+    }
+
+    #[test]
+    fn a_wide_terminal_gets_padded_columns_by_display_width() {
+        assert_eq!(
+            formulae().render(Some(80)),
+            "Name  Version  Description\n\
+             jq      1.7.1  Lightweight and flexible command-line JSON processor\n\
+             wget   1.24.5  Internet file retriever\n\
+             nkf     2.1.5  日本語の文字コード変換 🍺\n"
+        );
+    }
+
+    #[test]
+    fn a_narrow_terminal_truncates_at_words_or_between_wide_characters() {
+        assert_eq!(
+            formulae().render(Some(36)),
+            "Name  Version  Description\n\
+             jq      1.7.1  Lightweight and…\n\
+             wget   1.24.5  Internet file…\n\
+             nkf     2.1.5  日本語の文字コード変…\n"
+        );
+        // However narrow the terminal, a truncated column keeps its minimum width.
+        assert_eq!(
+            formulae().render(Some(10)),
+            "Name  Version  Descripti…\n\
+             jq      1.7.1  Lightweig…\n\
+             wget   1.24.5  Internet…\n\
+             nkf     2.1.5  日本語の…\n"
+        );
+    }
+
+    #[test]
+    fn plain_output_is_tab_separated_without_header() {
+        let mut table = formulae();
+        table.add_row(["tabs", "1.0\t", "line\none", "dropped"]);
+        table.add_row(["short"]);
+
+        assert_eq!(
+            table.render(None),
+            "jq\t1.7.1\tLightweight and flexible command-line JSON processor\n\
+             wget\t1.24.5\tInternet file retriever\n\
+             nkf\t2.1.5\t日本語の文字コード変換 🍺\n\
+             tabs\t1.0 \tline one\n\
+             short\t\t\n"
+        );
+    }
+
+    #[test]
+    fn a_headerless_table_aligns_only_its_rows() {
+        colored::control::set_override(false);
+        let mut table =
+            Table::new(vec![Column::left("Key"), Column::right("Value")]).without_header();
+        table.add_row(["Installed", "12"]);
+        table.add_row(["Size", "1.5 GB"]);
+        table.add_row(["Casks", ""]);
+
+        assert_eq!(
+            table.render(Some(80)),
+            "Installed      12\n\
+             Size       1.5 GB\n\
+             Casks\n"
+        );
+    }
+
+    #[test]
+    fn truncate_counts_display_width() {
+        let cases = [
+            ("exactly eleven", 14, "exactly eleven"),
+            (
+                "Lightweight and flexible command-line",
+                25,
+                "Lightweight and flexible…",
+            ),
+            (
+                "Lightweight and flexible command-line",
+                21,
+                "Lightweight and…",
+            ),
+            ("supercalifragilistic", 8, "superca…"),
+            // Wide characters never straddle the limit; an odd budget leaves a column free.
+            ("日本語の文字", 6, "日本…"),
+            ("日本語の文字", 7, "日本語…"),
+            ("🍺🍺🍺 beer", 5, "🍺🍺…"),
+            ("🍺 beer and more", 9, "🍺 beer…"),
+            ("anything", 1, "…"),
+            ("anything", 0, ""),
+        ];
+        for (text, max, expected) in cases {
+            let truncated = truncate(text, max);
+            assert_eq!(truncated, expected, "{text:?} to {max}");
+            assert!(truncated.width() <= max, "{text:?} to {max}");
+        }
+    }
+}
@@ -1,9 +1,98 @@
-//! UI utility functions for creating common elements like spinners.
+//! UI utility functions for creating common elements like spinners, and the single place that
+//! decides whether output is colored and whether status marks are emoji or ASCII.
 
+use std::env;
+use std::io::IsTerminal;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::Duration;
 
+use clap::ValueEnum;
+use colored::{ColoredString, Colorize};
 use indicatif::{ProgressBar, ProgressStyle};
 
+static COLOR: AtomicBool = AtomicBool::new(true);
+static EMOJI: AtomicBool = AtomicBool::new(true);
+
+/// The `--color` setting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum ColorChoice {
+    /// Color when stdout is a terminal, unless `NO_COLOR` or `CLICOLOR_FORCE` says otherwise
+    #[default]
+    Auto,
+    /// Always color, even when piped
+    Always,
+    /// Never color; also switches status marks to ASCII
+    Never,
+}
+
+/// Resolves the color and emoji settings for the rest of the process. An explicit `--color`
+/// wins; under `auto`, a non-empty `NO_COLOR` disables color, a `CLICOLOR_FORCE` other than `0`
+/// forces it, and otherwise it follows whether stdout is a terminal. Emoji marks are dropped
+/// with `--no-emoji`, `sps_NO_EMOJI`, or whenever color is off.
+pub fn init(choice: ColorChoice, no_emoji: bool) {
+    let color = color_for(
+        choice,
+        |name| env::var(name).ok(),
+        std::io::stdout().is_terminal(),
+    );
+    let no_emoji = no_emoji || env::var("sps_NO_EMOJI").is_ok_and(|v| !v.is_empty());
+    COLOR.store(color, Ordering::Relaxed);
+    EMOJI.store(color && !no_emoji, Ordering::Relaxed);
+    colored::control::set_override(color);
+}
+
+/// Whether to color, given `--color`, a lookup of environment variables and whether stdout is
+/// a terminal.
+fn color_for(
+    choice: ColorChoice,
+    var: impl Fn(&str) -> Option<String>,
+    stdout_is_terminal: bool,
+) -> bool {
+    let env_set = |name: &str| var(name).is_some_and(|v| !v.is_empty());
+    match choice {
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+        ColorChoice::Auto if env_set("NO_COLOR") => false,
+        ColorChoice::Auto if env_set("CLICOLOR_FORCE") => {
+            var("CLICOLOR_FORCE").is_some_and(|v| v != "0")
+        }
+        ColorChoice::Auto => stdout_is_terminal,
+    }
+}
+
+pub fn color_enabled() -> bool {
+    COLOR.load(Ordering::Relaxed)
+}
+
+/// `emoji` when emoji output is on, `plain` otherwise.
+pub fn glyph(emoji: &'static str, plain: &'static str) -> &'static str {
+    if EMOJI.load(Ordering::Relaxed) {
+        emoji
+    } else {
+        plain
+    }
+}
+
+/// Mark for a completed step.
+pub fn ok_mark() -> ColoredString {
+    glyph("✓", "ok").green()
+}
+
+/// Mark for a failed step, also used at the start of error log lines.
+pub fn fail_mark() -> ColoredString {
+    glyph("✖", "x").red()
+}
+
+/// Mark for an upgrade the user did not ask for directly.
+pub fn upgrade_mark() -> ColoredString {
+    glyph("↑", "^").yellow()
+}
+
+/// List bullet.
+pub fn bullet() -> &'static str {
+    glyph("•", "-")
+}
+
 /// Creates and configures a default spinner ProgressBar.
 ///
 /// # Arguments
@@ -15,8 +104,56 @@ use indicatif::{ProgressBar, ProgressStyle};
 /// A configured `ProgressBar` instance ready to be used.
 pub fn create_spinner(message: &str) -> ProgressBar {
     let pb = ProgressBar::new_spinner();
-    pb.set_style(ProgressStyle::with_template("{spinner:.blue.bold} {msg}").unwrap());
+    let template = if color_enabled() {
+        "{spinner:.blue.bold} {msg}"
+    } else {
+        "{spinner} {msg}"
+    };
+    pb.set_style(ProgressStyle::with_template(template).unwrap());
     pb.set_message(message.to_string());
     pb.enable_steady_tick(Duration::from_millis(100)); // Standard tick rate
     pb
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn color(choice: ColorChoice, vars: &[(&str, &str)], terminal: bool) -> bool {
+        let lookup = |name: &str| {
+            vars.iter()
+                .find(|(n, _)| *n == name)
+                .map(|(_, v)| v.to_string())
+        };
+        color_for(choice, lookup, terminal)
+    }
+
+    #[test]
+    fn auto_follows_the_terminal() {
+        assert!(color(ColorChoice::Auto, &[], true));
+        assert!(!color(ColorChoice::Auto, &[], false));
+    }
+
+    #[test]
+    fn no_color_turns_auto_off_and_an_empty_value_is_ignored() {
+        assert!(!color(ColorChoice::Auto, &[("NO_COLOR", "1")], true));
+        assert!(color(ColorChoice::Auto, &[("NO_COLOR", "")], true));
+    }
+
+    #[test]
+    fn clicolor_force_colors_pipes_unless_zero() {
+        assert!(color(ColorChoice::Auto, &[("CLICOLOR_FORCE", "1")], false));
+        assert!(!color(ColorChoice::Auto, &[("CLICOLOR_FORCE", "0")], true));
+        assert!(!color(
+            ColorChoice::Auto,
+            &[("NO_COLOR", "1"), ("CLICOLOR_FORCE", "1")],
+            true
+        ));
+    }
+
+    #[test]
+    fn an_explicit_choice_wins_over_the_environment() {
+        assert!(color(ColorChoice::Always, &[("NO_COLOR", "1")], false));
+        assert!(!color(ColorChoice::Never, &[("CLICOLOR_FORCE", "1")], true));
+    }
+}
@@ -0,0 +1,235 @@
+//! `adopt` takes over the kegs of another installation's Cellar that match the formulary, keeps
+//! what their receipts recorded, and skips the rest with the reason; `--dry-run` only reports.
+//! Adopted kegs are then listed, upgraded and uninstalled like sps's own.
+
+use std::fs;
+use std::os::unix::fs::{symlink, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+use serde_json::{json, Value};
+use sps_testkit::{describe, Fixtures, FormulaFixture, TestEnv};
+use walkdir::WalkDir;
+
+const SPS: &str = env!("CARGO_BIN_EXE_sps");
+
+fn env_with_formulae() -> TestEnv {
+    TestEnv::new(
+        &Fixtures::new()
+            .formula(FormulaFixture::new("jq", "1.7").file("bin/jq", "#!/bin/sh\necho jq 1.7\n"))
+            .formula(FormulaFixture::new("oniguruma", "6.9"))
+            .formula(FormulaFixture::new("hollow", "1.0")),
+    )
+}
+
+/// Writes the keg `<prefix>/Cellar/<name>/<version>` with `files` (executable scripts) and, if
+/// given, `receipt`.
+fn foreign_keg(
+    prefix: &Path,
+    name: &str,
+    version: &str,
+    files: &[&str],
+    receipt: Option<&str>,
+) -> PathBuf {
+    let keg = prefix.join("Cellar").join(name).join(version);
+    fs::create_dir_all(&keg).unwrap();
+    for file in files {
+        let path = keg.join(file);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, format!("#!/bin/sh\necho {name} {version}\n")).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+    }
+    if let Some(receipt) = receipt {
+        fs::write(keg.join("INSTALL_RECEIPT.json"), receipt).unwrap();
+    }
+    keg
+}
+
+fn homebrew_receipt(tap: &str) -> String {
+    json!({
+        "homebrew_version": "4.2.0",
+        "installed_on_request": false,
+        "poured_from_bottle": true,
+        "source": { "tap": tap },
+    })
+    .to_string()
+}
+
+/// A Homebrew prefix with two kegs that match the formulary (`jq` behind it, `oniguruma`
+/// without a receipt) and one of each kind of mismatch.
+fn homebrew_prefix(root: &Path) -> PathBuf {
+    let prefix = root.join("homebrew");
+    let core = homebrew_receipt("homebrew/core");
+    foreign_keg(&prefix, "jq", "1.6", &["bin/jq"], Some(&core));
+    foreign_keg(&prefix, "oniguruma", "6.9", &["lib/libonig.a"], None);
+    foreign_keg(&prefix, "mystery", "1.0", &["bin/mystery"], None);
+    foreign_keg(&prefix, "broken", "2.0", &["bin/broken"], Some("not json"));
+    foreign_keg(&prefix, "hollow", "1.0", &[], Some(&core));
+    let tapped = homebrew_receipt("someone/tools");
+    foreign_keg(&prefix, "tapped", "0.1", &["bin/tapped"], Some(&tapped));
+    prefix
+}
+
+/// Every path under `dir` with the contents of the files, to tell whether anything changed.
+fn tree(dir: &Path) -> Vec<(PathBuf, Option<Vec<u8>>)> {
+    WalkDir::new(dir)
+        .sort_by_file_name()
+        .into_iter()
+        .map(|entry| {
+            let entry = entry.unwrap();
+            (entry.path().to_path_buf(), fs::read(entry.path()).ok())
+        })
+        .collect()
+}
+
+fn receipt(keg: &Path) -> Value {
+    serde_json::from_str(&fs::read_to_string(keg.join("INSTALL_RECEIPT.json")).unwrap()).unwrap()
+}
+
+fn adopt(env: &TestEnv, args: &[&str]) -> String {
+    let output = env.run(SPS, &[&["adopt"], args].concat());
+    assert!(output.status.success(), "{}", describe(&output));
+    String::from_utf8_lossy(&output.stdout).to_string()
+}
+
+const SKIPPED: [&str; 4] = [
+    "Skipped: broken 2.0: INSTALL_RECEIPT.json is corrupt",
+    "Skipped: hollow 1.0: the keg is empty",
+    "Skipped: mystery 1.0: no such formula in the formulary",
+    "Skipped: tapped 0.1: comes from the tap someone/tools; install it with sps instead",
+];
+
+#[test]
+fn a_dry_run_reports_every_keg_and_changes_nothing() {
+    let env = env_with_formulae();
+    let root = tempfile::tempdir().unwrap();
+    let from = homebrew_prefix(root.path());
+    let (foreign, own) = (tree(&from), tree(&env.prefix()));
+
+    let stdout = adopt(&env, &["--from", from.to_str().unwrap(), "--dry-run"]);
+
+    for line in [
+        "Would adopt jq 1.6 (1.7 available)",
+        "Would adopt oniguruma 6.9\n",
+        "Would adopt 2 keg(s); 0 already managed by sps, 4 skipped",
+    ]
+    .iter()
+    .chain(&SKIPPED)
+    {
+        assert!(stdout.contains(line), "{line}\n{stdout}");
+    }
+    assert_eq!(tree(&from), foreign);
+    assert_eq!(tree(&env.prefix()), own);
+}
+
+#[test]
+fn matching_kegs_are_adopted_where_they_are_and_the_rest_skipped() {
+    let env = env_with_formulae();
+    let root = tempfile::tempdir().unwrap();
+    let from = homebrew_prefix(root.path());
+
+    let stdout = adopt(&env, &["--from", from.to_str().unwrap()]);
+
+    assert!(
+        stdout.contains("Adopted 2 keg(s); 0 already managed by sps, 4 skipped"),
+        "{stdout}"
+    );
+    for line in SKIPPED {
+        assert!(stdout.contains(line), "{line}\n{stdout}");
+    }
+    // The kegs stay in the other Cellar; the sps Cellar links to them.
+    let jq = from.join("Cellar/jq/1.6");
+    assert_eq!(fs::read_link(env.keg("jq", "1.6")).unwrap(), jq);
+    assert_eq!(
+        fs::read_link(env.keg("oniguruma", "6.9")).unwrap(),
+        from.join("Cellar/oniguruma/6.9")
+    );
+    for name in ["mystery", "broken", "hollow", "tapped"] {
+        assert!(!env.cellar().join(name).exists(), "{name}");
+    }
+    let jq_receipt = receipt(&jq);
+    assert_eq!(jq_receipt["homebrew_version"], "4.2.0");
+    assert_eq!(jq_receipt["installed_on_request"], false);
+    assert_eq!(jq_receipt["name"], "jq");
+    assert_eq!(jq_receipt["version"], "1.6");
+    assert_eq!(jq_receipt["adopted"]["from"], from.display().to_string());
+    assert_eq!(jq_receipt["adopted"]["linked"], "external");
+    assert_eq!(
+        receipt(&from.join("Cellar/oniguruma/6.9"))["name"],
+        "oniguruma"
+    );
+    // Nothing was linked into the sps prefix.
+    assert!(env.bin("jq").symlink_metadata().is_err());
+
+    let again = adopt(&env, &["--from", from.to_str().unwrap()]);
+    assert!(
+        again.contains("Adopted 0 keg(s); 2 already managed by sps, 4 skipped"),
+        "{again}"
+    );
+
+    let outdated = env.run(SPS, &["outdated"]);
+    let row: Vec<String> = String::from_utf8_lossy(&outdated.stdout)
+        .lines()
+        .find(|line| line.split_whitespace().next() == Some("jq"))
+        .unwrap_or_else(|| panic!("{}", describe(&outdated)))
+        .split_whitespace()
+        .map(str::to_string)
+        .collect();
+    assert_eq!(row, ["jq", "1.6", "1.7"]);
+
+    let output = env.run(SPS, &["uninstall", "jq"]);
+
+    assert!(output.status.success(), "{}", describe(&output));
+    assert!(env.keg("jq", "1.6").symlink_metadata().is_err());
+    assert!(
+        jq.join("bin/jq").is_file(),
+        "the other installation keeps its keg"
+    );
+}
+
+#[test]
+fn link_links_adopted_kegs_into_the_sps_prefix() {
+    let env = env_with_formulae();
+    let root = tempfile::tempdir().unwrap();
+    let from = homebrew_prefix(root.path());
+
+    adopt(&env, &["--from", from.to_str().unwrap(), "--link", "jq"]);
+
+    assert_eq!(
+        fs::read_link(env.prefix().join("opt/jq")).unwrap(),
+        env.keg("jq", "1.6")
+    );
+    let run = std::process::Command::new(env.bin("jq")).output().unwrap();
+    assert_eq!(String::from_utf8_lossy(&run.stdout), "jq 1.6\n");
+    assert_eq!(receipt(&env.keg("jq", "1.6"))["adopted"]["linked"], "sps");
+    assert!(env.keg("oniguruma", "6.9").symlink_metadata().is_err());
+}
+
+#[test]
+fn a_shared_prefix_adopts_in_place_and_uninstall_removes_the_existing_links() {
+    let env = env_with_formulae();
+    let keg = foreign_keg(
+        &env.prefix(),
+        "jq",
+        "1.6",
+        &["bin/jq"],
+        Some(&homebrew_receipt("homebrew/core")),
+    );
+    symlink("../Cellar/jq/1.6/bin/jq", env.bin("jq")).unwrap();
+    symlink("../Cellar/jq/1.6", env.prefix().join("opt/jq")).unwrap();
+
+    let stdout = adopt(&env, &[]);
+
+    assert!(
+        stdout.contains("Adopted jq 1.6 (1.7 available)"),
+        "{stdout}"
+    );
+    assert!(keg.is_dir() && !keg.is_symlink());
+    assert_eq!(receipt(&keg)["adopted"]["linked"], "external");
+
+    let output = env.run(SPS, &["uninstall", "jq"]);
+
+    assert!(output.status.success(), "{}", describe(&output));
+    assert!(!keg.exists());
+    assert!(env.bin("jq").symlink_metadata().is_err());
+    assert!(env.prefix().join("opt/jq").symlink_metadata().is_err());
+}
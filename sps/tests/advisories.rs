@@ -0,0 +1,105 @@
+//! `advisories = block` refuses a formula with a HIGH advisory in the cached feed unless
+//! `--allow-vulnerable` is passed, and a feed that can't be fetched or hasn't been cached only
+//! warns.
+
+use serde_json::json;
+use sps_testkit::{describe, Fixtures, FormulaFixture, Response, TestEnv};
+
+const SPS: &str = env!("CARGO_BIN_EXE_sps");
+const FEED: &str = "/advisories.json";
+
+fn env_with_feed() -> TestEnv {
+    TestEnv::new(&Fixtures::new().formula(FormulaFixture::new("jq", "1.7")))
+}
+
+fn run(env: &TestEnv, args: &[&str]) -> std::process::Output {
+    env.command(SPS)
+        .env("sps_ADVISORIES", "block")
+        .env("sps_ADVISORY_FEED", env.server.url(FEED))
+        .args(args)
+        .output()
+        .expect("run sps")
+}
+
+fn stderr(output: &std::process::Output) -> String {
+    String::from_utf8_lossy(&output.stderr).to_string()
+}
+
+#[test]
+fn a_high_advisory_blocks_the_install_unless_allowed() {
+    let env = env_with_feed();
+    env.server.serve(
+        FEED,
+        Response::json(&json!([{
+            "id": "GHSA-jq-0001",
+            "aliases": ["CVE-2024-0001"],
+            "summary": "Heap overflow in the parser",
+            "database_specific": { "severity": "HIGH" },
+            "affected": [{
+                "package": { "ecosystem": "Homebrew", "name": "jq" },
+                "ranges": [{
+                    "type": "ECOSYSTEM",
+                    "events": [{ "introduced": "0" }, { "fixed": "1.8" }],
+                }],
+            }],
+        }])),
+    );
+    let output = run(&env, &["update"]);
+    assert!(output.status.success(), "{}", describe(&output));
+
+    let blocked = run(&env, &["install", "jq"]);
+
+    assert!(!blocked.status.success(), "{}", describe(&blocked));
+    assert!(
+        stderr(&blocked).contains("advisories = block: jq has"),
+        "{}",
+        describe(&blocked)
+    );
+    assert!(
+        String::from_utf8_lossy(&blocked.stdout).contains("HIGH CVE-2024-0001"),
+        "{}",
+        describe(&blocked)
+    );
+    assert!(!env.keg("jq", "1.7").exists());
+
+    // The cached copy stays in force while the feed is down.
+    env.server.serve(FEED, Response::status(503));
+    let output = run(&env, &["update"]);
+    assert!(output.status.success(), "{}", describe(&output));
+    assert!(
+        stderr(&output).contains("keeping the cached copy"),
+        "{}",
+        describe(&output)
+    );
+    assert!(!run(&env, &["install", "jq"]).status.success());
+
+    let allowed = run(&env, &["install", "--allow-vulnerable", "jq"]);
+
+    assert!(allowed.status.success(), "{}", describe(&allowed));
+    assert!(env.keg("jq", "1.7").is_dir());
+}
+
+#[test]
+fn an_unreachable_feed_only_warns_and_the_install_goes_ahead() {
+    let env = env_with_feed();
+    env.server.serve(FEED, Response::status(503));
+
+    let update = run(&env, &["update"]);
+
+    assert!(update.status.success(), "{}", describe(&update));
+    assert!(
+        stderr(&update).contains("Could not update the advisory feed"),
+        "{}",
+        describe(&update)
+    );
+
+    let install = run(&env, &["install", "jq"]);
+
+    assert!(install.status.success(), "{}", describe(&install));
+    assert!(
+        stderr(&install).contains("No advisory feed cached yet"),
+        "{}",
+        describe(&install)
+    );
+    assert!(env.keg("jq", "1.7").is_dir());
+}
@@ -0,0 +1,75 @@
+//! A dependency declared by an alias of a formula is planned, ordered and given up under the
+//! formula's own name, whether definitions come from the index or one at a time.
+
+use serde_json::json;
+use sps_testkit::{describe, BottleServing, Fixtures, FormulaFixture, TestEnv};
+
+const SPS: &str = env!("CARGO_BIN_EXE_sps");
+
+fn openssl() -> FormulaFixture {
+    FormulaFixture::new("openssl@3", "3.3.1").extra(json!({ "aliases": ["openssl"] }))
+}
+
+/// `app` depends on `openssl`, an alias of `openssl@3`.
+fn fixtures(openssl: FormulaFixture) -> Fixtures {
+    Fixtures::new()
+        .formula(openssl)
+        .formula(FormulaFixture::new("app", "1.0").depends_on(&["openssl"]))
+}
+
+#[test]
+fn a_dependency_declared_by_alias_is_installed_under_its_own_name() {
+    for strategy in ["full", "lazy"] {
+        let env = TestEnv::new(&fixtures(openssl()));
+
+        let output = env
+            .command(SPS)
+            .env("sps_METADATA_STRATEGY", strategy)
+            .args(["install", "app"])
+            .output()
+            .unwrap();
+
+        assert!(output.status.success(), "{strategy}: {}", describe(&output));
+        assert!(
+            String::from_utf8_lossy(&output.stdout).contains("Plan: 2 package(s): openssl@3, app"),
+            "{strategy}: {}",
+            describe(&output)
+        );
+        assert!(env.keg("openssl@3", "3.3.1").is_dir(), "{strategy}");
+        assert!(env.keg("app", "1.0").is_dir(), "{strategy}");
+        assert!(!env.cellar().join("openssl").exists(), "{strategy}");
+    }
+}
+
+#[test]
+fn lazy_loading_finds_an_alias_through_the_index_once() {
+    let env = TestEnv::new(&fixtures(openssl()));
+
+    let output = env
+        .command(SPS)
+        .env("sps_METADATA_STRATEGY", "lazy")
+        .args(["install", "app"])
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "{}", describe(&output));
+    // The alias has no JSON of its own; the index is what maps it.
+    assert_eq!(env.server.hits("/api/formula/openssl.json"), 1);
+    assert_eq!(env.server.hits("/api/formula.json"), 1);
+    assert_eq!(env.server.hits("/api/formula/app.json"), 1);
+}
+
+#[test]
+fn a_failed_dependency_declared_by_alias_gives_up_its_dependent() {
+    let env = TestEnv::new(&fixtures(openssl().bottle(BottleServing::Missing)));
+
+    let output = env.run(SPS, &["install", "app"]);
+
+    assert!(!output.status.success(), "{}", describe(&output));
+    assert!(!env.keg("app", "1.0").exists(), "{}", describe(&output));
+    assert!(
+        describe(&output).contains("openssl@3"),
+        "{}",
+        describe(&output)
+    );
+}
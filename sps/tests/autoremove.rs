@@ -0,0 +1,60 @@
+//! Install reasons recorded in receipts, as `list --why` shows them and `autoremove` uses them.
+
+use sps_testkit::{describe, Fixtures, FormulaFixture, TestEnv};
+
+const SPS: &str = env!("CARGO_BIN_EXE_sps");
+
+fn fixtures() -> Fixtures {
+    Fixtures::new()
+        .formula(FormulaFixture::new("app", "1.0").depends_on(&["lib"]))
+        .formula(FormulaFixture::new("tool", "1.0").depends_on(&["lib"]))
+        .formula(FormulaFixture::new("lib", "1.0"))
+}
+
+fn run_ok(env: &TestEnv, args: &[&str]) -> String {
+    let output = env.run(SPS, args);
+    assert!(output.status.success(), "{}", describe(&output));
+    String::from_utf8_lossy(&output.stdout).to_string()
+}
+
+#[test]
+fn list_why_shows_every_root_that_installed_a_dependency() {
+    let env = TestEnv::new(&fixtures());
+    run_ok(&env, &["install", "app"]);
+    run_ok(&env, &["install", "tool"]);
+
+    let listing = run_ok(&env, &["list", "--why"]);
+
+    let lines: Vec<&str> = listing.lines().collect();
+    assert_eq!(
+        lines,
+        [
+            "app\t1.0\tformula\tinstalled on request",
+            "lib\t1.0\tformula\tinstalled as dependency of: app, tool",
+            "tool\t1.0\tformula\tinstalled on request",
+        ],
+        "{listing}"
+    );
+}
+
+#[test]
+fn autoremove_keeps_a_dependency_while_a_requested_root_remains() {
+    let env = TestEnv::new(&fixtures());
+    run_ok(&env, &["install", "app"]);
+    run_ok(&env, &["install", "tool"]);
+
+    run_ok(&env, &["uninstall", "app"]);
+    run_ok(&env, &["autoremove"]);
+    assert!(
+        env.keg("lib", "1.0").is_dir(),
+        "lib removed while tool needs it"
+    );
+
+    run_ok(&env, &["uninstall", "tool"]);
+    let dry_run = run_ok(&env, &["autoremove", "--dry-run"]);
+    assert!(dry_run.contains("1. lib"), "{dry_run}");
+    assert!(env.keg("lib", "1.0").is_dir());
+
+    run_ok(&env, &["autoremove"]);
+    assert!(!env.keg("lib", "1.0").exists());
+}
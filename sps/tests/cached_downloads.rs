@@ -0,0 +1,103 @@
+//! A cached cask archive that was cut short is evicted and downloaded again, whether its checksum
+//! catches it or, for `no_check` casks, unpacking it fails.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use sps_testkit::{describe, CaskFixture, Fixtures, TestEnv};
+
+const SPS: &str = env!("CARGO_BIN_EXE_sps");
+
+/// Every cached download of `token` under `dir`.
+fn cached_archives(dir: &Path, token: &str) -> Vec<PathBuf> {
+    let mut found = Vec::new();
+    for entry in fs::read_dir(dir).into_iter().flatten().flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            found.extend(cached_archives(&path, token));
+        } else if entry
+            .file_name()
+            .to_string_lossy()
+            .starts_with(&format!("cask-{token}-"))
+        {
+            found.push(path);
+        }
+    }
+    found
+}
+
+/// Installs `cask`, uninstalls it, truncates the cached archive and installs it again. Returns
+/// the output of the second install.
+fn reinstall_from_a_truncated_cache(env: &TestEnv, cask: &CaskFixture) -> std::process::Output {
+    let token = cask.token.as_str();
+    for args in [
+        &["install", "--cask", token],
+        &["uninstall", "--cask", token],
+    ] {
+        let output = env.run(SPS, args);
+        assert!(output.status.success(), "{}", describe(&output));
+    }
+    let cached = cached_archives(&env.cache_dir(), token);
+    assert_eq!(cached.len(), 1, "{cached:?}");
+    let bytes = fs::read(&cached[0]).unwrap();
+    fs::write(&cached[0], &bytes[..bytes.len() / 2]).unwrap();
+
+    let output = env.run(SPS, &["install", "--cask", token]);
+
+    assert!(output.status.success(), "{}", describe(&output));
+    assert_eq!(env.server.hits(&cask.archive_path()), 2);
+    assert_eq!(fs::read(&cached[0]).unwrap(), cask.archive_bytes());
+    let installed = env
+        .prefix()
+        .join("Caskroom")
+        .join(token)
+        .join(&cask.version);
+    assert_eq!(
+        fs::read_to_string(installed.join(token)).unwrap(),
+        format!("#!/bin/sh\necho {token} {}\n", cask.version)
+    );
+    output
+}
+
+#[test]
+fn a_truncated_unverified_archive_is_evicted_when_it_fails_to_unpack() {
+    let cask = CaskFixture::new("viewer", "1.5").without_checksum();
+    let env = TestEnv::new(&Fixtures::new().cask(cask.clone()));
+
+    let output = reinstall_from_a_truncated_cache(&env, &cask);
+
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("is unusable"),
+        "{}",
+        describe(&output)
+    );
+}
+
+#[test]
+fn a_truncated_archive_is_evicted_when_its_checksum_fails() {
+    let cask = CaskFixture::new("viewer", "1.5");
+    let env = TestEnv::new(&Fixtures::new().cask(cask.clone()));
+
+    let output = reinstall_from_a_truncated_cache(&env, &cask);
+
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("failed checksum verification"),
+        "{}",
+        describe(&output)
+    );
+}
+
+#[cfg(target_os = "macos")]
+#[test]
+fn a_truncated_unverified_dmg_is_evicted_when_it_fails_to_mount() {
+    let cask = CaskFixture::new("viewer", "1.5").without_checksum().dmg();
+    let env = TestEnv::new(&Fixtures::new().cask(cask.clone()));
+
+    let output = reinstall_from_a_truncated_cache(&env, &cask);
+
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("is unusable"),
+        "{}",
+        describe(&output)
+    );
+}
@@ -0,0 +1,156 @@
+//! Ctrl-C during an install: the run cancels its downloads and pours, keeps what finished, and
+//! leaves no partial keg or download behind.
+
+use std::process::{Command, Output};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use sps_common::error::exit_code;
+use sps_common::keg;
+use sps_testkit::{describe, Fixtures, FormulaFixture, Response, TestEnv};
+use walkdir::WalkDir;
+
+const SPS: &str = env!("CARGO_BIN_EXE_sps");
+
+/// Waits up to ten seconds for `ready`.
+fn wait_for(what: &str, ready: impl Fn() -> bool) {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while !ready() {
+        assert!(Instant::now() < deadline, "timed out waiting for {what}");
+        thread::sleep(Duration::from_millis(20));
+    }
+}
+
+fn interrupt(pid: u32) {
+    let status = Command::new("kill")
+        .args(["-INT", &pid.to_string()])
+        .status()
+        .unwrap();
+    assert!(status.success());
+}
+
+/// File names in the download cache, by name.
+fn cached_files(env: &TestEnv) -> Vec<String> {
+    let mut names: Vec<String> = WalkDir::new(env.cache_dir())
+        .into_iter()
+        .flatten()
+        .filter(|entry| {
+            entry.file_type().is_file() && entry.path().to_string_lossy().contains("bottle")
+        })
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .collect();
+    names.sort();
+    names
+}
+
+#[test]
+fn ctrl_c_mid_download_keeps_finished_kegs_and_leaves_nothing_partial() {
+    let slow = FormulaFixture::new("slow", "1.0");
+    let env = TestEnv::new(
+        &Fixtures::new()
+            .formula(FormulaFixture::new("quick", "1.0"))
+            .formula(slow.clone()),
+    );
+    // A bottle that takes a minute to arrive, so the run is still downloading it.
+    env.server.serve(
+        &slow.bottle_path(),
+        Response::ok(slow.bottle_bytes()).throttle(16, Duration::from_millis(100)),
+    );
+
+    let child = env
+        .command(SPS)
+        .args(["install", "quick", "slow"])
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    wait_for("quick to be installed and slow to be downloading", || {
+        env.bin("quick").exists() && env.server.hits(&slow.bottle_path()) > 0
+    });
+    let interrupted_at = Instant::now();
+    interrupt(child.id());
+    let output: Output = child.wait_with_output().unwrap();
+
+    assert_eq!(
+        output.status.code(),
+        Some(exit_code::INTERRUPTED),
+        "{}",
+        describe(&output)
+    );
+    assert!(
+        interrupted_at.elapsed() < Duration::from_secs(10),
+        "the download was not cut short"
+    );
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("1 of 2 package(s) installed before the run was cancelled"),
+        "{}",
+        describe(&output)
+    );
+    // The summary gives each package's final state.
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("ok quick"), "{}", describe(&output));
+    assert!(
+        stdout.contains("Cancelled: Download of slow-1.0."),
+        "{}",
+        describe(&output)
+    );
+    assert!(keg::has_valid_receipt(&env.keg("quick", "1.0")));
+    assert!(
+        !env.cellar().join("slow").join("1.0").exists(),
+        "{}",
+        describe(&output)
+    );
+    let cached = cached_files(&env);
+    assert!(
+        !cached.is_empty() && cached.iter().all(|name| name.starts_with("quick-1.0.")),
+        "only quick's bottle is cached: {cached:?}"
+    );
+}
+
+#[test]
+fn a_run_cancelled_before_a_dependency_arrives_installs_nothing_that_needs_it() {
+    let base = FormulaFixture::new("base", "1.0");
+    let env = TestEnv::new(
+        &Fixtures::new()
+            .formula(base.clone())
+            .formula(FormulaFixture::new("app", "1.0").depends_on(&["base"])),
+    );
+    env.server.serve(
+        &base.bottle_path(),
+        Response::ok(base.bottle_bytes()).throttle(16, Duration::from_millis(100)),
+    );
+
+    let child = env
+        .command(SPS)
+        .args(["install", "app"])
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    wait_for("base to be downloading", || {
+        env.server.hits(&base.bottle_path()) > 0
+    });
+    interrupt(child.id());
+    let output = child.wait_with_output().unwrap();
+
+    assert_eq!(
+        output.status.code(),
+        Some(exit_code::INTERRUPTED),
+        "{}",
+        describe(&output)
+    );
+    for name in ["base", "app"] {
+        assert!(
+            !env.cellar().join(name).join("1.0").exists(),
+            "{name}: {}",
+            describe(&output)
+        );
+    }
+    assert!(!env.bin("app").exists());
+    let cached = cached_files(&env);
+    assert!(
+        cached.iter().all(|name| name.starts_with("app-1.0.")),
+        "{cached:?}"
+    );
+}
@@ -0,0 +1,139 @@
+//! Casks that need the user afterwards: an `installer manual:` cask is staged in the Caskroom and
+//! reported as such with the path to open, and a cask's `postflight` steps are printed verbatim
+//! instead of being run.
+
+use std::fs;
+use std::path::Path;
+
+use serde_json::{json, Value};
+use sps_testkit::fixture::{sha256_hex, tarball};
+use sps_testkit::{describe, Fixtures, Response, TestEnv};
+
+const SPS: &str = env!("CARGO_BIN_EXE_sps");
+
+/// Serves `files` as the archive of `token` 1.0 and writes a definition for it with
+/// `artifacts` to `dir`.
+fn local_cask(
+    env: &TestEnv,
+    dir: &Path,
+    token: &str,
+    files: &[(&str, &[u8])],
+    artifacts: Value,
+) -> String {
+    let archive = tarball(files.iter().copied());
+    let path = format!("/casks/{token}-1.0.tar.gz");
+    env.server.serve(&path, Response::ok(archive.clone()));
+    let definition = json!({
+        "token": token,
+        "version": "1.0",
+        "url": env.server.url(&path),
+        "sha256": sha256_hex(&archive),
+        "artifacts": artifacts,
+    });
+    let file = dir.join(format!("{token}.json"));
+    fs::write(&file, definition.to_string()).unwrap();
+    file.display().to_string()
+}
+
+fn manifest(env: &TestEnv, token: &str) -> Value {
+    let path = env
+        .prefix()
+        .join(format!("Caskroom/{token}/1.0/CASK_INSTALL_MANIFEST.json"));
+    serde_json::from_str(&fs::read_to_string(path).unwrap()).unwrap()
+}
+
+#[test]
+fn a_manual_installer_is_staged_with_the_path_to_open() {
+    let env = TestEnv::new(&Fixtures::new());
+    let dir = tempfile::tempdir().unwrap();
+    let definition = local_cask(
+        &env,
+        dir.path(),
+        "setup",
+        &[
+            (
+                "Setup Installer.app/Contents/Info.plist",
+                &b"<plist/>\n"[..],
+            ),
+            (
+                "Setup Installer.app/Contents/MacOS/setup",
+                &b"#!/bin/sh\n"[..],
+            ),
+        ],
+        json!([{ "installer": [{ "manual": "Setup Installer.app" }] }]),
+    );
+
+    let output = env.run(SPS, &["install", "--cask", &definition]);
+
+    assert!(output.status.success(), "{}", describe(&output));
+    let staged = env.prefix().join("Caskroom/setup/1.0/Setup Installer.app");
+    assert!(
+        staged.join("Contents/MacOS/setup").is_file(),
+        "{}",
+        describe(&output)
+    );
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    for expected in [
+        "Staged Cask setup (manual action required)".to_string(),
+        "==> Action required".to_string(),
+        format!(
+            "Open {} and follow its instructions to finish the installation",
+            staged.display()
+        ),
+    ] {
+        assert!(
+            stdout.contains(&expected),
+            "{expected}\n{}",
+            describe(&output)
+        );
+    }
+    assert!(
+        !stdout.contains("Installed Cask setup"),
+        "{}",
+        describe(&output)
+    );
+    assert_eq!(
+        manifest(&env, "setup")["post_install_actions"],
+        json!([{ "open_manual_installer": { "path": staged } }])
+    );
+}
+
+#[test]
+fn postflight_steps_are_printed_verbatim_and_not_run() {
+    let env = TestEnv::new(&Fixtures::new());
+    let dir = tempfile::tempdir().unwrap();
+    let marker = dir.path().join("postflight-ran");
+    let steps = [
+        format!("touch '{}'", marker.display()),
+        "defaults write com.example.tool Setup -bool true".to_string(),
+    ];
+    let definition = local_cask(
+        &env,
+        dir.path(),
+        "tool",
+        &[("tool", &b"#!/bin/sh\necho tool 1.0\n"[..])],
+        json!([{ "binary": ["tool"] }, { "postflight": steps }]),
+    );
+
+    let output = env.run(SPS, &["install", "--cask", &definition]);
+
+    assert!(output.status.success(), "{}", describe(&output));
+    assert!(env.bin("tool").exists(), "{}", describe(&output));
+    assert!(!marker.exists(), "postflight steps must not run");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("Installed Cask tool (action required)"),
+        "{}",
+        describe(&output)
+    );
+    let section = stdout
+        .split("==> Postflight steps (not run by sps)\n")
+        .nth(1)
+        .unwrap_or_else(|| panic!("{}", describe(&output)));
+    let listed: Vec<&str> = section.lines().skip(1).take(2).map(str::trim).collect();
+    assert_eq!(listed, steps);
+    assert_eq!(
+        manifest(&env, "tool")["post_install_actions"],
+        json!([{ "review_postflight": { "commands": steps } }])
+    );
+}
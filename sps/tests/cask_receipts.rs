@@ -0,0 +1,160 @@
+//! The receipt in each Caskroom version directory records what was installed and where from,
+//! and a broken receipt is warned about rather than fatal.
+
+use std::fs;
+
+use serde_json::Value;
+use sps_core::build::formula::host_platform;
+use sps_testkit::fixture::sha256_hex;
+use sps_testkit::{describe, CaskFixture, Fixtures, TestEnv};
+
+const SPS: &str = env!("CARGO_BIN_EXE_sps");
+const RECEIPT: &str = "CASK_INSTALL_MANIFEST.json";
+
+fn installed_viewer() -> (TestEnv, CaskFixture) {
+    let cask = CaskFixture::new("viewer", "1.5");
+    let env = TestEnv::new(&Fixtures::new().cask(cask.clone()));
+    let output = env.run(SPS, &["install", "--cask", "viewer"]);
+    assert!(output.status.success(), "{}", describe(&output));
+    (env, cask)
+}
+
+#[test]
+fn an_install_records_its_source_checksum_and_artifacts() {
+    let (env, cask) = installed_viewer();
+    let version_dir = env.prefix().join("Caskroom/viewer/1.5");
+
+    let receipt: Value =
+        serde_json::from_str(&fs::read_to_string(version_dir.join(RECEIPT)).unwrap()).unwrap();
+
+    assert_eq!(receipt["token"], "viewer");
+    assert_eq!(receipt["version"], "1.5");
+    assert_eq!(receipt["source_url"], env.server.url(&cask.archive_path()));
+    assert_eq!(receipt["sha256"], sha256_hex(&cask.archive_bytes()));
+    assert!(receipt["sps_version"]
+        .as_str()
+        .is_some_and(|v| !v.is_empty()));
+    assert!(receipt["installed_at"].as_u64().is_some_and(|t| t > 0));
+    assert_eq!(
+        receipt["artifacts"],
+        serde_json::json!([{
+            "type": "binary_link",
+            "link_path": env.bin("viewer"),
+            "target_path": version_dir.join("viewer"),
+        }])
+    );
+    // Written to a temporary name and renamed into place.
+    let names: Vec<String> = fs::read_dir(&version_dir)
+        .unwrap()
+        .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+        .collect();
+    assert!(!names.iter().any(|n| n.ends_with(".tmp")), "{names:?}");
+}
+
+#[test]
+fn info_shows_the_receipt_of_the_installed_version() {
+    let (env, cask) = installed_viewer();
+
+    let output = env.run(SPS, &["info", "--cask", "viewer"]);
+
+    assert!(output.status.success(), "{}", describe(&output));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains(&format!("From {}", env.server.url(&cask.archive_path()))),
+        "{stdout}"
+    );
+    assert!(
+        stdout.contains(&format!("SHA256 {}", sha256_hex(&cask.archive_bytes()))),
+        "{stdout}"
+    );
+    assert!(
+        stdout.contains(&format!("Binary {}", env.bin("viewer").display())),
+        "{stdout}"
+    );
+}
+
+#[test]
+fn a_malformed_receipt_is_a_warning_for_info_and_uninstall() {
+    let (env, _) = installed_viewer();
+    let version_dir = env.prefix().join("Caskroom/viewer/1.5");
+    fs::write(version_dir.join(RECEIPT), "{").unwrap();
+
+    let info = env.run(SPS, &["info", "--cask", "viewer"]);
+    let uninstall = env.run(SPS, &["uninstall", "--cask", "viewer"]);
+
+    assert!(info.status.success(), "{}", describe(&info));
+    assert!(
+        String::from_utf8_lossy(&info.stderr).contains("Ignoring malformed cask receipt"),
+        "{}",
+        describe(&info)
+    );
+    assert!(uninstall.status.success(), "{}", describe(&uninstall));
+    assert!(!version_dir.exists(), "{}", describe(&uninstall));
+}
+
+/// `viewer` 1.5, with 2.0 published for this host's platform and a German archive.
+fn viewer_with_variations() -> (TestEnv, CaskFixture) {
+    let cask = CaskFixture::new("viewer", "1.5")
+        .platform_variation("an_unrelated_platform", "0.9")
+        .platform_variation(host_platform(), "2.0")
+        .language("de");
+    let env = TestEnv::new(&Fixtures::new().cask(cask.clone()));
+    (env, cask)
+}
+
+#[test]
+fn the_platform_and_language_variations_chosen_land_in_the_receipt() {
+    let (env, cask) = viewer_with_variations();
+
+    let output = env
+        .command(SPS)
+        .args(["install", "--cask", "viewer"])
+        .env("sps_LANGUAGE", "de-AT,en")
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "{}", describe(&output));
+    let version_dir = env.prefix().join("Caskroom/viewer/2.0");
+    let receipt: Value =
+        serde_json::from_str(&fs::read_to_string(version_dir.join(RECEIPT)).unwrap()).unwrap();
+    assert_eq!(receipt["version"], "2.0");
+    assert_eq!(
+        receipt["variant"],
+        serde_json::json!({ "platform": host_platform(), "language": "de" })
+    );
+    assert_eq!(
+        receipt["source_url"],
+        env.server.url(&cask.variant_archive_path("de"))
+    );
+    assert_eq!(
+        fs::read_to_string(version_dir.join("viewer")).unwrap(),
+        "#!/bin/sh\necho viewer de\n"
+    );
+    assert_eq!(env.server.hits(&cask.archive_path()), 0);
+    assert_eq!(env.server.hits(&cask.variant_archive_path("0.9")), 0);
+}
+
+#[test]
+fn without_a_matching_language_the_platform_variation_alone_is_recorded() {
+    let (env, cask) = viewer_with_variations();
+
+    let output = env
+        .command(SPS)
+        .args(["install", "--cask", "viewer"])
+        .env("sps_LANGUAGE", "fr")
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "{}", describe(&output));
+    let version_dir = env.prefix().join("Caskroom/viewer/2.0");
+    let receipt: Value =
+        serde_json::from_str(&fs::read_to_string(version_dir.join(RECEIPT)).unwrap()).unwrap();
+    assert_eq!(
+        receipt["variant"],
+        serde_json::json!({ "platform": host_platform() })
+    );
+    assert_eq!(
+        receipt["source_url"],
+        env.server.url(&cask.variant_archive_path("2.0"))
+    );
+}
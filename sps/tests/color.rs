@@ -0,0 +1,63 @@
+//! `--color`, `NO_COLOR` and `CLICOLOR_FORCE` on a real install, whose output is piped.
+
+use std::process::Output;
+
+use sps_testkit::{describe, Fixtures, FormulaFixture, TestEnv};
+
+const SPS: &str = env!("CARGO_BIN_EXE_sps");
+
+fn install(env_vars: &[(&str, &str)], args: &[&str]) -> Output {
+    let env = TestEnv::new(&Fixtures::new().formula(FormulaFixture::new("hello", "1.0")));
+    let mut command = env.command(SPS);
+    command.env_remove("NO_COLOR");
+    for (name, value) in env_vars {
+        command.env(name, value);
+    }
+    let output = command
+        .args(args)
+        .args(["install", "hello"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", describe(&output));
+    output
+}
+
+fn has_ansi_escapes(output: &Output) -> bool {
+    output.stdout.contains(&0x1b) || output.stderr.contains(&0x1b)
+}
+
+#[test]
+fn piped_output_is_plain_by_default() {
+    let output = install(&[], &[]);
+
+    assert!(!has_ansi_escapes(&output), "{}", describe(&output));
+}
+
+#[test]
+fn color_never_and_no_color_keep_forced_color_off() {
+    for (vars, args) in [
+        (&[("CLICOLOR_FORCE", "1")][..], &["--color", "never"][..]),
+        (&[("NO_COLOR", "1"), ("CLICOLOR_FORCE", "1")][..], &[][..]),
+    ] {
+        let output = install(vars, args);
+
+        assert!(!has_ansi_escapes(&output), "{}", describe(&output));
+        assert!(
+            !String::from_utf8_lossy(&output.stdout).contains('✓'),
+            "{}",
+            describe(&output)
+        );
+    }
+}
+
+#[test]
+fn color_can_be_forced_onto_a_pipe() {
+    for (vars, args) in [
+        (&[("CLICOLOR_FORCE", "1")][..], &[][..]),
+        (&[("NO_COLOR", "1")][..], &["--color", "always"][..]),
+    ] {
+        let output = install(vars, args);
+
+        assert!(has_ansi_escapes(&output), "{}", describe(&output));
+    }
+}
@@ -0,0 +1,74 @@
+//! `--download-dir` moves downloaded artifacts out of the cache directory, and a move away from
+//! a location that still holds artifacts is pointed out.
+
+use std::path::Path;
+use std::process::Output;
+
+use sps_testkit::{describe, Fixtures, FormulaFixture, TestEnv};
+
+const SPS: &str = env!("CARGO_BIN_EXE_sps");
+
+const RELOCATION_HINT: &str = "Download directory changed";
+
+fn run_with_download_dir(env: &TestEnv, dir: &Path, args: &[&str]) -> Output {
+    env.run(
+        SPS,
+        &[&["--download-dir", dir.to_str().unwrap()], args].concat(),
+    )
+}
+
+#[test]
+fn bottles_are_downloaded_into_the_download_dir() {
+    let env = TestEnv::new(&Fixtures::new().formula(FormulaFixture::new("hello", "1.0")));
+    let downloads = tempfile::tempdir().unwrap();
+
+    let output = run_with_download_dir(&env, downloads.path(), &["install", "hello"]);
+
+    assert!(output.status.success(), "{}", describe(&output));
+    let bottles: Vec<_> = std::fs::read_dir(downloads.path().join("bottles"))
+        .unwrap()
+        .flatten()
+        .map(|e| e.file_name().to_string_lossy().to_string())
+        .filter(|name| name.starts_with("hello"))
+        .collect();
+    assert!(!bottles.is_empty(), "{}", describe(&output));
+    assert!(!env.cache_dir().join("bottles").exists());
+
+    let paths = run_with_download_dir(&env, downloads.path(), &["cache", "path"]);
+    let stdout = String::from_utf8_lossy(&paths.stdout);
+    assert!(
+        stdout.contains(&format!("API metadata:     {}", env.cache_dir().display())),
+        "{stdout}"
+    );
+    assert!(
+        stdout.contains(&format!(
+            "Bottles:          {}",
+            downloads.path().join("bottles").display()
+        )),
+        "{stdout}"
+    );
+}
+
+#[test]
+fn moving_away_from_downloaded_artifacts_prints_a_hint_once() {
+    let env = TestEnv::new(&Fixtures::new().formula(FormulaFixture::new("hello", "1.0")));
+    let installed = env.run(SPS, &["install", "hello"]);
+    assert!(installed.status.success(), "{}", describe(&installed));
+    let downloads = tempfile::tempdir().unwrap();
+
+    let moved = run_with_download_dir(&env, downloads.path(), &["cache", "path"]);
+    let again = run_with_download_dir(&env, downloads.path(), &["cache", "path"]);
+
+    let stderr = String::from_utf8_lossy(&moved.stderr);
+    assert!(stderr.contains(RELOCATION_HINT), "{}", describe(&moved));
+    assert!(
+        stderr.contains(&env.cache_dir().display().to_string()),
+        "{}",
+        describe(&moved)
+    );
+    assert!(
+        !String::from_utf8_lossy(&again.stderr).contains(RELOCATION_HINT),
+        "{}",
+        describe(&again)
+    );
+}
@@ -0,0 +1,104 @@
+//! Exit codes of failed installs, which scripts use to tell retryable failures from others.
+
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+
+use sps_common::error::exit_code;
+use sps_testkit::{describe, BottleServing, Fixtures, FormulaFixture, TestEnv};
+
+const SPS: &str = env!("CARGO_BIN_EXE_sps");
+
+#[test]
+fn an_unknown_name_exits_not_found() {
+    let env = TestEnv::new(&Fixtures::new().formula(FormulaFixture::new("hello", "1.0")));
+
+    let output = env.run(SPS, &["install", "no-such-formula"]);
+
+    assert_eq!(
+        output.status.code(),
+        Some(exit_code::NOT_FOUND),
+        "{}",
+        describe(&output)
+    );
+}
+
+#[test]
+fn a_bottle_answering_404_exits_network() {
+    let env = TestEnv::new(
+        &Fixtures::new().formula(FormulaFixture::new("gone", "1.0").bottle(BottleServing::Missing)),
+    );
+
+    let output = env.run(SPS, &["install", "gone"]);
+
+    assert_eq!(
+        output.status.code(),
+        Some(exit_code::NETWORK),
+        "{}",
+        describe(&output)
+    );
+    assert!(!env.keg("gone", "1.0").exists());
+}
+
+#[test]
+fn a_bottle_not_matching_its_checksum_exits_checksum() {
+    let env = TestEnv::new(
+        &Fixtures::new()
+            .formula(FormulaFixture::new("bad", "1.0").bottle(BottleServing::ChecksumMismatch)),
+    );
+
+    let output = env.run(SPS, &["install", "bad"]);
+
+    assert_eq!(
+        output.status.code(),
+        Some(exit_code::CHECKSUM),
+        "{}",
+        describe(&output)
+    );
+}
+
+#[test]
+fn failures_of_different_kinds_exit_mixed() {
+    let env = TestEnv::new(
+        &Fixtures::new()
+            .formula(FormulaFixture::new("gone", "1.0").bottle(BottleServing::Missing))
+            .formula(FormulaFixture::new("bad", "1.0").bottle(BottleServing::ChecksumMismatch)),
+    );
+
+    let output = env.run(SPS, &["install", "gone", "bad"]);
+
+    assert_eq!(
+        output.status.code(),
+        Some(exit_code::MIXED_FAILURES),
+        "{}",
+        describe(&output)
+    );
+}
+
+#[test]
+fn a_read_only_prefix_exits_permission_before_any_download() {
+    let fixtures = Fixtures::new().formula(FormulaFixture::new("hello", "1.0"));
+    let env = TestEnv::new(&fixtures);
+    for dir in ["Cellar", "Caskroom", "opt", "bin"] {
+        fs::set_permissions(env.prefix().join(dir), fs::Permissions::from_mode(0o555)).unwrap();
+    }
+    if fs::write(env.cellar().join(".probe"), "").is_ok() {
+        // Permission bits don't bind this user (root); there's nothing to check.
+        return;
+    }
+
+    let output = env.run(SPS, &["install", "hello"]);
+
+    assert_eq!(
+        output.status.code(),
+        Some(exit_code::PERMISSION),
+        "{}",
+        describe(&output)
+    );
+    let message = describe(&output);
+    assert!(
+        message.contains(&env.cellar().display().to_string()),
+        "{message}"
+    );
+    assert!(message.contains("--prefix"), "{message}");
+    assert_eq!(env.server.hits(&fixtures.formulae[0].bottle_path()), 0);
+}
@@ -0,0 +1,327 @@
+//! `deps --graph` and `install --dry-run --graph` on a diamond: the DOT output is checked
+//! against the DOT grammar and must carry the diamond's edges, stably across runs.
+
+use std::collections::BTreeMap;
+
+use sps_testkit::{describe, Fixtures, FormulaFixture, TestEnv};
+
+const SPS: &str = env!("CARGO_BIN_EXE_sps");
+
+/// app -> lib -> base and app -> tool -> base, with lib also needing zlib.
+fn diamond() -> Fixtures {
+    Fixtures::new()
+        .formula(FormulaFixture::new("app", "1.0").depends_on(&["lib", "tool"]))
+        .formula(FormulaFixture::new("lib", "2.0").depends_on(&["base", "zlib"]))
+        .formula(FormulaFixture::new("tool", "3.0").depends_on(&["base"]))
+        .formula(FormulaFixture::new("base", "4.0"))
+        .formula(FormulaFixture::new("zlib", "1.3"))
+}
+
+type Attrs = BTreeMap<String, String>;
+
+/// A DOT graph as parsed by [`parse_dot`].
+#[derive(Debug, Default)]
+struct Dot {
+    nodes: BTreeMap<String, Attrs>,
+    edges: BTreeMap<(String, String), Attrs>,
+}
+
+impl Dot {
+    fn edge_list(&self) -> Vec<(&str, &str)> {
+        self.edges
+            .keys()
+            .map(|(from, to)| (from.as_str(), to.as_str()))
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Id(String),
+    Arrow,
+    Punct(char),
+}
+
+fn tokenize(input: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = input.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '{' | '}' | '[' | ']' | '=' | ',' | ';' => {
+                tokens.push(Token::Punct(c));
+                chars.next();
+            }
+            '-' => {
+                chars.next();
+                match chars.next() {
+                    Some('>') => tokens.push(Token::Arrow),
+                    other => return Err(format!("expected `->`, got `-{other:?}`")),
+                }
+            }
+            '"' => {
+                chars.next();
+                let mut s = String::new();
+                loop {
+                    match chars.next() {
+                        Some('\\') => match chars.next() {
+                            Some('"') => s.push('"'),
+                            Some(other) => {
+                                s.push('\\');
+                                s.push(other);
+                            }
+                            None => return Err("unterminated escape".into()),
+                        },
+                        Some('"') => break,
+                        Some(other) => s.push(other),
+                        None => return Err("unterminated string".into()),
+                    }
+                }
+                tokens.push(Token::Id(s));
+            }
+            c if c.is_ascii_alphanumeric() || c == '_' || c == '.' => {
+                let mut s = String::new();
+                while let Some(&c) = chars.peek() {
+                    if !(c.is_ascii_alphanumeric() || c == '_' || c == '.') {
+                        break;
+                    }
+                    s.push(c);
+                    chars.next();
+                }
+                let numeral = s.chars().all(|c| c.is_ascii_digit() || c == '.');
+                if !numeral && s.starts_with(|c: char| c.is_ascii_digit()) {
+                    return Err(format!("identifier starts with a digit: {s}"));
+                }
+                tokens.push(Token::Id(s));
+            }
+            other => return Err(format!("unexpected character {other:?}")),
+        }
+    }
+    Ok(tokens)
+}
+
+/// A recursive-descent checker for the DOT grammar (graphviz.org/doc/info/lang.html), without
+/// subgraphs and ports, which the output never uses. Returns the nodes and edges it declares.
+fn parse_dot(input: &str) -> Result<Dot, String> {
+    let tokens = tokenize(input)?;
+    let mut pos = 0;
+    let next = |pos: &mut usize| {
+        let token = tokens.get(*pos).cloned();
+        *pos += 1;
+        token
+    };
+    let expect = |pos: &mut usize, want: Token| match next(pos) {
+        Some(token) if token == want => Ok(()),
+        other => Err(format!("expected {want:?}, got {other:?}")),
+    };
+    let keyword = |token: &Option<Token>, word: &str| matches!(token, Some(Token::Id(id)) if id.eq_ignore_ascii_case(word));
+
+    let mut first = next(&mut pos);
+    if keyword(&first, "strict") {
+        first = next(&mut pos);
+    }
+    if !keyword(&first, "digraph") {
+        return Err(format!("expected `digraph`, got {first:?}"));
+    }
+    if let Some(Token::Id(_)) = tokens.get(pos) {
+        pos += 1;
+    }
+    expect(&mut pos, Token::Punct('{'))?;
+
+    let attr_list = |pos: &mut usize| -> Result<Attrs, String> {
+        let mut attrs = Attrs::new();
+        while tokens.get(*pos) == Some(&Token::Punct('[')) {
+            *pos += 1;
+            loop {
+                match next(pos) {
+                    Some(Token::Punct(']')) => break,
+                    Some(Token::Id(key)) => {
+                        expect(pos, Token::Punct('='))?;
+                        let Some(Token::Id(value)) = next(pos) else {
+                            return Err(format!("attribute {key} has no value"));
+                        };
+                        attrs.insert(key, value);
+                        if let Some(Token::Punct(',' | ';')) = tokens.get(*pos) {
+                            *pos += 1;
+                        }
+                    }
+                    other => return Err(format!("bad attribute list at {other:?}")),
+                }
+            }
+        }
+        Ok(attrs)
+    };
+
+    let mut dot = Dot::default();
+    loop {
+        match next(&mut pos) {
+            Some(Token::Punct('}')) => break,
+            Some(Token::Punct(';')) => {}
+            Some(Token::Id(id)) if ["graph", "node", "edge"].contains(&id.as_str()) => {
+                if tokens.get(pos) != Some(&Token::Punct('[')) {
+                    return Err(format!("`{id}` without attributes"));
+                }
+                attr_list(&mut pos)?;
+            }
+            Some(Token::Id(id)) => match tokens.get(pos) {
+                Some(Token::Punct('=')) => {
+                    pos += 1;
+                    let Some(Token::Id(_)) = next(&mut pos) else {
+                        return Err(format!("`{id} =` without a value"));
+                    };
+                }
+                Some(Token::Arrow) => {
+                    let mut chain = vec![id];
+                    while tokens.get(pos) == Some(&Token::Arrow) {
+                        pos += 1;
+                        let Some(Token::Id(to)) = next(&mut pos) else {
+                            return Err("`->` without a target".into());
+                        };
+                        chain.push(to);
+                    }
+                    let attrs = attr_list(&mut pos)?;
+                    for pair in chain.windows(2) {
+                        dot.edges
+                            .insert((pair[0].clone(), pair[1].clone()), attrs.clone());
+                    }
+                }
+                _ => {
+                    let attrs = attr_list(&mut pos)?;
+                    dot.nodes.insert(id, attrs);
+                }
+            },
+            other => return Err(format!("unexpected {other:?}")),
+        }
+    }
+    if pos != tokens.len() {
+        return Err(format!(
+            "trailing input after the graph: {:?}",
+            &tokens[pos..]
+        ));
+    }
+    for (from, to) in dot.edges.keys() {
+        if !dot.nodes.contains_key(from) || !dot.nodes.contains_key(to) {
+            return Err(format!("edge {from} -> {to} between undeclared nodes"));
+        }
+    }
+    Ok(dot)
+}
+
+fn graph(env: &TestEnv, args: &[&str]) -> String {
+    let output = env.run(SPS, args);
+    assert!(output.status.success(), "{}", describe(&output));
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn the_checker_rejects_what_is_not_dot() {
+    for bad in [
+        "graph {",
+        "digraph { \"a\" -> }",
+        "digraph { \"a\" [label] }",
+        "digraph { \"a\" -> \"b\" }",
+        "digraph { \"a\"; } extra",
+        "flowchart TD",
+    ] {
+        assert!(parse_dot(bad).is_err(), "{bad}");
+    }
+    assert!(parse_dot("strict digraph g { a; b [x=1]; a -> b -> a; }").is_ok());
+}
+
+#[test]
+fn deps_graph_dot_round_trips_the_diamond() {
+    let env = TestEnv::new(&diamond());
+
+    let output = graph(&env, &["deps", "--graph", "dot", "app"]);
+
+    let dot = parse_dot(&output).unwrap_or_else(|e| panic!("{e}\n{output}"));
+    assert_eq!(
+        dot.edge_list(),
+        [
+            ("app", "lib"),
+            ("app", "tool"),
+            ("lib", "base"),
+            ("lib", "zlib"),
+            ("tool", "base"),
+        ]
+    );
+    assert!(dot.edges.values().all(|attrs| attrs["label"] == "runtime"));
+    let labels: Vec<&str> = dot.nodes.values().map(|a| a["label"].as_str()).collect();
+    assert_eq!(
+        labels,
+        ["app@1.0", "base@4.0", "lib@2.0", "tool@3.0", "zlib@1.3"]
+    );
+    // Nothing is installed yet: every node is to be installed (blue).
+    assert!(dot
+        .nodes
+        .values()
+        .all(|attrs| attrs["fillcolor"] == "#1565c0"));
+    // Byte-for-byte the same on a second run.
+    assert_eq!(graph(&env, &["deps", "--graph", "dot", "app"]), output);
+}
+
+#[test]
+fn installed_nodes_are_green_and_pruning_drops_their_subtrees() {
+    let env = TestEnv::new(&diamond());
+    let install = env.run(SPS, &["install", "lib"]);
+    assert!(install.status.success(), "{}", describe(&install));
+
+    let full = parse_dot(&graph(&env, &["deps", "--graph", "dot", "app"])).unwrap();
+    let pruned = parse_dot(&graph(
+        &env,
+        &["deps", "--graph", "dot", "--prune-installed", "app"],
+    ))
+    .unwrap();
+
+    for name in ["lib", "base", "zlib"] {
+        assert_eq!(full.nodes[name]["fillcolor"], "#2e7d32", "{name}");
+    }
+    // lib stays as a leaf; zlib is only reachable through it. base is still needed by tool.
+    assert_eq!(
+        pruned.edge_list(),
+        [("app", "lib"), ("app", "tool"), ("tool", "base")]
+    );
+    assert!(!pruned.nodes.contains_key("zlib"));
+}
+
+#[test]
+fn a_missing_dependency_is_a_red_node() {
+    let fixtures =
+        Fixtures::new().formula(FormulaFixture::new("app", "1.0").depends_on(&["nowhere"]));
+    let env = TestEnv::new(&fixtures);
+
+    let output = env.run(SPS, &["deps", "--graph", "dot", "app"]);
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let dot = parse_dot(&stdout).unwrap_or_else(|e| panic!("{e}\n{}", describe(&output)));
+    assert_eq!(dot.nodes["nowhere"]["fillcolor"], "#c62828", "{stdout}");
+}
+
+#[test]
+fn install_dry_run_prints_the_same_graph_and_installs_nothing() {
+    let env = TestEnv::new(&diamond());
+
+    let output = graph(&env, &["install", "--dry-run", "--graph", "dot", "app"]);
+
+    let dot = parse_dot(&output).unwrap_or_else(|e| panic!("{e}\n{output}"));
+    assert_eq!(dot.edges.len(), 5, "{output}");
+    assert!(!env.keg("app", "1.0").exists());
+}
+
+#[test]
+fn mermaid_output_has_a_node_per_formula_and_an_edge_per_dependency() {
+    let env = TestEnv::new(&diamond());
+
+    let output = graph(&env, &["deps", "--graph", "mermaid", "app"]);
+
+    let mut lines = output.lines();
+    assert_eq!(lines.next(), Some("flowchart TD"));
+    let lines: Vec<&str> = lines.collect();
+    assert_eq!(lines.iter().filter(|l| l.contains(":::")).count(), 5);
+    assert_eq!(
+        lines.iter().filter(|l| l.contains("-->|runtime|")).count(),
+        5
+    );
+}
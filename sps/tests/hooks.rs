@@ -0,0 +1,125 @@
+//! The `[hooks]` commands run once per formula, dependencies first, with the formula's name,
+//! version, keg and opt path in the environment, and not at all under `--no-hooks`.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde_json::json;
+use sps_testkit::{describe, Fixtures, FormulaFixture, TestEnv};
+
+const SPS: &str = env!("CARGO_BIN_EXE_sps");
+
+/// `app` 2.0, depending on `lib` 1.7.
+fn env_with_app() -> TestEnv {
+    TestEnv::new(
+        &Fixtures::new()
+            .formula(FormulaFixture::new("lib", "1.7"))
+            .formula(FormulaFixture::new("app", "2.0").depends_on(&["lib"])),
+    )
+}
+
+/// A config file whose hooks append one line per call to `log`: the event, the variables and
+/// whether the keg exists at that moment.
+fn hooks_config(dir: &Path, log: &Path) -> PathBuf {
+    let line = format!(
+        "echo \"$sps_HOOK $sps_FORMULA $sps_VERSION $sps_KEG_PATH $sps_OPT_PATH \
+         $(test -d \"$sps_KEG_PATH\" && echo present || echo absent)\" >> '{}'",
+        log.display()
+    );
+    let config = dir.join("config.toml");
+    let hooks = ["post_install", "pre_uninstall", "post_upgrade"]
+        .map(|event| format!("{event} = '''{line}'''\n"))
+        .concat();
+    fs::write(&config, format!("[hooks]\n{hooks}")).unwrap();
+    config
+}
+
+fn run_with(env: &TestEnv, config: &Path, args: &[&str]) -> std::process::Output {
+    let output = env
+        .command(SPS)
+        .env("sps_CONFIG", config)
+        .args(args)
+        .output()
+        .expect("run sps");
+    assert!(output.status.success(), "{}", describe(&output));
+    output
+}
+
+fn logged(log: &Path) -> Vec<String> {
+    fs::read_to_string(log)
+        .unwrap_or_default()
+        .lines()
+        .map(str::to_string)
+        .collect()
+}
+
+#[test]
+fn hooks_run_in_order_with_the_formula_in_the_environment() {
+    let env = env_with_app();
+    let dir = tempfile::tempdir().unwrap();
+    let log = dir.path().join("hooks.log");
+    let config = hooks_config(dir.path(), &log);
+    let prefix = env.prefix().display().to_string();
+    let line = |event: &str, name: &str, version: &str, keg: &str| {
+        format!(
+            "{event} {name} {version} {prefix}/Cellar/{name}/{version} {prefix}/opt/{name} {keg}"
+        )
+    };
+
+    run_with(&env, &config, &["install", "app"]);
+
+    assert_eq!(
+        logged(&log),
+        [
+            line("post_install", "lib", "1.7", "present"),
+            line("post_install", "app", "2.0", "present"),
+        ]
+    );
+
+    run_with(&env, &config, &["uninstall", "app"]);
+
+    assert_eq!(
+        logged(&log)[2..],
+        [line("pre_uninstall", "app", "2.0", "present")]
+    );
+}
+
+#[test]
+fn an_upgrade_runs_the_post_upgrade_hook_for_the_new_keg() {
+    let env = env_with_app();
+    let old = env.keg("lib", "1.0");
+    fs::create_dir_all(old.join("bin")).unwrap();
+    fs::write(
+        old.join("INSTALL_RECEIPT.json"),
+        json!({ "name": "lib", "version": "1.0", "installed_on_request": true }).to_string(),
+    )
+    .unwrap();
+    std::os::unix::fs::symlink(&old, env.prefix().join("opt/lib")).unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    let log = dir.path().join("hooks.log");
+    let config = hooks_config(dir.path(), &log);
+
+    run_with(&env, &config, &["upgrade", "lib"]);
+
+    let prefix = env.prefix().display().to_string();
+    assert_eq!(
+        logged(&log),
+        [format!(
+            "post_upgrade lib 1.7 {prefix}/Cellar/lib/1.7 {prefix}/opt/lib present"
+        )]
+    );
+}
+
+#[test]
+fn no_hooks_skips_every_hook() {
+    let env = env_with_app();
+    let dir = tempfile::tempdir().unwrap();
+    let log = dir.path().join("hooks.log");
+    let config = hooks_config(dir.path(), &log);
+
+    run_with(&env, &config, &["install", "--no-hooks", "app"]);
+    run_with(&env, &config, &["uninstall", "--no-hooks", "app"]);
+
+    assert!(!log.exists(), "{:?}", logged(&log));
+    assert!(!env.keg("app", "2.0").exists());
+}
@@ -0,0 +1,190 @@
+//! End-to-end installs against the mock API and bottle server from `sps-testkit`.
+
+use sps_testkit::{describe, BottleServing, CaskFixture, Fixtures, FormulaFixture, TestEnv};
+
+const SPS: &str = env!("CARGO_BIN_EXE_sps");
+
+#[test]
+fn installs_a_formula_with_no_dependencies() {
+    let env = TestEnv::new(&Fixtures::new().formula(FormulaFixture::new("hello", "1.0")));
+
+    let output = env.run(SPS, &["install", "hello"]);
+
+    assert!(output.status.success(), "{}", describe(&output));
+    assert!(env.keg("hello", "1.0").join("bin/hello").is_file());
+    assert!(env.bin("hello").exists(), "{}", describe(&output));
+}
+
+#[test]
+fn installs_into_the_prefix_given_with_prefix() {
+    let env = TestEnv::new(&Fixtures::new().formula(FormulaFixture::new("hello", "1.0")));
+    let other = tempfile::tempdir().unwrap();
+    let prefix = other.path().join("sps");
+
+    let output = env.run(
+        SPS,
+        &["install", "--prefix", prefix.to_str().unwrap(), "hello"],
+    );
+
+    assert!(output.status.success(), "{}", describe(&output));
+    assert!(prefix.join("Cellar/hello/1.0/bin/hello").is_file());
+    assert!(prefix.join("bin/hello").exists(), "{}", describe(&output));
+    assert!(!env.keg("hello", "1.0").exists());
+}
+
+#[test]
+fn installs_a_diamond_fetching_each_bottle_once() {
+    let fixtures = Fixtures::new()
+        .formula(FormulaFixture::new("app", "3.0").depends_on(&["left", "right"]))
+        .formula(FormulaFixture::new("left", "1.0").depends_on(&["base"]))
+        .formula(FormulaFixture::new("right", "1.0").depends_on(&["base"]))
+        .formula(FormulaFixture::new("base", "2.1"));
+    let env = TestEnv::new(&fixtures);
+
+    let output = env.run(SPS, &["install", "app"]);
+
+    assert!(output.status.success(), "{}", describe(&output));
+    for formula in &fixtures.formulae {
+        assert!(
+            env.keg(&formula.name, &formula.version).is_dir(),
+            "{} not installed\n{}",
+            formula.name,
+            describe(&output)
+        );
+        assert_eq!(
+            env.server.hits(&formula.bottle_path()),
+            1,
+            "{}",
+            formula.name
+        );
+    }
+}
+
+#[test]
+fn a_missing_bottle_fails_the_install_and_leaves_no_keg() {
+    let fixtures = Fixtures::new()
+        .formula(FormulaFixture::new("app", "1.0").depends_on(&["gone"]))
+        .formula(FormulaFixture::new("gone", "1.0").bottle(BottleServing::Missing));
+    let env = TestEnv::new(&fixtures);
+
+    let output = env.run(SPS, &["install", "app"]);
+
+    assert!(!output.status.success(), "{}", describe(&output));
+    assert!(!env.keg("gone", "1.0").exists());
+    assert!(!env.keg("app", "1.0").exists());
+}
+
+#[test]
+fn a_bottle_with_the_wrong_checksum_is_not_poured() {
+    let fixtures = Fixtures::new()
+        .formula(FormulaFixture::new("tampered", "1.0").bottle(BottleServing::ChecksumMismatch));
+    let env = TestEnv::new(&fixtures);
+
+    let output = env.run(SPS, &["install", "tampered"]);
+
+    assert!(!output.status.success(), "{}", describe(&output));
+    assert!(!env.keg("tampered", "1.0").exists());
+    assert!(!env.bin("tampered").exists());
+}
+
+#[test]
+fn installing_an_installed_formula_downloads_nothing() {
+    let fixtures = Fixtures::new()
+        .formula(FormulaFixture::new("app", "1.0").depends_on(&["lib"]))
+        .formula(FormulaFixture::new("lib", "1.0"));
+    let env = TestEnv::new(&fixtures);
+    let first = env.run(SPS, &["install", "app"]);
+    assert!(first.status.success(), "{}", describe(&first));
+
+    let second = env.run(SPS, &["install", "app"]);
+
+    assert!(second.status.success(), "{}", describe(&second));
+    for formula in &fixtures.formulae {
+        assert_eq!(
+            env.server.hits(&formula.bottle_path()),
+            1,
+            "{}",
+            formula.name
+        );
+    }
+}
+
+#[test]
+fn installs_a_cask_together_with_the_formula_it_depends_on() {
+    let fixtures = Fixtures::new()
+        .formula(FormulaFixture::new("runtime", "4.2"))
+        .cask(CaskFixture::new("viewer", "1.5").depends_on_formulae(&["runtime"]));
+    let env = TestEnv::new(&fixtures);
+
+    let output = env.run(SPS, &["install", "--cask", "viewer"]);
+
+    assert!(output.status.success(), "{}", describe(&output));
+    assert!(env.keg("runtime", "4.2").is_dir(), "{}", describe(&output));
+    assert!(
+        env.prefix().join("Caskroom/viewer/1.5").is_dir(),
+        "{}",
+        describe(&output)
+    );
+    assert!(env.bin("viewer").exists(), "{}", describe(&output));
+}
+
+#[test]
+fn a_disabled_dependency_fails_the_install_before_any_download() {
+    let fixtures = Fixtures::new()
+        .formula(FormulaFixture::new("app", "1.0").depends_on(&["old"]))
+        .formula(FormulaFixture::new("old", "1.0").extra(serde_json::json!({
+            "disabled": true,
+            "disable_date": "2024-05-01",
+            "disable_reason": "unmaintained"
+        })));
+    let env = TestEnv::new(&fixtures);
+
+    let output = env.run(SPS, &["install", "app"]);
+
+    assert!(!output.status.success(), "{}", describe(&output));
+    assert!(
+        describe(&output).contains("old was disabled on 2024-05-01"),
+        "{}",
+        describe(&output)
+    );
+    for formula in &fixtures.formulae {
+        assert_eq!(
+            env.server.hits(&formula.bottle_path()),
+            0,
+            "{}",
+            formula.name
+        );
+    }
+}
+
+/// The `changes` object from a `--json-lines` run.
+fn changes_line(output: &std::process::Output) -> serde_json::Value {
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| serde_json::from_str::<serde_json::Value>(line).ok())
+        .find(|line| line["type"] == "changes")
+        .unwrap_or_else(|| panic!("no changes object\n{}", describe(output)))
+}
+
+#[test]
+fn reports_installs_and_upgrades_as_json_changes() {
+    let env = TestEnv::new(&Fixtures::new().formula(FormulaFixture::new("tool", "1.9")));
+    let output = env.run(SPS, &["--json-lines", "install", "tool"]);
+    assert!(output.status.success(), "{}", describe(&output));
+    let changes = changes_line(&output);
+    assert_eq!(changes["installed"][0]["name"], "tool");
+    assert_eq!(changes["installed"][0]["version"], "1.9");
+    assert_eq!(changes["new_bins"][0]["name"], "tool");
+
+    Fixtures::new()
+        .formula(FormulaFixture::new("tool", "1.10"))
+        .publish(&env.server);
+    env.run(SPS, &["update"]);
+    let output = env.run(SPS, &["--json-lines", "upgrade", "tool"]);
+
+    assert!(output.status.success(), "{}", describe(&output));
+    let changes = changes_line(&output);
+    assert_eq!(changes["upgraded"][0]["from"], "1.9", "{changes}");
+    assert_eq!(changes["upgraded"][0]["to"], "1.10", "{changes}");
+    assert!(changes["disk_delta"].is_i64());
+}
@@ -0,0 +1,222 @@
+//! `--json-lines`: stdout carries nothing but one JSON event per line, each with the fields its
+//! `type` promises, and the human-readable output goes to stderr.
+
+use std::time::Duration;
+
+use serde_json::Value;
+use sps_testkit::{describe, BottleServing, Fixtures, FormulaFixture, Response, TestEnv};
+
+const SPS: &str = env!("CARGO_BIN_EXE_sps");
+
+/// Every stdout line, parsed; panics on anything that is not a JSON object with a known `type`
+/// and its required fields.
+fn events(stdout: &[u8]) -> Vec<Value> {
+    let stdout = String::from_utf8(stdout.to_vec()).unwrap();
+    stdout
+        .lines()
+        .map(|line| {
+            let event: Value =
+                serde_json::from_str(line).unwrap_or_else(|e| panic!("not JSON ({e}): {line}"));
+            validate(&event);
+            event
+        })
+        .collect()
+}
+
+fn field<'a>(event: &'a Value, name: &str) -> &'a Value {
+    event
+        .get(name)
+        .unwrap_or_else(|| panic!("missing `{name}`: {event}"))
+}
+
+fn string_in(event: &Value, name: &str, allowed: &[&str]) {
+    let value = field(event, name).as_str();
+    assert!(
+        value.is_some_and(|v| allowed.contains(&v)),
+        "`{name}` not one of {allowed:?}: {event}"
+    );
+}
+
+fn validate_node(node: &Value, states: &[&str]) {
+    assert!(field(node, "name").is_string(), "{node}");
+    string_in(node, "kind", &["formula", "cask"]);
+    string_in(node, "action", &["install", "upgrade", "reinstall"]);
+    string_in(node, "state", states);
+}
+
+fn validate(event: &Value) {
+    match field(event, "type").as_str().unwrap_or_default() {
+        "plan" => {
+            assert_eq!(field(event, "version"), 1, "{event}");
+            for node in field(event, "nodes").as_array().unwrap() {
+                validate_node(node, &["pending"]);
+            }
+        }
+        "phase" => {
+            assert!(field(event, "name").is_string(), "{event}");
+            let state = field(event, "state").as_str().unwrap_or_default();
+            string_in(
+                event,
+                "state",
+                &["started", "finished", "failed", "skipped"],
+            );
+            if state == "skipped" {
+                assert!(field(event, "phase").is_null(), "{event}");
+            } else {
+                string_in(event, "phase", &["download", "install"]);
+            }
+            assert_eq!(
+                event.get("error").is_some_and(Value::is_string),
+                state == "failed",
+                "`error` only on failures: {event}"
+            );
+        }
+        "progress" => {
+            assert!(field(event, "name").is_string(), "{event}");
+            string_in(event, "phase", &["download", "install"]);
+            string_in(event, "unit", &["bytes", "files"]);
+            let current = field(event, "current").as_u64().unwrap();
+            let total = field(event, "total");
+            assert!(
+                total.is_null() || total.as_u64().unwrap() >= current,
+                "{event}"
+            );
+        }
+        "summary" => {
+            for count in ["succeeded", "failed", "skipped"] {
+                assert!(field(event, count).is_u64(), "{event}");
+            }
+            for node in field(event, "nodes").as_array().unwrap() {
+                validate_node(node, &["finished", "failed", "skipped", "pending"]);
+            }
+            for target in field(event, "targets").as_array().unwrap() {
+                assert!(field(target, "name").is_string(), "{target}");
+                string_in(target, "state", &["finished", "failed", "skipped"]);
+            }
+        }
+        "changes" => {
+            for installed in field(event, "installed").as_array().unwrap() {
+                assert!(field(installed, "name").is_string(), "{installed}");
+                assert!(field(installed, "version").is_string(), "{installed}");
+            }
+            assert!(field(event, "upgraded").is_array(), "{event}");
+            for bin in field(event, "new_bins").as_array().unwrap() {
+                assert!(field(bin, "name").is_string(), "{bin}");
+                assert!(bin.get("shadows").is_some(), "{bin}");
+            }
+            assert!(field(event, "disk_delta").is_i64(), "{event}");
+        }
+        other => panic!("unknown type {other:?}: {event}"),
+    }
+}
+
+fn types(events: &[Value]) -> Vec<&str> {
+    events.iter().map(|e| e["type"].as_str().unwrap()).collect()
+}
+
+#[test]
+fn an_install_streams_plan_phases_progress_summary_and_changes() {
+    let fixtures = Fixtures::new()
+        .formula(FormulaFixture::new("base", "1.0"))
+        .formula(FormulaFixture::new("top", "2.0").depends_on(&["base"]));
+    let env = TestEnv::new(&fixtures);
+    // Sent in pieces so the download reports progress along the way.
+    let top = &fixtures.formulae[1];
+    env.server.serve(
+        &top.bottle_path(),
+        Response::ok(top.bottle_bytes()).throttle(64, Duration::from_millis(5)),
+    );
+
+    let output = env.run(SPS, &["--json-lines", "install", "top"]);
+
+    assert!(output.status.success(), "{}", describe(&output));
+    let events = events(&output.stdout);
+    let types = types(&events);
+    assert_eq!(types.first(), Some(&"plan"), "{types:?}");
+    assert_eq!(
+        &types[types.len() - 2..],
+        ["summary", "changes"],
+        "{types:?}"
+    );
+    let planned: Vec<&str> = events[0]["nodes"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|n| n["name"].as_str().unwrap())
+        .collect();
+    assert_eq!(planned, ["base", "top"]);
+    for name in ["base", "top"] {
+        for (phase, state) in [
+            ("download", "started"),
+            ("download", "finished"),
+            ("install", "started"),
+            ("install", "finished"),
+        ] {
+            assert!(
+                events.iter().any(|e| e["type"] == "phase"
+                    && e["name"] == name
+                    && e["phase"] == phase
+                    && e["state"] == state),
+                "no {phase} {state} for {name}: {types:?}"
+            );
+        }
+    }
+    assert!(
+        events
+            .iter()
+            .any(|e| e["type"] == "progress" && e["name"] == "top" && e["phase"] == "download"),
+        "{types:?}"
+    );
+    let summary = &events[events.len() - 2];
+    assert_eq!(summary["succeeded"], 2);
+    assert_eq!(summary["failed"], 0);
+    assert_eq!(summary["targets"][0]["state"], "finished");
+    // The human-readable report is still written, to stderr.
+    assert!(!output.stderr.is_empty(), "{}", describe(&output));
+}
+
+#[test]
+fn a_failed_package_is_reported_in_its_phase_and_the_summary() {
+    let fixtures = Fixtures::new()
+        .formula(FormulaFixture::new("ok", "1.0"))
+        .formula(FormulaFixture::new("gone", "1.0").bottle(BottleServing::Missing));
+    let env = TestEnv::new(&fixtures);
+
+    let output = env.run(SPS, &["--json-lines", "install", "ok", "gone"]);
+
+    assert!(!output.status.success(), "{}", describe(&output));
+    let events = events(&output.stdout);
+    let types = types(&events);
+    assert_eq!(types.first(), Some(&"plan"), "{types:?}");
+    assert!(
+        events.iter().any(|e| e["type"] == "phase"
+            && e["name"] == "gone"
+            && e["state"] == "failed"
+            && e["phase"] == "download"),
+        "{types:?}"
+    );
+    let summary = events.iter().find(|e| e["type"] == "summary").unwrap();
+    assert_eq!(summary["succeeded"], 1);
+    assert_eq!(summary["failed"], 1);
+    let gone = summary["targets"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|t| t["name"] == "gone")
+        .unwrap();
+    assert_eq!(gone["state"], "failed");
+    assert_eq!(gone["failed"], serde_json::json!(["gone"]));
+}
+
+#[test]
+fn a_run_with_nothing_to_do_still_reports_plan_and_summary() {
+    let env = TestEnv::new(&Fixtures::new().formula(FormulaFixture::new("hello", "1.0")));
+    let first = env.run(SPS, &["install", "hello"]);
+    assert!(first.status.success(), "{}", describe(&first));
+
+    let output = env.run(SPS, &["--json-lines", "install", "hello"]);
+
+    assert!(output.status.success(), "{}", describe(&output));
+    let events = events(&output.stdout);
+    assert_eq!(types(&events), ["plan", "summary"]);
+}
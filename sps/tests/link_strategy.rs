@@ -0,0 +1,116 @@
+//! Whatever `link_strategy` places in the prefix, `uninstall` takes all of it out again: no file
+//! or link is left, only the (empty) directories merged entries were placed in. The opt link is
+//! a symlink either way.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+use serde_json::Value;
+use sps_testkit::{describe, Fixtures, FormulaFixture, TestEnv};
+use walkdir::WalkDir;
+
+const SPS: &str = env!("CARGO_BIN_EXE_sps");
+
+/// `foo` 1.0 with something for every linked prefix directory, a private `share` directory
+/// and a manpage in a shared one.
+fn env_with_foo() -> TestEnv {
+    TestEnv::new(
+        &Fixtures::new().formula(
+            FormulaFixture::new("foo", "1.0")
+                .file("bin/foo", "#!/bin/sh\necho foo 1.0\n")
+                .file("lib/libfoo.so", "libfoo")
+                .file("include/foo.h", "int foo(void);\n")
+                .file("share/man/man1/foo.1", ".TH FOO 1\n")
+                .file("share/foo/data/table", "1 2 3\n")
+                .file("etc/foo.conf", "answer = 42\n"),
+        ),
+    )
+}
+
+fn run(env: &TestEnv, strategy: &str, args: &[&str]) {
+    let output = env
+        .command(SPS)
+        .env("sps_LINK_STRATEGY", strategy)
+        .args(args)
+        .output()
+        .expect("run sps");
+    assert!(output.status.success(), "{}", describe(&output));
+}
+
+/// Every file and link under the prefix outside the Cellar and sps' own state, by kind.
+fn prefix_entries(env: &TestEnv) -> BTreeMap<PathBuf, &'static str> {
+    let prefix = env.prefix();
+    WalkDir::new(&prefix)
+        .min_depth(1)
+        .into_iter()
+        .filter_entry(|e| !["Cellar", "var"].iter().any(|d| e.path() == prefix.join(d)))
+        .map(Result::unwrap)
+        .filter(|entry| entry.path_is_symlink() || !entry.file_type().is_dir())
+        .map(|entry| {
+            let kind = if entry.path_is_symlink() {
+                "symlink"
+            } else {
+                "file"
+            };
+            (
+                entry.path().strip_prefix(&prefix).unwrap().to_path_buf(),
+                kind,
+            )
+        })
+        .collect()
+}
+
+fn same_inode(a: &Path, b: &Path) -> bool {
+    let (a, b) = (a.symlink_metadata().unwrap(), b.symlink_metadata().unwrap());
+    a.dev() == b.dev() && a.ino() == b.ino()
+}
+
+#[test]
+fn uninstall_removes_everything_each_strategy_placed() {
+    for strategy in ["symlink", "hardlink", "copy"] {
+        let env = env_with_foo();
+
+        run(&env, strategy, &["install", "foo"]);
+
+        let keg = env.keg("foo", "1.0");
+        assert_eq!(fs::read_link(env.prefix().join("opt/foo")).unwrap(), keg);
+        let receipt: Value =
+            serde_json::from_str(&fs::read_to_string(keg.join("INSTALL_RECEIPT.json")).unwrap())
+                .unwrap();
+        assert_eq!(receipt["link_strategy"], strategy);
+        for file in [
+            "lib/libfoo.so",
+            "include/foo.h",
+            "share/man/man1/foo.1",
+            "share/foo/data/table",
+            "etc/foo.conf",
+        ] {
+            let placed = env.prefix().join(file);
+            assert_eq!(
+                fs::read(&placed).unwrap(),
+                fs::read(keg.join(file)).unwrap(),
+                "{strategy}: {file}"
+            );
+            let is_symlink = placed.symlink_metadata().unwrap().is_symlink()
+                || placed
+                    .ancestors()
+                    .take_while(|p| *p != env.prefix())
+                    .any(|p| p.is_symlink());
+            assert_eq!(is_symlink, strategy == "symlink", "{strategy}: {file}");
+            if strategy != "symlink" {
+                assert_eq!(
+                    same_inode(&placed, &keg.join(file)),
+                    strategy == "hardlink",
+                    "{strategy}: {file}"
+                );
+            }
+        }
+
+        run(&env, strategy, &["uninstall", "foo"]);
+
+        assert!(!keg.exists(), "{strategy}");
+        assert_eq!(prefix_entries(&env), BTreeMap::new(), "{strategy}");
+    }
+}
@@ -0,0 +1,170 @@
+//! `install --cask ./x.json` and `--cask-url` install a cask from a definition outside the API,
+//! record where it came from, and reject incomplete definitions with a message naming the field.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde_json::{json, Value};
+use sps_testkit::fixture::{sha256_hex, tarball};
+use sps_testkit::{describe, Fixtures, Response, TestEnv};
+
+const SPS: &str = env!("CARGO_BIN_EXE_sps");
+const ARCHIVE: &str = "/local/Viewer-0.9.tar.gz";
+
+/// A tiny `Viewer.app` bundle, served by `env`.
+fn serve_app(env: &TestEnv) -> Vec<u8> {
+    let archive = tarball([
+        ("Viewer.app/Contents/Info.plist", &b"<plist/>\n"[..]),
+        (
+            "Viewer.app/Contents/MacOS/Viewer",
+            &b"#!/bin/sh\necho viewer 0.9\n"[..],
+        ),
+    ]);
+    env.server.serve(ARCHIVE, Response::ok(archive.clone()));
+    archive
+}
+
+/// A minimal definition of `viewer` 0.9 installing `Viewer.app`.
+fn definition(env: &TestEnv, archive: &[u8]) -> Value {
+    json!({
+        "token": "viewer",
+        "version": "0.9",
+        "url": env.server.url(ARCHIVE),
+        "sha256": sha256_hex(archive),
+        "artifacts": [{ "app": ["Viewer.app"] }],
+    })
+}
+
+fn write_definition(dir: &Path, definition: &Value) -> PathBuf {
+    let path = dir.join("viewer.json");
+    fs::write(&path, definition.to_string()).unwrap();
+    path
+}
+
+/// The installed app bundle and the recorded definition source.
+#[cfg(not(target_os = "macos"))]
+fn assert_installed_from(env: &TestEnv, source: &str) {
+    let app = env.prefix().join("Applications/Viewer.app");
+    assert_eq!(
+        fs::read_to_string(app.join("Contents/MacOS/Viewer")).unwrap(),
+        "#!/bin/sh\necho viewer 0.9\n"
+    );
+    let receipt: Value = serde_json::from_str(
+        &fs::read_to_string(
+            env.prefix()
+                .join("Caskroom/viewer/0.9/CASK_INSTALL_MANIFEST.json"),
+        )
+        .unwrap(),
+    )
+    .unwrap();
+    assert_eq!(receipt["definition_source"], source);
+}
+
+// On macOS apps go to `/Applications`, which a test must not touch.
+#[cfg(not(target_os = "macos"))]
+#[test]
+fn a_local_definition_installs_its_app() {
+    let env = TestEnv::new(&Fixtures::new());
+    let archive = serve_app(&env);
+    let dir = tempfile::tempdir().unwrap();
+    let file = write_definition(dir.path(), &definition(&env, &archive));
+
+    let output = env.run(SPS, &["install", "--cask", file.to_str().unwrap()]);
+
+    assert!(output.status.success(), "{}", describe(&output));
+    assert_installed_from(&env, file.to_str().unwrap());
+    assert_eq!(env.server.hits(ARCHIVE), 1);
+}
+
+#[cfg(not(target_os = "macos"))]
+#[test]
+fn a_definition_url_installs_its_app() {
+    let env = TestEnv::new(&Fixtures::new());
+    let archive = serve_app(&env);
+    env.server.serve(
+        "/pr/viewer.json",
+        Response::json(&definition(&env, &archive)),
+    );
+    let url = env.server.url("/pr/viewer.json");
+
+    let output = env.run(SPS, &["install", "--cask", "viewer", "--cask-url", &url]);
+
+    assert!(output.status.success(), "{}", describe(&output));
+    assert_installed_from(&env, &url);
+}
+
+#[test]
+fn a_definition_url_for_another_token_is_refused() {
+    let env = TestEnv::new(&Fixtures::new());
+    let archive = serve_app(&env);
+    env.server.serve(
+        "/pr/viewer.json",
+        Response::json(&definition(&env, &archive)),
+    );
+    let url = env.server.url("/pr/viewer.json");
+
+    let output = env.run(SPS, &["install", "--cask", "editor", "--cask-url", &url]);
+
+    assert!(!output.status.success(), "{}", describe(&output));
+    assert!(
+        String::from_utf8_lossy(&output.stderr)
+            .contains(&format!("{url}: defines cask 'viewer', not 'editor'")),
+        "{}",
+        describe(&output)
+    );
+}
+
+#[test]
+fn incomplete_definitions_are_rejected_naming_the_problem() {
+    let env = TestEnv::new(&Fixtures::new());
+    let archive = serve_app(&env);
+    let valid = definition(&env, &archive);
+    let without = |field: &str| {
+        let mut definition = valid.clone();
+        definition.as_object_mut().unwrap().remove(field);
+        definition
+    };
+    let with = |field: &str, value: Value| {
+        let mut definition = valid.clone();
+        definition[field] = value;
+        definition
+    };
+    let cases = [
+        (json!(["viewer"]), "a cask definition must be a JSON object"),
+        (without("token"), "missing required field `token`"),
+        (
+            with("version", json!(" ")),
+            "`version` must be a non-empty string",
+        ),
+        (without("url"), "missing required field `url`"),
+        (
+            without("sha256"),
+            "missing required field `sha256` (use \"no_check\" to skip verification)",
+        ),
+        (
+            with("sha256", json!("abc123")),
+            "`sha256` must be a 64-character hex digest or \"no_check\"",
+        ),
+        (
+            with("artifacts", json!([])),
+            "`artifacts` must list at least one artifact",
+        ),
+    ];
+    let dir = tempfile::tempdir().unwrap();
+
+    for (definition, message) in cases {
+        let file = write_definition(dir.path(), &definition);
+
+        let output = env.run(SPS, &["install", "--cask", file.to_str().unwrap()]);
+
+        assert!(!output.status.success(), "{}", describe(&output));
+        assert!(
+            String::from_utf8_lossy(&output.stderr)
+                .contains(&format!("{}: {message}", file.display())),
+            "{message}\n{}",
+            describe(&output)
+        );
+    }
+    assert_eq!(env.server.hits(ARCHIVE), 0);
+    assert!(!env.prefix().join("Caskroom/viewer").exists());
+}
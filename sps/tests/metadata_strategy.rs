@@ -0,0 +1,186 @@
+//! Requests the API sees under each `metadata_strategy`: `full` fetches the index once and
+//! revalidates it with its ETag, `lazy` fetches each needed formula once (a bounded number at a
+//! time), `auto` picks between them, and both fill a cache the other reads.
+
+use std::fs::File;
+use std::process::Output;
+use std::time::{Duration, SystemTime};
+
+use sps_core::metadata::LAZY_FETCH_CONCURRENCY;
+use sps_testkit::{describe, Fixtures, FormulaFixture, Response, TestEnv};
+
+const SPS: &str = env!("CARGO_BIN_EXE_sps");
+const INDEX: &str = "/api/formula.json";
+
+fn fixtures() -> Fixtures {
+    Fixtures::new()
+        .formula(FormulaFixture::new("base", "1.0"))
+        .formula(FormulaFixture::new("top", "1.0").depends_on(&["base"]))
+        .formula(FormulaFixture::new("other", "1.0"))
+        .formula(FormulaFixture::new("third", "1.0"))
+}
+
+fn install(env: &TestEnv, strategy: &str, args: &[&str]) -> Output {
+    let output = env
+        .command(SPS)
+        .env("sps_METADATA_STRATEGY", strategy)
+        .arg("install")
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", describe(&output));
+    output
+}
+
+fn formula_fetches(env: &TestEnv, name: &str) -> usize {
+    env.server.hits(&format!("/api/formula/{name}.json"))
+}
+
+/// Requests for single formula definitions, by any name.
+fn all_formula_fetches(env: &TestEnv) -> usize {
+    env.server
+        .requests()
+        .iter()
+        .filter(|path| path.starts_with("/api/formula/"))
+        .count()
+}
+
+#[test]
+fn full_downloads_the_index_once_and_no_single_formula() {
+    let env = TestEnv::new(&fixtures());
+
+    install(&env, "full", &["top"]);
+    install(&env, "full", &["other"]);
+
+    assert_eq!(env.server.hits(INDEX), 1, "{:?}", env.server.requests());
+    assert_eq!(all_formula_fetches(&env), 0, "{:?}", env.server.requests());
+    assert!(env.keg("other", "1.0").exists());
+}
+
+#[test]
+fn full_revalidates_an_expired_index_with_its_etag() {
+    let env = TestEnv::new(&fixtures());
+    install(&env, "full", &["top"]);
+    // Past the day the cached index is trusted without asking.
+    File::options()
+        .append(true)
+        .open(env.cache_dir().join("formula.json"))
+        .unwrap()
+        .set_modified(SystemTime::now() - Duration::from_secs(2 * 24 * 60 * 60))
+        .unwrap();
+
+    install(&env, "full", &["other"]);
+    install(&env, "full", &["third"]);
+
+    // Asked again once, answered 304, and fresh again after that.
+    assert_eq!(env.server.hits(INDEX), 2, "{:?}", env.server.requests());
+    assert_eq!(env.server.not_modified(INDEX), 1);
+    assert!(env.keg("third", "1.0").exists());
+}
+
+#[test]
+fn lazy_fetches_each_needed_formula_once_and_never_the_index() {
+    let env = TestEnv::new(&fixtures());
+
+    install(&env, "lazy", &["top"]);
+    install(&env, "lazy", &["other"]);
+    install(&env, "lazy", &["--dry-run", "top"]);
+
+    assert_eq!(env.server.hits(INDEX), 0, "{:?}", env.server.requests());
+    for (name, fetches) in [("top", 1), ("base", 1), ("other", 1), ("third", 0)] {
+        assert_eq!(
+            formula_fetches(&env, name),
+            fetches,
+            "{name}: {:?}",
+            env.server.requests()
+        );
+    }
+    assert!(env.cache_dir().join("formula").join("top.json").is_file());
+}
+
+#[test]
+fn auto_is_lazy_for_a_small_request_on_a_cold_cache() {
+    let env = TestEnv::new(&fixtures());
+
+    install(&env, "auto", &["top"]);
+
+    assert_eq!(env.server.hits(INDEX), 0, "{:?}", env.server.requests());
+    assert_eq!(all_formula_fetches(&env), 2, "{:?}", env.server.requests());
+}
+
+#[test]
+fn auto_downloads_the_index_for_a_large_request() {
+    let names: Vec<String> = (0..21).map(|i| format!("pkg{i:02}")).collect();
+    let fixtures = names.iter().fold(Fixtures::new(), |fixtures, name| {
+        fixtures.formula(FormulaFixture::new(name, "1.0"))
+    });
+    let env = TestEnv::new(&fixtures);
+    let mut args = vec!["--dry-run"];
+    args.extend(names.iter().map(String::as_str));
+
+    install(&env, "auto", &args);
+
+    assert_eq!(env.server.hits(INDEX), 1, "{:?}", env.server.requests());
+    assert_eq!(all_formula_fetches(&env), 0, "{:?}", env.server.requests());
+}
+
+#[test]
+fn switching_strategies_reuses_what_is_already_cached() {
+    let env = TestEnv::new(&fixtures());
+    install(&env, "lazy", &["top"]);
+    let fetched_lazily = all_formula_fetches(&env);
+
+    // `full` downloads the index once...
+    install(&env, "full", &["--dry-run", "top"]);
+    install(&env, "full", &["other"]);
+    // ...and it serves `lazy` and `auto` lookups from then on.
+    install(&env, "lazy", &["third"]);
+    install(&env, "auto", &["--dry-run", "other", "third"]);
+
+    assert_eq!(env.server.hits(INDEX), 1, "{:?}", env.server.requests());
+    assert_eq!(
+        all_formula_fetches(&env),
+        fetched_lazily,
+        "{:?}",
+        env.server.requests()
+    );
+    assert!(env.keg("third", "1.0").exists());
+}
+
+#[test]
+fn lazy_fetches_dependencies_concurrently_up_to_the_limit() {
+    let deps: Vec<String> = (0..LAZY_FETCH_CONCURRENCY + 2)
+        .map(|i| format!("dep{i:02}"))
+        .collect();
+    let dep_names: Vec<&str> = deps.iter().map(String::as_str).collect();
+    let formulae: Vec<FormulaFixture> = deps
+        .iter()
+        .map(|name| FormulaFixture::new(name, "1.0"))
+        .chain([FormulaFixture::new("top", "1.0").depends_on(&dep_names)])
+        .collect();
+    let env = TestEnv::new(
+        &formulae
+            .iter()
+            .cloned()
+            .fold(Fixtures::new(), Fixtures::formula),
+    );
+    // Slow enough that a full batch is in flight at once even though each request takes a while
+    // to set up on a busy machine.
+    for formula in &formulae {
+        let json = formula.api_json(&env.server.base_url(), &formula.bottle_bytes());
+        env.server.serve(
+            &format!("/api/formula/{}.json", formula.name),
+            Response::json(&json).delay(Duration::from_secs(2)),
+        );
+    }
+
+    install(&env, "lazy", &["--dry-run", "top"]);
+
+    assert_eq!(all_formula_fetches(&env), deps.len() + 1);
+    assert_eq!(
+        env.server.peak_in_flight("/api/formula/"),
+        LAZY_FETCH_CONCURRENCY,
+        "{:?}",
+        env.server.requests()
+    );
+}
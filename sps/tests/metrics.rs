@@ -0,0 +1,102 @@
+//! With `metrics = local`, installs, upgrades and uninstalls are counted per package in
+//! `<prefix>/var/sps/metrics.json`; with metrics off (the default) nothing is written.
+
+use std::fs;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde_json::{json, Value};
+use sps_testkit::{describe, Fixtures, FormulaFixture, TestEnv};
+
+const SPS: &str = env!("CARGO_BIN_EXE_sps");
+
+/// `app` 2.0, depending on `lib` 1.7.
+fn env_with_app() -> TestEnv {
+    TestEnv::new(
+        &Fixtures::new()
+            .formula(FormulaFixture::new("lib", "1.7"))
+            .formula(FormulaFixture::new("app", "2.0").depends_on(&["lib"])),
+    )
+}
+
+fn run(env: &TestEnv, metrics: &str, args: &[&str]) {
+    let output = env
+        .command(SPS)
+        .env("sps_METRICS", metrics)
+        .args(args)
+        .output()
+        .expect("run sps");
+    assert!(output.status.success(), "{}", describe(&output));
+}
+
+#[test]
+fn local_metrics_count_each_operation_per_package() {
+    let env = env_with_app();
+    let started = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+
+    run(&env, "local", &["install", "app"]);
+    run(&env, "local", &["reinstall", "app"]);
+    run(&env, "local", &["uninstall", "app"]);
+
+    let store: Value = serde_json::from_str(
+        &fs::read_to_string(env.prefix().join("var/sps/metrics.json")).unwrap(),
+    )
+    .unwrap();
+    let packages = &store["packages"];
+    let counts = |name: &str| {
+        let stats = &packages[name];
+        json!([stats["installs"], stats["upgrades"], stats["uninstalls"]])
+    };
+    assert_eq!(counts("app"), json!([2, 0, 1]), "{store:#}");
+    assert_eq!(counts("lib"), json!([1, 0, 0]), "{store:#}");
+    assert_eq!(packages.as_object().unwrap().len(), 2, "{store:#}");
+    for name in ["app", "lib"] {
+        assert!(
+            packages[name]["install_seconds"].as_f64().unwrap() > 0.0,
+            "{store:#}"
+        );
+        assert!(
+            packages[name]["last_used"].as_u64().unwrap() >= started,
+            "{store:#}"
+        );
+    }
+}
+
+#[test]
+fn an_upgrade_is_counted_as_one() {
+    let env = env_with_app();
+    let old = env.keg("lib", "1.0");
+    fs::create_dir_all(old.join("bin")).unwrap();
+    fs::write(
+        old.join("INSTALL_RECEIPT.json"),
+        json!({ "name": "lib", "version": "1.0", "installed_on_request": true }).to_string(),
+    )
+    .unwrap();
+    std::os::unix::fs::symlink(&old, env.prefix().join("opt/lib")).unwrap();
+
+    run(&env, "local", &["upgrade", "lib"]);
+
+    let store: Value = serde_json::from_str(
+        &fs::read_to_string(env.prefix().join("var/sps/metrics.json")).unwrap(),
+    )
+    .unwrap();
+    assert_eq!(store["packages"]["lib"]["upgrades"], 1, "{store:#}");
+    assert_eq!(store["packages"]["lib"]["installs"], 0, "{store:#}");
+}
+
+#[test]
+fn metrics_off_writes_nothing() {
+    let env = env_with_app();
+
+    run(&env, "off", &["install", "app"]);
+    run(&env, "off", &["uninstall", "app"]);
+
+    let state_dir = env.prefix().join("var/sps");
+    assert!(
+        !state_dir.join("metrics.json").exists(),
+        "{:?}",
+        fs::read_dir(&state_dir).map(|d| d.flatten().map(|e| e.path()).collect::<Vec<_>>())
+    );
+}
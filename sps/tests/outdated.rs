@@ -0,0 +1,60 @@
+//! `outdated` calls out deprecated and disabled formulae the way `info` does.
+
+use std::fs;
+
+use serde_json::json;
+use sps_testkit::{describe, Fixtures, FormulaFixture, TestEnv};
+
+const SPS: &str = env!("CARGO_BIN_EXE_sps");
+
+/// Installs `name` 1.0 in the cellar, linked through `opt`, as if requested by the user.
+fn install_old(env: &TestEnv, name: &str) {
+    let keg = env.keg(name, "1.0");
+    fs::create_dir_all(keg.join("bin")).unwrap();
+    fs::write(
+        keg.join("INSTALL_RECEIPT.json"),
+        json!({ "name": name, "version": "1.0", "installed_on_request": true }).to_string(),
+    )
+    .unwrap();
+    std::os::unix::fs::symlink(&keg, env.prefix().join("opt").join(name)).unwrap();
+}
+
+#[test]
+fn deprecated_and_disabled_formulae_are_reported_below_the_table() {
+    let env = TestEnv::new(
+        &Fixtures::new()
+            .formula(FormulaFixture::new("jq", "1.7"))
+            .formula(FormulaFixture::new("oldtool", "2.0").extra(json!({
+                "deprecated": true,
+                "deprecation_date": "2024-01-01",
+                "deprecation_reason": "unmaintained",
+            })))
+            .formula(FormulaFixture::new("deadtool", "2.0").extra(json!({
+                "disabled": true,
+                "disable_date": "2024-06-01",
+                "disable_replacement": "jq",
+            }))),
+    );
+    for name in ["jq", "oldtool", "deadtool"] {
+        install_old(&env, name);
+    }
+
+    let output = env.run(SPS, &["outdated"]);
+
+    assert!(output.status.success(), "{}", describe(&output));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    for name in ["jq", "oldtool", "deadtool"] {
+        assert!(stdout.contains(name), "{}", describe(&output));
+    }
+    assert!(
+        stdout.contains("oldtool was deprecated on 2024-01-01 because it is not maintained"),
+        "{}",
+        describe(&output)
+    );
+    assert!(
+        stdout.contains("deadtool was disabled on 2024-06-01; use jq instead"),
+        "{}",
+        describe(&output)
+    );
+    assert!(!stdout.contains("jq was"), "{}", describe(&output));
+}
@@ -0,0 +1,47 @@
+//! Pipeline output when stdout is not a terminal, as in CI logs.
+
+use sps_testkit::{describe, Fixtures, FormulaFixture, TestEnv};
+
+const SPS: &str = env!("CARGO_BIN_EXE_sps");
+
+fn fixtures() -> Fixtures {
+    Fixtures::new()
+        .formula(FormulaFixture::new("one", "1.0"))
+        .formula(FormulaFixture::new("two", "1.0"))
+}
+
+fn stdout(output: &std::process::Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+#[test]
+fn per_package_lines_are_condensed_without_a_terminal() {
+    let env = TestEnv::new(&fixtures());
+
+    let output = env.run(SPS, &["install", "one", "two"]);
+
+    assert!(output.status.success(), "{}", describe(&output));
+    let stdout = stdout(&output);
+    assert!(!stdout.contains("Downloading bottle"), "{stdout}");
+    assert!(!stdout.contains("Installing bottle"), "{stdout}");
+}
+
+#[test]
+fn verbose_runs_print_every_package_line() {
+    let env = TestEnv::new(&fixtures());
+
+    let output = env.run(SPS, &["-v", "install", "one", "two"]);
+
+    assert!(output.status.success(), "{}", describe(&output));
+    let stdout = stdout(&output);
+    for name in ["one", "two"] {
+        assert!(
+            stdout.contains(&format!("Downloading bottle {name}")),
+            "{stdout}"
+        );
+        assert!(
+            stdout.contains(&format!("Installing bottle for {name}")),
+            "{stdout}"
+        );
+    }
+}
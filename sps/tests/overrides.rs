@@ -0,0 +1,54 @@
+//! Local overrides in `<prefix>/etc/sps/overrides` standing in for the API's definition.
+
+use std::fs;
+
+use serde_json::json;
+use sps_testkit::{describe, BottleServing, Fixtures, FormulaFixture, Response, TestEnv};
+
+const SPS: &str = env!("CARGO_BIN_EXE_sps");
+
+#[test]
+fn an_override_with_a_patched_bottle_url_is_downloaded_from_there() {
+    // Upstream's bottle URL is dead; the override points at a mirror.
+    let broken = FormulaFixture::new("hello", "1.0").bottle(BottleServing::Missing);
+    let env = TestEnv::new(&Fixtures::new().formula(broken.clone()));
+    let bottle = broken.bottle_bytes();
+    let mirror = "/mirror/hello-1.0.all.bottle.tar.gz";
+    env.server.serve(mirror, Response::ok(bottle.clone()));
+    let mut definition = broken.api_json(&env.server.base_url(), &bottle);
+    definition["bottle"]["stable"]["files"]["all"]["url"] = json!(env.server.url(mirror));
+    let overrides = env.prefix().join("etc/sps/overrides");
+    fs::create_dir_all(&overrides).unwrap();
+    fs::write(overrides.join("hello.json"), definition.to_string()).unwrap();
+
+    let output = env.run(SPS, &["install", "hello"]);
+
+    assert!(output.status.success(), "{}", describe(&output));
+    assert!(env.keg("hello", "1.0").join("bin/hello").is_file());
+    assert_eq!(env.server.hits(mirror), 1);
+    assert_eq!(env.server.hits(&broken.bottle_path()), 0);
+    assert!(
+        String::from_utf8_lossy(&output.stdout).contains("(local override)")
+            || String::from_utf8_lossy(&output.stderr).contains("(local override)"),
+        "{}",
+        describe(&output)
+    );
+}
+
+#[test]
+fn override_list_shows_an_override_and_remove_deletes_it() {
+    let fixture = FormulaFixture::new("hello", "1.0");
+    let env = TestEnv::new(&Fixtures::new().formula(fixture.clone()));
+    let overrides = env.prefix().join("etc/sps/overrides");
+    fs::create_dir_all(&overrides).unwrap();
+    let definition = fixture.api_json(&env.server.base_url(), &fixture.bottle_bytes());
+    fs::write(overrides.join("hello.json"), definition.to_string()).unwrap();
+
+    let listed = env.run(SPS, &["override", "list"]);
+    let removed = env.run(SPS, &["override", "remove", "hello"]);
+
+    assert!(listed.status.success(), "{}", describe(&listed));
+    assert!(String::from_utf8_lossy(&listed.stdout).contains("hello"));
+    assert!(removed.status.success(), "{}", describe(&removed));
+    assert!(!overrides.join("hello.json").exists());
+}
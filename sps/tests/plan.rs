@@ -0,0 +1,147 @@
+//! `install --dry-run --emit-plan` and `install --from-plan` against the mock API and bottle
+//! server.
+
+use std::fs;
+use std::path::PathBuf;
+
+use serde_json::Value;
+use sps_common::error::exit_code;
+use sps_testkit::{describe, Fixtures, FormulaFixture, TestEnv};
+
+const SPS: &str = env!("CARGO_BIN_EXE_sps");
+
+fn chain() -> Fixtures {
+    Fixtures::new()
+        .formula(FormulaFixture::new("app", "2.0").depends_on(&["lib"]))
+        .formula(FormulaFixture::new("lib", "1.0"))
+}
+
+fn plan_path(env: &TestEnv) -> PathBuf {
+    env.prefix().with_file_name("plan.json")
+}
+
+/// Runs a dry run of `install app` that writes its plan, and returns the plan.
+fn emit_plan(env: &TestEnv) -> Value {
+    let path = plan_path(env);
+    let output = env.run(
+        SPS,
+        &[
+            "install",
+            "--dry-run",
+            "--emit-plan",
+            path.to_str().unwrap(),
+            "app",
+        ],
+    );
+    assert!(output.status.success(), "{}", describe(&output));
+    serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap()
+}
+
+#[test]
+fn a_dry_run_downloads_nothing_and_emits_the_plan_in_install_order() {
+    let fixtures = chain();
+    let env = TestEnv::new(&fixtures);
+
+    let plan = emit_plan(&env);
+
+    assert_eq!(plan["schema_version"], 1);
+    let packages = plan["packages"].as_array().unwrap();
+    let names: Vec<&str> = packages
+        .iter()
+        .map(|p| p["name"].as_str().unwrap())
+        .collect();
+    assert_eq!(names, ["lib", "app"]);
+    assert_eq!(packages[0]["version"], "1.0");
+    assert_eq!(packages[0]["bottle_tag"], "all");
+    assert_eq!(packages[0]["requested"], false);
+    assert_eq!(packages[0]["dependency_of"], serde_json::json!(["app"]));
+    assert_eq!(packages[1]["requested"], true);
+    assert_eq!(packages[1]["dependencies"], serde_json::json!(["lib"]));
+    for formula in &fixtures.formulae {
+        assert_eq!(
+            env.server.hits(&formula.bottle_path()),
+            0,
+            "{}",
+            formula.name
+        );
+        assert!(!env.keg(&formula.name, &formula.version).exists());
+    }
+}
+
+#[test]
+fn installing_from_a_plan_installs_what_it_lists() {
+    let fixtures = chain();
+    let env = TestEnv::new(&fixtures);
+    emit_plan(&env);
+
+    let output = env.run(
+        SPS,
+        &["install", "--from-plan", plan_path(&env).to_str().unwrap()],
+    );
+
+    assert!(output.status.success(), "{}", describe(&output));
+    for formula in &fixtures.formulae {
+        assert!(env.keg(&formula.name, &formula.version).is_dir());
+        assert_eq!(
+            env.server.hits(&formula.bottle_path()),
+            1,
+            "{}",
+            formula.name
+        );
+    }
+}
+
+#[test]
+fn a_plan_whose_checksum_no_longer_matches_is_refused_before_downloading() {
+    let fixtures = chain();
+    let env = TestEnv::new(&fixtures);
+    let mut plan = emit_plan(&env);
+    plan["packages"][0]["sha256"] = Value::from("0".repeat(64));
+    fs::write(plan_path(&env), plan.to_string()).unwrap();
+
+    let output = env.run(
+        SPS,
+        &["install", "--from-plan", plan_path(&env).to_str().unwrap()],
+    );
+
+    assert_eq!(
+        output.status.code(),
+        Some(exit_code::CHECKSUM),
+        "{}",
+        describe(&output)
+    );
+    assert!(
+        describe(&output).contains("is stale"),
+        "{}",
+        describe(&output)
+    );
+    for formula in &fixtures.formulae {
+        assert_eq!(
+            env.server.hits(&formula.bottle_path()),
+            0,
+            "{}",
+            formula.name
+        );
+        assert!(!env.keg(&formula.name, &formula.version).exists());
+    }
+}
+
+#[test]
+fn a_plan_of_another_schema_version_is_refused() {
+    let env = TestEnv::new(&chain());
+    let mut plan = emit_plan(&env);
+    plan["schema_version"] = Value::from(2);
+    fs::write(plan_path(&env), plan.to_string()).unwrap();
+
+    let output = env.run(
+        SPS,
+        &["install", "--from-plan", plan_path(&env).to_str().unwrap()],
+    );
+
+    assert!(!output.status.success(), "{}", describe(&output));
+    assert!(
+        describe(&output).contains("plan schema version 2 is not supported"),
+        "{}",
+        describe(&output)
+    );
+}
@@ -0,0 +1,61 @@
+//! Bottle downloads run ahead of the pour order: every bottle in the plan is requested up front,
+//! not once its dependencies are installed.
+
+use std::time::{Duration, Instant};
+
+use sps_testkit::{describe, Fixtures, FormulaFixture, TestEnv};
+
+const SPS: &str = env!("CARGO_BIN_EXE_sps");
+
+/// How long the server holds back each bottle.
+const BOTTLE_DELAY: Duration = Duration::from_millis(600);
+
+#[test]
+fn downloads_of_a_dependency_chain_overlap() {
+    // top -> mid -> low -> base: pours have to run in that order, bottom first.
+    let chain = [("top", "mid"), ("mid", "low"), ("low", "base")];
+    let mut fixtures =
+        Fixtures::new().formula(FormulaFixture::new("base", "1.0").bottle_delay(BOTTLE_DELAY));
+    for (name, dep) in chain {
+        fixtures = fixtures.formula(
+            FormulaFixture::new(name, "1.0")
+                .depends_on(&[dep])
+                .bottle_delay(BOTTLE_DELAY),
+        );
+    }
+    let env = TestEnv::new(&fixtures);
+
+    let started = Instant::now();
+    let output = env
+        .command(SPS)
+        .env("sps_MAX_CONCURRENT_DOWNLOADS", "4")
+        .args(["install", "top"])
+        .output()
+        .unwrap();
+    let elapsed = started.elapsed();
+
+    assert!(output.status.success(), "{}", describe(&output));
+    let requested: Vec<Instant> = fixtures
+        .formulae
+        .iter()
+        .map(|f| env.server.first_request_at(&f.bottle_path()).unwrap())
+        .collect();
+    let (first, last) = (
+        requested.iter().min().unwrap(),
+        requested.iter().max().unwrap(),
+    );
+    // Nothing can be poured before the first bottle arrives, so every bottle being requested
+    // within one delay of the first means no download waited for a pour.
+    assert!(
+        last.duration_since(*first) < BOTTLE_DELAY,
+        "bottle requests spread over {:?}",
+        last.duration_since(*first)
+    );
+    // Downloading each bottle only after its dependency is installed takes at least one delay
+    // per level.
+    assert!(
+        elapsed < BOTTLE_DELAY * 4,
+        "install took {elapsed:?}\n{}",
+        describe(&output)
+    );
+}
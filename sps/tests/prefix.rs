@@ -0,0 +1,95 @@
+//! `prefix`, `--cellar` and `--caskroom` answer from `Config` and the one directory asked about:
+//! they neither read the API cache nor reach the API, and stay fast enough for Makefiles.
+
+use std::fs;
+use std::time::{Duration, Instant};
+
+use sps_testkit::{describe, Fixtures, TestEnv};
+
+const SPS: &str = env!("CARGO_BIN_EXE_sps");
+
+/// Process startup included; a command that loaded or fetched the index would not need more
+/// than this either on a fixture this small, so the cache and server checks below do the rest.
+const BUDGET: Duration = Duration::from_millis(500);
+
+/// `jq` 1.7 installed and linked, `viewer` 1.5 installed, and an API cache that fails to parse
+/// if anything reads it.
+fn env_with_installs_and_a_broken_api_cache() -> TestEnv {
+    let env = TestEnv::new(&Fixtures::new());
+    let keg = env.keg("jq", "1.7");
+    fs::create_dir_all(keg.join("bin")).unwrap();
+    fs::write(keg.join("INSTALL_RECEIPT.json"), "{}").unwrap();
+    std::os::unix::fs::symlink(&keg, env.prefix().join("opt/jq")).unwrap();
+    let caskroom = env.prefix().join("Caskroom/viewer/1.5");
+    fs::create_dir_all(&caskroom).unwrap();
+    fs::write(caskroom.join("CASK_INSTALL_MANIFEST.json"), "{}").unwrap();
+    fs::create_dir_all(env.cache_dir()).unwrap();
+    for file in ["formula.json", "cask.json"] {
+        fs::write(env.cache_dir().join(file), "not json").unwrap();
+    }
+    env
+}
+
+#[test]
+fn path_queries_print_paths_without_touching_the_api_cache() {
+    let env = env_with_installs_and_a_broken_api_cache();
+    let prefix = env.prefix().display().to_string();
+    let cases: [(&[&str], String); 6] = [
+        (&["prefix"], prefix.clone()),
+        (&["prefix", "jq"], format!("{prefix}/opt/jq")),
+        (&["--cellar"], format!("{prefix}/Cellar")),
+        (&["--cellar", "jq"], format!("{prefix}/Cellar/jq/1.7")),
+        (&["--caskroom"], format!("{prefix}/Caskroom")),
+        (
+            &["--caskroom", "viewer"],
+            format!("{prefix}/Caskroom/viewer/1.5"),
+        ),
+    ];
+
+    for (args, expected) in cases {
+        let started = Instant::now();
+        let output = env.run(SPS, args);
+        let took = started.elapsed();
+
+        assert!(output.status.success(), "{args:?}: {}", describe(&output));
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout).trim_end(),
+            expected,
+            "{args:?}"
+        );
+        assert!(took < BUDGET, "{args:?} took {took:?}");
+    }
+    assert_eq!(env.server.requests(), Vec::<String>::new());
+    for file in ["formula.json", "cask.json"] {
+        assert_eq!(
+            fs::read_to_string(env.cache_dir().join(file)).unwrap(),
+            "not json"
+        );
+    }
+}
+
+#[test]
+fn path_queries_for_missing_packages_exit_with_status_one() {
+    let env = env_with_installs_and_a_broken_api_cache();
+
+    for args in [
+        &["prefix", "wget"],
+        &["--cellar", "wget"],
+        &["--caskroom", "editor"],
+    ] {
+        let output = env.run(SPS, args);
+
+        assert_eq!(
+            output.status.code(),
+            Some(1),
+            "{args:?}: {}",
+            describe(&output)
+        );
+        assert!(
+            String::from_utf8_lossy(&output.stderr).contains("is not installed"),
+            "{args:?}: {}",
+            describe(&output)
+        );
+    }
+    assert_eq!(env.server.requests(), Vec::<String>::new());
+}
@@ -0,0 +1,76 @@
+//! A keg directory left without a receipt by an earlier, interrupted pour is moved aside before
+//! pouring fresh, reported with its new path, and removed by `cleanup`.
+
+use std::fs;
+
+use sps_testkit::{describe, Fixtures, FormulaFixture, TestEnv};
+
+const SPS: &str = env!("CARGO_BIN_EXE_sps");
+
+#[test]
+fn a_partial_keg_is_quarantined_and_a_fresh_one_poured() {
+    let env = TestEnv::new(
+        &Fixtures::new()
+            .formula(FormulaFixture::new("jq", "1.7").file("bin/jq", "#!/bin/sh\necho jq 1.7\n")),
+    );
+    let keg = env.keg("jq", "1.7");
+    fs::create_dir_all(keg.join("bin")).unwrap();
+    fs::write(keg.join("bin/jq"), "half a binary").unwrap();
+    fs::write(keg.join("leftover"), "from the earlier pour").unwrap();
+
+    let output = env.run(SPS, &["install", "jq"]);
+
+    assert!(output.status.success(), "{}", describe(&output));
+    let moved: Vec<_> = fs::read_dir(env.cellar().join("jq"))
+        .unwrap()
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path != &keg)
+        .collect();
+    assert_eq!(moved.len(), 1, "{moved:?}");
+    let quarantined = &moved[0];
+    assert!(
+        quarantined
+            .file_name()
+            .unwrap()
+            .to_string_lossy()
+            .starts_with("1.7.broken-"),
+        "{quarantined:?}"
+    );
+    assert_eq!(
+        fs::read_to_string(quarantined.join("leftover")).unwrap(),
+        "from the earlier pour"
+    );
+    assert!(
+        String::from_utf8_lossy(&output.stdout).contains(&format!(
+            "Moved an incomplete keg aside to {}; inspect it, or remove it with `sps cleanup`",
+            quarantined.display()
+        )),
+        "{}",
+        describe(&output)
+    );
+    // The fresh keg has only what the bottle holds.
+    assert!(!keg.join("leftover").exists());
+    assert!(keg.join("INSTALL_RECEIPT.json").is_file());
+    assert_eq!(
+        fs::read_to_string(keg.join("bin/jq")).unwrap(),
+        "#!/bin/sh\necho jq 1.7\n"
+    );
+
+    let dry_run = env.run(SPS, &["cleanup", "--dry-run"]);
+    assert!(
+        String::from_utf8_lossy(&dry_run.stdout).contains(&format!(
+            "Would remove incomplete keg of jq at {}",
+            quarantined.display()
+        )),
+        "{}",
+        describe(&dry_run)
+    );
+    assert!(quarantined.is_dir());
+
+    let cleanup = env.run(SPS, &["cleanup"]);
+
+    assert!(cleanup.status.success(), "{}", describe(&cleanup));
+    assert!(!quarantined.exists());
+    assert!(keg.join("bin/jq").is_file());
+}
@@ -0,0 +1,117 @@
+//! `sps resolve` reports the resolver's plan for the same flags `install` takes, node by node,
+//! without downloading anything.
+
+use serde_json::{json, Value};
+use sps_testkit::{describe, Fixtures, FormulaFixture, TestEnv};
+
+const SPS: &str = env!("CARGO_BIN_EXE_sps");
+
+/// `app` needs `lib`, and can use `extra`.
+fn fixtures() -> Fixtures {
+    Fixtures::new()
+        .formula(
+            FormulaFixture::new("app", "2.0")
+                .depends_on(&["lib"])
+                .extra(json!({ "optional_dependencies": ["extra"] })),
+        )
+        .formula(FormulaFixture::new("lib", "1.0"))
+        .formula(FormulaFixture::new("extra", "1.0"))
+}
+
+/// Runs `sps resolve --json` with `args` and returns the report.
+fn resolve(env: &TestEnv, args: &[&str]) -> Value {
+    let output = env.run(SPS, &[&["resolve", "--json"], args].concat());
+    assert!(output.status.success(), "{}", describe(&output));
+    serde_json::from_slice(&output.stdout).unwrap()
+}
+
+fn node<'a>(report: &'a Value, name: &str) -> &'a Value {
+    report["nodes"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|n| n["name"] == name)
+        .unwrap_or_else(|| panic!("no node {name}: {report}"))
+}
+
+#[test]
+fn reports_every_node_in_plan_order_without_downloading() {
+    let fixtures = fixtures();
+    let env = TestEnv::new(&fixtures);
+
+    let report = resolve(&env, &["app"]);
+
+    let names: Vec<&str> = report["nodes"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|n| n["name"].as_str().unwrap())
+        .collect();
+    // Planned nodes first, in plan order, then the rest.
+    assert_eq!(names, ["lib", "app", "extra"], "{report}");
+    let lib = node(&report, "lib");
+    assert_eq!(lib["status"], "missing");
+    assert_eq!(lib["position"], 1);
+    assert_eq!(lib["bottle"], "all");
+    assert_eq!(lib["source_build"], false);
+    assert_eq!(lib["target"], false);
+    assert_eq!(lib["required_by"], json!(["app"]));
+    let app = node(&report, "app");
+    assert_eq!(app["status"], "requested");
+    assert_eq!(app["position"], 2);
+    assert_eq!(app["target"], true);
+    let extra = node(&report, "extra");
+    assert_eq!(extra["status"], "skipped_optional");
+    assert_eq!(extra["position"], Value::Null);
+    assert_eq!(report["errors"], json!([]));
+    for formula in &fixtures.formulae {
+        assert_eq!(
+            env.server.hits(&formula.bottle_path()),
+            0,
+            "{}",
+            formula.name
+        );
+        assert!(!env.keg(&formula.name, &formula.version).exists());
+    }
+}
+
+#[test]
+fn install_flags_change_the_plan_the_same_way() {
+    let env = TestEnv::new(&fixtures());
+    let installed = env.run(SPS, &["install", "lib"]);
+    assert!(installed.status.success(), "{}", describe(&installed));
+
+    let default = resolve(&env, &["app"]);
+    let ignoring = resolve(&env, &["app", "--ignore-installed"]);
+    let optional = resolve(&env, &["app", "--include-optional"]);
+    let from_source = resolve(&env, &["app", "--build-from-source"]);
+
+    assert_eq!(node(&default, "lib")["status"], "installed");
+    assert_eq!(node(&default, "lib")["position"], Value::Null);
+    assert_eq!(node(&default, "app")["position"], 1);
+    assert_eq!(node(&ignoring, "lib")["position"], 1);
+    assert_eq!(node(&ignoring, "app")["position"], 2);
+    assert_eq!(node(&default, "extra")["position"], Value::Null);
+    assert!(node(&optional, "extra")["position"].is_u64(), "{optional}");
+    assert_eq!(node(&optional, "extra")["required_by"], json!(["app"]));
+    assert_eq!(node(&from_source, "app")["source_build"], true);
+}
+
+#[test]
+fn an_unknown_dependency_is_listed_and_fails_the_command() {
+    let env = TestEnv::new(
+        &Fixtures::new().formula(FormulaFixture::new("app", "2.0").depends_on(&["nowhere"])),
+    );
+
+    let output = env.run(SPS, &["resolve", "app"]);
+
+    assert!(!output.status.success(), "{}", describe(&output));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout
+            .lines()
+            .any(|l| l.contains("error") && l.contains("nowhere")),
+        "{}",
+        describe(&output)
+    );
+}
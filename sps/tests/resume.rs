@@ -0,0 +1,59 @@
+//! An install whose linking failed leaves the keg poured and marked unlinked; running the same
+//! install again only links it, without downloading or pouring anything.
+
+use std::fs;
+
+use sps_common::keg::UNLINKED_MARKER;
+use sps_testkit::{describe, Fixtures, FormulaFixture, TestEnv};
+
+const SPS: &str = env!("CARGO_BIN_EXE_sps");
+
+#[test]
+fn a_failed_link_is_retried_without_downloading_or_pouring_again() {
+    let jq = FormulaFixture::new("jq", "1.7")
+        .file("bin/jq", "#!/bin/sh\necho jq 1.7\n")
+        .file("lib/libjq.a", "libjq");
+    let tool = FormulaFixture::new("tool", "2.0").file("bin/tool", "#!/bin/sh\necho tool\n");
+    let env = TestEnv::new(&Fixtures::new().formula(jq.clone()).formula(tool.clone()));
+    // A file where the prefix's `lib` directory belongs makes linking jq fail.
+    let blocker = env.prefix().join("lib");
+    fs::write(&blocker, "in the way").unwrap();
+
+    let first = env.run(SPS, &["install", "jq", "tool"]);
+
+    assert!(!first.status.success(), "{}", describe(&first));
+    let keg = env.keg("jq", "1.7");
+    assert!(keg.join(UNLINKED_MARKER).exists(), "{}", describe(&first));
+    assert!(env.keg("tool", "2.0").is_dir(), "{}", describe(&first));
+    assert_eq!(env.server.hits(&jq.bottle_path()), 1);
+    // Survives only if the second run leaves the poured keg alone.
+    fs::write(keg.join("poured-once"), "").unwrap();
+    let requests = env.server.requests().len();
+    fs::remove_file(&blocker).unwrap();
+
+    let second = env.run(SPS, &["install", "jq", "tool"]);
+
+    assert!(second.status.success(), "{}", describe(&second));
+    let stdout = String::from_utf8_lossy(&second.stdout);
+    assert!(
+        stdout.contains("Linking jq 1.7 (poured by an earlier run)"),
+        "{}",
+        describe(&second)
+    );
+    let bottles: Vec<_> = env.server.requests()[requests..]
+        .iter()
+        .filter(|path| path.starts_with("/bottles/"))
+        .cloned()
+        .collect();
+    assert!(bottles.is_empty(), "{bottles:?}");
+    assert_eq!(env.server.hits(&jq.bottle_path()), 1);
+    assert_eq!(env.server.hits(&tool.bottle_path()), 1);
+    assert!(keg.join("poured-once").exists());
+    assert!(!keg.join(UNLINKED_MARKER).exists());
+    assert_eq!(fs::read_link(env.prefix().join("opt/jq")).unwrap(), keg);
+    assert_eq!(
+        fs::read(env.prefix().join("lib/libjq.a")).unwrap(),
+        b"libjq"
+    );
+    assert!(env.bin("jq").exists());
+}
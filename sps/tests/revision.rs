@@ -0,0 +1,60 @@
+//! A formula whose revision was bumped without a new upstream version is offered as an upgrade,
+//! and the revision is recorded in the receipt and shown by `info`.
+
+use std::fs;
+
+use serde_json::{json, Value};
+use sps_testkit::{describe, Fixtures, FormulaFixture, TestEnv};
+
+const SPS: &str = env!("CARGO_BIN_EXE_sps");
+
+/// `jq` 1.7 at revision 1, with `Cellar/jq/1.7` (revision 0) already installed and linked.
+fn env_with_revision_zero_installed() -> TestEnv {
+    let env = TestEnv::new(
+        &Fixtures::new().formula(FormulaFixture::new("jq", "1.7").extra(json!({ "revision": 1 }))),
+    );
+    let keg = env.keg("jq", "1.7");
+    fs::create_dir_all(keg.join("bin")).unwrap();
+    fs::write(keg.join("bin/jq"), "#!/bin/sh\necho jq 1.7\n").unwrap();
+    fs::write(
+        keg.join("INSTALL_RECEIPT.json"),
+        json!({ "name": "jq", "version": "1.7", "revision": 0, "installed_on_request": true })
+            .to_string(),
+    )
+    .unwrap();
+    std::os::unix::fs::symlink(&keg, env.prefix().join("opt/jq")).unwrap();
+    env
+}
+
+#[test]
+fn a_revision_only_bump_is_planned_and_installed_as_an_upgrade() {
+    let env = env_with_revision_zero_installed();
+
+    let output = env.run(SPS, &["upgrade", "jq"]);
+
+    assert!(output.status.success(), "{}", describe(&output));
+    let keg = env.keg("jq", "1.7_1");
+    assert!(keg.join("bin/jq").is_file(), "{}", describe(&output));
+    let receipt: Value =
+        serde_json::from_str(&fs::read_to_string(keg.join("INSTALL_RECEIPT.json")).unwrap())
+            .unwrap();
+    assert_eq!(receipt["revision"], 1);
+    assert_eq!(
+        fs::read_link(env.prefix().join("opt/jq")).unwrap(),
+        keg,
+        "{}",
+        describe(&output)
+    );
+}
+
+#[test]
+fn info_spells_out_the_available_and_installed_revisions() {
+    let env = env_with_revision_zero_installed();
+
+    let output = env.run(SPS, &["info", "jq"]);
+
+    assert!(output.status.success(), "{}", describe(&output));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("1.7 (revision 1)"), "{}", describe(&output));
+    assert!(!stdout.contains("1.7_1"), "{}", describe(&output));
+}
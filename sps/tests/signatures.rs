@@ -0,0 +1,250 @@
+//! Signed downloads from a mirror: with `signature_mode` set, a bottle or cask archive is only
+//! installed once a detached signature by a configured key verifies over it.
+
+use std::fs;
+use std::path::PathBuf;
+
+use ring::signature::{Ed25519KeyPair, KeyPair};
+use sps_common::error::exit_code;
+use sps_testkit::{describe, CaskFixture, Fixtures, FormulaFixture, Response, TestEnv};
+use walkdir::WalkDir;
+
+const SPS: &str = env!("CARGO_BIN_EXE_sps");
+
+fn mirror_key() -> Ed25519KeyPair {
+    Ed25519KeyPair::from_seed_unchecked(&[5; 32]).unwrap()
+}
+
+fn public_hex(pair: &Ed25519KeyPair) -> String {
+    hex::encode(pair.public_key().as_ref())
+}
+
+/// Serves a raw Ed25519 signature by `pair` next to the artifact at `path`.
+fn sign(env: &TestEnv, pair: &Ed25519KeyPair, path: &str, artifact: &[u8]) {
+    env.server.serve(
+        &format!("{path}.sig"),
+        Response::ok(pair.sign(artifact).as_ref().to_vec()),
+    );
+}
+
+fn jq() -> FormulaFixture {
+    FormulaFixture::new("jq", "1.7.1")
+}
+
+/// The cached files of jq's bottle whose names end with `suffix`.
+fn cached_bottle_files(env: &TestEnv, suffix: &str) -> Vec<PathBuf> {
+    WalkDir::new(env.cache_dir())
+        .into_iter()
+        .flatten()
+        .filter(|entry| {
+            let name = entry.file_name().to_string_lossy();
+            name.starts_with("jq-1.7.1.") && name.ends_with(suffix)
+        })
+        .map(|entry| entry.into_path())
+        .collect()
+}
+
+#[test]
+fn a_signed_bottle_installs_under_require() {
+    let env = TestEnv::new(&Fixtures::new().formula(jq()));
+    sign(
+        &env,
+        &mirror_key(),
+        &jq().bottle_path(),
+        &jq().bottle_bytes(),
+    );
+
+    let output = env
+        .command(SPS)
+        .args(["install", "jq"])
+        .env("sps_SIGNATURE_MODE", "require")
+        .env("sps_SIGNATURE_KEYS", public_hex(&mirror_key()))
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "{}", describe(&output));
+    assert!(env.keg("jq", "1.7.1").exists());
+    assert_eq!(env.server.hits(&format!("{}.sig", jq().bottle_path())), 1);
+    assert_eq!(
+        cached_bottle_files(&env, ".tar.gz.sig").len(),
+        1,
+        "the signature is cached beside the bottle"
+    );
+}
+
+#[test]
+fn an_unsigned_bottle_fails_under_require_and_is_evicted() {
+    let env = TestEnv::new(&Fixtures::new().formula(jq()));
+
+    let output = env
+        .command(SPS)
+        .args(["install", "jq"])
+        .env("sps_SIGNATURE_MODE", "require")
+        .env("sps_SIGNATURE_KEYS", public_hex(&mirror_key()))
+        .output()
+        .unwrap();
+
+    assert_eq!(
+        output.status.code(),
+        Some(exit_code::CHECKSUM),
+        "{}",
+        describe(&output)
+    );
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("no signature found"),
+        "{}",
+        describe(&output)
+    );
+    assert!(!env.keg("jq", "1.7.1").exists());
+    assert!(cached_bottle_files(&env, ".tar.gz").is_empty());
+}
+
+#[test]
+fn a_bottle_signed_by_another_key_fails_under_require() {
+    let env = TestEnv::new(&Fixtures::new().formula(jq()));
+    let stranger = Ed25519KeyPair::from_seed_unchecked(&[6; 32]).unwrap();
+    sign(&env, &stranger, &jq().bottle_path(), &jq().bottle_bytes());
+
+    let output = env
+        .command(SPS)
+        .args(["install", "jq"])
+        .env("sps_SIGNATURE_MODE", "require")
+        .env("sps_SIGNATURE_KEYS", public_hex(&mirror_key()))
+        .output()
+        .unwrap();
+
+    assert_eq!(
+        output.status.code(),
+        Some(exit_code::CHECKSUM),
+        "{}",
+        describe(&output)
+    );
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("signature does not match"),
+        "{}",
+        describe(&output)
+    );
+    assert!(!env.keg("jq", "1.7.1").exists());
+}
+
+#[test]
+fn an_unsigned_bottle_installs_under_warn_with_a_warning() {
+    let env = TestEnv::new(&Fixtures::new().formula(jq()));
+
+    let output = env
+        .command(SPS)
+        .args(["install", "jq"])
+        .env("sps_SIGNATURE_MODE", "warn")
+        .env("sps_SIGNATURE_KEYS", public_hex(&mirror_key()))
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "{}", describe(&output));
+    assert!(env.keg("jq", "1.7.1").exists());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("Signature check failed for jq"),
+        "{}",
+        describe(&output)
+    );
+    assert_eq!(
+        cached_bottle_files(&env, ".tar.gz").len(),
+        1,
+        "the bottle is kept"
+    );
+}
+
+#[test]
+fn keys_can_be_configured_as_key_files_in_the_config_file() {
+    let env = TestEnv::new(&Fixtures::new().formula(jq()));
+    sign(
+        &env,
+        &mirror_key(),
+        &jq().bottle_path(),
+        &jq().bottle_bytes(),
+    );
+    let dir = tempfile::tempdir().unwrap();
+    let key_file = dir.path().join("mirror key.hex");
+    fs::write(&key_file, format!("{}\n", public_hex(&mirror_key()))).unwrap();
+    let config_file = dir.path().join("config.toml");
+    fs::write(
+        &config_file,
+        format!(
+            "signature_mode = \"require\"\nsignature_keys = [{:?}]\n",
+            key_file.to_str().unwrap()
+        ),
+    )
+    .unwrap();
+
+    let output = env
+        .command(SPS)
+        .args(["install", "jq"])
+        .env("sps_CONFIG", &config_file)
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "{}", describe(&output));
+    assert!(env.keg("jq", "1.7.1").exists());
+}
+
+#[test]
+fn an_unparseable_key_fails_the_install() {
+    let env = TestEnv::new(&Fixtures::new().formula(jq()));
+    sign(
+        &env,
+        &mirror_key(),
+        &jq().bottle_path(),
+        &jq().bottle_bytes(),
+    );
+
+    let output = env
+        .command(SPS)
+        .args(["install", "jq"])
+        .env("sps_SIGNATURE_MODE", "require")
+        .env("sps_SIGNATURE_KEYS", "not-a-key")
+        .output()
+        .unwrap();
+
+    assert!(!output.status.success(), "{}", describe(&output));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("neither a minisign public key nor a raw Ed25519 key"),
+        "{}",
+        describe(&output)
+    );
+    assert!(!env.keg("jq", "1.7.1").exists());
+}
+
+#[test]
+fn cask_archives_are_checked_too() {
+    let viewer = CaskFixture::new("viewer", "2.0");
+    let env = TestEnv::new(&Fixtures::new().cask(viewer.clone()));
+    let run = |env: &TestEnv| {
+        env.command(SPS)
+            .args(["install", "--cask", "viewer"])
+            .env("sps_SIGNATURE_MODE", "require")
+            .env("sps_SIGNATURE_KEYS", public_hex(&mirror_key()))
+            .output()
+            .unwrap()
+    };
+
+    let unsigned = run(&env);
+    assert_eq!(
+        unsigned.status.code(),
+        Some(exit_code::CHECKSUM),
+        "{}",
+        describe(&unsigned)
+    );
+
+    sign(
+        &env,
+        &mirror_key(),
+        &viewer.archive_path(),
+        &viewer.archive_bytes(),
+    );
+    let signed = run(&env);
+    assert!(signed.status.success(), "{}", describe(&signed));
+    assert!(env.bin("viewer").exists());
+}
@@ -0,0 +1,66 @@
+//! A client of `install --status-socket` gets a status snapshot, then the started/finished
+//! events of a fixture install, and the socket is owner-only while it exists.
+
+use std::io::{BufRead, BufReader};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::UnixStream;
+use std::process::Stdio;
+use std::thread;
+use std::time::Duration;
+
+use serde_json::Value;
+use sps_testkit::{Fixtures, FormulaFixture, TestEnv};
+
+const SPS: &str = env!("CARGO_BIN_EXE_sps");
+
+#[test]
+fn a_client_sees_the_install_start_and_finish() {
+    // The bottle is held back so the client connects while the run is still in flight.
+    let env = TestEnv::new(
+        &Fixtures::new()
+            .formula(FormulaFixture::new("jq", "1.7").bottle_delay(Duration::from_millis(1500))),
+    );
+    let dir = tempfile::tempdir().unwrap();
+    let socket = dir.path().join("status.sock");
+    let mut child = env
+        .command(SPS)
+        .args(["install", "--status-socket"])
+        .arg(&socket)
+        .arg("jq")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+
+    let stream = (0..100)
+        .find_map(|_| {
+            UnixStream::connect(&socket)
+                .map_err(|_| thread::sleep(Duration::from_millis(50)))
+                .ok()
+        })
+        .expect("the status socket appears");
+    let mode = std::fs::metadata(&socket).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+    let events: Vec<Value> = BufReader::new(stream)
+        .lines()
+        .map(|line| serde_json::from_str(&line.unwrap()).unwrap())
+        .collect();
+    assert!(child.wait().unwrap().success());
+
+    assert_eq!(events[0]["event"], "status", "{events:#?}");
+    assert_eq!(events[0]["nodes"][0]["name"], "jq", "{events:#?}");
+    let position = |event: &str, phase: &str| {
+        events
+            .iter()
+            .position(|e| e["event"] == event && e["phase"] == phase && e["name"] == "jq")
+            .unwrap_or_else(|| panic!("no {event} {phase} event: {events:#?}"))
+    };
+    assert!(position("finished", "download") < position("started", "install"));
+    assert!(position("started", "install") < position("finished", "install"));
+    let done = events.last().unwrap();
+    assert_eq!(done["event"], "done", "{events:#?}");
+    assert_eq!(done["succeeded"], 1, "{events:#?}");
+    assert!(!socket.exists(), "the socket is removed after the run");
+    let leftovers: Vec<_> = std::fs::read_dir(dir.path()).unwrap().collect();
+    assert!(leftovers.is_empty(), "{leftovers:?}");
+}
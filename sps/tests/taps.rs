@@ -0,0 +1,66 @@
+//! Formulae from a tap are keyed by `user/repo/name`, so a tapped formula and the core formula
+//! with the same short name install side by side.
+
+use std::fs;
+
+use serde_json::json;
+use sps_testkit::{describe, Fixtures, FormulaFixture, TestEnv};
+
+const SPS: &str = env!("CARGO_BIN_EXE_sps");
+
+/// The core `jq` 1.7 and `mycorp/tools/jq` 2.0. The versions differ so the bottles are served
+/// from different paths.
+fn fixtures() -> Fixtures {
+    Fixtures::new()
+        .formula(FormulaFixture::new("jq", "1.7"))
+        .formula(
+            FormulaFixture::new("jq", "2.0")
+                .extra(json!({ "tap": "mycorp/tools", "full_name": "mycorp/tools/jq" })),
+        )
+}
+
+#[test]
+fn a_tapped_formula_and_the_core_one_install_side_by_side() {
+    let env = TestEnv::new(&fixtures());
+
+    let core = env.run(SPS, &["install", "jq"]);
+    let tapped = env.run(SPS, &["install", "mycorp/tools/jq"]);
+
+    assert!(core.status.success(), "{}", describe(&core));
+    assert!(tapped.status.success(), "{}", describe(&tapped));
+    assert!(env.keg("jq", "1.7").join("bin/jq").is_file());
+    assert!(env.keg("mycorp--tools--jq", "2.0").join("bin/jq").is_file());
+    let opt = env.prefix().join("opt");
+    assert_eq!(fs::read_link(opt.join("jq")).unwrap(), env.keg("jq", "1.7"));
+    assert_eq!(
+        fs::read_link(opt.join("mycorp--tools--jq")).unwrap(),
+        env.keg("mycorp--tools--jq", "2.0")
+    );
+
+    let listed = env.run(SPS, &["list", "--formula"]);
+    let stdout = String::from_utf8_lossy(&listed.stdout);
+    assert!(stdout.contains("mycorp/tools/jq"), "{}", describe(&listed));
+    assert!(
+        stdout
+            .lines()
+            .any(|l| l.split_whitespace().next() == Some("jq")),
+        "{}",
+        describe(&listed)
+    );
+}
+
+#[test]
+fn uninstalling_the_tapped_formula_leaves_the_core_one() {
+    let env = TestEnv::new(&fixtures());
+    for name in ["jq", "mycorp/tools/jq"] {
+        let output = env.run(SPS, &["install", name]);
+        assert!(output.status.success(), "{}", describe(&output));
+    }
+
+    let output = env.run(SPS, &["uninstall", "mycorp/tools/jq"]);
+
+    assert!(output.status.success(), "{}", describe(&output));
+    assert!(!env.keg("mycorp--tools--jq", "2.0").exists());
+    assert!(env.keg("jq", "1.7").join("bin/jq").is_file());
+    assert!(env.prefix().join("opt/jq").exists());
+}
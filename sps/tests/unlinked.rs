@@ -0,0 +1,77 @@
+//! A keg whose opt link was deleted is reported as unlinked by `list` and `doctor`, and `install`
+//! relinks it instead of skipping it or pouring it again.
+
+use std::fs;
+
+use sps_testkit::{describe, Fixtures, FormulaFixture, TestEnv};
+
+const SPS: &str = env!("CARGO_BIN_EXE_sps");
+
+/// `jq` 1.7 installed and linked by `sps`, then its opt link deleted by hand.
+fn env_with_deleted_opt_link() -> (TestEnv, FormulaFixture) {
+    let jq = FormulaFixture::new("jq", "1.7").file("bin/jq", "#!/bin/sh\necho jq 1.7\n");
+    let env = TestEnv::new(&Fixtures::new().formula(jq.clone()));
+    let output = env.run(SPS, &["install", "jq"]);
+    assert!(output.status.success(), "{}", describe(&output));
+    fs::remove_file(env.prefix().join("opt/jq")).unwrap();
+    (env, jq)
+}
+
+#[test]
+fn list_marks_a_keg_without_its_opt_link_as_unlinked() {
+    let (env, _) = env_with_deleted_opt_link();
+
+    let output = env.run(SPS, &["list", "--formula"]);
+
+    assert!(output.status.success(), "{}", describe(&output));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let row = stdout
+        .lines()
+        .find(|line| line.starts_with("jq"))
+        .unwrap_or_else(|| panic!("{}", describe(&output)));
+    assert!(row.contains("1.7 (unlinked)"), "{}", describe(&output));
+}
+
+#[test]
+fn doctor_reports_a_keg_without_its_opt_link() {
+    let (env, _) = env_with_deleted_opt_link();
+
+    let output = env.run(SPS, &["doctor"]);
+
+    assert!(!output.status.success(), "{}", describe(&output));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("jq 1.7 is installed but not linked"),
+        "{}",
+        describe(&output)
+    );
+}
+
+#[test]
+fn install_relinks_the_keg_without_downloading_it_again() {
+    let (env, jq) = env_with_deleted_opt_link();
+    let downloads = env.server.hits(&jq.bottle_path());
+
+    let output = env.run(SPS, &["install", "jq"]);
+
+    assert!(output.status.success(), "{}", describe(&output));
+    assert!(
+        String::from_utf8_lossy(&output.stdout).contains("Relinking jq 1.7"),
+        "{}",
+        describe(&output)
+    );
+    assert_eq!(env.server.hits(&jq.bottle_path()), downloads);
+    assert_eq!(
+        fs::read_link(env.prefix().join("opt/jq")).unwrap(),
+        env.keg("jq", "1.7"),
+        "{}",
+        describe(&output)
+    );
+
+    let listed = env.run(SPS, &["list", "--formula"]);
+    assert!(
+        !String::from_utf8_lossy(&listed.stdout).contains("unlinked"),
+        "{}",
+        describe(&listed)
+    );
+}
@@ -0,0 +1,83 @@
+//! `sps unpack` extracts a bottle or cask download without installing anything.
+
+use std::fs;
+
+use sps_testkit::{describe, CaskFixture, Fixtures, FormulaFixture, TestEnv};
+
+const SPS: &str = env!("CARGO_BIN_EXE_sps");
+
+fn fixtures() -> Fixtures {
+    Fixtures::new()
+        .formula(FormulaFixture::new("hello", "1.0").file("share/hello/greeting", "hi"))
+        .cask(CaskFixture::new("viewer", "2.0"))
+}
+
+#[test]
+fn unpacks_a_bottle_into_the_working_directory_without_installing() {
+    let env = TestEnv::new(&fixtures());
+    let work = tempfile::tempdir().unwrap();
+
+    let output = env
+        .command(SPS)
+        .args(["unpack", "hello"])
+        .current_dir(work.path())
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "{}", describe(&output));
+    let dest = work.path().join("hello-1.0");
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout).trim(),
+        dest.display().to_string()
+    );
+    assert_eq!(
+        fs::read_to_string(dest.join("share/hello/greeting")).unwrap(),
+        "hi"
+    );
+    assert!(!env.keg("hello", "1.0").exists());
+    assert!(!env.bin("hello").exists());
+}
+
+#[test]
+fn a_non_empty_destination_is_replaced_only_with_force() {
+    let env = TestEnv::new(&fixtures());
+    let dest = tempfile::tempdir().unwrap();
+    fs::write(dest.path().join("stale"), "old").unwrap();
+    let destdir = dest.path().to_str().unwrap();
+
+    let output = env.run(SPS, &["unpack", "hello", "--destdir", destdir]);
+    assert!(!output.status.success(), "{}", describe(&output));
+    assert!(
+        describe(&output).contains("pass --force"),
+        "{}",
+        describe(&output)
+    );
+    assert!(dest.path().join("stale").exists());
+
+    let output = env.run(SPS, &["unpack", "hello", "--destdir", destdir, "--force"]);
+    assert!(output.status.success(), "{}", describe(&output));
+    assert!(!dest.path().join("stale").exists());
+    assert!(dest.path().join("bin/hello").is_file());
+}
+
+#[test]
+fn unpacks_a_cask_download_without_touching_the_caskroom() {
+    let env = TestEnv::new(&fixtures());
+    let dest = tempfile::tempdir().unwrap();
+    let destdir = dest.path().join("viewer");
+
+    let output = env.run(
+        SPS,
+        &[
+            "unpack",
+            "--cask",
+            "viewer",
+            "--destdir",
+            destdir.to_str().unwrap(),
+        ],
+    );
+
+    assert!(output.status.success(), "{}", describe(&output));
+    assert!(destdir.join("viewer").is_file());
+    assert!(!env.prefix().join("Caskroom/viewer").exists());
+}
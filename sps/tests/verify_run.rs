@@ -0,0 +1,38 @@
+//! `--verify-run`: linked executables are run after the install, and one that can't start
+//! fails its formula.
+
+use sps_testkit::{describe, Fixtures, FormulaFixture, TestEnv};
+
+const SPS: &str = env!("CARGO_BIN_EXE_sps");
+
+#[test]
+fn a_working_executable_passes_the_smoke_check() {
+    let env = TestEnv::new(&Fixtures::new().formula(FormulaFixture::new("hello", "1.0")));
+
+    let output = env.run(SPS, &["install", "--verify-run", "hello"]);
+
+    assert!(output.status.success(), "{}", describe(&output));
+    assert!(env.bin("hello").exists());
+}
+
+#[test]
+fn an_executable_that_fails_to_run_fails_the_install() {
+    let broken = FormulaFixture::new("broken", "1.0").file(
+        "bin/broken",
+        "#!/bin/sh\necho 'broken: error while loading shared libraries: libgone.so' >&2\nexit 127\n",
+    );
+    let env = TestEnv::new(&Fixtures::new().formula(broken));
+
+    let unchecked = env.run(SPS, &["install", "broken"]);
+    let uninstalled = env.run(SPS, &["uninstall", "broken"]);
+    let checked = env.run(SPS, &["install", "--verify-run", "broken"]);
+
+    assert!(unchecked.status.success(), "{}", describe(&unchecked));
+    assert!(uninstalled.status.success(), "{}", describe(&uninstalled));
+    assert!(!checked.status.success(), "{}", describe(&checked));
+    assert!(
+        String::from_utf8_lossy(&checked.stderr).contains("libgone.so"),
+        "{}",
+        describe(&checked)
+    );
+}